@@ -17,6 +17,35 @@ fn main() {
 
   let mut args = env::args();
   args.next().unwrap();
+  let mut args = args.peekable();
+
+  if args.peek().map(|arg| arg == "--data-dir").unwrap_or(false) {
+    args.next();
+    let dir = args.next().expect("--data-dir needs a directory");
+    // This is where everything the client writes goes; see common::data_dir.
+    env::set_var("PLAYFORM_DATA_DIR", dir);
+  }
+
+  if args.peek().map(|arg| arg == "--replay").unwrap_or(false) {
+    args.next();
+    let path = args.next().expect("--replay needs a recording to play");
+    assert!(args.next().is_none());
+    info!("Watching {}.", path);
+    client_lib::watch_replay(path.borrow());
+    return
+  }
+
+  if args.peek().map(|arg| arg == "--screenshot-test").unwrap_or(false) {
+    args.next();
+    let path = args.next().expect("--screenshot-test needs a recording to play");
+    let dir = args.next().expect("--screenshot-test needs a directory of poses and golden images");
+    assert!(args.next().is_none());
+    if !client_lib::screenshot_test(path.borrow(), dir.borrow()) {
+      std::process::exit(1);
+    }
+    return
+  }
+
   let listen_url = args.next().unwrap_or_else(|| String::from("ipc:///tmp/client.ipc"));
   let server_url = args.next().unwrap_or_else(|| String::from("ipc:///tmp/server.ipc"));
   assert!(args.next().is_none());
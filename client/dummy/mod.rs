@@ -81,6 +81,7 @@ pub fn run(listen_url: &str, server_url: &str) {
 
   // View thread returned, so we got a quit event.
   *quit.lock().unwrap() = true;
+  server.talk.tell(&protocol::ClientToServer::Disconnect(client.id));
 
   monitor_thread.join();
   update_thread.join();
@@ -88,10 +89,18 @@ pub fn run(listen_url: &str, server_url: &str) {
 
 fn connect_client(listen_url: &str, server: &server::T) -> client::T {
   // TODO: Consider using RPCs to solidify the request-response patterns.
-  server.talk.tell(&protocol::ClientToServer::Init(listen_url.to_owned()));
+  // Each dummy plays under a name of its own.
+  let now = time::precise_time_ns();
+  let credentials =
+    protocol::Credentials {
+      name  : format!("dummy{}", now % 1_000_000_000),
+      token : format!("{:x}", now),
+    };
+  server.talk.tell(&protocol::ClientToServer::Init(listen_url.to_owned(), credentials));
   loop {
     match server.listen.wait() {
-      protocol::ServerToClient::LeaseId(client_id) => {
+      protocol::ServerToClient::Refused(reason) => panic!("The server refused the dummy client: {}", reason),
+      protocol::ServerToClient::LeaseId(client_id, _) => {
         server.talk.tell(&protocol::ClientToServer::AddPlayer(client_id));
         let client_id = client_id;
         loop {
@@ -0,0 +1,207 @@
+//! A texture atlas for terrain, generated at startup, and the texture coordinates the mesher gives
+//! terrain polygons into it.
+//! Materials without a tile here are still shaded procedurally.
+
+use cgmath::{Point3, Vector3, InnerSpace};
+
+use common::voxel;
+
+use terrain_mesh::{Triangle, tri};
+
+/// Width and height of each tile, in texels.
+pub const TILE_SIZE: u32 = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(missing_docs)]
+pub enum Tile {
+  GrassTop = 0,
+  GrassSide = 1,
+  Stone = 2,
+  Sand = 3,
+  Snow = 4,
+  Coal = 5,
+  Iron = 6,
+  Crystal = 7,
+}
+
+/// Number of tiles, laid out left to right.
+pub const TILES: u32 = 8;
+
+const ALL_TILES: [Tile; TILES as usize] =
+  [Tile::GrassTop, Tile::GrassSide, Tile::Stone, Tile::Sand, Tile::Snow, Tile::Coal, Tile::Iron, Tile::Crystal];
+
+/// World-space width that one repeat of each tile covers, indexed by tile.
+/// Grass has to match between its top and side tiles, since slopes blend the two.
+pub const TILE_WORLD_SIZES: [f32; TILES as usize] = [4.0, 4.0, 8.0, 3.0, 6.0, 4.0, 4.0, 2.0];
+
+/// Polygons whose normals are at least this close to straight up use the top tile.
+const TOP_COS: f32 = 0.7;
+
+/// RGBA8 texels of the atlas, bottom row first.
+pub struct Image {
+  #[allow(missing_docs)]
+  pub width  : u32,
+  #[allow(missing_docs)]
+  pub height : u32,
+  #[allow(missing_docs)]
+  pub pixels : Vec<u8>,
+}
+
+/// Integer hash noise, so the atlas is the same every time.
+fn hash(x: u32, y: u32, seed: u32) -> f32 {
+  let mut h = x.wrapping_mul(374761393) ^ y.wrapping_mul(668265263) ^ seed.wrapping_mul(2246822519);
+  h = (h ^ (h >> 13)).wrapping_mul(1274126177);
+  h = h ^ (h >> 16);
+  (h & 0xffff) as f32 / 65535.0
+}
+
+/// Smoothly interpolated noise that tiles every `period` texels.
+fn value_noise(x: u32, y: u32, period: u32, seed: u32) -> f32 {
+  let cell = TILE_SIZE / period;
+  let (cx, cy) = (x / cell, y / cell);
+  let (fx, fy) = ((x % cell) as f32 / cell as f32, (y % cell) as f32 / cell as f32);
+  let smooth = |t: f32| t * t * (3.0 - 2.0 * t);
+  let (sx, sy) = (smooth(fx), smooth(fy));
+  let corner = |dx: u32, dy: u32| hash((cx + dx) % period, (cy + dy) % period, seed);
+  let top = corner(0, 0) + (corner(1, 0) - corner(0, 0)) * sx;
+  let bottom = corner(0, 1) + (corner(1, 1) - corner(0, 1)) * sx;
+  top + (bottom - top) * sy
+}
+
+fn mix(a: [f32; 3], b: [f32; 3], t: f32) -> [f32; 3] {
+  [a[0] + (b[0] - a[0]) * t, a[1] + (b[1] - a[1]) * t, a[2] + (b[2] - a[2]) * t]
+}
+
+fn texel(tile: Tile, x: u32, y: u32) -> [f32; 3] {
+  let seed = tile as u32;
+  let fine = value_noise(x, y, 16, seed);
+  let coarse = value_noise(x, y, 4, seed + 100);
+  match tile {
+    Tile::GrassTop => mix([0.1, 0.4, 0.0], [0.3, 0.6, 0.0], 0.6 * fine + 0.4 * coarse),
+    Tile::GrassSide => {
+      // A fringe of grass along the top edge, over dirt.
+      let fringe = TILE_SIZE as f32 * (0.8 + 0.1 * coarse);
+      let dirt = mix([0.3, 0.2, 0.1], [0.45, 0.3, 0.15], fine);
+      if y as f32 > fringe { mix([0.1, 0.4, 0.0], [0.3, 0.6, 0.0], fine) } else { dirt }
+    },
+    Tile::Stone => mix([0.2, 0.2, 0.2], [0.45, 0.45, 0.45], 0.3 * fine + 0.7 * coarse),
+    Tile::Sand => mix([0.75, 0.65, 0.45], [0.9, 0.8, 0.6], fine),
+    Tile::Snow => mix([0.85, 0.88, 0.95], [1.0, 1.0, 1.0], 0.5 * fine + 0.5 * coarse),
+    // Ores are flecks of color in stone.
+    Tile::Coal => {
+      let stone = mix([0.2, 0.2, 0.2], [0.4, 0.4, 0.4], coarse);
+      if fine > 0.6 { mix([0.02, 0.02, 0.02], [0.1, 0.1, 0.1], coarse) } else { stone }
+    },
+    Tile::Iron => {
+      let stone = mix([0.25, 0.23, 0.22], [0.45, 0.42, 0.4], coarse);
+      if fine > 0.62 { mix([0.55, 0.3, 0.15], [0.8, 0.5, 0.3], coarse) } else { stone }
+    },
+    Tile::Crystal => mix([0.4, 0.3, 0.7], [0.7, 0.9, 1.0], 0.3 * coarse + 0.7 * fine),
+  }
+}
+
+/// Generate the atlas.
+pub fn generate() -> Image {
+  let width = TILE_SIZE * TILES;
+  let height = TILE_SIZE;
+  let mut pixels = Vec::with_capacity((width * height * 4) as usize);
+  for y in 0 .. height {
+  for x in 0 .. width {
+    let c = texel(ALL_TILES[(x / TILE_SIZE) as usize], x % TILE_SIZE, y);
+    for &channel in &c {
+      pixels.push((channel.max(0.0).min(1.0) * 255.0) as u8);
+    }
+    pixels.push(255);
+  }}
+  Image {
+    width  : width,
+    height : height,
+    pixels : pixels,
+  }
+}
+
+/// The tile a polygon is textured with, if any.
+pub fn tile_for(material: voxel::Material, normal: &Vector3<f32>) -> Option<Tile> {
+  let up = normal.dot(Vector3::new(0.0, 1.0, 0.0)) >= TOP_COS;
+  match material {
+    voxel::Material::Terrain => Some(if up { Tile::GrassTop } else { Tile::GrassSide }),
+    voxel::Material::Stone => Some(Tile::Stone),
+    voxel::Material::Sand => Some(Tile::Sand),
+    voxel::Material::Snow => Some(Tile::Snow),
+    voxel::Material::Coal => Some(Tile::Coal),
+    voxel::Material::Iron => Some(Tile::Iron),
+    voxel::Material::Crystal => Some(Tile::Crystal),
+    _ => None,
+  }
+}
+
+/// Texture coordinates for a polygon, as (u, v, tile) per vertex, with a tile of -1 for none.
+/// The polygon is projected onto whichever axis-aligned plane it faces most, with v pointing up on
+/// walls, so side tiles stand upright. Coordinates are in tile repeats, and wrap in the shader.
+pub fn uvs(
+  vertices : &Triangle<Point3<f32>>,
+  normals  : &Triangle<Vector3<f32>>,
+  material : voxel::Material,
+) -> Triangle<Vector3<f32>> {
+  let normal = normals.v1 + normals.v2 + normals.v3;
+  let tile =
+    match tile_for(material, &normal.normalize()) {
+      None => return tri(Vector3::new(0.0, 0.0, -1.0), Vector3::new(0.0, 0.0, -1.0), Vector3::new(0.0, 0.0, -1.0)),
+      Some(tile) => tile,
+    };
+  let size = TILE_WORLD_SIZES[tile as usize];
+  let (ax, ay, az) = (normal.x.abs(), normal.y.abs(), normal.z.abs());
+  let project = |p: &Point3<f32>| {
+    let (u, v) =
+      if ay >= ax && ay >= az {
+        (p.x, p.z)
+      } else if ax >= az {
+        (p.z, p.y)
+      } else {
+        (p.x, p.y)
+      };
+    Vector3::new(u / size, v / size, tile as u32 as f32)
+  };
+  tri(project(&vertices.v1), project(&vertices.v2), project(&vertices.v3))
+}
+
+#[test]
+fn atlas_is_deterministic_and_tiles_differ() {
+  let a = generate();
+  let b = generate();
+  assert_eq!(a.pixels, b.pixels);
+  assert_eq!(a.pixels.len(), (a.width * a.height * 4) as usize);
+
+  let texel_at = |image: &Image, x: u32, y: u32| {
+    let i = ((y * image.width + x) * 4) as usize;
+    (image.pixels[i], image.pixels[i + 1], image.pixels[i + 2])
+  };
+  let sand = texel_at(&a, Tile::Sand as u32 * TILE_SIZE + 10, 10);
+  let stone = texel_at(&a, Tile::Stone as u32 * TILE_SIZE + 10, 10);
+  assert!(sand != stone);
+}
+
+#[test]
+fn walls_get_side_tiles() {
+  let up = Vector3::new(0.0, 1.0, 0.0);
+  let east = Vector3::new(1.0, 0.0, 0.0);
+  assert_eq!(tile_for(voxel::Material::Terrain, &up), Some(Tile::GrassTop));
+  assert_eq!(tile_for(voxel::Material::Terrain, &east), Some(Tile::GrassSide));
+  assert_eq!(tile_for(voxel::Material::Bark, &up), None);
+
+  let wall = tri(Point3::new(0.0, 0.0, 0.0), Point3::new(0.0, 4.0, 0.0), Point3::new(0.0, 0.0, 4.0));
+  let uvs = uvs(&wall, &tri(east, east, east), voxel::Material::Terrain);
+  // On a wall, v follows the height.
+  assert_eq!(uvs.v2.y, 1.0);
+  assert_eq!(uvs.v2.z, Tile::GrassSide as u32 as f32);
+}
+
+#[test]
+fn tiles_repeat_at_their_own_scale() {
+  let up = Vector3::new(0.0, 1.0, 0.0);
+  let floor = tri(Point3::new(0.0, 0.0, 0.0), Point3::new(24.0, 0.0, 0.0), Point3::new(0.0, 0.0, 24.0));
+  let stone = uvs(&floor, &tri(up, up, up), voxel::Material::Stone);
+  let sand = uvs(&floor, &tri(up, up, up), voxel::Material::Sand);
+  assert_eq!(stone.v2.x, 24.0 / TILE_WORLD_SIZES[Tile::Stone as usize]);
+  assert_eq!(sand.v2.x, 24.0 / TILE_WORLD_SIZES[Tile::Sand as usize]);
+}
@@ -25,7 +25,8 @@ impl Track {
 impl Track {
   #[allow(missing_docs)]
   pub fn is_done(&self) -> bool {
-    !self.repeat && (self.idx >= self.data.len())
+    // An empty track (e.g. a sound that failed to load) is done even if it repeats.
+    (!self.repeat || self.data.is_empty()) && (self.idx >= self.data.len())
   }
 }
 
@@ -46,11 +47,21 @@ impl Iterator for Track {
   }
 }
 
+/// How much of each new sample gets through the low-pass filter when sound is muffled.
+const MUFFLED_CUTOFF: f32 = 0.08;
+
+/// Volume of muffled sound.
+const MUFFLED_GAIN: f32 = 0.6;
+
 /// all the playing sounds
 pub struct TracksPlaying {
   tracks: Vec<Track>,
   ready: atomic::AtomicBool,
   buffer: Vec<f32>,
+  /// Whether to low-pass filter the mix, e.g. because the listener is underwater.
+  muffled: bool,
+  /// The last filtered sample of each of the two interleaved channels.
+  filtered: [f32; 2],
 }
 
 unsafe impl Sync for TracksPlaying {}
@@ -62,9 +73,16 @@ impl TracksPlaying {
       tracks: Vec::new(),
       ready: atomic::AtomicBool::new(false),
       buffer: std::iter::repeat(0.0).take(buffer_len).collect(),
+      muffled: false,
+      filtered: [0.0; 2],
     }
   }
 
+  /// Start or stop muffling everything that's playing.
+  pub fn set_muffled(&mut self, muffled: bool) {
+    self.muffled = muffled;
+  }
+
   /// add a new independently playing audio track
   pub fn push(&mut self, t: Track) {
     self.tracks.push(t);
@@ -89,6 +107,14 @@ impl TracksPlaying {
       }
     }
 
+    if self.muffled {
+      for (i, x) in self.buffer.iter_mut().enumerate() {
+        let filtered = &mut self.filtered[i % 2];
+        *filtered = *filtered + MUFFLED_CUTOFF * (*x - *filtered);
+        *x = *filtered * MUFFLED_GAIN;
+      }
+    }
+
     let mut i = 0;
     while i < self.tracks.len() {
       if self.tracks[i].is_done() {
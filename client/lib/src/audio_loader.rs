@@ -1,9 +1,12 @@
 //! Load audio assets into memory
 
 use hound;
+use std::path::{Path, PathBuf};
 
 use common::fnv_map;
 
+use resource_pack;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 #[allow(missing_docs)]
 pub enum SoundId {
@@ -11,6 +14,8 @@ pub enum SoundId {
   Rainforest,
   #[allow(missing_docs)]
   Footstep(u8),
+  /// Played while the player's health is low.
+  Heartbeat,
 }
 
 impl SoundId {
@@ -19,6 +24,7 @@ impl SoundId {
     match *self {
       SoundId::Rainforest    => "sounds/rainforest_ambience-GlorySunz-1938133500.wav".to_owned(),
       SoundId::Footstep(idx) => format!("sounds/Walking_On_Gravel-SoundBible{}.wav", idx),
+      SoundId::Heartbeat     => "sounds/heartbeat.wav".to_owned(),
     }
   }
 }
@@ -26,29 +32,57 @@ impl SoundId {
 #[allow(missing_docs)]
 pub struct T {
   loaded: fnv_map::T<SoundId, Vec<f32>>,
+  /// The resource pack generation that `loaded` came from.
+  generation: u32,
 }
 
 #[allow(missing_docs)]
 pub fn new() -> T {
   T {
     loaded: fnv_map::new(),
+    generation: 0,
   }
 }
 
 impl T {
   #[allow(missing_docs)]
-  pub fn load(&mut self, id: SoundId) -> &Vec<f32> {
+  pub fn load(&mut self, resource_packs: &resource_pack::T, id: SoundId) -> &Vec<f32> {
+    if resource_packs.generation != self.generation {
+      self.loaded.clear();
+      self.generation = resource_packs.generation;
+    }
     self.loaded
       .entry(id)
-      .or_insert_with(|| load_from_file(&id.to_asset_path()))
+      .or_insert_with(|| load_with_fallback(resource_packs, id))
+  }
+}
+
+/// Load a sound from the active resource packs. A pack's broken or unreadable sound shouldn't take
+/// the client down, so fall back to the built-in sound, and failing that, to silence.
+fn load_with_fallback(resource_packs: &resource_pack::T, id: SoundId) -> Vec<f32> {
+  let asset = id.to_asset_path();
+  let path = resource_packs.resolve(&asset);
+  match load_from_file(&path) {
+    Ok(samples) => return samples,
+    Err(err) => warn!("Error loading sound {:?} from {:?}: {:?}", id, path, err),
   }
+
+  let default = PathBuf::from(&asset);
+  if path != default {
+    match load_from_file(&default) {
+      Ok(samples) => return samples,
+      Err(err) => warn!("Error loading built-in sound {:?} from {:?}: {:?}", id, default, err),
+    }
+  }
+
+  Vec::new()
 }
 
-fn load_from_file(path: &str) -> Vec<f32> {
-  let mut reader = hound::WavReader::open(path).unwrap();
+fn load_from_file(path: &Path) -> hound::Result<Vec<f32>> {
+  let mut reader = try!(hound::WavReader::open(path));
   reader.samples::<i16>()
   .map(|s| {
-    s.unwrap() as f32 / 32768.0
+    s.map(|s| s as f32 / 32768.0)
   })
   .collect()
 }
@@ -6,6 +6,7 @@ use std::sync::{Mutex};
 
 use audio;
 use audio_loader;
+use resource_pack;
 
 #[allow(missing_docs)]
 pub enum Message {
@@ -13,12 +14,15 @@ pub enum Message {
   PlayLoop(audio_loader::SoundId),
   /// play a sound once
   PlayOneShot(audio_loader::SoundId),
+  /// muffle (or stop muffling) everything, e.g. because the player is underwater
+  Muffle(bool),
 }
 
 #[allow(missing_docs)]
 #[cfg(not(feature="audio"))]
 pub fn audio_thread<RecvMessage>(
   _quit: &Mutex<bool>,
+  _resource_packs: &Mutex<resource_pack::T>,
   _recv_message: &mut RecvMessage,
 ) where
   RecvMessage: FnMut() -> Option<Message>,
@@ -28,6 +32,7 @@ pub fn audio_thread<RecvMessage>(
 #[cfg(feature="audio")]
 pub fn audio_thread<RecvMessage>(
   quit: &Mutex<bool>,
+  resource_packs: &Mutex<resource_pack::T>,
   recv_message: &mut RecvMessage,
 ) where
   RecvMessage: FnMut() -> Option<Message>,
@@ -66,12 +71,16 @@ pub fn audio_thread<RecvMessage>(
 
   while !*quit.lock().unwrap() && stream.is_active() == Ok(true) {
     if let Some(up) = recv_message() {
+      let resource_packs = resource_packs.lock().unwrap();
       match up {
         Message::PlayLoop(id) => {
-          tracks_playing.push(audio::Track::new(audio_loader.load(id).clone(), true))
+          tracks_playing.push(audio::Track::new(audio_loader.load(&resource_packs, id).clone(), true))
         },
         Message::PlayOneShot(id) => {
-          tracks_playing.push(audio::Track::new(audio_loader.load(id).clone(), false))
+          tracks_playing.push(audio::Track::new(audio_loader.load(&resource_packs, id).clone(), false))
+        },
+        Message::Muffle(muffled) => {
+          tracks_playing.set_muffled(muffled)
         },
       }
     } else {
@@ -29,7 +29,7 @@ impl T {
   }
 
   /// print all the stats into a file
-  pub fn output_to(&self, file: &str) {
+  pub fn output_to<P: AsRef<std::path::Path>>(&self, file: P) {
     let mut file = std::fs::File::create(file).unwrap();
 
     file.write_all(b"vram_chunk_loads = [").unwrap();
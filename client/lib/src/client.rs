@@ -4,14 +4,23 @@ use cgmath::Point3;
 use num;
 use rand;
 use rand::{Rng, SeedableRng};
+use std;
 use std::sync::Mutex;
 
+use common::data_dir;
+use common::entity;
 use common::id_allocator;
 use common::protocol;
 use common::surroundings_loader;
 
+use horizon_loader;
 use lod;
+use replay;
+use resource_pack;
+use settings;
 use terrain;
+use terrain_cache;
+use text_entry;
 use view;
 
 // TODO: Remove this once our RAM usage doesn't skyrocket with load distance.
@@ -35,14 +44,40 @@ pub struct T {
   pub grass_allocator          : Mutex<id_allocator::T<view::entity::id::Grass>>,
   #[allow(missing_docs)]
   pub surroundings_loader      : Mutex<surroundings_loader::T>,
-  #[allow(missing_docs)]
+  /// How far out this client can load terrain, in chunks.
   pub max_load_distance        : u32,
+  /// How far out terrain is actually loaded: `max_load_distance`, or less if the server asks.
+  pub load_distance            : Mutex<u32>,
   #[allow(missing_docs)]
   pub terrain                  : Mutex<terrain::T>,
+  /// The far horizon, past the polygonal terrain.
+  pub horizon                  : Mutex<horizon_loader::T>,
   /// The number of terrain requests that are outstanding,
   pub pending_terrain_requests : Mutex<u32>,
   #[allow(missing_docs)]
   pub rng                      : Mutex<rand::XorShiftRng>,
+  /// Settings persisted between runs.
+  pub settings                 : Mutex<settings::T>,
+  /// The active resource packs, which override the default assets.
+  pub resource_packs           : Mutex<resource_pack::T>,
+  /// The names of the shapes the server lets players place, in order.
+  pub shapes                   : Mutex<Vec<String>>,
+  /// The name of the shape to place with `PlaceShape`, if the server has any.
+  pub placement_shape          : Mutex<Option<String>>,
+  /// Playback controls, when this client is watching a recording rather than playing.
+  pub replay                   : Option<Mutex<replay::Controls>>,
+  /// The screenshot test being run on the recording, if there is one.
+  pub screenshot_test          : Option<Mutex<view::screenshot::T>>,
+  /// The latest simulation step the server has started.
+  pub server_tick              : Mutex<u64>,
+  /// The trader being traded with, and how many offers they made.
+  pub trade                    : Mutex<Option<(entity::id::Mob, usize)>>,
+  /// Whether the server says the player is flying.
+  pub flying                   : Mutex<bool>,
+  /// The chat message being typed, if there is one.
+  pub chat                     : Mutex<Option<text_entry::T>>,
+  /// The resource pack the server requires, while it's being offered to the player or fetched.
+  pub server_pack              : Mutex<Option<resource_pack::ServerPack>>,
 }
 
 fn load_distance(mut polygon_budget: i32) -> u32 {
@@ -91,7 +126,13 @@ fn load_distance(mut polygon_budget: i32) -> u32 {
 }
 
 #[allow(missing_docs)]
-pub fn new(client_id: protocol::ClientId, player_id: view::entity::id::Player, position: Point3<f32>) -> T {
+pub fn new(
+  client_id: protocol::ClientId,
+  player_id: view::entity::id::Player,
+  position: Point3<f32>,
+  server_url: &str,
+  settings: settings::T,
+) -> T {
   let mut rng: rand::XorShiftRng = rand::SeedableRng::from_seed([1, 2, 3, 4]);
   let s1 = rng.next_u32();
   let s2 = rng.next_u32();
@@ -99,7 +140,7 @@ pub fn new(client_id: protocol::ClientId, player_id: view::entity::id::Player, p
   let s4 = rng.next_u32();
   rng.reseed([s1, s2, s3, s4]);
 
-  let mut load_distance = load_distance(view::terrain_buffers::POLYGON_BUDGET as i32);
+  let mut load_distance = load_distance(view::terrain_buffers::polygon_budget(settings.vram_budget()) as i32);
 
   if load_distance > MAX_LOAD_DISTANCE {
     info!("load_distance {} capped at {}", load_distance, MAX_LOAD_DISTANCE);
@@ -108,6 +149,20 @@ pub fn new(client_id: protocol::ClientId, player_id: view::entity::id::Player, p
     info!("load_distance {}", load_distance);
   }
 
+  if let Some(&distance) = settings.render_distances.get(server_url) {
+    if distance < load_distance {
+      info!("load_distance {} reduced to {} for {}", load_distance, distance, server_url);
+      load_distance = distance;
+    }
+  }
+
+  let terrain_cache =
+    if settings.terrain_cache {
+      Some(terrain_cache::new(&data_dir::dir(data_dir::Kind::Cache).join(terrain_cache::ROOT), server_url))
+    } else {
+      None
+    };
+
   let surroundings_loader = {
     surroundings_loader::new(
       load_distance,
@@ -125,8 +180,36 @@ pub fn new(client_id: protocol::ClientId, player_id: view::entity::id::Player, p
     grass_allocator          : Mutex::new(id_allocator::new()),
     surroundings_loader      : Mutex::new(surroundings_loader),
     max_load_distance        : load_distance,
-    terrain                  : Mutex::new(terrain::new(load_distance as u32)),
+    load_distance            : Mutex::new(load_distance),
+    terrain                  : Mutex::new(terrain::new(load_distance as u32, terrain_cache)),
+    horizon                  : Mutex::new(horizon_loader::new()),
     pending_terrain_requests : Mutex::new(0),
     rng                      : Mutex::new(rng),
+    resource_packs           : Mutex::new(resource_pack::new(&settings.resource_packs)),
+    settings                 : Mutex::new(settings),
+    shapes                   : Mutex::new(Vec::new()),
+    placement_shape          : Mutex::new(None),
+    replay                   : None,
+    screenshot_test          : None,
+    server_tick              : Mutex::new(0),
+    trade                    : Mutex::new(None),
+    flying                   : Mutex::new(false),
+    chat                     : Mutex::new(None),
+    server_pack              : Mutex::new(None),
+  }
+}
+
+impl T {
+  /// Load terrain no farther out than the server says it can serve.
+  pub fn limit_load_distance(&self, server_max: u32) {
+    let distance = std::cmp::min(self.max_load_distance, server_max);
+    let mut load_distance = self.load_distance.lock().unwrap();
+    if *load_distance == distance {
+      return
+    }
+    info!("load_distance {} limited to {} by the server", self.max_load_distance, distance);
+    *load_distance = distance;
+    self.surroundings_loader.lock().unwrap().set_max_load_distance(distance);
+    self.terrain.lock().unwrap().set_max_load_distance(distance);
   }
 }
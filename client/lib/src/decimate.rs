@@ -0,0 +1,228 @@
+//! Polygon reduction for terrain meshes. Vertices in the middle of flat, single-material patches don't
+//! add anything to the shape, so they're collapsed into a neighbor, leaving a few big triangles where
+//! dual contouring produced a grid of small ones.
+//! Vertices on the border of the mesh are never moved, so neighboring chunks and skirts still line up.
+
+use cgmath::{Point3, Vector3, InnerSpace};
+
+use common::fnv_map;
+use common::voxel;
+
+use terrain_mesh::{Triangle, tri};
+
+/// Faces whose normals are at least this close (as a cosine) count as coplanar.
+const COPLANAR_COS: f32 = 0.9999;
+
+/// Triangles with less than this much area (times two) count as degenerate.
+const MIN_DOUBLE_AREA: f32 = 1e-6;
+
+#[allow(missing_docs)]
+pub type Polygon = (Triangle<Point3<f32>>, Triangle<Vector3<f32>>, voxel::Material);
+
+struct Mesh {
+  positions : Vec<Point3<f32>>,
+  normals   : Vec<Vector3<f32>>,
+  /// Vertex indices of each triangle, or None if it's been collapsed away.
+  triangles : Vec<Option<[usize; 3]>>,
+  materials : Vec<voxel::Material>,
+  /// The triangles using each vertex. May include triangles that have since been collapsed.
+  incident  : Vec<Vec<usize>>,
+}
+
+impl Mesh {
+  /// Unnormalized face normal, or None if the triangle is degenerate.
+  fn face_normal(&self, t: &[usize; 3]) -> Option<Vector3<f32>> {
+    let n = (self.positions[t[1]] - self.positions[t[0]]).cross(self.positions[t[2]] - self.positions[t[0]]);
+    if n.magnitude() < MIN_DOUBLE_AREA {
+      None
+    } else {
+      Some(n)
+    }
+  }
+
+  fn live_incident(&self, v: usize) -> Vec<usize> {
+    self.incident[v].iter()
+      .cloned()
+      .filter(|&t| self.triangles[t].map_or(false, |t| t.contains(&v)))
+      .collect()
+  }
+
+  /// If `v` sits in the middle of a flat patch of one material, the triangles around it.
+  fn flat_fan(&self, v: usize) -> Option<Vec<usize>> {
+    let fan = self.live_incident(v);
+    let first = match fan.first() { None => return None, Some(&t) => t };
+    let material = self.materials[first];
+    let normal = match self.face_normal(&self.triangles[first].unwrap()) { None => return None, Some(n) => n.normalize() };
+    for &t in &fan {
+      if self.materials[t] != material {
+        return None
+      }
+      match self.face_normal(&self.triangles[t].unwrap()) {
+        Some(n) if n.normalize().dot(normal) >= COPLANAR_COS => {},
+        _ => return None,
+      }
+    }
+    Some(fan)
+  }
+
+  /// Move `v` onto `u`, if that leaves every remaining triangle facing the same way with some area.
+  fn try_collapse(&mut self, fan: &[usize], v: usize, u: usize) -> bool {
+    let mut moved = Vec::new();
+    for &t in fan {
+      let old = self.triangles[t].unwrap();
+      if old.contains(&u) {
+        continue
+      }
+      let mut new = old;
+      for i in new.iter_mut() {
+        if *i == v {
+          *i = u;
+        }
+      }
+      match (self.face_normal(&old), self.face_normal(&new)) {
+        (Some(old_n), Some(new_n)) if old_n.dot(new_n) > 0.0 => moved.push((t, new)),
+        _ => return false,
+      }
+    }
+
+    for &t in fan {
+      if self.triangles[t].unwrap().contains(&u) {
+        self.triangles[t] = None;
+      }
+    }
+    for (t, new) in moved {
+      self.triangles[t] = Some(new);
+      self.incident[u].push(t);
+    }
+    true
+  }
+}
+
+/// Collapse vertices in flat, single-material patches, returning the reduced set of polygons.
+pub fn coplanar(polygons: &[Polygon]) -> Vec<Polygon> {
+  // Vertices shared between polygons are bit-identical, so quantizing is enough to match them up.
+  let key = |p: &Point3<f32>| {
+    ((p.x * 1024.0).round() as i64, (p.y * 1024.0).round() as i64, (p.z * 1024.0).round() as i64)
+  };
+
+  let mut mesh =
+    Mesh {
+      positions : Vec::new(),
+      normals   : Vec::new(),
+      triangles : Vec::with_capacity(polygons.len()),
+      materials : Vec::with_capacity(polygons.len()),
+      incident  : Vec::new(),
+    };
+  let mut index_of = fnv_map::new();
+  for (t, &(ref vertices, ref normals, material)) in polygons.iter().enumerate() {
+    let mut indices = [0; 3];
+    for (i, &(p, n)) in [(vertices.v1, normals.v1), (vertices.v2, normals.v2), (vertices.v3, normals.v3)].iter().enumerate() {
+      let next = mesh.positions.len();
+      let index = *index_of.entry(key(&p)).or_insert(next);
+      if index == next {
+        mesh.positions.push(p);
+        mesh.normals.push(n);
+        mesh.incident.push(Vec::new());
+      }
+      mesh.incident[index].push(t);
+      indices[i] = index;
+    }
+    mesh.triangles.push(Some(indices));
+    mesh.materials.push(material);
+  }
+
+  // Vertices on edges not shared by exactly two triangles are on the border of the mesh (or somewhere
+  // it isn't a simple surface), so they're left alone.
+  let mut uses = fnv_map::new();
+  for t in mesh.triangles.iter().filter_map(|t| *t) {
+    for i in 0 .. 3 {
+      let (a, b) = (t[i], t[(i + 1) % 3]);
+      *uses.entry(if a < b { (a, b) } else { (b, a) }).or_insert(0) += 1;
+    }
+  }
+  let mut border = vec!(false; mesh.positions.len());
+  for (&(a, b), &count) in &uses {
+    if count == 2 {
+      continue
+    }
+    border[a] = true;
+    border[b] = true;
+  }
+
+  for v in 0 .. mesh.positions.len() {
+    if border[v] {
+      continue
+    }
+    let fan =
+      match mesh.flat_fan(v) {
+        None => continue,
+        Some(fan) => fan,
+      };
+    let mut neighbors: Vec<usize> =
+      fan.iter()
+      .flat_map(|&t| mesh.triangles[t].unwrap().to_vec())
+      .filter(|&u| u != v)
+      .collect();
+    neighbors.sort();
+    neighbors.dedup();
+    for u in neighbors {
+      if mesh.try_collapse(&fan, v, u) {
+        break
+      }
+    }
+  }
+
+  mesh.triangles.iter().zip(mesh.materials.iter())
+    .filter_map(|(t, &material)| {
+      t.map(|t| {
+        (
+          tri(mesh.positions[t[0]], mesh.positions[t[1]], mesh.positions[t[2]]),
+          tri(mesh.normals[t[0]], mesh.normals[t[1]], mesh.normals[t[2]]),
+          material,
+        )
+      })
+    })
+    .collect()
+}
+
+#[cfg(test)]
+fn grid(n: usize) -> Vec<Polygon> {
+  let up = Vector3::new(0.0, 1.0, 0.0);
+  let p = |x: usize, z: usize| Point3::new(x as f32, 0.0, z as f32);
+  let mut polygons = Vec::new();
+  for x in 0 .. n {
+  for z in 0 .. n {
+    polygons.push((tri(p(x, z), p(x, z + 1), p(x + 1, z)), tri(up, up, up), voxel::Material::Stone));
+    polygons.push((tri(p(x + 1, z), p(x, z + 1), p(x + 1, z + 1)), tri(up, up, up), voxel::Material::Stone));
+  }}
+  polygons
+}
+
+#[cfg(test)]
+fn area(polygons: &[Polygon]) -> f32 {
+  polygons.iter()
+    .map(|&(ref t, _, _)| (t.v2 - t.v1).cross(t.v3 - t.v1).magnitude() / 2.0)
+    .sum()
+}
+
+#[test]
+fn flat_patches_shrink() {
+  let polygons = grid(8);
+  let reduced = coplanar(&polygons);
+  assert!(reduced.len() * 2 < polygons.len());
+  assert!((area(&reduced) - area(&polygons)).abs() < 0.001);
+}
+
+#[test]
+fn different_materials_are_kept_apart() {
+  let mut polygons = grid(4);
+  let n = polygons.len();
+  for polygon in polygons[n / 2 ..].iter_mut() {
+    polygon.2 = voxel::Material::Sand;
+  }
+  let reduced = coplanar(&polygons);
+  let sand = |polygons: &[Polygon]| -> Vec<Polygon> {
+    polygons.iter().cloned().filter(|p| p.2 == voxel::Material::Sand).collect()
+  };
+  assert!((area(&sand(&reduced)) - area(&sand(&polygons))).abs() < 0.001);
+}
@@ -0,0 +1,63 @@
+//! Which billboards decorate which terrain polygons: grass tufts, and now and then a flower.
+//! Decorations only grow on grassy terrain that's flat enough to stand on.
+
+use cgmath::{Vector3, InnerSpace};
+use rand;
+
+use common::voxel;
+
+use terrain_mesh::Triangle;
+
+/// Polygons whose normals are further than this (as a cosine) from straight up don't get decorations.
+const MIN_UP_COS: f32 = 0.6;
+
+/// Number of grass variants in the grass texture.
+pub const GRASS_VARIANTS: u32 = 9;
+
+/// Number of flower colors. Flowers are drawn procedurally by the billboard shader,
+/// and their texture ids come right after the grass variants.
+pub const FLOWER_VARIANTS: u32 = 4;
+
+/// Chance that a decoration is a flower rather than grass.
+const FLOWER_CHANCE: f32 = 0.08;
+
+/// The billboard texture id to decorate a polygon with, if it gets one.
+pub fn tex_id_for<Rng: rand::Rng>(
+  material : voxel::Material,
+  normals  : &Triangle<Vector3<f32>>,
+  rng      : &mut Rng,
+) -> Option<u32> {
+  if material != voxel::Material::Terrain {
+    return None
+  }
+  let normal = (normals.v1 + normals.v2 + normals.v3).normalize();
+  if normal.dot(Vector3::new(0.0, 1.0, 0.0)) < MIN_UP_COS {
+    return None
+  }
+  if rng.gen::<f32>() < FLOWER_CHANCE {
+    Some(GRASS_VARIANTS + rng.gen_range(0, FLOWER_VARIANTS))
+  } else {
+    Some(rng.gen_range(0, GRASS_VARIANTS))
+  }
+}
+
+#[test]
+fn only_flat_grassy_polygons_are_decorated() {
+  use rand::SeedableRng;
+  use terrain_mesh::tri;
+
+  let mut rng: rand::XorShiftRng = rand::SeedableRng::from_seed([1, 2, 3, 4]);
+  let up = Vector3::new(0.0, 1.0, 0.0);
+  let wall = Vector3::new(1.0, 0.0, 0.0);
+
+  assert!(tex_id_for(voxel::Material::Terrain, &tri(wall, wall, wall), &mut rng).is_none());
+  assert!(tex_id_for(voxel::Material::Stone, &tri(up, up, up), &mut rng).is_none());
+
+  let ids: Vec<u32> =
+    (0 .. 1000)
+    .map(|_| tex_id_for(voxel::Material::Terrain, &tri(up, up, up), &mut rng).unwrap())
+    .collect();
+  assert!(ids.iter().all(|&id| id < GRASS_VARIANTS + FLOWER_VARIANTS));
+  assert!(ids.iter().any(|&id| id >= GRASS_VARIANTS));
+  assert!(ids.iter().any(|&id| id < GRASS_VARIANTS));
+}
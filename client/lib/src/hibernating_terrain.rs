@@ -0,0 +1,116 @@
+//! Voxels for chunks outside the load radius, kept compressed in memory.
+//! Far terrain is rarely touched, so it's cheaper to decompress a chunk on the way back in
+//! than to keep every voxel we've ever seen in the voxel tree, or to ask the server for it again.
+
+use bincode;
+use lz4;
+
+use common::fnv_map;
+use common::telemetry;
+use common::voxel;
+
+use chunk;
+
+#[allow(missing_docs)]
+pub struct T {
+  chunks : fnv_map::T<chunk::position::T, Vec<u8>>,
+  /// Total compressed size of everything in `chunks`.
+  bytes  : usize,
+  /// Chunk loads that were served from hibernating voxels.
+  hits   : u64,
+  /// Chunk loads that had to go to the server.
+  misses : u64,
+}
+
+#[allow(missing_docs)]
+pub fn new() -> T {
+  T {
+    chunks : fnv_map::new(),
+    bytes  : 0,
+    hits   : 0,
+    misses : 0,
+  }
+}
+
+impl T {
+  /// Compress and store a chunk's voxels.
+  pub fn insert(&mut self, chunk_position: chunk::position::T, voxels: &Vec<(voxel::bounds::T, voxel::T)>) {
+    let compressed =
+      bincode::serialize(voxels, bincode::Infinite)
+      .map_err(|err| format!("{:?}", err))
+      .and_then(|bytes| lz4::block::compress(&bytes, None, true).map_err(|err| format!("{:?}", err)));
+    let compressed =
+      match compressed {
+        Ok(compressed) => compressed,
+        Err(err) => {
+          warn!("Error hibernating {:?}: {}", chunk_position, err);
+          return
+        },
+      };
+
+    self.bytes += compressed.len();
+    if let Some(previous) = self.chunks.insert(chunk_position, compressed) {
+      self.bytes -= previous.len();
+    }
+  }
+
+  /// Take a chunk's voxels back out, if they're here.
+  pub fn take(&mut self, chunk_position: &chunk::position::T) -> Option<Vec<(voxel::bounds::T, voxel::T)>> {
+    let compressed =
+      match self.chunks.remove(chunk_position) {
+        None => return None,
+        Some(compressed) => compressed,
+      };
+    self.bytes -= compressed.len();
+
+    let voxels =
+      lz4::block::decompress(&compressed, None)
+      .map_err(|err| format!("{:?}", err))
+      .and_then(|bytes| bincode::deserialize(&bytes).map_err(|err| format!("{:?}", err)));
+    match voxels {
+      Ok(voxels) => Some(voxels),
+      Err(err) => {
+        warn!("Error waking up {:?}: {}", chunk_position, err);
+        None
+      },
+    }
+  }
+
+  #[allow(missing_docs)]
+  pub fn contains(&self, chunk_position: &chunk::position::T) -> bool {
+    self.chunks.contains_key(chunk_position)
+  }
+
+  #[allow(missing_docs)]
+  pub fn record_hit(&mut self) {
+    self.hits += 1;
+  }
+
+  #[allow(missing_docs)]
+  pub fn record_miss(&mut self) {
+    self.misses += 1;
+  }
+
+  /// Add hibernation stats to a telemetry report.
+  pub fn report(&self, report: &mut telemetry::Report) {
+    report.push("hibernating_chunks", self.chunks.len());
+    report.push("hibernating_bytes", self.bytes);
+    report.push("hibernation_hits", self.hits);
+    report.push("hibernation_misses", self.misses);
+  }
+}
+
+#[test]
+fn round_trip() {
+  let mut hibernating = new();
+  let chunk_position = chunk::position::new(1, -2, 3);
+  let voxels = vec!(
+    (voxel::bounds::new(8, -16, 24, 0), voxel::Volume(voxel::Material::Stone)),
+    (voxel::bounds::new(9, -16, 24, 0), voxel::Volume(voxel::Material::Empty)),
+  );
+  hibernating.insert(chunk_position, &voxels);
+  assert!(hibernating.contains(&chunk_position));
+  assert!(hibernating.take(&chunk_position) == Some(voxels));
+  assert!(!hibernating.contains(&chunk_position));
+  assert_eq!(hibernating.bytes, 0);
+}
@@ -0,0 +1,99 @@
+//! Keeps the far horizon filled in around the player: tiles are requested from the server as they
+//! come into range past the polygonal terrain, handed to the view as they arrive, and dropped once out of range.
+
+use cgmath::Point3;
+
+use common::fnv_set;
+use common::horizon;
+use common::protocol;
+
+use chunk;
+use view;
+
+/// How far out to draw the horizon. Nothing past the far clip plane is drawn anyway.
+const MAX_DISTANCE: f32 = 2048.0;
+
+/// Tiles are only dropped once they're this far out of range, so they don't flicker in and out
+/// as the player wanders back and forth across a tile boundary.
+const MARGIN: f32 = horizon::TILE_WIDTH;
+
+#[allow(missing_docs)]
+pub struct T {
+  /// Tiles that have been asked for, whether or not they've arrived.
+  requested   : fnv_set::T<horizon::Tile>,
+  /// Tiles that have been sent to the view.
+  loaded      : fnv_set::T<horizon::Tile>,
+  /// The tile the player was in, and the load distance, when the horizon was last updated.
+  last_update : Option<(horizon::Tile, u32)>,
+}
+
+#[allow(missing_docs)]
+pub fn new() -> T {
+  T {
+    requested   : fnv_set::new(),
+    loaded      : fnv_set::new(),
+    last_update : None,
+  }
+}
+
+impl T {
+  /// Request the tiles needed around `position`, given that polygonal terrain is loaded out to
+  /// `load_distance` chunks, and drop the ones that have gone out of range.
+  pub fn update<UpdateView, UpdateServer>(
+    &mut self,
+    client_id     : protocol::ClientId,
+    position      : &Point3<f32>,
+    load_distance : u32,
+    update_view   : &mut UpdateView,
+    update_server : &mut UpdateServer,
+  ) where
+    UpdateView   : FnMut(view::update::T),
+    UpdateServer : FnMut(protocol::ClientToServer),
+  {
+    let center = horizon::Tile::of_world_position(position);
+    if self.last_update == Some((center, load_distance)) {
+      return
+    }
+    let start = (load_distance * chunk::WIDTH) as f32;
+    if self.last_update.map(|(_, distance)| distance) != Some(load_distance) {
+      update_view(view::update::SetHorizonStart(start));
+    }
+    self.last_update = Some((center, load_distance));
+
+    let keep: fnv_set::T<horizon::Tile> =
+      horizon::tiles_between(position, start - MARGIN, MAX_DISTANCE + MARGIN).into_iter().collect();
+    for &tile in self.loaded.iter() {
+      if !keep.contains(&tile) {
+        update_view(view::update::UnloadHorizon(tile));
+      }
+    }
+    self.loaded.retain(|tile| keep.contains(tile));
+    self.requested.retain(|tile| keep.contains(tile));
+
+    for tile in horizon::tiles_between(position, start, MAX_DISTANCE) {
+      if self.requested.insert(tile) {
+        update_server(protocol::ClientToServer::RequestHorizon(client_id, tile));
+      }
+    }
+  }
+
+  /// Pass a tile that's arrived from the server on to the view, unless it's gone out of range in the meantime.
+  pub fn insert<UpdateView>(
+    &mut self,
+    tile        : horizon::Tile,
+    columns     : Vec<horizon::Column>,
+    update_view : &mut UpdateView,
+  ) where
+    UpdateView : FnMut(view::update::T),
+  {
+    if !self.requested.contains(&tile) {
+      return
+    }
+    if columns.len() != horizon::SAMPLES * horizon::SAMPLES {
+      warn!("Ignoring horizon tile {:?} with {} columns", tile, columns.len());
+      return
+    }
+    self.loaded.insert(tile);
+    update_view(view::update::LoadHorizon(tile, columns));
+  }
+}
@@ -1,23 +1,146 @@
 //! HUD initialization code.
 
 use cgmath::Point2;
+use time;
 
 use common::color::Color4;
+use common::protocol;
 
+use material_colors;
 use vertex::{ColoredVertex};
 use view;
 
+/// Index of the first vertex of the VRAM usage bar's fill, after the cursor and the bar's background.
+const VRAM_FILL_VERTEX: usize = 12;
+
+/// Index of the first vertex of the boss health bar's background, after the VRAM usage bar.
+/// Its fill follows.
+const BOSS_BAR_VERTEX: usize = 18;
+
+/// Index of the first vertex of the hotbar, after the boss health bar. Each slot has a background,
+/// a swatch of the material in it, and a bar showing how full it is.
+const HOTBAR_VERTEX: usize = 30;
+
+const VERTICES_PER_SLOT: usize = 18;
+
+/// The most of one material a slot holds. The server decides this; it's only used to show how full
+/// slots are.
+const MAX_STACK: u32 = 64;
+
+/// The boss health bar is hidden if the server hasn't mentioned the boss for this long, in ns,
+/// e.g. because the player has left the fight.
+const BOSS_BAR_TIMEOUT_NS: u64 = 3_000_000_000;
+
+/// Corners of the VRAM usage bar, in the bottom left of the screen.
+fn vram_bar(view: &view::T) -> (Point2<f32>, Point2<f32>) {
+  let left = -(view.window_size.x as f32 / view.window_size.y as f32) + 0.05;
+  (Point2 { x: left, y: -0.95 }, Point2 { x: left + 0.4, y: -0.93 })
+}
+
+/// Corners of the boss health bar, across the top of the screen.
+fn boss_bar() -> (Point2<f32>, Point2<f32>) {
+  (Point2 { x: -0.6, y: 0.88 }, Point2 { x: 0.6, y: 0.92 })
+}
+
+/// Corners of an inventory slot in the hotbar, along the bottom of the screen.
+fn hotbar_slot(i: usize) -> (Point2<f32>, Point2<f32>) {
+  let size = 0.1;
+  let gap = 0.02;
+  let left = -(protocol::INVENTORY_SLOTS as f32 * (size + gap) - gap) / 2.0 + i as f32 * (size + gap);
+  (Point2 { x: left, y: -0.9 }, Point2 { x: left + size, y: -0.9 + size })
+}
+
+/// The hotbar's triangles, for what's in the player's inventory.
+fn hotbar(view: &view::T) -> Vec<ColoredVertex> {
+  let mut triangles = Vec::with_capacity(protocol::INVENTORY_SLOTS * VERTICES_PER_SLOT);
+  for i in 0 .. protocol::INVENTORY_SLOTS {
+    let (low, high) = hotbar_slot(i);
+    let background =
+      if i == view.selected_slot {
+        Color4::of_rgba(1.0, 1.0, 1.0, 0.6)
+      } else {
+        Color4::of_rgba(0.0, 0.0, 0.0, 0.4)
+      };
+    triangles.extend_from_slice(&ColoredVertex::square(low, high, background));
+    let inset = 0.015;
+    let swatch_low = Point2 { x: low.x + inset, y: low.y + 2.0 * inset };
+    let swatch_high = Point2 { x: high.x - inset, y: high.y - inset };
+    match view.inventory.get(i).and_then(|slot| *slot) {
+      None => {
+        for _ in 0 .. 2 {
+          triangles.extend_from_slice(&ColoredVertex::square(low, low, Color4::of_rgba(0.0, 0.0, 0.0, 0.0)));
+        }
+      },
+      Some(stack) => {
+        triangles.extend_from_slice(&ColoredVertex::square(swatch_low, swatch_high, material_colors::of(stack.material)));
+        let full = (stack.count as f32 / MAX_STACK as f32).min(1.0);
+        let bar_low = Point2 { x: swatch_low.x, y: low.y + 0.5 * inset };
+        let bar_high = Point2 { x: swatch_low.x + (swatch_high.x - swatch_low.x) * full, y: low.y + 1.5 * inset };
+        triangles.extend_from_slice(&ColoredVertex::square(bar_low, bar_high, Color4::of_rgba(1.0, 1.0, 1.0, 0.9)));
+      },
+    }
+  }
+  triangles
+}
+
 /// Add HUD data into `view`.
 pub fn make_hud<'a, 'b:'a>(view: &'a mut view::T<'b>) {
   let cursor_color = Color4::of_rgba(0.0, 0.0, 0.0, 0.75);
 
-  let triangles: Vec<_> =
+  let (low, high) = vram_bar(view);
+
+  let mut triangles: Vec<_> =
     ColoredVertex::square(
       Point2 { x: -0.02, y: -0.02 },
       Point2 { x:  0.02, y:  0.02 },
       cursor_color
     ).iter().cloned().collect();
+  triangles.extend_from_slice(&ColoredVertex::square(low, high, Color4::of_rgba(0.0, 0.0, 0.0, 0.4)));
+  assert_eq!(triangles.len(), VRAM_FILL_VERTEX);
+  triangles.extend_from_slice(&ColoredVertex::square(low, low, Color4::of_rgba(0.0, 0.0, 0.0, 0.0)));
+  assert_eq!(triangles.len(), BOSS_BAR_VERTEX);
+  let (low, _) = boss_bar();
+  for _ in 0 .. 2 {
+    triangles.extend_from_slice(&ColoredVertex::square(low, low, Color4::of_rgba(0.0, 0.0, 0.0, 0.0)));
+  }
+  assert_eq!(triangles.len(), HOTBAR_VERTEX);
+  triangles.extend(hotbar(view));
 
   view.hud_triangles.bind(&mut view.gl);
   view.hud_triangles.push(&mut view.gl, triangles.as_ref());
+  view.hud_vertex_count += triangles.len();
+}
+
+/// Update the parts of the HUD that change: how full the terrain VRAM budget is, the health of any
+/// boss being fought, and the hotbar.
+pub fn update_hud<'a, 'b:'a>(view: &'a mut view::T<'b>) {
+  let used = view.vram.used_fraction().min(1.0);
+  let (low, high) = vram_bar(view);
+  let high = Point2 { x: low.x + (high.x - low.x) * used, y: high.y };
+  let fill = ColoredVertex::square(low, high, Color4::of_rgba(used, 1.0 - used, 0.0, 0.75));
+
+  view.hud_triangles.buffer.byte_buffer.bind(&mut view.gl);
+  view.hud_triangles.buffer.update(&mut view.gl, VRAM_FILL_VERTEX, &fill);
+
+  let now = time::precise_time_ns();
+  let (low, high) = boss_bar();
+  let mut boss_bar: Vec<_> =
+    match view.boss_health {
+      Some((health, heard_ns)) if now.saturating_sub(heard_ns) < BOSS_BAR_TIMEOUT_NS => {
+        let fill_high = Point2 { x: low.x + (high.x - low.x) * health, y: high.y };
+        ColoredVertex::square(low, high, Color4::of_rgba(0.0, 0.0, 0.0, 0.5)).iter().cloned()
+        .chain(ColoredVertex::square(low, fill_high, Color4::of_rgba(0.7, 0.05, 0.05, 0.85)).iter().cloned())
+        .collect()
+      },
+      _ => Vec::new(),
+    };
+  if boss_bar.is_empty() {
+    for _ in 0 .. 2 {
+      boss_bar.extend_from_slice(&ColoredVertex::square(low, low, Color4::of_rgba(0.0, 0.0, 0.0, 0.0)));
+    }
+  }
+  view.hud_triangles.buffer.update(&mut view.gl, BOSS_BAR_VERTEX, &boss_bar);
+
+  let hotbar = hotbar(view);
+  view.hud_triangles.buffer.update(&mut view.gl, HOTBAR_VERTEX, &hotbar);
 }
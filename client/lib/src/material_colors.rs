@@ -0,0 +1,29 @@
+//! Flat colors for materials, for where they're drawn untextured: the hotbar, and dropped items.
+
+use common::color::Color4;
+use common::voxel;
+
+/// The color a material is shown as.
+pub fn of(material: voxel::Material) -> Color4<f32> {
+  let (r, g, b) =
+    match material {
+      voxel::Material::Terrain => (0.35, 0.5, 0.2),
+      voxel::Material::Bark => (0.4, 0.25, 0.1),
+      voxel::Material::Leaves => (0.2, 0.6, 0.15),
+      voxel::Material::Stone => (0.5, 0.5, 0.5),
+      voxel::Material::Marble => (0.9, 0.9, 0.85),
+      voxel::Material::Sand => (0.85, 0.8, 0.5),
+      voxel::Material::Snow => (0.95, 0.97, 1.0),
+      voxel::Material::Gravel => (0.35, 0.33, 0.3),
+      voxel::Material::Wire | voxel::Material::PoweredWire => (0.7, 0.1, 0.1),
+      voxel::Material::Lever | voxel::Material::PoweredLever => (0.55, 0.4, 0.25),
+      voxel::Material::PressurePlate | voxel::Material::PoweredPressurePlate => (0.6, 0.6, 0.55),
+      voxel::Material::Lamp | voxel::Material::LitLamp => (1.0, 0.85, 0.4),
+      voxel::Material::Piston | voxel::Material::ExtendedPiston | voxel::Material::PistonHead => (0.65, 0.55, 0.4),
+      voxel::Material::Coal => (0.1, 0.1, 0.1),
+      voxel::Material::Iron => (0.7, 0.6, 0.55),
+      voxel::Material::Crystal => (0.5, 0.9, 1.0),
+      voxel::Material::Empty | voxel::Material::Water => (0.0, 0.0, 0.0),
+    };
+  Color4::of_rgba(r, g, b, 1.0)
+}
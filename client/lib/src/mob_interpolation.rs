@@ -0,0 +1,124 @@
+//! Smooth out mob motion between server updates.
+//! Mobs are drawn slightly in the past, interpolating between the transforms the server sent.
+//! If updates stop arriving, mobs keep moving along their last velocity for a little while.
+
+use cgmath::{Point3, EuclideanSpace};
+use std;
+use std::collections::VecDeque;
+
+use common::protocol::MobTransform;
+
+/// How far in the past mobs are drawn, so there's usually a later update to interpolate towards.
+pub const DELAY_NS: u64 = 100_000_000;
+
+/// The longest we'll extrapolate past the newest update.
+pub const MAX_EXTRAPOLATION_NS: u64 = 250_000_000;
+
+/// How many updates to keep around.
+const MAX_SNAPSHOTS: usize = 16;
+
+#[allow(missing_docs)]
+pub struct T {
+  /// Transforms along with when they arrived, oldest first.
+  snapshots: VecDeque<(u64, MobTransform)>,
+}
+
+#[allow(missing_docs)]
+pub fn new() -> T {
+  T {
+    snapshots: VecDeque::new(),
+  }
+}
+
+fn lerp_yaw(a: f32, b: f32, t: f32) -> f32 {
+  // Turn the short way around.
+  let tau = 2.0 * std::f32::consts::PI;
+  let mut d = (b - a) % tau;
+  if d > std::f32::consts::PI {
+    d -= tau;
+  } else if d < -std::f32::consts::PI {
+    d += tau;
+  }
+  a + d * t
+}
+
+fn lerp(a: &MobTransform, b: &MobTransform, t: f32) -> MobTransform {
+  MobTransform {
+    position : Point3::from_vec(a.position.to_vec() + (b.position - a.position) * t),
+    yaw      : lerp_yaw(a.yaw, b.yaw, t),
+    velocity : a.velocity + (b.velocity - a.velocity) * t,
+    size     : b.size,
+  }
+}
+
+impl T {
+  /// Record a transform that arrived at `time_ns`.
+  pub fn push(&mut self, time_ns: u64, transform: MobTransform) {
+    if let Some(&(last, _)) = self.snapshots.back() {
+      if time_ns < last {
+        return
+      }
+    }
+    self.snapshots.push_back((time_ns, transform));
+    while self.snapshots.len() > MAX_SNAPSHOTS {
+      self.snapshots.pop_front();
+    }
+  }
+
+  /// Where to draw the mob at `time_ns`, if we've heard anything about it.
+  pub fn sample(&self, time_ns: u64) -> Option<MobTransform> {
+    let time_ns = time_ns.saturating_sub(DELAY_NS);
+
+    let mut prev = None;
+    for &(t, ref transform) in &self.snapshots {
+      if t >= time_ns {
+        return
+          match prev {
+            None => Some(*transform),
+            Some((prev_t, prev_transform)) => {
+              let s = (time_ns - prev_t) as f32 / (t - prev_t).max(1) as f32;
+              Some(lerp(prev_transform, transform, s))
+            },
+          };
+      }
+      prev = Some((t, transform));
+    }
+
+    prev.map(|(t, transform)| {
+      let dt = std::cmp::min(time_ns - t, MAX_EXTRAPOLATION_NS) as f32 / 1_000_000_000.0;
+      MobTransform {
+        position : transform.position + transform.velocity * dt,
+        .. *transform
+      }
+    })
+  }
+}
+
+#[cfg(test)]
+fn at(x: f32) -> MobTransform {
+  use cgmath::Vector3;
+  MobTransform {
+    position : Point3::new(x, 0.0, 0.0),
+    yaw      : 0.0,
+    velocity : Vector3::new(10.0, 0.0, 0.0),
+    size     : Vector3::new(1.0, 2.0, 1.0),
+  }
+}
+
+#[test]
+fn interpolates_between_updates() {
+  let mut mob = new();
+  mob.push(1_000_000_000, at(0.0));
+  mob.push(1_100_000_000, at(1.0));
+  let transform = mob.sample(1_050_000_000 + DELAY_NS).unwrap();
+  assert!((transform.position.x - 0.5).abs() < 0.001);
+}
+
+#[test]
+fn extrapolation_is_capped() {
+  let mut mob = new();
+  mob.push(1_000_000_000, at(0.0));
+  let transform = mob.sample(1_000_000_000 + DELAY_NS + 10 * MAX_EXTRAPOLATION_NS).unwrap();
+  let max = 10.0 * MAX_EXTRAPOLATION_NS as f32 / 1_000_000_000.0;
+  assert!((transform.position.x - max).abs() < 0.001);
+}
@@ -7,6 +7,7 @@ extern crate bincode;
 extern crate cgmath;
 extern crate collision;
 extern crate common;
+extern crate curl;
 extern crate fnv;
 extern crate gl;
 extern crate hound;
@@ -15,34 +16,51 @@ extern crate isosurface_extraction;
 #[macro_use]
 extern crate log;
 extern crate libc;
+extern crate lz4;
 extern crate num;
 #[cfg(feature="audio")]
 extern crate portaudio;
 extern crate rand;
 extern crate sdl2;
+extern crate sha2;
 extern crate stopwatch;
+extern crate tar;
 extern crate thread_scoped;
 extern crate time;
 extern crate voxel_data;
 extern crate yaglw;
+extern crate zip;
 
+pub mod atlas;
 pub mod audio;
 pub mod audio_loader;
 pub mod audio_thread;
-pub mod chunk;
 pub mod chunk_stats;
 pub mod client;
+pub mod decimate;
+pub mod decoration;
+pub mod hibernating_terrain;
+pub mod horizon_loader;
 pub mod hud;
-pub mod lod;
+pub mod material_colors;
+pub mod mob_interpolation;
 pub mod process_event;
 pub mod record_book;
+pub mod replay;
+pub mod resource_pack;
 pub mod run;
 pub mod server;
 pub mod server_update;
+pub mod settings;
 pub mod terrain;
+pub mod terrain_cache;
 pub mod terrain_mesh;
+pub mod text_entry;
 pub mod update_thread;
 pub mod vertex;
 pub mod view;
+pub mod water;
 
-pub use run::run;
+pub use common::chunk;
+pub use common::lod;
+pub use run::{run, watch_replay, screenshot_test};
@@ -1,38 +1,63 @@
 //! SDL input event processing code.
 
-use cgmath::{Vector2, Vector3};
+use cgmath::{Vector2, Vector3, InnerSpace};
+use sdl2;
 use sdl2::event::Event;
 use sdl2::keyboard::Keycode;
 use sdl2::mouse::MouseButton;
 use std::f32::consts::PI;
 use stopwatch;
+use time;
 
 use common::entity;
 use common::protocol;
 
 use client;
+use resource_pack;
+use settings::Action;
+use text_entry;
 use view;
 
+/// How far ahead of the player's eyes to aim when digging or placing voxels.
+const EDIT_REACH: f32 = 8.0;
+
+/// How far away players can hit mobs. The server checks this too.
+const ATTACK_REACH: f32 = 4.0;
+
+/// The keys that accept a trader's offers, or otherwise select inventory slots, in order.
+const NUMBER_KEYS: [Keycode; 9] = [
+  Keycode::Num1, Keycode::Num2, Keycode::Num3,
+  Keycode::Num4, Keycode::Num5, Keycode::Num6,
+  Keycode::Num7, Keycode::Num8, Keycode::Num9,
+];
+
+/// How far the free camera moves per key press while watching a replay.
+const FLY_STEP: f32 = 4.0;
+
 #[allow(missing_docs)]
 pub fn process_event<UpdateServer>(
   update_server: &mut UpdateServer,
   view: &mut view::T,
   client: &client::T,
+  video: &sdl2::VideoSubsystem,
   event: Event,
 ) where UpdateServer: FnMut(protocol::ClientToServer)
 {
+  if chat_event(update_server, client, video, &event) {
+    return
+  }
   match event {
     Event::KeyDown{keycode, repeat, ..} => {
       keycode.map(|keycode| {
         if !repeat {
-          key_press(update_server, view, client, keycode);
+          key_press(update_server, view, client, video, keycode);
         }
       });
     },
     Event::KeyUp{keycode, repeat, ..} => {
       keycode.map(|keycode| {
         if !repeat {
-          key_release(client.player_id, update_server, keycode);
+          key_release(update_server, client, keycode);
         }
       });
     },
@@ -46,10 +71,72 @@ pub fn process_event<UpdateServer>(
   }
 }
 
+/// Type into the chat message, if one's being typed. Returns whether the event was used.
+fn chat_event<UpdateServer>(
+  update_server: &mut UpdateServer,
+  client: &client::T,
+  video: &sdl2::VideoSubsystem,
+  event: &Event,
+) -> bool where UpdateServer: FnMut(protocol::ClientToServer)
+{
+  let mut chat = client.chat.lock().unwrap();
+  let finished =
+    match chat.as_mut() {
+      None => return false,
+      Some(entry) => {
+        match *event {
+          Event::TextInput { ref text, .. } => {
+            entry.insert(text);
+            false
+          },
+          Event::TextEditing { ref text, .. } => {
+            entry.compose(text);
+            false
+          },
+          Event::KeyDown { keycode: Some(Keycode::Return), .. } |
+          Event::KeyDown { keycode: Some(Keycode::KpEnter), .. } |
+          Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
+            true
+          },
+          Event::KeyDown { keycode: Some(Keycode::Backspace), .. } => {
+            entry.backspace();
+            false
+          },
+          Event::KeyDown { keycode: Some(Keycode::V), keymod, .. }
+            if keymod.intersects(sdl2::keyboard::LCTRLMOD | sdl2::keyboard::RCTRLMOD) => {
+            match video.clipboard().clipboard_text() {
+              Ok(text) => entry.insert(&text),
+              Err(err) => warn!("Couldn't paste: {}", err),
+            }
+            false
+          },
+          // Keys type text rather than doing what they usually do.
+          Event::KeyDown { .. } | Event::KeyUp { .. } => false,
+          _ => return false,
+        }
+      },
+    };
+  if finished {
+    video.text_input().stop();
+    let entry = chat.take().unwrap();
+    let send =
+      match *event {
+        Event::KeyDown { keycode: Some(Keycode::Escape), .. } => false,
+        _ => true,
+      };
+    let text = entry.finish();
+    if send && !text.is_empty() {
+      update_server(protocol::ClientToServer::Chat(client.player_id, text));
+    }
+  }
+  true
+}
+
 fn key_press<UpdateServer>(
   update_server: &mut UpdateServer,
   view: &mut view::T,
   client: &client::T,
+  video: &sdl2::VideoSubsystem,
   key: Keycode,
 ) where UpdateServer: FnMut(protocol::ClientToServer)
 {
@@ -82,56 +169,222 @@ fn key_press<UpdateServer>(
   };
 
   stopwatch::time("event.key_press", || {
-    match key {
-      Keycode::A => {
+    // While trading, the number keys accept offers; otherwise they select inventory slots.
+    if let Some(i) = NUMBER_KEYS.iter().position(|&k| k == key) {
+      if let Some((trader, offer_count)) = *client.trade.lock().unwrap() {
+        if i < offer_count {
+          update_server(Trade(client.player_id, trader, i as u32));
+          return
+        }
+      }
+      if i < protocol::INVENTORY_SLOTS {
+        select_slot(update_server, view, client, i);
+        return
+      }
+    }
+
+    let action =
+      match client.settings.lock().unwrap().action_for(key) {
+        None => return,
+        Some(action) => action,
+      };
+    if client.replay.is_some() {
+      if let Some(direction) = fly_direction(action) {
+        fly(view, direction);
+        return
+      }
+    }
+    match action {
+      Action::MoveLeft => {
         update_server(Walk(client.player_id, Vector3::new(-1.0, 0.0, 0.0)));
       },
-      Keycode::D => {
+      Action::MoveRight => {
         update_server(Walk(client.player_id, Vector3::new(1.0, 0.0, 0.0)));
       },
-      Keycode::Space => {
-        update_server(StartJump(client.player_id));
+      Action::Jump => {
+        if *client.flying.lock().unwrap() {
+          update_server(Walk(client.player_id, Vector3::new(0.0, 1.0, 0.0)));
+        } else {
+          update_server(StartJump(client.player_id));
+        }
+      },
+      Action::Sprint => {
+        update_server(SetMovementMode(client.player_id, protocol::MovementMode::Sprint));
       },
-      Keycode::W => {
+      Action::Crouch => {
+        if *client.flying.lock().unwrap() {
+          update_server(Walk(client.player_id, Vector3::new(0.0, -1.0, 0.0)));
+        } else {
+          update_server(SetMovementMode(client.player_id, protocol::MovementMode::Crouch));
+        }
+      },
+      Action::ToggleFly => {
+        update_server(ToggleFly(client.player_id));
+      },
+      Action::MoveForward => {
         update_server(Walk(client.player_id, Vector3::new(0.0, 0.0, -1.0)));
       },
-      Keycode::S => {
+      Action::MoveBack => {
         update_server(Walk(client.player_id, Vector3::new(0.0, 0.0, 1.0)));
       },
-      Keycode::Left => {
+      Action::LookLeft => {
         lr(update_server, view, 1.0);
       },
-      Keycode::Right => {
+      Action::LookRight => {
         lr(update_server, view, -1.0);
       },
-      Keycode::Up => {
+      Action::LookUp => {
         ud(update_server, view, 1.0);
       },
-      Keycode::Down => {
+      Action::LookDown => {
         ud(update_server, view, -1.0);
       },
-      Keycode::H => {
+      Action::RemoveVoxel => {
+        let target = view.eye + view.camera.forward() * EDIT_REACH;
+        update_server(RemoveVoxel(client.player_id, target));
+      },
+      Action::PlaceVoxel => {
+        let target = view.eye + view.camera.forward() * EDIT_REACH;
+        update_server(UseItem(client.player_id, target));
+      },
+      Action::PlaceShape => {
+        let target = view.eye + view.camera.forward() * EDIT_REACH;
+        let shape = client.placement_shape.lock().unwrap().clone();
+        if let Some(shape) = shape {
+          update_server(PlaceShape(client.player_id, target, shape));
+        }
+      },
+      Action::NextShape => {
+        let shapes = client.shapes.lock().unwrap();
+        let mut shape = client.placement_shape.lock().unwrap();
+        let next =
+          match shape.as_ref().and_then(|shape| shapes.iter().position(|s| s == shape)) {
+            None => 0,
+            Some(i) => (i + 1) % shapes.len(),
+          };
+        *shape = shapes.get(next).cloned();
+        info!("Placing {:?}", *shape);
+      },
+      Action::Rotate => {
+        let target = view.eye + view.camera.forward() * EDIT_REACH;
+        update_server(Rotate(client.player_id, target));
+      },
+      Action::NextMaterial => {
+        let slot = (view.selected_slot + 1) % protocol::INVENTORY_SLOTS;
+        select_slot(update_server, view, client, slot);
+      },
+      Action::Interact => {
+        *client.trade.lock().unwrap() = None;
+        let target = view.eye + view.camera.forward() * EDIT_REACH;
+        update_server(Interact(client.player_id, target));
+      },
+      Action::Attack => {
+        view.aimed_mob(ATTACK_REACH, time::precise_time_ns()).map(|mob_id| {
+          update_server(Attack(client.player_id, entity::EntityId::Mob(mob_id)));
+        });
+      },
+      Action::Throw => {
+        update_server(Throw(client.player_id, view.camera.forward()));
+      },
+      Action::ToggleHud => {
         view.show_hud = !view.show_hud;
       },
-      Keycode::M => {
+      Action::Chat => {
+        *client.chat.lock().unwrap() = Some(text_entry::new());
+        // Text input also turns on the IME, which mustn't grab keys meant for playing.
+        video.text_input().start();
+      },
+      // Replays have their own free camera.
+      Action::ToggleCamera if client.replay.is_none() => {
+        view.third_person = !view.third_person;
+        if !view.third_person {
+          let eye = view.eye;
+          view.camera.translate_to(eye);
+        }
+      },
+      Action::ToggleCamera => {},
+      Action::ToggleInputMode => {
         view.input_mode =
           match view.input_mode {
             view::InputMode::Camera => view::InputMode::Sun,
             view::InputMode::Sun => view::InputMode::Camera,
           };
       },
-      Keycode::P => {
+      Action::ToggleLoadPosition => {
         let mut load_position = client.load_position.lock().unwrap();
         match *load_position {
           None => *load_position = Some(*client.player_position.lock().unwrap()),
           Some(_) => *load_position = None,
         }
       },
-      _ => {},
+      Action::ReloadResources => {
+        let mut resource_packs = client.resource_packs.lock().unwrap();
+        resource_packs.reload();
+        view.reload_resources(&resource_packs);
+      },
+      Action::AcceptResourcePack => {
+        resource_pack::accept(&mut client.server_pack.lock().unwrap());
+      },
+      Action::DeclineResourcePack => {
+        resource_pack::decline(&mut client.server_pack.lock().unwrap());
+      },
+      Action::ReplayPause => {
+        client.replay.as_ref().map(|controls| {
+          let mut controls = controls.lock().unwrap();
+          controls.paused = !controls.paused;
+        });
+      },
+      Action::ReplaySlower => {
+        client.replay.as_ref().map(|controls| controls.lock().unwrap().slower());
+      },
+      Action::ReplayFaster => {
+        client.replay.as_ref().map(|controls| controls.lock().unwrap().faster());
+      },
+      Action::ReplaySkip => {
+        client.replay.as_ref().map(|controls| controls.lock().unwrap().skip());
+      },
     }
   })
 }
 
+/// Pick the inventory slot to place from.
+fn select_slot<UpdateServer>(
+  update_server: &mut UpdateServer,
+  view: &mut view::T,
+  client: &client::T,
+  slot: usize,
+) where UpdateServer: FnMut(protocol::ClientToServer)
+{
+  view.selected_slot = slot;
+  update_server(protocol::ClientToServer::SelectSlot(client.player_id, slot as u32));
+  match view.inventory[slot] {
+    None => info!("Slot {} is empty", slot + 1),
+    Some(stack) => info!("Placing {:?} ({} left)", stack.material, stack.count),
+  }
+}
+
+/// Which way a movement action flies the free camera, as (right, up, forward).
+fn fly_direction(action: Action) -> Option<Vector3<f32>> {
+  match action {
+    Action::MoveLeft    => Some(Vector3::new(-1.0, 0.0,  0.0)),
+    Action::MoveRight   => Some(Vector3::new( 1.0, 0.0,  0.0)),
+    Action::MoveForward => Some(Vector3::new( 0.0, 0.0,  1.0)),
+    Action::MoveBack    => Some(Vector3::new( 0.0, 0.0, -1.0)),
+    Action::Jump        => Some(Vector3::new( 0.0, 1.0,  0.0)),
+    _ => None,
+  }
+}
+
+/// Move the free camera relative to where it's looking.
+fn fly(view: &mut view::T, direction: Vector3<f32>) {
+  let up = Vector3::new(0.0, 1.0, 0.0);
+  let forward = view.camera.forward();
+  let right = forward.cross(up).normalize();
+  let step = (right * direction.x + up * direction.y + forward * direction.z) * FLY_STEP;
+  let position = view.camera.position + step;
+  view.camera.translate_to(position);
+}
+
 fn mouse_press<UpdateServer>(
   player_id: entity::id::Player,
   update_server: &mut UpdateServer,
@@ -156,27 +409,43 @@ fn mouse_press<UpdateServer>(
 }
 
 fn key_release<UpdateServer>(
-  player_id: entity::id::Player,
   update_server: &mut UpdateServer,
+  client: &client::T,
   key: Keycode,
 ) where UpdateServer: FnMut(protocol::ClientToServer)
 {
+  let player_id = client.player_id;
   stopwatch::time("event.key_release", || {
-    match key {
+    let action =
+      match client.settings.lock().unwrap().action_for(key) {
+        None => return,
+        Some(action) => action,
+      };
+    match action {
       // accelerations are negated from those in key_press.
-      Keycode::A => {
+      Action::MoveLeft => {
         update_server(protocol::ClientToServer::Walk(player_id, Vector3::new(1.0, 0.0, 0.0)));
       },
-      Keycode::D => {
+      Action::MoveRight => {
         update_server(protocol::ClientToServer::Walk(player_id, Vector3::new(-1.0, 0.0, 0.0)));
       },
-      Keycode::Space => {
-        update_server(protocol::ClientToServer::StopJump(player_id));
+      Action::Jump => {
+        if *client.flying.lock().unwrap() {
+          update_server(protocol::ClientToServer::Walk(player_id, Vector3::new(0.0, -1.0, 0.0)));
+        } else {
+          update_server(protocol::ClientToServer::StopJump(player_id));
+        }
+      },
+      Action::Crouch if *client.flying.lock().unwrap() => {
+        update_server(protocol::ClientToServer::Walk(player_id, Vector3::new(0.0, 1.0, 0.0)));
+      },
+      Action::Sprint | Action::Crouch => {
+        update_server(protocol::ClientToServer::SetMovementMode(player_id, protocol::MovementMode::Walk));
       },
-      Keycode::W => {
+      Action::MoveForward => {
         update_server(protocol::ClientToServer::Walk(player_id, Vector3::new(0.0, 0.0, 1.0)));
       },
-      Keycode::S => {
+      Action::MoveBack => {
         update_server(protocol::ClientToServer::Walk(player_id, Vector3::new(0.0, 0.0, -1.0)));
       },
       _ => {}
@@ -0,0 +1,180 @@
+//! Recording sessions, and playing them back.
+//! A recording is the stream of messages the server sent, each stamped with how long after the start
+//! of the session it arrived. It starts with a `PlayerAdded` saying who the player was and where they
+//! spawned. Playing one back feeds the messages through the usual client pipeline in place of a server.
+
+use bincode;
+use cgmath::Point3;
+use std;
+use std::collections::VecDeque;
+use std::io::{BufReader, BufWriter, Write};
+use time;
+
+use common::data_dir;
+use common::entity;
+use common::protocol;
+
+/// Where recordings are written.
+pub fn directory() -> std::path::PathBuf {
+  data_dir::dir(data_dir::Kind::Replays)
+}
+
+/// How far `Controls::skip` jumps ahead, in ns.
+pub const SKIP_NS: u64 = 10_000_000_000;
+
+/// Playback speeds can be adjusted within this range.
+const MIN_SPEED: f32 = 0.125;
+const MAX_SPEED: f32 = 8.0;
+
+#[allow(missing_docs)]
+pub struct Recorder {
+  file     : BufWriter<std::fs::File>,
+  start_ns : u64,
+}
+
+/// Start recording a session to a new file in `directory()`.
+pub fn record(player_id: entity::id::Player, position: Point3<f32>) -> std::io::Result<Recorder> {
+  let path = directory().join(format!("{}.replay", time::get_time().sec));
+  info!("Recording session to {}", path.display());
+  let mut recorder =
+    Recorder {
+      file     : BufWriter::new(try!(std::fs::File::create(path))),
+      start_ns : time::precise_time_ns(),
+    };
+  recorder.record(&protocol::ServerToClient::PlayerAdded(player_id, position));
+  Ok(recorder)
+}
+
+impl Recorder {
+  /// Record a message that just arrived from the server.
+  pub fn record(&mut self, msg: &protocol::ServerToClient) {
+    let time_ns = time::precise_time_ns() - self.start_ns;
+    if let Err(err) = bincode::serialize_into(&mut self.file, &(time_ns, msg), bincode::Infinite) {
+      warn!("Couldn't record message: {:?}", err);
+    }
+  }
+}
+
+impl Drop for Recorder {
+  fn drop(&mut self) {
+    if let Err(err) = self.file.flush() {
+      warn!("Couldn't finish recording: {:?}", err);
+    }
+  }
+}
+
+/// Load a recording, returning where the player spawned and the messages that followed.
+pub fn load(path: &std::path::Path) -> Result<(entity::id::Player, Point3<f32>, VecDeque<(u64, protocol::ServerToClient)>), String> {
+  let file = try!(std::fs::File::open(path).map_err(|err| format!("{}", err)));
+  let mut file = BufReader::new(file);
+  let mut messages = VecDeque::new();
+  loop {
+    match bincode::deserialize_from(&mut file, bincode::Infinite) {
+      Ok(msg) => messages.push_back(msg),
+      // Assume any error is the end of the file. A recording cut short still plays up to where it ends.
+      Err(_) => break,
+    }
+  }
+  match messages.pop_front() {
+    Some((_, protocol::ServerToClient::PlayerAdded(player_id, position))) => Ok((player_id, position, messages)),
+    _ => Err(format!("{} doesn't start with a player", path.display())),
+  }
+}
+
+/// How the viewer wants playback to go.
+pub struct Controls {
+  #[allow(missing_docs)]
+  pub paused : bool,
+  /// Multiplier for how fast recorded time passes.
+  pub speed  : f32,
+  /// Recorded time to jump ahead by, in ns.
+  skip_ns    : u64,
+}
+
+#[allow(missing_docs)]
+pub fn controls() -> Controls {
+  Controls {
+    paused  : false,
+    speed   : 1.0,
+    skip_ns : 0,
+  }
+}
+
+impl Controls {
+  #[allow(missing_docs)]
+  pub fn faster(&mut self) {
+    self.speed = f32::min(self.speed * 2.0, MAX_SPEED);
+  }
+
+  #[allow(missing_docs)]
+  pub fn slower(&mut self) {
+    self.speed = f32::max(self.speed / 2.0, MIN_SPEED);
+  }
+
+  /// Jump ahead by `SKIP_NS`. Everything in between still gets played, just all at once,
+  /// since the client can't undo messages it's already seen. For the same reason, there's no going back.
+  pub fn skip(&mut self) {
+    self.skip_ns += SKIP_NS;
+  }
+}
+
+#[allow(missing_docs)]
+pub struct Playback {
+  messages     : VecDeque<(u64, protocol::ServerToClient)>,
+  /// How far into the recording playback has got, in ns.
+  position_ns  : u64,
+  last_poll_ns : Option<u64>,
+}
+
+#[allow(missing_docs)]
+pub fn playback(messages: VecDeque<(u64, protocol::ServerToClient)>) -> Playback {
+  Playback {
+    messages     : messages,
+    position_ns  : 0,
+    last_poll_ns : None,
+  }
+}
+
+impl Playback {
+  /// Advance playback to real time `now_ns`, and take the next message that's due, if any.
+  pub fn poll(&mut self, controls: &mut Controls, now_ns: u64) -> Option<protocol::ServerToClient> {
+    if let Some(last_poll_ns) = self.last_poll_ns {
+      if !controls.paused {
+        self.position_ns += (now_ns.saturating_sub(last_poll_ns) as f32 * controls.speed) as u64;
+      }
+    }
+    self.last_poll_ns = Some(now_ns);
+    self.position_ns += controls.skip_ns;
+    controls.skip_ns = 0;
+
+    match self.messages.front() {
+      Some(&(time_ns, _)) if time_ns <= self.position_ns => {},
+      _ => return None,
+    }
+    self.messages.pop_front().map(|(_, msg)| msg)
+  }
+
+  /// Whether every message has been played.
+  pub fn is_finished(&self) -> bool {
+    self.messages.is_empty()
+  }
+}
+
+#[test]
+fn playback_follows_the_controls() {
+  let messages = vec!((0, protocol::ServerToClient::Ping), (1000, protocol::ServerToClient::Ping)).into_iter().collect();
+  let mut playback = playback(messages);
+  let mut controls = controls();
+
+  assert!(playback.poll(&mut controls, 0).is_some());
+  assert!(playback.poll(&mut controls, 500).is_none());
+
+  controls.paused = true;
+  assert!(playback.poll(&mut controls, 5000).is_none());
+
+  controls.paused = false;
+  controls.faster();
+  assert!(playback.poll(&mut controls, 5200).is_none());
+  assert!(playback.poll(&mut controls, 5250).is_some());
+  assert!(playback.is_finished());
+}
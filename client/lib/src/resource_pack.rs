@@ -0,0 +1,477 @@
+//! Resource packs override the client's textures, sounds, and shaders without recompiling.
+//! A pack is a directory under `root()` that mirrors the layout of the default assets, e.g.
+//! `resource_packs/my_pack/textures/Free_Vector_Grass.png`, or a .zip archive of one, e.g.
+//! `resource_packs/my_pack.zip`, which is unpacked into the cache whenever the packs are scanned.
+//! Assets are looked up in each active pack in priority order, falling back to the default assets.
+
+use curl;
+use sha2;
+use sha2::Digest;
+use std;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use tar;
+use zip;
+
+use common::data_dir;
+
+use settings;
+
+/// Directory containing all resource packs.
+pub fn root() -> PathBuf {
+  data_dir::dir(data_dir::Kind::ResourcePacks)
+}
+
+#[allow(missing_docs)]
+pub struct T {
+  /// Names of the active packs, highest priority first.
+  names          : Vec<String>,
+  /// Directories of the active packs that actually exist.
+  dirs           : Vec<PathBuf>,
+  /// Incremented whenever the packs are reloaded, so cached assets know to refresh.
+  pub generation : u32,
+}
+
+#[allow(missing_docs)]
+pub fn new(names: &[String]) -> T {
+  let mut packs =
+    T {
+      names      : names.to_vec(),
+      dirs       : Vec::new(),
+      generation : 0,
+    };
+  packs.scan();
+  packs
+}
+
+impl T {
+  fn scan(&mut self) {
+    self.dirs.clear();
+    for name in &self.names {
+      let dir = root().join(name);
+      let zipped = root().join(format!("{}.zip", name));
+      if dir.is_dir() {
+        info!("Using resource pack {:?}", name);
+        self.dirs.push(dir);
+      } else if zipped.is_file() {
+        let unzipped = data_dir::dir(data_dir::Kind::Cache).join("resource_packs").join(name);
+        match unzip(&zipped, &unzipped) {
+          Ok(()) => {
+            info!("Using resource pack {:?} from {:?}", name, zipped);
+            self.dirs.push(unzipped);
+          },
+          Err(err) => warn!("Error unpacking resource pack {:?}: {}", name, err),
+        }
+      } else {
+        warn!("Resource pack {:?} not found in {:?}", name, root());
+      }
+    }
+  }
+
+  /// Use a pack in preference to all the currently-active ones.
+  pub fn push_front(&mut self, name: String) {
+    self.names.insert(0, name);
+    self.scan();
+    self.generation += 1;
+  }
+
+  /// Pick up any changes made to the active packs on disk.
+  pub fn reload(&mut self) {
+    self.scan();
+    self.generation += 1;
+  }
+
+  /// The path to load an asset from, given its path relative to the default assets.
+  pub fn resolve(&self, asset: &str) -> PathBuf {
+    for dir in &self.dirs {
+      let path = dir.join(asset);
+      if path.is_file() {
+        debug!("Loading {:?} from {:?}", asset, dir);
+        return path
+      }
+    }
+    PathBuf::from(asset)
+  }
+
+  /// Every directory that may contain assets from a given subdirectory, highest priority first.
+  pub fn search_path(&self, subdir: &str) -> Vec<PathBuf> {
+    let mut path: Vec<PathBuf> =
+      self.dirs.iter()
+      .map(|dir| dir.join(subdir))
+      .filter(|dir| dir.is_dir())
+      .collect();
+    path.push(PathBuf::from(subdir));
+    path
+  }
+}
+
+/// Absolute version of a path, so it can be handed to processes with other working directories.
+pub fn absolute(path: &Path) -> PathBuf {
+  if path.is_absolute() {
+    path.to_owned()
+  } else {
+    std::env::current_dir().unwrap().join(path)
+  }
+}
+
+/// Unpack a zipped pack into `dir`, replacing whatever was there.
+fn unzip(archive: &Path, dir: &Path) -> Result<(), String> {
+  let file = try!(std::fs::File::open(archive).map_err(|err| format!("{:?}", err)));
+  let mut archive = try!(zip::ZipArchive::new(file).map_err(|err| format!("{:?}", err)));
+
+  // Unpack somewhere temporary first, so a bad archive can't leave behind a half-unpacked pack.
+  let unpacked = dir.with_extension("partial");
+  let _ = std::fs::remove_dir_all(&unpacked);
+  let r = (|| {
+    for i in 0 .. archive.len() {
+      let mut entry = try!(archive.by_index(i).map_err(|err| format!("{:?}", err)));
+      // Entries are named by the archive, so keep them from pointing outside the pack.
+      let path = unpacked.join(entry.sanitized_name());
+      if entry.name().ends_with('/') {
+        try!(std::fs::create_dir_all(&path).map_err(|err| format!("{:?}", err)));
+        continue
+      }
+      if let Some(parent) = path.parent() {
+        try!(std::fs::create_dir_all(parent).map_err(|err| format!("{:?}", err)));
+      }
+      let mut file = try!(std::fs::File::create(&path).map_err(|err| format!("{:?}", err)));
+      try!(std::io::copy(&mut entry, &mut file).map_err(|err| format!("{:?}", err)));
+    }
+    try!(std::fs::create_dir_all(&unpacked).map_err(|err| format!("{:?}", err)));
+    let _ = std::fs::remove_dir_all(dir);
+    std::fs::rename(&unpacked, dir).map_err(|err| format!("{:?}", err))
+  })();
+
+  if r.is_err() {
+    let _ = std::fs::remove_dir_all(&unpacked);
+  }
+  r
+}
+
+/// A pack a server requires.
+#[derive(Debug, Clone)]
+pub struct Required {
+  #[allow(missing_docs)]
+  pub url    : String,
+  #[allow(missing_docs)]
+  pub sha256 : String,
+}
+
+/// How far along a pack the server requires is.
+pub enum ServerPack {
+  /// Waiting for the player to accept or decline it.
+  Offered(Required),
+  /// Being fetched in the background; the result arrives on the channel.
+  Fetching(Required, mpsc::Receiver<Result<String, String>>),
+}
+
+/// Decide (according to the user's settings) what to do about a pack a server requires: fetch it,
+/// ignore it, or offer it to the player. Nothing here waits on the player or the download.
+pub fn require(settings: &settings::T, pack: Required) -> Option<ServerPack> {
+  match settings.server_resource_packs {
+    settings::PackConsent::Always => Some(start_fetch(pack)),
+    settings::PackConsent::Never => {
+      warn!("Not using required resource pack {}; things may look wrong.", pack.url);
+      None
+    },
+    settings::PackConsent::Ask => Some(ServerPack::Offered(pack)),
+  }
+}
+
+fn start_fetch(pack: Required) -> ServerPack {
+  let (send, recv) = mpsc::channel();
+  let (url, sha256) = (pack.url.clone(), pack.sha256.clone());
+  std::thread::spawn(move || {
+    // The receiver's gone if the client quit in the meantime.
+    let _ = send.send(fetch(&url, &sha256));
+  });
+  ServerPack::Fetching(pack, recv)
+}
+
+/// The player accepted the offered pack, if there is one, so start fetching it.
+pub fn accept(server_pack: &mut Option<ServerPack>) {
+  match server_pack.take() {
+    Some(ServerPack::Offered(pack)) => *server_pack = Some(start_fetch(pack)),
+    other => *server_pack = other,
+  }
+}
+
+/// The player declined the offered pack, if there is one.
+pub fn decline(server_pack: &mut Option<ServerPack>) {
+  match server_pack.take() {
+    Some(ServerPack::Offered(pack)) => {
+      warn!("Not using required resource pack {}; things may look wrong.", pack.url);
+    },
+    other => *server_pack = other,
+  }
+}
+
+/// The pack on offer, if the player hasn't accepted or declined it yet.
+pub fn offered(server_pack: &Option<ServerPack>) -> Option<&Required> {
+  match *server_pack {
+    Some(ServerPack::Offered(ref pack)) => Some(pack),
+    _ => None,
+  }
+}
+
+/// If the pack being fetched is done, return the name to use it by, or why it couldn't be fetched.
+pub fn poll(server_pack: &mut Option<ServerPack>) -> Option<Result<String, String>> {
+  let r =
+    match *server_pack {
+      Some(ServerPack::Fetching(ref pack, ref recv)) => {
+        match recv.try_recv() {
+          Ok(r) => r.map_err(|err| format!("Couldn't get resource pack {}: {}", pack.url, err)),
+          Err(mpsc::TryRecvError::Empty) => return None,
+          Err(mpsc::TryRecvError::Disconnected) => Err(format!("Fetching resource pack {} stopped", pack.url)),
+        }
+      },
+      _ => return None,
+    };
+  *server_pack = None;
+  Some(r)
+}
+
+/// The biggest pack a server can make clients download.
+const MAX_DOWNLOAD_BYTES: usize = 1 << 28;
+
+/// The most redirects followed to get to a pack.
+const MAX_REDIRECTS: u32 = 8;
+
+/// Packs only come over the web: servers name the URLs, and mustn't be able to make clients read
+/// local files, or speak any other protocol curl knows.
+fn check_scheme(url: &str) -> Result<(), String> {
+  let url = url.to_lowercase();
+  if url.starts_with("http://") || url.starts_with("https://") {
+    Ok(())
+  } else {
+    Err(format!("Refusing to download {}: packs have to come over http or https", url))
+  }
+}
+
+/// Download `url` into a new file at `path`. Redirects are followed here rather than by curl, so
+/// each one can be checked with `check_scheme` too.
+fn download(url: &str, path: &Path) -> Result<(), String> {
+  let mut url = String::from(url);
+  for _ in 0 .. MAX_REDIRECTS + 1 {
+    try!(check_scheme(&url));
+    match try!(download_once(&url, path)) {
+      None => return Ok(()),
+      Some(location) => url = location,
+    }
+  }
+  Err(format!("Too many redirects downloading {}", url))
+}
+
+/// Download `url` into a new file at `path`, unless it redirects; then return where to.
+fn download_once(url: &str, path: &Path) -> Result<Option<String>, String> {
+  let mut file = try!(std::fs::File::create(path).map_err(|err| format!("{:?}", err)));
+  let mut easy = curl::easy::Easy::new();
+  try!(easy.url(url).map_err(|err| format!("{}", err)));
+  try!(easy.follow_location(false).map_err(|err| format!("{}", err)));
+  try!(easy.fail_on_error(true).map_err(|err| format!("{}", err)));
+
+  let mut downloaded = 0;
+  let mut write_error = None;
+  let performed = {
+    let mut transfer = easy.transfer();
+    try!(transfer.write_function(|data| {
+      downloaded += data.len();
+      if downloaded > MAX_DOWNLOAD_BYTES {
+        write_error = Some(format!("{} is bigger than {} bytes", url, MAX_DOWNLOAD_BYTES));
+        // Taking less than was given aborts the transfer.
+        return Ok(0)
+      }
+      match file.write_all(data) {
+        Ok(()) => Ok(data.len()),
+        Err(err) => {
+          write_error = Some(format!("Error writing {:?}: {:?}", path, err));
+          Ok(0)
+        },
+      }
+    }).map_err(|err| format!("{}", err)));
+    transfer.perform()
+  };
+  if let Some(err) = write_error {
+    return Err(err)
+  }
+  try!(performed.map_err(|err| format!("Error downloading {}: {}", url, err)));
+  let code = try!(easy.response_code().map_err(|err| format!("{}", err)));
+  if code / 100 != 3 {
+    return Ok(None)
+  }
+  match try!(easy.redirect_url().map_err(|err| format!("{}", err))) {
+    None => Err(format!("{} redirected nowhere", url)),
+    Some(location) => Ok(Some(String::from(location))),
+  }
+}
+
+fn sha256_of(path: &Path) -> Result<String, String> {
+  let mut file = try!(std::fs::File::open(path).map_err(|err| format!("{:?}", err)));
+  let mut hasher = sha2::Sha256::default();
+  let mut buffer = [0; 1 << 16];
+  loop {
+    let n = try!(file.read(&mut buffer).map_err(|err| format!("{:?}", err)));
+    if n == 0 {
+      break
+    }
+    hasher.input(&buffer[.. n]);
+  }
+  Ok(format!("{:x}", hasher.result()))
+}
+
+/// Unpack a .tar archive into `dir`. It's named by a server, so only plain files and directories
+/// inside `dir` are allowed: links could point anywhere, as could paths with `..` in them.
+fn untar(archive: &Path, dir: &Path) -> Result<(), String> {
+  let file = try!(std::fs::File::open(archive).map_err(|err| format!("{:?}", err)));
+  try!(std::fs::create_dir_all(dir).map_err(|err| format!("{:?}", err)));
+  let mut archive = tar::Archive::new(file);
+  for entry in try!(archive.entries().map_err(|err| format!("{:?}", err))) {
+    let mut entry = try!(entry.map_err(|err| format!("{:?}", err)));
+    let path = try!(entry.path().map_err(|err| format!("{:?}", err))).into_owned();
+    let entry_type = entry.header().entry_type();
+    if !entry_type.is_file() && !entry_type.is_dir() {
+      return Err(format!("{:?} isn't a plain file or directory", path))
+    }
+    let inside =
+      path.components().all(|component| {
+        match component {
+          std::path::Component::Normal(_) | std::path::Component::CurDir => true,
+          _ => false,
+        }
+      });
+    if !inside {
+      return Err(format!("{:?} points outside the pack", path))
+    }
+    try!(entry.unpack_in(dir).map_err(|err| format!("Error unpacking {:?}: {:?}", path, err)));
+  }
+  Ok(())
+}
+
+/// Download, verify, and unpack a pack (a .tar archive) required by a server, and return its name.
+/// Packs are stored by hash, so each one is only downloaded once.
+pub fn fetch(url: &str, sha256: &str) -> Result<String, String> {
+  // The hash names a directory, so make sure it can't point anywhere unexpected.
+  if sha256.len() != 64 || !sha256.chars().all(|c| c.is_digit(16)) {
+    return Err(format!("Invalid resource pack hash {:?}", sha256))
+  }
+  let sha256 = sha256.to_lowercase();
+
+  let name = format!("server-{}", sha256);
+  let dir = root().join(&name);
+  if dir.is_dir() {
+    return Ok(name)
+  }
+
+  try!(std::fs::create_dir_all(root()).map_err(|err| format!("{:?}", err)));
+  let archive = root().join(format!("{}.tar", name));
+  let unpacked = root().join(format!("{}.partial", name));
+
+  let r = (|| {
+    info!("Downloading resource pack {}", url);
+    try!(download(url, &archive));
+
+    let actual = try!(sha256_of(&archive));
+    if actual != sha256 {
+      return Err(format!("Resource pack {} has hash {}, expected {}", url, actual, sha256))
+    }
+
+    // Unpack somewhere temporary first, so a failure can't leave behind a half-unpacked pack.
+    let _ = std::fs::remove_dir_all(&unpacked);
+    try!(std::fs::create_dir_all(&unpacked).map_err(|err| format!("{:?}", err)));
+    try!(untar(&archive, &unpacked).map_err(|err| format!("Error unpacking {}: {}", url, err)));
+    std::fs::rename(&unpacked, &dir).map_err(|err| format!("{:?}", err))
+  })();
+
+  let _ = std::fs::remove_file(&archive);
+  if r.is_err() {
+    let _ = std::fs::remove_dir_all(&unpacked);
+  }
+  r.map(|()| name)
+}
+
+#[test]
+fn zipped_packs_unpack() {
+  let scratch = std::env::temp_dir().join(format!("playform-unzip-test-{}", std::process::id()));
+  let _ = std::fs::remove_dir_all(&scratch);
+  std::fs::create_dir_all(&scratch).unwrap();
+  let archive = scratch.join("pack.zip");
+  {
+    let mut writer = zip::ZipWriter::new(std::fs::File::create(&archive).unwrap());
+    writer.start_file("textures/grass.png", zip::write::FileOptions::default()).unwrap();
+    writer.write_all(b"grass").unwrap();
+    writer.start_file("../outside.txt", zip::write::FileOptions::default()).unwrap();
+    writer.write_all(b"outside").unwrap();
+    writer.finish().unwrap();
+  }
+
+  let dir = scratch.join("pack");
+  std::fs::create_dir_all(dir.join("stale")).unwrap();
+  unzip(&archive, &dir).unwrap();
+
+  let mut grass = String::new();
+  std::fs::File::open(dir.join("textures/grass.png")).unwrap().read_to_string(&mut grass).unwrap();
+  assert_eq!(grass, "grass");
+  assert!(!dir.join("stale").exists());
+  assert!(!scratch.join("outside.txt").exists());
+  assert!(!dir.with_extension("partial").exists());
+
+  std::fs::remove_dir_all(&scratch).unwrap();
+}
+
+#[test]
+fn hashes_match_sha256sum() {
+  let path = std::env::temp_dir().join(format!("playform-sha256-test-{}", std::process::id()));
+  std::fs::File::create(&path).unwrap().write_all(b"abc").unwrap();
+  assert_eq!(sha256_of(&path).unwrap(), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+  std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn only_web_urls_are_downloaded() {
+  assert!(check_scheme("https://example.com/pack.tar").is_ok());
+  assert!(check_scheme("HTTP://example.com/pack.tar").is_ok());
+  assert!(check_scheme("file:///etc/passwd").is_err());
+  assert!(check_scheme("ftp://example.com/pack.tar").is_err());
+  assert!(check_scheme("example.com/pack.tar").is_err());
+  assert!(download("file:///etc/passwd", &std::env::temp_dir().join("playform-download-test")).is_err());
+}
+
+#[test]
+fn tarred_packs_cant_link_out() {
+  let scratch = std::env::temp_dir().join(format!("playform-untar-test-{}", std::process::id()));
+  let _ = std::fs::remove_dir_all(&scratch);
+  std::fs::create_dir_all(&scratch).unwrap();
+  let tar_with = |name: &str, entry_type: tar::EntryType| {
+    let path = scratch.join(name);
+    let mut builder = tar::Builder::new(std::fs::File::create(&path).unwrap());
+    let mut header = tar::Header::new_gnu();
+    header.set_path("textures/grass.png").unwrap();
+    header.set_entry_type(entry_type);
+    if entry_type == tar::EntryType::Regular {
+      header.set_size(5);
+      header.set_cksum();
+      builder.append(&header, &b"grass"[..]).unwrap();
+    } else {
+      header.set_size(0);
+      header.set_link_name("/etc/passwd").unwrap();
+      header.set_cksum();
+      builder.append(&header, std::io::empty()).unwrap();
+    }
+    builder.finish().unwrap();
+    path
+  };
+
+  let plain = scratch.join("plain");
+  untar(&tar_with("plain.tar", tar::EntryType::Regular), &plain).unwrap();
+  let mut contents = String::new();
+  std::fs::File::open(plain.join("textures/grass.png")).unwrap().read_to_string(&mut contents).unwrap();
+  assert_eq!(contents, "grass");
+
+  for &(name, entry_type) in &[("symlink.tar", tar::EntryType::Symlink), ("link.tar", tar::EntryType::Link)] {
+    let dir = scratch.join(name).with_extension("unpacked");
+    assert!(untar(&tar_with(name, entry_type), &dir).is_err());
+    assert!(std::fs::symlink_metadata(dir.join("textures/grass.png")).is_err());
+  }
+  std::fs::remove_dir_all(&scratch).unwrap();
+}
@@ -5,31 +5,167 @@ use std::io::Write;
 use std::sync::{Mutex};
 use stopwatch;
 use thread_scoped;
+use time;
 
+use common::data_dir;
 use common::protocol;
+use common::thread_priority;
 
 use audio_loader;
 use audio_thread;
 use client;
 use record_book;
+use replay;
+use resource_pack;
 use server;
+use settings;
 use terrain;
 use update_thread::update_thread;
+use view;
 use view::thread::view_thread;
 
 #[allow(missing_docs)]
 pub fn run(listen_url: &str, server_url: &str) {
+  let server = server::new(&server_url, &listen_url);
+
+  let mut settings = settings::load();
+  settings.add_last_server(server_url);
+  // Saved right away, so a new token isn't lost if the client doesn't quit cleanly.
+  if settings.choose_identity() {
+    info!("Playing as {}.", settings.player_name);
+    settings.save();
+  }
+
+  let client =
+    match connect_client(&listen_url, &server_url, &server, settings) {
+      Ok(client) => client,
+      Err(reason) => {
+        error!("The server refused to let us play: {}", reason);
+        return
+      },
+    };
+
+  let recorder =
+    if client.settings.lock().unwrap().record_sessions {
+      match replay::record(client.player_id, *client.player_position.lock().unwrap()) {
+        Ok(recorder) => Some(recorder),
+        Err(err) => {
+          warn!("Couldn't start recording: {}", err);
+          None
+        },
+      }
+    } else {
+      None
+    };
+
+  play(&client, &server, recorder);
+
+  client.settings.lock().unwrap().save();
+}
+
+/// Watch a recorded session, with a free camera.
+pub fn watch_replay(path: &str) {
+  play_recording(path, settings::load(), None);
+}
+
+/// Play a recorded session, then check what's rendered from the poses in `test_dir` against the
+/// golden images there (see `view::screenshot`). Returns whether they all matched.
+pub fn screenshot_test(path: &str, test_dir: &str) -> bool {
+  let test =
+    match view::screenshot::load(std::path::Path::new(test_dir)) {
+      Ok(test) => test,
+      Err(err) => {
+        error!("Couldn't load screenshot test: {}", err);
+        return false
+      },
+    };
+  // Whatever the player's settings, so screenshots can be compared between machines.
+  let settings = settings::default();
+  let test =
+    match play_recording(path, settings, Some(test)) {
+      None => return false,
+      Some(test) => test,
+    };
+  for failure in &test.failures {
+    error!("Screenshot {}", failure);
+  }
+  test.failures.is_empty()
+}
+
+/// Play a recorded session, running a screenshot test on it if there is one, and return the test
+/// once the client quits. Returns None if the recording couldn't be loaded.
+fn play_recording(
+  path: &str,
+  mut settings: settings::T,
+  screenshot_test: Option<view::screenshot::T>,
+) -> Option<view::screenshot::T> {
+  let (player_id, position, messages) =
+    match replay::load(std::path::Path::new(path)) {
+      Ok(recording) => recording,
+      Err(err) => {
+        error!("Couldn't load {}: {}", path, err);
+        return None
+      },
+    };
+
+  let (server, feed) = server::local();
+
+  // Recordings carry their own terrain; don't mix it up with what servers have sent.
+  settings.terrain_cache = false;
+  let mut client = client::new(protocol::ClientId::default(), player_id, position, path, settings);
+  client.replay = Some(Mutex::new(replay::controls()));
+  client.screenshot_test = screenshot_test.map(Mutex::new);
+
+  let done = Mutex::new(false);
+  {
+    let client = &client;
+    let done = &done;
+    let _feed_thread =
+      unsafe {
+        thread_scoped::scoped(move || {
+          let mut playback = replay::playback(messages);
+          while !*done.lock().unwrap() && !playback.is_finished() {
+            let msg = {
+              let mut controls = client.replay.as_ref().unwrap().lock().unwrap();
+              playback.poll(&mut controls, time::precise_time_ns())
+            };
+            match msg {
+              None => std::thread::sleep(std::time::Duration::from_millis(1)),
+              Some(msg) => {
+                if feed.send(msg).is_err() {
+                  break
+                }
+              },
+            }
+          }
+          info!("Replay finished");
+          if let Some(ref test) = client.screenshot_test {
+            test.lock().unwrap().replay_done = true;
+          }
+        })
+      };
+
+    play(client, &server, None);
+    *done.lock().unwrap() = true;
+  }
+
+  client.screenshot_test.map(|test| test.into_inner().unwrap())
+}
+
+/// Run the client's threads until the player quits, recording what the server says if there's a recorder.
+fn play(client: &client::T, server: &server::T, mut recorder: Option<replay::Recorder>) {
   let view_updates0 = Mutex::new(std::collections::VecDeque::new());
   let view_updates1 = Mutex::new(std::collections::VecDeque::new());
   let audio_updates = Mutex::new(std::collections::VecDeque::new());
 
+  // Start the camera where the player spawned.
+  view_updates0.lock().unwrap().push_back(view::update::MoveCamera(*client.player_position.lock().unwrap()));
+
   let quit = Mutex::new(false);
   let quit = &quit;
 
-  let server = server::new(&server_url, &listen_url);
-
-  let client = connect_client(&listen_url, &server);
-  let client = &client;
+  let thread_config = client.settings.lock().unwrap().thread_config();
+  let thread_config = &thread_config;
 
   {
     let monitor_thread = {
@@ -48,10 +184,12 @@ pub fn run(listen_url: &str, server_url: &str) {
 
     let audio_thread = {
       let audio_updates = &audio_updates;
+      let resource_packs = &client.resource_packs;
       unsafe {
         thread_scoped::scoped(move || {
           audio_thread::audio_thread(
             quit,
+            resource_packs,
             &mut || { audio_updates.lock().unwrap().pop_front() },
           );
         })
@@ -70,10 +208,17 @@ pub fn run(listen_url: &str, server_url: &str) {
       let server = server.clone();
       unsafe {
         thread_scoped::scoped(move || {
+          thread_priority::apply(thread_config, thread_priority::Role::Tick);
           update_thread(
             quit,
             client,
-            &mut || { server.listen.try() },
+            &mut || {
+              let msg = server.listen.try();
+              if let Some(ref msg) = msg {
+                recorder.as_mut().map(|recorder| recorder.record(msg));
+              }
+              msg
+            },
             &mut |up| { view_updates0.lock().unwrap().push_back(up) },
             &mut |up| { view_updates1.lock().unwrap().push_back(up) },
             &mut |up| { audio_updates.lock().unwrap().push_back(up) },
@@ -81,7 +226,8 @@ pub fn run(listen_url: &str, server_url: &str) {
             &mut |msg| {
               match msg {
                 terrain::Load::Voxels { time_requested: None, .. } => {},
-                terrain::Load::Voxels { time_requested: Some(_), .. } => {
+                terrain::Load::Voxels { time_requested: Some(_), .. } |
+                terrain::Load::Current { .. } => {
                   *client.pending_terrain_requests.lock().unwrap() -= 1;
                 }
               };
@@ -92,7 +238,7 @@ pub fn run(listen_url: &str, server_url: &str) {
           let mut recorded = record_book::thread_local::clone();
           recorded.chunk_loads.sort_by(|x, y| x.loaded_time_ns.cmp(&y.loaded_time_ns));
 
-          let mut file = std::fs::File::create("chunk_loads.out").unwrap();
+          let mut file = std::fs::File::create(data_dir::dir(data_dir::Kind::Logs).join("chunk_loads.out")).unwrap();
 
           file.write_all(b"records = [").unwrap();
           for (i, record) in recorded.chunk_loads.iter().enumerate() {
@@ -113,11 +259,13 @@ pub fn run(listen_url: &str, server_url: &str) {
     {
       let client = &client;
       let server = server.clone();
+      thread_priority::apply(thread_config, thread_priority::Role::Render);
       view_thread(
         client,
         &mut || { view_updates0.lock().unwrap().pop_front() },
         &mut || { view_updates1.lock().unwrap().pop_front() },
         &mut |server_update| { server.talk.tell(&server_update) },
+        &mut |up| { audio_updates.lock().unwrap().push_back(up) },
       );
 
       stopwatch::clone().print();
@@ -125,6 +273,7 @@ pub fn run(listen_url: &str, server_url: &str) {
 
     // View thread returned, so we got a quit event.
     *quit.lock().unwrap() = true;
+    server.talk.tell(&protocol::ClientToServer::Disconnect(client.id));
 
     audio_thread.join();
     monitor_thread.join();
@@ -135,18 +284,31 @@ pub fn run(listen_url: &str, server_url: &str) {
   }
 }
 
-fn connect_client(listen_url: &str, server: &server::T) -> client::T {
+fn connect_client(
+  listen_url: &str,
+  server_url: &str,
+  server: &server::T,
+  settings: settings::T,
+) -> Result<client::T, String> {
   // TODO: Consider using RPCs to solidify the request-response patterns.
-  server.talk.tell(&protocol::ClientToServer::Init(listen_url.to_owned()));
+  server.talk.tell(&protocol::ClientToServer::Init(listen_url.to_owned(), settings.credentials()));
   loop {
     match server.listen.wait() {
-      protocol::ServerToClient::LeaseId(client_id) => {
+      protocol::ServerToClient::Refused(reason) => return Err(reason),
+      protocol::ServerToClient::LeaseId(client_id, _) => {
         server.talk.tell(&protocol::ClientToServer::AddPlayer(client_id));
         let client_id = client_id;
+        let mut server_pack = None;
         loop {
           match server.listen.wait() {
+            protocol::ServerToClient::RequiredResourcePack { url, sha256 } => {
+              // It's offered and fetched once the game's running, so connecting doesn't wait on it.
+              server_pack = resource_pack::require(&settings, resource_pack::Required { url: url, sha256: sha256 });
+            },
             protocol::ServerToClient::PlayerAdded(player_id, position) => {
-              return client::new(client_id, player_id, position);
+              let client = client::new(client_id, player_id, position, server_url, settings);
+              *client.server_pack.lock().unwrap() = server_pack;
+              return Ok(client)
             },
             msg => {
               // Ignore other messages in the meantime.
@@ -1,28 +1,37 @@
-//! A low-level interface to send and receive server-client protocol messages
+//! A low-level interface to send and receive server-client protocol messages. Messages go over the
+//! wire in `reliable` envelopes, so critical ones survive dropped sends and reconnects; the ones to
+//! the server are tagged with the client's id and key, once it has them, so the server knows whose
+//! they are.
 
 use std;
 
 use std::sync::{Arc, Mutex};
-use std::sync::mpsc::{Sender, Receiver, TryRecvError};
+use std::sync::mpsc::{Sender, Receiver, TryRecvError, RecvTimeoutError};
 use std::sync::atomic::{Ordering, AtomicUsize};
 
 use bincode;
+use time;
 
 use common::protocol;
+use common::reliable;
+use common::socket;
 use common::socket::{SendSocket, ReceiveSocket};
 
+/// How long the send thread waits for something to send before checking for resends, and for acks
+/// owed to the server that haven't had anything to ride along on.
+const IDLE_MS: u64 = 100;
+
 #[allow(missing_docs)]
 #[derive(Clone)]
 pub struct SSender {
-  // A boxed slice is used to reduce the sent size
-  pub sender: Sender<Box<[u8]>>,
+  pub sender: Sender<protocol::ClientToServer>,
   // Please replace with AtomicU64 when it becomes stable
   pub bytes_sent: Arc<AtomicUsize>,
 }
 
 impl SSender {
   #[allow(missing_docs)]
-  pub fn new(sender: Sender<Box<[u8]>>) -> SSender {
+  pub fn new(sender: Sender<protocol::ClientToServer>) -> SSender {
     SSender {
       sender: sender,
       bytes_sent: Arc::new(AtomicUsize::new(0)),
@@ -31,22 +40,19 @@ impl SSender {
 
   #[allow(missing_docs)]
   pub fn tell(&self, msg: &protocol::ClientToServer) {
-    let msg = bincode::serialize(msg, bincode::Infinite).unwrap();
-    // We aren't reading this until long after the write, so we use `Relaxed`
-    self.bytes_sent.fetch_add(msg.len() as usize, Ordering::Relaxed);
-    self.sender.send(msg.into_boxed_slice()).unwrap();
+    self.sender.send(msg.clone()).unwrap();
   }
 }
 
 #[allow(missing_docs)]
 #[derive(Clone)]
-pub struct SReceiver (Arc<Mutex<Receiver<Box<[u8]>>>>);
+pub struct SReceiver (Arc<Mutex<Receiver<protocol::ServerToClient>>>);
 
 impl SReceiver {
   #[allow(missing_docs)]
   pub fn try(&self) -> Option<protocol::ServerToClient> {
     match self.0.lock().unwrap().try_recv() {
-      Ok(msg) => Some(bincode::deserialize(&Vec::from(msg)).unwrap()),
+      Ok(msg) => Some(msg),
       Err(TryRecvError::Empty) => None,
       e => {
         e.unwrap();
@@ -57,8 +63,7 @@ impl SReceiver {
 
   #[allow(missing_docs)]
   pub fn wait(&self) -> protocol::ServerToClient {
-    let msg = self.0.lock().unwrap().recv().unwrap();
-    bincode::deserialize(msg.as_ref()).unwrap()
+    self.0.lock().unwrap().recv().unwrap()
   }
 }
 
@@ -76,10 +81,16 @@ pub fn new(
 ) -> T {
   let (send_send, send_recv) = std::sync::mpsc::channel();
   let (recv_send, recv_recv) = std::sync::mpsc::channel();
+  let talk = SSender::new(send_send);
+  let link = Arc::new(Mutex::new(reliable::new::<protocol::ClientToServer, protocol::ServerToClient>()));
+  // Set by the receive thread when the server leases us an id and key.
+  let lease = Arc::new(Mutex::new(None));
 
   let _recv_thread ={
     let listen_url = listen_url.to_owned();
     let recv_send = recv_send.clone();
+    let link = link.clone();
+    let lease = lease.clone();
     std::thread::spawn(move || {
       let mut listen_socket =
         ReceiveSocket::new(
@@ -88,9 +99,26 @@ pub fn new(
         );
       loop {
         match listen_socket.read() {
-          None => break,
-          Some(msg) => {
-            recv_send.send(msg.into_boxed_slice()).unwrap()
+          socket::Result::Terminating => break,
+          socket::Result::Empty => {},
+          socket::Result::Success(msg) => {
+            let envelope: reliable::Envelope<protocol::ServerToClient> =
+              match bincode::deserialize(msg.as_ref()) {
+                Ok(envelope) => envelope,
+                Err(err) => {
+                  warn!("Ignoring a malformed message from the server: {:?}", err);
+                  continue
+                },
+              };
+            let msgs = link.lock().unwrap().receive(envelope);
+            for msg in msgs {
+              if let protocol::ServerToClient::LeaseId(id, key) = msg {
+                *lease.lock().unwrap() = Some((id, key));
+              }
+              if recv_send.send(msg).is_err() {
+                return
+              }
+            }
           },
         }
       }
@@ -99,6 +127,7 @@ pub fn new(
 
   let _send_thread = {
     let server_url = server_url.to_owned();
+    let bytes_sent = talk.bytes_sent.clone();
     std::thread::spawn(move || {
       let mut talk_socket =
         SendSocket::new(
@@ -106,19 +135,65 @@ pub fn new(
           Some(std::time::Duration::from_secs(30)),
         );
       loop {
-        match send_recv.recv() {
-          Err(_) => break,
-          Ok(msg) => {
-            let msg = Vec::from(msg);
-            talk_socket.write(msg.as_ref()).unwrap();
-          },
+        let msg =
+          match send_recv.recv_timeout(std::time::Duration::from_millis(IDLE_MS)) {
+            Ok(msg) => Some(msg),
+            Err(RecvTimeoutError::Timeout) => None,
+            Err(RecvTimeoutError::Disconnected) => break,
+          };
+        let lease = *lease.lock().unwrap();
+        let now = time::precise_time_ns();
+        let envelopes = {
+          let mut link = link.lock().unwrap();
+          let mut envelopes = link.resends(now);
+          match (msg, lease) {
+            // Until the server knows who we are, it has nowhere to keep track of what it's received.
+            (Some(msg), None) => envelopes.push(link.send(msg, false, now)),
+            (Some(msg), Some(_)) => {
+              let critical = msg.is_critical();
+              envelopes.push(link.send(msg, critical, now));
+            },
+            (None, Some((client_id, _))) => {
+              if envelopes.is_empty() && link.ack_owed() {
+                envelopes.push(link.send(protocol::ClientToServer::Ping(client_id), false, now));
+              }
+            },
+            (None, None) => {},
+          }
+          envelopes
+        };
+        for envelope in envelopes {
+          let msg = bincode::serialize(&(lease, envelope), bincode::Infinite).unwrap();
+          // We aren't reading this until long after the write, so we use `Relaxed`
+          bytes_sent.fetch_add(msg.len() as usize, Ordering::Relaxed);
+          // The socket logs failures and reconnects on its own, and anything critical is resent.
+          let _ = talk_socket.write(msg.as_ref());
         }
       }
     })
   };
 
   T {
-    talk: SSender::new(send_send),
+    talk: talk,
     listen: SReceiver(Arc::new(Mutex::new(recv_recv))),
   }
 }
+
+/// A stand-in for a server, for playing back recordings. It receives whatever is sent through the returned
+/// sender, and ignores whatever it's told.
+pub fn local() -> (T, Sender<protocol::ServerToClient>) {
+  let (send_send, send_recv) = std::sync::mpsc::channel::<protocol::ClientToServer>();
+  let (recv_send, recv_recv) = std::sync::mpsc::channel();
+
+  let _send_thread =
+    std::thread::spawn(move || {
+      for _ in send_recv.iter() {}
+    });
+
+  let server =
+    T {
+      talk: SSender::new(send_send),
+      listen: SReceiver(Arc::new(Mutex::new(recv_recv))),
+    };
+  (server, recv_send)
+}
@@ -2,25 +2,18 @@
 
 use cgmath;
 use cgmath::{Point3, EuclideanSpace, InnerSpace, ElementWise};
-use collision::{Aabb3};
 use rand::Rng;
 use stopwatch;
 use time;
 
-use common::color::Color4;
 use common::protocol;
 
 use audio_loader;
 use audio_thread;
 use client;
 use terrain;
-use vertex::ColoredVertex;
 use view;
 
-const TRIANGLES_PER_BOX: u32 = 12;
-const VERTICES_PER_TRIANGLE: u32 = 3;
-const TRIANGLE_VERTICES_PER_BOX: u32 = TRIANGLES_PER_BOX * VERTICES_PER_TRIANGLE;
-
 /// dispatch a server message among the subsystems
 pub fn apply_server_update<UpdateView, UpdateAudio, UpdateServer, EnqueueTerrainLoad>(
   client               : &client::T,
@@ -37,18 +30,26 @@ pub fn apply_server_update<UpdateView, UpdateAudio, UpdateServer, EnqueueTerrain
 {
   stopwatch::time("apply_server_update", move || {
     match update {
-      protocol::ServerToClient::LeaseId(_) => {
+      protocol::ServerToClient::LeaseId(_, _) => {
         warn!("Client ID has already been leased.");
       },
+      protocol::ServerToClient::Refused(reason) => {
+        warn!("Ignoring refusal after connecting: {}", reason);
+      },
+      protocol::ServerToClient::RequiredResourcePack { url, .. } => {
+        warn!("Ignoring resource pack {} required after connecting.", url);
+      },
       protocol::ServerToClient::Ping => {
         update_server(protocol::ClientToServer::Ping(client.id));
       },
+      protocol::ServerToClient::Tick(tick) => {
+        *client.server_tick.lock().unwrap() = tick;
+      },
       protocol::ServerToClient::PlayerAdded(id, _) => {
         warn!("Unexpected PlayerAdded event: {:?}.", id);
       },
       protocol::ServerToClient::UpdatePlayer(player_id, bounds) => {
-        let mesh = to_triangles(&bounds, &Color4::of_rgba(0.0, 0.0, 1.0, 1.0));
-        update_view(view::update::UpdatePlayer(player_id, mesh));
+        update_view(view::update::UpdatePlayer(player_id, bounds));
 
         // We "lock" the client to client.player_id, so for updates to that player only,
         // there is more client-specific logic.
@@ -62,11 +63,31 @@ pub fn apply_server_update<UpdateView, UpdateAudio, UpdateServer, EnqueueTerrain
         let position = Point3::from_vec(position);
 
         *client.player_position.lock().unwrap() = position;
-        update_view(view::update::MoveCamera(position));
+        // When watching a replay, the camera flies freely instead of following the player.
+        if client.replay.is_none() {
+          update_view(view::update::MoveCamera(position));
+        }
+      },
+      protocol::ServerToClient::RemovePlayer(id) => {
+        update_view(view::update::RemovePlayer(id));
+      },
+      protocol::ServerToClient::UpdateMob(id, transform) => {
+        update_view(view::update::UpdateMob(id, time::precise_time_ns(), transform));
+      },
+      protocol::ServerToClient::RemoveMob(id) => {
+        update_view(view::update::RemoveMob(id));
       },
-      protocol::ServerToClient::UpdateMob(id, bounds) => {
-        let mesh = to_triangles(&bounds, &Color4::of_rgba(1.0, 0.0, 0.0, 1.0));
-        update_view(view::update::UpdateMob(id, mesh));
+      protocol::ServerToClient::UpdateItem(id, position, material) => {
+        update_view(view::update::UpdateItem(id, position, material));
+      },
+      protocol::ServerToClient::RemoveItem(id) => {
+        update_view(view::update::RemoveItem(id));
+      },
+      protocol::ServerToClient::UpdateProjectile(id, position, velocity, material) => {
+        update_view(view::update::UpdateProjectile(id, position, velocity, material));
+      },
+      protocol::ServerToClient::RemoveProjectile(id) => {
+        update_view(view::update::RemoveProjectile(id));
       },
       protocol::ServerToClient::UpdateSun(fraction) => {
         update_view(view::update::SetSun(
@@ -76,10 +97,55 @@ pub fn apply_server_update<UpdateView, UpdateAudio, UpdateServer, EnqueueTerrain
           }
         ));
       },
-      protocol::ServerToClient::Voxels { voxels, reason } => {
+      protocol::ServerToClient::UpdateHealth { health, max_health } => {
+        update_view(view::update::SetHealth(health, max_health, time::precise_time_ns()));
+      },
+      protocol::ServerToClient::BossHealth { health, max_health } => {
+        update_view(view::update::SetBossHealth(health, max_health, time::precise_time_ns()));
+      },
+      protocol::ServerToClient::Message(kind, text) => {
+        if kind == protocol::MessageKind::Hint && !client.settings.lock().unwrap().show_hints {
+          return
+        }
+        info!("{}", text);
+        update_view(view::update::ShowMessage(text));
+      },
+      protocol::ServerToClient::Flying(flying) => {
+        *client.flying.lock().unwrap() = flying;
+        let text = if flying { "Flying: jump to rise, crouch to sink" } else { "Stopped flying" };
+        update_view(view::update::ShowMessage(String::from(text)));
+      },
+      protocol::ServerToClient::UpdateInventory(slots) => {
+        update_view(view::update::SetInventory(slots));
+      },
+      protocol::ServerToClient::TradeOffers(trader, name, offers) => {
+        *client.trade.lock().unwrap() = Some((trader, offers.len()));
+        let offers: Vec<String> =
+          offers.iter().enumerate()
+          .map(|(i, offer)| {
+            let (price, price_count) = offer.price;
+            let (goods, goods_count) = offer.goods;
+            format!("{}: {} {:?} for {} {:?}", i + 1, goods_count, goods, price_count, price)
+          })
+          .collect();
+        update_view(view::update::ShowMessage(format!("Trade with {} (press a number): {}", name, offers.join(", "))));
+      },
+      protocol::ServerToClient::MaxLoadDistance(distance) => {
+        client.limit_load_distance(distance);
+      },
+      protocol::ServerToClient::Shapes(names) => {
+        let mut shapes = client.shapes.lock().unwrap();
+        let mut shape = client.placement_shape.lock().unwrap();
+        // Keep placing the same shape if the server still has it.
+        if !shape.as_ref().map_or(false, |shape| names.contains(shape)) {
+          *shape = names.first().cloned();
+        }
+        *shapes = names;
+      },
+      protocol::ServerToClient::Voxels { voxels, reason, .. } => {
         let time_requested;
         match reason {
-          protocol::VoxelReason::Updated => {
+          protocol::VoxelReason::Updated | protocol::VoxelReason::Pushed => {
             time_requested = None;
           },
           protocol::VoxelReason::Requested { at } => {
@@ -95,6 +161,12 @@ pub fn apply_server_update<UpdateView, UpdateAudio, UpdateServer, EnqueueTerrain
           }
         );
       },
+      protocol::ServerToClient::BlockCurrent(chunk, lod) => {
+        enqueue_terrain_load(terrain::Load::Current { chunk: chunk, lod: lod });
+      },
+      protocol::ServerToClient::Horizon(tile, columns) => {
+        client.horizon.lock().unwrap().insert(tile, columns, update_view);
+      },
       protocol::ServerToClient::Collision(collision_type) => {
         if let protocol::Collision::PlayerTerrain(..) = collision_type {
           let player_position = *client.player_position.lock().unwrap();
@@ -109,41 +181,3 @@ pub fn apply_server_update<UpdateView, UpdateAudio, UpdateServer, EnqueueTerrain
     }
   })
 }
-
-fn to_triangles(
-  bounds: &Aabb3<f32>,
-  c: &Color4<f32>,
-) -> [ColoredVertex; TRIANGLE_VERTICES_PER_BOX as usize] {
-  let (x1, y1, z1) = (bounds.min.x, bounds.min.y, bounds.min.z);
-  let (x2, y2, z2) = (bounds.max.x, bounds.max.y, bounds.max.z);
-
-  let vtx = |x, y, z| {
-    ColoredVertex {
-      position: Point3::new(x, y, z),
-      color: *c,
-    }
-  };
-
-  // Remember: x increases to the right, y increases up, and z becomes more
-  // negative as depth from the viewer increases.
-  [
-    // front
-    vtx(x1, y1, z2), vtx(x2, y2, z2), vtx(x1, y2, z2),
-    vtx(x1, y1, z2), vtx(x2, y1, z2), vtx(x2, y2, z2),
-    // left
-    vtx(x1, y1, z1), vtx(x1, y2, z2), vtx(x1, y2, z1),
-    vtx(x1, y1, z1), vtx(x1, y1, z2), vtx(x1, y2, z2),
-    // top
-    vtx(x1, y2, z1), vtx(x2, y2, z2), vtx(x2, y2, z1),
-    vtx(x1, y2, z1), vtx(x1, y2, z2), vtx(x2, y2, z2),
-    // back
-    vtx(x1, y1, z1), vtx(x2, y2, z1), vtx(x2, y1, z1),
-    vtx(x1, y1, z1), vtx(x1, y2, z1), vtx(x2, y2, z1),
-    // right
-    vtx(x2, y1, z1), vtx(x2, y2, z2), vtx(x2, y1, z2),
-    vtx(x2, y1, z1), vtx(x2, y2, z1), vtx(x2, y2, z2),
-    // bottom
-    vtx(x1, y1, z1), vtx(x2, y1, z2), vtx(x1, y1, z2),
-    vtx(x1, y1, z1), vtx(x2, y1, z1), vtx(x2, y1, z2),
-  ]
-}
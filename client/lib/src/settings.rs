@@ -0,0 +1,485 @@
+//! Client settings, persisted between runs.
+//! The settings file is a list of `name = value` lines. Unrecognized names are ignored and missing
+//! names get their defaults, so adding new options doesn't invalidate existing files.
+
+use cgmath::Vector2;
+use rand;
+use rand::Rng;
+use sdl2::keyboard::Keycode;
+use std;
+use std::io::{Read, Write};
+
+use common::data_dir;
+use common::fnv_map;
+use common::protocol;
+use common::thread_priority;
+
+use view::terrain_buffers;
+
+/// The current version of the settings format.
+pub const VERSION: u32 = 1;
+
+/// Where settings are stored.
+pub fn path() -> std::path::PathBuf {
+  data_dir::dir(data_dir::Kind::Config).join("settings.txt")
+}
+
+/// How many recently-used servers to remember.
+const MAX_LAST_SERVERS: usize = 8;
+
+#[allow(missing_docs)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+/// Things that can be bound to keys.
+pub enum Action {
+  MoveForward,
+  MoveBack,
+  MoveLeft,
+  MoveRight,
+  Jump,
+  Sprint,
+  Crouch,
+  ToggleFly,
+  LookLeft,
+  LookRight,
+  LookUp,
+  LookDown,
+  RemoveVoxel,
+  PlaceVoxel,
+  ToggleHud,
+  ToggleCamera,
+  ToggleInputMode,
+  ToggleLoadPosition,
+  ReloadResources,
+  AcceptResourcePack,
+  DeclineResourcePack,
+  PlaceShape,
+  NextShape,
+  Rotate,
+  NextMaterial,
+  Interact,
+  Attack,
+  Throw,
+  Chat,
+  ReplayPause,
+  ReplaySlower,
+  ReplayFaster,
+  ReplaySkip,
+}
+
+/// Every action, along with its name in the settings file and its default key.
+const ACTIONS: [(Action, &'static str, Keycode); 33] = [
+  (Action::MoveForward        , "move_forward"         , Keycode::W),
+  (Action::MoveBack           , "move_back"            , Keycode::S),
+  (Action::MoveLeft           , "move_left"            , Keycode::A),
+  (Action::MoveRight          , "move_right"           , Keycode::D),
+  (Action::Jump               , "jump"                 , Keycode::Space),
+  (Action::Sprint             , "sprint"               , Keycode::LShift),
+  (Action::Crouch             , "crouch"               , Keycode::LCtrl),
+  (Action::ToggleFly          , "toggle_fly"           , Keycode::V),
+  (Action::LookLeft           , "look_left"            , Keycode::Left),
+  (Action::LookRight          , "look_right"           , Keycode::Right),
+  (Action::LookUp             , "look_up"              , Keycode::Up),
+  (Action::LookDown           , "look_down"            , Keycode::Down),
+  (Action::RemoveVoxel        , "remove_voxel"         , Keycode::Q),
+  (Action::PlaceVoxel         , "place_voxel"          , Keycode::E),
+  (Action::ToggleHud          , "toggle_hud"           , Keycode::H),
+  (Action::ToggleCamera       , "toggle_camera"        , Keycode::C),
+  (Action::ToggleInputMode    , "toggle_input_mode"    , Keycode::M),
+  (Action::ToggleLoadPosition , "toggle_load_position" , Keycode::P),
+  (Action::ReloadResources    , "reload_resources"     , Keycode::F5),
+  (Action::AcceptResourcePack , "accept_resource_pack" , Keycode::Y),
+  (Action::DeclineResourcePack, "decline_resource_pack", Keycode::N),
+  (Action::PlaceShape         , "place_shape"          , Keycode::R),
+  (Action::NextShape          , "next_shape"           , Keycode::T),
+  (Action::Rotate             , "rotate"               , Keycode::B),
+  (Action::NextMaterial       , "next_material"        , Keycode::G),
+  (Action::Interact           , "interact"             , Keycode::F),
+  (Action::Attack             , "attack"               , Keycode::X),
+  (Action::Throw              , "throw"                , Keycode::Z),
+  (Action::Chat               , "chat"                 , Keycode::Return),
+  (Action::ReplayPause        , "replay_pause"         , Keycode::K),
+  (Action::ReplaySlower       , "replay_slower"        , Keycode::J),
+  (Action::ReplayFaster       , "replay_faster"        , Keycode::L),
+  (Action::ReplaySkip         , "replay_skip"          , Keycode::Period),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Whether to use resource packs that servers require.
+pub enum PackConsent {
+  /// Offer each pack a server requires to the player, who can accept or decline it while playing.
+  Ask,
+  #[allow(missing_docs)]
+  Always,
+  #[allow(missing_docs)]
+  Never,
+}
+
+#[allow(missing_docs)]
+pub struct T {
+  /// The version of the file these settings were loaded from.
+  pub version               : u32,
+  #[allow(missing_docs)]
+  pub window_size           : Vector2<u32>,
+  #[allow(missing_docs)]
+  pub keybinds              : fnv_map::T<Action, Keycode>,
+  /// Recently-used servers, most recent first.
+  pub last_servers          : Vec<String>,
+  /// Render distance overrides, by server url.
+  pub render_distances      : fnv_map::T<String, u32>,
+  /// Names of the active resource packs, highest priority first.
+  pub resource_packs        : Vec<String>,
+  #[allow(missing_docs)]
+  pub server_resource_packs : PackConsent,
+  /// Whether to run the update thread at a lower priority than the render thread.
+  pub thread_priorities     : bool,
+  /// Whether to pin the render and update threads to their own cores.
+  pub pin_cores             : bool,
+  /// Megabytes of VRAM to use for terrain.
+  pub vram_budget_mb        : u32,
+  /// Whether water reflects the scene. This roughly doubles the cost of drawing terrain.
+  pub reflections           : bool,
+  /// Whether to render in HDR and tone map the result onto the screen.
+  pub tone_mapping          : bool,
+  /// Whether bright things glow. Only applies with tone mapping.
+  pub bloom                 : bool,
+  /// Whether to texture terrain by projecting along all three axes, which doesn't stretch on slopes.
+  pub triplanar             : bool,
+  /// Distance past which grass and flowers fade out.
+  pub decoration_distance   : u32,
+  /// Whether to show the tips servers send.
+  pub show_hints            : bool,
+  /// Whether to record sessions for watching later.
+  pub record_sessions       : bool,
+  /// Whether to keep terrain from servers on disk, so it needn't be downloaded again next time.
+  pub terrain_cache         : bool,
+  /// The name to play under. A random one is picked if it's empty.
+  pub player_name           : String,
+  /// The secret that proves to servers that `player_name` is ours. A random one is picked if it's
+  /// empty.
+  pub token                 : String,
+}
+
+#[allow(missing_docs)]
+pub fn default() -> T {
+  let mut keybinds = fnv_map::new();
+  for &(action, _, key) in ACTIONS.iter() {
+    keybinds.insert(action, key);
+  }
+
+  T {
+    version               : VERSION,
+    window_size           : Vector2::new(800, 600),
+    keybinds              : keybinds,
+    last_servers          : Vec::new(),
+    render_distances      : fnv_map::new(),
+    resource_packs        : Vec::new(),
+    server_resource_packs : PackConsent::Ask,
+    thread_priorities     : thread_priority::default().priorities,
+    pin_cores             : thread_priority::default().pin_cores,
+    vram_budget_mb        : (terrain_buffers::DEFAULT_BYTE_BUDGET / 1_000_000) as u32,
+    reflections           : false,
+    tone_mapping          : true,
+    bloom                 : true,
+    triplanar             : false,
+    decoration_distance   : 96,
+    show_hints            : true,
+    record_sessions       : false,
+    terrain_cache         : true,
+    player_name           : String::new(),
+    token                 : String::new(),
+  }
+}
+
+/// Parse settings from the contents of a settings file.
+pub fn parse(contents: &str) -> T {
+  let mut settings = default();
+
+  for line in contents.lines() {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+      continue
+    }
+
+    let mut split = line.splitn(2, '=');
+    let name = split.next().unwrap().trim();
+    let value =
+      match split.next() {
+        None => {
+          warn!("Ignoring malformed setting {:?}", line);
+          continue
+        },
+        Some(value) => value.trim(),
+      };
+
+    let parse_u32 = |value: &str| {
+      let r = value.parse();
+      if r.is_err() {
+        warn!("Ignoring non-numeric value for {}: {:?}", name, value);
+      }
+      r.ok()
+    };
+
+    let parse_bool = |value: &str| {
+      match value {
+        "true" => Some(true),
+        "false" => Some(false),
+        _ => {
+          warn!("Ignoring non-boolean value for {}: {:?}", name, value);
+          None
+        },
+      }
+    };
+
+    match name {
+      "version" => {
+        parse_u32(value).map(|v| settings.version = v);
+      },
+      "window_width" => {
+        parse_u32(value).map(|v| settings.window_size.x = v);
+      },
+      "window_height" => {
+        parse_u32(value).map(|v| settings.window_size.y = v);
+      },
+      "last_server" => {
+        if settings.last_servers.len() < MAX_LAST_SERVERS {
+          settings.last_servers.push(value.to_owned());
+        }
+      },
+      "resource_pack" => {
+        settings.resource_packs.push(value.to_owned());
+      },
+      "server_resource_packs" => {
+        match value {
+          "ask" => settings.server_resource_packs = PackConsent::Ask,
+          "always" => settings.server_resource_packs = PackConsent::Always,
+          "never" => settings.server_resource_packs = PackConsent::Never,
+          _ => warn!("Ignoring unknown value for {}: {:?}", name, value),
+        }
+      },
+      "vram_budget_mb" => {
+        parse_u32(value).map(|v| settings.vram_budget_mb = v);
+      },
+      "thread_priorities" => {
+        parse_bool(value).map(|v| settings.thread_priorities = v);
+      },
+      "pin_cores" => {
+        parse_bool(value).map(|v| settings.pin_cores = v);
+      },
+      "reflections" => {
+        parse_bool(value).map(|v| settings.reflections = v);
+      },
+      "tone_mapping" => {
+        parse_bool(value).map(|v| settings.tone_mapping = v);
+      },
+      "bloom" => {
+        parse_bool(value).map(|v| settings.bloom = v);
+      },
+      "triplanar" => {
+        parse_bool(value).map(|v| settings.triplanar = v);
+      },
+      "decoration_distance" => {
+        parse_u32(value).map(|v| settings.decoration_distance = v);
+      },
+      "show_hints" => {
+        parse_bool(value).map(|v| settings.show_hints = v);
+      },
+      "record_sessions" => {
+        parse_bool(value).map(|v| settings.record_sessions = v);
+      },
+      "player_name" => {
+        settings.player_name = value.to_owned();
+      },
+      "token" => {
+        settings.token = value.to_owned();
+      },
+      "terrain_cache" => {
+        parse_bool(value).map(|v| settings.terrain_cache = v);
+      },
+      name if name.starts_with("key.") => {
+        let action_name = &name["key.".len() ..];
+        match ACTIONS.iter().find(|&&(_, n, _)| n == action_name) {
+          None => warn!("Ignoring keybind for unknown action {:?}", action_name),
+          Some(&(action, _, _)) => {
+            match Keycode::from_name(value) {
+              None => warn!("Ignoring unknown key {:?} for {}", value, action_name),
+              Some(key) => { settings.keybinds.insert(action, key); },
+            }
+          },
+        }
+      },
+      name if name.starts_with("render_distance.") => {
+        let server = &name["render_distance.".len() ..];
+        parse_u32(value).map(|v| settings.render_distances.insert(server.to_owned(), v));
+      },
+      _ => {
+        debug!("Ignoring unrecognized setting {:?}", name);
+      },
+    }
+  }
+
+  if settings.version > VERSION {
+    warn!("Settings are from a newer version ({}); some may be ignored.", settings.version);
+  }
+  settings.version = VERSION;
+
+  settings
+}
+
+/// Load settings from `path()`, or use defaults if there are none.
+pub fn load() -> T {
+  let mut contents = String::new();
+  match std::fs::File::open(path()).and_then(|mut file| file.read_to_string(&mut contents)) {
+    Ok(_) => parse(&contents),
+    Err(err) => {
+      info!("Using default settings: {:?}", err);
+      default()
+    },
+  }
+}
+
+impl T {
+  /// The action bound to a key, if any.
+  pub fn action_for(&self, key: Keycode) -> Option<Action> {
+    self.keybinds.iter()
+      .find(|&(_, &k)| k == key)
+      .map(|(&action, _)| action)
+  }
+
+  /// Note that we've connected to a server.
+  pub fn add_last_server(&mut self, server: &str) {
+    self.last_servers.retain(|s| s != server);
+    self.last_servers.insert(0, server.to_owned());
+    self.last_servers.truncate(MAX_LAST_SERVERS);
+  }
+
+  /// Pick a random name and token for whichever of them aren't set. Returns whether either was.
+  pub fn choose_identity(&mut self) -> bool {
+    let mut rng = rand::thread_rng();
+    let mut changed = false;
+    if self.player_name.is_empty() {
+      self.player_name = format!("player{:04}", rng.gen_range(0, 10000));
+      changed = true;
+    }
+    if self.token.is_empty() {
+      self.token = format!("{:016x}{:016x}", rng.gen::<u64>(), rng.gen::<u64>());
+      changed = true;
+    }
+    changed
+  }
+
+  /// Who to tell servers we are.
+  pub fn credentials(&self) -> protocol::Credentials {
+    protocol::Credentials {
+      name  : self.player_name.clone(),
+      token : self.token.clone(),
+    }
+  }
+
+  /// Bytes of VRAM to use for terrain.
+  pub fn vram_budget(&self) -> usize {
+    self.vram_budget_mb as usize * 1_000_000
+  }
+
+  /// How to schedule the client's threads.
+  pub fn thread_config(&self) -> thread_priority::Config {
+    thread_priority::Config {
+      priorities : self.thread_priorities,
+      pin_cores  : self.pin_cores,
+    }
+  }
+
+  /// Serialize these settings into the settings file format.
+  pub fn serialize(&self) -> String {
+    let mut s = String::new();
+    s.push_str(&format!("version = {}\n", self.version));
+    s.push_str(&format!("window_width = {}\n", self.window_size.x));
+    s.push_str(&format!("window_height = {}\n", self.window_size.y));
+    for &(action, name, _) in ACTIONS.iter() {
+      s.push_str(&format!("key.{} = {}\n", name, self.keybinds[&action].name()));
+    }
+    for server in &self.last_servers {
+      s.push_str(&format!("last_server = {}\n", server));
+    }
+    for pack in &self.resource_packs {
+      s.push_str(&format!("resource_pack = {}\n", pack));
+    }
+    s.push_str(&format!("server_resource_packs = {}\n",
+      match self.server_resource_packs {
+        PackConsent::Ask => "ask",
+        PackConsent::Always => "always",
+        PackConsent::Never => "never",
+      }
+    ));
+    s.push_str(&format!("vram_budget_mb = {}\n", self.vram_budget_mb));
+    s.push_str(&format!("thread_priorities = {}\n", self.thread_priorities));
+    s.push_str(&format!("pin_cores = {}\n", self.pin_cores));
+    s.push_str(&format!("reflections = {}\n", self.reflections));
+    s.push_str(&format!("tone_mapping = {}\n", self.tone_mapping));
+    s.push_str(&format!("bloom = {}\n", self.bloom));
+    s.push_str(&format!("triplanar = {}\n", self.triplanar));
+    s.push_str(&format!("decoration_distance = {}\n", self.decoration_distance));
+    s.push_str(&format!("show_hints = {}\n", self.show_hints));
+    s.push_str(&format!("record_sessions = {}\n", self.record_sessions));
+    s.push_str(&format!("terrain_cache = {}\n", self.terrain_cache));
+    s.push_str(&format!("player_name = {}\n", self.player_name));
+    s.push_str(&format!("token = {}\n", self.token));
+    for (server, distance) in &self.render_distances {
+      s.push_str(&format!("render_distance.{} = {}\n", server, distance));
+    }
+    s
+  }
+
+  /// Write these settings to `path()`.
+  pub fn save(&self) {
+    let r =
+      std::fs::File::create(path())
+      .and_then(|mut file| file.write_all(self.serialize().as_bytes()));
+    if let Err(err) = r {
+      warn!("Error saving settings: {:?}", err);
+    }
+  }
+}
+
+#[test]
+fn missing_fields_get_defaults() {
+  let settings = parse("version = 0\nwindow_width = 1024\nkey.jump = J\nsome_future_option = 7\n");
+  assert_eq!(settings.version, VERSION);
+  assert_eq!(settings.window_size, Vector2::new(1024, 600));
+  assert_eq!(settings.action_for(Keycode::J), Some(Action::Jump));
+  assert_eq!(settings.action_for(Keycode::W), Some(Action::MoveForward));
+  assert!(settings.last_servers.is_empty());
+}
+
+#[test]
+fn round_trip() {
+  let mut settings = default();
+  settings.add_last_server("ipc:///tmp/server.ipc");
+  settings.render_distances.insert(String::from("ipc:///tmp/server.ipc"), 40);
+  let parsed = parse(&settings.serialize());
+  assert_eq!(parsed.last_servers, settings.last_servers);
+  assert_eq!(parsed.render_distances.get("ipc:///tmp/server.ipc"), Some(&40));
+}
+
+#[test]
+fn thread_settings() {
+  let settings = parse("thread_priorities = false\npin_cores = true\n");
+  assert!(!settings.thread_priorities);
+  assert!(settings.pin_cores);
+  let settings = parse("pin_cores = maybe\n");
+  assert_eq!(settings.pin_cores, default().pin_cores);
+}
+
+#[test]
+fn identity_is_chosen_once() {
+  let mut settings = default();
+  assert!(settings.choose_identity());
+  assert!(!settings.player_name.is_empty());
+  assert!(!settings.token.is_empty());
+  let parsed = parse(&settings.serialize());
+  assert_eq!(parsed.player_name, settings.player_name);
+  assert_eq!(parsed.token, settings.token);
+
+  let mut parsed = parsed;
+  assert!(!parsed.choose_identity());
+  assert_eq!(parsed.token, settings.token);
+}
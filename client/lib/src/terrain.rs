@@ -9,15 +9,21 @@ use time;
 use common::{fnv_set, fnv_map};
 use common::id_allocator;
 use common::surroundings_loader;
+use common::telemetry;
 use common::voxel;
 
 use chunk;
 use chunk_stats;
+use hibernating_terrain;
 use lod;
 use record_book;
+use terrain_cache;
 use terrain_mesh;
 use view;
 
+/// The most chunks to rebuild per tick after edits. The rest keep their old meshes until their turn.
+const MAX_REMESHES_PER_TICK: usize = 4;
+
 #[allow(missing_docs)]
 #[derive(Debug, Clone)]
 pub enum Load {
@@ -27,6 +33,11 @@ pub enum Load {
     /// these voxels because they were updated.
     time_requested : Option<u64>,
   },
+  /// The server confirmed that the voxels held for a chunk are current, so it can be loaded from them.
+  Current {
+    chunk : chunk::position::T,
+    lod   : lod::T,
+  },
 }
 
 #[allow(missing_docs)]
@@ -37,22 +48,36 @@ pub struct T {
   chunk_voxels_loaded : fnv_map::T<(chunk::position::T, lod::T), u32>,
   /// The voxels we have cached from the server.
   voxels              : voxel::tree::T,
+  /// Cached voxels for chunks that have been unloaded, compressed.
+  hibernating         : hibernating_terrain::T,
   max_load_distance   : u32,
   queue               : std::collections::VecDeque<Load>,
+  /// Loaded chunks whose voxels have been edited, waiting to have their meshes rebuilt.
+  remesh              : fnv_set::T<(chunk::position::T, lod::T)>,
+  /// Blocks received from this server on earlier visits, if they're being cached.
+  cache               : Option<terrain_cache::T>,
 }
 
 #[allow(missing_docs)]
-pub fn new(max_load_distance: u32) -> T {
+pub fn new(max_load_distance: u32, cache: Option<terrain_cache::T>) -> T {
   T {
     loaded_chunks       : fnv_map::new(),
     chunk_voxels_loaded : fnv_map::new(),
     voxels              : voxel::tree::new(),
+    hibernating         : hibernating_terrain::new(),
     max_load_distance   : max_load_distance,
     queue               : std::collections::VecDeque::new(),
+    remesh              : fnv_set::new(),
+    cache               : cache,
   }
 }
 
 impl T {
+  /// Change how far from the player chunks get loaded.
+  pub fn set_max_load_distance(&mut self, max_load_distance: u32) {
+    self.max_load_distance = max_load_distance;
+  }
+
   /// return the LOD at which a chunk is loaded
   pub fn load_state(&self, chunk_position: &chunk::position::T) -> Option<lod::T> {
     self.loaded_chunks
@@ -60,6 +85,30 @@ impl T {
       .map(|&(_, lod)| lod)
   }
 
+  /// The material of the loaded voxel containing a point, sampled at whatever LOD its chunk is loaded at.
+  pub fn material_at(&self, position: &cgmath::Point3<f32>) -> Option<voxel::Material> {
+    let lod =
+      match self.load_state(&chunk::position::of_world_position(position)) {
+        None => return None,
+        Some(lod) => lod,
+      };
+    let lg_size = lod.lg_sample_size();
+    let size = (lg_size as f32).exp2();
+    let bounds =
+      voxel::bounds::new(
+        (position.x / size).floor() as i32,
+        (position.y / size).floor() as i32,
+        (position.z / size).floor() as i32,
+        lg_size,
+      );
+    self.voxels.get(&bounds).map(|voxel| {
+      match *voxel {
+        voxel::Volume(material) => material,
+        voxel::Surface(ref surface) => surface.corner,
+      }
+    })
+  }
+
   /// get the count of queued messages
   pub fn queued_update_count(&self) -> usize {
     self.queue.len()
@@ -115,12 +164,76 @@ impl T {
             time_requested,
           );
         },
+        Load::Current { chunk, lod } => {
+          let r =
+            self.load_chunk(
+              terrain_allocator,
+              grass_allocator,
+              rng,
+              chunk_stats,
+              update_view,
+              &chunk,
+              lod,
+            );
+          if r.is_err() {
+            warn!("Voxels for {:?} went missing after the server confirmed them", chunk);
+          }
+        },
       }
 
       if time::precise_time_ns() - start >= 1_000_000 {
         break
       }
     }
+
+    self.remesh_nearest(
+      terrain_allocator,
+      grass_allocator,
+      rng,
+      chunk_stats,
+      update_view,
+      player_position,
+    );
+  }
+
+  /// Rebuild the meshes of the edited chunks nearest the player, a few per tick, so a big edit
+  /// converges over a few frames instead of stalling one.
+  fn remesh_nearest<Rng, UpdateView>(
+    &mut self,
+    terrain_allocator : &std::sync::Mutex<id_allocator::T<view::entity::id::Terrain>>,
+    grass_allocator   : &std::sync::Mutex<id_allocator::T<view::entity::id::Grass>>,
+    rng               : &mut Rng,
+    chunk_stats       : &mut chunk_stats::T,
+    update_view       : &mut UpdateView,
+    player_position   : &cgmath::Point3<f32>,
+  ) where
+    UpdateView : FnMut(view::update::T),
+    Rng        : rand::Rng,
+  {
+    if self.remesh.is_empty() {
+      return
+    }
+
+    let player_position = chunk::position::of_world_position(player_position);
+    let mut pending: Vec<(chunk::position::T, lod::T)> = self.remesh.iter().cloned().collect();
+    pending.sort_by_key(|&(chunk, _)| surroundings_loader::distance_between(player_position.as_pnt(), chunk.as_pnt()));
+    for &(chunk, lod) in pending.iter().take(MAX_REMESHES_PER_TICK) {
+      self.remesh.remove(&(chunk, lod));
+      // Skip chunks that have been unloaded, or reloaded at another LOD, since they were edited.
+      if self.load_state(&chunk) != Some(lod) {
+        continue
+      }
+      let _ =
+        self.load_chunk(
+          terrain_allocator,
+          grass_allocator,
+          rng,
+          chunk_stats,
+          update_view,
+          &chunk,
+          lod,
+        );
+    }
   }
 
   #[inline(never)]
@@ -138,8 +251,22 @@ impl T {
     Rng        : rand::Rng,
   {
     debug!("generate {:?} at {:?}", chunk_position, lod);
-    let mesh_chunk: view::chunked_terrain::T =
-      terrain_mesh::generate(&self.voxels, chunk_stats, &chunk_position, lod, terrain_allocator, grass_allocator, rng);
+    // Stitch up the sides where the neighbor isn't loaded at this LOD, since it might show up at a different one.
+    let mut skirts = [false; 6];
+    let offsets = [
+      cgmath::Vector3::new(-1,  0,  0),
+      cgmath::Vector3::new( 1,  0,  0),
+      cgmath::Vector3::new( 0, -1,  0),
+      cgmath::Vector3::new( 0,  1,  0),
+      cgmath::Vector3::new( 0,  0, -1),
+      cgmath::Vector3::new( 0,  0,  1),
+    ];
+    for (skirt, &offset) in skirts.iter_mut().zip(offsets.iter()) {
+      *skirt = self.load_state(&(*chunk_position + offset)) != Some(lod);
+    }
+
+    let mut mesh_chunk: view::chunked_terrain::T =
+      terrain_mesh::generate(&self.voxels, chunk_stats, &chunk_position, lod, &skirts, terrain_allocator, grass_allocator, rng);
 
     let mut updates = Vec::new();
 
@@ -147,19 +274,23 @@ impl T {
       terrain_mesh::Ids {
         chunk_ids: mesh_chunk.ids.clone(),
         grass_ids: mesh_chunk.grass.ids.clone(),
+        water_ids: mesh_chunk.water.ids.clone(),
       };
 
     use std::collections::hash_map::Entry::*;
     // TODO: Rc instead of clone.
-    match self.loaded_chunks.entry(*chunk_position) {
-      Vacant(entry) => {
-        entry.insert((ids, lod));
-      },
-      Occupied(mut entry) => {
-        let (ids, _) = entry.insert((ids, lod));
-        updates.push(view::update::UnloadMesh(ids));
-      },
-    };
+    mesh_chunk.appear =
+      match self.loaded_chunks.entry(*chunk_position) {
+        Vacant(entry) => {
+          entry.insert((ids, lod));
+          true
+        },
+        Occupied(mut entry) => {
+          let (ids, _) = entry.insert((ids, lod));
+          updates.push(view::update::UnloadMesh(ids));
+          false
+        },
+      };
 
     if !mesh_chunk.is_empty() {
       updates.push(view::update::LoadMesh(Box::new(mesh_chunk)));
@@ -183,12 +314,23 @@ impl T {
     UpdateView : FnMut(view::update::T),
     Rng        : rand::Rng,
   {
+    // A chunk's mesh also needs the voxels just past its edges.
+    let mut woken = false;
+    for dx in -1 .. 2 {
+    for dy in -1 .. 2 {
+    for dz in -1 .. 2 {
+      woken |= self.wake(&(*chunk_position + cgmath::Vector3::new(dx, dy, dz)));
+    }}}
+
     let all_voxels_loaded =
       self.all_voxels_loaded(
         *chunk_position,
         lod,
       );
     if all_voxels_loaded {
+      if woken {
+        self.hibernating.record_hit();
+      }
       self.force_load_chunk(
         terrain_allocator,
         grass_allocator,
@@ -200,23 +342,8 @@ impl T {
       );
       Ok(())
     } else {
-      let voxel_size = 1 << lod.lg_sample_size();
-      let voxels =
-        terrain_mesh::voxels_in(
-          &collision::Aabb3::new(
-            cgmath::Point3::new(
-              (chunk_position.as_pnt().x << chunk::LG_WIDTH) - voxel_size,
-              (chunk_position.as_pnt().y << chunk::LG_WIDTH) - voxel_size,
-              (chunk_position.as_pnt().z << chunk::LG_WIDTH) - voxel_size,
-            ),
-            cgmath::Point3::new(
-              ((chunk_position.as_pnt().x + 1) << chunk::LG_WIDTH) + voxel_size,
-              ((chunk_position.as_pnt().y + 1) << chunk::LG_WIDTH) + voxel_size,
-              ((chunk_position.as_pnt().z + 1) << chunk::LG_WIDTH) + voxel_size,
-            ),
-          ),
-          lod.lg_sample_size(),
-        );
+      let voxels = lod::voxels_for_chunk(&chunk_position, lod);
+      self.hibernating.record_miss();
       Err(voxels)
     }
   }
@@ -233,6 +360,9 @@ impl T {
   {
     let player_position = chunk::position::of_world_position(player_position);
 
+    // Wake up any hibernating copy first, so it doesn't clobber this voxel later.
+    self.wake(&chunk::position::containing_voxel(bounds));
+
     // Has a new voxel been loaded? (or did we change an existing voxel)
     let new_voxel_loaded;
     {
@@ -248,29 +378,12 @@ impl T {
 
     trace!("voxel bounds {:?}", bounds);
 
-    // The LOD of the chunks that should be updated.
-    // This doesn't necessarily match the LOD they're loaded at.
-    let mut updated_lods = Vec::new();
-    for lod in 0..lod::COUNT as u32 {
-      let lod = lod::T(lod);
-
-      let lg_size = lod.lg_sample_size();
-      if lg_size == bounds.lg_size {
-        updated_lods.push(lod);
-      }
+    if new_voxel_loaded {
+      self.count_voxel(bounds, true);
     }
 
     for chunk_position in updated_chunk_positions(&bounds) {
       trace!("chunk_position {:?}", chunk_position);
-      if new_voxel_loaded {
-        for &updated_lod in &updated_lods {
-          let chunk_voxels_loaded =
-            self.chunk_voxels_loaded.entry((chunk_position, updated_lod))
-            .or_insert_with(|| 0);
-          trace!("{:?} gets {:?}", chunk_position, bounds);
-          *chunk_voxels_loaded += 1;
-        }
-      }
 
       let distance =
         surroundings_loader::distance_between(
@@ -287,7 +400,7 @@ impl T {
         continue;
       }
 
-      let lod = lod::of_distance(distance as u32);
+      let lod = lod::select(player_position.as_pnt(), chunk_position.as_pnt(), self.load_state(&chunk_position));
       let lg_size = lod.lg_sample_size();
       if lg_size != bounds.lg_size {
         debug!(
@@ -333,7 +446,12 @@ impl T {
 
     let processed_time = time::precise_time_ns();
     for (chunk, lod) in update_chunks {
-      let _ =
+      if time_requested.is_none() && self.load_state(&chunk).is_some() {
+        // An edit to a chunk that's already showing, so it can wait its turn.
+        self.remesh.insert((chunk, lod));
+        continue
+      }
+      let r =
         self.load_chunk(
           terrain_allocator,
           grass_allocator,
@@ -343,6 +461,9 @@ impl T {
           &chunk,
           lod,
         );
+      if r.is_ok() && time_requested.is_some() {
+        self.save_to_cache(&chunk, lod);
+      }
     }
 
     let chunk_loaded = time::precise_time_ns();
@@ -362,6 +483,144 @@ impl T {
     }
   }
 
+  /// Fill in the voxels missing for a chunk from the disk cache, if it's there. Returns the checksum
+  /// of the block as now held, to check with the server before the chunk is loaded.
+  /// `voxels` are the bounds the block needs, in the order `lod::voxels_for_chunk` gives them.
+  pub fn load_from_cache(
+    &mut self,
+    player_position : &cgmath::Point3<f32>,
+    chunk_position  : &chunk::position::T,
+    lod             : lod::T,
+    voxels          : &[voxel::bounds::T],
+  ) -> Option<u64> {
+    let cached =
+      match self.cache {
+        None => return None,
+        Some(ref cache) => cache.get(chunk_position, lod),
+      };
+    let cached =
+      match cached {
+        None => return None,
+        Some(cached) => cached,
+      };
+    if !cached.iter().map(|&(bounds, _)| bounds).eq(voxels.iter().cloned()) {
+      return None
+    }
+
+    // Voxels already held are at least as new as the cached ones.
+    let mut block = Vec::with_capacity(cached.len());
+    for (bounds, cached_voxel) in cached {
+      match self.voxels.get(&bounds).cloned() {
+        Some(voxel) => block.push((bounds, voxel)),
+        None => {
+          self.load_voxel(player_position, cached_voxel, &bounds, |_, _| {});
+          block.push((bounds, cached_voxel));
+        },
+      }
+    }
+    Some(voxel::checksum(&block))
+  }
+
+  /// Write a loaded chunk's voxels to the disk cache.
+  fn save_to_cache(&self, chunk_position: &chunk::position::T, lod: lod::T) {
+    let cache =
+      match self.cache {
+        None => return,
+        Some(ref cache) => cache,
+      };
+    let block: Option<Vec<(voxel::bounds::T, voxel::T)>> =
+      lod::voxels_for_chunk(chunk_position, lod).into_iter()
+      .map(|bounds| self.voxels.get(&bounds).map(|voxel| (bounds, *voxel)))
+      .collect();
+    if let Some(block) = block {
+      cache.insert(chunk_position, lod, &block);
+    }
+  }
+
+  /// Add or remove a voxel from the loaded voxel counts of every chunk that uses it.
+  fn count_voxel(&mut self, bounds: &voxel::bounds::T, loaded: bool) {
+    // The LODs of the chunks that use this voxel.
+    // This doesn't necessarily match the LOD they're loaded at.
+    let lods: Vec<lod::T> = lod::ALL.iter().cloned().filter(|lod| lod.lg_sample_size() == bounds.lg_size).collect();
+
+    for chunk_position in updated_chunk_positions(bounds) {
+      for &lod in &lods {
+        if loaded {
+          trace!("{:?} gets {:?}", chunk_position, bounds);
+          *self.chunk_voxels_loaded.entry((chunk_position, lod)).or_insert_with(|| 0) += 1;
+        } else {
+          let now_empty =
+            match self.chunk_voxels_loaded.get_mut(&(chunk_position, lod)) {
+              None => false,
+              Some(count) => {
+                *count -= 1;
+                *count == 0
+              },
+            };
+          if now_empty {
+            self.chunk_voxels_loaded.remove(&(chunk_position, lod));
+          }
+        }
+      }
+    }
+  }
+
+  /// Move the voxels inside a chunk out of the voxel tree and into compressed storage.
+  fn hibernate(&mut self, chunk_position: &chunk::position::T) {
+    // Merge with anything already hibernating here.
+    self.wake(chunk_position);
+
+    let p = chunk_position.as_pnt();
+    let bounds =
+      collision::Aabb3::new(
+        cgmath::Point3::new(p.x << chunk::LG_WIDTH, p.y << chunk::LG_WIDTH, p.z << chunk::LG_WIDTH),
+        cgmath::Point3::new((p.x + 1) << chunk::LG_WIDTH, (p.y + 1) << chunk::LG_WIDTH, (p.z + 1) << chunk::LG_WIDTH),
+      );
+    let mut lg_sizes: Vec<i16> = lod::ALL.iter().map(|lod| lod.lg_sample_size()).collect();
+    lg_sizes.dedup();
+
+    let mut voxels = Vec::new();
+    for lg_size in lg_sizes {
+      for voxel_bounds in terrain_mesh::voxels_in(&bounds, lg_size) {
+        // TODO: Prune the emptied nodes out of the tree.
+        let voxel =
+          match self.voxels.get(&voxel_bounds) {
+            None => continue,
+            Some(_) => self.voxels.get_mut_or_create(&voxel_bounds).data.take().unwrap(),
+          };
+        self.count_voxel(&voxel_bounds, false);
+        voxels.push((voxel_bounds, voxel));
+      }
+    }
+
+    if !voxels.is_empty() {
+      self.hibernating.insert(*chunk_position, &voxels);
+    }
+  }
+
+  /// Put a hibernating chunk's voxels back into the voxel tree. Returns whether there was anything to wake up.
+  fn wake(&mut self, chunk_position: &chunk::position::T) -> bool {
+    let voxels =
+      match self.hibernating.take(chunk_position) {
+        None => return false,
+        Some(voxels) => voxels,
+      };
+    for (bounds, voxel) in voxels {
+      let is_new = {
+        let data = &mut self.voxels.get_mut_or_create(&bounds).data;
+        let is_new = data.is_none();
+        if is_new {
+          *data = Some(voxel);
+        }
+        is_new
+      };
+      if is_new {
+        self.count_voxel(&bounds, true);
+      }
+    }
+    true
+  }
+
   /// unload a chunk
   pub fn unload<UpdateView>(
     &mut self,
@@ -372,10 +631,17 @@ impl T {
   {
     match self.loaded_chunks.remove(chunk_position) {
       None => {},
-      Some((ids, _)) => {
+      Some((ids, lod)) => {
+        self.remesh.remove(&(*chunk_position, lod));
         update_view(view::update::UnloadMesh(ids));
       },
     }
+    self.hibernate(chunk_position);
+  }
+
+  /// Add terrain cache stats to a telemetry report.
+  pub fn report(&self, report: &mut telemetry::Report) {
+    self.hibernating.report(report);
   }
 }
 
@@ -0,0 +1,113 @@
+//! Terrain received from servers, cached on disk so it needn't be downloaded again on the next visit.
+//! Blocks are stored separately for each server, by position and LOD. Cached blocks aren't used as-is:
+//! the server is first asked, by checksum, whether each one is still current.
+
+use bincode;
+use lz4;
+use std;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use common::voxel;
+
+use chunk;
+use lod;
+
+/// Where cached terrain is stored, within the cache directory.
+pub const ROOT: &'static str = "terrain_cache";
+
+#[allow(missing_docs)]
+pub struct T {
+  /// The directory for this server's blocks.
+  dir : PathBuf,
+}
+
+/// The directory name for a server's cache: its url, with anything that isn't safe in a file name replaced.
+fn dir_name(server_url: &str) -> String {
+  server_url.chars()
+    .map(|c| {
+      match c {
+        'a' ... 'z' | 'A' ... 'Z' | '0' ... '9' | '.' | '-' => c,
+        _ => '_',
+      }
+    })
+    .collect()
+}
+
+/// Open the cache of blocks from the server at `server_url`, under `root`.
+pub fn new(root: &Path, server_url: &str) -> T {
+  let dir = root.join(dir_name(server_url));
+  if let Err(err) = std::fs::create_dir_all(&dir) {
+    warn!("Error creating terrain cache {:?}: {:?}", dir, err);
+  }
+  T {
+    dir : dir,
+  }
+}
+
+impl T {
+  fn path(&self, chunk_position: &chunk::position::T, lod: lod::T) -> PathBuf {
+    let p = chunk_position.as_pnt();
+    let lod::T(lod) = lod;
+    self.dir.join(format!("block.{}.{}.{}.{}.bin", p.x, p.y, p.z, lod))
+  }
+
+  /// A block's voxels, if they're cached.
+  pub fn get(&self, chunk_position: &chunk::position::T, lod: lod::T) -> Option<Vec<(voxel::bounds::T, voxel::T)>> {
+    let mut compressed = Vec::new();
+    match std::fs::File::open(self.path(chunk_position, lod)) {
+      Err(_) => return None,
+      Ok(mut file) => {
+        if let Err(err) = file.read_to_end(&mut compressed) {
+          warn!("Error reading cached {:?}: {:?}", chunk_position, err);
+          return None
+        }
+      },
+    }
+
+    let voxels =
+      lz4::block::decompress(&compressed, None)
+      .map_err(|err| format!("{:?}", err))
+      .and_then(|bytes| bincode::deserialize(&bytes).map_err(|err| format!("{:?}", err)));
+    match voxels {
+      Ok(voxels) => Some(voxels),
+      Err(err) => {
+        warn!("Error decoding cached {:?}: {}", chunk_position, err);
+        None
+      },
+    }
+  }
+
+  /// Cache a block's voxels, replacing anything cached for it before.
+  pub fn insert(&self, chunk_position: &chunk::position::T, lod: lod::T, voxels: &Vec<(voxel::bounds::T, voxel::T)>) {
+    let r =
+      bincode::serialize(voxels, bincode::Infinite)
+      .map_err(|err| format!("{:?}", err))
+      .and_then(|bytes| lz4::block::compress(&bytes, None, true).map_err(|err| format!("{:?}", err)))
+      .and_then(|compressed| {
+        std::fs::File::create(self.path(chunk_position, lod))
+        .and_then(|mut file| file.write_all(&compressed))
+        .map_err(|err| format!("{:?}", err))
+      });
+    if let Err(err) = r {
+      warn!("Error caching {:?}: {}", chunk_position, err);
+    }
+  }
+}
+
+#[test]
+fn round_trip() {
+  let root = std::env::temp_dir().join("playform_terrain_cache_test");
+  let _ = std::fs::remove_dir_all(&root);
+  let cache = new(&root, "tcp://localhost:5555");
+  let chunk_position = chunk::position::new(1, -2, 3);
+  let voxels = vec!(
+    (voxel::bounds::new(8, -16, 24, 0), voxel::Volume(voxel::Material::Stone)),
+    (voxel::bounds::new(9, -16, 24, 0), voxel::Volume(voxel::Material::Empty)),
+  );
+  assert!(cache.get(&chunk_position, lod::T(0)).is_none());
+  cache.insert(&chunk_position, lod::T(0), &voxels);
+  assert!(cache.get(&chunk_position, lod::T(0)) == Some(voxels));
+  assert!(cache.get(&chunk_position, lod::T(1)).is_none());
+  std::fs::remove_dir_all(&root).unwrap();
+}
@@ -8,16 +8,21 @@ use rand;
 use std::sync::Mutex;
 use stopwatch;
 
+use common::fnv_map;
 use common::id_allocator;
 use common::voxel;
 // TODO: Move the server-only parts to the server, like BLOCK_WIDTH and sample_info.
 
+use atlas;
 use chunk;
 use chunk_stats;
+use decimate;
+use decoration;
 use lod;
 
 use view;
 use view::chunked_terrain;
+use water;
 
 #[derive(Debug, Copy, Clone)]
 /// [T; 3], but serializable.
@@ -114,12 +119,90 @@ mod voxel_storage {
   }
 }
 
+/// Which side of a chunk a point is closest to, as an index into the `skirts` passed to `generate`:
+/// -x, +x, -y, +y, -z, +z.
+fn nearest_face(bounds: &Aabb3<f32>, p: &Point3<f32>) -> usize {
+  let distances = [
+    (p.x - bounds.min.x).abs(),
+    (bounds.max.x - p.x).abs(),
+    (p.y - bounds.min.y).abs(),
+    (bounds.max.y - p.y).abs(),
+    (p.z - bounds.min.z).abs(),
+    (bounds.max.z - p.z).abs(),
+  ];
+  let mut nearest = 0;
+  for i in 1 .. distances.len() {
+    if distances[i] < distances[nearest] {
+      nearest = i;
+    }
+  }
+  nearest
+}
+
+/// Transition geometry to hide cracks between chunks at different LODs.
+/// Every edge on the border of the mesh gets a strip hanging back from the surface, on the sides of the chunk
+/// flagged in `faces`. Neighbors at the same LOD line up exactly, so the strips stay hidden behind them.
+fn skirt(
+  polygons : &[dual_contouring::polygon::T<voxel::Material>],
+  bounds   : &Aabb3<f32>,
+  faces    : &[bool; 6],
+  depth    : f32,
+) -> Vec<(Triangle<Point3<f32>>, Triangle<Vector3<f32>>, voxel::Material)> {
+  if !faces.iter().any(|&f| f) {
+    return Vec::new()
+  }
+
+  // Vertices shared between polygons are bit-identical, so quantizing is enough to match them up.
+  let key = |p: &Point3<f32>| {
+    ((p.x * 1024.0).round() as i64, (p.y * 1024.0).round() as i64, (p.z * 1024.0).round() as i64)
+  };
+
+  // Count how many polygons use each edge. Edges used once are on the border of the mesh.
+  let mut uses = fnv_map::new();
+  for polygon in polygons {
+    for i in 0 .. 3 {
+      let a = key(&polygon.vertices[i]);
+      let b = key(&polygon.vertices[(i + 1) % 3]);
+      let edge = if a < b { (a, b) } else { (b, a) };
+      *uses.entry(edge).or_insert(0) += 1;
+    }
+  }
+
+  let mut triangles = Vec::new();
+  for polygon in polygons {
+    for i in 0 .. 3 {
+      let j = (i + 1) % 3;
+      let (a, b) = (polygon.vertices[i], polygon.vertices[j]);
+      let (ka, kb) = (key(&a), key(&b));
+      let edge = if ka < kb { (ka, kb) } else { (kb, ka) };
+      if uses[&edge] != 1 {
+        continue
+      }
+      let middle = Point3::new((a.x + b.x) / 2.0, (a.y + b.y) / 2.0, (a.z + b.z) / 2.0);
+      if !faces[nearest_face(bounds, &middle)] {
+        continue
+      }
+
+      let (na, nb) = (polygon.normals[i], polygon.normals[j]);
+      let a2 = a + -na * depth;
+      let b2 = b + -nb * depth;
+      // Skirts can be seen from either side, so add both windings.
+      triangles.push((tri(a, b2, b), tri(na, nb, nb), polygon.material));
+      triangles.push((tri(a, a2, b2), tri(na, na, nb), polygon.material));
+      triangles.push((tri(a, b, b2), tri(na, nb, nb), polygon.material));
+      triangles.push((tri(a, b2, a2), tri(na, nb, na), polygon.material));
+    }
+  }
+  triangles
+}
+
 #[allow(missing_docs)]
 pub fn generate<Rng: rand::Rng>(
   voxels          : &voxel::tree::T,
   chunk_stats     : &mut chunk_stats::T,
   chunk_position  : &chunk::position::T,
   lod             : lod::T,
+  skirts          : &[bool; 6],
   chunk_allocator : &Mutex<id_allocator::T<view::entity::id::Terrain>>,
   grass_allocator : &Mutex<id_allocator::T<view::entity::id::Grass>>,
   rng             : &mut Rng,
@@ -130,6 +213,7 @@ pub fn generate<Rng: rand::Rng>(
     let lg_sample_size = lod.lg_sample_size();
 
     let mut chunked_terrain = chunked_terrain::empty();
+    let mut polygons = Vec::new();
 
     let low = *chunk_position.as_pnt();
     let high = low + (&Vector3::new(1, 1, 1));
@@ -167,27 +251,7 @@ pub fn generate<Rng: rand::Rng>(
               &mut voxel_storage::T { voxels: voxels },
               &edge,
               &mut |polygon: dual_contouring::polygon::T<voxel::Material>| {
-                let vertices = tri(polygon.vertices[0], polygon.vertices[1], polygon.vertices[2]);
-                let normals = tri(polygon.normals[0], polygon.normals[1], polygon.normals[2]);
-                let material = polygon.material as i32;
-
-                let grass =
-                  if polygon.material == voxel::Material::Terrain && lod <= lod::MAX_GRASS_LOD {
-                    Some(chunked_terrain::PushGrass {
-                      tex_id : rng.gen_range(0, 9),
-                      id     : grass_allocator.lock().unwrap().allocate(),
-                    })
-                  } else {
-                    None
-                  };
-
-                chunked_terrain.push(
-                  &mut *chunk_allocator.lock().unwrap(),
-                  vertices,
-                  normals,
-                  material,
-                  grass,
-                );
+                polygons.push(polygon);
               }
             );
         }}}
@@ -213,6 +277,79 @@ pub fn generate<Rng: rand::Rng>(
       );
     }
 
+    let surface: Vec<decimate::Polygon> =
+      polygons.iter()
+      .map(|polygon| {
+        (
+          tri(polygon.vertices[0], polygon.vertices[1], polygon.vertices[2]),
+          tri(polygon.normals[0], polygon.normals[1], polygon.normals[2]),
+          polygon.material,
+        )
+      })
+      .collect();
+    // Grass tufts are placed per polygon, so only merge polygons in chunks too far away for grass.
+    let surface =
+      if lod > lod::MAX_GRASS_LOD {
+        stopwatch::time("terrain_mesh::decimate", || decimate::coplanar(&surface))
+      } else {
+        surface
+      };
+
+    for (vertices, normals, material) in surface {
+      let grass =
+        if lod <= lod::MAX_GRASS_LOD {
+          decoration::tex_id_for(material, &normals, rng).map(|tex_id| {
+            chunked_terrain::PushGrass {
+              tex_id : tex_id,
+              id     : grass_allocator.lock().unwrap().allocate(),
+            }
+          })
+        } else {
+          None
+        };
+
+      let uvs = atlas::uvs(&vertices, &normals, material);
+      chunked_terrain.push(
+        &mut *chunk_allocator.lock().unwrap(),
+        vertices,
+        normals,
+        uvs,
+        material as i32,
+        grass,
+      );
+    }
+
+    let chunk_bounds =
+      Aabb3::new(
+        Point3::new(
+          (chunk_position.as_pnt().x << chunk::LG_WIDTH) as f32,
+          (chunk_position.as_pnt().y << chunk::LG_WIDTH) as f32,
+          (chunk_position.as_pnt().z << chunk::LG_WIDTH) as f32,
+        ),
+        Point3::new(
+          ((chunk_position.as_pnt().x + 1) << chunk::LG_WIDTH) as f32,
+          ((chunk_position.as_pnt().y + 1) << chunk::LG_WIDTH) as f32,
+          ((chunk_position.as_pnt().z + 1) << chunk::LG_WIDTH) as f32,
+        ),
+      );
+    chunked_terrain.center = chunk_bounds.center();
+    let skirt_depth = (1 << lg_sample_size) as f32;
+    for (vertices, normals, material) in skirt(&polygons, &chunk_bounds, skirts, skirt_depth) {
+      let uvs = atlas::uvs(&vertices, &normals, material);
+      chunked_terrain.push(
+        &mut *chunk_allocator.lock().unwrap(),
+        vertices,
+        normals,
+        uvs,
+        material as i32,
+        None,
+      );
+    }
+
+    for vertices in water::surface(voxels, chunk_position, lod) {
+      chunked_terrain.water.push(&mut *chunk_allocator.lock().unwrap(), vertices);
+    }
+
     chunk_stats.add(chunked_terrain.polygon_count());
     chunked_terrain
   })
@@ -257,4 +394,6 @@ pub struct Ids {
   pub chunk_ids : Vec<view::entity::id::Terrain>,
   #[allow(missing_docs)]
   pub grass_ids : Vec<view::entity::id::Grass>,
+  #[allow(missing_docs)]
+  pub water_ids : Vec<view::entity::id::Terrain>,
 }
@@ -0,0 +1,93 @@
+//! A line of text being typed, e.g. a chat message. Text arrives as whole strings rather than keys,
+//! so pasted text and text from input methods (IMEs) for non-Latin scripts work like typing.
+
+/// The longest entry, in characters (not bytes).
+pub const MAX_LENGTH: usize = 256;
+
+#[allow(missing_docs)]
+pub struct T {
+  text        : String,
+  /// Text an IME is still composing. It's shown after `text`, but isn't part of it until the IME
+  /// commits it, which arrives through `insert`.
+  composition : String,
+}
+
+#[allow(missing_docs)]
+pub fn new() -> T {
+  T {
+    text        : String::new(),
+    composition : String::new(),
+  }
+}
+
+impl T {
+  /// Add typed, pasted or committed text. Line breaks and tabs become spaces, other control
+  /// characters are dropped, and anything past `MAX_LENGTH` is cut off.
+  pub fn insert(&mut self, text: &str) {
+    let room = MAX_LENGTH.saturating_sub(self.text.chars().count());
+    self.text.extend(
+      text.chars()
+      .map(|c| if c.is_whitespace() { ' ' } else { c })
+      .filter(|c| !c.is_control())
+      .take(room)
+    );
+    self.composition.clear();
+  }
+
+  /// Replace the text the IME is composing.
+  pub fn compose(&mut self, text: &str) {
+    self.composition = String::from(text);
+  }
+
+  /// Delete the last character.
+  pub fn backspace(&mut self) {
+    // While composing, the IME handles backspace itself.
+    if self.composition.is_empty() {
+      self.text.pop();
+    }
+  }
+
+  /// The entry as it should be shown, with any composition in brackets.
+  pub fn display(&self) -> String {
+    if self.composition.is_empty() {
+      self.text.clone()
+    } else {
+      format!("{}[{}]", self.text, self.composition)
+    }
+  }
+
+  /// The finished entry.
+  pub fn finish(self) -> String {
+    String::from(self.text.trim())
+  }
+}
+
+#[test]
+fn backspace_removes_whole_characters() {
+  let mut entry = new();
+  entry.insert("héllo, 世界");
+  entry.backspace();
+  assert_eq!(entry.display(), "héllo, 世");
+}
+
+#[test]
+fn pastes_are_flattened_and_limited() {
+  let mut entry = new();
+  entry.insert("two\nlines\u{7}");
+  assert_eq!(entry.display(), "two lines");
+
+  let long: String = vec!['ä'; MAX_LENGTH * 2].into_iter().collect();
+  entry.insert(&long);
+  assert_eq!(entry.finish().chars().count(), MAX_LENGTH);
+}
+
+#[test]
+fn composition_is_shown_until_committed() {
+  let mut entry = new();
+  entry.insert("to ");
+  entry.compose("にほ");
+  entry.backspace();
+  assert_eq!(entry.display(), "to [にほ]");
+  entry.insert("日本");
+  assert_eq!(entry.display(), "to 日本");
+}
@@ -4,6 +4,7 @@ use std::sync::Mutex;
 use stopwatch;
 use time;
 
+use common::data_dir;
 use common::protocol;
 use common::surroundings_loader;
 use common::surroundings_loader::LoadType;
@@ -53,6 +54,10 @@ pub fn update_thread<RecvServer, UpdateView0, UpdateView1, UpdateAudio, UpdateSe
           update_surroundings(client, &mut chunk_stats, update_view1, update_server);
         });
 
+        stopwatch::time("update_horizon", || {
+          update_horizon(client, update_view1, update_server);
+        });
+
         stopwatch::time("process_voxel_updates", || {
           process_voxel_updates(client, &mut chunk_stats, update_view1);
         });
@@ -61,7 +66,7 @@ pub fn update_thread<RecvServer, UpdateView0, UpdateView1, UpdateAudio, UpdateSe
   }
 
   debug!("Printing chunk stats");
-  chunk_stats.output_to("vram_chunk_loads.out");
+  chunk_stats.output_to(data_dir::dir(data_dir::Kind::Logs).join("vram_chunk_loads.out"));
 }
 
 #[inline(never)]
@@ -109,8 +114,8 @@ fn update_surroundings<UpdateView, UpdateServer>(
       LoadType::Load => {
         stopwatch::time("update_thread.load_chunk", || {
           trace!("Loading distance {}", distance);
-          let new_lod = lod::of_distance(distance as u32);
           let load_state = client.terrain.lock().unwrap().load_state(&chunk_position);
+          let new_lod = lod::select(load_position.as_pnt(), chunk_position.as_pnt(), load_state);
           if load_state == Some(new_lod) {
             debug!("Not re-loading {:?} at {:?}", chunk_position, new_lod);
           } else {
@@ -120,8 +125,8 @@ fn update_surroundings<UpdateView, UpdateServer>(
       },
       LoadType::Downgrade => {
         stopwatch::time("update_thread.update_chunk", || {
-          let new_lod = lod::of_distance(distance as u32);
           let load_state = client.terrain.lock().unwrap().load_state(&chunk_position);
+          let new_lod = lod::select(load_position.as_pnt(), chunk_position.as_pnt(), load_state);
           let is_downgrade = load_state.map(|lod| new_lod < lod) == Some(true);
           if is_downgrade {
             load_or_request_chunk(client, chunk_stats, update_server, update_view, &chunk_position, new_lod);
@@ -147,6 +152,23 @@ fn update_surroundings<UpdateView, UpdateServer>(
   }
 }
 
+#[inline(never)]
+fn update_horizon<UpdateView, UpdateServer>(
+  client        : &client::T,
+  update_view   : &mut UpdateView,
+  update_server : &mut UpdateServer,
+) where
+  UpdateView   : FnMut(view::update::T),
+  UpdateServer : FnMut(protocol::ClientToServer),
+{
+  let position = {
+    let load_position = *client.load_position.lock().unwrap();
+    load_position.unwrap_or_else(|| *client.player_position.lock().unwrap())
+  };
+  let load_distance = *client.load_distance.lock().unwrap();
+  client.horizon.lock().unwrap().update(client.id, &position, load_distance, update_view, update_server);
+}
+
 fn load_or_request_chunk<UpdateServer, UpdateView>(
   client         : &client::T,
   chunk_stats    : &mut chunk_stats::T,
@@ -173,13 +195,29 @@ fn load_or_request_chunk<UpdateServer, UpdateView>(
   match r {
     Ok(()) => {},
     Err(voxels) => {
-      update_server(
-        protocol::ClientToServer::RequestVoxels {
-          time_requested_ns : time::precise_time_ns(),
-          client_id       : client.id,
-          voxels          : voxels,
-        }
-      );
+      let player_position = *client.player_position.lock().unwrap();
+      match terrain.load_from_cache(&player_position, chunk_position, lod, &voxels) {
+        Some(checksum) => {
+          update_server(
+            protocol::ClientToServer::CheckBlock {
+              time_requested_ns : time::precise_time_ns(),
+              client_id       : client.id,
+              chunk           : *chunk_position,
+              lod             : lod,
+              checksum        : checksum,
+            }
+          );
+        },
+        None => {
+          update_server(
+            protocol::ClientToServer::RequestVoxels {
+              time_requested_ns : time::precise_time_ns(),
+              client_id       : client.id,
+              voxels          : voxels,
+            }
+          );
+        },
+      }
       *client.pending_terrain_requests.lock().unwrap() += 1;
     },
   }
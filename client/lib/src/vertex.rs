@@ -1,6 +1,7 @@
 //! Vertex data structures.
 
 use cgmath::{Point2,Point3,Vector2};
+use collision::Aabb3;
 #[cfg(test)]
 use std::mem;
 
@@ -34,6 +35,42 @@ impl ColoredVertex {
       vtx(min.x, min.y), vtx(max.x, min.y), vtx(max.x, max.y),
     ]
   }
+
+  /// Generates the twelve colored triangles of a box with the given bounds.
+  pub fn cuboid(bounds: &Aabb3<f32>, c: Color4<f32>) -> [ColoredVertex; 36] {
+    let (x1, y1, z1) = (bounds.min.x, bounds.min.y, bounds.min.z);
+    let (x2, y2, z2) = (bounds.max.x, bounds.max.y, bounds.max.z);
+
+    let vtx = |x, y, z| {
+      ColoredVertex {
+        position: Point3::new(x, y, z),
+        color: c,
+      }
+    };
+
+    // Remember: x increases to the right, y increases up, and z becomes more
+    // negative as depth from the viewer increases.
+    [
+      // front
+      vtx(x1, y1, z2), vtx(x2, y2, z2), vtx(x1, y2, z2),
+      vtx(x1, y1, z2), vtx(x2, y1, z2), vtx(x2, y2, z2),
+      // left
+      vtx(x1, y1, z1), vtx(x1, y2, z2), vtx(x1, y2, z1),
+      vtx(x1, y1, z1), vtx(x1, y1, z2), vtx(x1, y2, z2),
+      // top
+      vtx(x1, y2, z1), vtx(x2, y2, z2), vtx(x2, y2, z1),
+      vtx(x1, y2, z1), vtx(x1, y2, z2), vtx(x2, y2, z2),
+      // back
+      vtx(x1, y1, z1), vtx(x2, y2, z1), vtx(x2, y1, z1),
+      vtx(x1, y1, z1), vtx(x1, y2, z1), vtx(x2, y2, z1),
+      // right
+      vtx(x2, y1, z1), vtx(x2, y2, z2), vtx(x2, y1, z2),
+      vtx(x2, y1, z1), vtx(x2, y2, z1), vtx(x2, y2, z2),
+      // bottom
+      vtx(x1, y1, z1), vtx(x2, y1, z2), vtx(x1, y1, z2),
+      vtx(x1, y1, z1), vtx(x2, y1, z1), vtx(x2, y1, z2),
+    ]
+  }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -0,0 +1,114 @@
+//! A backend that doesn't draw anything, but records what it was asked to draw and with what state,
+//! so render logic can be checked without a GPU or a display.
+
+use cgmath::Vector2;
+
+use super::{Draw, Target};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// A draw call, along with the state it was made in.
+pub struct Submission {
+  #[allow(missing_docs)]
+  pub draw           : Draw,
+  #[allow(missing_docs)]
+  pub target         : Target,
+  #[allow(missing_docs)]
+  pub culling        : bool,
+  #[allow(missing_docs)]
+  pub depth_write    : bool,
+  #[allow(missing_docs)]
+  pub polygon_offset : Option<(f32, f32)>,
+}
+
+#[allow(missing_docs)]
+pub struct T {
+  /// Every draw call so far, in order.
+  pub submissions : Vec<Submission>,
+  /// Every clear so far, as (target, color, depth).
+  pub clears      : Vec<(Target, bool, bool)>,
+  target          : Target,
+  culling         : bool,
+  depth_write     : bool,
+  polygon_offset  : Option<(f32, f32)>,
+}
+
+/// A recorder starting out in OpenGL's default state.
+pub fn new() -> T {
+  T {
+    submissions    : Vec::new(),
+    clears         : Vec::new(),
+    target         : Target::Screen,
+    culling        : false,
+    depth_write    : true,
+    polygon_offset : None,
+  }
+}
+
+impl T {
+  /// The draw calls with a given label.
+  pub fn labeled(&self, label: &str) -> Vec<&Submission> {
+    self.submissions.iter().filter(|s| s.draw.label == label).collect()
+  }
+
+  /// Forget everything recorded so far, e.g. between frames.
+  pub fn clear_recording(&mut self) {
+    self.submissions.clear();
+    self.clears.clear();
+  }
+}
+
+impl super::T for T {
+  fn bind_target(&mut self, target: Target, _size: &Vector2<i32>) {
+    self.target = target;
+  }
+
+  fn clear(&mut self, color: bool, depth: bool) {
+    self.clears.push((self.target, color, depth));
+  }
+
+  fn set_culling(&mut self, cull: bool) {
+    self.culling = cull;
+  }
+
+  fn set_depth_write(&mut self, write: bool) {
+    self.depth_write = write;
+  }
+
+  fn set_polygon_offset(&mut self, offset: Option<(f32, f32)>) {
+    self.polygon_offset = offset;
+  }
+
+  fn draw(&mut self, draw: &Draw) {
+    self.submissions.push(Submission {
+      draw           : *draw,
+      target         : self.target,
+      culling        : self.culling,
+      depth_write    : self.depth_write,
+      polygon_offset : self.polygon_offset,
+    });
+  }
+}
+
+#[test]
+fn records_state_with_each_draw() {
+  use super::T as Backend;
+
+  let mut backend = new();
+  backend.bind_target(Target::Framebuffer(3), &Vector2::new(16, 16));
+  backend.clear(false, true);
+  backend.set_polygon_offset(Some((2.0, 4.0)));
+  backend.draw(&Draw::triangles("terrain", 96));
+  backend.bind_target(Target::Screen, &Vector2::new(800, 600));
+  backend.set_polygon_offset(None);
+  backend.set_depth_write(false);
+  backend.draw(&Draw::triangles("water", 6));
+
+  assert_eq!(backend.clears, vec!((Target::Framebuffer(3), false, true)));
+  let terrain = backend.labeled("terrain");
+  assert_eq!(terrain.len(), 1);
+  assert_eq!(terrain[0].target, Target::Framebuffer(3));
+  assert_eq!(terrain[0].polygon_offset, Some((2.0, 4.0)));
+  let water = backend.labeled("water");
+  assert_eq!(water[0].target, Target::Screen);
+  assert!(!water[0].depth_write);
+}
@@ -0,0 +1,76 @@
+//! The interface between the renderer and the graphics API it draws with.
+//! Render passes describe where they draw, with what fixed-function state, and how many vertices;
+//! a backend turns that into API calls.
+//!
+//! Only render targets, render state and draw submission go through here. Buffers, textures and
+//! shaders are still created and bound through OpenGL directly, so OpenGL is the only backend that
+//! actually draws; `headless` just records what it's asked to do.
+//!
+//! TODO: Move buffer, texture and pipeline (shader program plus vertex layout) creation and binding
+//! behind `T`, port the `*_buffers`, `shaders`, `post`, `shadows` and `reflection` modules to it,
+//! and add a wgpu backend so the client can run on Metal.
+
+pub mod headless;
+pub mod opengl;
+
+use cgmath::Vector2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(missing_docs)]
+pub enum Primitive {
+  Triangles,
+  TriangleStrip,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Somewhere to draw to.
+pub enum Target {
+  /// The window.
+  Screen,
+  /// An offscreen framebuffer, by API-specific handle.
+  Framebuffer(u32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// A single draw call, from whatever vertex source is currently bound.
+pub struct Draw {
+  /// What's being drawn, for debugging.
+  pub label     : &'static str,
+  #[allow(missing_docs)]
+  pub primitive : Primitive,
+  /// The first vertex to draw.
+  pub first     : u32,
+  /// The number of vertices to draw, per instance.
+  pub count     : u32,
+  /// The number of instances to draw.
+  pub instances : u32,
+}
+
+impl Draw {
+  /// Draw `count` vertices once, as triangles.
+  pub fn triangles(label: &'static str, count: u32) -> Draw {
+    Draw {
+      label     : label,
+      primitive : Primitive::Triangles,
+      first     : 0,
+      count     : count,
+      instances : 1,
+    }
+  }
+}
+
+/// A graphics API that the renderer can draw with.
+pub trait T {
+  /// Draw into `target` from now on, covering `size` pixels.
+  fn bind_target(&mut self, target: Target, size: &Vector2<i32>);
+  /// Clear the current target's color and/or depth.
+  fn clear(&mut self, color: bool, depth: bool);
+  /// Whether to skip drawing back-facing triangles.
+  fn set_culling(&mut self, cull: bool);
+  /// Whether drawing writes to the depth buffer.
+  fn set_depth_write(&mut self, write: bool);
+  /// Depth offset as (slope factor, constant units), or None for no offset.
+  fn set_polygon_offset(&mut self, offset: Option<(f32, f32)>);
+  #[allow(missing_docs)]
+  fn draw(&mut self, draw: &Draw);
+}
@@ -0,0 +1,88 @@
+//! Draw with OpenGL.
+
+use cgmath::Vector2;
+use gl;
+use gl::types::*;
+
+use super::{Draw, Primitive, Target};
+
+#[allow(missing_docs)]
+pub struct T;
+
+#[allow(missing_docs)]
+pub fn new() -> T {
+  T
+}
+
+fn mode(primitive: Primitive) -> GLenum {
+  match primitive {
+    Primitive::Triangles => gl::TRIANGLES,
+    Primitive::TriangleStrip => gl::TRIANGLE_STRIP,
+  }
+}
+
+fn enable(capability: GLenum, enabled: bool) {
+  unsafe {
+    if enabled {
+      gl::Enable(capability);
+    } else {
+      gl::Disable(capability);
+    }
+  }
+}
+
+impl super::T for T {
+  fn bind_target(&mut self, target: Target, size: &Vector2<i32>) {
+    let framebuffer =
+      match target {
+        Target::Screen => 0,
+        Target::Framebuffer(framebuffer) => framebuffer,
+      };
+    unsafe {
+      gl::BindFramebuffer(gl::FRAMEBUFFER, framebuffer);
+      gl::Viewport(0, 0, size.x, size.y);
+    }
+  }
+
+  fn clear(&mut self, color: bool, depth: bool) {
+    let mut mask = 0;
+    if color {
+      mask |= gl::COLOR_BUFFER_BIT;
+    }
+    if depth {
+      mask |= gl::DEPTH_BUFFER_BIT;
+    }
+    unsafe {
+      gl::Clear(mask);
+    }
+  }
+
+  fn set_culling(&mut self, cull: bool) {
+    enable(gl::CULL_FACE, cull);
+  }
+
+  fn set_depth_write(&mut self, write: bool) {
+    unsafe {
+      gl::DepthMask(if write { gl::TRUE } else { gl::FALSE });
+    }
+  }
+
+  fn set_polygon_offset(&mut self, offset: Option<(f32, f32)>) {
+    enable(gl::POLYGON_OFFSET_FILL, offset.is_some());
+    if let Some((factor, units)) = offset {
+      unsafe {
+        gl::PolygonOffset(factor, units);
+      }
+    }
+  }
+
+  fn draw(&mut self, draw: &Draw) {
+    unsafe {
+      if draw.instances == 1 {
+        gl::DrawArrays(mode(draw.primitive), draw.first as GLint, draw.count as GLsizei);
+      } else {
+        gl::DrawArraysInstanced(mode(draw.primitive), draw.first as GLint, draw.count as GLsizei, draw.instances as GLsizei);
+      }
+    }
+  }
+}
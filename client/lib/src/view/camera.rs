@@ -3,11 +3,34 @@
 use gl;
 use gl::types::*;
 use cgmath;
-use cgmath::{Matrix3, Matrix4, One, Vector3, Point3, EuclideanSpace};
+use cgmath::{Matrix3, Matrix4, One, Vector3, Point3, EuclideanSpace, InnerSpace};
 use std::f32::consts::PI;
 use yaglw::gl_context::GLContext;
 use yaglw::shader::Shader;
 
+/// How far behind the player the third-person camera sits, unless something's in the way.
+pub const THIRD_PERSON_DISTANCE: f32 = 4.0;
+
+/// How finely to search for terrain between the player and the third-person camera.
+const THIRD_PERSON_STEP: f32 = 0.25;
+
+/// How far the third-person camera keeps from terrain, so the near clip plane doesn't cut into it.
+const THIRD_PERSON_MARGIN: f32 = 0.3;
+
+/// How much space the first-person camera keeps above itself, so the near clip plane doesn't cut
+/// into low ceilings.
+const HEAD_ROOM: f32 = 0.2;
+
+/// The furthest the first-person camera drops below the player's eyes to keep its head room.
+const MAX_PULL_DOWN: f32 = 0.5;
+
+/// How finely to search for a low enough first-person camera position.
+const PULL_DOWN_STEP: f32 = 0.05;
+
+/// Once there's room again, the first-person camera eases back up to the player's eyes, closing
+/// most of the gap in about this long.
+const RECOVER_SECONDS: f32 = 0.15;
+
 /// T representation as 3 distinct matrices, as well as a position + two rotations.
 pub struct T {
   #[allow(missing_docs)]
@@ -55,6 +78,18 @@ impl T {
     self.translation = Matrix4::from_translation(-p.to_vec());
   }
 
+  /// The direction the camera is facing.
+  pub fn forward(&self) -> Vector3<f32> {
+    let y_axis = Vector3::new(0.0, 1.0, 0.0);
+    let right =
+      Matrix3::from_axis_angle(y_axis, cgmath::Rad(self.lateral_rotation))
+      * Vector3::new(1.0, 0.0, 0.0);
+    let transform =
+      Matrix3::from_axis_angle(right, cgmath::Rad(self.vertical_rotation))
+      * Matrix3::from_axis_angle(y_axis, cgmath::Rad(self.lateral_rotation));
+    transform * Vector3::new(0.0, 0.0, -1.0)
+  }
+
   /// Rotate about a given vector, by `r` radians.
   fn rotate(&mut self, v: &Vector3<f32>, r: f32) {
     let mat = Matrix3::from_axis_angle(*v, -cgmath::Rad(r));
@@ -97,6 +132,78 @@ impl T {
   }
 }
 
+/// Where to put a camera looking along `forward` over the shoulder of a player whose eyes are at
+/// `eye`: `THIRD_PERSON_DISTANCE` behind them, or closer if that's inside something `is_solid`.
+pub fn third_person_position<IsSolid>(
+  eye: Point3<f32>,
+  forward: Vector3<f32>,
+  mut is_solid: IsSolid,
+) -> Point3<f32> where
+  IsSolid: FnMut(&Point3<f32>) -> bool,
+{
+  let back = -forward.normalize();
+  let mut distance = 0.0;
+  while distance < THIRD_PERSON_DISTANCE {
+    let next = (distance + THIRD_PERSON_STEP).min(THIRD_PERSON_DISTANCE);
+    if is_solid(&(eye + back * (next + THIRD_PERSON_MARGIN))) {
+      break
+    }
+    distance = next;
+  }
+  eye + back * distance
+}
+
+/// Keeps the first-person camera out of ceilings: when there isn't `HEAD_ROOM` over the player's
+/// eyes, like when they jump under a low ceiling or walk under an overhang, the camera drops as far
+/// as it needs to right away, then eases back up once there's room.
+pub struct HeadRoom {
+  /// How far below the eyes the camera is.
+  pull_down : f32,
+  /// When the camera was last placed.
+  last_ns   : Option<u64>,
+}
+
+#[allow(missing_docs)]
+pub fn head_room() -> HeadRoom {
+  HeadRoom {
+    pull_down : 0.0,
+    last_ns   : None,
+  }
+}
+
+impl HeadRoom {
+  /// Where to put the first-person camera at `time_ns` for a player whose eyes are at `eye`.
+  pub fn place<IsSolid>(
+    &mut self,
+    eye: Point3<f32>,
+    time_ns: u64,
+    mut is_solid: IsSolid,
+  ) -> Point3<f32> where
+    IsSolid: FnMut(&Point3<f32>) -> bool,
+  {
+    let mut needed = 0.0;
+    while needed < MAX_PULL_DOWN {
+      let position = eye + Vector3::new(0.0, -needed, 0.0);
+      if !is_solid(&position) && !is_solid(&(position + Vector3::new(0.0, HEAD_ROOM, 0.0))) {
+        break
+      }
+      needed = (needed + PULL_DOWN_STEP).min(MAX_PULL_DOWN);
+    }
+
+    let eased =
+      match self.last_ns {
+        None => 0.0,
+        Some(last_ns) => {
+          let seconds = time_ns.saturating_sub(last_ns) as f32 / 1_000_000_000.0;
+          self.pull_down * (-seconds / RECOVER_SECONDS).exp()
+        },
+      };
+    self.last_ns = Some(time_ns);
+    self.pull_down = eased.max(needed);
+    eye + Vector3::new(0.0, -self.pull_down, 0.0)
+  }
+}
+
 /// Set a shader's projection matrix to match that of a camera.
 pub fn set_camera(shader: &mut Shader, gl: &mut GLContext, c: &T) {
   let projection_matrix = shader.get_uniform_location("projection_matrix");
@@ -107,3 +214,38 @@ pub fn set_camera(shader: &mut Shader, gl: &mut GLContext, c: &T) {
     gl::UniformMatrix4fv(projection_matrix, 1, 0, ptr);
   }
 }
+
+#[test]
+fn third_person_camera_stops_short_of_walls() {
+  let eye = Point3::new(0.0, 0.0, 0.0);
+  let forward = Vector3::new(0.0, 0.0, -1.0);
+
+  let open = third_person_position(eye, forward, |_| false);
+  assert_eq!(open, Point3::new(0.0, 0.0, THIRD_PERSON_DISTANCE));
+
+  // A wall behind the player, at z = 2.
+  let walled = third_person_position(eye, forward, |p| p.z >= 2.0);
+  assert!(walled.z > 0.0);
+  assert!(walled.z + THIRD_PERSON_MARGIN < 2.0);
+}
+
+#[test]
+fn first_person_camera_ducks_under_ceilings() {
+  let eye = Point3::new(0.0, 1.0, 0.0);
+  let mut head_room = head_room();
+
+  assert_eq!(head_room.place(eye, 0, |_| false), eye);
+
+  // A ceiling just over the player's eyes.
+  let ceiling = eye.y + HEAD_ROOM / 2.0;
+  let ducked = head_room.place(eye, 1_000_000, |p| p.y >= ceiling);
+  assert!(ducked.y + HEAD_ROOM < ceiling);
+  assert!(ducked.y >= eye.y - MAX_PULL_DOWN);
+
+  // Out from under it, the camera comes back up gradually.
+  let rising = head_room.place(eye, 2_000_000, |_| false);
+  assert!(rising.y > ducked.y);
+  assert!(rising.y < eye.y);
+  let risen = head_room.place(eye, 2_000_000_000, |_| false);
+  assert!(eye.y - risen.y < 0.001);
+}
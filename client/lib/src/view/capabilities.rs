@@ -0,0 +1,115 @@
+//! What the OpenGL driver we're running on can do, queried once at startup, so the renderer can size its
+//! buffers and pick code paths to fit instead of failing opaquely on older drivers.
+
+use gl;
+use gl::types::*;
+use std;
+
+use super::terrain_buffers;
+
+/// Texels needed per terrain chunk in the terrain vertex buffer texture.
+const TEXELS_PER_TERRAIN_CHUNK: usize = terrain_buffers::CHUNK_LENGTH * 3 * terrain_buffers::TEXELS_PER_VERTEX;
+
+#[allow(missing_docs)]
+pub struct T {
+  /// The driver's version string.
+  pub version                 : String,
+  /// The driver's name for the GPU.
+  pub renderer                : String,
+  /// Maximum number of texels in a buffer texture.
+  pub max_texture_buffer_size : usize,
+  /// Maximum size of a uniform block, in bytes.
+  pub max_uniform_block_size  : usize,
+  /// Whether buffers can be persistently mapped (GL 4.4 or ARB_buffer_storage).
+  pub buffer_storage          : bool,
+  /// Whether many draws can be issued from one indirect buffer (GL 4.3 or ARB_multi_draw_indirect).
+  pub multi_draw_indirect     : bool,
+}
+
+unsafe fn get_string(name: GLenum) -> String {
+  let s = gl::GetString(name);
+  if s.is_null() {
+    return String::new()
+  }
+  std::ffi::CStr::from_ptr(s as *const _).to_string_lossy().into_owned()
+}
+
+unsafe fn get_integer(name: GLenum) -> GLint {
+  let mut value = 0;
+  gl::GetIntegerv(name, &mut value);
+  value
+}
+
+/// Query the current OpenGL context.
+pub fn detect() -> T {
+  unsafe {
+    let major = get_integer(gl::MAJOR_VERSION);
+    let minor = get_integer(gl::MINOR_VERSION);
+    let at_least = |want_major, want_minor| (major, minor) >= (want_major, want_minor);
+
+    let mut extensions = Vec::new();
+    for i in 0 .. get_integer(gl::NUM_EXTENSIONS) {
+      let s = gl::GetStringi(gl::EXTENSIONS, i as GLuint);
+      if !s.is_null() {
+        extensions.push(std::ffi::CStr::from_ptr(s as *const _).to_string_lossy().into_owned());
+      }
+    }
+    let has = |name: &str| extensions.iter().any(|e| e == name);
+
+    T {
+      version                 : get_string(gl::VERSION),
+      renderer                : get_string(gl::RENDERER),
+      max_texture_buffer_size : get_integer(gl::MAX_TEXTURE_BUFFER_SIZE) as usize,
+      max_uniform_block_size  : get_integer(gl::MAX_UNIFORM_BLOCK_SIZE) as usize,
+      buffer_storage          : at_least(4, 4) || has("GL_ARB_buffer_storage"),
+      multi_draw_indirect     : at_least(4, 3) || has("GL_ARB_multi_draw_indirect"),
+    }
+  }
+}
+
+/// The largest terrain VRAM budget, no bigger than `requested`, whose buffer textures fit in `max_texels`.
+fn fit_terrain_budget(requested: usize, max_texels: usize) -> usize {
+  let max_chunks = max_texels / TEXELS_PER_TERRAIN_CHUNK;
+  let max_bytes = max_chunks * terrain_buffers::CHUNK_LENGTH * terrain_buffers::POLYGON_COST;
+  std::cmp::min(requested, max_bytes)
+}
+
+impl T {
+  /// The terrain VRAM budget to actually use, given the one that was asked for.
+  pub fn terrain_byte_budget(&self, requested: usize) -> usize {
+    fit_terrain_budget(requested, self.max_texture_buffer_size)
+  }
+
+  /// Log what was detected and which code paths were chosen.
+  pub fn report(&self, terrain_byte_budget: usize, requested: usize) {
+    info!("OpenGL {} on {}", self.version, self.renderer);
+    info!(
+      "max texture buffer size: {} texels, max uniform block size: {} bytes",
+      self.max_texture_buffer_size,
+      self.max_uniform_block_size,
+    );
+    if terrain_byte_budget < requested {
+      warn!(
+        "Terrain VRAM budget reduced from {}MB to {}MB to fit in buffer textures",
+        requested / 1_000_000,
+        terrain_byte_budget / 1_000_000,
+      );
+    }
+    info!(
+      "persistently mapped buffers: {}, multi-draw-indirect: {}",
+      if self.buffer_storage { "yes" } else { "no" },
+      if self.multi_draw_indirect { "yes" } else { "no" },
+    );
+  }
+}
+
+#[test]
+fn budgets_shrink_to_fit_buffer_textures() {
+  let requested = terrain_buffers::DEFAULT_BYTE_BUDGET;
+  // GL 3.3 only guarantees 64k texels.
+  let fit = fit_terrain_budget(requested, 1 << 16);
+  assert!(fit < requested);
+  assert!(terrain_buffers::chunk_budget(fit) * TEXELS_PER_TERRAIN_CHUNK <= 1 << 16);
+
+  assert_eq!(fit_terrain_budget(requested, 1 << 27), requested);
+}
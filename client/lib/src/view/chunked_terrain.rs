@@ -40,6 +40,43 @@ impl Grass {
   }
 }
 
+/// Water surface triangles, allocated in vram-sized chunks of their own.
+pub struct Water {
+  /// Position of each vertex.
+  pub vertex_coordinates : Vec<Chunk<terrain_mesh::Triangle<Point3<f32>>>>,
+  /// per-chunk ids
+  pub ids                : Vec<entity::id::Terrain>,
+
+  /// The index within the last `Chunk` that we should write to next.
+  next_idx_inside_chunks : usize,
+}
+
+impl Water {
+  fn empty() -> Self {
+    Water {
+      vertex_coordinates     : Vec::new(),
+      ids                    : Vec::new(),
+      next_idx_inside_chunks : 0,
+    }
+  }
+
+  #[allow(missing_docs)]
+  pub fn push(
+    &mut self,
+    id_allocator : &mut id_allocator::T<entity::id::Terrain>,
+    vertices     : terrain_mesh::Triangle<Point3<GLfloat>>,
+  ) {
+    if self.next_idx_inside_chunks == 0 {
+      let zero = Point3::new(0.0, 0.0, 0.0);
+      self.vertex_coordinates.push(terrain_buffers::Chunk([terrain_mesh::tri(zero, zero, zero); terrain_buffers::CHUNK_LENGTH]));
+      self.ids.push(id_allocator.allocate());
+    }
+
+    self.vertex_coordinates.last_mut().unwrap().0[self.next_idx_inside_chunks] = vertices;
+    self.next_idx_inside_chunks = (self.next_idx_inside_chunks + 1) % terrain_buffers::CHUNK_LENGTH;
+  }
+}
+
 #[allow(missing_docs)]
 // TODO: Consider making fields non-pub and exposing read-only accessors.
 pub struct T {
@@ -51,9 +88,17 @@ pub struct T {
   pub normals: Vec<terrain_buffers::Chunk<terrain_mesh::Triangle<Vector3<f32>>>>,
   /// Material IDs for each triangle.
   pub materials: Vec<terrain_buffers::Chunk<i32>>,
+  /// Texture atlas coordinates of each vertex, as (u, v, tile).
+  pub uvs: Vec<terrain_buffers::Chunk<terrain_mesh::Triangle<Vector3<f32>>>>,
   /// per-chunk ids
   pub ids: Vec<entity::id::Terrain>,
   pub grass : Grass,
+  pub water : Water,
+  /// The middle of the terrain chunk this was generated for.
+  pub center : Point3<f32>,
+  /// Whether this is the first mesh for its terrain chunk, so it should animate in.
+  /// Meshes that replace one already showing (after an edit, or a LOD change) just swap in.
+  pub appear : bool,
 
   /// The index within each `Chunk` that we should write to next when pushing new data.
   next_idx_inside_chunks: usize,
@@ -84,7 +129,7 @@ impl T {
 
   /// is there nothing to be loaded in this chunk?
   pub fn is_empty(&self) -> bool {
-    self.chunk_count() == 0
+    self.chunk_count() == 0 && self.water.ids.is_empty()
   }
 
   #[allow(missing_docs)]
@@ -93,6 +138,7 @@ impl T {
     id_allocator : &mut id_allocator::T<entity::id::Terrain>,
     vertices     : terrain_mesh::Triangle<Point3<GLfloat>>,
     normals      : terrain_mesh::Triangle<Vector3<GLfloat>>,
+    uvs          : terrain_mesh::Triangle<Vector3<GLfloat>>,
     material     : GLint,
     grass        : Option<PushGrass>,
   ) {
@@ -104,6 +150,7 @@ impl T {
       self.vertex_coordinates.push(terrain_buffers::Chunk([terrain_mesh::tri(zero, zero, zero); terrain_buffers::CHUNK_LENGTH]));
       let zero = Vector3::new(0.0, 0.0, 0.0);
       self.normals.push(terrain_buffers::Chunk([terrain_mesh::tri(zero, zero, zero); terrain_buffers::CHUNK_LENGTH]));
+      self.uvs.push(terrain_buffers::Chunk([terrain_mesh::tri(zero, zero, zero); terrain_buffers::CHUNK_LENGTH]));
       self.materials.push(terrain_buffers::Chunk([0; terrain_buffers::CHUNK_LENGTH]));
       let id = id_allocator.allocate();
       self.ids.push(id);
@@ -113,6 +160,7 @@ impl T {
 
     self.vertex_coordinates.last_mut().unwrap().0[self.next_idx_inside_chunks] = vertices;
     self.normals.last_mut().unwrap().0[self.next_idx_inside_chunks] = normals;
+    self.uvs.last_mut().unwrap().0[self.next_idx_inside_chunks] = uvs;
     self.materials.last_mut().unwrap().0[self.next_idx_inside_chunks] = material;
 
     grass.map(|grass| {
@@ -132,8 +180,12 @@ pub fn empty() -> T {
     vertex_coordinates     : Vec::new(),
     normals                : Vec::new(),
     materials              : Vec::new(),
+    uvs                    : Vec::new(),
     ids                    : Vec::new(),
     grass                  : Grass::empty(),
+    water                  : Water::empty(),
+    center                 : Point3::new(0.0, 0.0, 0.0),
+    appear                 : false,
     next_idx_inside_chunks : 0
   }
 }
@@ -0,0 +1,108 @@
+//! Data structures and functions to load/unload/maintain entities drawn as colored boxes, like mobs
+//! and dropped items, in VRAM.
+
+use std::collections::hash_map::Entry;
+use std::hash::Hash;
+use yaglw::vertex_buffer::{GLArray, GLBuffer, VertexAttribData};
+use yaglw::vertex_buffer::{DrawMode, GLType};
+use yaglw::gl_context::GLContext;
+
+use common::fnv_map;
+
+use vertex::ColoredVertex;
+use view;
+use view::backend;
+
+/// Number of vertices in each entity's mesh.
+pub const VERTICES_PER_CUBOID: usize = 36;
+
+/// This data structure keeps tracks of entity data in VRAM.
+pub struct T<'a, Id> {
+  /// What's drawn, for profiling.
+  name: &'static str,
+  id_to_index: fnv_map::T<Id, usize>,
+  index_to_id: Vec<Id>,
+
+  triangles: GLArray<'a, ColoredVertex>,
+}
+
+/// Buffers for up to `capacity` entities.
+pub fn new<'a, 'b, Id>(
+  gl: &'b mut GLContext,
+  shader: &view::shaders::color::T<'a>,
+  capacity: usize,
+  name: &'static str,
+) -> T<'a, Id> where
+  'a: 'b,
+  Id: Copy + Eq + Hash,
+{
+  let buffer = GLBuffer::new(gl, capacity * VERTICES_PER_CUBOID);
+  T {
+    name: name,
+    id_to_index: fnv_map::new(),
+    index_to_id: Vec::new(),
+
+    triangles: GLArray::new(
+      gl,
+      &shader.shader,
+      &[
+        VertexAttribData { name: "position", size: 3, unit: GLType::Float, divisor: 0 },
+        VertexAttribData { name: "in_color", size: 4, unit: GLType::Float, divisor: 0 },
+      ],
+      DrawMode::Triangles,
+      buffer,
+    ),
+  }
+}
+
+impl<'a, Id> T<'a, Id> where Id: Copy + Eq + Hash {
+  /// Add a single entity into VRAM and return true.
+  /// If the ID is already loaded, replace the existing entity and return false.
+  pub fn insert(
+    &mut self,
+    gl: &mut GLContext,
+    id: Id,
+    triangles: &[ColoredVertex; VERTICES_PER_CUBOID],
+  ) -> bool {
+    match self.id_to_index.entry(id) {
+      Entry::Vacant(entry) => {
+        entry.insert(self.index_to_id.len());
+        self.index_to_id.push(id);
+
+        self.triangles.buffer.byte_buffer.bind(gl);
+        self.triangles.push(gl, triangles);
+        true
+      },
+      Entry::Occupied(entry) => {
+        let idx = *entry.get();
+        self.triangles.buffer.byte_buffer.bind(gl);
+        self.triangles.buffer.update(gl, idx * VERTICES_PER_CUBOID, triangles);
+        false
+      },
+    }
+  }
+
+  /// Remove an entity from VRAM, if it's there.
+  pub fn swap_remove(&mut self, gl: &mut GLContext, id: Id) {
+    let idx =
+      match self.id_to_index.remove(&id) {
+        None => return,
+        Some(idx) => idx,
+      };
+    let swapped_id = self.index_to_id[self.index_to_id.len() - 1];
+    self.index_to_id.swap_remove(idx);
+    if id != swapped_id {
+      self.id_to_index.insert(swapped_id, idx);
+    }
+
+    self.triangles.buffer.byte_buffer.bind(gl);
+    self.triangles.buffer.swap_remove(gl, idx * VERTICES_PER_CUBOID, VERTICES_PER_CUBOID);
+  }
+
+  /// Draw all the entities.
+  /// N.B. This does not bind any shaders.
+  pub fn draw(&self, gl: &mut GLContext, backend: &mut backend::T) {
+    self.triangles.bind(gl);
+    backend.draw(&backend::Draw::triangles(self.name, (self.index_to_id.len() * VERTICES_PER_CUBOID) as u32));
+  }
+}
@@ -0,0 +1,170 @@
+//! How brightly to draw entities: a rough sky light, worked out per voxel from the terrain around
+//! them and interpolated at each entity's position, so mobs and players in caves are drawn dark and
+//! ones out in the open are drawn bright.
+
+use cgmath::{Point3, Vector3};
+
+use common::color::Color4;
+use common::fnv_map;
+
+/// The light in a voxel that can't see the sky at all.
+pub const MIN_LIGHT: f32 = 0.2;
+
+/// How far up a column of voxels is checked for cover. Anything higher up counts as open sky.
+const SKY_REACH: i32 = 8;
+
+/// Sky light also reaches a voxel down open columns this far off to each side, so overhangs and
+/// cave mouths are only partly dark.
+const SPREAD: i32 = 2;
+
+/// How often light is worked out again, in ns, so it catches up with terrain that's changed.
+const REFRESH_NS: u64 = 500_000_000;
+
+#[allow(missing_docs)]
+pub struct T {
+  /// The light in each voxel looked at since the last refresh, or None if it's solid.
+  levels          : fnv_map::T<Point3<i32>, Option<f32>>,
+  last_refresh_ns : u64,
+}
+
+#[allow(missing_docs)]
+pub fn new() -> T {
+  T {
+    levels          : fnv_map::new(),
+    last_refresh_ns : 0,
+  }
+}
+
+impl T {
+  /// Work out the light in the voxels around `points`, for sampling with `at`. Call this each frame
+  /// with where the entities are, while the terrain's at hand.
+  pub fn relight<Points, IsSolid>(&mut self, time_ns: u64, points: Points, mut is_solid: IsSolid) where
+    Points: IntoIterator<Item=Point3<f32>>,
+    IsSolid: FnMut(&Point3<f32>) -> bool,
+  {
+    if time_ns >= self.last_refresh_ns + REFRESH_NS {
+      self.levels.clear();
+      self.last_refresh_ns = time_ns;
+    }
+    for point in points {
+      let (base, _) = cell(&point);
+      for corner in corners(base).iter() {
+        if !self.levels.contains_key(corner) {
+          let level = level(corner, &mut is_solid);
+          self.levels.insert(*corner, level);
+        }
+      }
+    }
+  }
+
+  /// The light at a point, in [`MIN_LIGHT`, 1], interpolated between the centers of the voxels
+  /// around it. Solid voxels are left out, so entities standing on the ground aren't darkened by it.
+  /// Voxels that haven't been relit count as fully lit.
+  pub fn at(&self, point: &Point3<f32>) -> f32 {
+    let (base, fraction) = cell(point);
+    let mut total = 0.0;
+    let mut total_weight = 0.0;
+    for corner in corners(base).iter() {
+      let d = *corner - base;
+      let weight =
+        (if d.x == 0 { 1.0 - fraction.x } else { fraction.x }) *
+        (if d.y == 0 { 1.0 - fraction.y } else { fraction.y }) *
+        (if d.z == 0 { 1.0 - fraction.z } else { fraction.z });
+      let level =
+        match self.levels.get(corner) {
+          None => 1.0,
+          Some(&None) => continue,
+          Some(&Some(level)) => level,
+        };
+      total += weight * level;
+      total_weight += weight;
+    }
+    if total_weight > 0.0 {
+      total / total_weight
+    } else {
+      // Buried.
+      MIN_LIGHT
+    }
+  }
+}
+
+/// Darken a color by a light level.
+pub fn lit(color: Color4<f32>, light: f32) -> Color4<f32> {
+  Color4::of_rgba(color.r * light, color.g * light, color.b * light, color.a)
+}
+
+/// The voxel whose center is just below and behind `point` on each axis, and how far past that
+/// center `point` is.
+fn cell(point: &Point3<f32>) -> (Point3<i32>, Vector3<f32>) {
+  let shifted = Point3::new(point.x - 0.5, point.y - 0.5, point.z - 0.5);
+  let base = Point3::new(shifted.x.floor() as i32, shifted.y.floor() as i32, shifted.z.floor() as i32);
+  let fraction =
+    Vector3::new(
+      shifted.x - base.x as f32,
+      shifted.y - base.y as f32,
+      shifted.z - base.z as f32,
+    );
+  (base, fraction)
+}
+
+fn corners(base: Point3<i32>) -> [Point3<i32>; 8] {
+  let mut corners = [base; 8];
+  for (i, corner) in corners.iter_mut().enumerate() {
+    *corner = base + Vector3::new((i & 1) as i32, ((i >> 1) & 1) as i32, ((i >> 2) & 1) as i32);
+  }
+  corners
+}
+
+fn center(voxel: &Point3<i32>) -> Point3<f32> {
+  Point3::new(voxel.x as f32 + 0.5, voxel.y as f32 + 0.5, voxel.z as f32 + 0.5)
+}
+
+/// The light in a voxel: how many of the columns above it and around it are open to the sky.
+fn level<IsSolid>(voxel: &Point3<i32>, is_solid: &mut IsSolid) -> Option<f32> where
+  IsSolid: FnMut(&Point3<f32>) -> bool,
+{
+  if is_solid(&center(voxel)) {
+    return None
+  }
+  let columns = [
+    Vector3::new(0, 0, 0),
+    Vector3::new(-SPREAD, 0, 0),
+    Vector3::new(SPREAD, 0, 0),
+    Vector3::new(0, 0, -SPREAD),
+    Vector3::new(0, 0, SPREAD),
+  ];
+  let open =
+    columns.iter()
+    .filter(|&&offset| {
+      (0 .. SKY_REACH + 1).all(|dy| !is_solid(&center(&(*voxel + offset + Vector3::new(0, dy, 0)))))
+    })
+    .count();
+  Some(MIN_LIGHT + (1.0 - MIN_LIGHT) * open as f32 / columns.len() as f32)
+}
+
+#[test]
+fn entities_are_dark_under_cover() {
+  let feet = Point3::new(0.3, 1.0, 0.7);
+  let head = Point3::new(0.3, 2.5, 0.7);
+
+  // Standing on the ground out in the open, where the ground itself shouldn't darken them.
+  let ground = |p: &Point3<f32>| p.y < 1.0;
+  let mut light = new();
+  light.relight(0, vec![feet, head], ground);
+  assert_eq!(light.at(&feet), 1.0);
+  assert_eq!(light.at(&head), 1.0);
+
+  // Deep in a cave, under a wide roof.
+  let cave = |p: &Point3<f32>| p.y < 1.0 || p.y > 4.0;
+  let mut light = new();
+  light.relight(0, vec![feet, head], cave);
+  assert!((light.at(&feet) - MIN_LIGHT).abs() < 0.0001);
+  assert!((light.at(&head) - MIN_LIGHT).abs() < 0.0001);
+
+  // Under a small overhang, with open sky off to the side.
+  let overhang = |p: &Point3<f32>| p.y < 1.0 || (p.y > 4.0 && p.x < 1.0 && p.x > -1.0 && p.z < 2.0 && p.z > -1.0);
+  let mut light = new();
+  light.relight(0, vec![head], overhang);
+  assert!(light.at(&head) > MIN_LIGHT);
+  assert!(light.at(&head) < 1.0);
+}
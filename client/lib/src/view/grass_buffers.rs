@@ -11,13 +11,18 @@ use yaglw::gl_context::GLContext;
 
 use common::fnv_map;
 
+use super::backend;
 use super::entity;
 use super::terrain_buffers;
 
 // VRAM bytes
 const BYTE_BUDGET: usize = 64_000_000;
 const TUFT_COST: usize = 8;
-const TUFT_BUDGET: usize = BYTE_BUDGET / TUFT_COST;
+/// Maximum number of grass tufts in VRAM.
+pub const TUFT_BUDGET: usize = BYTE_BUDGET / TUFT_COST;
+
+/// Horizontal direction and strength of the wind that billboards sway in.
+pub const WIND: [f32; 2] = [0.35, 0.2];
 
 #[derive(Debug, Clone)]
 #[repr(C)]
@@ -256,10 +261,16 @@ impl<'a> T<'a> {
   }
 
   #[allow(missing_docs)]
-  pub fn draw(&self, _gl: &mut GLContext) {
+  pub fn draw(&self, backend: &mut backend::T) {
     unsafe {
       gl::BindVertexArray(self.gl_array.gl_id);
-      gl::DrawArraysInstanced(gl::TRIANGLES, 0, 18, self.index_to_id.len() as i32);
     }
+    backend.draw(&backend::Draw {
+      label     : "grass",
+      primitive : backend::Primitive::Triangles,
+      first     : 0,
+      count     : 18,
+      instances : self.index_to_id.len() as u32,
+    });
   }
 }
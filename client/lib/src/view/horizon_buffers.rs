@@ -0,0 +1,195 @@
+//! Data structures for loading/unloading/maintaining far horizon tiles in VRAM.
+//! Only each tile's column samples are uploaded, as a heightmap in a buffer texture. The horizon
+//! shader displaces a flat grid by them, one instance per tile, so the whole horizon goes out in
+//! one draw call and the CPU never builds a mesh for it.
+
+use cgmath::Vector2;
+use gl;
+use gl::types::*;
+use std;
+use yaglw;
+use yaglw::gl_context::GLContext;
+use yaglw::texture::{BufferTexture, TextureUnit};
+use yaglw::vertex_buffer;
+
+use common::color::Color3;
+use common::fnv_map;
+use common::horizon;
+use common::id_allocator;
+use common::voxel;
+
+use super::backend;
+
+/// Number of vertices in a tile's grid: two triangles between each square of four columns.
+const VERTICES_PER_TILE: usize = horizon::TILE_COLUMNS * horizon::TILE_COLUMNS * 6;
+
+/// Number of column samples in a tile.
+const SAMPLES_PER_TILE: usize = horizon::SAMPLES * horizon::SAMPLES;
+
+/// Maximum number of horizon tiles in VRAM.
+const TILE_BUDGET: usize = 128;
+
+/// A column sample as laid out in VRAM: one RGBA32F texel.
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+struct Sample {
+  height : GLfloat,
+  color  : Color3<GLfloat>,
+}
+
+/// Struct for loading/unloading/maintaining horizon tiles in VRAM.
+pub struct T<'a> {
+  id_to_index: fnv_map::T<horizon::Tile, usize>,
+  index_to_id: Vec<horizon::Tile>,
+
+  gl_array: yaglw::vertex_buffer::ArrayHandle<'a>,
+  /// The world-space x and z of each tile's first column, one per instance.
+  origins: yaglw::vertex_buffer::GLBuffer<'a, Vector2<GLfloat>>,
+  /// Every tile's column samples, `SAMPLES_PER_TILE` per tile, in the same order as `origins`.
+  samples: BufferTexture<'a, Sample>,
+}
+
+#[allow(missing_docs)]
+pub fn new<'a, 'b:'a>(
+  gl: &'a mut GLContext,
+  shader: &yaglw::shader::Shader<'a>,
+) -> T<'b>
+{
+  let gl_array = vertex_buffer::ArrayHandle::new(gl);
+  let origins = vertex_buffer::GLBuffer::new(gl, TILE_BUDGET);
+
+  unsafe {
+    gl::BindVertexArray(gl_array.gl_id);
+  }
+  shader.use_shader(gl);
+  origins.byte_buffer.bind(gl);
+  let attrib_span =
+    vertex_buffer::VertexAttribData::apply(
+      &[
+        vertex_buffer::VertexAttribData {
+          name: "origin",
+          size: 2,
+          unit: vertex_buffer::GLType::Float,
+          divisor: 1,
+        },
+      ],
+      gl,
+      shader,
+    );
+  assert!(attrib_span == std::mem::size_of::<Vector2<GLfloat>>() as u32);
+
+  T {
+    id_to_index: fnv_map::new(),
+    index_to_id: Vec::new(),
+
+    gl_array: gl_array,
+    origins: origins,
+    samples: BufferTexture::new(gl, gl::RGBA32F, TILE_BUDGET * SAMPLES_PER_TILE),
+  }
+}
+
+/// The color a material shows as on the horizon.
+fn color(material: voxel::Material) -> Color3<GLfloat> {
+  match material {
+    voxel::Material::Terrain => Color3::of_rgb(0.3, 0.45, 0.2),
+    voxel::Material::Sand => Color3::of_rgb(0.8, 0.75, 0.55),
+    voxel::Material::Snow => Color3::of_rgb(0.95, 0.95, 1.0),
+    voxel::Material::Gravel => Color3::of_rgb(0.45, 0.43, 0.4),
+    voxel::Material::Water => Color3::of_rgb(0.05, 0.2, 0.3),
+    _ => Color3::of_rgb(0.5, 0.5, 0.5),
+  }
+}
+
+impl<'a> T<'a> {
+  /// Bind the column samples to a shader's `samples` sampler, and tell it the shape of the tiles.
+  pub fn bind_samples(
+    &self,
+    gl: &mut GLContext,
+    texture_unit_alloc: &mut id_allocator::T<TextureUnit>,
+    shader: &mut yaglw::shader::Shader,
+  ) {
+    shader.use_shader(gl);
+    let unit = texture_unit_alloc.allocate();
+    unsafe {
+      gl::ActiveTexture(unit.gl_id());
+      gl::BindTexture(gl::TEXTURE_BUFFER, self.samples.handle.gl_id);
+      let samples_uniform = shader.get_uniform_location("samples");
+      gl::Uniform1i(samples_uniform, unit.glsl_id as GLint);
+      let tile_columns_uniform = shader.get_uniform_location("tile_columns");
+      gl::Uniform1i(tile_columns_uniform, horizon::TILE_COLUMNS as GLint);
+      let column_spacing_uniform = shader.get_uniform_location("column_spacing");
+      gl::Uniform1f(column_spacing_uniform, horizon::COLUMN_SPACING);
+    }
+  }
+
+  /// Add a tile into VRAM, replacing it if it's already there.
+  /// Tiles past the budget are dropped.
+  pub fn insert(
+    &mut self,
+    gl      : &mut GLContext,
+    tile    : horizon::Tile,
+    columns : &[horizon::Column],
+  ) {
+    assert_eq!(columns.len(), SAMPLES_PER_TILE);
+    let samples: Vec<Sample> =
+      columns.iter()
+      .map(|column| Sample { height: column.height, color: color(column.material) })
+      .collect();
+
+    if let Some(&idx) = self.id_to_index.get(&tile) {
+      self.samples.buffer.byte_buffer.bind(gl);
+      self.samples.buffer.update(gl, idx * SAMPLES_PER_TILE, &samples);
+      return
+    }
+
+    if self.index_to_id.len() >= TILE_BUDGET {
+      debug!("No room in VRAM for horizon tile {:?}", tile);
+      return
+    }
+
+    self.id_to_index.insert(tile, self.index_to_id.len());
+    self.index_to_id.push(tile);
+
+    let (x, z) = tile.sample_position(0, 0);
+    self.origins.byte_buffer.bind(gl);
+    assert!(self.origins.push(gl, &[Vector2::new(x, z)]));
+    self.samples.buffer.byte_buffer.bind(gl);
+    assert!(self.samples.buffer.push(gl, &samples));
+  }
+
+  /// Remove a tile from VRAM, if it's there.
+  pub fn swap_remove(&mut self, gl: &mut GLContext, tile: horizon::Tile) {
+    let idx =
+      match self.id_to_index.remove(&tile) {
+        None => return,
+        Some(idx) => idx,
+      };
+    let swapped_tile = self.index_to_id[self.index_to_id.len() - 1];
+    self.index_to_id.swap_remove(idx);
+    if tile != swapped_tile {
+      self.id_to_index.insert(swapped_tile, idx);
+    }
+
+    self.origins.byte_buffer.bind(gl);
+    self.origins.swap_remove(gl, idx, 1);
+    self.samples.buffer.byte_buffer.bind(gl);
+    self.samples.buffer.swap_remove(gl, idx * SAMPLES_PER_TILE, SAMPLES_PER_TILE);
+  }
+
+  /// Draw the whole horizon.
+  /// N.B. This does not bind any shaders.
+  pub fn draw(&self, backend: &mut backend::T) {
+    unsafe {
+      gl::BindVertexArray(self.gl_array.gl_id);
+    }
+    backend.draw(
+      &backend::Draw {
+        label     : "horizon",
+        primitive : backend::Primitive::Triangles,
+        first     : 0,
+        count     : VERTICES_PER_TILE as u32,
+        instances : self.index_to_id.len() as u32,
+      }
+    );
+  }
+}
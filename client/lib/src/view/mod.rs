@@ -1,21 +1,38 @@
 //! The state associated with perceiving the world state.
 
+pub mod backend;
 mod camera;
+pub mod capabilities;
 pub mod chunked_terrain;
+mod cuboid_buffers;
 mod grass_buffers;
+mod horizon_buffers;
 pub mod entity;
+mod entity_light;
 pub mod light;
-mod mob_buffers;
 mod player_buffers;
+mod post;
+mod reflection;
 mod render;
+pub mod screenshot;
 pub mod shaders;
+mod shadows;
 pub mod terrain_buffers;
 pub mod thread;
+mod toasts;
+mod underwater;
 pub mod update;
+mod upload;
+mod vitals;
+pub mod vram_budget;
+mod water_buffers;
 
 pub use self::render::render;
 
 use cgmath;
+use cgmath::{EuclideanSpace, InnerSpace};
+use collision;
+use collision::Aabb;
 use gl;
 use gl::types::*;
 use image;
@@ -26,14 +43,47 @@ use yaglw;
 use yaglw::vertex_buffer::{GLArray, GLBuffer, GLType, DrawMode, VertexAttribData};
 use yaglw::texture::{TextureUnit};
 
+use common::color::Color4;
+use common::fnv_map;
+use common::fnv_set;
+use common::frustum;
 use common::id_allocator;
+use common::protocol;
+use common::ray;
+use common::voxel;
+
+use atlas;
+use material_colors;
+use mob_interpolation;
+use resource_pack;
 use vertex::{ColoredVertex};
 
 /// FOV in radians
-pub const FOV: f32 = std::f32::consts::FRAC_PI_3;
+pub const FOV: f32 = frustum::FOV;
 
 const VERTICES_PER_TRIANGLE: usize = 3;
 
+/// The most dropped items there's room to draw.
+pub const MAX_ITEMS: usize = 256;
+
+/// How big dropped items are drawn.
+const ITEM_SIZE: f32 = 0.25;
+
+/// How fast dropped items spin, in radians per second.
+const ITEM_SPIN: f32 = 2.0;
+
+/// The most thrown things there's room to draw.
+const MAX_PROJECTILES: usize = 64;
+
+/// How big thrown things are drawn.
+const PROJECTILE_SIZE: f32 = 0.2;
+
+/// How long the streak behind thrown things is, in seconds of their flight.
+const TRACER_SECONDS: f32 = 0.1;
+
+/// How thick the streak behind thrown things is.
+const TRACER_WIDTH: f32 = 0.04;
+
 #[allow(missing_docs)]
 pub enum InputMode {
   Camera,
@@ -44,6 +94,10 @@ pub enum InputMode {
 pub struct T<'a> {
   /// Current OpengL context.
   pub gl: GLContext,
+  /// What render passes draw with.
+  pub backend: Box<backend::T>,
+  /// What the OpenGL driver supports.
+  pub capabilities: capabilities::T,
 
   #[allow(missing_docs)]
   pub shaders: shaders::T<'a>,
@@ -57,21 +111,80 @@ pub struct T<'a> {
   pub grass_buffers: grass_buffers::T<'a>,
   /// The OpenGL texture to sample for grass
   pub grass_texture: yaglw::texture::Texture2D<'a>,
+  /// Terrain texture atlas
+  pub atlas_texture: yaglw::texture::Texture2D<'a>,
+  /// The OpenGL buffers for the water surface
+  pub water_buffers: water_buffers::T<'a>,
+  /// The OpenGL buffers for the far horizon
+  pub horizon_buffers: horizon_buffers::T<'a>,
+  /// How far out, along x and z, the polygonal terrain reaches. The horizon is drawn past this.
+  pub horizon_start: f32,
+  /// Sun shadow maps for the terrain
+  pub shadows: shadows::T,
+  /// Where water reflections are drawn, if they're turned on
+  pub reflection: Option<reflection::T>,
+  /// HDR framebuffers for bloom and tone mapping, if they're turned on
+  pub post: Option<post::T>,
+  /// Which terrain meshes are in VRAM, to keep them within budget
+  pub vram: vram_budget::T,
   /// OpenGL buffers for mob render data
-  pub mob_buffers: mob_buffers::T<'a>,
+  pub mob_buffers: cuboid_buffers::T<'a, entity::id::Mob>,
+  /// Recent mob transforms, to draw mobs smoothly between server updates.
+  pub mobs: fnv_map::T<entity::id::Mob, mob_interpolation::T>,
+  /// Mobs last moved somewhere out of view, whose meshes needn't move again until they're back in it.
+  pub culled_mobs: fnv_set::T<entity::id::Mob>,
+  /// OpenGL buffers for dropped item render data
+  pub item_buffers: cuboid_buffers::T<'a, entity::id::Item>,
+  /// Where each dropped item is, and what it's made of.
+  pub items: fnv_map::T<entity::id::Item, (cgmath::Point3<f32>, voxel::Material)>,
+  /// OpenGL buffers for thrown things.
+  pub projectile_buffers: cuboid_buffers::T<'a, entity::id::Projectile>,
+  /// OpenGL buffers for the streaks behind thrown things.
+  pub tracer_buffers: cuboid_buffers::T<'a, entity::id::Projectile>,
+  /// The thrown things being drawn.
+  pub projectiles: fnv_set::T<entity::id::Projectile>,
+  /// The bounds of each player's avatar.
+  pub players: fnv_map::T<entity::id::Player, collision::Aabb3<f32>>,
+  /// How brightly to draw entities where they are.
+  pub entity_light: entity_light::T,
   /// OpenGL buffers for player render data
   pub player_buffers: player_buffers::T<'a>,
+  /// What player avatars look like.
+  pub player_model: player_buffers::Model,
   /// Hud triangles for non-text.
   pub hud_triangles: GLArray<'a, ColoredVertex>,
+  /// Number of vertices in `hud_triangles`.
+  pub hud_vertex_count: usize,
 
   #[allow(missing_docs)]
   pub sun: light::Sun,
   #[allow(missing_docs)]
   pub camera: camera::T,
+  /// Where the player's eyes are. The camera is here in first person, and looks over their shoulder
+  /// in third person.
+  pub eye: cgmath::Point3<f32>,
+  /// Whether the camera follows behind the player, instead of looking out of their eyes.
+  pub third_person: bool,
+  /// Keeps the first-person camera from poking into ceilings.
+  pub head_room: camera::HeadRoom,
   #[allow(missing_docs)]
   pub window_size: cgmath::Vector2<i32>,
   /// Whether to render HUD elements
   pub show_hud: bool,
+  /// Whether the camera is inside water.
+  pub underwater: bool,
+  /// Grass and flowers fade out approaching this distance from the camera.
+  pub decoration_distance: f32,
+  /// Feedback about the player's health.
+  pub vitals: vitals::T,
+  /// Messages from the server waiting to be shown.
+  pub toasts: toasts::T,
+  /// The health of the boss being fought nearby, as a fraction of its max, and when it arrived.
+  pub boss_health: Option<(f32, u64)>,
+  /// What's in each of the player's inventory slots, as shown in the hotbar.
+  pub inventory: Vec<Option<protocol::ItemStack>>,
+  /// The inventory slot being placed from.
+  pub selected_slot: usize,
 
   /// Whether to render HUD elements
   pub input_mode: InputMode,
@@ -84,9 +197,10 @@ pub struct T<'a> {
 
 fn load_grass_texture<'a, 'b:'a>(
   gl: &'a mut GLContext,
+  resource_packs: &resource_pack::T,
 ) -> image::ImageResult<yaglw::texture::Texture2D<'b>> {
   let grass_texture = yaglw::texture::Texture2D::new(&gl);
-  let fd = std::fs::File::open("textures/Free_Vector_Grass.png").unwrap();
+  let fd = try!(std::fs::File::open(resource_packs.resolve("textures/Free_Vector_Grass.png")));
   let buffered_file = std::io::BufReader::new(fd);
   let image = try!(image::load(buffered_file, image::ImageFormat::PNG));
   let (w, h) = image.dimensions();
@@ -114,46 +228,72 @@ fn load_grass_texture<'a, 'b:'a>(
   Ok(grass_texture)
 }
 
+/// Generate the terrain texture atlas into a texture bound to `unit`.
+fn make_atlas_texture<'a, 'b:'a>(gl: &'a GLContext, unit: &TextureUnit) -> yaglw::texture::Texture2D<'b> {
+  let texture = yaglw::texture::Texture2D::new(gl);
+  let image = atlas::generate();
+  unsafe {
+    gl::ActiveTexture(unit.gl_id());
+    gl::BindTexture(gl::TEXTURE_2D, texture.handle.gl_id);
+    gl::TexImage2D(
+      gl::TEXTURE_2D, 0, gl::RGBA as i32, image.width as i32, image.height as i32, 0,
+      gl::RGBA, gl::UNSIGNED_BYTE, image.pixels.as_ptr() as *const _,
+    );
+    gl::GenerateMipmap(gl::TEXTURE_2D);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as i32);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR_MIPMAP_LINEAR as i32);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as i32);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as i32);
+  }
+  texture
+}
+
 #[allow(missing_docs)]
 pub fn new<'a>(
   mut gl: GLContext,
+  resource_packs: &resource_pack::T,
   window_size: cgmath::Vector2<i32>,
+  vram_budget: usize,
+  reflections: bool,
+  tone_mapping: bool,
+  bloom: bool,
+  triplanar: bool,
+  decoration_distance: f32,
 ) -> T<'a> {
+  let capabilities = capabilities::detect();
+  let requested_vram_budget = vram_budget;
+  let vram_budget = capabilities.terrain_byte_budget(requested_vram_budget);
+  capabilities.report(vram_budget, requested_vram_budget);
+
   let mut texture_unit_alloc = id_allocator::new();
 
-  let mut shaders = shaders::new(&mut gl, window_size);
+  let mut shaders = shaders::new(&mut gl, resource_packs, window_size);
 
-  let terrain_buffers = terrain_buffers::new(&mut gl);
-  terrain_buffers.bind_vertex_positions(
+  let terrain_buffers = terrain_buffers::new(&mut gl, vram_budget, capabilities.buffer_storage);
+  terrain_buffers.bind_vertices(
     &mut gl,
     &mut texture_unit_alloc,
     &mut shaders.terrain_shader.shader,
   );
-  terrain_buffers.bind_normals(
-    &mut gl,
-    &mut texture_unit_alloc,
-    &mut shaders.terrain_shader.shader,
-  );
-  terrain_buffers.bind_materials(
-    &mut gl,
-    &mut texture_unit_alloc,
-    &mut shaders.terrain_shader.shader,
-  );
-  terrain_buffers.bind_vertex_positions(
+  terrain_buffers.bind_vertices(
     &mut gl,
     &mut texture_unit_alloc,
     &mut shaders.grass_billboard.shader,
   );
-  terrain_buffers.bind_normals(
+  terrain_buffers.bind_vertices(
     &mut gl,
     &mut texture_unit_alloc,
-    &mut shaders.grass_billboard.shader,
+    &mut shaders.shadow.shader,
   );
 
-  let mob_buffers = mob_buffers::new(&mut gl, &shaders.mob_shader);
+  let water_buffers = water_buffers::new(&mut gl, &shaders.water.shader);
+  let mob_buffers = cuboid_buffers::new(&mut gl, &shaders.mob_shader, 32, "mobs");
+  let item_buffers = cuboid_buffers::new(&mut gl, &shaders.mob_shader, MAX_ITEMS, "items");
+  let projectile_buffers = cuboid_buffers::new(&mut gl, &shaders.mob_shader, MAX_PROJECTILES, "projectiles");
+  let tracer_buffers = cuboid_buffers::new(&mut gl, &shaders.mob_shader, MAX_PROJECTILES, "tracers");
   let player_buffers = player_buffers::new(&mut gl, &shaders.mob_shader);
 
-  let buffer = GLBuffer::new(&mut gl, 16 * VERTICES_PER_TRIANGLE);
+  let buffer = GLBuffer::new(&mut gl, 64 * VERTICES_PER_TRIANGLE);
   let hud_triangles = {
     GLArray::new(
       &mut gl,
@@ -167,6 +307,48 @@ pub fn new<'a>(
     )
   };
 
+  let horizon_buffers = horizon_buffers::new(&mut gl, &shaders.horizon.shader);
+  horizon_buffers.bind_samples(&mut gl, &mut texture_unit_alloc, &mut shaders.horizon.shader);
+
+  let shadows = shadows::new(&mut texture_unit_alloc);
+
+  let reflection =
+    if reflections {
+      let reflection = reflection::new(&mut texture_unit_alloc, &window_size);
+      let reflection_uniform = shaders.water.shader.get_uniform_location("reflection");
+      shaders.water.shader.use_shader(&mut gl);
+      unsafe {
+        gl::Uniform1i(reflection_uniform, reflection.unit.glsl_id as GLint);
+      }
+      Some(reflection)
+    } else {
+      None
+    };
+
+  let atlas_texture = {
+    let unit = texture_unit_alloc.allocate();
+    let atlas_texture = make_atlas_texture(&gl, &unit);
+    shaders.terrain_shader.shader.use_shader(&mut gl);
+    unsafe {
+      let atlas_uniform = shaders.terrain_shader.shader.get_uniform_location("atlas");
+      gl::Uniform1i(atlas_uniform, unit.glsl_id as GLint);
+      let atlas_tiles_uniform = shaders.terrain_shader.shader.get_uniform_location("atlas_tiles");
+      gl::Uniform1i(atlas_tiles_uniform, atlas::TILES as GLint);
+      let triplanar_uniform = shaders.terrain_shader.shader.get_uniform_location("triplanar");
+      gl::Uniform1i(triplanar_uniform, triplanar as GLint);
+      let tile_world_size_uniform = shaders.terrain_shader.shader.get_uniform_location("tile_world_size");
+      gl::Uniform1fv(tile_world_size_uniform, atlas::TILES as GLsizei, atlas::TILE_WORLD_SIZES.as_ptr());
+    }
+    atlas_texture
+  };
+
+  let post =
+    if tone_mapping {
+      Some(post::new(&mut texture_unit_alloc, &mut shaders.post, &mut gl, &window_size, bloom))
+    } else {
+      None
+    };
+
   let misc_texture_unit = texture_unit_alloc.allocate();
 
   unsafe {
@@ -204,7 +386,7 @@ pub fn new<'a>(
   }
 
   let grass_buffers = grass_buffers::new(&mut gl, &shaders.grass_billboard.shader);
-  let grass_texture = load_grass_texture(&mut gl).unwrap();
+  let grass_texture = load_grass_texture(&mut gl, resource_packs).unwrap();
 
   let empty_gl_array = yaglw::vertex_buffer::ArrayHandle::new(&gl);
 
@@ -213,14 +395,40 @@ pub fn new<'a>(
 
   T {
     gl: gl,
+    backend: Box::new(backend::opengl::new()),
+    capabilities: capabilities,
     shaders: shaders,
 
     terrain_buffers: terrain_buffers,
     grass_buffers: grass_buffers,
     grass_texture: grass_texture,
+    atlas_texture: atlas_texture,
+    water_buffers: water_buffers,
+    horizon_buffers: horizon_buffers,
+    horizon_start: 0.0,
+    shadows: shadows,
+    reflection: reflection,
+    post: post,
+    vram:
+      vram_budget::new(vram_budget::Usage {
+        terrain_chunks : terrain_buffers::chunk_budget(vram_budget),
+        water_chunks   : water_buffers::CHUNK_BUDGET,
+        grass          : grass_buffers::TUFT_BUDGET,
+      }),
     mob_buffers: mob_buffers,
+    item_buffers: item_buffers,
+    items: fnv_map::new(),
+    projectile_buffers: projectile_buffers,
+    tracer_buffers: tracer_buffers,
+    projectiles: fnv_set::new(),
+    players: fnv_map::new(),
+    entity_light: entity_light::new(),
+    mobs: fnv_map::new(),
+    culled_mobs: fnv_set::new(),
     player_buffers: player_buffers,
+    player_model: player_buffers::load_model(resource_packs),
     hud_triangles: hud_triangles,
+    hud_vertex_count: 0,
 
     empty_gl_array: empty_gl_array,
     misc_texture_unit: misc_texture_unit,
@@ -238,6 +446,10 @@ pub fn new<'a>(
       camera
     },
 
+    eye: cgmath::Point3::new(0.0, 0.0, 0.0),
+    third_person: false,
+    head_room: camera::head_room(),
+
     sun:
       light::Sun {
         progression: 0.0,
@@ -245,9 +457,211 @@ pub fn new<'a>(
       },
 
     show_hud: true,
+    underwater: false,
+    decoration_distance: decoration_distance,
+    vitals: vitals::new(),
+    toasts: toasts::new(),
+    boss_health: None,
+    inventory: vec![None; protocol::INVENTORY_SLOTS],
+    selected_slot: 0,
     input_mode: InputMode::Camera,
 
     near_clip: near_clip,
     far_clip: far_clip,
   }
 }
+
+impl<'a> T<'a> {
+  /// Reload textures, shaders, and models, e.g. after the active resource packs have changed.
+  pub fn reload_resources(&mut self, resource_packs: &resource_pack::T) {
+    match load_grass_texture(&mut self.gl, resource_packs) {
+      Ok(grass_texture) => self.grass_texture = grass_texture,
+      Err(err) => warn!("Error reloading grass texture: {:?}", err),
+    }
+    self.shaders.reload(&mut self.gl, resource_packs);
+    self.player_model = player_buffers::load_model(resource_packs);
+    self.update_players();
+  }
+
+  /// Put the third-person camera behind the player, in front of any terrain in the way.
+  pub fn place_third_person_camera<IsSolid>(&mut self, is_solid: IsSolid) where
+    IsSolid: FnMut(&cgmath::Point3<f32>) -> bool,
+  {
+    let position = camera::third_person_position(self.eye, self.camera.forward(), is_solid);
+    self.camera.translate_to(position);
+  }
+
+  /// Put the first-person camera at the player's eyes at `time_ns`, or a little below them if it
+  /// would be inside or right under terrain.
+  pub fn place_first_person_camera<IsSolid>(&mut self, time_ns: u64, is_solid: IsSolid) where
+    IsSolid: FnMut(&cgmath::Point3<f32>) -> bool,
+  {
+    let position = self.head_room.place(self.eye, time_ns, is_solid);
+    self.camera.translate_to(position);
+  }
+
+  /// What the camera can see.
+  pub fn frustum(&self) -> frustum::T {
+    let aspect = self.window_size.x as f32 / self.window_size.y as f32;
+    frustum::new(&self.camera.position, &self.camera.forward(), FOV, aspect, self.near_clip, self.far_clip)
+  }
+
+  /// Put the camera at `position`, turned `yaw` radians to the left of where it starts out facing
+  /// and tilted `pitch` radians up.
+  pub fn pose_camera(&mut self, position: cgmath::Point3<f32>, yaw: f32, pitch: f32) {
+    let fov = self.camera.fov;
+    self.camera = camera::unit();
+    self.camera.fov = fov;
+    self.camera.rotate_lateral(std::f32::consts::PI / 2.0 + yaw);
+    self.camera.rotate_vertical(pitch);
+    self.camera.translate_to(position);
+    self.eye = position;
+    self.third_person = false;
+  }
+
+  /// Work out how brightly to draw the mobs, players and items at `time_ns`, from the terrain around
+  /// them.
+  pub fn relight_entities<IsSolid>(&mut self, time_ns: u64, is_solid: IsSolid) where
+    IsSolid: FnMut(&cgmath::Point3<f32>) -> bool,
+  {
+    let mut points: Vec<_> = self.mobs.values().filter_map(|mob| mob.sample(time_ns)).map(|t| t.position).collect();
+    points.extend(self.players.values().map(|bounds| bounds.center()));
+    points.extend(self.items.values().map(|&(position, _)| position));
+    self.entity_light.relight(time_ns, points, is_solid);
+  }
+
+  /// Move the mob meshes to where the mobs should be drawn at `time_ns`. Mobs out of view are moved
+  /// once, so they aren't left showing where they were, then left alone until they come back.
+  pub fn update_mobs(&mut self, time_ns: u64) {
+    let frustum = self.frustum();
+    for (&id, mob) in &self.mobs {
+      let transform =
+        match mob.sample(time_ns) {
+          None => continue,
+          Some(transform) => transform,
+        };
+      let half_size = transform.size * 0.5;
+      if frustum.intersects_sphere(&transform.position, half_size.magnitude()) {
+        self.culled_mobs.remove(&id);
+      } else if !self.culled_mobs.insert(id) {
+        continue
+      }
+      let bounds = collision::Aabb3::new(cgmath::Point3::from_vec(-half_size), cgmath::Point3::from_vec(half_size));
+      let light = self.entity_light.at(&transform.position);
+      let mut triangles = ColoredVertex::cuboid(&bounds, entity_light::lit(Color4::of_rgba(1.0, 0.0, 0.0, 1.0), light));
+      let rotation = cgmath::Matrix3::from_axis_angle(cgmath::Vector3::new(0.0, 1.0, 0.0), cgmath::Rad(transform.yaw));
+      for vertex in triangles.iter_mut() {
+        vertex.position = transform.position + rotation * vertex.position.to_vec();
+      }
+      self.mob_buffers.insert(&mut self.gl, id, &triangles);
+    }
+  }
+
+  /// Shade the player avatars for how much light they're standing in.
+  pub fn update_players(&mut self) {
+    for (&id, bounds) in &self.players {
+      let light = self.entity_light.at(&bounds.center());
+      self.player_buffers.insert(&mut self.gl, id, &player_buffers::avatar(&self.player_model, bounds, light));
+    }
+  }
+
+  /// Turn the dropped item meshes to where they've spun to at `time_ns`.
+  pub fn update_items(&mut self, time_ns: u64) {
+    let half_size = ITEM_SIZE / 2.0;
+    let bounds =
+      collision::Aabb3::new(
+        cgmath::Point3::new(-half_size, -half_size, -half_size),
+        cgmath::Point3::new(half_size, half_size, half_size),
+      );
+    // Wrapped, so the angle keeps its precision.
+    let seconds = (time_ns % 1_000_000_000_000) as f32 / 1_000_000_000.0;
+    let rotation = cgmath::Matrix3::from_axis_angle(cgmath::Vector3::new(0.0, 1.0, 0.0), cgmath::Rad(seconds * ITEM_SPIN));
+    for (&id, &(position, material)) in &self.items {
+      let light = self.entity_light.at(&position);
+      let mut triangles = ColoredVertex::cuboid(&bounds, entity_light::lit(material_colors::of(material), light));
+      for vertex in triangles.iter_mut() {
+        vertex.position = position + rotation * vertex.position.to_vec();
+      }
+      self.item_buffers.insert(&mut self.gl, id, &triangles);
+    }
+  }
+
+  /// Draw something thrown where it is, with a streak behind it along the way it came. `velocity` is
+  /// in voxels per second.
+  pub fn show_projectile(
+    &mut self,
+    id: entity::id::Projectile,
+    position: cgmath::Point3<f32>,
+    velocity: cgmath::Vector3<f32>,
+    material: voxel::Material,
+  ) {
+    // Past the limit, new projectiles are left undrawn until others go.
+    if !self.projectiles.contains(&id) && self.projectiles.len() >= MAX_PROJECTILES {
+      return
+    }
+    self.projectiles.insert(id);
+
+    let half_size = PROJECTILE_SIZE / 2.0;
+    let bounds =
+      collision::Aabb3::new(
+        position + cgmath::Vector3::new(-half_size, -half_size, -half_size),
+        position + cgmath::Vector3::new(half_size, half_size, half_size),
+      );
+    self.projectile_buffers.insert(&mut self.gl, id, &ColoredVertex::cuboid(&bounds, material_colors::of(material)));
+
+    // A thin box trailing back along -z, turned to face along the velocity.
+    let speed = velocity.magnitude();
+    if speed == 0.0 {
+      return
+    }
+    let direction = velocity / speed;
+    let half_width = TRACER_WIDTH / 2.0;
+    let tracer =
+      collision::Aabb3::new(
+        cgmath::Point3::new(-half_width, -half_width, -speed * TRACER_SECONDS),
+        cgmath::Point3::new(half_width, half_width, 0.0),
+      );
+    let pitch = direction.y.max(-1.0).min(1.0).asin();
+    let yaw = direction.x.atan2(direction.z);
+    let rotation =
+      cgmath::Matrix3::from_axis_angle(cgmath::Vector3::new(0.0, 1.0, 0.0), cgmath::Rad(yaw)) *
+      cgmath::Matrix3::from_axis_angle(cgmath::Vector3::new(1.0, 0.0, 0.0), cgmath::Rad(-pitch));
+    let mut triangles = ColoredVertex::cuboid(&tracer, Color4::of_rgba(1.0, 0.9, 0.6, 1.0));
+    for vertex in triangles.iter_mut() {
+      vertex.position = position + rotation * vertex.position.to_vec();
+    }
+    self.tracer_buffers.insert(&mut self.gl, id, &triangles);
+  }
+
+  /// Stop drawing something thrown.
+  pub fn hide_projectile(&mut self, id: entity::id::Projectile) {
+    self.projectiles.remove(&id);
+    self.projectile_buffers.swap_remove(&mut self.gl, id);
+    self.tracer_buffers.swap_remove(&mut self.gl, id);
+  }
+
+  /// The nearest mob in the middle of the screen within `reach` of the eye, as drawn at `time_ns`.
+  pub fn aimed_mob(&self, reach: f32, time_ns: u64) -> Option<entity::id::Mob> {
+    let ray = collision::Ray3::new(self.eye, self.camera.forward());
+    let mut nearest = None;
+    for (&id, mob) in &self.mobs {
+      let transform =
+        match mob.sample(time_ns) {
+          None => continue,
+          Some(transform) => transform,
+        };
+      let half_size = transform.size * 0.5;
+      let bounds = collision::Aabb3::new(transform.position - half_size, transform.position + half_size);
+      match ray::hit(&ray, &bounds) {
+        Some((distance, _)) if distance <= reach => {
+          match nearest {
+            Some((nearest_distance, _)) if nearest_distance <= distance => {},
+            _ => nearest = Some((distance, id)),
+          }
+        },
+        _ => {},
+      }
+    }
+    nearest.map(|(_, id)| id)
+  }
+}
@@ -1,18 +1,149 @@
 //! Data structures and functions to load/unload/maintain mob data in VRAM.
 
+use cgmath::Point3;
+use collision::Aabb3;
+use std;
 use std::collections::hash_map::Entry;
+use std::io::Read;
 use yaglw::vertex_buffer::{GLArray, GLBuffer, VertexAttribData};
 use yaglw::vertex_buffer::{DrawMode, GLType};
 use yaglw::gl_context::GLContext;
 
+use common::color::Color4;
 use common::entity;
 use common::fnv_map;
 
+use resource_pack;
 use vertex::ColoredVertex;
 use view;
+use view::backend;
+use view::entity_light;
 
-/// Number of vertices in a player mesh.
-pub const VERTICES_PER_PLAYER: usize = 36;
+/// Number of vertices in a player mesh: a body and a head.
+pub const VERTICES_PER_PLAYER: usize = 72;
+
+/// Where resource packs can override the player model, relative to the default assets.
+pub const MODEL_ASSET: &'static str = "models/player.txt";
+
+/// The proportions and colors of the player avatar: a head on top of a body.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Model {
+  /// The fraction of a player's height taken up by their head.
+  pub head_height : f32,
+  /// How much narrower the head is than the body on each side, as a fraction of the body's width.
+  pub head_inset  : f32,
+  #[allow(missing_docs)]
+  pub body_color  : Color4<f32>,
+  #[allow(missing_docs)]
+  pub head_color  : Color4<f32>,
+}
+
+/// The built-in player model.
+pub fn default_model() -> Model {
+  Model {
+    head_height : 0.25,
+    head_inset  : 0.2,
+    body_color  : Color4::of_rgba(0.0, 0.0, 1.0, 1.0),
+    head_color  : Color4::of_rgba(0.9, 0.7, 0.55, 1.0),
+  }
+}
+
+/// Parse a player model from `name = value` lines, e.g. `head_color = 0.9 0.7 0.55 1.0`.
+/// Anything missing or malformed keeps its built-in value.
+pub fn parse_model(contents: &str) -> Model {
+  let mut model = default_model();
+
+  for line in contents.lines() {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+      continue
+    }
+
+    let mut split = line.splitn(2, '=');
+    let name = split.next().unwrap().trim();
+    let value =
+      match split.next() {
+        None => {
+          warn!("Ignoring malformed player model line {:?}", line);
+          continue
+        },
+        Some(value) => value.trim(),
+      };
+
+    let numbers: Vec<f32> = value.split_whitespace().filter_map(|x| x.parse().ok()).collect();
+    if numbers.len() != value.split_whitespace().count() {
+      warn!("Ignoring non-numeric value for {}: {:?}", name, value);
+      continue
+    }
+    let fraction = |numbers: &[f32]| {
+      if numbers.len() == 1 && 0.0 <= numbers[0] && numbers[0] <= 1.0 {
+        Some(numbers[0])
+      } else {
+        warn!("Ignoring {}: expected a number between 0 and 1, got {:?}", name, value);
+        None
+      }
+    };
+    let color = |numbers: &[f32]| {
+      if numbers.len() == 4 {
+        Some(Color4::of_rgba(numbers[0], numbers[1], numbers[2], numbers[3]))
+      } else {
+        warn!("Ignoring {}: expected r g b a, got {:?}", name, value);
+        None
+      }
+    };
+
+    match name {
+      "head_height" => fraction(&numbers).map(|x| model.head_height = x),
+      // Past half, the sides of the head would cross over.
+      "head_inset"  => fraction(&numbers).map(|x| model.head_inset = x.min(0.5)),
+      "body_color"  => color(&numbers).map(|c| model.body_color = c),
+      "head_color"  => color(&numbers).map(|c| model.head_color = c),
+      _ => {
+        warn!("Ignoring unknown player model property {:?}", name);
+        None
+      },
+    };
+  }
+
+  model
+}
+
+/// Load the player model from the active resource packs, falling back to the built-in one.
+pub fn load_model(resource_packs: &resource_pack::T) -> Model {
+  let path = resource_packs.resolve(MODEL_ASSET);
+  let mut contents = String::new();
+  match std::fs::File::open(&path).and_then(|mut file| file.read_to_string(&mut contents)) {
+    Ok(_) => parse_model(&contents),
+    Err(err) => {
+      warn!("Error loading player model from {:?}: {:?}", path, err);
+      default_model()
+    },
+  }
+}
+
+/// A player's avatar, filling their bounds: a head on top of a body, shaded by `light`.
+pub fn avatar(model: &Model, bounds: &Aabb3<f32>, light: f32) -> [ColoredVertex; VERTICES_PER_PLAYER] {
+  let size = bounds.max - bounds.min;
+  let neck = bounds.max.y - size.y * model.head_height;
+  let body =
+    ColoredVertex::cuboid(
+      &Aabb3::new(bounds.min, Point3::new(bounds.max.x, neck, bounds.max.z)),
+      entity_light::lit(model.body_color, light),
+    );
+  let inset = size * model.head_inset;
+  let head =
+    ColoredVertex::cuboid(
+      &Aabb3::new(
+        Point3::new(bounds.min.x + inset.x, neck, bounds.min.z + inset.z),
+        Point3::new(bounds.max.x - inset.x, bounds.max.y, bounds.max.z - inset.z),
+      ),
+      entity_light::lit(model.head_color, light),
+    );
+  let mut triangles = [body[0]; VERTICES_PER_PLAYER];
+  triangles[.. body.len()].copy_from_slice(&body);
+  triangles[body.len() ..].copy_from_slice(&head);
+  triangles
+}
 
 /// This data structure keeps tracks of mob data in VRAM.
 pub struct T<'a> {
@@ -74,10 +205,44 @@ impl<'a> T<'a> {
     }
   }
 
+  /// Remove a player's mesh from VRAM, if it's there.
+  pub fn swap_remove(&mut self, gl: &mut GLContext, id: entity::id::Player) {
+    let idx =
+      match self.id_to_index.remove(&id) {
+        None => return,
+        Some(idx) => idx,
+      };
+    let swapped_id = self.index_to_id[self.index_to_id.len() - 1];
+    self.index_to_id.swap_remove(idx);
+    if id != swapped_id {
+      self.id_to_index.insert(swapped_id, idx);
+    }
+
+    self.triangles.buffer.byte_buffer.bind(gl);
+    self.triangles.buffer.swap_remove(gl, idx * VERTICES_PER_PLAYER, VERTICES_PER_PLAYER);
+  }
+
   /// Draw all the mobs.
   /// N.B. This does not bind any shaders.
-  pub fn draw(&self, gl: &mut GLContext) {
+  pub fn draw(&self, gl: &mut GLContext, backend: &mut backend::T) {
     self.triangles.bind(gl);
-    self.triangles.draw(gl);
+    backend.draw(&backend::Draw::triangles("players", (self.index_to_id.len() * VERTICES_PER_PLAYER) as u32));
   }
 }
+
+#[test]
+fn player_models_override_only_what_they_set() {
+  let model =
+    parse_model("
+      # A green player with a big head.
+      head_height = 0.5
+      body_color = 0 1 0 1
+      head_inset = lots
+      head_color = 1 1 1
+      legs = 2
+    ");
+  assert_eq!(model.head_height, 0.5);
+  assert_eq!(model.body_color, Color4::of_rgba(0.0, 1.0, 0.0, 1.0));
+  assert_eq!(model.head_inset, default_model().head_inset);
+  assert_eq!(model.head_color, default_model().head_color);
+}
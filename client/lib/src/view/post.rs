@@ -0,0 +1,164 @@
+//! HDR rendering and post-processing. The scene is drawn into a floating-point framebuffer, then its
+//! brightest parts are blurred into a bloom, and the two are tone mapped onto the screen.
+
+use cgmath;
+use gl;
+use gl::types::*;
+use std;
+use yaglw::gl_context::GLContext;
+use yaglw::texture::TextureUnit;
+
+use common::id_allocator;
+
+use view::shaders;
+use view::underwater;
+
+/// The bloom is blurred at this fraction of the window resolution in each dimension.
+const BLOOM_DIVISOR: i32 = 2;
+
+/// Brightness past which things start to bloom.
+pub const BLOOM_THRESHOLD: f32 = 1.0;
+
+/// How much of the bloom is added back onto the scene.
+pub const BLOOM_STRENGTH: f32 = 0.3;
+
+/// A color texture with a framebuffer drawing into it.
+struct Target {
+  framebuffer : GLuint,
+  texture     : GLuint,
+  unit        : TextureUnit,
+}
+
+fn target(
+  texture_unit_alloc : &mut id_allocator::T<TextureUnit>,
+  size               : &cgmath::Vector2<i32>,
+  depth_buffer       : Option<GLuint>,
+) -> Target {
+  let unit = texture_unit_alloc.allocate();
+  let mut framebuffer = 0;
+  let mut texture = 0;
+  unsafe {
+    gl::GenTextures(1, &mut texture);
+    gl::ActiveTexture(unit.gl_id());
+    gl::BindTexture(gl::TEXTURE_2D, texture);
+    gl::TexImage2D(
+      gl::TEXTURE_2D, 0, gl::RGBA16F as GLint, size.x, size.y, 0,
+      gl::RGBA, gl::FLOAT, std::ptr::null(),
+    );
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as GLint);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as GLint);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as GLint);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as GLint);
+
+    gl::GenFramebuffers(1, &mut framebuffer);
+    gl::BindFramebuffer(gl::FRAMEBUFFER, framebuffer);
+    gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::COLOR_ATTACHMENT0, gl::TEXTURE_2D, texture, 0);
+    if let Some(depth_buffer) = depth_buffer {
+      gl::FramebufferRenderbuffer(gl::FRAMEBUFFER, gl::DEPTH_ATTACHMENT, gl::RENDERBUFFER, depth_buffer);
+    }
+    let status = gl::CheckFramebufferStatus(gl::FRAMEBUFFER);
+    if status != gl::FRAMEBUFFER_COMPLETE {
+      warn!("Post-processing framebuffer is incomplete: 0x{:x}", status);
+    }
+    gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+  }
+
+  Target {
+    framebuffer : framebuffer,
+    texture     : texture,
+    unit        : unit,
+  }
+}
+
+#[allow(missing_docs)]
+pub struct T {
+  scene              : Target,
+  depth_buffer       : GLuint,
+  /// Bloom ping-pongs between these while it's blurred. It ends up in the first one.
+  bloom              : [Target; 2],
+  #[allow(missing_docs)]
+  pub size           : cgmath::Vector2<i32>,
+  /// Size of the bloom textures, in pixels.
+  pub bloom_size     : cgmath::Vector2<i32>,
+  /// How much bloom to add; zero if bloom is turned off.
+  pub bloom_strength : f32,
+}
+
+/// Allocate the HDR framebuffers and point the post-processing shaders at them.
+pub fn new(
+  texture_unit_alloc : &mut id_allocator::T<TextureUnit>,
+  shaders            : &mut shaders::post::T,
+  gl                 : &mut GLContext,
+  window_size        : &cgmath::Vector2<i32>,
+  bloom              : bool,
+) -> T {
+  let mut depth_buffer = 0;
+  unsafe {
+    gl::GenRenderbuffers(1, &mut depth_buffer);
+    gl::BindRenderbuffer(gl::RENDERBUFFER, depth_buffer);
+    gl::RenderbufferStorage(gl::RENDERBUFFER, gl::DEPTH_COMPONENT24, window_size.x, window_size.y);
+  }
+  let bloom_size = cgmath::Vector2::new(window_size.x / BLOOM_DIVISOR, window_size.y / BLOOM_DIVISOR);
+  let scene = target(texture_unit_alloc, window_size, Some(depth_buffer));
+  let bloom_targets = [
+    target(texture_unit_alloc, &bloom_size, None),
+    target(texture_unit_alloc, &bloom_size, None),
+  ];
+
+  unsafe {
+    shaders.bloom_extract.use_shader(gl);
+    let scene_uniform = shaders.bloom_extract.get_uniform_location("scene");
+    gl::Uniform1i(scene_uniform, scene.unit.glsl_id as GLint);
+    let threshold_uniform = shaders.bloom_extract.get_uniform_location("threshold");
+    gl::Uniform1f(threshold_uniform, BLOOM_THRESHOLD);
+
+    shaders.tonemap.use_shader(gl);
+    let scene_uniform = shaders.tonemap.get_uniform_location("scene");
+    gl::Uniform1i(scene_uniform, scene.unit.glsl_id as GLint);
+    let bloom_uniform = shaders.tonemap.get_uniform_location("bloom");
+    gl::Uniform1i(bloom_uniform, bloom_targets[0].unit.glsl_id as GLint);
+    let exposure_uniform = shaders.tonemap.get_uniform_location("exposure");
+    gl::Uniform1f(exposure_uniform, 1.0);
+    let tint_uniform = shaders.tonemap.get_uniform_location("underwater_tint");
+    let tint = underwater::TINT;
+    gl::Uniform3f(tint_uniform, tint[0], tint[1], tint[2]);
+  }
+
+  T {
+    scene          : scene,
+    depth_buffer   : depth_buffer,
+    bloom          : bloom_targets,
+    size           : *window_size,
+    bloom_size     : bloom_size,
+    bloom_strength : if bloom { BLOOM_STRENGTH } else { 0.0 },
+  }
+}
+
+impl T {
+  /// The framebuffer the scene should be drawn into.
+  pub fn scene_framebuffer(&self) -> u32 {
+    self.scene.framebuffer
+  }
+
+  /// The framebuffer each bloom stage draws into.
+  pub fn bloom_framebuffer(&self, i: usize) -> u32 {
+    self.bloom[i].framebuffer
+  }
+
+  /// The texture unit of the texture `bloom_framebuffer(i)` draws into.
+  pub fn bloom_unit(&self, i: usize) -> &TextureUnit {
+    &self.bloom[i].unit
+  }
+}
+
+impl Drop for T {
+  fn drop(&mut self) {
+    unsafe {
+      for target in std::iter::once(&self.scene).chain(self.bloom.iter()) {
+        gl::DeleteFramebuffers(1, &target.framebuffer);
+        gl::DeleteTextures(1, &target.texture);
+      }
+      gl::DeleteRenderbuffers(1, &self.depth_buffer);
+    }
+  }
+}
@@ -0,0 +1,113 @@
+//! Planar reflections for the water surface. The scene is drawn mirrored about sea level, at reduced
+//! resolution, into a texture the water shader samples in screen space.
+
+use cgmath;
+use cgmath::{Matrix4, Vector3};
+use gl;
+use gl::types::*;
+use std;
+use yaglw::texture::TextureUnit;
+
+use common::id_allocator;
+use common::voxel;
+
+use view;
+
+/// The reflection is drawn at this fraction of the window resolution in each dimension.
+const RESOLUTION_DIVISOR: i32 = 2;
+
+/// Framebuffer and textures the reflection is drawn into.
+pub struct T {
+  framebuffer   : GLuint,
+  color_texture : GLuint,
+  depth_buffer  : GLuint,
+  /// The texture unit the reflection is bound to.
+  pub unit      : TextureUnit,
+  /// Size of the reflection, in pixels.
+  pub size      : cgmath::Vector2<i32>,
+}
+
+#[allow(missing_docs)]
+pub fn new(texture_unit_alloc: &mut id_allocator::T<TextureUnit>, window_size: &cgmath::Vector2<i32>) -> T {
+  let size = cgmath::Vector2::new(window_size.x / RESOLUTION_DIVISOR, window_size.y / RESOLUTION_DIVISOR);
+  let unit = texture_unit_alloc.allocate();
+  let mut framebuffer = 0;
+  let mut color_texture = 0;
+  let mut depth_buffer = 0;
+  unsafe {
+    gl::GenTextures(1, &mut color_texture);
+    gl::ActiveTexture(unit.gl_id());
+    gl::BindTexture(gl::TEXTURE_2D, color_texture);
+    gl::TexImage2D(
+      gl::TEXTURE_2D, 0, gl::RGBA8 as GLint, size.x, size.y, 0,
+      gl::RGBA, gl::UNSIGNED_BYTE, std::ptr::null(),
+    );
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as GLint);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as GLint);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as GLint);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as GLint);
+
+    gl::GenRenderbuffers(1, &mut depth_buffer);
+    gl::BindRenderbuffer(gl::RENDERBUFFER, depth_buffer);
+    gl::RenderbufferStorage(gl::RENDERBUFFER, gl::DEPTH_COMPONENT24, size.x, size.y);
+
+    gl::GenFramebuffers(1, &mut framebuffer);
+    gl::BindFramebuffer(gl::FRAMEBUFFER, framebuffer);
+    gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::COLOR_ATTACHMENT0, gl::TEXTURE_2D, color_texture, 0);
+    gl::FramebufferRenderbuffer(gl::FRAMEBUFFER, gl::DEPTH_ATTACHMENT, gl::RENDERBUFFER, depth_buffer);
+    let status = gl::CheckFramebufferStatus(gl::FRAMEBUFFER);
+    if status != gl::FRAMEBUFFER_COMPLETE {
+      warn!("Reflection framebuffer is incomplete: 0x{:x}", status);
+    }
+    gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+  }
+
+  T {
+    framebuffer   : framebuffer,
+    color_texture : color_texture,
+    depth_buffer  : depth_buffer,
+    unit          : unit,
+    size          : size,
+  }
+}
+
+/// Reflect world space about the sea-level plane.
+pub fn mirror() -> Matrix4<GLfloat> {
+  let sea_level = voxel::SEA_LEVEL as f32;
+  Matrix4::from_translation(Vector3::new(0.0, sea_level, 0.0))
+    * Matrix4::from_nonuniform_scale(1.0, -1.0, 1.0)
+    * Matrix4::from_translation(Vector3::new(0.0, -sea_level, 0.0))
+}
+
+impl T {
+  #[allow(missing_docs)]
+  pub fn framebuffer(&self) -> u32 {
+    self.framebuffer
+  }
+
+  /// The camera's projection, as seen in the water.
+  pub fn projection_matrix(&self, camera: &view::camera::T) -> Matrix4<GLfloat> {
+    camera.projection_matrix() * mirror()
+  }
+}
+
+impl Drop for T {
+  fn drop(&mut self) {
+    unsafe {
+      gl::DeleteFramebuffers(1, &self.framebuffer);
+      gl::DeleteRenderbuffers(1, &self.depth_buffer);
+      gl::DeleteTextures(1, &self.color_texture);
+    }
+  }
+}
+
+#[test]
+fn mirror_keeps_sea_level_fixed() {
+  use cgmath::Vector4;
+
+  let sea_level = voxel::SEA_LEVEL as f32;
+  let on_surface = mirror() * Vector4::new(3.0, sea_level, -2.0, 1.0);
+  assert_eq!(on_surface, Vector4::new(3.0, sea_level, -2.0, 1.0));
+  let above = mirror() * Vector4::new(0.0, sea_level + 5.0, 0.0, 1.0);
+  assert_eq!(above, Vector4::new(0.0, sea_level - 5.0, 0.0, 1.0));
+}
@@ -2,12 +2,17 @@
 
 use cgmath;
 use gl;
+use gl::types::*;
 use time;
 use yaglw;
 
+use common::voxel;
+
 use view;
+use view::backend;
 use view::camera::{set_camera};
 use view::light::{set_sun, set_ambient_light};
+use view::underwater::set_fog;
 
 fn set_eye_position(shader: &mut yaglw::shader::Shader, camera: &view::camera::T) {
   unsafe {
@@ -17,6 +22,13 @@ fn set_eye_position(shader: &mut yaglw::shader::Shader, camera: &view::camera::T
   }
 }
 
+fn set_terrain_clock(shader: &mut yaglw::shader::Shader, terrain_buffers: &view::terrain_buffers::T) {
+  unsafe {
+    let uniform = shader.get_uniform_location("terrain_clock_ms");
+    gl::Uniform1f(uniform, terrain_buffers.clock_ms());
+  }
+}
+
 fn set_clip(shader: &mut yaglw::shader::Shader, near: f32, far: f32) {
   unsafe {
     let uniform = shader.get_uniform_location("near_clip");
@@ -26,8 +38,28 @@ fn set_clip(shader: &mut yaglw::shader::Shader, near: f32, far: f32) {
   }
 }
 
+/// Where the scene is drawn: into the HDR framebuffer if there's post-processing, otherwise straight to the screen.
+fn scene_target(rndr: &view::T) -> backend::Target {
+  match rndr.post {
+    None => backend::Target::Screen,
+    Some(ref post) => backend::Target::Framebuffer(post.scene_framebuffer()),
+  }
+}
+
+fn fullscreen(label: &'static str) -> backend::Draw {
+  backend::Draw {
+    label     : label,
+    primitive : backend::Primitive::TriangleStrip,
+    first     : 0,
+    count     : 4,
+    instances : 1,
+  }
+}
+
 fn draw_backdrop(
   rndr: &mut view::T,
+  projection_matrix: &cgmath::Matrix4<f32>,
+  size: &cgmath::Vector2<i32>,
 ) {
   rndr.shaders.sky.shader.use_shader(&mut rndr.gl);
 
@@ -37,25 +69,24 @@ fn draw_backdrop(
 
   set_sun(&mut rndr.shaders.sky.shader, &mut rndr.gl, &rndr.sun);
   set_eye_position(&mut rndr.shaders.sky.shader, &rndr.camera);
+  set_fog(&mut rndr.shaders.sky.shader, rndr.underwater);
 
   unsafe {
     let time_ms_uniform = rndr.shaders.sky.shader.get_uniform_location("time_ms");
     gl::Uniform1f(time_ms_uniform, (time::precise_time_ns() / 1_000_000) as f32);
 
     let projection_uniform = rndr.shaders.sky.shader.get_uniform_location("projection_matrix");
-    let projection_matrix = rndr.camera.projection_matrix();
-    let ptr = &projection_matrix as *const _ as *const _;
+    let ptr = projection_matrix as *const _ as *const _;
     gl::UniformMatrix4fv(projection_uniform, 1, 0, ptr);
 
     let window_size_uniform = rndr.shaders.sky.shader.get_uniform_location("window_size");
-    let window_size = cgmath::Vector2::new(rndr.window_size.x as f32, rndr.window_size.y as f32);
+    let window_size = cgmath::Vector2::new(size.x as f32, size.y as f32);
     let ptr = &window_size as *const _ as *const _;
     gl::Uniform2fv(window_size_uniform, 1, ptr);
-
-    gl::DrawArrays(gl::TRIANGLE_STRIP, 0, 4);
-
-    gl::Clear(gl::DEPTH_BUFFER_BIT);
   }
+
+  rndr.backend.draw(&fullscreen("sky"));
+  rndr.backend.clear(false, true);
 }
 
 fn draw_grass_billboards(
@@ -71,29 +102,246 @@ fn draw_grass_billboards(
   set_clip(&mut rndr.shaders.grass_billboard.shader, rndr.near_clip, rndr.far_clip);
   set_eye_position(&mut rndr.shaders.grass_billboard.shader, &rndr.camera);
   set_sun(&mut rndr.shaders.grass_billboard.shader, &mut rndr.gl, &rndr.sun);
+  set_fog(&mut rndr.shaders.grass_billboard.shader, rndr.underwater);
   let alpha_threshold_uniform =
     rndr.shaders.grass_billboard.shader.get_uniform_location("alpha_threshold");
   unsafe {
-    gl::Disable(gl::CULL_FACE);
     gl::Uniform1f(alpha_threshold_uniform, 0.5);
+    let wind_uniform = rndr.shaders.grass_billboard.shader.get_uniform_location("wind");
+    gl::Uniform2f(wind_uniform, view::grass_buffers::WIND[0], view::grass_buffers::WIND[1]);
+    let fade_distance_uniform = rndr.shaders.grass_billboard.shader.get_uniform_location("fade_distance");
+    gl::Uniform1f(fade_distance_uniform, rndr.decoration_distance);
     gl::ActiveTexture(rndr.misc_texture_unit.gl_id());
     gl::BindTexture(gl::TEXTURE_2D, rndr.grass_texture.handle.gl_id);
   }
-  rndr.grass_buffers.draw(&mut rndr.gl);
+  rndr.backend.set_culling(false);
+  rndr.grass_buffers.draw(&mut *rndr.backend);
 }
 
-#[allow(missing_docs)]
-pub fn render(
+/// Render terrain depth from the sun into each shadow cascade.
+fn draw_shadows(
+  rndr: &mut view::T,
+) {
+  let sun_direction = rndr.sun.direction();
+  rndr.shadows.update(&rndr.camera, &rndr.window_size, rndr.near_clip, &sun_direction);
+
+  rndr.shaders.shadow.shader.use_shader(&mut rndr.gl);
+  set_terrain_clock(&mut rndr.shaders.shadow.shader, &rndr.terrain_buffers);
+  let light_matrix_uniform = rndr.shaders.shadow.shader.get_uniform_location("light_matrix");
+  // Slope-scaled bias keeps surfaces from shadowing themselves.
+  rndr.backend.set_polygon_offset(Some((2.0, 4.0)));
+  // Thin terrain features should still cast shadows when seen edge-on by the sun.
+  rndr.backend.set_culling(false);
+  let map_size = cgmath::Vector2::new(view::shadows::MAP_SIZE, view::shadows::MAP_SIZE);
+  for i in 0 .. view::shadows::CASCADES {
+    rndr.backend.bind_target(backend::Target::Framebuffer(rndr.shadows.framebuffer(i)), &map_size);
+    rndr.backend.clear(false, true);
+    unsafe {
+      let ptr = &rndr.shadows.matrices[i] as *const _ as *const _;
+      gl::UniformMatrix4fv(light_matrix_uniform, 1, 0, ptr);
+    }
+    rndr.terrain_buffers.draw(&mut *rndr.backend);
+  }
+  rndr.backend.set_polygon_offset(None);
+  let target = scene_target(rndr);
+  rndr.backend.bind_target(target, &rndr.window_size);
+}
+
+/// Draw the sky and terrain mirrored about sea level into the reflection texture.
+/// Only what's above the water is drawn, so things underneath don't show up in the reflection.
+fn draw_reflection(
+  rndr: &mut view::T,
+) {
+  let (framebuffer, size, projection_matrix) =
+    match rndr.reflection {
+      None => return,
+      Some(ref reflection) => (reflection.framebuffer(), reflection.size, reflection.projection_matrix(&rndr.camera)),
+    };
+
+  rndr.backend.bind_target(backend::Target::Framebuffer(framebuffer), &size);
+  rndr.backend.clear(true, true);
+  draw_backdrop(rndr, &projection_matrix, &size);
+
+  rndr.shaders.terrain_shader.shader.use_shader(&mut rndr.gl);
+  set_ambient_light(&mut rndr.shaders.terrain_shader.shader, &mut rndr.gl, &rndr.sun);
+  set_clip(&mut rndr.shaders.terrain_shader.shader, rndr.near_clip, rndr.far_clip);
+  set_eye_position(&mut rndr.shaders.terrain_shader.shader, &rndr.camera);
+  set_sun(&mut rndr.shaders.terrain_shader.shader, &mut rndr.gl, &rndr.sun);
+  set_fog(&mut rndr.shaders.terrain_shader.shader, rndr.underwater);
+  set_terrain_clock(&mut rndr.shaders.terrain_shader.shader, &rndr.terrain_buffers);
+  rndr.shadows.set_uniforms(&mut rndr.shaders.terrain_shader.shader, &mut rndr.gl, &rndr.camera);
+  unsafe {
+    let projection_uniform = rndr.shaders.terrain_shader.shader.get_uniform_location("projection_matrix");
+    let ptr = &projection_matrix as *const _ as *const _;
+    gl::UniformMatrix4fv(projection_uniform, 1, 0, ptr);
+
+    let clip_plane_uniform = rndr.shaders.terrain_shader.shader.get_uniform_location("clip_plane");
+    gl::Uniform4f(clip_plane_uniform, 0.0, 1.0, 0.0, -(voxel::SEA_LEVEL as f32));
+    gl::Enable(gl::CLIP_DISTANCE0);
+  }
+  // Mirroring flips the winding of every triangle.
+  rndr.backend.set_culling(false);
+  rndr.terrain_buffers.draw(&mut *rndr.backend);
+  unsafe {
+    gl::Disable(gl::CLIP_DISTANCE0);
+  }
+
+  let target = scene_target(rndr);
+  rndr.backend.bind_target(target, &rndr.window_size);
+}
+
+/// Draw the far horizon past the edge of the polygonal terrain.
+fn draw_horizon(
+  rndr: &mut view::T,
+) {
+  rndr.shaders.horizon.shader.use_shader(&mut rndr.gl);
+  set_ambient_light(&mut rndr.shaders.horizon.shader, &mut rndr.gl, &rndr.sun);
+  set_camera(&mut rndr.shaders.horizon.shader, &mut rndr.gl, &rndr.camera);
+  set_clip(&mut rndr.shaders.horizon.shader, rndr.near_clip, rndr.far_clip);
+  set_eye_position(&mut rndr.shaders.horizon.shader, &rndr.camera);
+  set_sun(&mut rndr.shaders.horizon.shader, &mut rndr.gl, &rndr.sun);
+  set_fog(&mut rndr.shaders.horizon.shader, rndr.underwater);
+  unsafe {
+    let horizon_start_uniform = rndr.shaders.horizon.shader.get_uniform_location("horizon_start");
+    gl::Uniform1f(horizon_start_uniform, rndr.horizon_start);
+  }
+  rndr.horizon_buffers.draw(&mut *rndr.backend);
+}
+
+/// Translucent pass, after everything opaque. Every water triangle lies in the sea-level plane,
+/// so they never overlap on screen and don't need sorting; they just mustn't write depth.
+fn draw_water(
   rndr: &mut view::T,
 ) {
-  rndr.gl.clear_buffer();
+  rndr.shaders.water.shader.use_shader(&mut rndr.gl);
+  unsafe {
+    let time_ms_uniform = rndr.shaders.water.shader.get_uniform_location("time_ms");
+    gl::Uniform1f(time_ms_uniform, (time::precise_time_ns() / 1_000_000) as f32);
+  }
+  set_ambient_light(&mut rndr.shaders.water.shader, &mut rndr.gl, &rndr.sun);
+  set_camera(&mut rndr.shaders.water.shader, &mut rndr.gl, &rndr.camera);
+  set_clip(&mut rndr.shaders.water.shader, rndr.near_clip, rndr.far_clip);
+  set_eye_position(&mut rndr.shaders.water.shader, &rndr.camera);
+  set_sun(&mut rndr.shaders.water.shader, &mut rndr.gl, &rndr.sun);
+  set_fog(&mut rndr.shaders.water.shader, rndr.underwater);
+  unsafe {
+    let reflections_uniform = rndr.shaders.water.shader.get_uniform_location("reflections");
+    gl::Uniform1i(reflections_uniform, rndr.reflection.is_some() as GLint);
+    let window_size_uniform = rndr.shaders.water.shader.get_uniform_location("window_size");
+    gl::Uniform2f(window_size_uniform, rndr.window_size.x as f32, rndr.window_size.y as f32);
+  }
+  // Water can be seen from underneath.
+  rndr.backend.set_culling(false);
+  rndr.backend.set_depth_write(false);
+  rndr.water_buffers.draw(&mut rndr.gl, &mut *rndr.backend);
+  rndr.backend.set_depth_write(true);
+}
 
-  draw_backdrop(rndr);
+/// Bloom the brightest parts of the HDR scene, and tone map it all onto the screen.
+fn draw_post(
+  rndr: &mut view::T,
+) {
+  let (bloom_size, bloom_strength, framebuffers, units) =
+    match rndr.post {
+      None => return,
+      Some(ref post) => (
+        post.bloom_size,
+        post.bloom_strength,
+        [post.bloom_framebuffer(0), post.bloom_framebuffer(1)],
+        [post.bloom_unit(0).glsl_id as GLint, post.bloom_unit(1).glsl_id as GLint],
+      ),
+    };
 
+  rndr.backend.set_culling(false);
+  rndr.backend.set_depth_write(false);
   unsafe {
-    gl::Enable(gl::CULL_FACE);
+    gl::Disable(gl::DEPTH_TEST);
+    gl::Disable(gl::BLEND);
+    gl::BindVertexArray(rndr.empty_gl_array.gl_id);
   }
 
+  if bloom_strength > 0.0 {
+    rndr.backend.bind_target(backend::Target::Framebuffer(framebuffers[0]), &bloom_size);
+    rndr.shaders.post.bloom_extract.use_shader(&mut rndr.gl);
+    rndr.backend.draw(&fullscreen("bloom_extract"));
+
+    // Blur horizontally into the second bloom texture, then vertically back into the first.
+    rndr.shaders.post.blur.use_shader(&mut rndr.gl);
+    let source_uniform = rndr.shaders.post.blur.get_uniform_location("source");
+    let step_uniform = rndr.shaders.post.blur.get_uniform_location("step");
+    let steps = [(1.0 / bloom_size.x as f32, 0.0), (0.0, 1.0 / bloom_size.y as f32)];
+    for (pass, &(dx, dy)) in steps.iter().enumerate() {
+      let (from, to) = (pass, 1 - pass);
+      rndr.backend.bind_target(backend::Target::Framebuffer(framebuffers[to]), &bloom_size);
+      unsafe {
+        gl::Uniform1i(source_uniform, units[from]);
+        gl::Uniform2f(step_uniform, dx, dy);
+      }
+      rndr.backend.draw(&fullscreen("blur"));
+    }
+  }
+
+  rndr.backend.bind_target(backend::Target::Screen, &rndr.window_size);
+  rndr.shaders.post.tonemap.use_shader(&mut rndr.gl);
+  unsafe {
+    let bloom_strength_uniform = rndr.shaders.post.tonemap.get_uniform_location("bloom_strength");
+    gl::Uniform1f(bloom_strength_uniform, bloom_strength);
+    let underwater_uniform = rndr.shaders.post.tonemap.get_uniform_location("underwater");
+    gl::Uniform1i(underwater_uniform, rndr.underwater as GLint);
+    let time_ms_uniform = rndr.shaders.post.tonemap.get_uniform_location("time_ms");
+    gl::Uniform1f(time_ms_uniform, (time::precise_time_ns() / 1_000_000) as f32);
+    let saturation_uniform = rndr.shaders.post.tonemap.get_uniform_location("saturation");
+    gl::Uniform1f(saturation_uniform, rndr.vitals.saturation());
+  }
+  rndr.backend.draw(&fullscreen("tonemap"));
+
+  unsafe {
+    gl::Enable(gl::DEPTH_TEST);
+    gl::Enable(gl::BLEND);
+  }
+  rndr.backend.set_depth_write(true);
+  // The HUD is drawn on top, and the screen's depth buffer hasn't been touched this frame.
+  rndr.backend.clear(false, true);
+}
+
+/// Redden the edges of the screen when the player's hurt. This is drawn straight onto the screen,
+/// so it shows with or without post-processing.
+fn draw_vignette(
+  rndr: &mut view::T,
+) {
+  let strength = rndr.vitals.vignette(time::precise_time_ns());
+  if strength <= 0.0 {
+    return
+  }
+
+  rndr.shaders.post.vignette.use_shader(&mut rndr.gl);
+  unsafe {
+    let strength_uniform = rndr.shaders.post.vignette.get_uniform_location("strength");
+    gl::Uniform1f(strength_uniform, strength);
+    gl::Disable(gl::DEPTH_TEST);
+    gl::BindVertexArray(rndr.empty_gl_array.gl_id);
+  }
+  rndr.backend.draw(&fullscreen("vignette"));
+  unsafe {
+    gl::Enable(gl::DEPTH_TEST);
+  }
+}
+
+#[allow(missing_docs)]
+pub fn render(
+  rndr: &mut view::T,
+) {
+  draw_shadows(rndr);
+  draw_reflection(rndr);
+
+  rndr.backend.clear(true, true);
+
+  let projection_matrix = rndr.camera.projection_matrix();
+  let window_size = rndr.window_size;
+  draw_backdrop(rndr, &projection_matrix, &window_size);
+
+  rndr.backend.set_culling(true);
+
   // draw the world
   rndr.shaders.terrain_shader.shader.use_shader(&mut rndr.gl);
   set_ambient_light(&mut rndr.shaders.terrain_shader.shader, &mut rndr.gl, &rndr.sun);
@@ -101,19 +349,33 @@ pub fn render(
   set_clip(&mut rndr.shaders.terrain_shader.shader, rndr.near_clip, rndr.far_clip);
   set_eye_position(&mut rndr.shaders.terrain_shader.shader, &rndr.camera);
   set_sun(&mut rndr.shaders.terrain_shader.shader, &mut rndr.gl, &rndr.sun);
-  rndr.terrain_buffers.draw(&mut rndr.gl);
+  set_fog(&mut rndr.shaders.terrain_shader.shader, rndr.underwater);
+  set_terrain_clock(&mut rndr.shaders.terrain_shader.shader, &rndr.terrain_buffers);
+  rndr.shadows.set_uniforms(&mut rndr.shaders.terrain_shader.shader, &mut rndr.gl, &rndr.camera);
+  rndr.terrain_buffers.draw(&mut *rndr.backend);
+
+  draw_horizon(rndr);
 
   rndr.shaders.mob_shader.shader.use_shader(&mut rndr.gl);
   set_camera(&mut rndr.shaders.mob_shader.shader, &mut rndr.gl, &rndr.camera);
   set_clip(&mut rndr.shaders.mob_shader.shader, rndr.near_clip, rndr.far_clip);
-  rndr.mob_buffers.draw(&mut rndr.gl);
-  rndr.player_buffers.draw(&mut rndr.gl);
+  rndr.mob_buffers.draw(&mut rndr.gl, &mut *rndr.backend);
+  rndr.item_buffers.draw(&mut rndr.gl, &mut *rndr.backend);
+  rndr.projectile_buffers.draw(&mut rndr.gl, &mut *rndr.backend);
+  rndr.tracer_buffers.draw(&mut rndr.gl, &mut *rndr.backend);
+  rndr.player_buffers.draw(&mut rndr.gl, &mut *rndr.backend);
 
   draw_grass_billboards(rndr);
 
+  draw_water(rndr);
+
+  draw_post(rndr);
+  draw_vignette(rndr);
+
   if rndr.show_hud {
     rndr.shaders.hud_color_shader.shader.use_shader(&mut rndr.gl);
     rndr.hud_triangles.bind(&mut rndr.gl);
-    rndr.hud_triangles.draw(&mut rndr.gl);
+    rndr.backend.draw(&backend::Draw::triangles("hud", rndr.hud_vertex_count as u32));
   }
+  rndr.terrain_buffers.fence_uploads();
 }
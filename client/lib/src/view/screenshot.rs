@@ -0,0 +1,240 @@
+//! Screenshot regression tests. Once a recorded session has finished playing, the camera is moved to
+//! each of a list of fixed poses, and what's rendered is compared with a golden image, so changes to
+//! shaders or meshing that quietly break how things look get noticed.
+//!
+//! A test lives in a directory holding `poses.txt`, which lists a pose per line as `x y z yaw pitch`
+//! (angles in degrees; blank lines and lines starting with `#` are skipped), and the golden image for
+//! the pose on line `i` (counting from 0, poses only) as `i.png`. Poses without a golden image have
+//! one written, so new poses are added by running the test once.
+
+use cgmath::{Point3, Vector2};
+use gl;
+use gl::types::*;
+use image;
+use std;
+use std::path::{Path, PathBuf};
+
+use common::data_dir;
+
+use view;
+
+/// The poses in a test directory.
+pub const POSES_FILE: &'static str = "poses.txt";
+
+/// Color channels can differ by this much before a pixel counts as different, since drivers round
+/// differently.
+const CHANNEL_TOLERANCE: u8 = 8;
+
+/// A screenshot matches if no more than this fraction of its pixels differ, to allow for things that
+/// move on their own, like grass and water.
+const MAX_DIFFERING: f32 = 0.01;
+
+/// How many frames in a row nothing has to change for, once the recording has finished, before
+/// screenshots are taken.
+const SETTLE_FRAMES: u32 = 60;
+
+#[allow(missing_docs)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Pose {
+  pub position : Point3<f32>,
+  /// Turn to the left, in radians.
+  pub yaw      : f32,
+  /// Tilt upward, in radians.
+  pub pitch    : f32,
+}
+
+/// Parse the contents of a `POSES_FILE`.
+pub fn parse_poses(text: &str) -> Result<Vec<Pose>, String> {
+  let mut poses = Vec::new();
+  for (i, line) in text.lines().enumerate() {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+      continue
+    }
+    let numbers: Result<Vec<f32>, _> = line.split_whitespace().map(|word| word.parse()).collect();
+    match numbers {
+      Ok(ref numbers) if numbers.len() == 5 => {
+        poses.push(Pose {
+          position : Point3::new(numbers[0], numbers[1], numbers[2]),
+          yaw      : numbers[3].to_radians(),
+          pitch    : numbers[4].to_radians(),
+        });
+      },
+      _ => return Err(format!("line {}: expected `x y z yaw pitch`, got {:?}", i + 1, line)),
+    }
+  }
+  Ok(poses)
+}
+
+/// An RGBA image, top row first.
+#[allow(missing_docs)]
+pub struct Image {
+  pub width  : u32,
+  pub height : u32,
+  pub pixels : Vec<u8>,
+}
+
+/// How many pixels differ by more than `tolerance` in any channel, or None if the images aren't the
+/// same size.
+pub fn differing_pixels(expected: &Image, actual: &Image, tolerance: u8) -> Option<usize> {
+  if expected.width != actual.width || expected.height != actual.height {
+    return None
+  }
+  let differing =
+    expected.pixels.chunks(4).zip(actual.pixels.chunks(4))
+    .filter(|&(e, a)| e.iter().zip(a.iter()).any(|(&e, &a)| (e as i32 - a as i32).abs() > tolerance as i32))
+    .count();
+  Some(differing)
+}
+
+/// Read back what's been drawn to the screen.
+pub fn capture(size: &Vector2<i32>) -> Image {
+  let (width, height) = (size.x as usize, size.y as usize);
+  let mut pixels = vec![0; width * height * 4];
+  unsafe {
+    gl::PixelStorei(gl::PACK_ALIGNMENT, 1);
+    gl::ReadPixels(0, 0, size.x, size.y, gl::RGBA, gl::UNSIGNED_BYTE, pixels.as_mut_ptr() as *mut GLvoid);
+  }
+  // OpenGL reads from the bottom row up.
+  let mut flipped = Vec::with_capacity(pixels.len());
+  for row in pixels.chunks(width * 4).rev() {
+    flipped.extend_from_slice(row);
+  }
+  Image {
+    width  : width as u32,
+    height : height as u32,
+    pixels : flipped,
+  }
+}
+
+fn load_image(path: &Path) -> Result<Image, String> {
+  let image = try!(image::open(path).map_err(|err| format!("{:?}", err))).to_rgba();
+  Ok(Image {
+    width  : image.width(),
+    height : image.height(),
+    pixels : image.into_raw(),
+  })
+}
+
+fn save_image(path: &Path, image: &Image) -> Result<(), String> {
+  image::save_buffer(path, &image.pixels, image.width, image.height, image::ColorType::RGBA(8))
+    .map_err(|err| format!("{:?}", err))
+}
+
+/// A screenshot test in progress.
+pub struct T {
+  dir             : PathBuf,
+  poses           : Vec<Pose>,
+  /// The pose being rendered this frame, if any.
+  posed           : Option<usize>,
+  /// The next pose to render.
+  next            : usize,
+  settled_frames  : u32,
+  /// Whether the recording has finished playing.
+  pub replay_done : bool,
+  /// What went wrong, for each screenshot that didn't match.
+  pub failures    : Vec<String>,
+}
+
+/// Load the test in a directory.
+pub fn load(dir: &Path) -> Result<T, String> {
+  let path = dir.join(POSES_FILE);
+  let mut text = String::new();
+  {
+    use std::io::Read;
+    let mut file = try!(std::fs::File::open(&path).map_err(|err| format!("{}: {}", path.display(), err)));
+    try!(file.read_to_string(&mut text).map_err(|err| format!("{}: {}", path.display(), err)));
+  }
+  let poses = try!(parse_poses(&text).map_err(|err| format!("{}: {}", path.display(), err)));
+  Ok(T {
+    dir            : dir.to_path_buf(),
+    poses          : poses,
+    posed          : None,
+    next           : 0,
+    settled_frames : 0,
+    replay_done    : false,
+    failures       : Vec::new(),
+  })
+}
+
+impl T {
+  /// Call before rendering each frame, with whether anything changed since the last one. Once the
+  /// world has settled, this moves the camera to the next pose.
+  pub fn before_render(&mut self, view: &mut view::T, changed: bool) {
+    if !self.replay_done || changed {
+      self.settled_frames = 0;
+      return
+    }
+    if self.settled_frames < SETTLE_FRAMES {
+      self.settled_frames += 1;
+      return
+    }
+    if let Some(&pose) = self.poses.get(self.next) {
+      view.pose_camera(pose.position, pose.yaw, pose.pitch);
+      self.posed = Some(self.next);
+      self.next += 1;
+    }
+  }
+
+  /// Call after rendering each frame, before the buffers are swapped. Returns true once every pose
+  /// has been checked.
+  pub fn after_render(&mut self, window_size: &Vector2<i32>) -> bool {
+    if let Some(i) = self.posed.take() {
+      let actual = capture(window_size);
+      if let Err(err) = self.check(i, &actual) {
+        warn!("Screenshot {} doesn't match: {}", i, err);
+        let path = data_dir::dir(data_dir::Kind::Screenshots).join(format!("regression-{}.png", i));
+        match save_image(&path, &actual) {
+          Ok(()) => info!("Saved what was rendered instead to {}", path.display()),
+          Err(err) => warn!("Couldn't save {}: {}", path.display(), err),
+        }
+        self.failures.push(format!("{}: {}", i, err));
+      }
+    }
+    self.next >= self.poses.len() && self.posed.is_none()
+  }
+
+  fn check(&self, i: usize, actual: &Image) -> Result<(), String> {
+    let path = self.dir.join(format!("{}.png", i));
+    if !path.exists() {
+      info!("No golden image for pose {}; saving this one as {}", i, path.display());
+      return save_image(&path, actual)
+    }
+    let expected = try!(load_image(&path));
+    match differing_pixels(&expected, actual, CHANNEL_TOLERANCE) {
+      None =>
+        Err(format!("{}x{}, but the golden image is {}x{}", actual.width, actual.height, expected.width, expected.height)),
+      Some(differing) => {
+        let fraction = differing as f32 / (actual.width * actual.height) as f32;
+        if fraction > MAX_DIFFERING {
+          Err(format!("{:.1}% of pixels differ", fraction * 100.0))
+        } else {
+          Ok(())
+        }
+      },
+    }
+  }
+}
+
+#[test]
+fn poses_are_parsed_in_degrees() {
+  let poses = parse_poses("# a comment\n\n0 64 0 90 -45\n  1.5 2 3 0 0  \n").unwrap();
+  assert_eq!(poses.len(), 2);
+  assert_eq!(poses[0].position, Point3::new(0.0, 64.0, 0.0));
+  assert!((poses[0].yaw - std::f32::consts::FRAC_PI_2).abs() < 0.0001);
+  assert!((poses[0].pitch + std::f32::consts::FRAC_PI_4).abs() < 0.0001);
+  assert_eq!(poses[1].position, Point3::new(1.5, 2.0, 3.0));
+
+  assert!(parse_poses("1 2 3 4").is_err());
+  assert!(parse_poses("1 2 3 4 five").is_err());
+}
+
+#[test]
+fn small_differences_are_tolerated() {
+  let image = |pixels: Vec<u8>| Image { width: 2, height: 1, pixels: pixels };
+  let expected = image(vec![10, 20, 30, 255, 0, 0, 0, 255]);
+  assert_eq!(differing_pixels(&expected, &image(vec![14, 16, 30, 255, 0, 0, 0, 255]), 8), Some(0));
+  assert_eq!(differing_pixels(&expected, &image(vec![10, 20, 30, 255, 0, 9, 0, 255]), 8), Some(1));
+  let wrong_size = Image { width: 1, height: 2, pixels: expected.pixels.clone() };
+  assert_eq!(differing_pixels(&expected, &wrong_size, 8), None);
+}
@@ -3,6 +3,7 @@
 use yaglw::gl_context::GLContext;
 use yaglw::shader::Shader;
 
+use resource_pack;
 use view::shaders;
 
 #[allow(missing_docs)]
@@ -12,8 +13,8 @@ pub struct T<'a> {
 }
 
 #[allow(missing_docs)]
-pub fn new<'a, 'b>(gl: &'b GLContext) -> T<'a> where 'a: 'b {
+pub fn new<'a, 'b>(gl: &'b GLContext, resource_packs: &resource_pack::T) -> T<'a> where 'a: 'b {
   T {
-    shader: shaders::shader_from_prefix(gl, "grass_billboard")
+    shader: shaders::shader_from_prefix(gl, resource_packs, "grass_billboard")
   }
 }
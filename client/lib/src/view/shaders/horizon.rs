@@ -0,0 +1,20 @@
+//! Draw the far horizon: colored terrain columns, lit and fogged, beyond the polygonal terrain.
+
+use yaglw::gl_context::GLContext;
+use yaglw::shader::Shader;
+
+use resource_pack;
+use view::shaders;
+
+#[allow(missing_docs)]
+pub struct T<'a> {
+  #[allow(missing_docs)]
+  pub shader: Shader<'a>,
+}
+
+#[allow(missing_docs)]
+pub fn new<'a, 'b>(gl: &'b GLContext, resource_packs: &resource_pack::T) -> T<'a> where 'a: 'b {
+  T {
+    shader: shaders::shader_from_prefix(gl, resource_packs, "horizon")
+  }
+}
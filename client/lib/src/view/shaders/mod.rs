@@ -2,29 +2,48 @@
 
 pub mod color;
 pub mod grass_billboard;
+pub mod horizon;
+pub mod post;
+pub mod shadow;
 pub mod sky;
 pub mod terrain;
 pub mod texture;
+pub mod water;
 
 use cgmath;
 use cgmath::{Vector2};
 use gl;
+use gl::types::*;
 use std;
 use yaglw::gl_context::GLContext;
 use yaglw;
+use yaglw::shader::Shader;
 
+use resource_pack;
 use view::camera;
 
 /// Load a shader from a filename prefix.
 /// e.g. shader_from_prefix("foo") will load a vertex shader from shaders/foo.vs.glsl and fragment shader
 /// from shaders/foo.fs.glsl.
-pub fn shader_from_prefix<'a, 'b:'a>(gl: &'a GLContext, prefix: &'static str) -> yaglw::shader::Shader<'b> {
+/// Shaders and their includes are overridden by any resource pack that provides them.
+pub fn shader_from_prefix<'a, 'b:'a>(
+  gl: &'a GLContext,
+  resource_packs: &resource_pack::T,
+  prefix: &'static str,
+) -> yaglw::shader::Shader<'b> {
+  let search_path = resource_packs.search_path("shaders");
   let read_preprocessed_shader =
-    |name| {
+    |name: String| {
+      let path = resource_pack::absolute(&resource_packs.resolve(&format!("shaders/{}", name)));
+      // m4 looks for includes in its working directory first, then in each -I directory in order.
+      let mut m4 = std::process::Command::new("m4");
+      m4.current_dir(&search_path[0]);
+      for dir in &search_path[1..] {
+        m4.arg("-I").arg(resource_pack::absolute(dir));
+      }
       String::from_utf8(
-        std::process::Command::new("m4")
-        .arg(name)
-        .current_dir(std::path::Path::new("shaders/"))
+        m4
+        .arg(path)
         .output()
         .unwrap()
         .stdout
@@ -58,16 +77,32 @@ pub struct T<'a> {
   pub hud_color_shader: self::color::T<'a>,
   #[allow(missing_docs)]
   pub sky: self::sky::T<'a>,
+  #[allow(missing_docs)]
+  pub water: self::water::T<'a>,
+  #[allow(missing_docs)]
+  pub horizon: self::horizon::T<'a>,
+  #[allow(missing_docs)]
+  pub shadow: self::shadow::T<'a>,
+  #[allow(missing_docs)]
+  pub post: self::post::T<'a>,
 }
 
 #[allow(missing_docs)]
-pub fn new<'a, 'b>(gl: &'b mut GLContext, window_size: Vector2<i32>) -> T<'a> where 'a: 'b {
-  let terrain_shader       = self::terrain::new(gl);
-  let mob_shader           = self::color::new(gl);
-  let mut hud_color_shader = self::color::new(gl);
-  let texture_shader       = self::texture::new(gl);
-  let grass_billboard      = self::grass_billboard::new(gl);
-  let sky                  = self::sky::new(gl);
+pub fn new<'a, 'b>(
+  gl: &'b mut GLContext,
+  resource_packs: &resource_pack::T,
+  window_size: Vector2<i32>,
+) -> T<'a> where 'a: 'b {
+  let terrain_shader       = self::terrain::new(gl, resource_packs);
+  let mob_shader           = self::color::new(gl, resource_packs);
+  let mut hud_color_shader = self::color::new(gl, resource_packs);
+  let texture_shader       = self::texture::new(gl, resource_packs);
+  let grass_billboard      = self::grass_billboard::new(gl, resource_packs);
+  let sky                  = self::sky::new(gl, resource_packs);
+  let water                = self::water::new(gl, resource_packs);
+  let horizon              = self::horizon::new(gl, resource_packs);
+  let shadow               = self::shadow::new(gl, resource_packs);
+  let post                 = self::post::new(gl, resource_packs);
 
   let hud_camera = {
     let mut c = camera::unit();
@@ -95,5 +130,151 @@ pub fn new<'a, 'b>(gl: &'b mut GLContext, window_size: Vector2<i32>) -> T<'a> wh
     grass_billboard: grass_billboard,
     hud_color_shader: hud_color_shader,
     sky: sky,
+    water: water,
+    horizon: horizon,
+    shadow: shadow,
+    post: post,
+  }
+}
+
+impl<'a> T<'a> {
+  /// Reload every shader from the active resource packs. Any that won't link keep the old version.
+  pub fn reload<'b>(&mut self, gl: &'b mut GLContext, resource_packs: &resource_pack::T) where 'a: 'b {
+    let fresh = self::color::new(gl, resource_packs).shader;
+    swap_in(gl, &mut self.mob_shader.shader, fresh);
+    let fresh = self::terrain::new(gl, resource_packs).shader;
+    swap_in(gl, &mut self.terrain_shader.shader, fresh);
+    let fresh = self::texture::new(gl, resource_packs).shader;
+    swap_in(gl, &mut self.texture_shader.shader, fresh);
+    let fresh = self::grass_billboard::new(gl, resource_packs).shader;
+    swap_in(gl, &mut self.grass_billboard.shader, fresh);
+    let fresh = self::color::new(gl, resource_packs).shader;
+    swap_in(gl, &mut self.hud_color_shader.shader, fresh);
+    let fresh = self::sky::new(gl, resource_packs).shader;
+    swap_in(gl, &mut self.sky.shader, fresh);
+    let fresh = self::water::new(gl, resource_packs).shader;
+    swap_in(gl, &mut self.water.shader, fresh);
+    let fresh = self::horizon::new(gl, resource_packs).shader;
+    swap_in(gl, &mut self.horizon.shader, fresh);
+    let fresh = self::shadow::new(gl, resource_packs).shader;
+    swap_in(gl, &mut self.shadow.shader, fresh);
+
+    let post = self::post::new(gl, resource_packs);
+    swap_in(gl, &mut self.post.bloom_extract, post.bloom_extract);
+    swap_in(gl, &mut self.post.blur, post.blur);
+    swap_in(gl, &mut self.post.tonemap, post.tonemap);
+    swap_in(gl, &mut self.post.vignette, post.vignette);
+
+    match gl.get_error() {
+      gl::NO_ERROR => {},
+      err => warn!("OpenGL error 0x{:x} reloading shaders", err),
+    }
+  }
+}
+
+fn swap_in<'a>(gl: &mut GLContext, shader: &mut Shader<'a>, mut fresh: Shader<'a>) {
+  if carry_over(gl, shader, &mut fresh) {
+    *shader = fresh;
+  } else {
+    warn!("Keeping the old version of a shader that won't link");
+  }
+}
+
+/// Make a freshly-loaded shader a drop-in replacement for `old`. Vertex arrays were set up against
+/// `old`'s attribute locations, and most uniforms (texture units, the atlas layout, the HUD camera)
+/// are only set once at startup, so relink `fresh` with the same attribute locations and copy the
+/// uniforms across. Returns false if `fresh` won't link that way.
+fn carry_over(gl: &mut GLContext, old: &Shader, fresh: &mut Shader) -> bool {
+  let old_id = old.handle.gl_id;
+  let fresh_id = fresh.handle.gl_id;
+  unsafe {
+    let mut count = 0;
+    gl::GetProgramiv(old_id, gl::ACTIVE_ATTRIBUTES, &mut count);
+    for index in 0 .. count as GLuint {
+      let (name, _, _) = active(old_id, index, gl::GetActiveAttrib);
+      // Built-in inputs like gl_VertexID have no location, and can't be bound.
+      let location = gl::GetAttribLocation(old_id, name.as_ptr());
+      if location >= 0 {
+        gl::BindAttribLocation(fresh_id, location as GLuint, name.as_ptr());
+      }
+    }
+    gl::LinkProgram(fresh_id);
+    let mut linked = 0;
+    gl::GetProgramiv(fresh_id, gl::LINK_STATUS, &mut linked);
+    if linked == gl::FALSE as GLint {
+      return false
+    }
+  }
+
+  fresh.use_shader(gl);
+  unsafe {
+    let mut count = 0;
+    gl::GetProgramiv(old_id, gl::ACTIVE_UNIFORMS, &mut count);
+    for index in 0 .. count as GLuint {
+      let (name, size, ty) = active(old_id, index, gl::GetActiveUniform);
+      let name = name.into_string().unwrap();
+      // Arrays are listed once, as e.g. "foo[0]"; each element has its own location.
+      let base = if name.ends_with("[0]") { &name[.. name.len() - 3] } else { &name[..] };
+      for i in 0 .. size {
+        let element = if size > 1 { format!("{}[{}]", base, i) } else { name.clone() };
+        let element = std::ffi::CString::new(element).unwrap();
+        let from = gl::GetUniformLocation(old_id, element.as_ptr());
+        let to = gl::GetUniformLocation(fresh_id, element.as_ptr());
+        if from >= 0 && to >= 0 {
+          copy_uniform(old_id, from, to, ty);
+        }
+      }
+    }
+  }
+
+  true
+}
+
+/// The name, array size, and type of a program's `index`th active attribute or uniform.
+unsafe fn active(
+  program    : GLuint,
+  index      : GLuint,
+  get_active : unsafe fn(GLuint, GLuint, GLsizei, *mut GLsizei, *mut GLint, *mut GLenum, *mut GLchar),
+) -> (std::ffi::CString, GLint, GLenum) {
+  let mut name = [0 as GLchar; 256];
+  let mut length = 0;
+  let mut size = 0;
+  let mut ty = 0;
+  get_active(program, index, name.len() as GLsizei, &mut length, &mut size, &mut ty, name.as_mut_ptr());
+  let name: Vec<u8> = name[.. length as usize].iter().map(|&c| c as u8).collect();
+  (std::ffi::CString::new(name).unwrap(), size, ty)
+}
+
+/// Copy the value of a uniform of type `ty` from `old` into the program in use.
+unsafe fn copy_uniform(old: GLuint, from: GLint, to: GLint, ty: GLenum) {
+  let mut floats = [0.0 as GLfloat; 16];
+  let mut ints = [0 as GLint; 4];
+  let mut uints = [0 as GLuint; 4];
+  let f = floats.as_mut_ptr();
+  let i = ints.as_mut_ptr();
+  let u = uints.as_mut_ptr();
+  match ty {
+    gl::FLOAT             => { gl::GetUniformfv(old, from, f); gl::Uniform1fv(to, 1, f) },
+    gl::FLOAT_VEC2        => { gl::GetUniformfv(old, from, f); gl::Uniform2fv(to, 1, f) },
+    gl::FLOAT_VEC3        => { gl::GetUniformfv(old, from, f); gl::Uniform3fv(to, 1, f) },
+    gl::FLOAT_VEC4        => { gl::GetUniformfv(old, from, f); gl::Uniform4fv(to, 1, f) },
+    gl::FLOAT_MAT2        => { gl::GetUniformfv(old, from, f); gl::UniformMatrix2fv(to, 1, gl::FALSE, f) },
+    gl::FLOAT_MAT3        => { gl::GetUniformfv(old, from, f); gl::UniformMatrix3fv(to, 1, gl::FALSE, f) },
+    gl::FLOAT_MAT4        => { gl::GetUniformfv(old, from, f); gl::UniformMatrix4fv(to, 1, gl::FALSE, f) },
+    gl::FLOAT_MAT2x3      => { gl::GetUniformfv(old, from, f); gl::UniformMatrix2x3fv(to, 1, gl::FALSE, f) },
+    gl::FLOAT_MAT2x4      => { gl::GetUniformfv(old, from, f); gl::UniformMatrix2x4fv(to, 1, gl::FALSE, f) },
+    gl::FLOAT_MAT3x2      => { gl::GetUniformfv(old, from, f); gl::UniformMatrix3x2fv(to, 1, gl::FALSE, f) },
+    gl::FLOAT_MAT3x4      => { gl::GetUniformfv(old, from, f); gl::UniformMatrix3x4fv(to, 1, gl::FALSE, f) },
+    gl::FLOAT_MAT4x2      => { gl::GetUniformfv(old, from, f); gl::UniformMatrix4x2fv(to, 1, gl::FALSE, f) },
+    gl::FLOAT_MAT4x3      => { gl::GetUniformfv(old, from, f); gl::UniformMatrix4x3fv(to, 1, gl::FALSE, f) },
+    gl::UNSIGNED_INT      => { gl::GetUniformuiv(old, from, u); gl::Uniform1uiv(to, 1, u) },
+    gl::UNSIGNED_INT_VEC2 => { gl::GetUniformuiv(old, from, u); gl::Uniform2uiv(to, 1, u) },
+    gl::UNSIGNED_INT_VEC3 => { gl::GetUniformuiv(old, from, u); gl::Uniform3uiv(to, 1, u) },
+    gl::UNSIGNED_INT_VEC4 => { gl::GetUniformuiv(old, from, u); gl::Uniform4uiv(to, 1, u) },
+    gl::INT_VEC2 | gl::BOOL_VEC2 => { gl::GetUniformiv(old, from, i); gl::Uniform2iv(to, 1, i) },
+    gl::INT_VEC3 | gl::BOOL_VEC3 => { gl::GetUniformiv(old, from, i); gl::Uniform3iv(to, 1, i) },
+    gl::INT_VEC4 | gl::BOOL_VEC4 => { gl::GetUniformiv(old, from, i); gl::Uniform4iv(to, 1, i) },
+    // Ints, bools, and every kind of sampler.
+    _ => { gl::GetUniformiv(old, from, i); gl::Uniform1iv(to, 1, i) },
   }
 }
@@ -0,0 +1,29 @@
+//! Full-screen post-processing passes: bloom and tone mapping over the HDR scene, and overlays on the final image.
+
+use yaglw::gl_context::GLContext;
+use yaglw::shader::Shader;
+
+use resource_pack;
+use view::shaders;
+
+#[allow(missing_docs)]
+pub struct T<'a> {
+  /// Keep only the parts of the scene bright enough to glow.
+  pub bloom_extract: Shader<'a>,
+  /// Separable gaussian blur, one direction at a time.
+  pub blur: Shader<'a>,
+  /// Add the bloom and map the scene to displayable colors.
+  pub tonemap: Shader<'a>,
+  /// Tint the edges of the screen, blended over whatever's there.
+  pub vignette: Shader<'a>,
+}
+
+#[allow(missing_docs)]
+pub fn new<'a, 'b>(gl: &'b GLContext, resource_packs: &resource_pack::T) -> T<'a> where 'a: 'b {
+  T {
+    bloom_extract : shaders::shader_from_prefix(gl, resource_packs, "bloom_extract"),
+    blur          : shaders::shader_from_prefix(gl, resource_packs, "blur"),
+    tonemap       : shaders::shader_from_prefix(gl, resource_packs, "tonemap"),
+    vignette      : shaders::shader_from_prefix(gl, resource_packs, "vignette"),
+  }
+}
@@ -0,0 +1,173 @@
+//! Cascaded shadow maps from the sun. The view frustum is split into slices by distance, and terrain depth
+//! is rendered from the sun into one map per slice, so nearby shadows get most of the resolution.
+
+use cgmath;
+use cgmath::{Matrix4, Point3, Vector3, Vector4, InnerSpace, EuclideanSpace};
+use gl;
+use gl::types::*;
+use std;
+use yaglw::gl_context::GLContext;
+use yaglw::shader::Shader;
+use yaglw::texture::TextureUnit;
+
+use common::id_allocator;
+
+use view;
+
+/// Number of shadow cascades.
+pub const CASCADES: usize = 3;
+
+/// Width and height of each shadow map, in texels.
+pub const MAP_SIZE: i32 = 2048;
+
+/// The far edge of each cascade, as a distance in front of the camera. Past the last one, nothing is shadowed.
+pub const CASCADE_ENDS: [f32; CASCADES] = [24.0, 96.0, 384.0];
+
+/// How far beyond a cascade, toward the sun, to look for things casting shadows into it.
+const CASTER_MARGIN: f32 = 128.0;
+
+/// Framebuffers and depth textures for the shadow cascades.
+pub struct T {
+  framebuffers   : [GLuint; CASCADES],
+  depth_textures : [GLuint; CASCADES],
+  /// The texture unit each cascade's depth map is bound to.
+  pub units      : Vec<TextureUnit>,
+  /// The world-to-shadow-map transform for each cascade, as of the last call to `update`.
+  pub matrices   : [Matrix4<GLfloat>; CASCADES],
+}
+
+#[allow(missing_docs)]
+pub fn new(texture_unit_alloc: &mut id_allocator::T<TextureUnit>) -> T {
+  let mut framebuffers = [0; CASCADES];
+  let mut depth_textures = [0; CASCADES];
+  let mut units = Vec::with_capacity(CASCADES);
+  unsafe {
+    gl::GenFramebuffers(CASCADES as GLsizei, framebuffers.as_mut_ptr());
+    gl::GenTextures(CASCADES as GLsizei, depth_textures.as_mut_ptr());
+    for i in 0 .. CASCADES {
+      let unit = texture_unit_alloc.allocate();
+      gl::ActiveTexture(unit.gl_id());
+      gl::BindTexture(gl::TEXTURE_2D, depth_textures[i]);
+      gl::TexImage2D(
+        gl::TEXTURE_2D, 0, gl::DEPTH_COMPONENT32F as GLint, MAP_SIZE, MAP_SIZE, 0,
+        gl::DEPTH_COMPONENT, gl::FLOAT, std::ptr::null(),
+      );
+      gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as GLint);
+      gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as GLint);
+      gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as GLint);
+      gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as GLint);
+      // Sample with hardware depth comparison, so filtering smooths the shadow edges.
+      gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_COMPARE_MODE, gl::COMPARE_REF_TO_TEXTURE as GLint);
+      gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_COMPARE_FUNC, gl::LEQUAL as GLint);
+
+      gl::BindFramebuffer(gl::FRAMEBUFFER, framebuffers[i]);
+      gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::DEPTH_ATTACHMENT, gl::TEXTURE_2D, depth_textures[i], 0);
+      gl::DrawBuffer(gl::NONE);
+      gl::ReadBuffer(gl::NONE);
+      let status = gl::CheckFramebufferStatus(gl::FRAMEBUFFER);
+      if status != gl::FRAMEBUFFER_COMPLETE {
+        warn!("Shadow framebuffer {} is incomplete: 0x{:x}", i, status);
+      }
+
+      units.push(unit);
+    }
+    gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+  }
+
+  T {
+    framebuffers   : framebuffers,
+    depth_textures : depth_textures,
+    units          : units,
+    matrices       : [Matrix4::from_scale(1.0); CASCADES],
+  }
+}
+
+/// An orthographic projection from the sun that covers a slice of the view frustum.
+fn cascade_matrix(
+  camera : &view::camera::T,
+  aspect : f32,
+  sun    : &Vector3<f32>,
+  near   : f32,
+  far    : f32,
+) -> Matrix4<GLfloat> {
+  let forward = camera.forward();
+  let right = {
+    let right = forward.cross(Vector3::new(0.0, 1.0, 0.0));
+    if right.magnitude2() < 0.0001 { Vector3::new(1.0, 0.0, 0.0) } else { right.normalize() }
+  };
+  let up = right.cross(forward);
+  let tan_y = (view::FOV / 2.0).tan();
+  let tan_x = tan_y * aspect;
+
+  let mut corners = Vec::with_capacity(8);
+  for &d in &[near, far] {
+    let center = camera.position + forward * d;
+    for &sx in &[-1.0, 1.0] {
+    for &sy in &[-1.0, 1.0] {
+      corners.push(center + right * (sx * d * tan_x) + up * (sy * d * tan_y));
+    }}
+  }
+
+  // Fit a sphere rather than a box, so the map doesn't change size as the camera turns.
+  let center = Point3::centroid(&corners);
+  let radius = corners.iter().map(|c| (*c - center).magnitude()).fold(0.0, f32::max).ceil();
+
+  let eye = center + sun * (radius + CASTER_MARGIN);
+  // The sun moves in the xy plane, so z is never parallel to it.
+  let light_view = Matrix4::look_at(eye, center, Vector3::new(0.0, 0.0, 1.0));
+  let mut projection = cgmath::ortho(-radius, radius, -radius, radius, 0.0, 2.0 * radius + CASTER_MARGIN);
+
+  // Snap to whole texels, so shadow edges don't shimmer as the camera moves.
+  let origin = (projection * light_view) * Vector4::new(0.0, 0.0, 0.0, 1.0);
+  let texels = MAP_SIZE as f32 / 2.0;
+  let snap = |x: f32| ((x * texels).round() - x * texels) / texels;
+  projection.w.x += snap(origin.x);
+  projection.w.y += snap(origin.y);
+
+  projection * light_view
+}
+
+impl T {
+  /// Fit the cascades to the current camera and sun.
+  pub fn update(&mut self, camera: &view::camera::T, window_size: &cgmath::Vector2<i32>, near_clip: f32, sun: &Vector3<f32>) {
+    let aspect = window_size.x as f32 / window_size.y as f32;
+    let mut near = near_clip;
+    for i in 0 .. CASCADES {
+      self.matrices[i] = cascade_matrix(camera, aspect, sun, near, CASCADE_ENDS[i]);
+      near = CASCADE_ENDS[i];
+    }
+  }
+
+  /// The framebuffer that renders into one cascade's depth map.
+  pub fn framebuffer(&self, i: usize) -> u32 {
+    self.framebuffers[i]
+  }
+
+  /// Set the shadow uniforms that the terrain shader samples with.
+  pub fn set_uniforms(&self, shader: &mut Shader, gl: &mut GLContext, camera: &view::camera::T) {
+    shader.use_shader(gl);
+    unsafe {
+      let forward = camera.forward();
+      let uniform = shader.get_uniform_location("eye_forward");
+      gl::Uniform3f(uniform, forward.x, forward.y, forward.z);
+      for i in 0 .. CASCADES {
+        let uniform = shader.get_uniform_location(&format!("shadow_matrices[{}]", i));
+        let ptr = &self.matrices[i] as *const _ as *const _;
+        gl::UniformMatrix4fv(uniform, 1, 0, ptr);
+        let uniform = shader.get_uniform_location(&format!("cascade_ends[{}]", i));
+        gl::Uniform1f(uniform, CASCADE_ENDS[i]);
+        let uniform = shader.get_uniform_location(&format!("shadow_map_{}", i));
+        gl::Uniform1i(uniform, self.units[i].glsl_id as GLint);
+      }
+    }
+  }
+}
+
+impl Drop for T {
+  fn drop(&mut self) {
+    unsafe {
+      gl::DeleteFramebuffers(CASCADES as GLsizei, self.framebuffers.as_ptr());
+      gl::DeleteTextures(CASCADES as GLsizei, self.depth_textures.as_ptr());
+    }
+  }
+}
@@ -2,8 +2,9 @@
 
 use gl;
 use gl::types::*;
-use cgmath::{Point3, Vector3};
+use cgmath::{Point3, Vector2, Vector3};
 use std;
+use time;
 use yaglw;
 use yaglw::gl_context::GLContext;
 use yaglw::texture::BufferTexture;
@@ -13,29 +14,63 @@ use common::index;
 use common::fnv_map;
 use common::id_allocator;
 
-use terrain_mesh::Triangle;
+use terrain_mesh::{Triangle, tri};
 
+use super::backend;
 use super::entity;
+use super::upload;
 
 #[cfg(test)]
 use std::mem;
 
 const VERTICES_PER_TRIANGLE: usize = 3;
 
-/// Maximum number of bytes to be used in VRAM
-pub const BYTE_BUDGET: usize = 64_000_000;
-const POLYGON_COST: usize = 100;
-/// Maximum number of polygons to be used in VRAM
-pub const POLYGON_BUDGET: usize = BYTE_BUDGET / POLYGON_COST;
+/// Default number of bytes of VRAM to use for terrain.
+pub const DEFAULT_BYTE_BUDGET: usize = 64_000_000;
+/// VRAM used per terrain polygon, in bytes.
+pub const POLYGON_COST: usize = 150;
+
+/// Maximum number of polygons that fit in a VRAM budget.
+pub fn polygon_budget(byte_budget: usize) -> usize {
+  byte_budget / POLYGON_COST
+}
 
 /// Number of elements in a chunk in vram.
 pub const CHUNK_LENGTH: usize = 1 << 5;
-/// The number of polygons loaded contiguously into VRAM.
-const CHUNK_BUDGET: usize = POLYGON_BUDGET / CHUNK_LENGTH;
+
+/// Maximum number of chunks of polygons that fit in a VRAM budget.
+pub fn chunk_budget(byte_budget: usize) -> usize {
+  polygon_budget(byte_budget) / CHUNK_LENGTH
+}
 /// Instead of storing individual vertices, normals, etc. in VRAM, store them in chunks.
 /// This makes it much faster to unload things.
 pub struct Chunk<V>(pub [V; CHUNK_LENGTH]);
 
+/// A terrain vertex as laid out in VRAM: three RGBA32F texels, so a vertex is three fetches.
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct Vertex {
+  #[allow(missing_docs)]
+  pub position  : Point3<GLfloat>,
+  /// The polygon's material id, in the position texel's fourth component.
+  pub material  : GLfloat,
+  #[allow(missing_docs)]
+  pub normal    : Vector3<GLfloat>,
+  /// The polygon's texture atlas tile, or -1 for none, in the normal texel's fourth component.
+  pub tile      : GLfloat,
+  /// Texture atlas coordinates, in tile repeats.
+  pub uv        : Vector2<GLfloat>,
+  /// When the vertex's chunk was loaded, by `T::clock_ms`. Newly loaded terrain rises into place.
+  pub loaded_at : GLfloat,
+  _padding      : GLfloat,
+}
+
+/// Texels in the vertex buffer texture per terrain vertex.
+pub const TEXELS_PER_VERTEX: usize = 3;
+
+/// `loaded_at` for terrain that shouldn't animate in: long enough ago that it's finished.
+const LOADED_LONG_AGO: GLfloat = -1e9;
+
 impl<V> Chunk<V> {
   #[allow(missing_docs)]
   pub fn as_ptr(&self) -> *const V {
@@ -52,11 +87,17 @@ pub struct T<'a> {
   empty_array: GLuint,
   length: u32,
 
-  // Per-triangle buffers
+  /// Maximum number of chunks.
+  capacity: usize,
+  /// Staging for uploads into the buffers below. The buffers' own lengths aren't used;
+  /// `index_to_id` says how much of them is in use.
+  upload: upload::T,
 
-  vertex_positions: BufferTexture<'a, Chunk<Triangle<Point3<GLfloat>>>>,
-  normals: BufferTexture<'a, Chunk<Triangle<Vector3<GLfloat>>>>,
-  materials: BufferTexture<'a, Chunk<GLint>>,
+  /// Every attribute of every vertex, interleaved.
+  vertices: BufferTexture<'a, Chunk<Triangle<Vertex>>>,
+
+  /// Load times are measured from here, so they keep their precision as floats.
+  epoch_ns: u64,
 }
 
 /// Phantom type for this buffer.
@@ -78,11 +119,18 @@ fn correct_size() {
   assert!(mem::size_of::<Point2<GLfloat>>() == 2 * mem::size_of::<GLfloat>());
   assert!(mem::size_of::<Point3<GLfloat>>() == 3 * mem::size_of::<GLfloat>());
   assert!(mem::size_of::<Vector3<GLfloat>>() == 3 * mem::size_of::<GLfloat>());
+  assert!(mem::size_of::<Vertex>() == TEXELS_PER_VERTEX * 4 * mem::size_of::<GLfloat>());
 }
 
-#[allow(missing_docs)]
+/// Bytes of staging per upload segment.
+const UPLOAD_SEGMENT_BYTES: usize = 1 << 20;
+
+/// Allocate terrain buffers within `byte_budget`. If `persistent_upload`, uploads are staged through
+/// a persistently mapped buffer.
 pub fn new<'a, 'b>(
   gl: &'b mut GLContext,
+  byte_budget: usize,
+  persistent_upload: bool,
 ) -> T<'a> where
   'a: 'b,
 {
@@ -95,10 +143,45 @@ pub fn new<'a, 'b>(
       empty_array
     },
     length: 0,
-    vertex_positions: BufferTexture::new(gl, gl::R32F, CHUNK_BUDGET),
-    normals: BufferTexture::new(gl, gl::R32F, CHUNK_BUDGET),
-    materials: BufferTexture::new(gl, gl::R32UI, CHUNK_BUDGET),
+    capacity: chunk_budget(byte_budget),
+    upload: upload::new(UPLOAD_SEGMENT_BYTES, persistent_upload),
+    vertices: BufferTexture::new(gl, gl::RGBA32F, chunk_budget(byte_budget)),
+    epoch_ns: time::precise_time_ns(),
+  }
+}
+
+fn as_bytes<V>(chunk: &Chunk<V>) -> &[u8] {
+  unsafe {
+    std::slice::from_raw_parts(chunk.as_ptr() as *const u8, std::mem::size_of_val(chunk))
+  }
+}
+
+/// Interleave a chunk's attributes into the layout the shaders read.
+fn interleave(
+  positions : &Chunk<Triangle<Point3<GLfloat>>>,
+  normals   : &Chunk<Triangle<Vector3<GLfloat>>>,
+  uvs       : &Chunk<Triangle<Vector3<GLfloat>>>,
+  materials : &Chunk<GLint>,
+  loaded_at : GLfloat,
+) -> Chunk<Triangle<Vertex>> {
+  let vertex = |position, normal, uv: Vector3<GLfloat>, material| {
+    Vertex {
+      position  : position,
+      material  : material,
+      normal    : normal,
+      tile      : uv.z,
+      uv        : Vector2::new(uv.x, uv.y),
+      loaded_at : loaded_at,
+      _padding  : 0.0,
+    }
+  };
+  let zero = vertex(Point3::new(0.0, 0.0, 0.0), Vector3::new(0.0, 0.0, 0.0), Vector3::new(0.0, 0.0, -1.0), 0.0);
+  let mut chunk = Chunk([tri(zero, zero, zero); CHUNK_LENGTH]);
+  for i in 0 .. CHUNK_LENGTH {
+    let (p, n, t, m) = (&positions.0[i], &normals.0[i], &uvs.0[i], materials.0[i] as GLfloat);
+    chunk.0[i] = tri(vertex(p.v1, n.v1, t.v1, m), vertex(p.v2, n.v2, t.v2, m), vertex(p.v3, n.v3, t.v3, m));
   }
+  chunk
 }
 
 impl<'a> T<'a> {
@@ -128,117 +211,214 @@ impl<'a> T<'a> {
     }
   }
 
-  #[allow(missing_docs)]
-  pub fn bind_vertex_positions(
-    &self,
-    gl: &mut GLContext,
-    texture_unit_alloc: &mut id_allocator::T<TextureUnit>,
-    shader: &mut yaglw::shader::Shader,
-  ) {
-    shader.use_shader(gl);
-    self.bind(texture_unit_alloc, shader, "positions", self.vertex_positions.handle.gl_id);
-  }
-
-  #[allow(missing_docs)]
-  pub fn bind_normals(
+  /// Bind the interleaved vertex data to a shader's `vertices` sampler.
+  pub fn bind_vertices(
     &self,
     gl: &mut GLContext,
     texture_unit_alloc: &mut id_allocator::T<TextureUnit>,
     shader: &mut yaglw::shader::Shader,
   ) {
     shader.use_shader(gl);
-    self.bind(texture_unit_alloc, shader, "normals", self.normals.handle.gl_id);
+    self.bind(texture_unit_alloc, shader, "vertices", self.vertices.handle.gl_id);
   }
 
-  #[allow(missing_docs)]
-  pub fn bind_materials(
-    &self,
-    gl: &mut GLContext,
-    texture_unit_alloc: &mut id_allocator::T<TextureUnit>,
-    shader: &mut yaglw::shader::Shader,
-  ) {
-    shader.use_shader(gl);
-    self.bind(texture_unit_alloc, shader, "materials", self.materials.handle.gl_id);
+  /// The clock that vertices' `loaded_at` times are on, in ms.
+  pub fn clock_ms(&self) -> f32 {
+    ((time::precise_time_ns() - self.epoch_ns) / 1_000_000) as f32
   }
 
-  /// Add a series of entites into VRAM.
+  /// Add a series of entites into VRAM. If `appear`, the chunk rises into place instead of popping in.
+  /// The data goes through the staging ring, so this doesn't wait on draws still reading the buffers.
   pub fn push(
     &mut self,
-    gl        : &mut GLContext,
     chunk_id  : entity::id::Terrain,
     vertices  : &Chunk<Triangle<Point3<GLfloat>>>,
     normals   : &Chunk<Triangle<Vector3<GLfloat>>>,
+    uvs       : &Chunk<Triangle<Vector3<GLfloat>>>,
     materials : &Chunk<GLint>,
+    appear    : bool,
   ) {
     debug!("Insert {:?}", chunk_id);
+    assert!(self.index_to_id.len() < self.capacity, "Ran out of VRAM for terrain");
 
-    let vertices  = unsafe { std::slice::from_raw_parts(vertices.as_ptr()  as *const _, 1) };
-    let normals   = unsafe { std::slice::from_raw_parts(normals.as_ptr()   as *const _, 1) };
-    let materials = unsafe { std::slice::from_raw_parts(materials.as_ptr() as *const _, 1) };
-
-    self.vertex_positions.buffer.byte_buffer.bind(gl);
-    let success = self.vertex_positions.buffer.push(gl, vertices);
-    assert!(success);
+    let idx = self.index_to_id.len();
+    let loaded_at = if appear { self.clock_ms() } else { LOADED_LONG_AGO };
+    let chunk = interleave(vertices, normals, uvs, materials, loaded_at);
+    let buffer = self.vertices.buffer.byte_buffer.handle.gl_id;
+    self.upload.upload(buffer, idx * std::mem::size_of_val(&chunk), as_bytes(&chunk));
 
-    self.normals.buffer.byte_buffer.bind(gl);
-    let success = self.normals.buffer.push(gl, normals);
-    assert!(success);
-
-    let previous = self.id_to_index.insert(chunk_id, self.index_to_id.len());
+    let previous = self.id_to_index.insert(chunk_id, idx);
     assert!(previous.is_none());
     self.index_to_id.push(chunk_id);
     assert_eq!(self.id_to_index.len(), self.index_to_id.len());
 
-    self.materials.buffer.byte_buffer.bind(gl);
-    let success = self.materials.buffer.push(gl, materials);
-    assert!(success);
-
     self.length += 1;
   }
 
+  /// Fence the uploads made since the last call. Call this once per frame.
+  pub fn fence_uploads(&mut self) {
+    self.upload.fence();
+  }
+
+  /// Number of chunks that couldn't go through the staging ring and were uploaded directly.
+  pub fn direct_uploads(&self) -> u64 {
+    self.upload.direct
+  }
+
+  /// Move the last `count` chunks into `idx .. idx + count`, on the GPU.
+  fn move_chunks(&self, idx: usize, count: usize) {
+    let len = self.index_to_id.len();
+    if idx >= len - count {
+      return
+    }
+    let size = std::mem::size_of::<Chunk<Triangle<Vertex>>>();
+    let buffer = self.vertices.buffer.byte_buffer.handle.gl_id;
+    unsafe {
+      gl::BindBuffer(gl::COPY_READ_BUFFER, buffer);
+      gl::BindBuffer(gl::COPY_WRITE_BUFFER, buffer);
+      gl::CopyBufferSubData(
+        gl::COPY_READ_BUFFER,
+        gl::COPY_WRITE_BUFFER,
+        ((len - count) * size) as GLintptr,
+        (idx * size) as GLintptr,
+        (count * size) as GLsizeiptr,
+      );
+    }
+  }
+
   /// Remove some entity from VRAM.
   /// Returns the swapped ID and its VRAM index, if any.
   pub fn swap_remove(
     &mut self,
-    gl: &mut GLContext,
     id: entity::id::Terrain,
   ) -> Option<(ChunkIndex, ChunkIndex)>
   {
-    let idx = self.id_to_index[&id];
-    let swapped_idx = self.index_to_id.len() - 1;
-    let swapped_id = self.index_to_id[swapped_idx];
-    self.index_to_id.swap_remove(idx);
-    self.id_to_index.remove(&id);
+    self.swap_remove_range(&[id]).pop()
+  }
+
+  /// Remove many entities from VRAM at once, moving contiguous runs of survivors into contiguous runs
+  /// of holes so the number of GL calls depends on how fragmented the removal is, not on its size.
+  /// Returns the (new, old) VRAM index of every entity that was moved.
+  pub fn swap_remove_range(
+    &mut self,
+    ids: &[entity::id::Terrain],
+  ) -> Vec<(ChunkIndex, ChunkIndex)>
+  {
+    let mut removing = vec!(false; self.index_to_id.len());
+    for id in ids {
+      let idx = self.id_to_index.remove(id).unwrap();
+      removing[idx] = true;
+    }
 
-    debug!("Swap-remove {:?} {:?} with {:?} {:?}", id, idx, swapped_id, swapped_idx);
+    let mut moved = Vec::new();
+    for (idx, count) in compaction(removing) {
+      let len = self.index_to_id.len();
+      debug!("Swap-remove {} chunks at {:?} with {:?}", count, idx, len - count);
+
+      self.move_chunks(idx, count);
+
+      for i in 0 .. count {
+        let from = len - count + i;
+        if idx + i < len - count {
+          let id = self.index_to_id[from];
+          self.index_to_id[idx + i] = id;
+          self.id_to_index.insert(id, idx + i);
+          moved.push((index::of_u32((idx + i) as u32), index::of_u32(from as u32)));
+        }
+      }
+      self.index_to_id.truncate(len - count);
+      self.length -= count as u32;
+    }
+    assert_eq!(self.id_to_index.len(), self.index_to_id.len());
 
-    let r =
-      if id == swapped_id {
-        None
-      } else {
-        self.id_to_index.insert(swapped_id, idx);
-        Some((index::of_u32(idx as u32), index::of_u32(swapped_idx as u32)))
-      };
+    moved
+  }
 
-    self.length -= 1;
+  /// Draw the terrain.
+  pub fn draw(&self, backend: &mut backend::T) {
+    unsafe {
+      gl::BindVertexArray(self.empty_array);
+    }
+    backend.draw(&backend::Draw::triangles("terrain", self.length * CHUNK_LENGTH as u32 * VERTICES_PER_TRIANGLE as u32));
+  }
+}
 
-    self.vertex_positions.buffer.byte_buffer.bind(gl);
-    self.vertex_positions.buffer.swap_remove(gl, idx, 1);
+/// The `swap_remove(idx, count)` calls that remove the entries marked in `removing`. Each call moves
+/// the last `count` entries into `idx .. idx + count` and shortens the buffer by `count`.
+fn compaction(mut removing: Vec<bool>) -> Vec<(usize, usize)> {
+  let mut calls = Vec::new();
+  let mut len = removing.len();
+  let mut hole = 0;
+  loop {
+    // Drop removed entries off the end; nothing needs to move for those.
+    let mut tail = len;
+    while tail > 0 && removing[tail - 1] {
+      tail -= 1;
+    }
+    if tail < len {
+      calls.push((tail, len - tail));
+      len = tail;
+    }
 
-    self.normals.buffer.byte_buffer.bind(gl);
-    self.normals.buffer.swap_remove(gl, idx, 1);
+    while hole < len && !removing[hole] {
+      hole += 1;
+    }
+    if hole >= len {
+      return calls
+    }
 
-    self.materials.buffer.byte_buffer.bind(gl);
-    self.materials.buffer.swap_remove(gl, idx, 1);
+    // Fill as much of this run of holes as possible from the run of survivors at the end.
+    // The end isn't removed, so the two runs can't overlap.
+    let mut holes = 1;
+    while hole + holes < len && removing[hole + holes] {
+      holes += 1;
+    }
+    let mut survivors = 1;
+    while survivors < len - hole - holes && !removing[len - 1 - survivors] {
+      survivors += 1;
+    }
+    let count = std::cmp::min(holes, survivors);
+    calls.push((hole, count));
+    for i in 0 .. count {
+      removing[hole + i] = false;
+    }
+    len -= count;
+    hole += count;
+  }
+}
 
-    r
+#[test]
+fn compaction_removes_runs_in_few_calls() {
+  let mut entries: Vec<usize> = (0 .. 1000).collect();
+  let removing: Vec<bool> = entries.iter().map(|&i| (i >= 100 && i < 400) || i >= 900).collect();
+  let calls = compaction(removing);
+  assert!(calls.len() <= 3, "{:?}", calls);
+
+  for (idx, count) in calls {
+    let len = entries.len();
+    for i in 0 .. count {
+      entries[idx + i] = entries[len - count + i];
+    }
+    entries.truncate(len - count);
   }
+  entries.sort();
+  let expected: Vec<usize> = (0 .. 100).chain(400 .. 900).collect();
+  assert_eq!(entries, expected);
+}
 
-  /// Draw the terrain.
-  pub fn draw(&self, _gl: &mut GLContext) {
-    unsafe {
-      gl::BindVertexArray(self.empty_array);
-      gl::DrawArrays(gl::TRIANGLES, 0, (self.length * CHUNK_LENGTH as u32 * VERTICES_PER_TRIANGLE as u32) as GLint);
+#[test]
+fn compaction_handles_scattered_removals() {
+  let mut entries: Vec<usize> = (0 .. 64).collect();
+  let removing: Vec<bool> = entries.iter().map(|&i| i % 3 == 0).collect();
+  for (idx, count) in compaction(removing) {
+    let len = entries.len();
+    assert!(idx + count <= len - count || idx == len - count);
+    for i in 0 .. count {
+      entries[idx + i] = entries[len - count + i];
     }
+    entries.truncate(len - count);
   }
+  entries.sort();
+  let expected: Vec<usize> = (0 .. 64).filter(|i| i % 3 != 0).collect();
+  assert_eq!(entries, expected);
 }
@@ -10,12 +10,19 @@ use stopwatch;
 use time;
 use yaglw::gl_context::GLContext;
 
+use common::data_dir;
 use common::interval_timer::IntervalTimer;
 use common::protocol;
+use common::telemetry;
+use common::voxel;
 
+use audio_loader;
+use audio_thread;
 use client;
-use hud::make_hud;
+use hud::{make_hud, update_hud};
 use process_event::process_event;
+use resource_pack;
+use settings::Action;
 use view;
 
 use super::update;
@@ -34,15 +41,17 @@ enum ViewIteration {
 }
 
 #[allow(missing_docs)]
-pub fn view_thread<Recv0, Recv1, UpdateServer>(
+pub fn view_thread<Recv0, Recv1, UpdateServer, UpdateAudio>(
   client: &client::T,
   recv0: &mut Recv0,
   recv1: &mut Recv1,
   update_server: &mut UpdateServer,
+  update_audio: &mut UpdateAudio,
 ) where
   Recv0: FnMut() -> Option<update::T>,
   Recv1: FnMut() -> Option<update::T>,
   UpdateServer: FnMut(protocol::ClientToServer),
+  UpdateAudio: FnMut(audio_thread::Message),
 {
   let sdl = sdl2::init().unwrap();
   let sdl_event = sdl.event().unwrap();
@@ -52,14 +61,14 @@ pub fn view_thread<Recv0, Recv1, UpdateServer>(
   gl_attr.set_context_profile(video::GLProfile::Core);
   gl_attr.set_context_version(GL_MAJOR_VERSION, GL_MINOR_VERSION);
 
-  // Open the window as fullscreen at the current resolution.
+  let window_size = client.settings.lock().unwrap().window_size;
   let mut window =
     video.window(
       "Playform",
-      800, 600,
+      window_size.x, window_size.y,
     );
   let window = window.opengl();
-  let window = window.build().unwrap();
+  let mut window = window.build().unwrap();
 
   assert_eq!(gl_attr.context_profile(), video::GLProfile::Core);
   assert_eq!(gl_attr.context_version(), (GL_MAJOR_VERSION, GL_MINOR_VERSION));
@@ -82,9 +91,33 @@ pub fn view_thread<Recv0, Recv1, UpdateServer>(
     Vector2::new(w as i32, h as i32)
   };
 
-  let mut view = view::new(gl, window_size);
+  let (vram_budget, reflections, tone_mapping, bloom, triplanar, decoration_distance) = {
+    let settings = client.settings.lock().unwrap();
+    (
+      settings.vram_budget(),
+      settings.reflections,
+      settings.tone_mapping,
+      settings.bloom,
+      settings.triplanar,
+      settings.decoration_distance as f32,
+    )
+  };
+  let mut view =
+    view::new(
+      gl,
+      &client.resource_packs.lock().unwrap(),
+      window_size,
+      vram_budget,
+      reflections,
+      tone_mapping,
+      bloom,
+      triplanar,
+      decoration_distance,
+    );
 
   sdl.mouse().set_relative_mouse_mode(true);
+  // SDL starts out taking text input; it's only wanted while typing a chat message.
+  video.text_input().stop();
 
   make_hud(&mut view);
 
@@ -100,6 +133,11 @@ pub fn view_thread<Recv0, Recv1, UpdateServer>(
 
   let mut last_update = time::precise_time_ns();
 
+  let gpu = view.capabilities.renderer.clone();
+  let mut frame_times = telemetry::new();
+  let mut last_render = time::precise_time_ns();
+  let mut shown_toast = None;
+
   loop {
     let view_iteration =
       stopwatch::time("view_iteration", || {
@@ -117,11 +155,14 @@ pub fn view_thread<Recv0, Recv1, UpdateServer>(
             Event::Quit{..} => return ViewIteration::Quit,
             Event::AppTerminating{..} => return ViewIteration::Quit,
             Event::Window { win_event: WindowEvent::Close, .. } => return ViewIteration::Quit,
+            // Screenshot tests don't take input, so the camera stays where they put it.
+            _ if client.screenshot_test.is_some() => {},
             event => {
               process_event(
                 update_server,
                 &mut view,
                 &client,
+                &video,
                 event,
               );
             },
@@ -132,13 +173,16 @@ pub fn view_thread<Recv0, Recv1, UpdateServer>(
           sdl.mouse().warp_mouse_in_window(&window, window_size.x / 2, window_size.y / 2);
         }
 
+        let mut updated = false;
         stopwatch::time("apply_updates", || {
           let start = time::precise_time_ns();
           loop {
             if let Some(update) = recv0() {
               update::apply_client_to_view(&mut view, update);
+              updated = true;
             } else if let Some(update) = recv1() {
               update::apply_client_to_view(&mut view, update);
+              updated = true;
             } else {
               info!("Out of view updates");
               break
@@ -152,11 +196,96 @@ pub fn view_thread<Recv0, Recv1, UpdateServer>(
 
         let renders = render_timer.update(time::precise_time_ns());
         if renders > 0 {
+          // If the update thread has the terrain, just keep last frame's answer rather than stall.
+          if let Ok(terrain) = client.terrain.try_lock() {
+            if view.third_person {
+              view.place_third_person_camera(|p| terrain.material_at(p).map_or(false, |m| m.is_solid()));
+            } else if client.replay.is_none() {
+              // Replays have their own free camera.
+              view.place_first_person_camera(
+                time::precise_time_ns(),
+                |p| terrain.material_at(p).map_or(false, |m| m.is_solid()),
+              );
+            }
+            view.relight_entities(
+              time::precise_time_ns(),
+              |p| terrain.material_at(p).map_or(false, |m| m.is_solid()),
+            );
+            let underwater = terrain.material_at(&view.camera.position) == Some(voxel::Material::Water);
+            if underwater != view.underwater {
+              view.underwater = underwater;
+              update_audio(audio_thread::Message::Muffle(underwater));
+            }
+          }
+          // Once the camera's in place, so mobs are culled against this frame's view.
+          view.update_mobs(time::precise_time_ns());
+          view.update_items(time::precise_time_ns());
+          view.update_players();
+          if view.vitals.heartbeat_due(time::precise_time_ns()) {
+            update_audio(audio_thread::Message::PlayOneShot(audio_loader::SoundId::Heartbeat));
+          }
+          match resource_pack::poll(&mut client.server_pack.lock().unwrap()) {
+            None => {},
+            Some(Ok(name)) => {
+              let mut resource_packs = client.resource_packs.lock().unwrap();
+              resource_packs.push_front(name);
+              view.reload_resources(&resource_packs);
+            },
+            Some(Err(err)) => {
+              warn!("{}", err);
+              view.toasts.push(err);
+            },
+          }
+          let toast =
+            match *client.chat.lock().unwrap() {
+              Some(ref entry) => Some(format!("Say: {}", entry.display())),
+              None => {
+                match resource_pack::offered(&client.server_pack.lock().unwrap()) {
+                  Some(pack) => {
+                    let settings = client.settings.lock().unwrap();
+                    Some(format!(
+                      "The server requires the resource pack {}. Use it? {} for yes, {} for no",
+                      pack.url,
+                      settings.keybinds[&Action::AcceptResourcePack].name(),
+                      settings.keybinds[&Action::DeclineResourcePack].name(),
+                    ))
+                  },
+                  None => view.toasts.current(time::precise_time_ns()).map(String::from),
+                }
+              },
+            };
+          if toast != shown_toast {
+            let title =
+              match toast {
+                None => String::from("Playform"),
+                Some(ref text) => format!("Playform - {}", text),
+              };
+            if let Err(err) = window.set_title(&title) {
+              warn!("Couldn't show message: {:?}", err);
+            }
+            shown_toast = toast;
+          }
+          update_hud(&mut view);
+          if let Some(ref test) = client.screenshot_test {
+            let changed = updated || client.terrain.lock().unwrap().queued_update_count() > 0;
+            test.lock().unwrap().before_render(&mut view, changed);
+          }
+          let mut tested = false;
           stopwatch::time("render", || {
             view::render::render(&mut view);
+            if let Some(ref test) = client.screenshot_test {
+              tested = test.lock().unwrap().after_render(&view.window_size);
+            }
             // swap buffers
             window.gl_swap_window();
           });
+          if tested {
+            return ViewIteration::Quit
+          }
+
+          let now = time::precise_time_ns();
+          frame_times.push(now - last_render);
+          last_render = now;
         }
 
         ViewIteration::Continue
@@ -168,5 +297,22 @@ pub fn view_thread<Recv0, Recv1, UpdateServer>(
     }
   }
 
+  if telemetry::enabled() {
+    let mut report = telemetry::report();
+    report.push("gpu", gpu);
+    report.push("load_distance", client.max_load_distance);
+    client.terrain.lock().unwrap().report(&mut report);
+    report.push("vram_evictions", view.vram.evictions);
+    report.push("vram_rejections", view.vram.rejections);
+    report.push("vram_direct_uploads", view.terrain_buffers.direct_uploads());
+    report.push("avg_fps", frame_times.mean().map(|ns| 1_000_000_000 / ns.max(1)).unwrap_or(0));
+    report.push_samples("frame_ns", &frame_times);
+    let path = data_dir::dir(data_dir::Kind::Logs).join("telemetry_client.out");
+    match report.write_to(&path) {
+      Ok(()) => info!("Wrote telemetry to {}", path.display()),
+      Err(err) => warn!("Error writing telemetry: {:?}", err),
+    }
+  }
+
   debug!("view exiting.");
 }
@@ -0,0 +1,55 @@
+//! Short messages from the server, shown one at a time for a few seconds each.
+//! There's no text rendering in the view, so the current one goes in the window title.
+
+use std::collections::VecDeque;
+
+/// How long each message is shown for, in ns.
+const SHOW_NS: u64 = 6_000_000_000;
+
+#[allow(missing_docs)]
+pub struct T {
+  /// Messages waiting to be shown, oldest first.
+  queue   : VecDeque<String>,
+  /// The message being shown, and when it started showing.
+  current : Option<(String, u64)>,
+}
+
+#[allow(missing_docs)]
+pub fn new() -> T {
+  T {
+    queue   : VecDeque::new(),
+    current : None,
+  }
+}
+
+impl T {
+  /// Queue up a message to show.
+  pub fn push(&mut self, text: String) {
+    self.queue.push_back(text);
+  }
+
+  /// The message to show at `now_ns`, if any. Call this every frame.
+  pub fn current(&mut self, now_ns: u64) -> Option<&str> {
+    let expired =
+      match self.current {
+        None => true,
+        Some((_, shown_ns)) => now_ns.saturating_sub(shown_ns) >= SHOW_NS,
+      };
+    if expired {
+      self.current = self.queue.pop_front().map(|text| (text, now_ns));
+    }
+    self.current.as_ref().map(|&(ref text, _)| text.as_str())
+  }
+}
+
+#[test]
+fn messages_show_in_turn() {
+  let mut toasts = new();
+  assert_eq!(toasts.current(0), None);
+  toasts.push(String::from("welcome"));
+  toasts.push(String::from("hint"));
+  assert_eq!(toasts.current(1), Some("welcome"));
+  assert_eq!(toasts.current(SHOW_NS), Some("welcome"));
+  assert_eq!(toasts.current(SHOW_NS + 1), Some("hint"));
+  assert_eq!(toasts.current(3 * SHOW_NS), None);
+}
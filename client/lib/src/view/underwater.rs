@@ -0,0 +1,31 @@
+//! Effects for when the camera is underwater: short, murky fog, and with post-processing,
+//! a tinted screen that wobbles as if seen through the water.
+//! Whether the camera is underwater is worked out each frame from the voxels the client has loaded.
+
+use gl;
+use yaglw::shader::Shader;
+
+/// Distance over which the fog thickens, above water.
+const FOG_DISTANCE: f32 = 768.0;
+
+/// Distance over which the fog thickens, underwater.
+const UNDERWATER_FOG_DISTANCE: f32 = 24.0;
+
+/// What the fog color (and with post-processing, the whole screen) is multiplied by underwater.
+pub const TINT: [f32; 3] = [0.3, 0.6, 0.75];
+
+/// Set the `fog_distance` and `fog_tint` uniforms used by depth_fog.glsl.
+pub fn set_fog(shader: &mut Shader, underwater: bool) {
+  let (distance, tint) =
+    if underwater {
+      (UNDERWATER_FOG_DISTANCE, TINT)
+    } else {
+      (FOG_DISTANCE, [1.0, 1.0, 1.0])
+    };
+  unsafe {
+    let distance_uniform = shader.get_uniform_location("fog_distance");
+    gl::Uniform1f(distance_uniform, distance);
+    let tint_uniform = shader.get_uniform_location("fog_tint");
+    gl::Uniform3f(tint_uniform, tint[0], tint[1], tint[2]);
+  }
+}
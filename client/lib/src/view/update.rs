@@ -1,37 +1,66 @@
 //! Define the updates passed from the client to the view.
 
-use cgmath::Point3;
+use cgmath::{Point3, Vector3};
+use collision::{Aabb, Aabb3};
 use stopwatch;
 
+use mob_interpolation;
 use terrain_mesh;
-use vertex::ColoredVertex;
 use view;
 
+use common::horizon;
 use common::index;
+use common::protocol;
+use common::voxel;
 
 use super::chunked_terrain;
 use super::entity;
 use super::light;
-use super::mob_buffers::VERTICES_PER_MOB;
-use super::player_buffers::VERTICES_PER_PLAYER;
+use super::player_buffers;
 
 /// Messages from the client to the view.
 pub enum T {
-  /// Set the camera location.
+  /// Move the player's eyes, and the camera with them.
   MoveCamera(Point3<f32>),
 
-  /// Update a player mesh.
-  UpdatePlayer(entity::id::Player, [ColoredVertex; VERTICES_PER_PLAYER]),
-  /// Update a mob mesh.
-  UpdateMob(entity::id::Mob, [ColoredVertex; VERTICES_PER_MOB]),
+  /// Move a player's avatar to fill some bounds.
+  UpdatePlayer(entity::id::Player, Aabb3<f32>),
+  /// Stop drawing a player.
+  RemovePlayer(entity::id::Player),
+  /// Record a mob's transform, along with when it arrived.
+  UpdateMob(entity::id::Mob, u64, protocol::MobTransform),
+  /// Stop drawing a mob.
+  RemoveMob(entity::id::Mob),
+  /// Move a dropped item, or start drawing it.
+  UpdateItem(entity::id::Item, Point3<f32>, voxel::Material),
+  /// Stop drawing a dropped item.
+  RemoveItem(entity::id::Item),
+  /// Move something thrown, or start drawing it.
+  UpdateProjectile(entity::id::Projectile, Point3<f32>, Vector3<f32>, voxel::Material),
+  /// Stop drawing something thrown.
+  RemoveProjectile(entity::id::Projectile),
 
   /// Update the sun.
   SetSun(light::Sun),
+  /// Record the player's health and max health, along with when they arrived.
+  SetHealth(u32, u32, u64),
+  /// Show the player a message.
+  ShowMessage(String),
+  /// Record a nearby boss's health and max health, along with when they arrived.
+  SetBossHealth(u32, u32, u64),
+  /// Record what's in the player's inventory.
+  SetInventory(Vec<Option<protocol::ItemStack>>),
 
   /// Add a terrain chunk to the view.
   LoadMesh (Box<chunked_terrain::T>),
   /// Remove a terrain entity.
   UnloadMesh(terrain_mesh::Ids),
+  /// Add or replace a tile of the far horizon, as its columns.
+  LoadHorizon(horizon::Tile, Vec<horizon::Column>),
+  /// Remove a tile of the far horizon.
+  UnloadHorizon(horizon::Tile),
+  /// Set how far out the polygonal terrain reaches, so the horizon is only drawn past it.
+  SetHorizonStart(f32),
   /// Treat a series of updates as an atomic operation.
   Atomic(Vec<T>),
 }
@@ -42,13 +71,47 @@ pub use self::T::*;
 pub fn apply_client_to_view(view: &mut view::T, up: T) {
   match up {
     T::MoveCamera(position) => {
-      view.camera.translate_to(position);
+      view.eye = position;
+      // The third-person camera is placed each frame, once terrain's been checked.
+      if !view.third_person {
+        view.camera.translate_to(position);
+      }
+    },
+    T::UpdateMob(id, time_ns, transform) => {
+      view.mobs
+        .entry(id)
+        .or_insert_with(mob_interpolation::new)
+        .push(time_ns, transform);
+    },
+    T::RemoveMob(id) => {
+      view.mobs.remove(&id);
+      view.culled_mobs.remove(&id);
+      view.mob_buffers.swap_remove(&mut view.gl, id);
+    },
+    T::UpdateItem(id, position, material) => {
+      // Past the limit, new items are left undrawn until others go.
+      if view.items.contains_key(&id) || view.items.len() < view::MAX_ITEMS {
+        view.items.insert(id, (position, material));
+      }
+    },
+    T::RemoveItem(id) => {
+      view.items.remove(&id);
+      view.item_buffers.swap_remove(&mut view.gl, id);
     },
-    T::UpdateMob(id, triangles) => {
-      view.mob_buffers.insert(&mut view.gl, id, &triangles);
+    T::UpdateProjectile(id, position, velocity, material) => {
+      view.show_projectile(id, position, velocity, material);
     },
-    T::UpdatePlayer(id, triangles) => {
-      view.player_buffers.insert(&mut view.gl, id, &triangles);
+    T::RemoveProjectile(id) => {
+      view.hide_projectile(id);
+    },
+    T::UpdatePlayer(id, bounds) => {
+      view.players.insert(id, bounds);
+      let light = view.entity_light.at(&bounds.center());
+      view.player_buffers.insert(&mut view.gl, id, &player_buffers::avatar(&view.player_model, &bounds, light));
+    },
+    T::RemovePlayer(id) => {
+      view.players.remove(&id);
+      view.player_buffers.swap_remove(&mut view.gl, id);
     },
     T::SetSun(sun) => {
       match view.input_mode {
@@ -58,16 +121,53 @@ pub fn apply_client_to_view(view: &mut view::T, up: T) {
         },
       }
     },
+    T::SetHealth(health, max_health, time_ns) => {
+      view.vitals.set(health, max_health, time_ns);
+    },
+    T::ShowMessage(text) => {
+      view.toasts.push(text);
+    },
+    T::SetBossHealth(health, max_health, time_ns) => {
+      view.boss_health =
+        if health == 0 {
+          None
+        } else {
+          Some((health as f32 / max_health.max(1) as f32, time_ns))
+        };
+    },
+    T::SetInventory(slots) => {
+      view.inventory = slots;
+    },
     T::LoadMesh(mesh) => {
       stopwatch::time("add_chunk", move || {
         let mesh = *mesh;
+        let ids =
+          terrain_mesh::Ids {
+            chunk_ids : mesh.ids.clone(),
+            grass_ids : mesh.grass.ids.clone(),
+            water_ids : mesh.water.ids.clone(),
+          };
+        let evicted =
+          match view.vram.make_room(&ids, &mesh.center, &view.camera.position) {
+            None => {
+              debug!("No room in VRAM for mesh at {:?}", mesh.center);
+              return
+            },
+            Some(evicted) => evicted,
+          };
+        for ids in evicted {
+          unload_mesh(view, ids);
+        }
+        view.vram.insert(ids, mesh.center);
+
         for i in 0 .. mesh.chunk_count() {
           view.terrain_buffers.push(
-            &mut view.gl,
             mesh.ids[i],
             &mesh.vertex_coordinates[i],
             &mesh.normals[i],
+            &mesh.uvs[i],
             &mesh.materials[i],
+            mesh.appear,
           );
         }
         let mut grass_entries = Vec::with_capacity(mesh.grass.len());
@@ -88,28 +188,26 @@ pub fn apply_client_to_view(view: &mut view::T, up: T) {
           grass_entries.as_ref(),
           mesh.grass.ids.as_ref(),
         );
+        for (&id, vertices) in mesh.water.ids.iter().zip(mesh.water.vertex_coordinates.iter()) {
+          view.water_buffers.push(&mut view.gl, id, vertices);
+        }
       })
     },
-    T::UnloadMesh(terrain_mesh::Ids { chunk_ids, grass_ids }) => {
-      // Removing grass needs to happen before the calls to [update_polygon_index], or we will remove the wrong things.
-      for id in grass_ids {
-        view.grass_buffers.swap_remove(&mut view.gl, id);
-      }
-      for chunk_id in chunk_ids {
-        match view.terrain_buffers.swap_remove(&mut view.gl, chunk_id) {
-          None => {},
-          Some((idx, swapped_idx)) => {
-            for i in index::all() {
-              view.grass_buffers.update_polygon_index(
-                &mut view.gl,
-                swapped_idx.subindex(i),
-                idx.subindex(i),
-              );
-            }
-          }
-        }
+    T::UnloadMesh(ids) => {
+      // Meshes that were evicted, or never fit in the first place, are already gone.
+      if view.vram.remove(&ids) {
+        unload_mesh(view, ids);
       }
     },
+    T::LoadHorizon(tile, columns) => {
+      view.horizon_buffers.insert(&mut view.gl, tile, &columns);
+    },
+    T::UnloadHorizon(tile) => {
+      view.horizon_buffers.swap_remove(&mut view.gl, tile);
+    },
+    T::SetHorizonStart(distance) => {
+      view.horizon_start = distance;
+    },
     T::Atomic(updates) => {
       for up in updates {
         apply_client_to_view(view, up);
@@ -117,3 +215,23 @@ pub fn apply_client_to_view(view: &mut view::T, up: T) {
     },
   };
 }
+
+fn unload_mesh(view: &mut view::T, ids: terrain_mesh::Ids) {
+  let terrain_mesh::Ids { chunk_ids, grass_ids, water_ids } = ids;
+  for id in water_ids {
+    view.water_buffers.swap_remove(&mut view.gl, id);
+  }
+  // Removing grass needs to happen before the calls to [update_polygon_index], or we will remove the wrong things.
+  for id in grass_ids {
+    view.grass_buffers.swap_remove(&mut view.gl, id);
+  }
+  for (idx, swapped_idx) in view.terrain_buffers.swap_remove_range(&chunk_ids) {
+    for i in index::all() {
+      view.grass_buffers.update_polygon_index(
+        &mut view.gl,
+        swapped_idx.subindex(i),
+        idx.subindex(i),
+      );
+    }
+  }
+}
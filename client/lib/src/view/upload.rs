@@ -0,0 +1,177 @@
+//! Stream data into VRAM without waiting for the GPU to finish reading it.
+//! Data is written into a staging buffer split into segments, and copied from there into its destination
+//! on the GPU. Each segment is fenced once it's been used, and only written to again once the GPU is past
+//! the fence, so writing never has to wait for draws still in flight.
+
+use gl;
+use gl::types::*;
+use std;
+
+/// Number of staging segments. Uploads rotate through these.
+const SEGMENTS: usize = 3;
+
+struct Segment {
+  /// Signaled once the GPU is done copying out of this segment.
+  fence : Option<GLsync>,
+  /// Bytes of this segment used so far.
+  used  : usize,
+}
+
+#[allow(missing_docs)]
+pub struct T {
+  buffer       : GLuint,
+  segment_size : usize,
+  /// The whole staging buffer, if it's persistently mapped.
+  mapped       : Option<*mut u8>,
+  segments     : [Segment; SEGMENTS],
+  current      : usize,
+  /// Uploads that had to go straight to the destination because every segment was busy or too small.
+  pub direct   : u64,
+}
+
+/// Make a staging buffer. If `persistent`, it's mapped once up front (needs GL 4.4 or
+/// ARB_buffer_storage); otherwise each upload maps its part of the buffer without synchronizing.
+pub fn new(segment_size: usize, persistent: bool) -> T {
+  let size = (segment_size * SEGMENTS) as GLsizeiptr;
+  let mut buffer = 0;
+  let mapped =
+    unsafe {
+      gl::GenBuffers(1, &mut buffer);
+      gl::BindBuffer(gl::COPY_READ_BUFFER, buffer);
+      if persistent {
+        let flags = gl::MAP_WRITE_BIT | gl::MAP_PERSISTENT_BIT | gl::MAP_COHERENT_BIT;
+        gl::BufferStorage(gl::COPY_READ_BUFFER, size, std::ptr::null(), flags);
+        let ptr = gl::MapBufferRange(gl::COPY_READ_BUFFER, 0, size, flags);
+        if ptr.is_null() { None } else { Some(ptr as *mut u8) }
+      } else {
+        gl::BufferData(gl::COPY_READ_BUFFER, size, std::ptr::null(), gl::STREAM_DRAW);
+        None
+      }
+    };
+
+  let segment = || Segment { fence: None, used: 0 };
+  T {
+    buffer       : buffer,
+    segment_size : segment_size,
+    mapped       : mapped,
+    segments     : [segment(), segment(), segment()],
+    current      : 0,
+    direct       : 0,
+  }
+}
+
+impl T {
+  /// Whether the GPU is done with a segment. Never waits.
+  fn is_free(&mut self, i: usize) -> bool {
+    let done =
+      match self.segments[i].fence {
+        None => return true,
+        Some(fence) => unsafe {
+          match gl::ClientWaitSync(fence, 0, 0) {
+            gl::ALREADY_SIGNALED | gl::CONDITION_SATISFIED => true,
+            _ => false,
+          }
+        },
+      };
+    if done {
+      unsafe {
+        gl::DeleteSync(self.segments[i].fence.take().unwrap());
+      }
+      self.segments[i].used = 0;
+    }
+    done
+  }
+
+  /// Find room for `len` bytes of staging, returning its offset into the staging buffer.
+  fn allocate(&mut self, len: usize) -> Option<usize> {
+    if len > self.segment_size {
+      return None
+    }
+    if self.segments[self.current].fence.is_none() && self.segments[self.current].used + len <= self.segment_size {
+      let offset = self.current * self.segment_size + self.segments[self.current].used;
+      self.segments[self.current].used += len;
+      return Some(offset)
+    }
+
+    // This segment is full; fence it and move on to the next one, if the GPU is done with it.
+    self.fence();
+    let next = (self.current + 1) % SEGMENTS;
+    if !self.is_free(next) {
+      return None
+    }
+    self.current = next;
+    self.segments[next].used = len;
+    Some(next * self.segment_size)
+  }
+
+  /// Copy `data` into `dst` (a buffer object) at byte offset `dst_offset`.
+  pub fn upload(&mut self, dst: GLuint, dst_offset: usize, data: &[u8]) {
+    let offset =
+      match self.allocate(data.len()) {
+        Some(offset) => offset,
+        None => {
+          self.direct += 1;
+          unsafe {
+            gl::BindBuffer(gl::COPY_WRITE_BUFFER, dst);
+            gl::BufferSubData(
+              gl::COPY_WRITE_BUFFER,
+              dst_offset as GLintptr,
+              data.len() as GLsizeiptr,
+              data.as_ptr() as *const _,
+            );
+          }
+          return
+        },
+      };
+
+    unsafe {
+      gl::BindBuffer(gl::COPY_READ_BUFFER, self.buffer);
+      match self.mapped {
+        Some(ptr) => {
+          std::ptr::copy_nonoverlapping(data.as_ptr(), ptr.offset(offset as isize), data.len());
+        },
+        None => {
+          let flags = gl::MAP_WRITE_BIT | gl::MAP_UNSYNCHRONIZED_BIT | gl::MAP_INVALIDATE_RANGE_BIT;
+          let ptr = gl::MapBufferRange(gl::COPY_READ_BUFFER, offset as GLintptr, data.len() as GLsizeiptr, flags);
+          std::ptr::copy_nonoverlapping(data.as_ptr(), ptr as *mut u8, data.len());
+          gl::UnmapBuffer(gl::COPY_READ_BUFFER);
+        },
+      }
+      gl::BindBuffer(gl::COPY_WRITE_BUFFER, dst);
+      gl::CopyBufferSubData(
+        gl::COPY_READ_BUFFER,
+        gl::COPY_WRITE_BUFFER,
+        offset as GLintptr,
+        dst_offset as GLintptr,
+        data.len() as GLsizeiptr,
+      );
+    }
+  }
+
+  /// Fence the uploads made into the current segment, so it isn't written to again until they're done.
+  /// Call this after a batch of uploads, e.g. once per frame.
+  pub fn fence(&mut self) {
+    let segment = &mut self.segments[self.current];
+    if segment.fence.is_some() || segment.used == 0 {
+      return
+    }
+    segment.fence = Some(unsafe { gl::FenceSync(gl::SYNC_GPU_COMMANDS_COMPLETE, 0) });
+  }
+}
+
+impl Drop for T {
+  fn drop(&mut self) {
+    unsafe {
+      for segment in self.segments.iter_mut() {
+        if let Some(fence) = segment.fence.take() {
+          gl::DeleteSync(fence);
+        }
+      }
+      if self.mapped.is_some() {
+        gl::BindBuffer(gl::COPY_READ_BUFFER, self.buffer);
+        gl::UnmapBuffer(gl::COPY_READ_BUFFER);
+      }
+      gl::DeleteBuffers(1, &self.buffer);
+    }
+  }
+}
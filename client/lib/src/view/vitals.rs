@@ -0,0 +1,118 @@
+//! On-screen feedback about the player's health: the screen edges pulse red when they're hurt,
+//! stay red (with a heartbeat) while health is low, and the scene loses its color when they die.
+//! Everything here is driven by the `UpdateHealth` messages the server sends; nothing is asked for.
+
+/// Below this fraction of max health, the screen edges stay red and the heartbeat plays.
+pub const LOW_HEALTH: f32 = 0.25;
+
+/// How long the red pulse from taking damage takes to fade, in ns.
+const PULSE_NS: u64 = 600_000_000;
+
+/// Time between heartbeats at the low health threshold, in ns. They speed up from here as health drops.
+const SLOWEST_HEARTBEAT_NS: u64 = 1_000_000_000;
+
+/// Time between heartbeats at almost no health, in ns.
+const FASTEST_HEARTBEAT_NS: u64 = 500_000_000;
+
+#[allow(missing_docs)]
+pub struct T {
+  /// Health as a fraction of max health.
+  fraction          : f32,
+  /// When health last went down.
+  last_damage_ns    : Option<u64>,
+  last_heartbeat_ns : u64,
+}
+
+#[allow(missing_docs)]
+pub fn new() -> T {
+  T {
+    fraction          : 1.0,
+    last_damage_ns    : None,
+    last_heartbeat_ns : 0,
+  }
+}
+
+impl T {
+  /// Record a health update from the server, which arrived at `time_ns`.
+  pub fn set(&mut self, health: u32, max_health: u32, time_ns: u64) {
+    let fraction = health as f32 / max_health.max(1) as f32;
+    if fraction < self.fraction {
+      self.last_damage_ns = Some(time_ns);
+    }
+    self.fraction = fraction;
+  }
+
+  #[allow(missing_docs)]
+  pub fn is_dead(&self) -> bool {
+    self.fraction <= 0.0
+  }
+
+  fn is_low(&self) -> bool {
+    self.fraction < LOW_HEALTH
+  }
+
+  /// How strongly to tint the edges of the screen red, from 0 to 1.
+  pub fn vignette(&self, now_ns: u64) -> f32 {
+    let pulse =
+      match self.last_damage_ns {
+        None => 0.0,
+        Some(t) => 1.0 - f32::min(now_ns.saturating_sub(t) as f32 / PULSE_NS as f32, 1.0),
+      };
+    let persistent =
+      if self.is_dead() {
+        1.0
+      } else if self.is_low() {
+        0.3 + 0.4 * (1.0 - self.fraction / LOW_HEALTH)
+      } else {
+        0.0
+      };
+    f32::max(pulse, persistent)
+  }
+
+  /// How much color to leave in the scene, from 0 to 1.
+  pub fn saturation(&self) -> f32 {
+    if self.is_dead() { 0.0 } else { 1.0 }
+  }
+
+  /// Whether a heartbeat should be played now. Call this every frame.
+  pub fn heartbeat_due(&mut self, now_ns: u64) -> bool {
+    if self.is_dead() || !self.is_low() {
+      return false
+    }
+    let urgency = 1.0 - self.fraction / LOW_HEALTH;
+    let interval =
+      SLOWEST_HEARTBEAT_NS - ((SLOWEST_HEARTBEAT_NS - FASTEST_HEARTBEAT_NS) as f32 * urgency) as u64;
+    if now_ns.saturating_sub(self.last_heartbeat_ns) < interval {
+      return false
+    }
+    self.last_heartbeat_ns = now_ns;
+    true
+  }
+}
+
+#[test]
+fn damage_pulse_fades() {
+  let mut vitals = new();
+  vitals.set(100, 100, 0);
+  assert_eq!(vitals.vignette(0), 0.0);
+  vitals.set(80, 100, 1_000);
+  assert!(vitals.vignette(1_000) > 0.9);
+  assert_eq!(vitals.vignette(1_000 + PULSE_NS), 0.0);
+  // Healing doesn't pulse.
+  vitals.set(90, 100, 2 * PULSE_NS);
+  assert_eq!(vitals.vignette(2 * PULSE_NS), 0.0);
+}
+
+#[test]
+fn heartbeat_only_at_low_health() {
+  let mut vitals = new();
+  vitals.set(50, 100, 0);
+  assert!(!vitals.heartbeat_due(SLOWEST_HEARTBEAT_NS));
+  vitals.set(10, 100, 0);
+  assert!(vitals.heartbeat_due(SLOWEST_HEARTBEAT_NS));
+  assert!(!vitals.heartbeat_due(SLOWEST_HEARTBEAT_NS + 1));
+  assert!(vitals.vignette(10 * PULSE_NS) > 0.0);
+  vitals.set(0, 100, 0);
+  assert!(!vitals.heartbeat_due(10 * SLOWEST_HEARTBEAT_NS));
+  assert_eq!(vitals.saturation(), 0.0);
+}
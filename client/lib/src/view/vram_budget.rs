@@ -0,0 +1,204 @@
+//! Keep terrain meshes within the VRAM set aside for them.
+//! When a mesh doesn't fit, the meshes farthest from the camera are evicted to make room for it,
+//! as long as they're farther away than it is. Otherwise the new mesh isn't loaded.
+
+use cgmath::{Point3, MetricSpace};
+use std;
+
+use common::fnv_map;
+#[cfg(test)]
+use common::id_allocator;
+
+use terrain_mesh;
+
+use super::entity;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// VRAM used by some terrain, in each of the buffers terrain is spread across.
+pub struct Usage {
+  #[allow(missing_docs)]
+  pub terrain_chunks : usize,
+  #[allow(missing_docs)]
+  pub water_chunks   : usize,
+  #[allow(missing_docs)]
+  pub grass          : usize,
+}
+
+impl Usage {
+  /// The VRAM used by a mesh.
+  pub fn of(ids: &terrain_mesh::Ids) -> Usage {
+    Usage {
+      terrain_chunks : ids.chunk_ids.len(),
+      water_chunks   : ids.water_ids.len(),
+      grass          : ids.grass_ids.len(),
+    }
+  }
+
+  fn plus(&self, other: &Usage) -> Usage {
+    Usage {
+      terrain_chunks : self.terrain_chunks + other.terrain_chunks,
+      water_chunks   : self.water_chunks + other.water_chunks,
+      grass          : self.grass + other.grass,
+    }
+  }
+
+  fn minus(&self, other: &Usage) -> Usage {
+    Usage {
+      terrain_chunks : self.terrain_chunks - other.terrain_chunks,
+      water_chunks   : self.water_chunks - other.water_chunks,
+      grass          : self.grass - other.grass,
+    }
+  }
+
+  fn fits_in(&self, capacity: &Usage) -> bool {
+    self.terrain_chunks <= capacity.terrain_chunks &&
+    self.water_chunks <= capacity.water_chunks &&
+    self.grass <= capacity.grass
+  }
+}
+
+struct Resident {
+  ids    : terrain_mesh::Ids,
+  center : Point3<f32>,
+}
+
+#[allow(missing_docs)]
+pub struct T {
+  capacity       : Usage,
+  used           : Usage,
+  /// Loaded meshes, by their first id.
+  resident       : fnv_map::T<entity::id::Terrain, Resident>,
+  /// Number of meshes evicted to make room for nearer ones.
+  pub evictions  : u64,
+  /// Number of meshes that weren't loaded for lack of room.
+  pub rejections : u64,
+}
+
+#[allow(missing_docs)]
+pub fn new(capacity: Usage) -> T {
+  T {
+    capacity   : capacity,
+    used       : Usage { terrain_chunks: 0, water_chunks: 0, grass: 0 },
+    resident   : fnv_map::new(),
+    evictions  : 0,
+    rejections : 0,
+  }
+}
+
+fn key(ids: &terrain_mesh::Ids) -> Option<entity::id::Terrain> {
+  ids.chunk_ids.first().or(ids.water_ids.first()).cloned()
+}
+
+impl T {
+  /// Make room for a mesh centered at `center`. Returns the meshes that have to be unloaded first,
+  /// or None if the mesh can't be loaded.
+  pub fn make_room(
+    &mut self,
+    ids    : &terrain_mesh::Ids,
+    center : &Point3<f32>,
+    camera : &Point3<f32>,
+  ) -> Option<Vec<terrain_mesh::Ids>> {
+    let needed = Usage::of(ids);
+    let distance = camera.distance(*center);
+
+    let mut farther: Vec<(f32, entity::id::Terrain)> =
+      self.resident.iter()
+      .map(|(&key, resident)| (camera.distance(resident.center), key))
+      .filter(|&(d, _)| d > distance)
+      .collect();
+    farther.sort_by(|x, y| y.0.partial_cmp(&x.0).unwrap_or(std::cmp::Ordering::Equal));
+
+    let mut used = self.used;
+    let mut evict = Vec::new();
+    for (_, key) in farther {
+      if used.plus(&needed).fits_in(&self.capacity) {
+        break
+      }
+      used = used.minus(&Usage::of(&self.resident[&key].ids));
+      evict.push(key);
+    }
+
+    if !used.plus(&needed).fits_in(&self.capacity) {
+      self.rejections += 1;
+      return None
+    }
+
+    self.evictions += evict.len() as u64;
+    self.used = used;
+    Some(evict.into_iter().map(|key| self.resident.remove(&key).unwrap().ids).collect())
+  }
+
+  /// Note that a mesh has been loaded. Call `make_room` first.
+  pub fn insert(&mut self, ids: terrain_mesh::Ids, center: Point3<f32>) {
+    let key =
+      match key(&ids) {
+        None => return,
+        Some(key) => key,
+      };
+    self.used = self.used.plus(&Usage::of(&ids));
+    self.resident.insert(key, Resident { ids: ids, center: center });
+  }
+
+  /// Note that a mesh is being unloaded. Returns false if it wasn't loaded in the first place,
+  /// e.g. because it was evicted.
+  pub fn remove(&mut self, ids: &terrain_mesh::Ids) -> bool {
+    match key(ids).and_then(|key| self.resident.remove(&key)) {
+      None => false,
+      Some(resident) => {
+        self.used = self.used.minus(&Usage::of(&resident.ids));
+        true
+      },
+    }
+  }
+
+  /// The fraction of the fullest buffer that's in use.
+  pub fn used_fraction(&self) -> f32 {
+    let fraction = |used: usize, capacity: usize| used as f32 / capacity.max(1) as f32;
+    fraction(self.used.terrain_chunks, self.capacity.terrain_chunks)
+      .max(fraction(self.used.water_chunks, self.capacity.water_chunks))
+      .max(fraction(self.used.grass, self.capacity.grass))
+  }
+}
+
+#[cfg(test)]
+fn mesh(allocator: &mut id_allocator::T<entity::id::Terrain>, chunks: usize) -> terrain_mesh::Ids {
+  terrain_mesh::Ids {
+    chunk_ids : (0 .. chunks).map(|_| allocator.allocate()).collect(),
+    grass_ids : Vec::new(),
+    water_ids : Vec::new(),
+  }
+}
+
+#[test]
+fn evicts_farthest_first() {
+  let mut allocator = id_allocator::new();
+  let mut budget = new(Usage { terrain_chunks: 4, water_chunks: 0, grass: 0 });
+  let camera = Point3::new(0.0, 0.0, 0.0);
+  let near = mesh(&mut allocator, 2);
+  let far = mesh(&mut allocator, 2);
+  for &(ref ids, x) in &[(near.clone(), 10.0), (far.clone(), 100.0)] {
+    let center = Point3::new(x, 0.0, 0.0);
+    assert!(budget.make_room(ids, &center, &camera).unwrap().is_empty());
+    budget.insert(ids.clone(), center);
+  }
+
+  let new = mesh(&mut allocator, 1);
+  let evicted = budget.make_room(&new, &Point3::new(50.0, 0.0, 0.0), &camera).unwrap();
+  assert_eq!(evicted.len(), 1);
+  assert_eq!(evicted[0].chunk_ids, far.chunk_ids);
+  assert!(!budget.remove(&far));
+}
+
+#[test]
+fn rejects_meshes_farther_than_everything_loaded() {
+  let mut allocator = id_allocator::new();
+  let mut budget = new(Usage { terrain_chunks: 2, water_chunks: 0, grass: 0 });
+  let camera = Point3::new(0.0, 0.0, 0.0);
+  let near = mesh(&mut allocator, 2);
+  assert!(budget.make_room(&near, &Point3::new(10.0, 0.0, 0.0), &camera).is_some());
+  budget.insert(near, Point3::new(10.0, 0.0, 0.0));
+
+  let far = mesh(&mut allocator, 1);
+  assert!(budget.make_room(&far, &Point3::new(20.0, 0.0, 0.0), &camera).is_none());
+  assert_eq!(budget.rejections, 1);
+}
@@ -0,0 +1,105 @@
+//! Data structures for loading/unloading/maintaining water surfaces in VRAM.
+//! Water is kept apart from the terrain buffers so it can be drawn in its own translucent pass.
+
+use cgmath::Point3;
+use gl::types::*;
+use std;
+use yaglw::gl_context::GLContext;
+use yaglw::shader::Shader;
+use yaglw::vertex_buffer::{GLArray, GLBuffer, VertexAttribData};
+use yaglw::vertex_buffer::{DrawMode, GLType};
+
+use common::fnv_map;
+
+use terrain_mesh::Triangle;
+
+use super::backend;
+use super::entity;
+use super::terrain_buffers::{Chunk, CHUNK_LENGTH};
+
+const VERTICES_PER_TRIANGLE: usize = 3;
+const VERTICES_PER_CHUNK: usize = CHUNK_LENGTH * VERTICES_PER_TRIANGLE;
+
+/// Maximum number of bytes to be used in VRAM
+const BYTE_BUDGET: usize = 16_000_000;
+const CHUNK_COST: usize = VERTICES_PER_CHUNK * 3 * 4;
+/// Maximum number of chunks of water triangles in VRAM.
+pub const CHUNK_BUDGET: usize = BYTE_BUDGET / CHUNK_COST;
+
+/// Struct for loading/unloading/maintaining water data in VRAM.
+pub struct T<'a> {
+  id_to_index: fnv_map::T<entity::id::Terrain, usize>,
+  index_to_id: Vec<entity::id::Terrain>,
+
+  vertices: GLArray<'a, Point3<GLfloat>>,
+}
+
+#[allow(missing_docs)]
+pub fn new<'a, 'b>(
+  gl: &'b mut GLContext,
+  shader: &Shader<'a>,
+) -> T<'a> where
+  'a: 'b,
+{
+  let buffer = GLBuffer::new(gl, CHUNK_BUDGET * VERTICES_PER_CHUNK);
+  T {
+    id_to_index: fnv_map::new(),
+    index_to_id: Vec::new(),
+
+    vertices: GLArray::new(
+      gl,
+      shader,
+      &[
+        VertexAttribData { name: "position", size: 3, unit: GLType::Float, divisor: 0 },
+      ],
+      DrawMode::Triangles,
+      buffer,
+    ),
+  }
+}
+
+impl<'a> T<'a> {
+  /// Add a chunk of water triangles into VRAM.
+  pub fn push(
+    &mut self,
+    gl       : &mut GLContext,
+    id       : entity::id::Terrain,
+    vertices : &Chunk<Triangle<Point3<GLfloat>>>,
+  ) {
+    debug!("Insert water {:?}", id);
+
+    let vertices = unsafe { std::slice::from_raw_parts(vertices.as_ptr() as *const Point3<GLfloat>, VERTICES_PER_CHUNK) };
+
+    let previous = self.id_to_index.insert(id, self.index_to_id.len());
+    assert!(previous.is_none());
+    self.index_to_id.push(id);
+
+    self.vertices.buffer.byte_buffer.bind(gl);
+    let success = self.vertices.buffer.push(gl, vertices);
+    assert!(success);
+  }
+
+  /// Remove a chunk of water from VRAM, if it's there.
+  pub fn swap_remove(&mut self, gl: &mut GLContext, id: entity::id::Terrain) {
+    let idx =
+      match self.id_to_index.remove(&id) {
+        None => return,
+        Some(idx) => idx,
+      };
+    let swapped_id = self.index_to_id[self.index_to_id.len() - 1];
+    self.index_to_id.swap_remove(idx);
+    if id != swapped_id {
+      self.id_to_index.insert(swapped_id, idx);
+    }
+
+    self.vertices.buffer.byte_buffer.bind(gl);
+    self.vertices.buffer.swap_remove(gl, idx * VERTICES_PER_CHUNK, VERTICES_PER_CHUNK);
+  }
+
+  /// Draw all the water.
+  /// N.B. This does not bind any shaders.
+  pub fn draw(&self, gl: &mut GLContext, backend: &mut backend::T) {
+    self.vertices.bind(gl);
+    backend.draw(&backend::Draw::triangles("water", (self.index_to_id.len() * VERTICES_PER_CHUNK) as u32));
+  }
+}
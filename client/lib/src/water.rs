@@ -0,0 +1,61 @@
+//! Generate the water surface for a chunk of terrain.
+//! Water voxels aren't part of the terrain mesh. Instead, water is drawn as a flat sheet at sea level
+//! over every column that's flooded there; the terrain mesh hides whatever part of the sheet ends up underground.
+
+use cgmath::Point3;
+
+use common::voxel;
+
+use chunk;
+use lod;
+use terrain_mesh::{Triangle, tri};
+
+fn is_water(voxel: &voxel::T) -> bool {
+  match *voxel {
+    voxel::Volume(material) => material == voxel::Material::Water,
+    voxel::Surface(ref surface) => surface.corner == voxel::Material::Water,
+  }
+}
+
+/// The water triangles in a chunk. Only the chunks that contain the sea surface get any.
+pub fn surface(
+  voxels         : &voxel::tree::T,
+  chunk_position : &chunk::position::T,
+  lod            : lod::T,
+) -> Vec<Triangle<Point3<f32>>> {
+  let position = chunk_position.as_pnt();
+  let low_y = position.y << chunk::LG_WIDTH;
+  let high_y = (position.y + 1) << chunk::LG_WIDTH;
+  if voxel::SEA_LEVEL <= low_y || voxel::SEA_LEVEL > high_y {
+    return Vec::new()
+  }
+
+  let lg_sample_size = lod.lg_sample_size();
+  let edge_samples = lod.edge_samples() as i32;
+  let lg_edge_samples = lod.lg_edge_samples();
+  // The layer of voxels just under the surface.
+  let y = (voxel::SEA_LEVEL - 1) >> lg_sample_size;
+  let sea_level = voxel::SEA_LEVEL as f32;
+  let size = (lg_sample_size as f32).exp2();
+
+  let mut triangles = Vec::new();
+  for dx in 0 .. edge_samples {
+  for dz in 0 .. edge_samples {
+    let x = (position.x << lg_edge_samples) + dx;
+    let z = (position.z << lg_edge_samples) + dz;
+    let flooded =
+      voxels.get(&voxel::bounds::new(x, y, z, lg_sample_size))
+      .map(is_water)
+      .unwrap_or(false);
+    if !flooded {
+      continue
+    }
+
+    let (x0, z0) = (x as f32 * size, z as f32 * size);
+    let (x1, z1) = (x0 + size, z0 + size);
+    // Counterclockwise when seen from above.
+    triangles.push(tri(Point3::new(x0, sea_level, z0), Point3::new(x0, sea_level, z1), Point3::new(x1, sea_level, z1)));
+    triangles.push(tri(Point3::new(x0, sea_level, z0), Point3::new(x1, sea_level, z1), Point3::new(x1, sea_level, z0)));
+  }}
+  triangles
+}
@@ -0,0 +1,12 @@
+// Newly loaded terrain rises into place instead of popping in. Each vertex carries when its chunk was
+// loaded, on the same clock as `terrain_clock_ms`.
+uniform float terrain_clock_ms;
+
+const float appear_ms = 300;
+// How far below its place terrain starts rising from.
+const float appear_depth = 8;
+
+vec3 appear(vec3 position, float loaded_at) {
+  float t = clamp((terrain_clock_ms - loaded_at) / appear_ms, 0, 1);
+  return position - vec3(0, appear_depth * (1 - t) * (1 - t), 0);
+}
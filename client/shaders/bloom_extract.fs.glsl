@@ -0,0 +1,15 @@
+#version 330 core
+
+// The HDR scene.
+uniform sampler2D scene;
+// Brightness past which things start to glow.
+uniform float threshold;
+
+in vec2 uv;
+
+out vec4 frag_color;
+
+void main() {
+  vec3 c = texture(scene, uv).rgb;
+  frag_color = vec4(max(c - vec3(threshold), vec3(0)), 1);
+}
@@ -0,0 +1,21 @@
+#version 330 core
+
+uniform sampler2D source;
+// One texel along the direction to blur in.
+uniform vec2 step;
+
+in vec2 uv;
+
+out vec4 frag_color;
+
+// A 9-tap gaussian, folded into 5 weights.
+const float weights[5] = float[](0.227027, 0.1945946, 0.1216216, 0.054054, 0.016216);
+
+void main() {
+  vec3 c = texture(source, uv).rgb * weights[0];
+  for (int i = 1; i < 5; ++i) {
+    c += texture(source, uv + i * step).rgb * weights[i];
+    c += texture(source, uv - i * step).rgb * weights[i];
+  }
+  frag_color = vec4(c, 1);
+}
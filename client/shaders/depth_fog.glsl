@@ -1,7 +1,12 @@
+// Distance over which the fog thickens. Much shorter underwater.
+uniform float fog_distance;
+// Multiplies the fog color, e.g. to make it murky underwater.
+uniform vec3 fog_tint;
+
 float fog_density(float distance) {
-  return 1 - exp(-distance / 768);
+  return 1 - exp(-distance / fog_distance);
 }
 
 vec4 apply_fog(vec4 base_color, vec4 fog_color, float distance) {
-  return mix(base_color, fog_color, fog_density(distance));
+  return mix(base_color, fog_color * vec4(fog_tint, 1), fog_density(distance));
 }
@@ -0,0 +1,16 @@
+// Cover the screen with a triangle strip of 4 vertices, passing on texture coordinates.
+
+out vec2 uv;
+
+void main() {
+  if (gl_VertexID == 0) {
+    gl_Position = vec4(1, -1, 0, 1);
+  } else if (gl_VertexID == 1) {
+    gl_Position = vec4(1, 1, 0, 1);
+  } else if (gl_VertexID == 2) {
+    gl_Position = vec4(-1, -1, 0, 1);
+  } else if (gl_VertexID == 3) {
+    gl_Position = vec4(-1, 1, 0, 1);
+  }
+  uv = (gl_Position.xy + 1) / 2;
+}
@@ -20,14 +20,34 @@ in float vs_tex_id;
 
 out vec4 frag_color;
 
+// Ids below this are grass variants in `texture_in`. The rest are flowers. See decoration.rs.
+const int GRASS_VARIANTS = 9;
+
+// A blossom on top of a thin stem.
+vec4 flower(int color, vec2 p) {
+  const vec3 colors[4] = vec3[4](vec3(1, 0.85, 0.1), vec3(0.9, 0.15, 0.1), vec3(0.95, 0.95, 0.9), vec3(0.6, 0.25, 0.8));
+  if (length((p - vec2(0.5, 0.8)) * vec2(1, 1.3)) < 0.12) {
+    return vec4(colors[color % 4], 1);
+  }
+  if (abs(p.x - 0.5) < 0.025 && p.y < 0.8) {
+    return vec4(0.2, 0.5, 0.1, 1);
+  }
+  return vec4(0);
+}
+
 void main() {
   int tex_id = int(round(vs_tex_id));
-  int y = tex_id / 3;
-  int x = tex_id % 3;
-  vec2 tex_position =
-    (vs_texture_position + y*vec2(0, 1) + x*vec2(1, 0)) / 3
-    - vec2(0.0, 0.05);
-  vec4 c = texture(texture_in, tex_position);
+  vec4 c;
+  if (tex_id >= GRASS_VARIANTS) {
+    c = flower(tex_id - GRASS_VARIANTS, vs_texture_position);
+  } else {
+    int y = tex_id / 3;
+    int x = tex_id % 3;
+    vec2 tex_position =
+      (vs_texture_position + y*vec2(0, 1) + x*vec2(1, 0)) / 3
+      - vec2(0.0, 0.05);
+    c = texture(texture_in, tex_position);
+  }
   if (c.a < alpha_threshold) {
     discard;
   }
@@ -37,6 +57,7 @@ void main() {
     world_fragment(
       sun.direction,
       sun.intensity,
+      1.0,
       normalize(world_position - eye_position),
       ambient_light,
       c,
@@ -8,9 +8,13 @@ uniform float far_clip;
 uniform mat4 projection_matrix;
 uniform vec3 eye_position;
 uniform float time_ms;
+// Horizontal wind, which billboards lean with. Its length is how hard it blows.
+uniform vec2 wind;
+// Billboards shrink away as they approach this distance from the eye.
+uniform float fade_distance;
 
-uniform samplerBuffer positions;
-uniform samplerBuffer normals;
+// Three texels per terrain vertex: (position, material id), (normal, atlas tile), (atlas uv, load time, unused).
+uniform samplerBuffer vertices;
 
 in vec2 texture_position;
 in vec3 vertex_position;
@@ -35,12 +39,12 @@ mat3 between(vec3 v1, vec3 v2) {
   return mat3(1) + skew + skew*skew*(1-c)/(s*s);
 }
 
-vec3 vec3Fetch(samplerBuffer vs, int float_idx) {
-  vec3 r;
-  r.x = texelFetch(vs, float_idx + 0).r;
-  r.y = texelFetch(vs, float_idx + 1).r;
-  r.z = texelFetch(vs, float_idx + 2).r;
-  return r;
+vec3 position_of(int vertex_id) {
+  return texelFetch(vertices, vertex_id * 3).xyz;
+}
+
+vec3 normal_of(int vertex_id) {
+  return texelFetch(vertices, vertex_id * 3 + 1).xyz;
 }
 
 // compute a model-space shear to transform (0,1,0) to a desired vector.
@@ -68,29 +72,28 @@ void main() {
   vs_tex_id = float(tex_id);
 
   // Put the grass tuft in the middle of the underlying terrain polygon.
-  int position_id = polygon_id * 3 * 3;
-  mat3 vertices =
+  int vertex_id = polygon_id * 3;
+  mat3 corners =
     mat3(
-      vec3Fetch(positions, position_id),
-      vec3Fetch(positions, position_id + 3),
-      vec3Fetch(positions, position_id + 6)
+      position_of(vertex_id),
+      position_of(vertex_id + 1),
+      position_of(vertex_id + 2)
     );
   vec3 side_length =
     vec3(
-      length(vertices[0] - vertices[1]),
-      length(vertices[1] - vertices[2]),
-      length(vertices[2] - vertices[0])
+      length(corners[0] - corners[1]),
+      length(corners[1] - corners[2]),
+      length(corners[2] - corners[0])
     );
-  vec3 root = vertices * vec3(1.0/3.0);
+  vec3 root = corners * vec3(1.0/3.0);
 
   // Find the normal for the grass by barycentrically interpolating the
   // vertex normals to the root.
-  int normal_id = polygon_id * 3 * 3;
   mat3 vertex_normals =
     mat3(
-      vec3Fetch(normals, normal_id),
-      vec3Fetch(normals, normal_id + 3),
-      vec3Fetch(normals, normal_id + 6)
+      normal_of(vertex_id),
+      normal_of(vertex_id + 1),
+      normal_of(vertex_id + 2)
     );
   vec3 normal = vertex_normals * vec3(1.0/3.0);
 
@@ -113,6 +116,9 @@ void main() {
         sin(altitude),
         cos(altitude) * sin(azimuth)
       );
+    // Lean with the wind, in gusts that roll across the ground.
+    float gust = (sin(time_ms / 700 - dot(root.xz, wind) / 8) + 1) / 2;
+    v = normalize(v + vec3(wind.x, 0, wind.y) * (0.5 + 0.5 * gust));
     noise_shear = shearTo(v);
   }
 
@@ -123,6 +129,14 @@ void main() {
     1.5;
   grassiness = clamp(grassiness, 0, 1);
 
+  float fade = 1 - smoothstep(0.75 * fade_distance, fade_distance, distance(root, eye_position));
+  if (fade <= 0) {
+    // Put the whole billboard outside the clip volume.
+    gl_Position = vec4(2, 2, 2, 1);
+    return;
+  }
+  grassiness *= fade;
+
   mat4 scale = mat4(1.0);
   float max_side = max(max(side_length[0], side_length[1]), side_length[2]);
   float min_side = min(min(side_length[0], side_length[1]), side_length[2]);
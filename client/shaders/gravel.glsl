@@ -0,0 +1,10 @@
+vec3 gravel(vec3 world_position) {
+  // Coarse noise for the pebbles, fine noise for grain within them.
+  float pebble = cnoise(8 * world_position);
+  float grain = cnoise(32 * world_position);
+
+  float noise = (sign(pebble) * pow(abs(pebble), 0.3) + 0.3 * grain) / 1.3;
+  noise = (noise + 1) / 2;
+
+  return mix(vec3(0.35, 0.33, 0.3), vec3(0.6, 0.57, 0.52), noise);
+}
@@ -0,0 +1,34 @@
+#version 330 core
+
+uniform struct Sun {
+  vec3 direction;
+  vec3 intensity;
+} sun;
+
+uniform vec3 ambient_light;
+uniform vec3 eye_position;
+// The polygonal terrain reaches this far from the eye along x and z; the horizon is only drawn beyond it.
+// By then the fog is thick enough to hide the seam.
+uniform float horizon_start;
+
+in vec3 world_position;
+in vec4 color;
+
+out vec4 frag_color;
+
+include(depth_fog.glsl)
+
+void main() {
+  vec2 offset = abs(world_position.xz - eye_position.xz);
+  if (max(offset.x, offset.y) < horizon_start) {
+    discard;
+  }
+
+  // The horizon is faceted anyway, so flat shading from screen-space derivatives is enough.
+  vec3 normal = normalize(cross(dFdx(world_position), dFdy(world_position)));
+  float diffuse = clamp(dot(normal, sun.direction), 0, 1);
+  vec4 lit = vec4((ambient_light + diffuse * sun.intensity) * color.rgb, 1);
+
+  vec4 fog_color = vec4(sun.intensity, 1);
+  frag_color = apply_fog(lit, fog_color, gl_FragCoord.z / gl_FragCoord.w);
+}
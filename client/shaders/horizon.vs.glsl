@@ -0,0 +1,35 @@
+#version 330 core
+
+include(adjust_depth_precision.glsl)
+
+uniform mat4 projection_matrix;
+uniform float near_clip;
+uniform float far_clip;
+
+// One texel per column: (height, r, g, b). Each tile has (tile_columns + 1)^2 of them, row by row.
+uniform samplerBuffer samples;
+uniform int tile_columns;
+uniform float column_spacing;
+
+// The world-space x and z of this instance's tile.
+in vec2 origin;
+
+out vec3 world_position;
+out vec4 color;
+
+// The corners of the two triangles in each square of the grid, counterclockwise seen from above.
+const ivec2 corners[6] = ivec2[6](
+  ivec2(0, 0), ivec2(0, 1), ivec2(1, 0),
+  ivec2(1, 0), ivec2(0, 1), ivec2(1, 1)
+);
+
+void main() {
+  int square = gl_VertexID / 6;
+  ivec2 column = ivec2(square / tile_columns, square % tile_columns) + corners[gl_VertexID % 6];
+  int samples_per_tile = (tile_columns + 1) * (tile_columns + 1);
+  vec4 sample = texelFetch(samples, gl_InstanceID * samples_per_tile + column.x * (tile_columns + 1) + column.y);
+
+  world_position = vec3(origin.x + column.x * column_spacing, sample.x, origin.y + column.y * column_spacing);
+  color = vec4(sample.yzw, 1);
+  gl_Position = adjust_depth_precision(near_clip, far_clip, projection_matrix * vec4(world_position, 1.0));
+}
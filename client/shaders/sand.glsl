@@ -0,0 +1,17 @@
+vec3 sand(vec3 world_position) {
+  float total_amp = 0.0;
+  float noise = 0.0;
+
+  for (int i = 0; i < 6; ++i) {
+    float freq = 4 << i;
+    float amp = 1.0 / (1 << i);
+    float c = cnoise(freq * world_position);
+    noise += amp * c;
+    total_amp += amp;
+  }
+
+  noise /= total_amp;
+  noise = (noise + 1) / 2;
+
+  return mix(vec3(0.76, 0.66, 0.44), vec3(0.9, 0.82, 0.6), noise);
+}
@@ -0,0 +1,14 @@
+#version 330 core
+
+include(appear.glsl)
+
+uniform mat4 light_matrix;
+
+uniform samplerBuffer vertices;
+
+void main() {
+  vec3 world_position = texelFetch(vertices, gl_VertexID * 3).xyz;
+  world_position = appear(world_position, texelFetch(vertices, gl_VertexID * 3 + 2).z);
+
+  gl_Position = light_matrix * vec4(world_position, 1.0);
+}
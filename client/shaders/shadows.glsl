@@ -0,0 +1,50 @@
+// Sample the cascaded sun shadow maps. Needs `eye_position` declared.
+
+uniform mat4 shadow_matrices[3];
+uniform float cascade_ends[3];
+uniform vec3 eye_forward;
+
+uniform sampler2DShadow shadow_map_0;
+uniform sampler2DShadow shadow_map_1;
+uniform sampler2DShadow shadow_map_2;
+
+float shadow_lookup(int cascade, vec3 p) {
+  // Samplers can't be indexed dynamically in GLSL 3.30.
+  if (cascade == 0) {
+    return texture(shadow_map_0, p);
+  } else if (cascade == 1) {
+    return texture(shadow_map_1, p);
+  } else {
+    return texture(shadow_map_2, p);
+  }
+}
+
+// How much of the sun reaches a point, in [0, 1].
+float sunlight(vec3 world_position, vec3 normal) {
+  float depth = dot(world_position - eye_position, eye_forward);
+  int cascade = 0;
+  while (cascade < 3 && depth > cascade_ends[cascade]) {
+    cascade += 1;
+  }
+  if (cascade >= 3) {
+    return 1;
+  }
+
+  // Push the lookup out along the normal, further in the coarser cascades, to avoid shadow acne.
+  float offset = 0.05 * float(1 << (2 * cascade));
+  vec4 p = shadow_matrices[cascade] * vec4(world_position + offset * normal, 1);
+  p.xyz = p.xyz / p.w * 0.5 + 0.5;
+  if (any(lessThan(p.xy, vec2(0))) || any(greaterThan(p.xy, vec2(1)))) {
+    return 1;
+  }
+
+  // 2x2 percentage-closer filtering, on top of the hardware's bilinear comparison.
+  vec2 texel = 1.0 / vec2(textureSize(shadow_map_0, 0));
+  float lit = 0;
+  for (int x = 0; x < 2; ++x) {
+  for (int y = 0; y < 2; ++y) {
+    vec2 d = (vec2(x, y) - 0.5) * texel;
+    lit += shadow_lookup(cascade, vec3(p.xy + d, p.z));
+  }}
+  return lit / 4;
+}
@@ -11,6 +11,7 @@ uniform struct Sun {
 } sun;
 
 const float sun_angular_radius = 3.14/32;
+const float sun_glow = 2;
 
 uniform mat4 projection_matrix;
 uniform vec3 eye_position;
@@ -75,8 +76,12 @@ void main() {
   }
 
   float sunniness = exp(64 * (dot(sun.direction, direction) - cos(sun_angular_radius)));
-  vec3 infinity_color = mix(sun.intensity, vec3(1), sunniness);
+  vec3 infinity_color = mix(sun.intensity, vec3(1), min(sunniness, 1));
   c += alpha * infinity_color;
+  // The sun itself is brighter than anything else. This only shows when rendering in HDR, where it blooms.
+  c += alpha * max(sunniness - 1, 0) * sun_glow * sun.intensity;
+  // The sky is infinitely far away, so it's the same color as the thickest fog.
+  c *= fog_tint;
 
-  frag_color = min(vec4(c, 1), vec4(1));
+  frag_color = vec4(c, 1);
 }
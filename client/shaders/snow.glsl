@@ -0,0 +1,4 @@
+vec3 snow(vec3 world_position) {
+  float noise = (cnoise(world_position / 4) + 1) / 2;
+  return mix(vec3(0.85, 0.88, 0.92), vec3(0.97, 0.97, 1.0), noise);
+}
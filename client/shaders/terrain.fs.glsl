@@ -8,15 +8,25 @@ uniform struct Sun {
 uniform vec3 ambient_light;
 uniform vec3 eye_position;
 
-uniform samplerBuffer positions;
-
 in vec3 world_position;
 in vec3 vs_normal;
 flat in int material;
+in vec2 vs_uv;
+// Texture atlas tile, or -1 to shade procedurally.
+flat in int tile;
+
+uniform sampler2D atlas;
+uniform int atlas_tiles;
+// Project the atlas along all three axes from the world position and blend by the normal,
+// instead of using the mesher's per-polygon UVs, which stretch on steep slopes.
+uniform bool triplanar;
+// World-space width of one repeat of each tile. At least `atlas_tiles` long.
+uniform float tile_world_size[16];
 
 out vec4 frag_color;
 
 include(depth_fog.glsl)
+include(shadows.glsl)
 include(world_fragment.glsl)
 include(noise.glsl)
 include(grass.glsl)
@@ -24,6 +34,9 @@ include(dirt.glsl)
 include(bark.glsl)
 include(leaves.glsl)
 include(stone.glsl)
+include(sand.glsl)
+include(snow.glsl)
+include(gravel.glsl)
 
 // http://www.neilmendoza.com/glsl-rotation-about-an-arbitrary-axis/
 mat3 rotationMatrix(vec3 axis, float angle)
@@ -52,6 +65,29 @@ vec3 bump_map(float shallowness, float frequency, vec3 v) {
   return rotationMatrix(axis, acos(c) / shallowness) * v;
 }
 
+// Sample a repeating tile from the atlas. The repeat is done here rather than by the sampler, so
+// gradients come from the unwrapped coordinates to keep mipmapping from breaking at the seams.
+vec4 atlas_color(int tile, vec2 uv) {
+  vec2 inset = vec2(0.5) / textureSize(atlas, 0);
+  vec2 within = clamp(fract(uv), inset * atlas_tiles, 1 - inset * atlas_tiles);
+  vec2 atlas_uv = vec2((tile + within.x) / atlas_tiles, within.y);
+  vec2 scale = vec2(1.0 / atlas_tiles, 1);
+  return textureGrad(atlas, atlas_uv, dFdx(uv) * scale, dFdy(uv) * scale);
+}
+
+// Grass tiles are 0 (top) and 1 (side): slopes get the top tile from above and the side tile from the sides.
+vec4 triplanar_color(int tile, vec3 position, vec3 normal) {
+  int top = tile == 1 ? 0 : tile;
+  int side = tile == 0 ? 1 : tile;
+  vec3 weights = pow(abs(normal), vec3(4));
+  weights /= weights.x + weights.y + weights.z;
+  vec3 p = position / tile_world_size[tile];
+  return
+    weights.x * atlas_color(side, p.zy) +
+    weights.y * atlas_color(top, p.xz) +
+    weights.z * atlas_color(side, p.xy);
+}
+
 void main() {
   vec4 base_color;
 
@@ -76,16 +112,75 @@ void main() {
   } else if (material == 5) {
     base_color = vec4(0, 0, 0, 1);
     shininess = 40;
+  } else if (material == 6) {
+    base_color = vec4(sand(world_position), 1);
+  } else if (material == 7) {
+    base_color = vec4(snow(world_position), 1);
+    shininess = 200;
+  } else if (material == 8) {
+    base_color = vec4(gravel(world_position), 1);
+    normal = bump_map(8, 1, normal);
+  } else if (material == 9) {
+    // wire
+    base_color = vec4(0.3, 0.05, 0.05, 1);
+  } else if (material == 10) {
+    // powered wire
+    base_color = vec4(0.9, 0.1, 0.1, 1);
+    shininess = 40;
+  } else if (material == 11) {
+    // lever
+    base_color = vec4(0.45, 0.3, 0.15, 1);
+  } else if (material == 12) {
+    // pulled lever
+    base_color = vec4(0.75, 0.55, 0.2, 1);
+  } else if (material == 13) {
+    // pressure plate
+    base_color = vec4(0.5, 0.5, 0.45, 1);
+  } else if (material == 14) {
+    // pressed pressure plate
+    base_color = vec4(0.65, 0.65, 0.55, 1);
+  } else if (material == 15) {
+    // lamp
+    base_color = vec4(0.35, 0.3, 0.15, 1);
+  } else if (material == 16) {
+    // lit lamp
+    base_color = vec4(1.0, 0.9, 0.5, 1);
+    shininess = 10;
+  } else if (material == 17 || material == 18) {
+    // piston
+    base_color = vec4(stone(world_position) * vec3(1.1, 1.0, 0.8), 1);
+  } else if (material == 19) {
+    // piston head
+    base_color = vec4(0.55, 0.4, 0.25, 1);
+  } else if (material == 21) {
+    // coal
+    base_color = vec4(stone(world_position) * 0.3, 1);
+    normal = bump_map(4, 2, normal);
+  } else if (material == 22) {
+    // iron
+    base_color = vec4(stone(world_position) * vec3(1.3, 0.9, 0.7), 1);
+    normal = bump_map(4, 2, normal);
+  } else if (material == 23) {
+    // crystal
+    base_color = vec4(0.6, 0.7, 1.0, 1);
+    shininess = 20;
   } else {
     base_color = vec4(0.5, 0, 0.5, 0.5);
     shininess = 1;
   }
 
+  if (tile >= 0 && triplanar) {
+    base_color = triplanar_color(tile, world_position, vs_normal);
+  } else if (tile >= 0) {
+    base_color = atlas_color(tile, vs_uv);
+  }
+
   vec4 fog_color = vec4(sun.intensity, 1);
   frag_color =
     world_fragment(
       sun.direction,
       sun.intensity,
+      sunlight(world_position, vs_normal),
       normalize(world_position - eye_position),
       ambient_light,
       base_color,
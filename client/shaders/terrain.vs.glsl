@@ -1,34 +1,34 @@
 #version 330 core
 
 include(adjust_depth_precision.glsl)
+include(appear.glsl)
 
 uniform float near_clip;
 uniform float far_clip;
 uniform mat4 projection_matrix;
+// Only used while drawing reflections, to cut off what's below the water.
+uniform vec4 clip_plane;
 
-uniform samplerBuffer positions;
-uniform samplerBuffer normals;
-uniform isamplerBuffer materials;
+// Three texels per vertex: (position, material id), (normal, atlas tile), (atlas uv, load time, unused).
+uniform samplerBuffer vertices;
 
 out vec3 world_position;
 out vec3 vs_normal;
 flat out int material;
+out vec2 vs_uv;
+flat out int tile;
 
 void main() {
-  // Mutiply by 3 because there are 3 components for each normal vector.
-  int position_id = gl_VertexID * 3;
-  world_position.x = texelFetch(positions, position_id + 0).r;
-  world_position.y = texelFetch(positions, position_id + 1).r;
-  world_position.z = texelFetch(positions, position_id + 2).r;
-
-  int normal_id = position_id;
-  vs_normal.x = texelFetch(normals, normal_id + 0).r;
-  vs_normal.y = texelFetch(normals, normal_id + 1).r;
-  vs_normal.z = texelFetch(normals, normal_id + 2).r;
-
-  int face_id = gl_VertexID / 3;
-
-  material = texelFetch(materials, face_id).r;
-
+  vec4 position_material = texelFetch(vertices, gl_VertexID * 3);
+  world_position = position_material.xyz;
+  material = int(round(position_material.w));
+  vec4 normal_tile = texelFetch(vertices, gl_VertexID * 3 + 1);
+  vs_normal = normal_tile.xyz;
+  tile = int(round(normal_tile.w));
+  vec4 uv_loaded = texelFetch(vertices, gl_VertexID * 3 + 2);
+  vs_uv = uv_loaded.xy;
+  world_position = appear(world_position, uv_loaded.z);
+
+  gl_ClipDistance[0] = dot(vec4(world_position, 1), clip_plane);
   gl_Position = adjust_depth_precision(near_clip, far_clip, projection_matrix * vec4(world_position, 1.0));
 }
@@ -0,0 +1,42 @@
+#version 330 core
+
+// The HDR scene.
+uniform sampler2D scene;
+// The blurred bright parts of the scene.
+uniform sampler2D bloom;
+uniform float bloom_strength;
+uniform float exposure;
+// How much color to leave in the scene, from 0 (grayscale) to 1.
+uniform float saturation;
+// Whether the camera is underwater, and if so what to tint the screen.
+uniform bool underwater;
+uniform vec3 underwater_tint;
+uniform float time_ms;
+
+in vec2 uv;
+
+out vec4 frag_color;
+
+// Narkowicz's fit of the ACES filmic curve.
+vec3 filmic(vec3 x) {
+  return clamp((x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14), 0, 1);
+}
+
+// Ripple the image a little, as if it's refracted through moving water.
+vec2 wobble(vec2 uv) {
+  float t = time_ms / 1000;
+  return uv + 0.004 * vec2(sin(uv.y * 40 + t * 2), cos(uv.x * 30 + t * 1.7));
+}
+
+void main() {
+  vec2 st = uv;
+  if (underwater) {
+    st = clamp(wobble(uv), 0, 1);
+  }
+  vec3 c = texture(scene, st).rgb + bloom_strength * texture(bloom, st).rgb;
+  if (underwater) {
+    c *= underwater_tint;
+  }
+  c = mix(vec3(dot(c, vec3(0.2126, 0.7152, 0.0722))), c, saturation);
+  frag_color = vec4(filmic(c * exposure), 1);
+}
@@ -0,0 +1,14 @@
+#version 330 core
+
+// How red to make the edges of the screen, from 0 to 1.
+uniform float strength;
+
+in vec2 uv;
+
+out vec4 frag_color;
+
+void main() {
+  // 0 at the center of the screen, 1 in the corners.
+  float edge = length(uv - 0.5) * sqrt(2);
+  frag_color = vec4(0.6, 0, 0, strength * smoothstep(0.35, 1, edge));
+}
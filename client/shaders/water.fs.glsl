@@ -0,0 +1,68 @@
+#version 330 core
+
+uniform struct Sun {
+  vec3 direction;
+  vec3 intensity;
+} sun;
+
+uniform vec3 ambient_light;
+uniform vec3 eye_position;
+uniform float time_ms;
+uniform vec2 window_size;
+// Whether `reflection` holds the scene mirrored about the water surface.
+uniform bool reflections;
+uniform sampler2D reflection;
+
+in vec3 world_position;
+
+out vec4 frag_color;
+
+include(depth_fog.glsl)
+include(noise.glsl)
+
+float wave_height(vec2 p, float t) {
+  return
+    0.6 * cnoise(vec3(p / 6, t * 0.4)) +
+    0.3 * cnoise(vec3(p / 2, t * 0.9));
+}
+
+// Animated ripples, as a perturbation of the flat surface normal.
+vec3 water_normal() {
+  float t = time_ms / 1000;
+  vec2 p = world_position.xz;
+  float d = 0.1;
+  float h0 = wave_height(p, t);
+  float hx = wave_height(p + vec2(d, 0), t);
+  float hz = wave_height(p + vec2(0, d), t);
+  float strength = 0.15;
+  return normalize(vec3(strength * (h0 - hx) / d, 1, strength * (h0 - hz) / d));
+}
+
+void main() {
+  vec3 normal = water_normal();
+  vec3 view_direction = normalize(world_position - eye_position);
+  // Seen from below, the surface faces the other way.
+  if (view_direction.y > 0) {
+    normal = -normal;
+  }
+
+  // Schlick's approximation, with the reflectance of water head-on.
+  float cos_theta = clamp(dot(-view_direction, normal), 0, 1);
+  float fresnel = 0.02 + 0.98 * pow(1 - cos_theta, 5);
+
+  vec3 sky = sun.intensity;
+  if (reflections && view_direction.y < 0) {
+    // Ripples distort the reflection a little.
+    vec2 uv = gl_FragCoord.xy / window_size + normal.xz * 0.02;
+    sky = texture(reflection, clamp(uv, 0, 1)).rgb;
+  }
+  float diffuse = clamp(dot(normal, sun.direction), 0, 1);
+  vec3 body = vec3(0.05, 0.2, 0.3) * (ambient_light + diffuse * sun.intensity);
+
+  vec3 reflected = reflect(view_direction, normal);
+  float specular = pow(clamp(dot(reflected, sun.direction), 0, 1), 200);
+
+  vec4 color = vec4(mix(body, sky, fresnel) + specular * sun.intensity, mix(0.6, 1, fresnel));
+  vec4 fog_color = vec4(sun.intensity, 1);
+  frag_color = apply_fog(color, fog_color, gl_FragCoord.z / gl_FragCoord.w);
+}
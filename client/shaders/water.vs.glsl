@@ -0,0 +1,16 @@
+#version 330 core
+
+include(adjust_depth_precision.glsl)
+
+uniform float near_clip;
+uniform float far_clip;
+uniform mat4 projection_matrix;
+
+in vec3 position;
+
+out vec3 world_position;
+
+void main() {
+  world_position = position;
+  gl_Position = adjust_depth_precision(near_clip, far_clip, projection_matrix * vec4(position, 1.0));
+}
@@ -1,6 +1,7 @@
 vec4 world_fragment(
   vec3 light_direction,
   vec3 intensity,
+  float shadow,
   vec3 view_direction,
   vec3 ambient_light,
   vec4 material_color,
@@ -17,6 +18,9 @@ vec4 world_fragment(
   specular = clamp(specular, 0, 1);
   specular = pow(specular, shininess);
 
+  diffuse *= shadow;
+  specular *= shadow;
+
   vec4 with_light = diffuse*vec4(intensity, 1)*material_color + specular*vec4(1) + vec4(ambient_light, 1)*material_color;
   return apply_fog(with_light, fog_color, frag_distance);
 }
@@ -10,11 +10,11 @@ pub mod position {
   use cgmath::{Point3, Vector3};
   use std::ops::Add;
 
-  use common::voxel;
+  use voxel;
 
   use chunk;
 
-  #[derive(Debug, PartialEq, Eq, Hash, Copy, Clone)]
+  #[derive(Debug, PartialEq, Eq, Hash, Copy, Clone, Serialize, Deserialize)]
   /// Position of blocks on an "infinite" regular grid.
   /// The position is implicitly in units of chunk::WIDTH.
   pub struct T(Point3<i32>);
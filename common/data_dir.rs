@@ -0,0 +1,126 @@
+//! Where Playform keeps the files it writes (settings, saved worlds, replays, screenshots, logs and
+//! caches): the OS's usual places for application data, rather than the working directory, so
+//! packaged builds behave wherever they're run from.
+//! Set `PLAYFORM_DATA_DIR` (which the binaries' `--data-dir` flag does) to keep everything under one
+//! directory instead.
+
+use std;
+use std::path::PathBuf;
+
+/// Environment variable naming a directory to keep all data under.
+pub const VAR: &'static str = "PLAYFORM_DATA_DIR";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// The kinds of files Playform writes.
+pub enum Kind {
+  /// Settings.
+  Config,
+  /// Saved worlds.
+  Saves,
+  /// Recorded sessions.
+  Replays,
+  #[allow(missing_docs)]
+  Screenshots,
+  /// Telemetry reports and other diagnostics.
+  Logs,
+  /// Resource packs, both added by hand and downloaded from servers.
+  ResourcePacks,
+  /// Anything that can be thrown away, e.g. terrain cached from servers.
+  Cache,
+}
+
+impl Kind {
+  fn name(self) -> &'static str {
+    match self {
+      Kind::Config        => "config",
+      Kind::Saves         => "saves",
+      Kind::Replays       => "replays",
+      Kind::Screenshots   => "screenshots",
+      Kind::Logs          => "logs",
+      Kind::ResourcePacks => "resource_packs",
+      Kind::Cache         => "cache",
+    }
+  }
+}
+
+/// Where files of some kind go on an OS (as in `std::env::consts::OS`), given a way to look up
+/// environment variables.
+fn locate(os: &str, var: &Fn(&str) -> Option<String>, kind: Kind) -> PathBuf {
+  if let Some(root) = var(VAR) {
+    return PathBuf::from(root).join(kind.name())
+  }
+
+  let home = var("HOME").map(PathBuf::from);
+  let dir =
+    match os {
+      "windows" => {
+        let base =
+          match kind {
+            Kind::Cache => var("LOCALAPPDATA").or_else(|| var("APPDATA")),
+            _ => var("APPDATA"),
+          };
+        base.map(|base| PathBuf::from(base).join("Playform").join(kind.name()))
+      },
+      "macos" => {
+        home.map(|home| {
+          match kind {
+            Kind::Cache => home.join("Library").join("Caches").join("Playform"),
+            _ => home.join("Library").join("Application Support").join("Playform").join(kind.name()),
+          }
+        })
+      },
+      _ => {
+        // The XDG base directories, falling back to their defaults under $HOME.
+        let xdg = |name: &str, default: &str| {
+          var(name).map(PathBuf::from).or_else(|| home.as_ref().map(|home| home.join(default)))
+        };
+        match kind {
+          Kind::Config => xdg("XDG_CONFIG_HOME", ".config").map(|dir| dir.join("playform")),
+          Kind::Cache => xdg("XDG_CACHE_HOME", ".cache").map(|dir| dir.join("playform")),
+          _ => xdg("XDG_DATA_HOME", ".local/share").map(|dir| dir.join("playform").join(kind.name())),
+        }
+      },
+    };
+  // Without anywhere better, use the working directory.
+  dir.unwrap_or_else(|| PathBuf::from(kind.name()))
+}
+
+/// The directory for files of some kind, created if it doesn't exist yet.
+pub fn dir(kind: Kind) -> PathBuf {
+  let dir = locate(std::env::consts::OS, &|name| std::env::var(name).ok(), kind);
+  if let Err(err) = std::fs::create_dir_all(&dir) {
+    warn!("Error creating {:?}: {:?}", dir, err);
+  }
+  dir
+}
+
+#[cfg(test)]
+fn env(vars: &'static [(&'static str, &'static str)]) -> Box<Fn(&str) -> Option<String>> {
+  Box::new(move |name: &str| vars.iter().find(|&&(n, _)| n == name).map(|&(_, value)| String::from(value)))
+}
+
+#[test]
+fn follows_each_os() {
+  let linux = env(&[("HOME", "/home/p"), ("XDG_CONFIG_HOME", "/etc/p")]);
+  assert_eq!(locate("linux", &*linux, Kind::Config), PathBuf::from("/etc/p/playform"));
+  assert_eq!(locate("linux", &*linux, Kind::Cache), PathBuf::from("/home/p/.cache/playform"));
+  assert_eq!(locate("linux", &*linux, Kind::Saves), PathBuf::from("/home/p/.local/share/playform/saves"));
+
+  let windows = env(&[("APPDATA", "C:\\Roaming"), ("LOCALAPPDATA", "C:\\Local")]);
+  assert_eq!(locate("windows", &*windows, Kind::Replays), PathBuf::from("C:\\Roaming").join("Playform").join("replays"));
+  assert_eq!(locate("windows", &*windows, Kind::Cache), PathBuf::from("C:\\Local").join("Playform").join("cache"));
+
+  let macos = env(&[("HOME", "/Users/p")]);
+  assert_eq!(locate("macos", &*macos, Kind::Logs), PathBuf::from("/Users/p/Library/Application Support/Playform/logs"));
+
+  assert_eq!(locate("linux", &*env(&[]), Kind::Saves), PathBuf::from("saves"));
+}
+
+#[test]
+fn override_keeps_everything_together() {
+  let vars = env(&[(VAR, "/srv/playform"), ("HOME", "/home/p"), ("APPDATA", "C:\\Roaming")]);
+  for &os in &["linux", "windows", "macos"] {
+    assert_eq!(locate(os, &*vars, Kind::Config), PathBuf::from("/srv/playform/config"));
+    assert_eq!(locate(os, &*vars, Kind::Cache), PathBuf::from("/srv/playform/cache"));
+  }
+}
@@ -7,6 +7,12 @@ mod types {
 
   #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
   pub struct Mob;
+
+  #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+  pub struct Item;
+
+  #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+  pub struct Projectile;
 }
 
 #[allow(missing_docs)]
@@ -49,4 +55,18 @@ pub mod id {
 
   pub type Player = T<super::types::Player>;
   pub type Mob = T<super::types::Mob>;
+  pub type Item = T<super::types::Item>;
+  pub type Projectile = T<super::types::Projectile>;
+}
+
+/// Any entity, whatever kind of id it has.
+#[allow(missing_docs)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum EntityId {
+  Player(id::Player),
+  Mob(id::Mob),
+  /// A dropped item.
+  Item(id::Item),
+  /// Something thrown.
+  Projectile(id::Projectile),
 }
@@ -0,0 +1,166 @@
+//! What a player can see: the view frustum, built the same way on client and server so they agree.
+//! The client culls with the frustum itself; the server, which doesn't know each client's window
+//! shape or clip distances, can use the cheaper cone around it instead.
+
+use cgmath::{Point3, Vector3, InnerSpace, EuclideanSpace};
+use collision::Aabb3;
+use std;
+
+/// The vertical field of view, in radians.
+pub const FOV: f32 = std::f32::consts::FRAC_PI_3;
+
+/// A plane, as the points `p` where `normal.dot(p) + offset == 0`. Points with positive distances
+/// are inside.
+#[derive(Debug, Clone, Copy)]
+struct Plane {
+  normal : Vector3<f32>,
+  offset : f32,
+}
+
+impl Plane {
+  fn through(point: &Point3<f32>, normal: Vector3<f32>) -> Plane {
+    let normal = normal.normalize();
+    Plane {
+      normal : normal,
+      offset : -normal.dot(point.to_vec()),
+    }
+  }
+
+  fn distance(&self, p: &Point3<f32>) -> f32 {
+    self.normal.dot(p.to_vec()) + self.offset
+  }
+}
+
+#[allow(missing_docs)]
+#[derive(Debug, Clone, Copy)]
+pub struct T {
+  /// Near, far, left, right, bottom and top, all facing in.
+  planes : [Plane; 6],
+}
+
+/// The camera's axes, given the way it's facing: right and up, with up as close to world up as it
+/// can be.
+fn axes(forward: &Vector3<f32>) -> (Vector3<f32>, Vector3<f32>) {
+  let right = forward.cross(Vector3::new(0.0, 1.0, 0.0));
+  let right = if right.magnitude2() < 0.0001 { Vector3::new(1.0, 0.0, 0.0) } else { right.normalize() };
+  let up = right.cross(*forward);
+  (right, up)
+}
+
+/// The frustum of a camera at `position` looking along `forward`, with a vertical field of view of
+/// `fov` radians, a width/height `aspect` ratio, and clip planes at `near` and `far`.
+pub fn new(position: &Point3<f32>, forward: &Vector3<f32>, fov: f32, aspect: f32, near: f32, far: f32) -> T {
+  let forward = forward.normalize();
+  let (right, up) = axes(&forward);
+  let tan_y = (fov / 2.0).tan();
+  let tan_x = tan_y * aspect;
+  T {
+    planes: [
+      Plane::through(&(*position + forward * near), forward),
+      Plane::through(&(*position + forward * far), -forward),
+      Plane::through(position, right + forward * tan_x),
+      Plane::through(position, -right + forward * tan_x),
+      Plane::through(position, up + forward * tan_y),
+      Plane::through(position, -up + forward * tan_y),
+    ],
+  }
+}
+
+impl T {
+  /// Whether any of a sphere might be visible.
+  pub fn intersects_sphere(&self, center: &Point3<f32>, radius: f32) -> bool {
+    self.planes.iter().all(|plane| plane.distance(center) >= -radius)
+  }
+
+  /// Whether any of a box might be visible.
+  pub fn intersects_aabb(&self, bounds: &Aabb3<f32>) -> bool {
+    self.planes.iter().all(|plane| {
+      // The corner furthest along the plane's normal.
+      let corner =
+        Point3::new(
+          if plane.normal.x >= 0.0 { bounds.max.x } else { bounds.min.x },
+          if plane.normal.y >= 0.0 { bounds.max.y } else { bounds.min.y },
+          if plane.normal.z >= 0.0 { bounds.max.z } else { bounds.min.z },
+        );
+      plane.distance(&corner) >= 0.0
+    })
+  }
+}
+
+/// An infinite cone.
+#[derive(Debug, Clone, Copy)]
+pub struct Cone {
+  apex : Point3<f32>,
+  axis : Vector3<f32>,
+  /// Cosine and sine of the angle between the axis and the sides.
+  cos  : f32,
+  sin  : f32,
+}
+
+/// The narrowest cone containing the frustum of a camera at `position` looking along `forward`, with
+/// a vertical field of view of `fov` radians and a width/height `aspect` ratio.
+pub fn cone(position: &Point3<f32>, forward: &Vector3<f32>, fov: f32, aspect: f32) -> Cone {
+  let tan_y = (fov / 2.0).tan();
+  let tan_x = tan_y * aspect;
+  // The corners of the frustum are the furthest off its axis.
+  let angle = (tan_x * tan_x + tan_y * tan_y).sqrt().atan();
+  Cone {
+    apex : *position,
+    axis : forward.normalize(),
+    cos  : angle.cos(),
+    sin  : angle.sin(),
+  }
+}
+
+impl Cone {
+  /// Whether any of a sphere is inside the cone.
+  pub fn intersects_sphere(&self, center: &Point3<f32>, radius: f32) -> bool {
+    let v = *center - self.apex;
+    if v.magnitude2() <= radius * radius {
+      return true
+    }
+    let along = v.dot(self.axis);
+    let across = (v.magnitude2() - along * along).max(0.0).sqrt();
+    if along * self.cos + across * self.sin < 0.0 {
+      // The nearest point of the cone is its apex, which is out of reach.
+      return false
+    }
+    across * self.cos - along * self.sin <= radius
+  }
+}
+
+#[test]
+fn frustum_sees_only_whats_in_front() {
+  let frustum = new(&Point3::new(0.0, 0.0, 0.0), &Vector3::new(0.0, 0.0, -1.0), FOV, 1.0, 0.1, 100.0);
+  assert!(frustum.intersects_sphere(&Point3::new(0.0, 0.0, -10.0), 0.5));
+  assert!(!frustum.intersects_sphere(&Point3::new(0.0, 0.0, 10.0), 0.5));
+  assert!(!frustum.intersects_sphere(&Point3::new(0.0, 0.0, -200.0), 0.5));
+  assert!(!frustum.intersects_sphere(&Point3::new(20.0, 0.0, -10.0), 0.5));
+  // Just outside the top, but big enough to poke in.
+  assert!(frustum.intersects_sphere(&Point3::new(0.0, 6.5, -10.0), 1.0));
+
+  assert!(frustum.intersects_aabb(&Aabb3::new(Point3::new(-1.0, -1.0, -11.0), Point3::new(1.0, 1.0, -9.0))));
+  assert!(frustum.intersects_aabb(&Aabb3::new(Point3::new(-1.0, -1.0, -1.0), Point3::new(1.0, 1.0, 1.0))));
+  assert!(!frustum.intersects_aabb(&Aabb3::new(Point3::new(-1.0, -1.0, 1.0), Point3::new(1.0, 1.0, 3.0))));
+}
+
+#[test]
+fn cone_contains_frustum() {
+  let position = Point3::new(3.0, 1.0, -2.0);
+  let forward = Vector3::new(1.0, -0.5, 0.3);
+  let aspect = 16.0 / 9.0;
+  let frustum = new(&position, &forward, FOV, aspect, 0.1, 64.0);
+  let cone = cone(&position, &forward, FOV, aspect);
+  let mut seen = 0;
+  for x in -16 .. 17 {
+  for y in -16 .. 17 {
+  for z in -16 .. 17 {
+    let p = position + Vector3::new(x as f32, y as f32, z as f32) * 4.0;
+    if frustum.intersects_sphere(&p, 0.0) {
+      seen += 1;
+      assert!(cone.intersects_sphere(&p, 0.0), "{:?}", p);
+    }
+  }}}
+  assert!(seen > 0);
+  assert!(!cone.intersects_sphere(&(position - forward * 10.0), 1.0));
+}
@@ -0,0 +1,102 @@
+//! The far horizon: terrain beyond the polygonal load distance, kept only as a coarse grid of columns,
+//! each with a height and the material on top. The grid is split into square tiles, which the client
+//! requests and draws as cheap meshes, so distant mountains stay visible.
+
+use cgmath::Point3;
+
+use voxel;
+
+/// Distance between neighboring columns, in world units.
+pub const COLUMN_SPACING: f32 = 16.0;
+/// Columns along each side of a tile.
+pub const TILE_COLUMNS: usize = 32;
+/// Width of a tile, in world units.
+pub const TILE_WIDTH: f32 = COLUMN_SPACING * TILE_COLUMNS as f32;
+/// Samples along each side of a tile. Tiles include their far edges, so neighbors meet without gaps.
+pub const SAMPLES: usize = TILE_COLUMNS + 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+/// A tile of columns, in units of `TILE_WIDTH`.
+pub struct Tile {
+  #[allow(missing_docs)]
+  pub x : i32,
+  #[allow(missing_docs)]
+  pub z : i32,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+/// The top of one column of terrain.
+pub struct Column {
+  /// The height of the ground, or of the water over it.
+  pub height   : f32,
+  /// The material at the top of the column.
+  pub material : voxel::Material,
+}
+
+impl Tile {
+  /// The tile containing a point in the world.
+  pub fn of_world_position(p: &Point3<f32>) -> Tile {
+    Tile {
+      x : (p.x / TILE_WIDTH).floor() as i32,
+      z : (p.z / TILE_WIDTH).floor() as i32,
+    }
+  }
+
+  /// The world-space x and z of the `(i, j)`th sample in this tile.
+  pub fn sample_position(&self, i: usize, j: usize) -> (f32, f32) {
+    (
+      self.x as f32 * TILE_WIDTH + i as f32 * COLUMN_SPACING,
+      self.z as f32 * TILE_WIDTH + j as f32 * COLUMN_SPACING,
+    )
+  }
+
+  /// The horizontal distances from a point to the nearest and farthest parts of this tile, measured
+  /// as the larger of the x and z distances, like the distances between chunks.
+  pub fn distances(&self, p: &Point3<f32>) -> (f32, f32) {
+    let axis = |low: f32, x: f32| {
+      let high = low + TILE_WIDTH;
+      let near = f32::max(0.0, f32::max(low - x, x - high));
+      let far = f32::max((low - x).abs(), (high - x).abs());
+      (near, far)
+    };
+    let (near_x, far_x) = axis(self.x as f32 * TILE_WIDTH, p.x);
+    let (near_z, far_z) = axis(self.z as f32 * TILE_WIDTH, p.z);
+    (f32::max(near_x, near_z), f32::max(far_x, far_z))
+  }
+}
+
+/// The index of the `(i, j)`th sample in a tile's columns.
+pub fn index(i: usize, j: usize) -> usize {
+  i * SAMPLES + j
+}
+
+/// The tiles needed to draw the horizon from `p`: those reaching past `min_distance` that start
+/// within `max_distance`, nearest first.
+pub fn tiles_between(p: &Point3<f32>, min_distance: f32, max_distance: f32) -> Vec<Tile> {
+  let center = Tile::of_world_position(p);
+  let reach = (max_distance / TILE_WIDTH).ceil() as i32 + 1;
+  let mut tiles = Vec::new();
+  for x in center.x - reach .. center.x + reach + 1 {
+    for z in center.z - reach .. center.z + reach + 1 {
+      let tile = Tile { x: x, z: z };
+      let (near, far) = tile.distances(p);
+      if near < max_distance && far > min_distance {
+        tiles.push((near, tile));
+      }
+    }
+  }
+  tiles.sort_by(|&(d1, _), &(d2, _)| d1.partial_cmp(&d2).unwrap());
+  tiles.into_iter().map(|(_, tile)| tile).collect()
+}
+
+#[test]
+fn tiles_between_skip_the_middle() {
+  let p = Point3::new(10.0, 0.0, 10.0);
+  let tiles = tiles_between(&p, 2.0 * TILE_WIDTH, 4.0 * TILE_WIDTH);
+  assert!(!tiles.contains(&Tile { x: 0, z: 0 }));
+  assert!(!tiles.contains(&Tile { x: 1, z: -1 }));
+  assert!(tiles.contains(&Tile { x: 3, z: 0 }));
+  assert!(tiles.contains(&Tile { x: -4, z: 2 }));
+  assert!(!tiles.contains(&Tile { x: 5, z: 0 }));
+  assert_eq!(tiles.first().map(|t| t.distances(&p).0 < tiles.last().unwrap().distances(&p).0), Some(true));
+}
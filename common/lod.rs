@@ -0,0 +1,152 @@
+//! Structs for keeping track of terrain level of detail.
+
+use cgmath::{Point3, ElementWise};
+use num;
+
+use surroundings_loader;
+use voxel;
+
+use chunk;
+
+// TODO: terrain_mesh is now chunk-agnostic. Some/all of these values should be moved.
+/// Number of LODs
+pub const COUNT: usize = 5;
+
+/// exhaustive list of all the LODs
+pub const ALL: [T; COUNT] = [T(0), T(1), T(2), T(3), T(4)];
+
+/// lg(EDGE_SAMPLES)
+// NOTE: If there are duplicates here, weird invariants will fail.
+// Just remove the LODs if you don't want duplicates.
+const LG_EDGE_SAMPLES: [u16; COUNT] = [3, 2, 1, 1, 0];
+
+/// The base-2 log of the width of a voxel within a chunk, indexed by LOD.
+const LG_SAMPLE_SIZE: [i16; COUNT] = [
+  chunk::LG_WIDTH as i16 - LG_EDGE_SAMPLES[0] as i16,
+  chunk::LG_WIDTH as i16 - LG_EDGE_SAMPLES[1] as i16,
+  chunk::LG_WIDTH as i16 - LG_EDGE_SAMPLES[2] as i16,
+  chunk::LG_WIDTH as i16 - LG_EDGE_SAMPLES[3] as i16,
+  chunk::LG_WIDTH as i16 - LG_EDGE_SAMPLES[4] as i16,
+];
+
+/// max LOD where grass will display
+pub const MAX_GRASS_LOD: T = T(3);
+
+/// The distances at which LOD switches.
+pub const THRESHOLDS: [u32; COUNT-1] = [1, 15, 31, 47];
+
+/// How far past a threshold a chunk has to be before its LOD changes,
+/// so chunks near a boundary don't flip back and forth as the player moves around.
+pub const HYSTERESIS: u32 = 2;
+
+/// Past this distance, chunks are grouped into aligned super-blocks that all get the same LOD.
+/// Super-blocks double in width every time the distance doubles, so LOD regions form an octree
+/// rather than ragged bands around the player.
+const SUPER_BLOCK_DISTANCE: u32 = 16;
+
+/// lg of the widest super-block, in chunks.
+const MAX_LG_SUPER_BLOCK: u32 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+/// A strongly-typed index into various LOD-indexed arrays.
+/// 0 is the highest LOD.
+/// Ordering is "backwards": x > y means that x is bigger (lower level of detail) than y.
+pub struct T(pub u32);
+
+impl T {
+  /// base-2 log of voxel sample size for this LOD
+  pub fn lg_sample_size(self) -> i16 {
+    LG_SAMPLE_SIZE[self.0 as usize]
+  }
+
+  /// base-2 log of the number of voxel samples per chunk for this LOD
+  pub fn lg_edge_samples(self) -> u16 {
+    LG_EDGE_SAMPLES[self.0 as usize]
+  }
+
+  /// the number of voxel samples per chunk for this LOD
+  pub fn edge_samples(self) -> u16 {
+    1 << self.lg_edge_samples()
+  }
+}
+
+/// determine the LOD for a certain chunk distance away
+pub fn of_distance(distance: u32) -> T {
+  let mut lod = 0;
+  while
+    lod < THRESHOLDS.len()
+    && THRESHOLDS[lod] < distance
+  {
+    lod += 1;
+  }
+  T(num::traits::FromPrimitive::from_usize(lod).unwrap())
+}
+
+
+/// lg of the width (in chunks) of the super-blocks at a given distance.
+fn lg_super_block(distance: u32) -> u32 {
+  let mut lg = 0;
+  let mut limit = SUPER_BLOCK_DISTANCE;
+  while lg < MAX_LG_SUPER_BLOCK && distance >= limit {
+    lg += 1;
+    limit *= 2;
+  }
+  lg
+}
+
+/// The distance from `player` to the middle of the super-block containing `chunk`.
+fn super_block_distance(player: &Point3<i32>, chunk: &Point3<i32>) -> u32 {
+  let lg = lg_super_block(surroundings_loader::distance_between(player, chunk));
+  let middle = |c: i32| ((c >> lg) << lg) + ((1 << lg) >> 1);
+  surroundings_loader::distance_between(player, &Point3::new(middle(chunk.x), middle(chunk.y), middle(chunk.z)))
+}
+
+/// Choose the LOD for a chunk, given the LOD it's currently loaded at (if any).
+pub fn select(player: &Point3<i32>, chunk: &Point3<i32>, current: Option<T>) -> T {
+  let distance = super_block_distance(player, chunk);
+  let target = of_distance(distance);
+  match current {
+    Some(current) if target > current => {
+      // Coarsen once we're well past the boundary.
+      if distance > THRESHOLDS[current.0 as usize] + HYSTERESIS {
+        target
+      } else {
+        current
+      }
+    },
+    Some(current) if target < current => {
+      // Refine once we're well inside the boundary.
+      if distance + HYSTERESIS <= THRESHOLDS[current.0 as usize - 1] {
+        target
+      } else {
+        current
+      }
+    },
+    _ => target,
+  }
+}
+
+/// The voxels it takes to mesh a chunk at an LOD: its own samples, and a border one sample wide.
+pub fn voxels_for_chunk(chunk_position: &chunk::position::T, lod: T) -> Vec<voxel::bounds::T> {
+  let lg_size = lod.lg_sample_size();
+  let edge_samples = lod.edge_samples() as i32;
+  let low = chunk_position.as_pnt().mul_element_wise(edge_samples);
+  let mut voxels = Vec::with_capacity(((edge_samples + 2) * (edge_samples + 2) * (edge_samples + 2)) as usize);
+  for x in low.x - 1 .. low.x + edge_samples + 1 {
+  for y in low.y - 1 .. low.y + edge_samples + 1 {
+  for z in low.z - 1 .. low.z + edge_samples + 1 {
+    voxels.push(voxel::bounds::new(x, y, z, lg_size));
+  }}}
+  voxels
+}
+
+#[test]
+fn lod_changes_lag_behind_thresholds() {
+  let player = Point3::new(0, 0, 0);
+  let boundary = THRESHOLDS[1] as i32;
+  assert_eq!(select(&player, &Point3::new(boundary + 1, 0, 0), None), T(2));
+  assert_eq!(select(&player, &Point3::new(boundary + 1, 0, 0), Some(T(1))), T(1));
+  assert_eq!(select(&player, &Point3::new(boundary + 1 + HYSTERESIS as i32, 0, 0), Some(T(1))), T(2));
+  assert_eq!(select(&player, &Point3::new(boundary, 0, 0), Some(T(2))), T(2));
+  assert_eq!(select(&player, &Point3::new(boundary - HYSTERESIS as i32, 0, 0), Some(T(2))), T(1));
+}
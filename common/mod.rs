@@ -3,13 +3,17 @@
 #![deny(missing_docs)]
 #![deny(warnings)]
 
+extern crate bincode;
 extern crate cgmath;
 extern crate collision;
 extern crate fnv;
 extern crate isosurface_extraction;
+extern crate libc;
+#[macro_use]
 extern crate log;
 extern crate nanomsg;
 extern crate num;
+extern crate rand;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
@@ -17,17 +21,28 @@ extern crate stopwatch;
 extern crate time;
 extern crate voxel_data;
 
+pub mod chunk;
 pub mod closure_series;
 pub mod color;
 pub mod cube_shell;
+pub mod data_dir;
 pub mod entity;
 pub mod fnv_map;
 pub mod fnv_set;
+pub mod frustum;
+pub mod horizon;
 pub mod id_allocator;
 pub mod index;
 pub mod interval_timer;
+pub mod lod;
 pub mod protocol;
 pub mod range_abs;
+pub mod ray;
+pub mod reliable;
+pub mod rng;
+pub mod shape;
 pub mod socket;
 pub mod surroundings_loader;
+pub mod telemetry;
+pub mod thread_priority;
 pub mod voxel;
@@ -5,7 +5,10 @@ use collision::{Aabb3};
 use std::default::Default;
 use std::ops::Add;
 
+use chunk;
 use entity;
+use horizon;
+use lod;
 use voxel;
 
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Serialize, Deserialize)]
@@ -27,13 +30,37 @@ impl Add<u32> for ClientId {
   }
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Who a client wants to play as.
+pub struct Credentials {
+  /// The name to play under. Only one client can play under a name at a time.
+  pub name  : String,
+  /// A secret chosen the first time a name is played under, which has to be presented to play
+  /// under it again.
+  pub token : String,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 /// Messages the client sends to the server.
 pub enum ClientToServer {
-  /// Notify the server that the client exists, and provide a "return address".
-  Init(String),
-  /// Ping
+  /// Notify the server that the client exists, and provide a "return address" and who it wants to
+  /// play as.
+  Init(String, Credentials),
+  /// Like `Init`, but sent by a proxy on behalf of the client it's forwarding.
+  ProxiedInit {
+    /// The "return address" of the client, as reachable by the server.
+    return_url     : String,
+    /// The address the client originally connected to the proxy from.
+    client_address : String,
+    /// Secret shared between the server and its trusted proxies.
+    proxy_secret   : String,
+    /// Who the client wants to play as.
+    credentials    : Credentials,
+  },
+  /// Answer a `Ping` from the server, to show the client's still there.
   Ping(ClientId),
+  /// The client is leaving. Its player is saved and taken out of the world.
+  Disconnect(ClientId),
   /// Ask the server to create a new player.
   AddPlayer(ClientId),
   /// Add a vector the player's acceleration.
@@ -44,6 +71,13 @@ pub enum ClientToServer {
   StartJump(entity::id::Player),
   /// [Try to] stop a jump for the player.
   StopJump(entity::id::Player),
+  /// Change how the player moves.
+  SetMovementMode(entity::id::Player, MovementMode),
+  /// Say something to everyone.
+  Chat(entity::id::Player, String),
+  /// Start or stop flying, if the server allows it. Flying players ignore gravity and terrain, and
+  /// can `Walk` up and down.
+  ToggleFly(entity::id::Player),
   /// Ask the server to send a block of terrain.
   RequestVoxels {
     /// The time, in nanoseconds, when the voxels were requested.
@@ -53,10 +87,74 @@ pub enum ClientToServer {
     /// The bounds of the voxels to fetch.
     voxels          : Vec<voxel::bounds::T>,
   },
+  /// Ask the server whether a block the client already holds is current. The server answers with
+  /// `BlockCurrent` if it is, and sends the block's voxels if it isn't.
+  CheckBlock {
+    /// The time, in nanoseconds, when the block was checked.
+    time_requested_ns : u64,
+    /// The ID of the requesting client.
+    client_id       : ClientId,
+    /// The position of the block.
+    chunk           : chunk::position::T,
+    /// The LOD the block is held at.
+    lod             : lod::T,
+    /// `voxel::checksum` of the block's voxels, in the order `lod::voxels_for_chunk` gives them.
+    checksum        : u64,
+  },
+  /// Ask the server to send a tile of the far horizon.
+  RequestHorizon(ClientId, horizon::Tile),
   /// Brush-remove where the player's looking.
   Add(entity::id::Player),
   /// Brush-add at where the player's looking.
   Remove(entity::id::Player),
+  /// Dig out the voxel hit by a ray from the player toward a target point.
+  RemoveVoxel(entity::id::Player, Point3<f32>),
+  /// Use the item in the player's selected inventory slot on the surface hit by a ray from the player
+  /// toward a target point. For now, every item is a material, which is placed as a voxel against
+  /// the surface.
+  UseItem(entity::id::Player, Point3<f32>),
+  /// Like `UseItem`, but place a grid-aligned shape of the selected material instead of a single voxel.
+  /// The shape is named by one of the names in `ServerToClient::Shapes`.
+  PlaceShape(entity::id::Player, Point3<f32>, String),
+  /// Turn what's hit by a ray from the player toward a target point: a placed shape, which is
+  /// re-brushed in its next orientation, or a piston, which turns to push the next way round.
+  Rotate(entity::id::Player, Point3<f32>),
+  /// Pick which inventory slot `UseItem` and `PlaceShape` take from.
+  SelectSlot(entity::id::Player, u32),
+  /// Use what's hit by a ray from the player toward a target point: flip a lever, or talk to a trader.
+  Interact(entity::id::Player, Point3<f32>),
+  /// Hit a player or mob within reach.
+  Attack(entity::id::Player, entity::EntityId),
+  /// Throw one of whatever's in the player's selected inventory slot, in some direction.
+  Throw(entity::id::Player, Vector3<f32>),
+  /// Accept one of a trader's offers, by its index in the `TradeOffers` the trader sent.
+  Trade(entity::id::Player, entity::id::Mob, u32),
+}
+
+impl ClientToServer {
+  /// Whether this has to get through, in order, even if it has to be resent (see `reliable`).
+  /// Anything that changes the world or the player's things is; movement and requests the client
+  /// repeats anyway aren't.
+  pub fn is_critical(&self) -> bool {
+    match *self {
+      ClientToServer::AddPlayer(_) |
+      ClientToServer::Disconnect(_) |
+      ClientToServer::Chat(_, _) |
+      ClientToServer::ToggleFly(_) |
+      ClientToServer::Add(_) |
+      ClientToServer::Remove(_) |
+      ClientToServer::RemoveVoxel(_, _) |
+      ClientToServer::UseItem(_, _) |
+      ClientToServer::PlaceShape(_, _, _) |
+      ClientToServer::Rotate(_, _) |
+      ClientToServer::SelectSlot(_, _) |
+      ClientToServer::Interact(_, _) |
+      ClientToServer::Attack(_, _) |
+      ClientToServer::Throw(_, _) |
+      ClientToServer::Trade(_, _, _) => true,
+      _ => false,
+    }
+  }
 }
 
 /// Why a block is being sent to a client.
@@ -69,6 +167,8 @@ pub enum VoxelReason {
   },
   /// The block has been updated.
   Updated,
+  /// The server sent it unasked, expecting the client to want it soon.
+  Pushed,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -79,23 +179,138 @@ pub enum Collision {
   PlayerMisc(entity::id::Player),
 }
 
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+/// A mob's state as of one server tick.
+pub struct MobTransform {
+  /// The center of the mob's bounding box.
+  pub position : Point3<f32>,
+  /// Rotation about the vertical axis, in radians.
+  pub yaw      : f32,
+  /// Velocity in units per second.
+  pub velocity : Vector3<f32>,
+  /// The dimensions of the mob's bounding box.
+  pub size     : Vector3<f32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+/// How a player moves.
+pub enum MovementMode {
+  #[allow(missing_docs)]
+  Walk,
+  /// Faster.
+  Sprint,
+  /// Slower and lower, and without walking off the edges of blocks.
+  Crouch,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+/// Something a trader will exchange: `goods` for the player's `price`, each a count of some material.
+pub struct TradeOffer {
+  #[allow(missing_docs)]
+  pub price : (voxel::Material, u32),
+  #[allow(missing_docs)]
+  pub goods : (voxel::Material, u32),
+}
+
+/// How many slots players' inventories have. They're all shown in the client's hotbar.
+pub const INVENTORY_SLOTS: usize = 9;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+/// A pile of one kind of item in an inventory slot.
+pub struct ItemStack {
+  #[allow(missing_docs)]
+  pub material : voxel::Material,
+  #[allow(missing_docs)]
+  pub count    : u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+/// What kind of text a `ServerToClient::Message` carries, so players can choose what they see.
+pub enum MessageKind {
+  /// The server's welcome message, sent when a player joins.
+  Motd,
+  /// A tip, sent when something happens to a player for the first time.
+  Hint,
+  /// How a trade went.
+  Trade,
+  /// News of a boss fight.
+  Boss,
+  /// Anything else the server has to say, e.g. why it refused a request.
+  Notice,
+  /// Something a player said.
+  Chat,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 /// Messages the server sends to the client.
 pub enum ServerToClient {
-  /// Provide the client a unique id to tag its messages.
-  LeaseId(ClientId),
-  /// Ping
+  /// Provide the client a unique id to tag its messages, and a secret key to tag them with alongside
+  /// it, so nobody else can pass messages off as the client's (see `reliable`).
+  LeaseId(ClientId, u64),
+  /// The server won't let the client play, and why: e.g. its name is taken, or its token is wrong.
+  Refused(String),
+  /// The client needs this resource pack to display this server's world correctly.
+  RequiredResourcePack {
+    /// Where to download the pack from.
+    url    : String,
+    /// Hex-encoded SHA-256 of the pack archive, to verify the download.
+    sha256 : String,
+  },
+  /// Check that the client's still there. Clients that don't answer with a `Ping` are dropped.
   Ping,
+  /// The server has started simulation step number `n`. Steps run at a fixed rate, so this is
+  /// the server's clock; the updates that follow belong to this step.
+  Tick(u64),
 
   /// Complete an AddPlayer request.
   PlayerAdded(entity::id::Player, Point3<f32>),
 
   /// Update a player's position.
   UpdatePlayer(entity::id::Player, Aabb3<f32>),
-  /// Update the client's view of a mob with a given mesh.
-  UpdateMob(entity::id::Mob, Aabb3<f32>),
+  /// Stop drawing a player, who's left.
+  RemovePlayer(entity::id::Player),
+  /// Update a mob's position and motion.
+  UpdateMob(entity::id::Mob, MobTransform),
+  /// Stop drawing a mob.
+  RemoveMob(entity::id::Mob),
+  /// Move a dropped item of some material, by its center, or start drawing it.
+  UpdateItem(entity::id::Item, Point3<f32>, voxel::Material),
+  /// Stop drawing a dropped item.
+  RemoveItem(entity::id::Item),
+  /// Move something thrown, with its velocity in voxels per second, or start drawing it.
+  UpdateProjectile(entity::id::Projectile, Point3<f32>, Vector3<f32>, voxel::Material),
+  /// Stop drawing something thrown.
+  RemoveProjectile(entity::id::Projectile),
   /// The sun as a [0, 1) portion of its cycle.
   UpdateSun(f32),
+  /// The client's player's health changed.
+  UpdateHealth {
+    #[allow(missing_docs)]
+    health     : u32,
+    #[allow(missing_docs)]
+    max_health : u32,
+  },
+  /// The health of the boss being fought near the player. It's sent every so often for as long as
+  /// the player's nearby, and with zero health once the boss is defeated.
+  BossHealth {
+    #[allow(missing_docs)]
+    health     : u32,
+    #[allow(missing_docs)]
+    max_health : u32,
+  },
+  /// Text to show the player.
+  Message(MessageKind, String),
+  /// Whether the player is now flying.
+  Flying(bool),
+  /// What's in each of the player's `INVENTORY_SLOTS` inventory slots.
+  UpdateInventory(Vec<Option<ItemStack>>),
+  /// The player has started trading with a trader, called by the given name, who makes these offers.
+  TradeOffers(entity::id::Mob, String, Vec<TradeOffer>),
+  /// The farthest out, in chunks, that the server currently has capacity to serve terrain.
+  /// It shrinks as the server gets busier; clients should trim how far out they load to fit.
+  MaxLoadDistance(u32),
+  /// The names of the shapes `PlaceShape` can place, in the order players should cycle through them.
+  Shapes(Vec<String>),
 
   /// Provide a block of terrain to a client.
   Voxels {
@@ -103,7 +318,39 @@ pub enum ServerToClient {
     voxels : Vec<(voxel::bounds::T, voxel::T)>,
     /// The reason the voxels are being sent.
     reason : VoxelReason,
+    /// `voxel::checksum` of `voxels`. When they're a whole block, as requested with
+    /// `lod::voxels_for_chunk`, this is the block's checksum, which `CheckBlock` takes.
+    checksum : u64,
   },
+  /// The client's copy of a block, as given in `CheckBlock`, is current.
+  BlockCurrent(chunk::position::T, lod::T),
+  /// Provide a tile of the far horizon to a client: `horizon::SAMPLES` squared columns, indexed by `horizon::index`.
+  Horizon(horizon::Tile, Vec<horizon::Column>),
   /// A collision happened.
   Collision(Collision),
 }
+
+impl ServerToClient {
+  /// Whether this has to get through, in order, even if it has to be resent (see `reliable`).
+  /// Edits to terrain the client already has, things leaving, and anything the player's told are;
+  /// updates that are sent again every tick, and terrain the client asked for, aren't.
+  pub fn is_critical(&self) -> bool {
+    match *self {
+      ServerToClient::LeaseId(_, _) |
+      ServerToClient::Refused(_) |
+      ServerToClient::RequiredResourcePack { .. } |
+      ServerToClient::PlayerAdded(_, _) |
+      ServerToClient::RemovePlayer(_) |
+      ServerToClient::RemoveMob(_) |
+      ServerToClient::RemoveItem(_) |
+      ServerToClient::RemoveProjectile(_) |
+      ServerToClient::Message(_, _) |
+      ServerToClient::Flying(_) |
+      ServerToClient::UpdateInventory(_) |
+      ServerToClient::TradeOffers(_, _, _) |
+      ServerToClient::Shapes(_) |
+      ServerToClient::Voxels { reason: VoxelReason::Updated, .. } => true,
+      _ => false,
+    }
+  }
+}
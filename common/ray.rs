@@ -0,0 +1,50 @@
+//! Rays against boxes.
+
+use cgmath::Vector3;
+use collision::{Aabb3, Ray3};
+use std;
+
+/// Where a ray enters a box, as a distance along the ray, and the normal of the face it enters by.
+/// Boxes the ray starts inside aren't hit.
+pub fn hit(ray: &Ray3<f32>, bounds: &Aabb3<f32>) -> Option<(f32, Vector3<f32>)> {
+  let mut near = std::f32::NEG_INFINITY;
+  let mut far = std::f32::INFINITY;
+  let mut normal = Vector3::new(0.0, 0.0, 0.0);
+  for &(origin, direction, low, high, axis) in &[
+    (ray.origin.x, ray.direction.x, bounds.min.x, bounds.max.x, Vector3::new(1.0, 0.0, 0.0)),
+    (ray.origin.y, ray.direction.y, bounds.min.y, bounds.max.y, Vector3::new(0.0, 1.0, 0.0)),
+    (ray.origin.z, ray.direction.z, bounds.min.z, bounds.max.z, Vector3::new(0.0, 0.0, 1.0)),
+  ] {
+    if direction == 0.0 {
+      if origin < low || origin > high {
+        return None
+      }
+      continue
+    }
+    let (t0, t1) = ((low - origin) / direction, (high - origin) / direction);
+    let (enter, exit) = if t0 < t1 { (t0, t1) } else { (t1, t0) };
+    if enter > near {
+      near = enter;
+      normal = axis * -direction.signum();
+    }
+    far = f32::min(far, exit);
+  }
+  if near < 0.0 || near > far {
+    None
+  } else {
+    Some((near, normal))
+  }
+}
+
+#[test]
+fn rays_enter_boxes_through_the_near_face() {
+  use cgmath::Point3;
+
+  let bounds = Aabb3::new(Point3::new(1.0, -1.0, -1.0), Point3::new(3.0, 1.0, 1.0));
+  let ray = Ray3::new(Point3::new(0.0, 0.0, 0.0), Vector3::new(1.0, 0.0, 0.0));
+  assert_eq!(hit(&ray, &bounds), Some((1.0, Vector3::new(-1.0, 0.0, 0.0))));
+  let away = Ray3::new(Point3::new(0.0, 0.0, 0.0), Vector3::new(-1.0, 0.0, 0.0));
+  assert_eq!(hit(&away, &bounds), None);
+  let inside = Ray3::new(Point3::new(2.0, 0.0, 0.0), Vector3::new(1.0, 0.0, 0.0));
+  assert_eq!(hit(&inside, &bounds), None);
+}
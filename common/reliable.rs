@@ -0,0 +1,205 @@
+//! Reliable, ordered delivery of the messages that matter, over sockets that can drop things. Each
+//! critical message is numbered and kept until the other side acks it, and resent every `RESEND_NS`
+//! until then; the other side delivers critical messages in order, once each, holding back any that
+//! arrive ahead of a gap. Other messages, like positions that'll be sent again next tick anyway, go
+//! out unnumbered and are delivered as they come. Acks ride along on every message going the other
+//! way, so there's nothing extra to send while traffic's flowing.
+
+use std::collections::{BTreeMap, VecDeque};
+
+/// How long a critical message goes unacked before it's sent again.
+pub const RESEND_NS: u64 = 1_000_000_000;
+
+/// How far past the next expected critical message one can arrive and still be held on to. Anything
+/// further ahead is dropped (and resent later), so a peer can't make us hold an unbounded backlog.
+pub const MAX_AHEAD: u64 = 1024;
+
+/// A message as it goes over the wire.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Envelope<Message> {
+  /// The message's place among the sender's critical messages, or None if it isn't critical.
+  pub seq     : Option<u64>,
+  /// Every critical message the sender has had from the other side, up to and including this one.
+  pub ack     : u64,
+  #[allow(missing_docs)]
+  pub message : Message,
+}
+
+struct Unacked<Message> {
+  seq     : u64,
+  sent_ns : u64,
+  message : Message,
+}
+
+/// One side's end of a conversation, sending `Out`s and receiving `In`s.
+pub struct Link<Out, In> {
+  /// The last sequence number given out.
+  last_seq  : u64,
+  /// Critical messages sent but not acked yet, oldest first.
+  unacked   : VecDeque<Unacked<Out>>,
+  /// Every critical message received up to this one has been delivered.
+  delivered : u64,
+  /// Critical messages received past a gap, waiting for it to be filled.
+  ahead     : BTreeMap<u64, In>,
+  /// Whether something has been delivered that the other side hasn't been told about.
+  ack_owed  : bool,
+}
+
+#[allow(missing_docs)]
+pub fn new<Out, In>() -> Link<Out, In> {
+  Link {
+    last_seq  : 0,
+    unacked   : VecDeque::new(),
+    delivered : 0,
+    ahead     : BTreeMap::new(),
+    ack_owed  : false,
+  }
+}
+
+impl<Out: Clone, In> Link<Out, In> {
+  /// Wrap a message to be sent now. Critical messages are kept until they're acked.
+  pub fn send(&mut self, message: Out, critical: bool, now_ns: u64) -> Envelope<Out> {
+    let seq =
+      if critical {
+        self.last_seq += 1;
+        self.unacked.push_back(Unacked {
+          seq     : self.last_seq,
+          sent_ns : now_ns,
+          message : message.clone(),
+        });
+        Some(self.last_seq)
+      } else {
+        None
+      };
+    self.ack_owed = false;
+    Envelope {
+      seq     : seq,
+      ack     : self.delivered,
+      message : message,
+    }
+  }
+
+  /// The critical messages that have gone unacked for `RESEND_NS`, to be sent again.
+  pub fn resends(&mut self, now_ns: u64) -> Vec<Envelope<Out>> {
+    let mut resends = Vec::new();
+    for unacked in self.unacked.iter_mut() {
+      if now_ns < unacked.sent_ns + RESEND_NS {
+        continue
+      }
+      unacked.sent_ns = now_ns;
+      resends.push(Envelope {
+        seq     : Some(unacked.seq),
+        ack     : self.delivered,
+        message : unacked.message.clone(),
+      });
+    }
+    if !resends.is_empty() {
+      self.ack_owed = false;
+    }
+    resends
+  }
+
+  /// Take in a message from the other side, and return what can be delivered now, in order:
+  /// nothing if it's a duplicate or arrived ahead of a gap, or more than one if it filled one.
+  pub fn receive(&mut self, envelope: Envelope<In>) -> Vec<In> {
+    while self.unacked.front().map_or(false, |unacked| unacked.seq <= envelope.ack) {
+      self.unacked.pop_front();
+    }
+
+    let seq =
+      match envelope.seq {
+        None => return vec!(envelope.message),
+        Some(seq) => seq,
+      };
+    // Duplicates are still acked, in case the ack for the first copy went missing.
+    self.ack_owed = true;
+    if seq <= self.delivered {
+      return Vec::new()
+    }
+    if seq > self.delivered + 1 {
+      if seq - self.delivered <= MAX_AHEAD {
+        self.ahead.insert(seq, envelope.message);
+      }
+      return Vec::new()
+    }
+
+    let mut delivered = vec!(envelope.message);
+    self.delivered = seq;
+    while let Some(message) = self.ahead.remove(&(self.delivered + 1)) {
+      delivered.push(message);
+      self.delivered += 1;
+    }
+    delivered
+  }
+
+  /// Whether critical messages have been received since the last thing sent, so the other side is
+  /// waiting on an ack.
+  pub fn ack_owed(&self) -> bool {
+    self.ack_owed
+  }
+
+  /// How many critical messages are waiting to be acked.
+  pub fn unacked(&self) -> usize {
+    self.unacked.len()
+  }
+}
+
+#[test]
+fn critical_messages_arrive_once_and_in_order() {
+  let mut sender: Link<&'static str, ()> = new();
+  let mut receiver: Link<(), &'static str> = new();
+
+  let first = sender.send("first", true, 0);
+  let position = sender.send("position", false, 0);
+  let second = sender.send("second", true, 0);
+  let third = sender.send("third", true, 0);
+  assert_eq!(first.seq, Some(1));
+  assert_eq!(position.seq, None);
+
+  // The first is lost, and the rest arrive out of order.
+  assert_eq!(receiver.receive(position), vec!("position"));
+  assert_eq!(receiver.receive(third), Vec::<&str>::new());
+  assert_eq!(receiver.receive(second.clone()), Vec::<&str>::new());
+  assert!(receiver.ack_owed());
+
+  // Nothing's acked, so everything critical comes around again, and fills the gap.
+  let resends = sender.resends(RESEND_NS);
+  assert_eq!(resends.len(), 3);
+  let delivered: Vec<&str> = resends.into_iter().flat_map(|envelope| receiver.receive(envelope)).collect();
+  assert_eq!(delivered, vec!("first", "second", "third"));
+  assert_eq!(receiver.receive(second), Vec::<&str>::new());
+
+  // Once the ack gets back, nothing's resent.
+  let ack = receiver.send((), false, RESEND_NS);
+  assert_eq!(ack.ack, 3);
+  assert!(!receiver.ack_owed());
+  assert_eq!(sender.receive(ack), vec!(()));
+  assert_eq!(sender.unacked(), 0);
+  assert!(sender.resends(10 * RESEND_NS).is_empty());
+}
+
+#[test]
+fn resends_wait_for_the_interval() {
+  let mut sender: Link<u32, ()> = new();
+  sender.send(1, true, 0);
+  sender.send(2, true, RESEND_NS / 2);
+  assert!(sender.resends(RESEND_NS / 2).is_empty());
+  assert_eq!(sender.resends(RESEND_NS).len(), 1);
+  assert_eq!(sender.resends(RESEND_NS + RESEND_NS / 2).len(), 1);
+  assert!(sender.resends(RESEND_NS + RESEND_NS / 2).is_empty());
+}
+
+#[test]
+fn messages_too_far_ahead_are_dropped() {
+  let mut receiver: Link<(), u64> = new();
+  let envelope = |seq| Envelope { seq: Some(seq), ack: 0, message: seq };
+  assert!(receiver.receive(envelope(MAX_AHEAD)).is_empty());
+  assert!(receiver.receive(envelope(MAX_AHEAD + 1)).is_empty());
+  assert!(receiver.receive(envelope(u64::max_value())).is_empty());
+  assert_eq!(receiver.ahead.len(), 1);
+
+  // Filling the gap delivers what was kept; the rest has to come again.
+  let delivered = (1 .. MAX_AHEAD).flat_map(|seq| receiver.receive(envelope(seq))).collect::<Vec<_>>();
+  assert_eq!(delivered, (1 .. MAX_AHEAD + 1).collect::<Vec<_>>());
+  assert_eq!(receiver.receive(envelope(MAX_AHEAD + 1)), vec!(MAX_AHEAD + 1));
+}
@@ -0,0 +1,121 @@
+//! Seeded random streams. Each thing that draws random numbers from the world seed draws them from
+//! its own stream, named after the subsystem drawing and keyed by whatever it's drawing for (a
+//! position, a tick, an entity), so streams never share state: adding a new stream, or drawing more
+//! from one, never changes what any other draws, and the same seed always draws the same things.
+
+use cgmath::Point3;
+use rand;
+use rand::SeedableRng;
+
+/// The names of the subsystems that draw random streams. They're all here, so they can't collide.
+/// Names are part of what a world is: renaming one changes everything drawn from its streams.
+pub mod subsystem {
+  /// Where trees are placed in generated terrain.
+  pub const TREES         : &'static str = "worldgen/trees";
+  /// Where huts and other structures are placed in generated terrain.
+  pub const STRUCTURES    : &'static str = "worldgen/structures";
+  /// The shapes of trees grown by players.
+  pub const TREE_GROWTH   : &'static str = "trees/growth";
+  /// Which mobs spawn, and where.
+  pub const MOB_SPAWN     : &'static str = "mobs/spawn";
+  /// Where idle mobs wander.
+  pub const MOB_WANDER    : &'static str = "mobs/wander";
+  /// What's dropped when things die or are opened.
+  pub const LOOT          : &'static str = "loot";
+  /// Which way dropped items pop out.
+  pub const ITEM_SCATTER  : &'static str = "items/scatter";
+  /// Which offers each trader makes.
+  pub const TRADE_OFFERS  : &'static str = "trade/offers";
+  /// The world's name.
+  pub const WORLD_NAME    : &'static str = "names/world";
+  /// Traders' names.
+  pub const TRADER_NAME   : &'static str = "names/trader";
+  /// Names made up on request.
+  pub const NAMES         : &'static str = "names/other";
+
+  /// Every subsystem.
+  pub const ALL: &'static [&'static str] = &[
+    TREES, STRUCTURES, TREE_GROWTH, MOB_SPAWN, MOB_WANDER, LOOT, ITEM_SCATTER, TRADE_OFFERS,
+    WORLD_NAME, TRADER_NAME, NAMES,
+  ];
+}
+
+/// The random stream for a subsystem at a voxel position.
+pub fn at(seed: u64, subsystem: &str, position: &Point3<i32>) -> rand::XorShiftRng {
+  stream(seed, subsystem, &[position.x as i64, position.y as i64, position.z as i64])
+}
+
+/// The random stream for a subsystem in a cell of a grid laid over the world, by its x and z.
+pub fn cell(seed: u64, subsystem: &str, cell: (i32, i32)) -> rand::XorShiftRng {
+  stream(seed, subsystem, &[cell.0 as i64, cell.1 as i64])
+}
+
+/// The random stream for a subsystem (one of `subsystem`) and a key in a world generated from
+/// `seed`.
+pub fn stream(seed: u64, subsystem: &str, key: &[i64]) -> rand::XorShiftRng {
+  // 64-bit FNV-1a over everything that names the stream, with a separator so the subsystem name and
+  // the key can't run into each other.
+  let mut hash: u64 = 0xcbf29ce484222325;
+  {
+    let mut eat = |byte: u8| {
+      hash ^= byte as u64;
+      hash = hash.wrapping_mul(0x100000001b3);
+    };
+    for i in 0 .. 8 {
+      eat((seed >> (8 * i)) as u8);
+    }
+    for &byte in subsystem.as_bytes() {
+      eat(byte);
+    }
+    eat(0xff);
+    for &k in key {
+      for i in 0 .. 8 {
+        eat((k >> (8 * i)) as u8);
+      }
+    }
+  }
+  let a = mix(hash);
+  let b = mix(a);
+  let words = [a as u32, (a >> 32) as u32, b as u32, (b >> 32) as u32];
+  if words == [0; 4] {
+    // XorShiftRng won't take an all-zero seed.
+    SeedableRng::from_seed([1, 0, 0, 0])
+  } else {
+    SeedableRng::from_seed(words)
+  }
+}
+
+/// The splitmix64 finalizer, to spread the hash over all the seed bits.
+fn mix(x: u64) -> u64 {
+  let x = x.wrapping_add(0x9e3779b97f4a7c15);
+  let x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+  let x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+  x ^ (x >> 31)
+}
+
+#[test]
+fn streams_are_stable_and_independent() {
+  use rand::Rng;
+
+  let draw = |seed: u64, subsystem: &str, key: &[i64]| -> Vec<u32> {
+    let mut rng = stream(seed, subsystem, key);
+    (0 .. 8).map(|_| rng.next_u32()).collect()
+  };
+
+  assert_eq!(draw(7, "names/world", &[]), draw(7, "names/world", &[]));
+  assert_eq!(draw(7, "trees", &[3, -4]), draw(7, "trees", &[3, -4]));
+
+  assert!(draw(7, "names/world", &[]) != draw(8, "names/world", &[]));
+  assert!(draw(7, "names/world", &[]) != draw(7, "names/npc", &[]));
+  assert!(draw(7, "trees", &[3, -4]) != draw(7, "trees", &[-4, 3]));
+  assert!(draw(7, "trees", &[1]) != draw(7, "trees1", &[]));
+}
+
+#[test]
+fn subsystems_are_distinct() {
+  for (i, a) in subsystem::ALL.iter().enumerate() {
+    for b in &subsystem::ALL[i + 1 ..] {
+      assert!(a != b);
+    }
+  }
+}
@@ -0,0 +1,343 @@
+//! Shapes that can be placed into the terrain besides full cubes, e.g. slabs and stairs.
+//! Each shape is a model: a list of axis-aligned box elements inside the unit cube. Models are data
+//! (see `parse`), so new shapes don't need code. The server reads them, tells clients their names,
+//! and brushes a model into the terrain by orienting it, scaling it up to a grid cell, and leaving
+//! out faces pressed against solid neighbors (see `culled_faces`). The terrain mesher then meshes
+//! that like any other terrain, and collision and persistence follow it too.
+
+use cgmath::Vector3;
+
+/// An axis-aligned box inside the unit cube, as (low corner, high corner).
+pub type Element = ([f32; 3], [f32; 3]);
+
+/// The shapes every server starts with, unless it's given its own.
+pub const DEFAULT_MODELS: &'static str = "
+shape slab
+box 0 0 0  1 0.5 1
+
+shape stairs
+box 0 0 0    1 0.5 1
+box 0 0.5 0.5  1 1 1
+
+shape fence
+# post
+box 0.375 0 0.375  0.625 1 0.625
+# rails
+box 0 0.375 0.4375  1 0.5 0.5625
+box 0 0.75 0.4375  1 0.875 0.5625
+
+shape pane
+box 0 0 0.4375  1 1 0.5625
+
+# Beams and logs line up with the direction they're placed in.
+shape beam axial
+box 0.25 0 0.25  0.75 1 0.75
+
+shape log axial
+box 0.0625 0 0.0625  0.9375 1 0.9375
+
+shape cube
+box 0 0 0  1 1 1
+";
+
+#[derive(Debug, Clone, PartialEq)]
+/// A shape that can be placed.
+pub struct Model {
+  /// What players and data files call it.
+  pub name     : String,
+  /// The boxes that make it up, unrotated.
+  pub elements : Vec<Element>,
+  /// Whether it lines up with the direction it's placed in, rather than standing upright.
+  pub axial    : bool,
+}
+
+/// Parse a list of models: each is a `shape <name>` line, with `axial` after the name if it lines
+/// up with the direction it's placed in, followed by a `box x0 y0 z0 x1 y1 z1` line for each of its
+/// elements, as low and high corners inside the unit cube. Blank lines, and anything after a `#`, are
+/// ignored.
+pub fn parse(text: &str) -> Result<Vec<Model>, String> {
+  let mut models: Vec<Model> = Vec::new();
+  for (i, line) in text.lines().enumerate() {
+    let line = line.split('#').next().unwrap().trim();
+    if line.is_empty() {
+      continue
+    }
+    let words: Vec<&str> = line.split_whitespace().collect();
+    match words[0] {
+      "shape" => {
+        let axial =
+          match words.len() {
+            2 => false,
+            3 if words[2] == "axial" => true,
+            _ => return Err(format!("line {}: expected `shape name` or `shape name axial`", i + 1)),
+          };
+        if models.iter().any(|model| model.name == words[1]) {
+          return Err(format!("line {}: there's already a shape called {:?}", i + 1, words[1]))
+        }
+        models.push(Model {
+          name     : String::from(words[1]),
+          elements : Vec::new(),
+          axial    : axial,
+        });
+      },
+      "box" => {
+        let model =
+          match models.last_mut() {
+            None => return Err(format!("line {}: boxes go after the `shape` line they belong to", i + 1)),
+            Some(model) => model,
+          };
+        let mut coords = Vec::new();
+        for word in &words[1 ..] {
+          coords.push(try!(word.parse::<f32>().map_err(|_| format!("line {}: invalid coordinate {:?}", i + 1, word))));
+        }
+        if coords.len() != 6 {
+          return Err(format!("line {}: expected `box x0 y0 z0 x1 y1 z1`", i + 1))
+        }
+        let low = [coords[0], coords[1], coords[2]];
+        let high = [coords[3], coords[4], coords[5]];
+        for d in 0 .. 3 {
+          if !(0.0 <= low[d] && low[d] < high[d] && high[d] <= 1.0) {
+            return Err(format!("line {}: boxes go from low to high corners inside the unit cube", i + 1))
+          }
+        }
+        model.elements.push((low, high));
+      },
+      word => return Err(format!("line {}: expected `shape` or `box`, not {:?}", i + 1, word)),
+    }
+  }
+  if models.is_empty() {
+    return Err(String::from("no shapes"))
+  }
+  if let Some(model) = models.iter().find(|model| model.elements.is_empty()) {
+    return Err(format!("shape {:?} has no boxes", model.name))
+  }
+  Ok(models)
+}
+
+/// The models in `DEFAULT_MODELS`.
+pub fn default_models() -> Vec<Model> {
+  parse(DEFAULT_MODELS).unwrap()
+}
+
+/// The model with a given name.
+pub fn find<'a>(models: &'a [Model], name: &str) -> Option<&'a Model> {
+  models.iter().find(|model| model.name == name)
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+/// Horizontal direction a shape faces. Unrotated shapes face south (+z).
+#[allow(missing_docs)]
+pub enum Facing {
+  South,
+  West,
+  North,
+  East,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+/// The axis that a shape's "up" is aligned with.
+#[allow(missing_docs)]
+pub enum Axis {
+  X,
+  Y,
+  Z,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[allow(missing_docs)]
+pub struct Orientation {
+  pub facing : Facing,
+  pub axis   : Axis,
+}
+
+impl Orientation {
+  /// The orientation a shape should be placed with by someone looking in `direction`.
+  /// Shapes face the way the player is looking, except axial shapes (e.g. beams), which line up with it instead.
+  pub fn looking(model: &Model, direction: Vector3<f32>) -> Orientation {
+    let facing =
+      if model.axial {
+        Facing::South
+      } else if direction.x.abs() > direction.z.abs() {
+        if direction.x > 0.0 { Facing::East } else { Facing::West }
+      } else {
+        if direction.z > 0.0 { Facing::South } else { Facing::North }
+      };
+    let axis =
+      if !model.axial {
+        Axis::Y
+      } else if direction.x.abs() >= direction.y.abs() && direction.x.abs() >= direction.z.abs() {
+        Axis::X
+      } else if direction.y.abs() >= direction.z.abs() {
+        Axis::Y
+      } else {
+        Axis::Z
+      };
+    Orientation {
+      facing : facing,
+      axis   : axis,
+    }
+  }
+
+  /// The orientation a placed shape turns to when it's rotated: a quarter turn about the vertical,
+  /// or for axial shapes, the next axis along.
+  pub fn rotated(&self, model: &Model) -> Orientation {
+    if model.axial {
+      let axis =
+        match self.axis {
+          Axis::X => Axis::Y,
+          Axis::Y => Axis::Z,
+          Axis::Z => Axis::X,
+        };
+      Orientation { axis: axis, .. *self }
+    } else {
+      let facing =
+        match self.facing {
+          Facing::South => Facing::West,
+          Facing::West  => Facing::North,
+          Facing::North => Facing::East,
+          Facing::East  => Facing::South,
+        };
+      Orientation { facing: facing, .. *self }
+    }
+  }
+
+  /// Transform a point in the unit cube, rotating about the cube's center.
+  pub fn apply(&self, p: [f32; 3]) -> [f32; 3] {
+    let (x, y, z) = (p[0] - 0.5, p[1] - 0.5, p[2] - 0.5);
+    // Point "up" along the axis.
+    let (x, y, z) =
+      match self.axis {
+        Axis::X => (y, -x, z),
+        Axis::Y => (x, y, z),
+        Axis::Z => (x, -z, y),
+      };
+    // Quarter turns about y, each taking +z to the next facing.
+    let turns =
+      match self.facing {
+        Facing::South => 0,
+        Facing::West  => 1,
+        Facing::North => 2,
+        Facing::East  => 3,
+      };
+    let (mut x, mut z) = (x, z);
+    for _ in 0 .. turns {
+      let (nx, nz) = (-z, x);
+      x = nx;
+      z = nz;
+    }
+    [x + 0.5, y + 0.5, z + 0.5]
+  }
+}
+
+impl Model {
+  /// The boxes that make up this shape in a given orientation.
+  pub fn oriented_elements(&self, orientation: &Orientation) -> Vec<Element> {
+    self.elements.iter()
+      .map(|&(low, high)| {
+        let a = orientation.apply(low);
+        let b = orientation.apply(high);
+        (
+          [f32::min(a[0], b[0]), f32::min(a[1], b[1]), f32::min(a[2], b[2])],
+          [f32::max(a[0], b[0]), f32::max(a[1], b[1]), f32::max(a[2], b[2])],
+        )
+      })
+      .collect()
+  }
+}
+
+/// Which of an element's faces, in the order -x, +x, -y, +y, -z, +z, can be left out because they're
+/// pressed flat against a solid neighbor of the cell. `solid` says which neighbors are solid, in the
+/// same order. Nothing could see those faces, and leaving them in would leave a seam where the shape
+/// meets its neighbor.
+pub fn culled_faces(&(low, high): &Element, solid: &[bool; 6]) -> [bool; 6] {
+  let mut culled = [false; 6];
+  for d in 0 .. 3 {
+    culled[2 * d] = solid[2 * d] && low[d] <= 0.0;
+    culled[2 * d + 1] = solid[2 * d + 1] && high[d] >= 1.0;
+  }
+  culled
+}
+
+#[cfg(test)]
+fn default_model(name: &str) -> Model {
+  find(&default_models(), name).unwrap().clone()
+}
+
+#[test]
+fn default_models_parse() {
+  let models = default_models();
+  let names: Vec<&str> = models.iter().map(|model| model.name.as_str()).collect();
+  assert_eq!(names, vec!("slab", "stairs", "fence", "pane", "beam", "log", "cube"));
+  assert_eq!(default_model("fence").elements.len(), 3);
+  assert!(default_model("beam").axial);
+  assert!(!default_model("stairs").axial);
+}
+
+#[test]
+fn parse_rejects_bad_models() {
+  assert!(parse("").is_err());
+  assert!(parse("box 0 0 0 1 1 1").is_err());
+  assert!(parse("shape slab").is_err());
+  assert!(parse("shape slab sideways\nbox 0 0 0 1 0.5 1").is_err());
+  assert!(parse("shape slab\nbox 0 0 0 1 0.5").is_err());
+  assert!(parse("shape slab\nbox 0 0 0 1 half 1").is_err());
+  assert!(parse("shape slab\nbox 0 0.5 0 1 0 1").is_err());
+  assert!(parse("shape slab\nbox 0 0 0 1 1.5 1").is_err());
+  assert!(parse("shape slab\nbox 0 0 0 1 0.5 1\nshape slab\nbox 0 0 0 1 1 1").is_err());
+  assert!(parse("slab\nbox 0 0 0 1 0.5 1").is_err());
+}
+
+#[test]
+fn stairs_face_the_viewer() {
+  let stairs = default_model("stairs");
+  let orientation = Orientation::looking(&stairs, Vector3::new(0.1, -0.5, -1.0));
+  assert_eq!(orientation, Orientation { facing: Facing::North, axis: Axis::Y });
+  // The upper step of unrotated stairs is toward +z; facing north, it's toward -z.
+  let elements = stairs.oriented_elements(&orientation);
+  assert_eq!(elements[1], ([0.0, 0.5, 0.0], [1.0, 1.0, 0.5]));
+}
+
+#[test]
+fn beams_follow_the_view_axis() {
+  let beam = default_model("beam");
+  let orientation = Orientation::looking(&beam, Vector3::new(1.0, 0.2, 0.3));
+  assert_eq!(orientation.axis, Axis::X);
+  let elements = beam.oriented_elements(&orientation);
+  assert_eq!(elements[0], ([0.0, 0.25, 0.25], [1.0, 0.75, 0.75]));
+}
+
+#[test]
+fn rotating_comes_back_around() {
+  let stairs = default_model("stairs");
+  let log = default_model("log");
+  let upright = Orientation { facing: Facing::South, axis: Axis::Y };
+  assert_eq!(upright.rotated(&stairs), Orientation { facing: Facing::West, axis: Axis::Y });
+  assert_eq!(upright.rotated(&log), Orientation { facing: Facing::South, axis: Axis::Z });
+  let mut orientation = upright;
+  for _ in 0 .. 4 {
+    orientation = orientation.rotated(&stairs);
+  }
+  assert_eq!(orientation, upright);
+  for _ in 0 .. 3 {
+    orientation = orientation.rotated(&log);
+  }
+  assert_eq!(orientation, upright);
+}
+
+#[test]
+fn faces_against_solid_neighbors_are_culled() {
+  let slab = default_model("slab").elements[0];
+  // Solid ground below, and a solid neighbor to the east.
+  let solid = [false, true, true, false, false, false];
+  // The bottom and east faces are flush with the cell, so they go; the top is halfway up, so it stays.
+  assert_eq!(culled_faces(&slab, &solid), [false, true, true, false, false, false]);
+
+  // The pane sits in the middle of the cell along z, so it's not against its -z or +z neighbors.
+  let pane = default_model("pane").elements[0];
+  assert_eq!(culled_faces(&pane, &[true; 6]), [true, true, true, true, false, false]);
+
+  // Nothing's culled against open air.
+  let cube = default_model("cube").elements[0];
+  assert_eq!(culled_faces(&cube, &[false; 6]), [false; 6]);
+  assert_eq!(culled_faces(&cube, &[true; 6]), [true; 6]);
+}
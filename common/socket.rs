@@ -5,11 +5,23 @@ use std;
 use std::convert::AsRef;
 use std::io::{Read, Write};
 use std::time::Duration;
+use time;
 
-/// A send-only socket.
+/// The shortest wait before reconnecting a socket that failed to send.
+const MIN_BACKOFF_NS: u64 = 100_000_000;
+/// The longest wait before reconnecting a socket that keeps failing to send.
+const MAX_BACKOFF_NS: u64 = 10_000_000_000;
+
+/// A send-only socket. When a send fails, the socket reconnects and tries again, but not until a
+/// backoff has passed, which doubles with each failure in a row; until then, sends fail right away.
 pub struct SendSocket {
   socket: Socket,
   endpoint: Endpoint,
+  url: String,
+  /// How many sends in a row have failed.
+  failures: u32,
+  /// When to reconnect, after a failure.
+  retry_at_ns: Option<u64>,
 }
 
 fn as_millis(duration: Duration) -> isize {
@@ -20,18 +32,58 @@ impl SendSocket {
   #[allow(missing_docs)]
   pub fn new(url: &str, timeout: Option<Duration>) -> SendSocket {
     let mut socket = Socket::new(Protocol::Push).unwrap();
-    timeout.map(|timeout| socket.set_receive_timeout(as_millis(timeout)).unwrap());
+    timeout.map(|timeout| {
+      socket.set_send_timeout(as_millis(timeout)).unwrap();
+      socket.set_receive_timeout(as_millis(timeout)).unwrap();
+    });
     let endpoint = socket.connect(url).unwrap();
 
     SendSocket {
       socket: socket,
       endpoint: endpoint,
+      url: url.to_owned(),
+      failures: 0,
+      retry_at_ns: None,
     }
   }
 
-  /// Block until we can send this socket a message.
+  /// Block until we can send this socket a message. If it's backing off after a failure, this fails
+  /// without trying.
   pub fn write(&mut self, msg: &[u8]) -> std::io::Result<()> {
-    self.socket.write(msg).map(|_| ())
+    let now = time::precise_time_ns();
+    if let Some(retry_at_ns) = self.retry_at_ns {
+      if now < retry_at_ns {
+        return Err(std::io::Error::new(std::io::ErrorKind::Other, "waiting to reconnect"))
+      }
+      self.reconnect();
+    }
+    match self.socket.write(msg) {
+      Ok(_) => {
+        if self.failures > 0 {
+          info!("Reconnected to {}", self.url);
+        }
+        self.failures = 0;
+        self.retry_at_ns = None;
+        Ok(())
+      },
+      Err(err) => {
+        self.failures += 1;
+        let backoff = std::cmp::min(MIN_BACKOFF_NS << std::cmp::min(self.failures - 1, 16), MAX_BACKOFF_NS);
+        if self.failures == 1 {
+          warn!("Error sending to {}: {:?}; reconnecting", self.url, err);
+        }
+        self.retry_at_ns = Some(now + backoff);
+        Err(err)
+      },
+    }
+  }
+
+  fn reconnect(&mut self) {
+    self.endpoint.shutdown().unwrap_or(());
+    match self.socket.connect(&self.url) {
+      Ok(endpoint) => self.endpoint = endpoint,
+      Err(err) => warn!("Error reconnecting to {}: {:?}", self.url, err),
+    }
   }
 
   /// Terminate this connection.
@@ -72,13 +124,18 @@ impl ReceiveSocket {
     }
   }
 
-  /// Block until a message can be fetched from this socket.
-  pub fn read(&mut self) -> Option<Vec<u8>> {
+  /// Block until a message can be fetched from this socket. If none comes before the timeout, this
+  /// is `Empty`; so is any other error, after a moment's wait so one that lasts doesn't spin.
+  pub fn read(&mut self) -> Result<Vec<u8>> {
     let mut msg = Vec::new();
-    if self.socket.read_to_end(&mut msg).is_ok() {
-      Some(msg)
-    } else {
-      None
+    match self.socket.read_to_end(&mut msg) {
+      Ok(_) => Result::Success(msg),
+      Err(ref err) if err.kind() == std::io::ErrorKind::TimedOut => Result::Empty,
+      Err(err) => {
+        warn!("Error reading from socket: {:?}", err);
+        std::thread::sleep(Duration::from_millis(100));
+        Result::Empty
+      },
     }
   }
 
@@ -90,10 +147,10 @@ impl ReceiveSocket {
       Ok(_) => Result::Success(msg),
       Err(Error::TryAgain) => Result::Empty,
       Err(Error::Terminating) => Result::Terminating,
-      Err(_) => {
-        result.unwrap();
-        unreachable!()
-      }
+      Err(err) => {
+        warn!("Error reading from socket: {:?}", err);
+        Result::Empty
+      },
     }
   }
 
@@ -80,6 +80,21 @@ impl T {
       position: *position,
     }
   }
+
+  /// Change how far out to load. If it shrinks, everything past the new distance gets unloaded.
+  pub fn set_max_load_distance(&mut self, max_load_distance: u32) {
+    if max_load_distance == self.max_load_distance {
+      return
+    }
+    let old_distance = self.max_load_distance;
+    self.max_load_distance = max_load_distance;
+    if let Some(position) = self.last_position {
+      for radius in max_load_distance .. old_distance {
+        self.to_recheck.extend(cube_shell(&position, radius as i32).into_iter());
+      }
+      self.to_load = Some(surroundings_iter(position, max_load_distance as i32));
+    }
+  }
 }
 
 /// Iterator for the updates from a T.
@@ -115,3 +130,20 @@ impl<'a> Iterator for Updates<'a> {
     })
   }
 }
+
+#[test]
+fn shrinking_unloads_the_edges() {
+  let center = Point3::new(0, 0, 0);
+  let mut loader = new(4, Vec::new());
+  assert_eq!(loader.updates(&center).count(), 7 * 7 * 7);
+
+  loader.set_max_load_distance(2);
+  let mut unloads = 0;
+  for (position, load_type) in loader.updates(&center) {
+    if let LoadType::Unload = load_type {
+      assert!(distance_between(&center, &position) > 2);
+      unloads += 1;
+    }
+  }
+  assert_eq!(unloads, 7 * 7 * 7 - 5 * 5 * 5);
+}
@@ -0,0 +1,152 @@
+//! Opt-in anonymous performance reporting.
+//! Nothing leaves the machine: reports are written to a local file, which the user can choose to share.
+
+use std;
+use std::io::Write;
+
+/// Set this environment variable to opt in to telemetry.
+pub const ENABLE_VAR: &'static str = "PLAYFORM_TELEMETRY";
+
+/// Has the user opted in to telemetry?
+pub fn enabled() -> bool {
+  std::env::var_os(ENABLE_VAR).is_some()
+}
+
+/// How many of the most recent samples are kept for percentiles. Frame times are pushed every
+/// frame for as long as the client runs, so keeping them all would grow without bound.
+pub const MAX_SAMPLES: usize = 1 << 16;
+
+/// A collection of samples (e.g. frame or tick durations), to be summarized.
+pub struct Samples {
+  /// The most recent samples, at most `MAX_SAMPLES` of them, overwritten oldest first.
+  samples : Vec<u64>,
+  /// Where the next sample goes once `samples` is full.
+  next    : usize,
+  /// How many samples have ever been pushed.
+  count   : usize,
+  /// The sum of every sample ever pushed.
+  total   : u64,
+}
+
+#[allow(missing_docs)]
+pub fn new() -> Samples {
+  Samples {
+    samples : Vec::new(),
+    next    : 0,
+    count   : 0,
+    total   : 0,
+  }
+}
+
+impl Samples {
+  /// Record a single sample.
+  pub fn push(&mut self, sample: u64) {
+    if self.samples.len() < MAX_SAMPLES {
+      self.samples.push(sample);
+    } else {
+      self.samples[self.next] = sample;
+      self.next = (self.next + 1) % MAX_SAMPLES;
+    }
+    self.count += 1;
+    self.total = self.total.saturating_add(sample);
+  }
+
+  /// How many samples have ever been pushed, including ones no longer kept.
+  pub fn len(&self) -> usize {
+    self.count
+  }
+
+  /// The mean of every sample ever pushed.
+  pub fn mean(&self) -> Option<u64> {
+    if self.count == 0 {
+      return None
+    }
+    Some(self.total / self.count as u64)
+  }
+
+  /// The sample at the given percentile, in [0, 100], among the most recent `MAX_SAMPLES`.
+  pub fn percentile(&self, percentile: u32) -> Option<u64> {
+    assert!(percentile <= 100);
+    if self.samples.is_empty() {
+      return None
+    }
+    let mut sorted = self.samples.clone();
+    sorted.sort();
+    let idx = (sorted.len() - 1) * percentile as usize / 100;
+    Some(sorted[idx])
+  }
+}
+
+/// A list of named statistics to write out.
+pub struct Report {
+  entries: Vec<(&'static str, String)>,
+}
+
+#[allow(missing_docs)]
+pub fn report() -> Report {
+  let mut report =
+    Report {
+      entries: Vec::new(),
+    };
+  report.push("os", std::env::consts::OS);
+  report.push("arch", std::env::consts::ARCH);
+  report
+}
+
+impl Report {
+  /// Add a named statistic to the report.
+  pub fn push<V: std::fmt::Display>(&mut self, name: &'static str, value: V) {
+    self.entries.push((name, format!("{}", value)));
+  }
+
+  /// Summarize some samples as a mean and a spread of percentiles.
+  pub fn push_samples(&mut self, name: &'static str, samples: &Samples) {
+    let fmt = |x: Option<u64>| x.map(|x| format!("{}", x)).unwrap_or_else(|| String::from("none"));
+    self.entries.push((name, format!(
+      "count={} mean={} p50={} p90={} p99={}",
+      samples.len(),
+      fmt(samples.mean()),
+      fmt(samples.percentile(50)),
+      fmt(samples.percentile(90)),
+      fmt(samples.percentile(99)),
+    )));
+  }
+
+  /// Write the report into a file, one `name = value` line per statistic.
+  pub fn write_to<P: AsRef<std::path::Path>>(&self, path: P) -> std::io::Result<()> {
+    let mut file = try!(std::fs::File::create(path));
+    for &(name, ref value) in &self.entries {
+      try!(file.write_fmt(format_args!("{} = {}\n", name, value)));
+    }
+    Ok(())
+  }
+}
+
+#[test]
+fn percentiles() {
+  let mut samples = new();
+  assert_eq!(samples.percentile(50), None);
+  for x in (1 .. 101).rev() {
+    samples.push(x);
+  }
+  assert_eq!(samples.percentile(0), Some(1));
+  assert_eq!(samples.percentile(50), Some(50));
+  assert_eq!(samples.percentile(99), Some(99));
+  assert_eq!(samples.percentile(100), Some(100));
+  assert_eq!(samples.mean(), Some(50));
+}
+
+#[test]
+fn only_recent_samples_are_kept() {
+  let mut samples = new();
+  for _ in 0 .. MAX_SAMPLES {
+    samples.push(1);
+  }
+  for _ in 0 .. MAX_SAMPLES {
+    samples.push(3);
+  }
+  assert_eq!(samples.samples.len(), MAX_SAMPLES);
+  assert_eq!(samples.len(), 2 * MAX_SAMPLES);
+  assert_eq!(samples.mean(), Some(2));
+  assert_eq!(samples.percentile(0), Some(3));
+}
@@ -0,0 +1,123 @@
+//! Thread priorities and core pinning, so heavy terrain generation can't starve the threads players notice.
+//! Threads are ranked render > tick > gaia. Priorities are only ever lowered from the default,
+//! which doesn't need any special privileges.
+
+use libc;
+use std;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// What a thread does, which determines how it's scheduled.
+pub enum Role {
+  /// Draws frames and handles input.
+  Render,
+  /// Runs the game simulation, or applies updates from the server.
+  Tick,
+  /// Generates terrain. `index` distinguishes multiple gaia threads.
+  Gaia { index: usize },
+}
+
+#[derive(Debug, Clone, Copy)]
+#[allow(missing_docs)]
+pub struct Config {
+  /// Whether to lower the priority of less latency-sensitive threads.
+  pub priorities : bool,
+  /// Whether to pin each thread to its own core (where there are enough to go around).
+  pub pin_cores  : bool,
+}
+
+#[allow(missing_docs)]
+pub fn default() -> Config {
+  Config {
+    priorities : true,
+    pin_cores  : false,
+  }
+}
+
+/// The niceness for each role. Higher is lower priority.
+fn niceness(role: Role) -> i32 {
+  match role {
+    Role::Render => 0,
+    Role::Tick => 4,
+    Role::Gaia { .. } => 10,
+  }
+}
+
+/// The core to pin each role to. Render and tick threads get the first cores, and gaia threads
+/// share whatever's left, wrapping around onto the first cores if there aren't enough.
+fn core(role: Role, cores: usize) -> usize {
+  let core =
+    match role {
+      Role::Render => 0,
+      Role::Tick => 1,
+      Role::Gaia { index } => 2 + index,
+    };
+  core % cores
+}
+
+/// The number of cores available.
+pub fn cores() -> usize {
+  let n = unsafe { libc::sysconf(libc::_SC_NPROCESSORS_ONLN) };
+  if n < 1 { 1 } else { n as usize }
+}
+
+/// Configure the calling thread for `role`.
+pub fn apply(config: &Config, role: Role) {
+  if config.priorities {
+    if let Err(err) = set_niceness(niceness(role)) {
+      warn!("Couldn't set priority of {:?} thread: {}", role, err);
+    }
+  }
+  if config.pin_cores {
+    let core = core(role, cores());
+    match pin_to(core) {
+      Ok(()) => info!("Pinned {:?} thread to core {}", role, core),
+      Err(err) => warn!("Couldn't pin {:?} thread to core {}: {}", role, core, err),
+    }
+  }
+}
+
+#[cfg(target_os = "linux")]
+fn set_niceness(niceness: i32) -> Result<(), std::io::Error> {
+  // On Linux, niceness is per-thread, keyed by thread id.
+  let r =
+    unsafe {
+      let tid = libc::syscall(libc::SYS_gettid) as libc::id_t;
+      libc::setpriority(libc::PRIO_PROCESS, tid, niceness)
+    };
+  if r == 0 { Ok(()) } else { Err(std::io::Error::last_os_error()) }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_niceness(_niceness: i32) -> Result<(), std::io::Error> {
+  Err(std::io::Error::new(std::io::ErrorKind::Other, "per-thread priorities aren't supported on this platform"))
+}
+
+#[cfg(target_os = "linux")]
+fn pin_to(core: usize) -> Result<(), std::io::Error> {
+  let r =
+    unsafe {
+      let mut set: libc::cpu_set_t = std::mem::zeroed();
+      libc::CPU_SET(core, &mut set);
+      // 0 means the calling thread.
+      libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set)
+    };
+  if r == 0 { Ok(()) } else { Err(std::io::Error::last_os_error()) }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn pin_to(_core: usize) -> Result<(), std::io::Error> {
+  Err(std::io::Error::new(std::io::ErrorKind::Other, "core pinning isn't supported on this platform"))
+}
+
+#[test]
+fn ranks_render_over_tick_over_gaia() {
+  assert!(niceness(Role::Render) < niceness(Role::Tick));
+  assert!(niceness(Role::Tick) < niceness(Role::Gaia { index: 0 }));
+}
+
+#[test]
+fn gaia_threads_wrap_around_cores() {
+  assert_eq!(core(Role::Gaia { index: 0 }, 4), 2);
+  assert_eq!(core(Role::Gaia { index: 3 }, 4), 1);
+  assert_eq!(core(Role::Tick, 1), 0);
+}
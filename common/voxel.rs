@@ -1,6 +1,9 @@
 //! Voxel implementation for terrain
 
+use bincode;
+use fnv;
 use isosurface_extraction;
+use std::hash::Hasher;
 use voxel_data;
 
 pub use voxel_data::bounds;
@@ -11,7 +14,10 @@ pub use voxel_data::impls::surface_vertex::unwrap;
 #[allow(missing_docs)]
 pub type T = voxel_data::impls::surface_vertex::T<Material>;
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+/// World-space height of the sea surface. Generated terrain is flooded up to here.
+pub const SEA_LEVEL: i32 = -8;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 #[allow(missing_docs)]
 /// Terrain materials
 pub enum Material {
@@ -21,6 +27,34 @@ pub enum Material {
   Leaves = 3,
   Stone = 4,
   Marble = 5,
+  Sand = 6,
+  Snow = 7,
+  Gravel = 8,
+  Wire = 9,
+  PoweredWire = 10,
+  Lever = 11,
+  PoweredLever = 12,
+  PressurePlate = 13,
+  PoweredPressurePlate = 14,
+  Lamp = 15,
+  LitLamp = 16,
+  Piston = 17,
+  ExtendedPiston = 18,
+  PistonHead = 19,
+  Water = 20,
+  Coal = 21,
+  Iron = 22,
+  Crystal = 23,
+}
+
+impl Material {
+  /// Whether this material blocks movement and is drawn as part of the terrain surface.
+  pub fn is_solid(&self) -> bool {
+    match *self {
+      Material::Empty | Material::Water => false,
+      _ => true,
+    }
+  }
 }
 
 #[allow(missing_docs)]
@@ -51,8 +85,31 @@ pub mod mosaic {
   pub use voxel_data::mosaic::*;
 }
 
+/// A checksum of some voxels and their bounds, so that copies of them can be compared without sending them.
+/// The order of the voxels matters.
+pub fn checksum(voxels: &[(bounds::T, T)]) -> u64 {
+  checksum_bytes(&bincode::serialize(voxels, bincode::Infinite).unwrap())
+}
+
+/// A checksum of some serialized voxel data, e.g. a record in a save file.
+pub fn checksum_bytes(bytes: &[u8]) -> u64 {
+  let mut hasher = fnv::FnvHasher::default();
+  hasher.write(bytes);
+  hasher.finish()
+}
+
 impl isosurface_extraction::dual_contouring::material::T for Material {
   fn is_opaque(&self) -> bool {
-    *self != Material::Empty
+    self.is_solid()
   }
 }
+
+#[test]
+fn checksum_changes_with_contents() {
+  let stone = vec!((bounds::new(0, 0, 0, 0), Volume(Material::Stone)));
+  let sand = vec!((bounds::new(0, 0, 0, 0), Volume(Material::Sand)));
+  let moved = vec!((bounds::new(1, 0, 0, 0), Volume(Material::Stone)));
+  assert_eq!(checksum(&stone), checksum(&stone.clone()));
+  assert!(checksum(&stone) != checksum(&sand));
+  assert!(checksum(&stone) != checksum(&moved));
+}
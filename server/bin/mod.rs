@@ -20,28 +20,40 @@ fn main() {
 
   let mut args = env::args();
   args.next().unwrap();
+  let mut args = args.peekable();
+
+  if args.peek().map(|arg| arg == "--data-dir").unwrap_or(false) {
+    args.next();
+    let dir = args.next().expect("--data-dir needs a directory");
+    // This is where the world and everything else the server writes goes; see common::data_dir.
+    env::set_var("PLAYFORM_DATA_DIR", dir);
+  }
+
   let listen_url = args.next().unwrap_or_else(|| String::from("ipc:///tmp/server.ipc"));
   assert!(args.next().is_none());
 
   info!("Listening on {}.", listen_url);
 
   let quit_signal = Mutex::new(false);
+  let commands = Mutex::new(Vec::new());
 
   let _quit_thread =
     unsafe {
       let quit_signal = &quit_signal;
+      let commands = &commands;
       thread_scoped::scoped(move || {
-        wait_for_quit();
+        wait_for_quit(commands);
         *quit_signal.lock().unwrap() = true;
         // Close all sockets.
         nanomsg::Socket::terminate();
       })
     };
 
-  server_lib::run(listen_url.borrow(), &quit_signal);
+  server_lib::run(listen_url.borrow(), &quit_signal, &commands);
 }
 
-fn wait_for_quit() {
+/// Read console commands until "quit" comes along, passing the rest on to the server.
+fn wait_for_quit(commands: &Mutex<Vec<server_lib::admin::Command>>) {
   loop {
     let mut line = String::new();
     std::io::stdin().read_line(&mut line).unwrap();
@@ -49,8 +61,11 @@ fn wait_for_quit() {
     if line == "quit\n" {
       println!("Quitting");
       return
-    } else {
-      println!("Unrecognized command: {:?}", line);
+    }
+
+    match server_lib::admin::parse(&line) {
+      Ok(command) => commands.lock().unwrap().push(command),
+      Err(err) => println!("{}", err),
     }
   }
 }
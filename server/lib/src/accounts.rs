@@ -0,0 +1,219 @@
+//! Who players are. Each client plays under a name, which the first client to use it claims along
+//! with a token that has to be presented to play as that name again. Where each player was and what
+//! they were carrying is saved under their name, so they pick up where they left off next time.
+
+use bincode;
+use cgmath::Point3;
+use collision::Aabb3;
+use std;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use common::protocol;
+
+use inventory;
+use player;
+use server;
+
+/// The longest a player's name can be.
+const MAX_NAME_LENGTH: usize = 16;
+
+/// What's saved about a player between visits.
+pub struct Saved {
+  /// Where their center was.
+  pub position : Point3<f32>,
+  /// The contents of each inventory slot.
+  pub slots    : Vec<Option<protocol::ItemStack>>,
+  /// The inventory slot they had selected.
+  pub selected : usize,
+}
+
+#[allow(missing_docs)]
+pub struct T {
+  root : PathBuf,
+}
+
+/// Keep accounts in the directory `root`.
+pub fn open(root: &Path) -> T {
+  if let Err(err) = std::fs::create_dir_all(root) {
+    warn!("Error creating player directory {:?}: {:?}", root, err);
+  }
+  T {
+    root : root.to_owned(),
+  }
+}
+
+/// Names are used in file names, so they're kept to letters, digits, `-` and `_`.
+fn check_name(name: &str) -> Result<(), String> {
+  if name.is_empty() || name.len() > MAX_NAME_LENGTH {
+    return Err(format!("Names must be 1 to {} characters long.", MAX_NAME_LENGTH))
+  }
+  if !name.chars().all(|c| c.is_digit(36) || c == '-' || c == '_') {
+    return Err(String::from("Names can only have letters, digits, '-' and '_' in them."))
+  }
+  Ok(())
+}
+
+/// What a name is filed under. Names that only differ in case are the same name: otherwise, on
+/// filesystems that ignore case, they'd share files, and one player could take over another's save.
+fn key(name: &str) -> String {
+  name.to_ascii_lowercase()
+}
+
+/// A hash of a token, so tokens themselves aren't kept on disk. This is 64-bit FNV-1a, which keeps a
+/// casual look at the save directory from giving tokens away, but won't stand up to a determined attacker.
+fn token_hash(name: &str, token: &str) -> u64 {
+  let mut hash: u64 = 0xcbf29ce484222325;
+  for &byte in name.as_bytes().iter().chain(&[0]).chain(token.as_bytes()) {
+    hash ^= byte as u64;
+    hash = hash.wrapping_mul(0x100000001b3);
+  }
+  hash
+}
+
+impl T {
+  fn token_path(&self, name: &str) -> PathBuf {
+    self.root.join(format!("{}.token", key(name)))
+  }
+
+  fn state_path(&self, name: &str) -> PathBuf {
+    self.root.join(format!("{}.bin", key(name)))
+  }
+
+  /// Names used to be filed as they were typed. Move the files of one that was over to where it's
+  /// filed now, if there are any and nothing's there yet.
+  fn migrate(&self, name: &str) {
+    let moves = [
+      (self.root.join(format!("{}.token", name)), self.token_path(name)),
+      (self.root.join(format!("{}.bin", name)), self.state_path(name)),
+    ];
+    for &(ref old, ref new) in &moves {
+      if old != new && old.is_file() && !new.exists() {
+        info!("Moving {:?} to {:?}", old, new);
+        if let Err(err) = std::fs::rename(old, new) {
+          warn!("Error moving {:?} to {:?}: {:?}", old, new, err);
+        }
+      }
+    }
+  }
+
+  fn save_token(&self, name: &str, hash: u64) -> Result<(), String> {
+    let path = self.token_path(name);
+    std::fs::File::create(&path)
+    .map_err(|err| format!("{:?}", err))
+    .and_then(|mut file| {
+      bincode::serialize_into(&mut file, &hash, bincode::Infinite)
+      .map_err(|err| format!("{:?}", err))
+    })
+    .map_err(|err| format!("Error saving {:?}: {}", path, err))
+  }
+
+  /// Check that a client can play as `name`: it's a valid name that isn't in use by another client,
+  /// and `token` is the one it was claimed with. Unclaimed names are claimed.
+  pub fn log_in(&self, server: &server::T, credentials: &protocol::Credentials) -> Result<(), String> {
+    try!(check_name(&credentials.name));
+    let name = key(&credentials.name);
+    if server.clients.lock().unwrap().values().any(|client| key(&client.name) == name) {
+      return Err(format!("Someone is already playing as {}.", credentials.name))
+    }
+
+    self.migrate(&credentials.name);
+    let hash = token_hash(&name, &credentials.token);
+    let path = self.token_path(&name);
+    match std::fs::File::open(&path) {
+      Ok(mut file) => {
+        let mut bytes = Vec::new();
+        try!(file.read_to_end(&mut bytes).map_err(|err| format!("Error reading {:?}: {:?}", path, err)));
+        let saved: u64 = try!(bincode::deserialize(&bytes).map_err(|err| format!("Error reading {:?}: {:?}", path, err)));
+        if saved != hash {
+          // Tokens used to be hashed with the name as it was typed.
+          if saved != token_hash(&credentials.name, &credentials.token) {
+            return Err(format!("That isn't the token {} was claimed with.", credentials.name))
+          }
+          try!(self.save_token(&name, hash));
+        }
+      },
+      Err(_) => {
+        info!("Claiming the name {}", credentials.name);
+        try!(self.save_token(&name, hash));
+      },
+    }
+    Ok(())
+  }
+
+  /// What was saved about a player, if they've played before.
+  pub fn load(&self, name: &str) -> Option<Saved> {
+    let path = self.state_path(name);
+    let mut bytes = Vec::new();
+    match std::fs::File::open(&path) {
+      Err(_) => return None,
+      Ok(mut file) => {
+        if let Err(err) = file.read_to_end(&mut bytes) {
+          warn!("Error reading {:?}: {:?}", path, err);
+          return None
+        }
+      },
+    }
+    match bincode::deserialize::<(Point3<f32>, Vec<Option<protocol::ItemStack>>, u64)>(&bytes) {
+      Ok((position, slots, selected)) => {
+        Some(Saved {
+          position : position,
+          slots    : slots,
+          selected : selected as usize,
+        })
+      },
+      Err(err) => {
+        warn!("Ignoring corrupt player save {:?}: {:?}", path, err);
+        None
+      },
+    }
+  }
+
+  /// Save where a player is and what they're carrying.
+  pub fn save(&self, player: &player::T) {
+    let path = self.state_path(&player.name);
+    let state = (player.position, player.inventory.slots().to_vec(), player.inventory.selected_slot() as u64);
+    let r =
+      bincode::serialize(&state, bincode::Infinite)
+      .map_err(|err| format!("{:?}", err))
+      .and_then(|bytes| {
+        std::fs::File::create(&path)
+        .and_then(|mut file| file.write_all(&bytes))
+        .map_err(|err| format!("{:?}", err))
+      });
+    if let Err(err) = r {
+      warn!("Error saving {:?}: {}", path, err);
+    }
+  }
+}
+
+/// Where a player whose center was at `position` goes back to, with the bounds of a player who's
+/// just joined.
+pub fn bounds_at(position: Point3<f32>) -> Aabb3<f32> {
+  let spawn = player::spawn_bounds();
+  let half_size = (spawn.max - spawn.min) * 0.5;
+  Aabb3::new(position + -half_size, position + half_size)
+}
+
+/// The inventory a player had, or an empty one for new players.
+pub fn inventory(saved: Option<&Saved>) -> inventory::T {
+  match saved {
+    None => inventory::new(),
+    Some(saved) => inventory::restore(&saved.slots, saved.selected),
+  }
+}
+
+/// Save every player in the world.
+pub fn save_all(server: &server::T) {
+  for player in server.players.lock().unwrap().values() {
+    server.accounts.save(player);
+  }
+}
+
+#[test]
+fn names_differing_in_case_are_the_same_account() {
+  let accounts = open(&std::env::temp_dir().join("playform-accounts-test"));
+  assert_eq!(accounts.token_path("Bob"), accounts.token_path("bob"));
+  assert_eq!(accounts.state_path("BOB"), accounts.state_path("bob"));
+  assert_eq!(token_hash(&key("Bob"), "secret"), token_hash(&key("bob"), "secret"));
+}
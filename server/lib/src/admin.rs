@@ -0,0 +1,95 @@
+//! Commands typed into the server console.
+
+use cgmath::Point3;
+
+use boss;
+use spawn;
+
+/// Where to spawn something.
+#[derive(Debug, Clone, Copy)]
+pub enum Place {
+  #[allow(missing_docs)]
+  At(Point3<f32>),
+  /// Wherever the player with this id is looking.
+  LookedAtBy(u32),
+}
+
+/// Commands the server console can run, besides quitting.
+#[derive(Debug, Clone)]
+pub enum Command {
+  /// Generate and save all the terrain within this many regions of spawn.
+  Pregenerate(i32),
+  /// Start a fight with a boss of the named kind, standing at a position.
+  SpawnBoss(String, Point3<f32>),
+  /// Spawn a mob (or boss) of the named kind.
+  Spawn(String, Place),
+  /// Print the most recent recorded events that mention some text (or all of them, if it's empty).
+  Events(String),
+  /// Re-read the trade offers and spawn rules. See `reload`.
+  Reload,
+}
+
+/// Parse a line from the console.
+pub fn parse(line: &str) -> Result<Command, String> {
+  let mut words = line.split_whitespace();
+  match words.next() {
+    Some("pregenerate") => {
+      let radius =
+        match words.next().map(|radius| radius.parse()) {
+          Some(Ok(radius)) if radius >= 0 => radius,
+          _ => return Err(String::from("usage: pregenerate <radius in regions>")),
+        };
+      Ok(Command::Pregenerate(radius))
+    },
+    Some("events") => {
+      let filter: Vec<&str> = words.collect();
+      Ok(Command::Events(filter.join(" ")))
+    },
+    Some("reload") => Ok(Command::Reload),
+    Some("spawn") => {
+      let usage = || format!("usage: spawn <{}> (<x> <y> <z> | looking <player>)", spawn::names().join("|"));
+      let kind =
+        match words.next() {
+          Some(kind) if spawn::size(kind).is_some() => String::from(kind),
+          _ => return Err(usage()),
+        };
+      let rest: Vec<&str> = words.collect();
+      if rest.len() == 2 && rest[0] == "looking" {
+        return match rest[1].parse() {
+          Ok(player) => Ok(Command::Spawn(kind, Place::LookedAtBy(player))),
+          Err(_) => Err(usage()),
+        }
+      }
+      let coords: Vec<f32> =
+        match rest.iter().map(|word| word.parse()).collect() {
+          Ok(coords) => coords,
+          Err(_) => return Err(usage()),
+        };
+      if coords.len() != 3 {
+        return Err(usage())
+      }
+      Ok(Command::Spawn(kind, Place::At(Point3::new(coords[0], coords[1], coords[2]))))
+    },
+    Some("spawnboss") => {
+      let usage = || {
+        let kinds: Vec<&str> = boss::KINDS.iter().map(|kind| kind.name).collect();
+        format!("usage: spawnboss <{}> <x> <y> <z>", kinds.join("|"))
+      };
+      let kind =
+        match words.next() {
+          Some(kind) if boss::kind(kind).is_some() => String::from(kind),
+          _ => return Err(usage()),
+        };
+      let coords: Vec<f32> =
+        match words.map(|word| word.parse()).collect() {
+          Ok(coords) => coords,
+          Err(_) => return Err(usage()),
+        };
+      if coords.len() != 3 {
+        return Err(usage())
+      }
+      Ok(Command::SpawnBoss(kind, Point3::new(coords[0], coords[1], coords[2])))
+    },
+    _ => Err(format!("Unrecognized command: {:?}", line)),
+  }
+}
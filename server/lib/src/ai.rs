@@ -0,0 +1,329 @@
+//! Mob AI: each mob is in one of a few states (wandering, chasing or fleeing players), and finds its
+//! way around terrain with an A* search over the places it could stand.
+//! Kinds of mobs are `mob::Behavior`s that pick which state the mob's in, e.g. `hunt` and `shy`.
+
+use cgmath::{Point3, Vector3, InnerSpace, MetricSpace};
+use collision::Aabb3;
+use rand::Rng;
+use std;
+use std::collections::BinaryHeap;
+
+use common::fnv_map;
+use common::rng;
+
+use mob;
+use physics;
+use server;
+
+/// Mobs notice players this close.
+const SIGHT_RADIUS: f32 = 16.0;
+
+/// Mobs that noticed a player lose interest once they're this far away.
+const LOSE_RADIUS: f32 = 24.0;
+
+/// Chasing mobs stop this close to their player.
+const CHASE_DISTANCE: f32 = 2.0;
+
+/// How far from the nearest player fleeing mobs try to get.
+const FLEE_DISTANCE: f32 = 12.0;
+
+/// How far wandering mobs stray at a time.
+const WANDER_RADIUS: f32 = 8.0;
+
+/// A wandering mob that's standing still sets off again with this chance each tick.
+const WANDER_CHANCE: f32 = 1.0 / 60.0;
+
+const WANDER_SPEED: f32 = 0.05;
+const CHASE_SPEED: f32 = 0.15;
+const FLEE_SPEED: f32 = 0.2;
+
+/// Paths are recomputed this often, in ticks, so mobs keep up with things that move.
+const REPATH_INTERVAL: u32 = 20;
+
+/// Paths are recomputed straight away if the goal moves this far.
+const REPATH_DISTANCE: f32 = 2.0;
+
+/// A mob has reached a point on its path once it's this close, horizontally.
+const WAYPOINT_RADIUS: f32 = 0.3;
+
+/// A path search has arrived once it's this close to its goal.
+const ARRIVAL_RADIUS: f32 = 1.0;
+
+/// The most places a path search looks at before settling for getting as close as it can.
+const MAX_SEARCH: usize = 512;
+
+/// The farthest mobs will drop, in voxels.
+const MAX_DROP: i32 = 3;
+
+/// Path costs are kept as integers: this is the cost of walking one voxel.
+const STEP_COST: u32 = 10;
+
+/// The cost of climbing a step. It's a little more than walking, so mobs don't zig-zag up hills.
+const CLIMB_COST: u32 = 15;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// What a mob's up to.
+pub enum State {
+  /// Stroll to nearby spots, every so often.
+  Wander,
+  /// Go after the nearest player.
+  Chase,
+  /// Get away from the nearest player.
+  Flee,
+}
+
+#[allow(missing_docs)]
+pub struct T {
+  pub state : State,
+  /// Where the mob is headed.
+  goal      : Option<Point3<f32>>,
+  /// The rest of the path to `goal`, as the points for the mob's feet to pass through, last first.
+  path      : Vec<Point3<f32>>,
+  /// Ticks until the path is recomputed.
+  repath_in : u32,
+}
+
+#[allow(missing_docs)]
+pub fn new() -> T {
+  T {
+    state     : State::Wander,
+    goal      : None,
+    path      : Vec::new(),
+    repath_in : 0,
+  }
+}
+
+/// Chase players that come close, and wander otherwise.
+pub fn hunt(server: &server::T, mob: &mut mob::Mob) {
+  think(server, mob, State::Chase);
+}
+
+/// Run from players that come close, and wander otherwise.
+pub fn shy(server: &server::T, mob: &mut mob::Mob) {
+  think(server, mob, State::Flee);
+}
+
+/// The position of the player nearest a point, if there are any players.
+fn nearest_player(server: &server::T, p: &Point3<f32>) -> Option<Point3<f32>> {
+  server.players.lock().unwrap().values()
+    .map(|player| player.position)
+    .min_by(|p1, p2| p1.distance2(*p).partial_cmp(&p2.distance2(*p)).unwrap())
+}
+
+/// Switch between wandering and `alarmed` as players come and go, and act on it.
+fn think(server: &server::T, mob: &mut mob::Mob, alarmed: State) {
+  let player = nearest_player(server, &mob.position);
+  let state =
+    match player {
+      Some(p) if p.distance(mob.position) <= SIGHT_RADIUS => alarmed,
+      Some(p) if mob.ai.state == alarmed && p.distance(mob.position) <= LOSE_RADIUS => alarmed,
+      _ => State::Wander,
+    };
+  if state != mob.ai.state {
+    mob.ai.state = state;
+    mob.ai.goal = None;
+    mob.ai.path.clear();
+  }
+
+  match (state, player) {
+    (State::Chase, Some(player)) => {
+      if player.distance(mob.position) <= CHASE_DISTANCE {
+        mob.ai.path.clear();
+        stop(mob);
+      } else {
+        go_to(server, mob, player, CHASE_SPEED);
+      }
+    },
+    (State::Flee, Some(player)) => {
+      let away = Vector3::new(mob.position.x - player.x, 0.0, mob.position.z - player.z);
+      let away = if away.magnitude2() > 0.0 { away.normalize() } else { Vector3::new(1.0, 0.0, 0.0) };
+      let goal = player + away * FLEE_DISTANCE;
+      go_to(server, mob, goal, FLEE_SPEED);
+    },
+    _ => wander(server, mob),
+  }
+}
+
+fn wander(server: &server::T, mob: &mut mob::Mob) {
+  if !mob.ai.path.is_empty() {
+    steer(mob, WANDER_SPEED);
+    return
+  }
+  stop(mob);
+  let goal = {
+    let mut rng = server.rng(rng::subsystem::MOB_WANDER, &[server.tick() as i64, mob.entity_id.to_u32() as i64]);
+    if rng.next_f32() >= WANDER_CHANCE {
+      return
+    }
+    mob.position + Vector3::new(
+      rng.gen_range(-WANDER_RADIUS, WANDER_RADIUS),
+      0.0,
+      rng.gen_range(-WANDER_RADIUS, WANDER_RADIUS),
+    )
+  };
+  mob.ai.goal = None;
+  go_to(server, mob, goal, WANDER_SPEED);
+}
+
+/// Walk toward `goal` at `speed`, finding a way around terrain.
+pub fn go_to(server: &server::T, mob: &mut mob::Mob, goal: Point3<f32>, speed: f32) {
+  let stale =
+    match mob.ai.goal {
+      None => true,
+      Some(old) => old.distance(goal) > REPATH_DISTANCE,
+    };
+  if stale || mob.ai.repath_in == 0 {
+    let physics = server.physics.lock().unwrap();
+    let bounds = *physics.get_bounds(mob.body.id).unwrap();
+    mob.ai.path = find_path(&physics, &bounds, &goal);
+    mob.ai.goal = Some(goal);
+    mob.ai.repath_in = REPATH_INTERVAL;
+  } else {
+    mob.ai.repath_in -= 1;
+  }
+  steer(mob, speed);
+}
+
+fn stop(mob: &mut mob::Mob) {
+  mob.speed.x = 0.0;
+  mob.speed.z = 0.0;
+}
+
+/// Head for the next point on the path, dropping points once they're reached.
+/// Climbing is left to physics, which steps mobs up ledges as they walk into them.
+fn steer(mob: &mut mob::Mob, speed: f32) {
+  loop {
+    let next =
+      match mob.ai.path.last() {
+        None => break,
+        Some(&next) => next,
+      };
+    let to_next = Vector3::new(next.x - mob.position.x, 0.0, next.z - mob.position.z);
+    if to_next.magnitude() > WAYPOINT_RADIUS {
+      let v = to_next.normalize() * speed;
+      mob.speed.x = v.x;
+      mob.speed.z = v.z;
+      return
+    }
+    mob.ai.path.pop();
+  }
+  stop(mob);
+}
+
+/// A place a mob could stand, in voxels from where it started.
+type Offset = (i32, i32, i32);
+
+fn shifted(bounds: &Aabb3<f32>, offset: Offset) -> Aabb3<f32> {
+  let v = Vector3::new(offset.0 as f32, offset.1 as f32, offset.2 as f32);
+  Aabb3::new(bounds.min + v, bounds.max + v)
+}
+
+/// Where a mob standing at `from` can get to in one step, and what each step costs.
+fn steps(physics: &physics::T, bounds: &Aabb3<f32>, from: Offset) -> Vec<(Offset, u32)> {
+  let stands = |offset| {
+    let bounds = shifted(bounds, offset);
+    !physics.terrain_blocks(&bounds) && physics.supported(&bounds, 1.0)
+  };
+
+  let mut steps = Vec::new();
+  for &(dx, dz) in &[(1, 0), (-1, 0), (0, 1), (0, -1)] {
+    let level = (from.0 + dx, from.1, from.2 + dz);
+    if !physics.terrain_blocks(&shifted(bounds, level)) {
+      // Walk across, or off a ledge.
+      for drop in 0 .. MAX_DROP + 1 {
+        let to = (level.0, level.1 - drop, level.2);
+        if physics.terrain_blocks(&shifted(bounds, to)) {
+          break
+        }
+        if physics.supported(&shifted(bounds, to), 1.0) {
+          steps.push((to, STEP_COST + drop as u32));
+          break
+        }
+      }
+    } else {
+      // Climb up a step, if there's headroom.
+      let up = (from.0, from.1 + 1, from.2);
+      let to = (level.0, level.1 + 1, level.2);
+      if !physics.terrain_blocks(&shifted(bounds, up)) && stands(to) {
+        steps.push((to, CLIMB_COST));
+      }
+    }
+  }
+  steps
+}
+
+/// A place waiting to be searched from, by its cost so far plus its estimated cost to the goal.
+#[derive(PartialEq, Eq)]
+struct Open {
+  priority : u32,
+  offset   : Offset,
+}
+
+// Reversed, so the heap pops the cheapest place first.
+impl Ord for Open {
+  fn cmp(&self, other: &Open) -> std::cmp::Ordering {
+    other.priority.cmp(&self.priority)
+  }
+}
+
+impl PartialOrd for Open {
+  fn partial_cmp(&self, other: &Open) -> Option<std::cmp::Ordering> {
+    Some(self.cmp(other))
+  }
+}
+
+/// Search the places a mob with some bounds could stand for a way to `goal`. If there isn't one
+/// within `MAX_SEARCH` places, settle for the way to the place searched that's nearest `goal`.
+/// Returns the points for the mob's feet to pass through, last first.
+fn find_path(physics: &physics::T, bounds: &Aabb3<f32>, goal: &Point3<f32>) -> Vec<Point3<f32>> {
+  let feet =
+    Point3::new(
+      (bounds.min.x + bounds.max.x) / 2.0,
+      bounds.min.y,
+      (bounds.min.z + bounds.max.z) / 2.0,
+    );
+  let at = |offset: Offset| feet + Vector3::new(offset.0 as f32, offset.1 as f32, offset.2 as f32);
+  let estimate = |offset: Offset| (at(offset).distance(*goal) * STEP_COST as f32) as u32;
+
+  let start = (0, 0, 0);
+  let mut open = BinaryHeap::new();
+  let mut cost: fnv_map::T<Offset, u32> = fnv_map::new();
+  let mut came_from: fnv_map::T<Offset, Offset> = fnv_map::new();
+  cost.insert(start, 0);
+  open.push(Open { priority: estimate(start), offset: start });
+
+  let mut nearest = (estimate(start), start);
+  let mut searched = 0;
+  while let Some(Open { offset, .. }) = open.pop() {
+    let remaining = estimate(offset);
+    if remaining < nearest.0 {
+      nearest = (remaining, offset);
+    }
+    if at(offset).distance(*goal) <= ARRIVAL_RADIUS {
+      nearest = (remaining, offset);
+      break
+    }
+    searched += 1;
+    if searched >= MAX_SEARCH {
+      break
+    }
+
+    let offset_cost = cost[&offset];
+    for (next, step_cost) in steps(physics, bounds, offset) {
+      let next_cost = offset_cost + step_cost;
+      if cost.get(&next).map_or(true, |&c| next_cost < c) {
+        cost.insert(next, next_cost);
+        came_from.insert(next, offset);
+        open.push(Open { priority: next_cost + estimate(next), offset: next });
+      }
+    }
+  }
+
+  let mut path = Vec::new();
+  let mut offset = nearest.1;
+  while offset != start {
+    path.push(at(offset));
+    offset = came_from[&offset];
+  }
+  path
+}
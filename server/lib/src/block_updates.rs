@@ -0,0 +1,102 @@
+//! Neighbor updates: when a voxel changes, the voxels next to it get a chance to react,
+//! e.g. sand starts falling if nothing's holding it up. Updates are queued and only a limited number run
+//! each tick, so cascades spread out over time instead of stalling the server.
+
+use std::collections::VecDeque;
+
+use common::fnv_set;
+use common::voxel;
+
+/// The most neighbor updates to run in a single world tick.
+pub const MAX_UPDATES_PER_TICK: usize = 256;
+
+#[allow(missing_docs)]
+pub struct T {
+  queue  : VecDeque<voxel::bounds::T>,
+  /// Everything in `queue`, so each voxel is queued at most once.
+  queued : fnv_set::T<voxel::bounds::T>,
+}
+
+#[allow(missing_docs)]
+pub fn new() -> T {
+  T {
+    queue  : VecDeque::new(),
+    queued : fnv_set::new(),
+  }
+}
+
+/// The six voxels sharing a face with `bounds`.
+pub fn neighbors(bounds: &voxel::bounds::T) -> [voxel::bounds::T; 6] {
+  let offset = |dx, dy, dz| voxel::bounds::new(bounds.x + dx, bounds.y + dy, bounds.z + dz, bounds.lg_size);
+  [
+    offset(-1,  0,  0),
+    offset( 1,  0,  0),
+    offset( 0, -1,  0),
+    offset( 0,  1,  0),
+    offset( 0,  0, -1),
+    offset( 0,  0,  1),
+  ]
+}
+
+impl T {
+  /// Note that a voxel changed, so its neighbors need updating.
+  pub fn voxel_changed(&mut self, bounds: &voxel::bounds::T) {
+    for neighbor in neighbors(bounds).iter() {
+      self.queue(neighbor);
+    }
+  }
+
+  /// Queue a voxel itself to be updated.
+  pub fn queue(&mut self, bounds: &voxel::bounds::T) {
+    if self.queued.insert(*bounds) {
+      self.queue.push_back(*bounds);
+    }
+  }
+
+  /// Take up to `max` voxels that need updating.
+  pub fn take(&mut self, max: usize) -> Vec<voxel::bounds::T> {
+    let mut taken = Vec::with_capacity(max);
+    while taken.len() < max {
+      match self.queue.pop_front() {
+        None => break,
+        Some(bounds) => {
+          self.queued.remove(&bounds);
+          taken.push(bounds);
+        },
+      }
+    }
+    taken
+  }
+
+  #[allow(missing_docs)]
+  pub fn len(&self) -> usize {
+    self.queue.len()
+  }
+}
+
+/// Whether a material falls when there's nothing under it.
+pub fn falls(material: voxel::Material) -> bool {
+  match material {
+    voxel::Material::Sand | voxel::Material::Gravel => true,
+    _ => false,
+  }
+}
+
+/// Let a voxel react to a change next to it.
+/// Returns the voxel's material if it's lost its support and should start falling.
+pub fn update(
+  voxels: &voxel::tree::T,
+  bounds: &voxel::bounds::T,
+) -> Option<voxel::Material> {
+  let material =
+    match voxels.get(bounds) {
+      Some(&voxel::Volume(material)) if falls(material) => material,
+      _ => return None,
+    };
+
+  let below = voxel::bounds::new(bounds.x, bounds.y - 1, bounds.z, bounds.lg_size);
+  match voxels.get(&below) {
+    Some(&voxel::Volume(below)) if !below.is_solid() => Some(material),
+    _ => None,
+  }
+}
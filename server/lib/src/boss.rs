@@ -0,0 +1,251 @@
+//! Boss fights: mobs with a health pool that change tactics as it runs down, fought in an arena
+//! around where they appeared. Nearby players are kept posted on the boss's health, nobody can edit
+//! the arena until the fight's over, and the players still in it split the loot.
+//! Bosses are data: a new one is an entry in `KINDS`, built out of phases and behaviors.
+
+use cgmath::{Point3, Vector3, InnerSpace, MetricSpace};
+use std;
+
+use common::fnv_map;
+use common::protocol;
+use common::voxel;
+
+use ai;
+use entity;
+use init_mobs;
+use mob;
+use server;
+
+/// Players within this distance of a boss hear about its fight.
+const BROADCAST_RADIUS: f32 = 96.0;
+
+/// How often, in ticks, nearby players are reminded of a boss's health.
+const BROADCAST_INTERVAL: u64 = server::UPDATES_PER_SECOND;
+
+/// One stage of a boss fight.
+pub struct Phase {
+  /// The phase starts once health falls to this fraction of max health.
+  pub below        : f32,
+  /// How the boss acts during this phase.
+  pub behavior     : mob::Behavior,
+  /// Told to everyone nearby when the phase starts.
+  pub announcement : &'static str,
+}
+
+/// A kind of boss.
+pub struct Kind {
+  /// What it's called in `spawnboss`.
+  pub name         : &'static str,
+  #[allow(missing_docs)]
+  pub max_health   : u32,
+  /// Width, height and depth.
+  pub size         : (f32, f32, f32),
+  /// The phases of the fight, in order, starting with one at full health.
+  pub phases       : &'static [Phase],
+  /// The arena is everything within this distance of where the boss appeared.
+  pub arena_radius : f32,
+  /// What each player still in the arena gets when the boss is defeated.
+  pub loot         : &'static [(voxel::Material, u32)],
+}
+
+/// Every kind of boss.
+pub const KINDS: &'static [Kind] = &[
+  Kind {
+    name         : "golem",
+    max_health   : 400,
+    size         : (2.0, 4.0, 2.0),
+    phases       : &[
+      Phase { below: 1.0, behavior: stalk, announcement: "A stone golem lumbers to life." },
+      Phase { below: 0.5, behavior: charge, announcement: "The golem's cracks glow red. It charges!" },
+      Phase { below: 0.15, behavior: stand_fast, announcement: "The golem is crumbling, and stands its ground." },
+    ],
+    arena_radius : 24.0,
+    loot         : &[(voxel::Material::Iron, 8), (voxel::Material::Crystal, 2)],
+  },
+];
+
+/// The kind of boss with a given name.
+pub fn kind(name: &str) -> Option<&'static Kind> {
+  KINDS.iter().find(|kind| kind.name == name)
+}
+
+/// The direction to the player nearest a mob, if there are any players.
+fn to_nearest_player(server: &server::T, mob: &mob::Mob) -> Option<Vector3<f32>> {
+  server.players.lock().unwrap().values()
+    .map(|player| player.position - mob.position)
+    .min_by(|v1, v2| v1.magnitude2().partial_cmp(&v2.magnitude2()).unwrap())
+}
+
+fn walk_toward_players(server: &server::T, mob: &mut mob::Mob, speed: f32) {
+  match to_nearest_player(server, mob) {
+    Some(v) if v.x != 0.0 || v.z != 0.0 => {
+      let goal = mob.position + v;
+      ai::go_to(server, mob, goal, speed);
+    },
+    _ => {
+      mob.speed.x = 0.0;
+      mob.speed.z = 0.0;
+    },
+  }
+}
+
+/// Plod toward the nearest player.
+fn stalk(server: &server::T, mob: &mut mob::Mob) {
+  walk_toward_players(server, mob, 0.05);
+}
+
+/// Run at the nearest player.
+fn charge(server: &server::T, mob: &mut mob::Mob) {
+  walk_toward_players(server, mob, 0.2);
+}
+
+/// Hold still.
+fn stand_fast(_: &server::T, mob: &mut mob::Mob) {
+  mob.speed.x = 0.0;
+  mob.speed.z = 0.0;
+}
+
+struct Fight {
+  kind   : &'static Kind,
+  health : u32,
+  /// Index into `kind.phases`.
+  phase  : usize,
+  /// Where the boss appeared: the middle of the arena.
+  arena  : Point3<f32>,
+}
+
+#[allow(missing_docs)]
+pub struct T {
+  fights : fnv_map::T<entity::id::Mob, Fight>,
+}
+
+#[allow(missing_docs)]
+pub fn new() -> T {
+  T {
+    fights : fnv_map::new(),
+  }
+}
+
+/// The clients of the players within `radius` of a point.
+fn clients_near(server: &server::T, p: &Point3<f32>, radius: f32) -> Vec<protocol::ClientId> {
+  server.players.lock().unwrap().values()
+    .filter(|player| player.position.distance(*p) <= radius)
+    .map(|player| player.client_id)
+    .collect()
+}
+
+fn tell(server: &server::T, client_ids: &[protocol::ClientId], message: &protocol::ServerToClient) {
+  let mut clients = server.clients.lock().unwrap();
+  for client_id in client_ids {
+    if let Some(client) = clients.get_mut(client_id) {
+      client.send(message.clone());
+    }
+  }
+}
+
+fn announce(server: &server::T, arena: &Point3<f32>, text: &str) {
+  let client_ids = clients_near(server, arena, BROADCAST_RADIUS);
+  tell(server, &client_ids, &protocol::ServerToClient::Message(protocol::MessageKind::Boss, String::from(text)));
+}
+
+/// Start a fight with a boss standing at `position`.
+pub fn spawn(server: &server::T, kind: &'static Kind, position: Point3<f32>) -> entity::id::Mob {
+  let (width, height, depth) = kind.size;
+  let low_corner = position + Vector3::new(-width / 2.0, 0.0, -depth / 2.0);
+  let id = init_mobs::add_mob(server, low_corner, Vector3::new(width, height, depth), kind.phases[0].behavior, Vec::new());
+  server.bosses.lock().unwrap().fights.insert(
+    id,
+    Fight {
+      kind   : kind,
+      health : kind.max_health,
+      phase  : 0,
+      arena  : position,
+    },
+  );
+  info!("Spawned a {} at {:?}", kind.name, position);
+  announce(server, &position, kind.phases[0].announcement);
+  id
+}
+
+/// Hurt a mob, if it's a boss, moving the fight on to later phases as its health drops and ending
+/// the fight if it runs out. Returns whether the mob was a boss.
+pub fn damage(server: &server::T, mob_id: entity::id::Mob, amount: u32) -> bool {
+  let (kind, arena, health, new_phase) = {
+    let mut bosses = server.bosses.lock().unwrap();
+    let fight =
+      match bosses.fights.get_mut(&mob_id) {
+        None => return false,
+        Some(fight) => fight,
+      };
+    fight.health = fight.health.saturating_sub(amount);
+    let fraction = fight.health as f32 / fight.kind.max_health as f32;
+    let phase =
+      fight.kind.phases.iter()
+      .rposition(|phase| fraction <= phase.below)
+      .unwrap_or(0);
+    let new_phase = if phase > fight.phase { Some(phase) } else { None };
+    fight.phase = std::cmp::max(fight.phase, phase);
+    (fight.kind, fight.arena, fight.health, new_phase)
+  };
+
+  let client_ids = clients_near(server, &arena, BROADCAST_RADIUS);
+  tell(server, &client_ids, &protocol::ServerToClient::BossHealth { health: health, max_health: kind.max_health });
+
+  if health == 0 {
+    defeat(server, mob_id, kind, &arena);
+  } else if let Some(phase) = new_phase {
+    let phase = &kind.phases[phase];
+    if let Some(mob) = server.mobs.lock().unwrap().get_mut(&mob_id) {
+      mob.behavior = phase.behavior;
+    }
+    announce(server, &arena, phase.announcement);
+  }
+  true
+}
+
+fn defeat(server: &server::T, mob_id: entity::id::Mob, kind: &Kind, arena: &Point3<f32>) {
+  server.bosses.lock().unwrap().fights.remove(&mob_id);
+  init_mobs::remove_mob(server, mob_id);
+
+  let mut winners = Vec::new();
+  let mut inventories = Vec::new();
+  for player in server.players.lock().unwrap().values_mut() {
+    if player.position.distance(*arena) <= kind.arena_radius {
+      for &(material, count) in kind.loot {
+        // Loot that doesn't fit is lost.
+        player.inventory.add(material, count);
+      }
+      winners.push(player.client_id);
+      inventories.push((player.client_id, player.inventory.update_message()));
+    }
+  }
+  info!("A {} was defeated", kind.name);
+  announce(server, arena, &format!("The {} is defeated!", kind.name));
+  let loot: Vec<String> = kind.loot.iter().map(|&(material, count)| format!("{} {:?}", count, material)).collect();
+  let text = format!("You won {}.", loot.join(", "));
+  tell(server, &winners, &protocol::ServerToClient::Message(protocol::MessageKind::Boss, text));
+  for (client_id, inventory) in inventories {
+    tell(server, &[client_id], &inventory);
+  }
+}
+
+/// Whether a point is in the arena of a fight that's going on, and mustn't be edited.
+pub fn protects(server: &server::T, p: &Point3<f32>) -> bool {
+  server.bosses.lock().unwrap().fights.values()
+    .any(|fight| fight.arena.distance(*p) <= fight.kind.arena_radius)
+}
+
+/// Keep nearby players posted on the fights going on. Call this every tick.
+pub fn update(server: &server::T, tick: u64) {
+  if tick % BROADCAST_INTERVAL != 0 {
+    return
+  }
+  let fights: Vec<_> =
+    server.bosses.lock().unwrap().fights.values()
+    .map(|fight| (fight.arena, fight.health, fight.kind.max_health))
+    .collect();
+  for (arena, health, max_health) in fights {
+    let client_ids = clients_near(server, &arena, BROADCAST_RADIUS);
+    tell(server, &client_ids, &protocol::ServerToClient::BossHealth { health: health, max_health: max_health });
+  }
+}
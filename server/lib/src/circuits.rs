@@ -0,0 +1,162 @@
+//! A minimal circuit layer: wire carries an on/off signal from levers and pressure plates to lamps and pistons.
+//! Circuits are re-evaluated incrementally, as part of neighbor updates, so a change only touches
+//! the wire network next to it.
+
+use common::fnv_map;
+use common::fnv_set;
+use common::voxel;
+use common::voxel::Material;
+
+use block_updates;
+use pistons;
+
+/// Wire networks bigger than this are left alone, to keep a single update bounded.
+const MAX_NETWORK: usize = 512;
+
+#[allow(missing_docs)]
+pub struct T {
+  /// Pressure plates that were stood on last tick.
+  pub pressed : fnv_set::T<voxel::bounds::T>,
+}
+
+#[allow(missing_docs)]
+pub fn new() -> T {
+  T {
+    pressed : fnv_set::new(),
+  }
+}
+
+/// The material a voxel is made of, for the purposes of circuits.
+pub fn material_of(voxel: &voxel::T) -> Material {
+  match *voxel {
+    voxel::Volume(material) => material,
+    voxel::Surface(ref surface) => surface.corner,
+  }
+}
+
+/// A voxel with its material swapped out, keeping its shape.
+pub fn with_material(voxel: voxel::T, material: Material) -> voxel::T {
+  match voxel {
+    voxel::Volume(_) => voxel::Volume(material),
+    voxel::Surface(mut surface) => {
+      surface.corner = material;
+      voxel::Surface(surface)
+    },
+  }
+}
+
+fn is_wire(material: Material) -> bool {
+  material == Material::Wire || material == Material::PoweredWire
+}
+
+/// Whether a material puts a signal onto neighboring wires.
+fn is_on_source(material: Material) -> bool {
+  material == Material::PoweredLever || material == Material::PoweredPressurePlate
+}
+
+/// Whether a material powers neighboring lamps and pistons.
+fn is_powered(material: Material) -> bool {
+  material == Material::PoweredWire || is_on_source(material)
+}
+
+/// The material that a lever becomes when it's used, if it's a lever.
+pub fn toggled(material: Material) -> Option<Material> {
+  match material {
+    Material::Lever => Some(Material::PoweredLever),
+    Material::PoweredLever => Some(Material::Lever),
+    _ => None,
+  }
+}
+
+fn material_at(voxels: &voxel::tree::T, bounds: &voxel::bounds::T) -> Option<Material> {
+  voxels.get(bounds).map(material_of)
+}
+
+/// If anything next to `bounds` is powering it, the index (into `block_updates::neighbors`) of the first one.
+pub fn powered_from(voxels: &voxel::tree::T, bounds: &voxel::bounds::T) -> Option<usize> {
+  block_updates::neighbors(bounds).iter()
+    .position(|neighbor| material_at(voxels, neighbor).map(is_powered) == Some(true))
+}
+
+/// Re-evaluate the circuit at `bounds` after one of its neighbors changed.
+/// Returns the voxels that need to change. Wire networks already in `evaluated` are skipped,
+/// so each network is only walked once per batch of updates. `facings` are the machines'
+/// directions (see `orientations`).
+pub fn update(
+  voxels    : &voxel::tree::T,
+  facings   : &fnv_map::T<voxel::bounds::T, usize>,
+  evaluated : &mut fnv_set::T<voxel::bounds::T>,
+  bounds    : &voxel::bounds::T,
+) -> Vec<(voxel::bounds::T, voxel::T)> {
+  let voxel =
+    match voxels.get(bounds) {
+      None => return Vec::new(),
+      Some(voxel) => *voxel,
+    };
+
+  match material_of(&voxel) {
+    Material::Wire | Material::PoweredWire => {
+      if evaluated.contains(bounds) {
+        Vec::new()
+      } else {
+        update_network(voxels, evaluated, bounds)
+      }
+    },
+    Material::Lamp | Material::LitLamp => {
+      let lit = powered_from(voxels, bounds).is_some();
+      let material = if lit { Material::LitLamp } else { Material::Lamp };
+      if material_of(&voxel) == material {
+        Vec::new()
+      } else {
+        vec!((*bounds, with_material(voxel, material)))
+      }
+    },
+    Material::Piston | Material::ExtendedPiston => pistons::update(voxels, facings, bounds, voxel),
+    _ => Vec::new(),
+  }
+}
+
+/// Find the wire network containing `start`, and power or unpower all of it.
+fn update_network(
+  voxels    : &voxel::tree::T,
+  evaluated : &mut fnv_set::T<voxel::bounds::T>,
+  start     : &voxel::bounds::T,
+) -> Vec<(voxel::bounds::T, voxel::T)> {
+  let mut network = vec!(*start);
+  evaluated.insert(*start);
+  let mut powered = false;
+  let mut i = 0;
+  while i < network.len() {
+    if network.len() > MAX_NETWORK {
+      warn!("Wire network at {:?} is too big to update", start);
+      return Vec::new()
+    }
+
+    for neighbor in block_updates::neighbors(&network[i]).iter() {
+      match material_at(voxels, neighbor) {
+        Some(material) if is_wire(material) => {
+          if evaluated.insert(*neighbor) {
+            network.push(*neighbor);
+          }
+        },
+        Some(material) if is_on_source(material) => {
+          powered = true;
+        },
+        _ => {},
+      }
+    }
+    i += 1;
+  }
+
+  let material = if powered { Material::PoweredWire } else { Material::Wire };
+  network.into_iter()
+    .filter_map(|bounds| {
+      let voxel = *voxels.get(&bounds).unwrap();
+      if material_of(&voxel) == material {
+        None
+      } else {
+        Some((bounds, with_material(voxel, material)))
+      }
+    })
+    .collect()
+}
@@ -1,22 +1,41 @@
-use cgmath::{Point3, Vector3, EuclideanSpace};
-use collision::{Aabb3};
+use cgmath::{Point3, Vector3, EuclideanSpace, InnerSpace, MetricSpace};
+use collision::{Aabb3, Ray3};
 use rand;
-use rand::distributions::IndependentSample;
+use rand::Rng;
 use std::convert::AsRef;
 use std::f32::consts::PI;
-use std::ops::DerefMut;
 use std::time::Duration;
 use stopwatch;
+use time;
 
+use common;
 use common::protocol;
+use common::reliable;
+use common::rng;
+use common::shape;
 use common::socket::SendSocket;
 use common::voxel;
 
+use accounts;
+use boss;
+use circuits;
+use commands;
+use components;
+use disconnect;
 use entity;
+use entity_regions;
+use event_log;
+use health;
+use hints;
+use items;
+use orientations;
 use player;
+use projectiles;
 use server;
 use server::Client;
+use shapes;
 use terrain;
+use trade;
 use voxel_data;
 use update_gaia;
 use update_gaia::LoadDestination;
@@ -25,6 +44,12 @@ fn center(bounds: &Aabb3<f32>) -> Point3<f32> {
   (bounds.min + bounds.max.to_vec()) * 0.5
 }
 
+/// How far away from the player voxels can be dug or placed.
+const MAX_EDIT_DISTANCE: f32 = 8.0;
+
+/// Longer chat messages are cut off, at this many characters.
+const MAX_CHAT_LENGTH: usize = 256;
+
 fn cast(
   server: &server::T,
   player_id: entity::id::Player,
@@ -36,72 +61,383 @@ fn cast(
     ray = player.forward_ray();
   }
 
+  cast_ray(server, &ray)
+}
+
+fn cast_ray(
+  server: &server::T,
+  ray: &Ray3<f32>,
+) -> Option<voxel::bounds::T> {
   server.terrain_loader.terrain.voxels.lock().unwrap().cast_ray(
     &ray,
     &mut |bounds, voxel| {
       match voxel {
-        &voxel::Volume(voxel::Material::Empty) => None,
+        &voxel::Volume(material) if !material.is_solid() => None,
         _ => Some(bounds),
       }
     }
   )
 }
 
+fn add_client(
+  server      : &server::T,
+  client_url  : String,
+  address     : String,
+  credentials : protocol::Credentials,
+) {
+  info!("Sending to {} at {}.", address, client_url);
+
+  let mut client =
+    Client {
+      socket        : SendSocket::new(client_url.as_ref(), Some(Duration::from_secs(30))),
+      address       : address,
+      name          : credentials.name.clone(),
+      last_heard_ns : time::precise_time_ns(),
+      link          : reliable::new(),
+      // This has to be unguessable, so it doesn't come from the world's seeded streams.
+      link_key      : rand::thread_rng().next_u64(),
+    };
+  if let Err(reason) = server.accounts.log_in(server, &credentials) {
+    warn!("Refusing {} at {}: {}", credentials.name, client.address, reason);
+    client.send(protocol::ServerToClient::Refused(reason));
+    return
+  }
+
+  let client_id = server.client_allocator.lock().unwrap().allocate();
+  server.event_log.lock().unwrap().record(
+    &event_log::Actor::Client(client_id, client.address.clone()),
+    &event_log::Event::Connected,
+  );
+  let link_key = client.link_key;
+  client.send(protocol::ServerToClient::LeaseId(client_id, link_key));
+  if let Some(ref pack) = server.resource_pack {
+    client.send(protocol::ServerToClient::RequiredResourcePack {
+      url    : pack.url.clone(),
+      sha256 : pack.sha256.clone(),
+    });
+  }
+
+  server.clients.lock().unwrap().insert(client_id, client);
+}
+
+/// Cast a ray from a player toward `target`, and return the hit voxel along with the ray's direction.
+fn cast_toward(
+  server: &server::T,
+  player_id: entity::id::Player,
+  target: &Point3<f32>,
+) -> Option<(voxel::bounds::T, Vector3<f32>)> {
+  let position = server.players.lock().unwrap().get(&player_id).unwrap().position;
+  let direction = *target - position;
+  if direction.magnitude2() == 0.0 {
+    return None
+  }
+  let direction = direction.normalize();
+
+  cast_ray(server, &Ray3::new(position, direction))
+    .and_then(|bounds| {
+      if bounds.center().distance(position) > MAX_EDIT_DISTANCE {
+        None
+      } else {
+        Some((bounds, direction))
+      }
+    })
+}
+
+/// Tell a player's client what's in their inventory.
+fn send_inventory(server: &server::T, player_id: entity::id::Player) {
+  let (client_id, update) = {
+    let players = server.players.lock().unwrap();
+    let player = players.get(&player_id).unwrap();
+    (player.client_id, player.inventory.update_message())
+  };
+  if let Some(client) = server.clients.lock().unwrap().get_mut(&client_id) {
+    client.send(update);
+  }
+}
+
+/// Take one of the material in a player's selected inventory slot, if there is any.
+fn take_selected(server: &server::T, player_id: entity::id::Player) -> Option<voxel::Material> {
+  let taken = {
+    let mut players = server.players.lock().unwrap();
+    let inventory = &mut players.get_mut(&player_id).unwrap().inventory;
+    inventory.selected().and_then(|material| {
+      if inventory.take(material, 1) { Some(material) } else { None }
+    })
+  };
+  if taken.is_some() {
+    send_inventory(server, player_id);
+  }
+  taken
+}
+
+/// If `p` is in the arena of a boss fight that's going on, tell the player they can't edit there
+/// and return true.
+fn arena_refuses(server: &server::T, player_id: entity::id::Player, p: &Point3<f32>) -> bool {
+  if !boss::protects(server, p) {
+    return false
+  }
+  let client_id = server.players.lock().unwrap().get(&player_id).unwrap().client_id;
+  if let Some(client) = server.clients.lock().unwrap().get_mut(&client_id) {
+    let text = String::from("The arena can't be changed until the fight is over.");
+    client.send(protocol::ServerToClient::Message(protocol::MessageKind::Boss, text));
+  }
+  true
+}
+
+/// Push players and mobs out of the way of terrain about to be placed in `bounds`. If they can't all
+/// be moved, tell the player placing it and return false.
+fn make_room(server: &server::T, player_id: entity::id::Player, bounds: &Aabb3<f32>) -> bool {
+  let pushed = server.physics.lock().unwrap().make_room(bounds);
+  let pushed =
+    match pushed {
+      Some(pushed) => pushed,
+      None => {
+        let client_id = server.players.lock().unwrap().get(&player_id).unwrap().client_id;
+        if let Some(client) = server.clients.lock().unwrap().get_mut(&client_id) {
+          let text = String::from("There's something in the way.");
+          client.send(protocol::ServerToClient::Message(protocol::MessageKind::Notice, text));
+        }
+        return false
+      },
+    };
+  for (id, push) in pushed {
+    for player in server.players.lock().unwrap().values_mut().filter(|player| player.body.id == id) {
+      player.position += push;
+    }
+    for mob in server.mobs.lock().unwrap().values_mut().filter(|mob| mob.body.id == id) {
+      mob.position += push;
+    }
+  }
+  true
+}
+
+/// Rotate the placed shape hit at `bounds` by a ray going in `direction`, and brush it back in.
+fn rotate_shape<UpdateGaia>(
+  server      : &server::T,
+  update_gaia : &mut UpdateGaia,
+  bounds      : &voxel::bounds::T,
+  direction   : &Vector3<f32>,
+) where
+  UpdateGaia: FnMut(update_gaia::Message),
+{
+  // The voxel hit can be just outside a shape whose face is on the edge of its cell.
+  let cells = [shapes::cell(&bounds.center()), shapes::cell(&(bounds.center() + *direction))];
+  let placed =
+    cells.iter().filter_map(|low| {
+      server.orientations.lock().unwrap().shapes.get(&shapes::cell_bounds(low)).map(|placed| (*low, placed.clone()))
+    })
+    .next();
+  let (low, mut placed) =
+    match placed {
+      None => return,
+      Some(placed) => placed,
+    };
+  if !shapes::occupied(server, &low, placed.material) {
+    // It's been dug out since.
+    server.orientations.lock().unwrap().shapes.remove(&shapes::cell_bounds(&low));
+    return
+  }
+  let model =
+    match server.shapes.lock().unwrap().find(&placed.shape) {
+      None => return,
+      Some(model) => model.clone(),
+    };
+  placed.orientation = placed.orientation.rotated(&model);
+  debug!("rotate {} at {:?} to {:?}", model.name, low, placed.orientation);
+  update_gaia(update_gaia::Message::Brush(shapes::clear(low)));
+  update_gaia(update_gaia::Message::Brush(shapes::brush(server, &model, &placed.orientation, low, placed.material)));
+  server.orientations.lock().unwrap().shapes.insert(shapes::cell_bounds(&low), placed);
+}
+
+/// A brush that fills a sphere with a single material.
+fn sphere_brush(
+  center: Point3<f32>,
+  radius: f32,
+  material: voxel::Material,
+) -> voxel_data::brush::T<Box<voxel_data::mosaic::T<voxel::Material> + Send>> {
+  let sphere =
+    voxel_data::mosaic::solid::T {
+      field: voxel_data::field::translation::T {
+        translation: center.to_vec(),
+        field: voxel_data::field::sphere::T {
+          radius: radius,
+        },
+      },
+      material: material,
+    };
+  let r = sphere.field.field.radius + 1.0;
+  voxel_data::brush::T {
+    bounds:
+      Aabb3::new(
+        {
+          let low = sphere.field.translation + (&-Vector3::new(r, r, r));
+          Point3::new(low.x.floor() as i32, low.y.floor() as i32, low.z.floor() as i32)
+        },
+        {
+          let high = sphere.field.translation + (&Vector3::new(r, r, r));
+          Point3::new(high.x.ceil() as i32, high.y.ceil() as i32, high.z.ceil() as i32)
+        },
+      ),
+    mosaic: Box::new(sphere) as Box<voxel_data::mosaic::T<voxel::Material> + Send>,
+    min_lg_size: 0,
+  }
+}
+
+/// The player an update is for, if it's for one.
+fn player_of(update: &protocol::ClientToServer) -> Option<entity::id::Player> {
+  match *update {
+    protocol::ClientToServer::Walk(player_id, _) |
+    protocol::ClientToServer::RotatePlayer(player_id, _) |
+    protocol::ClientToServer::StartJump(player_id) |
+    protocol::ClientToServer::StopJump(player_id) |
+    protocol::ClientToServer::SetMovementMode(player_id, _) |
+    protocol::ClientToServer::Chat(player_id, _) |
+    protocol::ClientToServer::ToggleFly(player_id) |
+    protocol::ClientToServer::Add(player_id) |
+    protocol::ClientToServer::Remove(player_id) |
+    protocol::ClientToServer::RemoveVoxel(player_id, _) |
+    protocol::ClientToServer::UseItem(player_id, _) |
+    protocol::ClientToServer::PlaceShape(player_id, _, _) |
+    protocol::ClientToServer::Rotate(player_id, _) |
+    protocol::ClientToServer::SelectSlot(player_id, _) |
+    protocol::ClientToServer::Interact(player_id, _) |
+    protocol::ClientToServer::Attack(player_id, _) |
+    protocol::ClientToServer::Throw(player_id, _) |
+    protocol::ClientToServer::Trade(player_id, _, _) => Some(player_id),
+    _ => None,
+  }
+}
+
+/// Compare a proxy's secret against ours without bailing out at the first difference, so how long
+/// the check takes says nothing about how much of a guess was right.
+fn same_secret(secret: &str, guess: &str) -> bool {
+  let secret = secret.as_bytes();
+  let guess = guess.as_bytes();
+  if secret.len() != guess.len() {
+    return false
+  }
+  secret.iter().zip(guess.iter()).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+/// The client an update says it's from, if it names one.
+fn client_of(update: &protocol::ClientToServer) -> Option<protocol::ClientId> {
+  match *update {
+    protocol::ClientToServer::Ping(client_id) |
+    protocol::ClientToServer::Disconnect(client_id) |
+    protocol::ClientToServer::AddPlayer(client_id) |
+    protocol::ClientToServer::RequestVoxels { client_id, .. } |
+    protocol::ClientToServer::CheckBlock { client_id, .. } |
+    protocol::ClientToServer::RequestHorizon(client_id, _) => Some(client_id),
+    _ => None,
+  }
+}
+
+/// Apply an update that arrived over the link for `sender`, or from a client that hasn't been
+/// given an id yet if it's None. Updates naming a client other than the sender, or a player the
+/// sender doesn't play, are dropped, so clients can't act as each other.
 pub fn apply_client_update<UpdateGaia>(
   server: &server::T,
   update_gaia: &mut UpdateGaia,
+  sender: Option<protocol::ClientId>,
   update: protocol::ClientToServer,
 ) where
   UpdateGaia: FnMut(update_gaia::Message),
 {
   stopwatch::time("apply_client_update", move || {
+    if let Some(client_id) = client_of(&update) {
+      if Some(client_id) != sender {
+        warn!("Ignoring an update for client {:?} from {:?}", client_id, sender);
+        return
+      }
+    }
+    if let Some(player_id) = player_of(&update) {
+      let owner = server.players.lock().unwrap().get(&player_id).map(|player| player.client_id);
+      match owner {
+        // Updates can still be on their way from a client after it's been dropped.
+        None => {
+          debug!("Ignoring an update for player {:?}, who isn't in the world", player_id);
+          return
+        },
+        Some(owner) => {
+          if Some(owner) != sender {
+            warn!("Ignoring an update for player {:?} from client {:?}, which doesn't play them", player_id, sender);
+            return
+          }
+        },
+      }
+    }
     match update {
-      protocol::ClientToServer::Init(client_url) => {
-        info!("Sending to {}.", client_url);
-
-        let mut client =
-          Client {
-            socket: SendSocket::new(client_url.as_ref(), Some(Duration::from_secs(30))),
+      protocol::ClientToServer::Init(client_url, credentials) => {
+        let address = client_url.clone();
+        add_client(server, client_url, address, credentials);
+      },
+      protocol::ClientToServer::ProxiedInit { return_url, client_address, proxy_secret, credentials } => {
+        let trusted =
+          match server.proxy_secret {
+            None => false,
+            Some(ref secret) => same_secret(secret, &proxy_secret),
           };
-
-        let client_id = server.client_allocator.lock().unwrap().allocate();
-        client.send(protocol::ServerToClient::LeaseId(client_id));
-
-        server.clients.lock().unwrap().insert(client_id, client);
+        if trusted {
+          add_client(server, return_url, client_address, credentials);
+        } else {
+          warn!("Rejecting proxied connection for {} from untrusted proxy at {}.", client_address, return_url);
+        }
       },
       protocol::ClientToServer::Ping(client_id) => {
-        server.clients.lock().unwrap()
-          .get_mut(&client_id)
-          .unwrap()
-          .send(protocol::ServerToClient::Ping);
+        disconnect::heard_from(server, client_id);
+      },
+      protocol::ClientToServer::Disconnect(client_id) => {
+        disconnect::disconnect(server, client_id, "disconnected");
       },
       protocol::ClientToServer::AddPlayer(client_id) => {
+        let name =
+          match server.clients.lock().unwrap().get(&client_id) {
+            None => return,
+            Some(client) => client.name.clone(),
+          };
+        let saved = server.accounts.load(&name);
         let mut player =
           player::new(
+            client_id,
+            name,
             server.player_allocator.lock().unwrap().allocate(),
             server.misc_allocator.lock().unwrap().allocate(),
             &server.owner_allocator,
           );
+        player.inventory = accounts::inventory(saved.as_ref());
 
-        // TODO: shift upward until outside terrain
-        let min = Point3::new(0.0, 64.0, 4.0);
-        let max = min + (&Vector3::new(1.0, 2.0, 1.0));
-        let bounds = Aabb3::new(min, max);
-        server.physics.lock().unwrap().insert_misc(player.physics_id, &bounds);
+        // Returning players come back where they left.
+        let bounds = saved.map_or_else(player::spawn_bounds, |saved| accounts::bounds_at(saved.position));
+        server.physics.lock().unwrap().insert_misc(player.body.id, &bounds);
 
         player.position = center(&bounds);
         player.rotate_lateral(PI / 2.0);
 
         let id = player.entity_id;
         let pos = player.position;
+        let inventory = player.inventory.update_message();
+        let name = player.name.clone();
 
         server.players.lock().unwrap().insert(id, player);
+        server.entity_regions.lock().unwrap().moved(entity_regions::Entity::Player(id), &pos);
+        server.components.lock().unwrap().health.insert(entity::EntityId::Player(id), components::Health::full(player::MAX_HEALTH));
+        update_gaia(update_gaia::Message::SpawnTraders(pos));
+        event_log::record_player(server, id, &event_log::Event::Joined(id, name));
+        items::send_all(server, client_id);
 
         let mut clients = server.clients.lock().unwrap();
         let client = clients.get_mut(&client_id).unwrap();
         client.send(
           protocol::ServerToClient::PlayerAdded(id, pos)
         );
+        client.send(protocol::ServerToClient::MaxLoadDistance(server.view_distance.lock().unwrap().current()));
+        client.send(protocol::ServerToClient::Shapes(server.shapes.lock().unwrap().names()));
+        client.send(protocol::ServerToClient::UpdateHealth { health: player::MAX_HEALTH, max_health: player::MAX_HEALTH });
+        client.send(inventory);
+        if let Some(ref motd) = server.motd {
+          client.send(protocol::ServerToClient::Message(protocol::MessageKind::Motd, motd.clone()));
+        }
+        server.hints.lock().unwrap().happened(client_id, hints::Event::Joined, time::precise_time_ns());
       },
       protocol::ClientToServer::StartJump(player_id) => {
         let mut players = server.players.lock().unwrap();
@@ -121,6 +457,30 @@ pub fn apply_client_update<UpdateGaia>(
           player.accel.y = player.accel.y - 0.3;
         }
       },
+      protocol::ClientToServer::SetMovementMode(player_id, mode) => {
+        let mut players = server.players.lock().unwrap();
+        let player = players.get_mut(&player_id).unwrap();
+        player.set_movement_mode(&server.physics, mode);
+      },
+      protocol::ClientToServer::ToggleFly(player_id) => {
+        let (client_id, flying) = {
+          let mut players = server.players.lock().unwrap();
+          let player = players.get_mut(&player_id).unwrap();
+          if server.allow_fly {
+            let flying = !player.flying;
+            player.set_flying(flying);
+          }
+          (player.client_id, player.flying)
+        };
+        if let Some(client) = server.clients.lock().unwrap().get_mut(&client_id) {
+          if server.allow_fly {
+            client.send(protocol::ServerToClient::Flying(flying));
+          } else {
+            let text = String::from("Flying isn't allowed on this server.");
+            client.send(protocol::ServerToClient::Message(protocol::MessageKind::Notice, text));
+          }
+        }
+      },
       protocol::ClientToServer::Walk(player_id, v) => {
         let mut players = server.players.lock().unwrap();
         let player = players.get_mut(&player_id).unwrap();
@@ -135,60 +495,56 @@ pub fn apply_client_update<UpdateGaia>(
       protocol::ClientToServer::RequestVoxels { time_requested_ns, client_id, voxels } => {
         update_gaia(update_gaia::Message::Load(time_requested_ns, voxels, LoadDestination::Client(client_id)));
       },
+      protocol::ClientToServer::CheckBlock { time_requested_ns, client_id, chunk, lod, checksum } => {
+        let voxels = common::lod::voxels_for_chunk(&chunk, lod);
+        let destination =
+          LoadDestination::Check {
+            client_id : client_id,
+            chunk     : chunk,
+            lod       : lod,
+            checksum  : checksum,
+          };
+        update_gaia(update_gaia::Message::Load(time_requested_ns, voxels, destination));
+      },
+      protocol::ClientToServer::RequestHorizon(client_id, tile) => {
+        update_gaia(update_gaia::Message::Horizon(client_id, tile));
+      },
       protocol::ClientToServer::Add(player_id) => {
         let bounds = cast(server, player_id);
 
         bounds.map(|bounds| {
-          let mut rng = server.rng.lock().unwrap();
-          let rng = rng.deref_mut();
-
-          let trunk_radius =
-            rand::distributions::normal::Normal::new(2.0, 0.5)
-            .ind_sample(rng);
-          let trunk_radius =
-            f64::max(1.0, f64::min(3.0, trunk_radius));
-
-          let trunk_height =
-            rand::distributions::normal::Normal::new(8.0 * trunk_radius, 2.0 * trunk_radius)
-            .ind_sample(rng);
-          let trunk_height =
-            f64::max(4.0 * trunk_radius, f64::min(12.0 * trunk_radius, trunk_height));
-
-          let leaf_radius =
-            rand::distributions::normal::Normal::new(4.0 * trunk_radius, trunk_radius)
-            .ind_sample(rng);
-          let leaf_radius =
-            f64::max(2.0 * trunk_radius, f64::min(6.0 * trunk_radius, leaf_radius));
-
+          if arena_refuses(server, player_id, &bounds.center()) {
+            return
+          }
           let (low, high) = bounds.corners();
           let mut bottom = (low + high.to_vec()) / 2.0;
           bottom.y = low.y;
+          event_log::record_player(server, player_id, &event_log::Event::Edited("tree", bottom));
 
-          let trunk_height = trunk_height as f32;
-          let trunk_radius = trunk_radius as f32;
-          let leaf_radius = leaf_radius as f32;
+          // The same tree grows in the same place.
+          let key = [bottom.x.floor() as i64, bottom.y.floor() as i64, bottom.z.floor() as i64];
+          let mut rng = server.rng(rng::subsystem::TREE_GROWTH, &key);
+
+          // Trees grow differently in different biomes.
+          let params = server.terrain_loader.terrain.climate.params(bottom.x, bottom.z);
+          let tree = terrain::tree::grow(&mut rng, params.tree_species, params.tree_scale);
+          let tree_bounds = tree.bounds();
 
           let tree =
             voxel_data::mosaic::translation::T {
               translation: bottom.to_vec(),
-              mosaic: terrain::tree::new(rng, trunk_height, trunk_radius, leaf_radius),
+              mosaic: tree,
             };
 
-          let center =
-            bottom + (&Vector3::new(0.0, trunk_height / 2.0, 0.0));
-          let r = trunk_height / 2.0 + leaf_radius + 20.0;
+          let margin = Vector3::new(20.0, 20.0, 20.0);
+          let low = bottom + tree_bounds.min.to_vec() + -margin;
+          let high = bottom + tree_bounds.max.to_vec() + margin;
           let brush =
             voxel_data::brush::T {
               bounds:
                 Aabb3::new(
-                  {
-                    let low = center + (&-Vector3::new(r, r, r));
-                    Point3::new(low.x.floor() as i32, low.y.floor() as i32, low.z.floor() as i32)
-                  },
-                  {
-                    let high = center + (&Vector3::new(r, r, r));
-                    Point3::new(high.x.ceil() as i32, high.y.ceil() as i32, high.z.ceil() as i32)
-                  },
+                  Point3::new(low.x.floor() as i32, low.y.floor() as i32, low.z.floor() as i32),
+                  Point3::new(high.x.ceil() as i32, high.y.ceil() as i32, high.z.ceil() as i32),
                 ),
               mosaic: Box::new(tree) as Box<voxel_data::mosaic::T<voxel::Material> + Send>,
               min_lg_size: 0,
@@ -201,40 +557,183 @@ pub fn apply_client_update<UpdateGaia>(
         let bounds = cast(server, player_id);
 
         bounds.map(|bounds| {
+          if arena_refuses(server, player_id, &bounds.center()) {
+            return
+          }
           debug!("remove bounds {:?}", bounds);
-          let center = bounds.center();
-          let r = 8.0;
-          let sphere =
-            voxel_data::mosaic::solid::T {
-              field: voxel_data::field::translation::T {
-                translation: center.to_vec(),
-                field: voxel_data::field::sphere::T {
-                  radius: r,
-                },
-              },
-              material: voxel::Material::Empty,
+          event_log::record_player(server, player_id, &event_log::Event::Edited("dig", bounds.center()));
+          let brush = sphere_brush(bounds.center(), 8.0, voxel::Material::Empty);
+          update_gaia(update_gaia::Message::Brush(brush));
+        });
+      },
+      protocol::ClientToServer::RemoveVoxel(player_id, target) => {
+        cast_toward(server, player_id, &target).map(|(bounds, _)| {
+          if arena_refuses(server, player_id, &bounds.center()) {
+            return
+          }
+          debug!("remove voxel {:?}", bounds);
+          let material = server.terrain_loader.terrain.voxels.lock().unwrap().get(&bounds).map(circuits::material_of);
+          if let Some(material) = material {
+            if material.is_solid() {
+              items::drop_stack(server, bounds.center(), protocol::ItemStack { material: material, count: 1 });
+            }
+          }
+          server.orientations.lock().unwrap().facings.remove(&bounds);
+          let brush = sphere_brush(bounds.center(), 0.75, voxel::Material::Empty);
+          update_gaia(update_gaia::Message::Brush(brush));
+        });
+      },
+      protocol::ClientToServer::UseItem(player_id, target) => {
+        let material =
+          match server.players.lock().unwrap().get(&player_id).unwrap().inventory.selected() {
+            None => return,
+            Some(material) => material,
+          };
+        cast_toward(server, player_id, &target).map(|(bounds, direction)| {
+          // Place against the hit surface, on the side facing the player.
+          let center = bounds.center() + -direction;
+          if arena_refuses(server, player_id, &center) {
+            return
+          }
+          let voxel = Point3::new(center.x.floor(), center.y.floor(), center.z.floor());
+          if material.is_solid() && !make_room(server, player_id, &Aabb3::new(voxel, voxel + Vector3::new(1.0, 1.0, 1.0))) {
+            return
+          }
+          if take_selected(server, player_id) != Some(material) {
+            return
+          }
+          debug!("place {:?} at {:?}", material, center);
+          {
+            // Pistons push away from whoever placed them.
+            let placed = voxel::bounds::new(voxel.x as i32, voxel.y as i32, voxel.z as i32, 0);
+            let facing = orientations::facing(&direction);
+            let mut orientations = server.orientations.lock().unwrap();
+            if material == voxel::Material::Piston {
+              orientations.facings.insert(placed, facing);
+            } else {
+              orientations.facings.remove(&placed);
+            }
+          }
+          let brush = sphere_brush(center, 0.75, material);
+          update_gaia(update_gaia::Message::Brush(brush));
+        });
+      },
+      protocol::ClientToServer::Interact(player_id, target) => {
+        if trade::interact(server, player_id, &target) {
+          return
+        }
+        cast_toward(server, player_id, &target).map(|(bounds, _)| {
+          let voxel = *server.terrain_loader.terrain.voxels.lock().unwrap().get(&bounds).unwrap();
+          circuits::toggled(circuits::material_of(&voxel)).map(|material| {
+            debug!("use {:?}: now {:?}", bounds, material);
+            update_gaia::set_voxels(server, vec!((bounds, circuits::with_material(voxel, material))));
+          });
+        });
+      },
+      protocol::ClientToServer::Attack(player_id, target) => {
+        health::attack(server, player_id, target);
+      },
+      protocol::ClientToServer::Throw(player_id, direction) => {
+        projectiles::throw(server, player_id, direction);
+      },
+      protocol::ClientToServer::Chat(player_id, text) => {
+        let text: String = text.trim().chars().filter(|c| !c.is_control()).take(MAX_CHAT_LENGTH).collect();
+        if text.is_empty() {
+          return
+        }
+        if commands::is_command(&text) {
+          commands::run(server, player_id, &text);
+          return
+        }
+        let name =
+          match server.players.lock().unwrap().get(&player_id) {
+            None => return,
+            Some(player) => player.name.clone(),
+          };
+        let text = format!("{}: {}", name, text);
+        info!("{}", text);
+        for (_, client) in server.clients.lock().unwrap().iter_mut() {
+          client.send(protocol::ServerToClient::Message(protocol::MessageKind::Chat, text.clone()));
+        }
+      },
+      protocol::ClientToServer::Trade(player_id, trader, index) => {
+        trade::accept(server, player_id, trader, index);
+      },
+      protocol::ClientToServer::SelectSlot(player_id, slot) => {
+        server.players.lock().unwrap().get_mut(&player_id).unwrap().inventory.select(slot as usize);
+      },
+      protocol::ClientToServer::PlaceShape(player_id, target, name) => {
+        let model =
+          match server.shapes.lock().unwrap().find(&name) {
+            None => {
+              warn!("Player {:?} tried to place an unknown shape {:?}", player_id, name);
+              return
+            },
+            Some(model) => model.clone(),
+          };
+        let material =
+          match server.players.lock().unwrap().get(&player_id).unwrap().inventory.selected() {
+            None => return,
+            Some(material) => material,
+          };
+        cast_toward(server, player_id, &target).map(|(bounds, direction)| {
+          let p = bounds.center() + -direction;
+          if arena_refuses(server, player_id, &p) {
+            return
+          }
+          let low = shapes::cell(&p);
+          let orientation = shape::Orientation::looking(&model, direction);
+          let shape_bounds = Aabb3::new(low, low + Vector3::new(shapes::WIDTH, shapes::WIDTH, shapes::WIDTH));
+          if material.is_solid() && !make_room(server, player_id, &shape_bounds) {
+            return
+          }
+          // A shape costs one voxel's worth, whatever its size.
+          if take_selected(server, player_id) != Some(material) {
+            return
+          }
+          debug!("place {:?} {} {:?} at {:?}", material, model.name, orientation, low);
+          event_log::record_player(server, player_id, &event_log::Event::Edited("shape", low));
+          let brush = shapes::brush(server, &model, &orientation, low, material);
+          update_gaia(update_gaia::Message::Brush(brush));
+          let placed =
+            orientations::Placed {
+              shape       : model.name.clone(),
+              orientation : orientation,
+              material    : material,
             };
-          let r = sphere.field.field.radius + 1.0;
-          let brush =
-            voxel_data::brush::T {
-              bounds:
-                Aabb3::new(
-                  {
-                    let low = sphere.field.translation + (&-Vector3::new(r, r, r));
-                    Point3::new(low.x.floor() as i32, low.y.floor() as i32, low.z.floor() as i32)
-                  },
-                  {
-                    let high = sphere.field.translation + (&Vector3::new(r, r, r));
-                    Point3::new(high.x.ceil() as i32, high.y.ceil() as i32, high.z.ceil() as i32)
-                  },
-                ),
-              mosaic: Box::new(sphere) as Box<voxel_data::mosaic::T<voxel::Material> + Send>,
-              min_lg_size: 0,
+          server.orientations.lock().unwrap().shapes.insert(shapes::cell_bounds(&low), placed);
+        });
+      },
+      protocol::ClientToServer::Rotate(player_id, target) => {
+        cast_toward(server, player_id, &target).map(|(bounds, direction)| {
+          if arena_refuses(server, player_id, &bounds.center()) {
+            return
+          }
+          let material = server.terrain_loader.terrain.voxels.lock().unwrap().get(&bounds).map(circuits::material_of);
+          if material == Some(voxel::Material::Piston) {
+            let facing = {
+              let mut orientations = server.orientations.lock().unwrap();
+              let facing = orientations.facings.get(&bounds).map_or(0, |&facing| (facing + 1) % 6);
+              orientations.facings.insert(bounds, facing);
+              facing
             };
-          let brush: voxel_data::brush::T<Box<voxel_data::mosaic::T<voxel::Material> + Send>> = brush;
-          update_gaia(update_gaia::Message::Brush(brush));
+            debug!("rotate piston {:?} to face {}", bounds, facing);
+            // It might be powered, and pushing in its new direction.
+            server.block_updates.lock().unwrap().queue(&bounds);
+            return
+          }
+          rotate_shape(server, update_gaia, &bounds, &direction);
         });
       },
     };
   })
 }
+
+#[test]
+fn secrets_match_only_exactly() {
+  assert!(same_secret("hunter2", "hunter2"));
+  assert!(!same_secret("hunter2", "hunter3"));
+  assert!(!same_secret("hunter2", "Hunter2"));
+  assert!(!same_secret("hunter2", "hunter"));
+  assert!(!same_secret("hunter2", ""));
+}
@@ -0,0 +1,316 @@
+//! Commands players type into chat, like `/tp 0 64 0`. Each is a `Command`, so new server features
+//! can be driven in-game by adding one to `new`. Most can only be run by operators: players whose
+//! name is in `OPS_VAR`. Names are claimed with a token (see `accounts`), so they can't be borrowed the
+//! way a client's self-reported address could.
+
+use cgmath::{Point3, Vector3};
+use std;
+
+use common::protocol;
+use common::rng;
+
+use entity;
+use event_log;
+use names;
+use server;
+use trade;
+
+/// Environment variable holding a comma-separated list of the names of the players who may run
+/// operator-only commands. If it's unset, nobody can.
+const OPS_VAR: &'static str = "PLAYFORM_OPS";
+
+/// The most of a material `/give` hands out at once.
+const MAX_GIVE: u32 = 640;
+
+/// The most names `/name` makes up at once.
+const MAX_NAMES: u32 = 10;
+
+/// Something a player can run from chat.
+pub trait Command: Send + Sync {
+  /// What it's typed as, after the `/`.
+  fn name(&self) -> &'static str;
+  /// The arguments it takes, for when they're wrong.
+  fn usage(&self) -> &'static str;
+  /// Whether only operators can run it.
+  fn op_only(&self) -> bool {
+    true
+  }
+  /// Run it for a player. Either way, the result is shown to them.
+  fn run(&self, server: &server::T, player_id: entity::id::Player, args: &[&str]) -> Result<String, String>;
+}
+
+#[allow(missing_docs)]
+pub struct T {
+  commands : Vec<Box<Command>>,
+  /// The names of the players allowed to run operator-only commands.
+  ops      : Vec<String>,
+}
+
+#[allow(missing_docs)]
+pub fn new() -> T {
+  let ops =
+    match std::env::var(OPS_VAR) {
+      Err(_) => Vec::new(),
+      Ok(ops) => ops.split(',').map(|op| String::from(op.trim())).filter(|op| !op.is_empty()).collect(),
+    };
+  if !ops.is_empty() {
+    info!("Operators: {}", ops.join(", "));
+  }
+  T {
+    commands : vec!(
+      Box::new(Teleport) as Box<Command>,
+      Box::new(Time),
+      Box::new(Give),
+      Box::new(Seed),
+      Box::new(Name),
+    ),
+    ops      : ops,
+  }
+}
+
+/// Whether a chat line is a command rather than something to say.
+pub fn is_command(text: &str) -> bool {
+  text.starts_with('/')
+}
+
+/// Run a command line typed by a player, and tell them how it went.
+pub fn run(server: &server::T, player_id: entity::id::Player, line: &str) {
+  let result = run_line(server, player_id, line.trim_left_matches('/'));
+  let text =
+    match result {
+      Ok(text) => text,
+      Err(text) => text,
+    };
+  let client_id =
+    match server.players.lock().unwrap().get(&player_id) {
+      None => return,
+      Some(player) => player.client_id,
+    };
+  if let Some(client) = server.clients.lock().unwrap().get_mut(&client_id) {
+    client.send(protocol::ServerToClient::Message(protocol::MessageKind::Notice, text));
+  }
+}
+
+fn run_line(server: &server::T, player_id: entity::id::Player, line: &str) -> Result<String, String> {
+  let words: Vec<&str> = line.split_whitespace().collect();
+  let name =
+    match words.first() {
+      None => return Err(String::from("Type /help to see the commands.")),
+      Some(&name) => name,
+    };
+
+  let op = is_op(server, player_id);
+  let commands = &server.commands.commands;
+  if name == "help" {
+    let names: Vec<String> =
+      commands.iter()
+      .filter(|command| op || !command.op_only())
+      .map(|command| format!("/{} {}", command.name(), command.usage()))
+      .collect();
+    return Ok(format!("Commands: {}", names.join(", ")))
+  }
+  let command =
+    match commands.iter().find(|command| command.name() == name) {
+      None => return Err(format!("Unknown command /{}. Type /help to see the commands.", name)),
+      Some(command) => command,
+    };
+  if command.op_only() && !op {
+    return Err(format!("Only operators can run /{}.", name))
+  }
+  if command.op_only() {
+    event_log::record_player(server, player_id, &event_log::Event::Command(String::from(line)));
+  }
+  command.run(server, player_id, &words[1 ..])
+}
+
+/// Whether a player is playing under one of the operators' names.
+fn is_op(server: &server::T, player_id: entity::id::Player) -> bool {
+  match server.players.lock().unwrap().get(&player_id) {
+    None => false,
+    Some(player) => server.commands.ops.iter().any(|op| *op == player.name),
+  }
+}
+
+/// `/tp x y z`: move yourself, feet first, to a point.
+struct Teleport;
+
+impl Command for Teleport {
+  fn name(&self) -> &'static str { "tp" }
+  fn usage(&self) -> &'static str { "<x> <y> <z>" }
+
+  fn run(&self, server: &server::T, player_id: entity::id::Player, args: &[&str]) -> Result<String, String> {
+    let usage = || format!("usage: /{} {}", self.name(), self.usage());
+    let coords: Vec<f32> =
+      match args.iter().map(|arg| arg.parse()).collect() {
+        Ok(coords) => coords,
+        Err(_) => return Err(usage()),
+      };
+    if coords.len() != 3 || coords.iter().any(|c| !c.is_finite()) {
+      return Err(usage())
+    }
+    let feet = Point3::new(coords[0], coords[1], coords[2]);
+
+    let mut players = server.players.lock().unwrap();
+    let player =
+      match players.get_mut(&player_id) {
+        None => return Err(String::from("You're not in the world.")),
+        Some(player) => player,
+      };
+    let mut physics = server.physics.lock().unwrap();
+    let bounds =
+      match physics.get_bounds(player.body.id) {
+        None => return Err(String::from("You're not in the world.")),
+        Some(&bounds) => bounds,
+      };
+    let bottom = Point3::new((bounds.min.x + bounds.max.x) / 2.0, bounds.min.y, (bounds.min.z + bounds.max.z) / 2.0);
+    let shift = feet - bottom;
+    physics.move_body_freely(&player.body, shift);
+    player.position += shift;
+    player.speed = Vector3::new(0.0, 0.0, 0.0);
+    Ok(format!("Teleported to ({:.1}, {:.1}, {:.1}).", feet.x, feet.y, feet.z))
+  }
+}
+
+/// `/time` to see how far through the day it is, or `/time set` to change it.
+struct Time;
+
+impl Command for Time {
+  fn name(&self) -> &'static str { "time" }
+  fn usage(&self) -> &'static str { "[set <sunrise|day|sunset|night|fraction of a day>]" }
+
+  fn op_only(&self) -> bool {
+    false
+  }
+
+  fn run(&self, server: &server::T, player_id: entity::id::Player, args: &[&str]) -> Result<String, String> {
+    if args.is_empty() {
+      let fraction = server.sun.lock().unwrap().fraction();
+      return Ok(format!("It's {:.0}% of the way through the day.", fraction * 100.0))
+    }
+    // Anyone can ask the time, but only operators can change it.
+    let usage = || format!("usage: /{} {}", self.name(), self.usage());
+    if args.len() != 2 || args[0] != "set" {
+      return Err(usage())
+    }
+    if !is_op(server, player_id) {
+      return Err(String::from("Only operators can change the time."))
+    }
+    let fraction =
+      match args[1] {
+        "sunrise" => 0.0,
+        "day" => 0.25,
+        "sunset" => 0.5,
+        "night" => 0.75,
+        fraction => {
+          match fraction.parse::<f32>() {
+            Ok(fraction) if fraction >= 0.0 && fraction < 1.0 => fraction,
+            _ => return Err(usage()),
+          }
+        },
+      };
+    event_log::record_player(server, player_id, &event_log::Event::Command(format!("time set {}", args[1])));
+    let fraction = {
+      let mut sun = server.sun.lock().unwrap();
+      sun.set_fraction(fraction);
+      sun.fraction()
+    };
+    for (_, client) in server.clients.lock().unwrap().iter_mut() {
+      client.send(protocol::ServerToClient::UpdateSun(fraction));
+    }
+    Ok(format!("Set the time to {:.0}% of the way through the day.", fraction * 100.0))
+  }
+}
+
+/// `/give <material> [count]`: put some of a material in your inventory.
+struct Give;
+
+impl Command for Give {
+  fn name(&self) -> &'static str { "give" }
+  fn usage(&self) -> &'static str { "<material> [count]" }
+
+  fn run(&self, server: &server::T, player_id: entity::id::Player, args: &[&str]) -> Result<String, String> {
+    let usage = || format!("usage: /{} {}", self.name(), self.usage());
+    let material =
+      match args.first().and_then(|&name| trade::material(name)) {
+        None => return Err(usage()),
+        Some(material) => material,
+      };
+    let count =
+      match args.get(1).map(|count| count.parse()) {
+        None => 1,
+        Some(Ok(count)) if count > 0 && count <= MAX_GIVE => count,
+        Some(_) => return Err(format!("The count must be from 1 to {}.", MAX_GIVE)),
+      };
+    if args.len() > 2 {
+      return Err(usage())
+    }
+    let (left, client_id, inventory) = {
+      let mut players = server.players.lock().unwrap();
+      let player =
+        match players.get_mut(&player_id) {
+          None => return Err(String::from("You're not in the world.")),
+          Some(player) => player,
+        };
+      let left = player.inventory.add(material, count);
+      (left, player.client_id, player.inventory.update_message())
+    };
+    if let Some(client) = server.clients.lock().unwrap().get_mut(&client_id) {
+      client.send(inventory);
+    }
+    if left == count {
+      Err(String::from("There's no room in your inventory."))
+    } else {
+      Ok(format!("Gave you {} {}.", count - left, args[0]))
+    }
+  }
+}
+
+/// `/seed`: the world's name and the seed it was generated from.
+struct Seed;
+
+impl Command for Seed {
+  fn name(&self) -> &'static str { "seed" }
+  fn usage(&self) -> &'static str { "" }
+
+  fn op_only(&self) -> bool {
+    false
+  }
+
+  fn run(&self, server: &server::T, _: entity::id::Player, _: &[&str]) -> Result<String, String> {
+    Ok(format!("{} was generated from seed {}.", server.world_name, server.seed))
+  }
+}
+
+/// `/name <world|npc|animal> [count]`: make up some new names, to try out the name grammars or to
+/// pick one for something.
+struct Name;
+
+impl Command for Name {
+  fn name(&self) -> &'static str { "name" }
+  fn usage(&self) -> &'static str { "<world|npc|animal> [count]" }
+
+  fn op_only(&self) -> bool {
+    false
+  }
+
+  fn run(&self, server: &server::T, player_id: entity::id::Player, args: &[&str]) -> Result<String, String> {
+    let usage = || format!("usage: /{} {}", self.name(), self.usage());
+    let kind =
+      match args.first().and_then(|&kind| names::Kind::parse(kind)) {
+        None => return Err(usage()),
+        Some(kind) => kind,
+      };
+    let count =
+      match args.get(1).map(|count| count.parse()) {
+        None => 1,
+        Some(Ok(count)) if count > 0 && count <= MAX_NAMES => count,
+        Some(_) => return Err(format!("The count must be from 1 to {}.", MAX_NAMES)),
+      };
+    if args.len() > 2 {
+      return Err(usage())
+    }
+    let mut rng = server.rng(rng::subsystem::NAMES, &[server.tick() as i64, player_id.to_u32() as i64]);
+    let made_up: Vec<String> = (0 .. count).map(|_| names::generate(&mut rng, kind)).collect();
+    Ok(made_up.join(", "))
+  }
+}
@@ -0,0 +1,256 @@
+//! Entity data kept by component: each kind of data lives in its own table keyed by `EntityId`, and
+//! systems like `simulate` run over whichever entities have the components they need. New kinds of
+//! entity are then just new combinations of components, rather than another struct and update loop.
+//! Players and mobs still keep most of their state in `player::T` and `mob::Mob`.
+
+use cgmath::{Point3, Vector3, EuclideanSpace, InnerSpace};
+use collision::Aabb3;
+use std;
+
+use common::fnv_map;
+use common::protocol;
+
+use entity;
+use entity::EntityId;
+use physics;
+use server;
+
+/// Downward acceleration of simulated entities, in voxels per tick per tick.
+pub const GRAVITY: f32 = 0.02;
+
+/// The fastest simulated entities fall, in voxels per tick.
+pub const TERMINAL_SPEED: f32 = 1.0;
+
+/// The fraction of their sideways speed entities keep each tick they're on the ground.
+const GROUND_FRICTION: f32 = 0.8;
+
+/// Entities slower than this along an axis after hitting something, in voxels per tick, stop.
+const REST_SPEED: f32 = 0.02;
+
+/// Clients aren't told about moves shorter than this, so resting entities aren't resent every tick.
+const MIN_UPDATE_SHIFT: f32 = 0.001;
+
+/// One kind of component, for the entities that have it.
+pub struct Store<C> {
+  components : fnv_map::T<EntityId, C>,
+}
+
+fn store<C>() -> Store<C> {
+  Store {
+    components : fnv_map::new(),
+  }
+}
+
+impl<C> Store<C> {
+  /// Give an entity this component, replacing any it had.
+  pub fn insert(&mut self, id: EntityId, component: C) -> Option<C> {
+    self.components.insert(id, component)
+  }
+
+  #[allow(missing_docs)]
+  pub fn remove(&mut self, id: &EntityId) -> Option<C> {
+    self.components.remove(id)
+  }
+
+  #[allow(missing_docs)]
+  pub fn get(&self, id: &EntityId) -> Option<&C> {
+    self.components.get(id)
+  }
+
+  #[allow(missing_docs)]
+  pub fn get_mut(&mut self, id: &EntityId) -> Option<&mut C> {
+    self.components.get_mut(id)
+  }
+
+  /// Whether an entity has this component.
+  pub fn contains(&self, id: &EntityId) -> bool {
+    self.components.contains_key(id)
+  }
+
+  #[allow(missing_docs)]
+  pub fn iter(&self) -> std::collections::hash_map::Iter<EntityId, C> {
+    self.components.iter()
+  }
+
+  #[allow(missing_docs)]
+  pub fn iter_mut(&mut self) -> std::collections::hash_map::IterMut<EntityId, C> {
+    self.components.iter_mut()
+  }
+}
+
+#[allow(missing_docs)]
+#[derive(Debug, Clone, Copy)]
+pub struct Health {
+  pub current : u32,
+  pub max     : u32,
+}
+
+impl Health {
+  /// Unhurt, with `max` health.
+  pub fn full(max: u32) -> Health {
+    Health {
+      current : max,
+      max     : max,
+    }
+  }
+}
+
+/// Something thrown, which `projectiles::update` moves instead of `simulate`.
+#[derive(Debug, Clone, Copy)]
+pub struct Projectile {
+  #[allow(missing_docs)]
+  pub thrower : entity::id::Player,
+  /// The tick it's dropped at if it hasn't hit anything.
+  pub expiry  : u64,
+}
+
+/// Shown to clients: as a mob the size of the entity's body, or as the item it carries.
+#[derive(Debug, Clone, Copy)]
+pub struct Mesh;
+
+#[allow(missing_docs)]
+pub struct T {
+  pub position   : Store<Point3<f32>>,
+  /// In voxels per tick. Entities with a velocity and either a body or loose bounds are moved by
+  /// `simulate`.
+  pub velocity   : Store<Vector3<f32>>,
+  pub body       : Store<physics::Body>,
+  /// The bounds of entities that only collide with terrain, and aren't in `physics` at all.
+  pub loose      : Store<Aabb3<f32>>,
+  /// The fraction of its speed an entity keeps when it bounces off something.
+  pub bounce     : Store<f32>,
+  pub mesh       : Store<Mesh>,
+  pub health     : Store<Health>,
+  /// What a dropped item is a stack of.
+  pub item       : Store<protocol::ItemStack>,
+  /// The loot table rolled for what an entity leaves behind when it dies.
+  pub loot       : Store<String>,
+  pub projectile : Store<Projectile>,
+}
+
+#[allow(missing_docs)]
+pub fn new() -> T {
+  T {
+    position   : store(),
+    velocity   : store(),
+    body       : store(),
+    loose      : store(),
+    bounce     : store(),
+    mesh       : store(),
+    health     : store(),
+    item       : store(),
+    loot       : store(),
+    projectile : store(),
+  }
+}
+
+impl T {
+  /// Take away all of an entity's components.
+  pub fn remove(&mut self, id: &EntityId) {
+    self.position.remove(id);
+    self.velocity.remove(id);
+    self.body.remove(id);
+    self.loose.remove(id);
+    self.bounce.remove(id);
+    self.mesh.remove(id);
+    self.health.remove(id);
+    self.item.remove(id);
+    self.loot.remove(id);
+    self.projectile.remove(id);
+  }
+}
+
+/// Move everything with a velocity under gravity, one axis at a time so it slides along whatever's
+/// in the way, bouncing off it or stopping against it, and show clients where the ones with meshes
+/// went. Call this every tick.
+pub fn simulate(server: &server::T) {
+  let mut updates = Vec::new();
+  {
+    let mut components = server.components.lock().unwrap();
+    let components = &mut *components;
+    let mut physics = server.physics.lock().unwrap();
+    for (&id, velocity) in components.velocity.iter_mut() {
+      let body = components.body.get(&id).cloned();
+      let mut loose = components.loose.get_mut(&id);
+      if body.is_none() && loose.is_none() {
+        continue
+      }
+      velocity.y = (velocity.y - GRAVITY).max(-TERMINAL_SPEED);
+      let bounce = components.bounce.get(&id).cloned().unwrap_or(0.0);
+
+      let mut shift = Vector3::new(0.0, 0.0, 0.0);
+      let mut bounds = None;
+      for axis in 0 .. 3 {
+        if velocity[axis] == 0.0 {
+          continue
+        }
+        let mut step = Vector3::new(0.0, 0.0, 0.0);
+        step[axis] = velocity[axis];
+        let (moved_bounds, moved_shift, blocked) =
+          match body {
+            Some(body) => {
+              let moved = physics.move_body(&body, step);
+              (moved.bounds, moved.shift, moved.blocked)
+            },
+            None => {
+              let loose = loose.as_mut().unwrap();
+              let (moved_bounds, blocked) = physics.move_loose(loose, step);
+              let moved_shift = moved_bounds.min - loose.min;
+              **loose = moved_bounds;
+              (moved_bounds, moved_shift, blocked)
+            },
+          };
+        shift += moved_shift;
+        bounds = Some(moved_bounds);
+        if blocked {
+          if axis == 1 && velocity.y < 0.0 {
+            velocity.x = slow(velocity.x * GROUND_FRICTION);
+            velocity.z = slow(velocity.z * GROUND_FRICTION);
+          }
+          velocity[axis] = slow(-velocity[axis] * bounce);
+        }
+      }
+
+      if let Some(position) = components.position.get_mut(&id) {
+        *position += shift;
+      }
+      if shift.magnitude2() < MIN_UPDATE_SHIFT * MIN_UPDATE_SHIFT {
+        continue
+      }
+      let bounds = bounds.unwrap();
+      let center = Point3::from_vec((bounds.min.to_vec() + bounds.max.to_vec()) * 0.5);
+      if !components.mesh.contains(&id) {
+        continue
+      }
+      match id {
+        EntityId::Mob(mob_id) => {
+          let transform =
+            protocol::MobTransform {
+              position : center,
+              yaw      : 0.0,
+              velocity : *velocity * server::UPDATES_PER_SECOND as f32,
+              size     : bounds.max - bounds.min,
+            };
+          updates.push(protocol::ServerToClient::UpdateMob(mob_id, transform));
+        },
+        EntityId::Item(item_id) => {
+          if let Some(stack) = components.item.get(&id) {
+            updates.push(protocol::ServerToClient::UpdateItem(item_id, center, stack.material));
+          }
+        },
+        EntityId::Player(_) | EntityId::Projectile(_) => {},
+      }
+    }
+  }
+
+  for (_, client) in server.clients.lock().unwrap().iter_mut() {
+    for update in &updates {
+      client.send(update.clone());
+    }
+  }
+}
+
+/// Speeds this close to zero are rounded down to it, so things come to rest.
+fn slow(speed: f32) -> f32 {
+  if speed.abs() < REST_SPEED { 0.0 } else { speed }
+}
@@ -0,0 +1,88 @@
+//! Clients leaving, whether they say so with `Disconnect` or just stop answering. Every client is
+//! pinged every `PING_INTERVAL_NS`, and ones that haven't answered in `TIMEOUT_NS` are dropped.
+//! Either way, the client's player is saved and taken out of the world and off the other clients,
+//! and the terrain loaded around them is released.
+
+use time;
+
+use common::protocol;
+
+use entity;
+use entity_regions;
+use event_log;
+use player;
+use server;
+
+/// How often clients are pinged.
+pub const PING_INTERVAL_NS: u64 = 5_000_000_000;
+
+/// Clients that haven't been heard from in this long are dropped.
+const TIMEOUT_NS: u64 = 30_000_000_000;
+
+/// Note that a client is still there.
+pub fn heard_from(server: &server::T, client_id: protocol::ClientId) {
+  if let Some(client) = server.clients.lock().unwrap().get_mut(&client_id) {
+    client.last_heard_ns = time::precise_time_ns();
+  }
+}
+
+/// Ping every client, and drop the ones that have gone quiet. This is run every step, but only does
+/// anything every `PING_INTERVAL_NS`.
+pub fn check(server: &server::T) {
+  let now = time::precise_time_ns();
+  if server.ping_timer.lock().unwrap().update(now) == 0 {
+    return
+  }
+  let quiet: Vec<protocol::ClientId> = {
+    let mut clients = server.clients.lock().unwrap();
+    for (_, client) in clients.iter_mut() {
+      client.send(protocol::ServerToClient::Ping);
+    }
+    clients.iter()
+      .filter(|&(_, client)| now > client.last_heard_ns + TIMEOUT_NS)
+      .map(|(&client_id, _)| client_id)
+      .collect()
+  };
+  for client_id in quiet {
+    disconnect(server, client_id, "timed out");
+  }
+}
+
+/// Take a client off the server, for some reason: save its player and take them out of the world,
+/// and let go of everything held for it.
+pub fn disconnect(server: &server::T, client_id: protocol::ClientId, reason: &str) {
+  let client =
+    match server.clients.lock().unwrap().remove(&client_id) {
+      None => return,
+      Some(client) => client,
+    };
+  info!("{} at {} left: {}", client.name, client.address, reason);
+  server.event_log.lock().unwrap().record(
+    &event_log::Actor::Client(client_id, client.address.clone()),
+    &event_log::Event::Left(String::from(reason)),
+  );
+
+  let players: Vec<player::T> = {
+    let mut players = server.players.lock().unwrap();
+    let ids: Vec<entity::id::Player> =
+      players.values()
+      .filter(|player| player.client_id == client_id)
+      .map(|player| player.entity_id)
+      .collect();
+    ids.iter().filter_map(|id| players.remove(id)).collect()
+  };
+  for player in players {
+    server.accounts.save(&player);
+    server.physics.lock().unwrap().remove_misc(player.body.id);
+    for &owner in player.owners().iter() {
+      server.terrain_loader.unload_owner(&server.physics, owner);
+    }
+    server.entity_regions.lock().unwrap().remove(&entity_regions::Entity::Player(player.entity_id));
+    server.components.lock().unwrap().remove(&entity::EntityId::Player(player.entity_id));
+    for (_, client) in server.clients.lock().unwrap().iter_mut() {
+      client.send(protocol::ServerToClient::RemovePlayer(player.entity_id));
+    }
+  }
+
+  server.hints.lock().unwrap().forget(client_id);
+}
@@ -1,3 +1,5 @@
+pub use common::entity::EntityId;
+
 mod types {
   #[allow(missing_docs)]
   #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
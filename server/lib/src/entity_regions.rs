@@ -0,0 +1,84 @@
+//! Index of which entities are in which regions, kept up to date as they move,
+//! so code that cares about some part of the world can look up the entities there directly
+//! instead of filtering every entity.
+
+use cgmath::Point3;
+
+use common::fnv_map;
+use common::fnv_set;
+
+use world_save;
+use world_save::Region;
+
+pub use entity::EntityId as Entity;
+
+#[allow(missing_docs)]
+pub struct T {
+  by_region : fnv_map::T<Region, fnv_set::T<Entity>>,
+  region_of : fnv_map::T<Entity, Region>,
+}
+
+#[allow(missing_docs)]
+pub fn new() -> T {
+  T {
+    by_region : fnv_map::new(),
+    region_of : fnv_map::new(),
+  }
+}
+
+impl T {
+  /// Note where an entity is now. Returns the entity's new region if it's changed regions.
+  pub fn moved(&mut self, entity: Entity, position: &Point3<f32>) -> Option<Region> {
+    let region = world_save::region_at(position);
+    match self.region_of.insert(entity, region) {
+      Some(prev) if prev == region => return None,
+      Some(prev) => self.remove_from(&prev, &entity),
+      None => {},
+    }
+    self.by_region.entry(region).or_insert_with(fnv_set::new).insert(entity);
+    Some(region)
+  }
+
+  /// Forget an entity, e.g. once it's gone from the world.
+  pub fn remove(&mut self, entity: &Entity) {
+    if let Some(region) = self.region_of.remove(entity) {
+      self.remove_from(&region, entity);
+    }
+  }
+
+  fn remove_from(&mut self, region: &Region, entity: &Entity) {
+    let now_empty =
+      match self.by_region.get_mut(region) {
+        None => false,
+        Some(entities) => {
+          entities.remove(entity);
+          entities.is_empty()
+        },
+      };
+    if now_empty {
+      self.by_region.remove(region);
+    }
+  }
+
+  /// The region an entity was last seen in.
+  pub fn region_of(&self, entity: &Entity) -> Option<Region> {
+    self.region_of.get(entity).cloned()
+  }
+
+  /// All the entities in some regions.
+  pub fn in_regions(&self, regions: &[Region]) -> Vec<Entity> {
+    let mut entities = Vec::new();
+    for region in regions {
+      if let Some(in_region) = self.by_region.get(region) {
+        entities.extend(in_region.iter().cloned());
+      }
+    }
+    entities
+  }
+
+  /// Whether there are any players within `radius` regions of `region`.
+  pub fn player_near(&self, region: &Region, radius: i32) -> bool {
+    self.in_regions(&world_save::regions_around(region, radius)).iter()
+      .any(|entity| match *entity { Entity::Player(_) => true, Entity::Mob(_) => false })
+  }
+}
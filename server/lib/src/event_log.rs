@@ -0,0 +1,137 @@
+//! An append-only record of significant events (who connected, who joined, who made big edits, what
+//! was run from the console) so operators can find out who did what after the fact.
+//! Each line is a UTC timestamp, the actor and the event, separated by tabs.
+
+use cgmath::Point3;
+use std;
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+use time;
+
+use common::protocol;
+
+use entity;
+use server;
+
+/// Who did something.
+#[derive(Debug, Clone)]
+pub enum Actor {
+  /// A client, with its address.
+  Client(protocol::ClientId, String),
+  /// A player, with the client controlling them.
+  Player(entity::id::Player, protocol::ClientId, String),
+  /// Someone at the server console.
+  Console,
+}
+
+impl std::fmt::Display for Actor {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    match *self {
+      Actor::Client(client_id, ref address) => write!(f, "client {:?} at {}", client_id, address),
+      Actor::Player(player_id, client_id, ref address) =>
+        write!(f, "player {} (client {:?} at {})", player_id.to_u32(), client_id, address),
+      Actor::Console => write!(f, "console"),
+    }
+  }
+}
+
+/// Something worth recording.
+#[derive(Debug, Clone)]
+pub enum Event {
+  /// A client connected.
+  Connected,
+  /// A client joined the world as a player, under a name.
+  Joined(entity::id::Player, String),
+  /// A client left, for some reason.
+  Left(String),
+  /// A large edit, e.g. a tree or a shape, and where it was made.
+  Edited(&'static str, Point3<f32>),
+  /// A console command was run.
+  Command(String),
+}
+
+impl std::fmt::Display for Event {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    match *self {
+      Event::Connected => write!(f, "connected"),
+      Event::Joined(player_id, ref name) => write!(f, "joined as {} (player {})", name, player_id.to_u32()),
+      Event::Left(ref reason) => write!(f, "left: {}", reason),
+      Event::Edited(what, p) => write!(f, "edited: {} at ({:.1}, {:.1}, {:.1})", what, p.x, p.y, p.z),
+      Event::Command(ref command) => write!(f, "ran: {}", command),
+    }
+  }
+}
+
+#[allow(missing_docs)]
+pub struct T {
+  path : PathBuf,
+  file : Option<std::fs::File>,
+}
+
+/// Open (or create) the event log at `path`.
+pub fn open(path: &Path) -> T {
+  let file =
+    std::fs::OpenOptions::new()
+    .append(true)
+    .create(true)
+    .open(path);
+  let file =
+    match file {
+      Ok(file) => Some(file),
+      Err(err) => {
+        warn!("Not recording events: error opening {:?}: {:?}", path, err);
+        None
+      },
+    };
+  T {
+    path : path.to_path_buf(),
+    file : file,
+  }
+}
+
+impl T {
+  /// Append an event to the log.
+  pub fn record(&mut self, actor: &Actor, event: &Event) {
+    let file =
+      match self.file {
+        None => return,
+        Some(ref mut file) => file,
+      };
+    let line = format!("{}\t{}\t{}\n", time::now_utc().rfc3339(), actor, event);
+    if let Err(err) = file.write_all(line.as_bytes()) {
+      warn!("Error recording event in {:?}: {:?}", self.path, err);
+    }
+  }
+
+  /// The last `limit` lines of the log that contain `filter`, oldest first.
+  pub fn query(&self, filter: &str, limit: usize) -> Result<Vec<String>, String> {
+    let file = try!(std::fs::File::open(&self.path).map_err(|err| format!("{:?}", err)));
+    let mut lines = std::collections::VecDeque::new();
+    for line in std::io::BufReader::new(file).lines() {
+      let line = try!(line.map_err(|err| format!("{:?}", err)));
+      if !line.contains(filter) {
+        continue
+      }
+      if lines.len() == limit {
+        lines.pop_front();
+      }
+      lines.push_back(line);
+    }
+    Ok(lines.into_iter().collect())
+  }
+}
+
+/// The actor for a player, with the client that's controlling them.
+fn player(server: &server::T, player_id: entity::id::Player) -> Actor {
+  let client_id = server.players.lock().unwrap().get(&player_id).unwrap().client_id;
+  let address =
+    server.clients.lock().unwrap().get(&client_id)
+    .map_or_else(|| String::from("?"), |client| client.address.clone());
+  Actor::Player(player_id, client_id, address)
+}
+
+/// Record something a player did.
+pub fn record_player(server: &server::T, player_id: entity::id::Player, event: &Event) {
+  let actor = player(server, player_id);
+  server.event_log.lock().unwrap().record(&actor, event);
+}
@@ -0,0 +1,85 @@
+//! Blocks of gravity-affected material that have lost their support.
+//! A falling block is taken out of the terrain and simulated as an entity until it lands,
+//! then put back into the terrain wherever it came to rest.
+
+use cgmath::{Point3, Vector3};
+
+use common::protocol;
+use common::voxel;
+
+use entity;
+use server;
+
+/// Downward acceleration, in voxels per tick per tick.
+const GRAVITY: f32 = 0.02;
+
+/// Falling blocks move at most one voxel per tick, so they can't skip over anything.
+const MAX_SPEED: f32 = 1.0;
+
+#[allow(missing_docs)]
+pub struct T {
+  /// The id this block is shown to clients as.
+  pub entity_id : entity::id::Mob,
+  #[allow(missing_docs)]
+  pub material  : voxel::Material,
+  /// The voxel the block fell from. It falls straight down from here.
+  origin        : voxel::bounds::T,
+  /// Height of the bottom of the block, in voxels.
+  y             : f32,
+  /// Downward speed, in voxels per tick.
+  speed         : f32,
+}
+
+#[allow(missing_docs)]
+pub fn new(entity_id: entity::id::Mob, origin: voxel::bounds::T, material: voxel::Material) -> T {
+  T {
+    entity_id : entity_id,
+    material  : material,
+    origin    : origin,
+    y         : origin.y as f32,
+    speed     : 0.0,
+  }
+}
+
+impl T {
+  fn at_height(&self, y: i32) -> voxel::bounds::T {
+    voxel::bounds::new(self.origin.x, y, self.origin.z, self.origin.lg_size)
+  }
+
+  /// Fall for one tick. If the block lands, returns the voxel it came to rest in.
+  /// Blocks sink through water and come to rest in it, displacing it.
+  /// Unloaded voxels count as solid, so blocks never fall out of the loaded world.
+  pub fn update(&mut self, voxels: &voxel::tree::T) -> Option<voxel::bounds::T> {
+    self.speed = f32::min(self.speed + GRAVITY, MAX_SPEED);
+    let target = self.y - self.speed;
+
+    let top = self.y.ceil() as i32 - 1;
+    let bottom = target.floor() as i32;
+    for y in (bottom .. top + 1).rev() {
+      let solid =
+        match voxels.get(&self.at_height(y)) {
+          Some(&voxel::Volume(material)) if !material.is_solid() => false,
+          _ => true,
+        };
+      if solid {
+        self.y = (y + 1) as f32;
+        return Some(self.at_height(y + 1))
+      }
+    }
+
+    self.y = target;
+    None
+  }
+
+  /// Where clients should draw this block.
+  pub fn transform(&self) -> protocol::MobTransform {
+    let width = (self.origin.lg_size as f32).exp2();
+    let low = Point3::new(self.origin.x as f32, self.y, self.origin.z as f32) * width;
+    protocol::MobTransform {
+      position : low + Vector3::new(0.5, 0.5, 0.5) * width,
+      yaw      : 0.0,
+      velocity : Vector3::new(0.0, -self.speed * width * server::UPDATES_PER_SECOND as f32, 0.0),
+      size     : Vector3::new(width, width, width),
+    }
+  }
+}
@@ -0,0 +1,44 @@
+//! A cellular automaton for water. Open space below sea level next to water floods, one voxel per update,
+//! so digging near the sea lets it pour in. The sea itself never drains.
+//! Water runs as part of neighbor updates, so it only does work near something that changed.
+//! Placing a voxel simply replaces any water there.
+
+use common::voxel;
+use common::voxel::Material;
+
+use block_updates;
+
+fn is_water(voxel: Option<&voxel::T>) -> bool {
+  match voxel {
+    Some(&voxel::Volume(Material::Water)) => true,
+    _ => false,
+  }
+}
+
+/// Re-evaluate the voxel at `bounds` after one of its neighbors changed.
+/// Returns what the voxel becomes, if it floods.
+pub fn update(
+  voxels : &voxel::tree::T,
+  bounds : &voxel::bounds::T,
+) -> Option<voxel::T> {
+  match voxels.get(bounds) {
+    Some(&voxel::Volume(Material::Empty)) => {},
+    _ => return None,
+  }
+
+  let (_, high) = bounds.corners();
+  if high.y > voxel::SEA_LEVEL as f32 {
+    return None
+  }
+
+  // Water flows down and sideways, but not up.
+  let flooded =
+    block_updates::neighbors(bounds).iter()
+    .filter(|neighbor| neighbor.y >= bounds.y)
+    .any(|neighbor| is_water(voxels.get(neighbor)));
+  if flooded {
+    Some(voxel::Volume(Material::Water))
+  } else {
+    None
+  }
+}
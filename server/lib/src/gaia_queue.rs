@@ -0,0 +1,246 @@
+//! Queue of pending gaia updates.
+//! Brushes are applied first, in order. Terrain loads are handled nearest-first, favoring what
+//! players are looking at, so the visible horizon fills in before the terrain behind them.
+//! Loads are queued separately for each client that asked for them, and the clients take turns,
+//! with whoever has been served least lately going next. That way one player racing across the
+//! world can't hog terrain generation while everyone else waits.
+
+use cgmath::{Point3, Vector3, InnerSpace, MetricSpace};
+use std;
+use std::collections::{BinaryHeap, VecDeque};
+
+use common::fnv_map;
+use common::horizon;
+use common::protocol;
+
+use update_gaia;
+
+/// How much farther away terrain directly behind a player seems, compared to terrain in front.
+const BEHIND_PENALTY: f32 = 2.0;
+
+/// How far a viewer has to move before loads are re-prioritized.
+const REPRIORITIZE_DISTANCE: f32 = 8.0;
+
+/// Re-prioritize loads if a viewer turns further than this (as a cosine).
+const REPRIORITIZE_MIN_COS: f32 = 0.9;
+
+/// How much each destination's count of recently served loads decays by every time a load is served.
+const SERVED_DECAY: f32 = 0.99;
+
+/// Idle destinations are forgotten once their served count decays below this.
+const MIN_SERVED: f32 = 0.01;
+
+#[derive(Debug, Clone, Copy)]
+/// A point of view that terrain loads are prioritized around.
+pub struct Viewer {
+  #[allow(missing_docs)]
+  pub position : Point3<f32>,
+  /// Unit vector in the direction the viewer is looking.
+  pub forward  : Vector3<f32>,
+}
+
+struct Entry {
+  /// Lower is more urgent.
+  score   : f32,
+  /// Order of arrival, to keep equally-urgent loads first-come-first-served.
+  seq     : u64,
+  message : update_gaia::Message,
+}
+
+impl PartialEq for Entry {
+  fn eq(&self, other: &Entry) -> bool {
+    self.cmp(other) == std::cmp::Ordering::Equal
+  }
+}
+
+impl Eq for Entry {}
+
+impl PartialOrd for Entry {
+  fn partial_cmp(&self, other: &Entry) -> Option<std::cmp::Ordering> {
+    Some(self.cmp(other))
+  }
+}
+
+impl Ord for Entry {
+  // `BinaryHeap` pops the greatest entry first, so more urgent entries compare greater.
+  fn cmp(&self, other: &Entry) -> std::cmp::Ordering {
+    other.score.partial_cmp(&self.score).unwrap_or(std::cmp::Ordering::Equal)
+      .then_with(|| other.seq.cmp(&self.seq))
+  }
+}
+
+/// Who a load is for: a client, or `None` for the server itself.
+type Destination = Option<protocol::ClientId>;
+
+fn destination(message: &update_gaia::Message) -> Destination {
+  match *message {
+    update_gaia::Message::Load(_, _, update_gaia::LoadDestination::Client(client_id)) |
+    update_gaia::Message::Load(_, _, update_gaia::LoadDestination::Pushed(client_id)) |
+    update_gaia::Message::Load(_, _, update_gaia::LoadDestination::Check { client_id, .. }) |
+    update_gaia::Message::Horizon(client_id, _) => Some(client_id),
+    _ => None,
+  }
+}
+
+/// The pending loads for one destination.
+struct Loads {
+  heap   : BinaryHeap<Entry>,
+  /// Roughly how many loads this destination has been served lately. Decays by `SERVED_DECAY`
+  /// every time any destination is served.
+  served : f32,
+}
+
+#[allow(missing_docs)]
+pub struct T {
+  brushes  : VecDeque<update_gaia::Message>,
+  loads    : fnv_map::T<Destination, Loads>,
+  next_seq : u64,
+  /// The viewers that `loads` was last prioritized for.
+  viewers  : Vec<Viewer>,
+}
+
+#[allow(missing_docs)]
+pub fn new() -> T {
+  T {
+    brushes  : VecDeque::new(),
+    loads    : fnv_map::new(),
+    next_seq : 0,
+    viewers  : Vec::new(),
+  }
+}
+
+/// How urgent something at `center` is to the nearest viewer, as a distance. Lower is more urgent.
+fn view_score(viewers: &[Viewer], center: Point3<f32>) -> f32 {
+  let mut best = std::f32::INFINITY;
+  for viewer in viewers {
+    let distance = viewer.position.distance(center);
+    let facing =
+      if distance == 0.0 {
+        1.0
+      } else {
+        viewer.forward.dot((center - viewer.position) / distance)
+      };
+    // 1 when directly in front, 1 + BEHIND_PENALTY when directly behind.
+    let weight = 1.0 + BEHIND_PENALTY * (1.0 - facing) / 2.0;
+    best = f32::min(best, distance * weight);
+  }
+  best
+}
+
+fn score(viewers: &[Viewer], message: &update_gaia::Message) -> f32 {
+  let best =
+    match *message {
+      update_gaia::Message::Load(_, ref bounds, _) => {
+        bounds.iter()
+          .map(|bounds| view_score(viewers, bounds.center()))
+          .fold(std::f32::INFINITY, f32::min)
+      },
+      update_gaia::Message::Horizon(_, ref tile) => {
+        // Only the horizontal distance to a horizon tile matters.
+        let (x, z) = tile.sample_position(horizon::SAMPLES / 2, horizon::SAMPLES / 2);
+        viewers.iter()
+          .map(|viewer| view_score(&[*viewer], Point3::new(x, viewer.position.y, z)))
+          .fold(std::f32::INFINITY, f32::min)
+      },
+      update_gaia::Message::SpawnTraders(position) => view_score(viewers, position),
+      update_gaia::Message::Brush(_) => return 0.0,
+    };
+
+  if best.is_finite() {
+    best
+  } else {
+    0.0
+  }
+}
+
+impl T {
+  #[allow(missing_docs)]
+  pub fn push(&mut self, message: update_gaia::Message) {
+    match message {
+      update_gaia::Message::Brush(_) => self.brushes.push_back(message),
+      update_gaia::Message::Load(..) |
+      update_gaia::Message::Horizon(..) |
+      update_gaia::Message::SpawnTraders(..) => {
+        let entry =
+          Entry {
+            score   : score(&self.viewers, &message),
+            seq     : self.next_seq,
+            message : message,
+          };
+        self.next_seq += 1;
+        let loads =
+          self.loads.entry(destination(&entry.message)).or_insert_with(|| {
+            Loads {
+              heap   : BinaryHeap::new(),
+              served : 0.0,
+            }
+          });
+        loads.heap.push(entry);
+      },
+    }
+  }
+
+  /// The most urgent pending update.
+  pub fn pop(&mut self) -> Option<update_gaia::Message> {
+    self.brushes.pop_front()
+      .or_else(|| self.pop_load())
+  }
+
+  /// The most urgent pending load for whichever destination has been served least lately,
+  /// leaving brushes for whoever's applying them in order.
+  pub fn pop_load(&mut self) -> Option<update_gaia::Message> {
+    let next =
+      self.loads.iter()
+      .filter_map(|(&destination, loads)| loads.heap.peek().map(|entry| (destination, loads.served, entry.seq)))
+      // Break ties by age, so the order doesn't depend on the map's.
+      .min_by(|&(_, served1, seq1), &(_, served2, seq2)| {
+        served1.partial_cmp(&served2).unwrap_or(std::cmp::Ordering::Equal)
+          .then_with(|| seq1.cmp(&seq2))
+      })
+      .map(|(destination, _, _)| destination);
+    let next =
+      match next {
+        None => return None,
+        Some(next) => next,
+      };
+
+    for loads in self.loads.values_mut() {
+      loads.served *= SERVED_DECAY;
+    }
+    let entry = {
+      let loads = self.loads.get_mut(&next).unwrap();
+      loads.served += 1.0;
+      loads.heap.pop().unwrap()
+    };
+    self.loads.retain(|_, loads| !loads.heap.is_empty() || loads.served >= MIN_SERVED);
+    Some(entry.message)
+  }
+
+  #[allow(missing_docs)]
+  pub fn len(&self) -> usize {
+    self.brushes.len() + self.loads.values().map(|loads| loads.heap.len()).sum::<usize>()
+  }
+
+  /// Tell the queue where the viewers are now. Pending loads are re-prioritized if they've moved
+  /// or turned enough to matter.
+  pub fn update_viewers(&mut self, viewers: Vec<Viewer>) {
+    let changed =
+      viewers.len() != self.viewers.len() ||
+      viewers.iter().zip(self.viewers.iter()).any(|(new, old)| {
+        new.position.distance(old.position) > REPRIORITIZE_DISTANCE ||
+        new.forward.dot(old.forward) < REPRIORITIZE_MIN_COS
+      });
+    if !changed {
+      return
+    }
+
+    self.viewers = viewers;
+    for loads in self.loads.values_mut() {
+      let heap = std::mem::replace(&mut loads.heap, BinaryHeap::new());
+      for mut entry in heap.into_vec() {
+        entry.score = score(&self.viewers, &entry.message);
+        loads.heap.push(entry);
+      }
+    }
+  }
+}
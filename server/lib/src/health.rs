@@ -0,0 +1,190 @@
+//! Damage and death, for players and mobs with a `components::Health`: players hit things, fall too
+//! far, and respawn when they die, and mobs are removed, dropping loot. Bosses keep their own
+//! health; see `boss::damage`.
+
+use cgmath::{Point3, Vector3, EuclideanSpace, InnerSpace};
+use collision::Aabb3;
+use time;
+
+use common::protocol;
+use common::rng;
+
+use boss;
+use entity;
+use entity::EntityId;
+use hints;
+use init_mobs;
+use items;
+use loot;
+use player;
+use server;
+
+/// How far players can reach to hit something, from their eyes to the nearest part of it.
+const MELEE_REACH: f32 = 4.0;
+
+const MELEE_DAMAGE: u32 = 2;
+
+/// Players can attack once every this many ticks.
+const ATTACK_COOLDOWN: u64 = 15;
+
+/// Players land unhurt at up to this speed, in voxels per tick, which is a fall of about 5 voxels.
+const SAFE_LANDING_SPEED: f32 = 1.0;
+
+/// Damage per voxel per tick of landing speed over `SAFE_LANDING_SPEED`.
+const FALL_DAMAGE: f32 = 10.0;
+
+/// Players are warned once their health drops to this fraction of their max.
+const LOW_HEALTH: f32 = 0.25;
+
+/// The bounds of a player or mob.
+fn bounds_of(server: &server::T, id: EntityId) -> Option<Aabb3<f32>> {
+  let body =
+    match id {
+      EntityId::Player(player_id) => server.players.lock().unwrap().get(&player_id).map(|player| player.body),
+      EntityId::Mob(mob_id) => server.mobs.lock().unwrap().get(&mob_id).map(|mob| mob.body),
+      EntityId::Item(_) | EntityId::Projectile(_) => None,
+    };
+  body.and_then(|body| server.physics.lock().unwrap().get_bounds(body.id).cloned())
+}
+
+/// How far a point is from the nearest part of a box.
+fn distance_to(bounds: &Aabb3<f32>, p: &Point3<f32>) -> f32 {
+  let nearest =
+    Point3::new(
+      p.x.max(bounds.min.x).min(bounds.max.x),
+      p.y.max(bounds.min.y).min(bounds.max.y),
+      p.z.max(bounds.min.z).min(bounds.max.z),
+    );
+  (nearest - *p).to_vec().magnitude()
+}
+
+/// A player hits something. Hits on things out of reach, or too soon after the last, miss.
+pub fn attack(server: &server::T, attacker: entity::id::Player, target: EntityId) {
+  if target == EntityId::Player(attacker) {
+    return
+  }
+  let tick = *server.tick.lock().unwrap();
+  let (position, tool) = {
+    let mut players = server.players.lock().unwrap();
+    let player =
+      match players.get_mut(&attacker) {
+        None => return,
+        Some(player) => player,
+      };
+    if tick < player.next_attack_tick {
+      return
+    }
+    player.next_attack_tick = tick + ATTACK_COOLDOWN;
+    let tool = player.inventory.selected().map_or(loot::Tool::Hand, loot::Tool::Material);
+    (player.position, tool)
+  };
+  match bounds_of(server, target) {
+    Some(bounds) if distance_to(&bounds, &position) <= MELEE_REACH => {},
+    _ => return,
+  }
+
+  strike(server, target, MELEE_DAMAGE, tool);
+}
+
+/// Hurt a player or mob, or a boss, which keeps its own health, with some tool.
+pub fn strike(server: &server::T, target: EntityId, amount: u32, tool: loot::Tool) {
+  if let EntityId::Mob(mob_id) = target {
+    if boss::damage(server, mob_id, amount) {
+      return
+    }
+  }
+  damage(server, target, amount, tool);
+}
+
+/// A player landed at some speed, in voxels per tick.
+pub fn landed(server: &server::T, player_id: entity::id::Player, speed: f32) {
+  if speed > SAFE_LANDING_SPEED {
+    let amount = ((speed - SAFE_LANDING_SPEED) * FALL_DAMAGE).ceil() as u32;
+    damage(server, EntityId::Player(player_id), amount, loot::Tool::Nothing);
+  }
+}
+
+/// Take health away from a player or mob, killing them if it runs out. Mobs killed drop loot rolled
+/// for the tool that killed them.
+pub fn damage(server: &server::T, target: EntityId, amount: u32, tool: loot::Tool) {
+  let health = {
+    let mut components = server.components.lock().unwrap();
+    let health =
+      match components.health.get_mut(&target) {
+        None => return,
+        Some(health) => health,
+      };
+    health.current = health.current.saturating_sub(amount);
+    *health
+  };
+
+  match target {
+    EntityId::Player(player_id) => {
+      let client_id =
+        match server.players.lock().unwrap().get(&player_id) {
+          None => return,
+          Some(player) => player.client_id,
+        };
+      if let Some(client) = server.clients.lock().unwrap().get_mut(&client_id) {
+        client.send(protocol::ServerToClient::UpdateHealth { health: health.current, max_health: health.max });
+      }
+      if health.current == 0 {
+        respawn(server, player_id);
+      } else if health.current as f32 <= health.max as f32 * LOW_HEALTH {
+        server.hints.lock().unwrap().happened(client_id, hints::Event::LowHealth, time::precise_time_ns());
+      }
+    },
+    EntityId::Mob(mob_id) => {
+      if health.current == 0 {
+        debug!("Mob {} died", mob_id.to_u32());
+        let position = server.mobs.lock().unwrap().get(&mob_id).map(|mob| mob.position);
+        let table = server.components.lock().unwrap().loot.remove(&target);
+        init_mobs::remove_mob(server, mob_id);
+        if let (Some(position), Some(table)) = (position, table) {
+          let death =
+            loot::Death {
+              tool       : tool,
+              biome      : server.terrain_loader.terrain.climate.kind(position.x, position.z),
+              difficulty : server.difficulty,
+            };
+          let mut rng = server.rng(rng::subsystem::LOOT, &[mob_id.to_u32() as i64]);
+          let drop = server.loot.lock().unwrap().roll(&table, &death, &mut rng);
+          if let Some(stack) = drop {
+            items::drop_stack(server, position, stack);
+          }
+        }
+      }
+    },
+    EntityId::Item(_) | EntityId::Projectile(_) => {},
+  }
+}
+
+/// Bring a dead player back at the spawn point, unhurt.
+fn respawn(server: &server::T, player_id: entity::id::Player) {
+  let client_id = {
+    let mut players = server.players.lock().unwrap();
+    let player =
+      match players.get_mut(&player_id) {
+        None => return,
+        Some(player) => player,
+      };
+    let spawn = player::spawn_bounds();
+    let shift = (spawn.min + spawn.max.to_vec()) * 0.5 - player.position;
+    server.physics.lock().unwrap().move_body_freely(&player.body, shift);
+    player.position += shift;
+    player.speed = Vector3::new(0.0, 0.0, 0.0);
+    player.client_id
+  };
+  info!("Player {} died", player_id.to_u32());
+
+  let health = {
+    let mut components = server.components.lock().unwrap();
+    let health = components.health.get_mut(&EntityId::Player(player_id)).unwrap();
+    health.current = health.max;
+    *health
+  };
+  if let Some(client) = server.clients.lock().unwrap().get_mut(&client_id) {
+    client.send(protocol::ServerToClient::Message(protocol::MessageKind::Notice, String::from("You died.")));
+    client.send(protocol::ServerToClient::UpdateHealth { health: health.current, max_health: health.max });
+  }
+}
@@ -0,0 +1,78 @@
+//! Scripted hints: tips sent to a player the first time something happens to them,
+//! each after a short delay so they don't all arrive at once.
+
+use common::fnv_set;
+use common::protocol;
+
+/// Things that happen to players that might warrant a hint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Event {
+  /// The player joined the server.
+  Joined,
+  /// The sun set.
+  Nightfall,
+  /// The player's health got low.
+  LowHealth,
+}
+
+/// Which hints follow which events, and how long after, in ns.
+const SCRIPT: &'static [(Event, u64, &'static str)] = &[
+  (Event::Joined   , 5_000_000_000 , "Walk with WASD and look around with the mouse. Space jumps."),
+  (Event::Joined   , 20_000_000_000, "Left click to grow a tree, and right click to dig."),
+  (Event::Nightfall, 0             , "Night is falling. Mobs are harder to see in the dark."),
+  (Event::LowHealth, 0             , "You're badly hurt. Get somewhere safe and rest."),
+];
+
+#[allow(missing_docs)]
+pub struct T {
+  /// Hints are only sent when this is set.
+  pub enabled : bool,
+  /// Events that have already happened to each client.
+  seen        : fnv_set::T<(protocol::ClientId, Event)>,
+  /// Hints waiting to be sent, with when they're due.
+  pending     : Vec<(u64, protocol::ClientId, &'static str)>,
+}
+
+#[allow(missing_docs)]
+pub fn new(enabled: bool) -> T {
+  T {
+    enabled : enabled,
+    seen    : fnv_set::new(),
+    pending : Vec::new(),
+  }
+}
+
+impl T {
+  /// Record that `event` happened to a client at `now_ns`. The first time, its hints are queued up.
+  pub fn happened(&mut self, client: protocol::ClientId, event: Event, now_ns: u64) {
+    if !self.enabled || !self.seen.insert((client, event)) {
+      return
+    }
+    for &(e, delay_ns, text) in SCRIPT {
+      if e == event {
+        self.pending.push((now_ns + delay_ns, client, text));
+      }
+    }
+  }
+
+  /// Take the hints that are due to be sent by `now_ns`.
+  pub fn due(&mut self, now_ns: u64) -> Vec<(protocol::ClientId, &'static str)> {
+    let mut due = Vec::new();
+    let mut i = 0;
+    while i < self.pending.len() {
+      if self.pending[i].0 <= now_ns {
+        let (_, client, text) = self.pending.swap_remove(i);
+        due.push((client, text));
+      } else {
+        i += 1;
+      }
+    }
+    due
+  }
+
+  /// Drop everything kept for a client that's left.
+  pub fn forget(&mut self, client: protocol::ClientId) {
+    self.seen.retain(|&(c, _)| c != client);
+    self.pending.retain(|&(_, c, _)| c != client);
+  }
+}
@@ -0,0 +1,58 @@
+//! Which clients need to hear about things moving around. If it's turned on, clients are only sent
+//! mob movements their player might see: those inside a cone around the player's view (the one from
+//! `common::frustum`, which the client also culls with), or close enough to matter anyway.
+
+use cgmath::{Point3, MetricSpace};
+
+use common::fnv_map;
+use common::frustum;
+use common::protocol;
+
+use server;
+
+/// The widest window clients are assumed to have, as a width/height ratio.
+const MAX_ASPECT: f32 = 2.5;
+
+/// Cones start this far behind players, so they also cover the third-person camera.
+const CAMERA_MARGIN: f32 = 8.0;
+
+/// Anything this close to a player is always sent, since it may be about to come into view.
+const NEAR_RADIUS: f32 = 16.0;
+
+#[allow(missing_docs)]
+pub struct T {
+  /// Each client's player's position and view cone. Clients without a player aren't culled.
+  views : Option<fnv_map::T<protocol::ClientId, (Point3<f32>, frustum::Cone)>>,
+}
+
+/// What every client's player can see at the moment.
+pub fn new(server: &server::T) -> T {
+  if !server.cull_updates {
+    return T { views: None }
+  }
+  let mut views = fnv_map::new();
+  for player in server.players.lock().unwrap().values() {
+    let forward = player.forward();
+    let cone = frustum::cone(&(player.position - forward * CAMERA_MARGIN), &forward, frustum::FOV, MAX_ASPECT);
+    views.insert(player.client_id, (player.position, cone));
+  }
+  T {
+    views : Some(views),
+  }
+}
+
+impl T {
+  /// Whether a client should be told about something in a sphere.
+  pub fn sees(&self, client_id: protocol::ClientId, center: &Point3<f32>, radius: f32) -> bool {
+    let views =
+      match self.views {
+        None => return true,
+        Some(ref views) => views,
+      };
+    match views.get(&client_id) {
+      None => true,
+      Some(&(position, ref cone)) =>
+        position.distance(*center) <= NEAR_RADIUS + radius || cone.intersects_sphere(center, radius),
+    }
+  }
+}
@@ -0,0 +1,153 @@
+//! What a player is carrying: stacks of the materials they've dug out of the world or traded for,
+//! in a fixed number of slots, one of which is selected to place from.
+
+use std;
+
+use common::protocol;
+use common::voxel;
+
+/// The most of one material a slot holds.
+pub const MAX_STACK: u32 = 64;
+
+#[allow(missing_docs)]
+pub struct T {
+  slots    : Vec<Option<protocol::ItemStack>>,
+  selected : usize,
+}
+
+#[allow(missing_docs)]
+pub fn new() -> T {
+  T {
+    slots    : vec![None; protocol::INVENTORY_SLOTS],
+    selected : 0,
+  }
+}
+
+/// An inventory with what a player was carrying when it was saved. Slots that no longer exist are
+/// dropped, and so are empty stacks; stacks too big for a slot are cut down to fit.
+pub fn restore(slots: &[Option<protocol::ItemStack>], selected: usize) -> T {
+  let mut inventory = new();
+  for (slot, &saved) in inventory.slots.iter_mut().zip(slots) {
+    *slot =
+      match saved {
+        Some(stack) if stack.count > 0 =>
+          Some(protocol::ItemStack { count: std::cmp::min(stack.count, MAX_STACK), .. stack }),
+        _ => None,
+      };
+  }
+  inventory.select(selected);
+  inventory
+}
+
+impl T {
+  /// How much of a material is being carried.
+  pub fn count(&self, material: voxel::Material) -> u32 {
+    self.slots.iter()
+      .filter_map(|slot| *slot)
+      .filter(|stack| stack.material == material)
+      .map(|stack| stack.count)
+      .sum()
+  }
+
+  /// How much more of a material there's room for.
+  pub fn room_for(&self, material: voxel::Material) -> u32 {
+    self.slots.iter()
+      .map(|slot| {
+        match *slot {
+          None => MAX_STACK,
+          Some(stack) if stack.material == material => MAX_STACK - stack.count,
+          Some(_) => 0,
+        }
+      })
+      .sum()
+  }
+
+  /// Add as much of a material as there's room for, topping up the stacks of it there are before
+  /// starting new ones. Returns how much didn't fit.
+  pub fn add(&mut self, material: voxel::Material, count: u32) -> u32 {
+    let mut left = count;
+    for slot in self.slots.iter_mut() {
+      if let Some(ref mut stack) = *slot {
+        if stack.material == material {
+          let added = std::cmp::min(left, MAX_STACK - stack.count);
+          stack.count += added;
+          left -= added;
+        }
+      }
+    }
+    for slot in self.slots.iter_mut() {
+      if left == 0 {
+        break
+      }
+      if slot.is_none() {
+        let added = std::cmp::min(left, MAX_STACK);
+        *slot = Some(protocol::ItemStack { material: material, count: added });
+        left -= added;
+      }
+    }
+    left
+  }
+
+  /// Take `count` of a material, if there's that much. Nothing is taken if there isn't. The smallest
+  /// stacks are used up first.
+  pub fn take(&mut self, material: voxel::Material, count: u32) -> bool {
+    if self.count(material) < count {
+      return false
+    }
+    let mut left = count;
+    while left > 0 {
+      let smallest =
+        (0 .. self.slots.len())
+        .filter(|&i| self.slots[i].map_or(false, |stack| stack.material == material))
+        .min_by_key(|&i| self.slots[i].unwrap().count)
+        .unwrap();
+      let stack = self.slots[smallest].unwrap();
+      if stack.count <= left {
+        left -= stack.count;
+        self.slots[smallest] = None;
+      } else {
+        self.slots[smallest] = Some(protocol::ItemStack { material: material, count: stack.count - left });
+        left = 0;
+      }
+    }
+    true
+  }
+
+  /// Pick the slot to place from. Slots that don't exist are ignored.
+  pub fn select(&mut self, slot: usize) {
+    if slot < self.slots.len() {
+      self.selected = slot;
+    }
+  }
+
+  #[allow(missing_docs)]
+  pub fn slots(&self) -> &[Option<protocol::ItemStack>] {
+    &self.slots
+  }
+
+  /// The index of the selected slot.
+  pub fn selected_slot(&self) -> usize {
+    self.selected
+  }
+
+  /// The material in the selected slot, if it isn't empty.
+  pub fn selected(&self) -> Option<voxel::Material> {
+    self.slots[self.selected].map(|stack| stack.material)
+  }
+
+  /// Tell the player's client what they're carrying.
+  pub fn update_message(&self) -> protocol::ServerToClient {
+    protocol::ServerToClient::UpdateInventory(self.slots.clone())
+  }
+}
+
+#[test]
+fn restored_stacks_fit_their_slots() {
+  let stone = |count| Some(protocol::ItemStack { material: voxel::Material::Stone, count: count });
+  let mut inventory = restore(&[stone(u32::max_value()), stone(0), stone(3)], 0);
+  assert_eq!(inventory.slots[0], stone(MAX_STACK));
+  assert_eq!(inventory.slots[1], None);
+  assert_eq!(inventory.count(voxel::Material::Stone), MAX_STACK + 3);
+  assert_eq!(inventory.room_for(voxel::Material::Stone), MAX_STACK * (protocol::INVENTORY_SLOTS as u32 - 1) - 3);
+  assert_eq!(inventory.add(voxel::Material::Stone, 1), 0);
+}
@@ -0,0 +1,222 @@
+//! Items dropped in the world: dug voxels and what mobs leave behind pop out as small stacks that
+//! fall and bounce under `components::simulate`, merge with matching stacks nearby, and go into the
+//! inventory of the first player to walk over them. Ones nobody picks up disappear after a while.
+
+use cgmath::{Point3, Vector3, MetricSpace};
+use collision::Aabb3;
+use rand::Rng;
+use std;
+
+use common::fnv_map;
+use common::protocol;
+use common::rng;
+
+use components;
+use entity;
+use entity::EntityId;
+use inventory;
+use server;
+
+/// The width of an item's bounds.
+const SIZE: f32 = 0.25;
+
+/// The fraction of its speed an item keeps when it bounces.
+const BOUNCE: f32 = 0.4;
+
+/// How fast items pop up when they're dropped, in voxels per tick.
+const POP_SPEED: f32 = 0.15;
+
+/// The fastest items scatter sideways when they're dropped, in voxels per tick.
+const SCATTER_SPEED: f32 = 0.05;
+
+/// Stacks of the same material this close together merge.
+const MERGE_DISTANCE: f32 = 0.75;
+
+/// Players pick up items this close to them.
+const PICKUP_DISTANCE: f32 = 1.5;
+
+/// Merging and pickup only run every this many ticks.
+const UPDATE_INTERVAL: u64 = 5;
+
+/// How many ticks items last before they disappear: five minutes.
+const LIFETIME: u64 = 5 * 60 * server::UPDATES_PER_SECOND;
+
+#[allow(missing_docs)]
+pub struct T {
+  /// The tick each item disappears at.
+  expiry : fnv_map::T<entity::id::Item, u64>,
+}
+
+#[allow(missing_docs)]
+pub fn new() -> T {
+  T {
+    expiry : fnv_map::new(),
+  }
+}
+
+/// Drop a stack centered at `position`, popping it up and off to a random side.
+pub fn drop_stack(server: &server::T, position: Point3<f32>, stack: protocol::ItemStack) -> entity::id::Item {
+  let item_id = server.item_allocator.lock().unwrap().allocate();
+  let id = EntityId::Item(item_id);
+  let velocity = {
+    let mut rng = server.rng(rng::subsystem::ITEM_SCATTER, &[item_id.to_u32() as i64]);
+    Vector3::new(
+      rng.gen_range(-SCATTER_SPEED, SCATTER_SPEED),
+      POP_SPEED,
+      rng.gen_range(-SCATTER_SPEED, SCATTER_SPEED),
+    )
+  };
+  let half_size = Vector3::new(SIZE, SIZE, SIZE) * 0.5;
+  {
+    let mut components = server.components.lock().unwrap();
+    components.position.insert(id, position);
+    components.velocity.insert(id, velocity);
+    components.loose.insert(id, Aabb3::new(position - half_size, position + half_size));
+    components.bounce.insert(id, BOUNCE);
+    components.mesh.insert(id, components::Mesh);
+    components.item.insert(id, stack);
+  }
+  let tick = *server.tick.lock().unwrap();
+  server.items.lock().unwrap().expiry.insert(item_id, tick + LIFETIME);
+
+  broadcast(server, protocol::ServerToClient::UpdateItem(item_id, position, stack.material));
+  item_id
+}
+
+/// Take an item out of the world.
+fn remove(server: &server::T, item_id: entity::id::Item) {
+  server.components.lock().unwrap().remove(&EntityId::Item(item_id));
+  server.items.lock().unwrap().expiry.remove(&item_id);
+  broadcast(server, protocol::ServerToClient::RemoveItem(item_id));
+}
+
+fn broadcast(server: &server::T, message: protocol::ServerToClient) {
+  for (_, client) in server.clients.lock().unwrap().iter_mut() {
+    client.send(message.clone());
+  }
+}
+
+/// Where an item is and what it's a stack of, if it's still around.
+fn get(server: &server::T, item_id: entity::id::Item) -> Option<(Point3<f32>, protocol::ItemStack)> {
+  let components = server.components.lock().unwrap();
+  let id = EntityId::Item(item_id);
+  match (components.position.get(&id), components.item.get(&id)) {
+    (Some(&position), Some(&stack)) => Some((position, stack)),
+    _ => None,
+  }
+}
+
+fn set_count(server: &server::T, item_id: entity::id::Item, count: u32) {
+  if let Some(stack) = server.components.lock().unwrap().item.get_mut(&EntityId::Item(item_id)) {
+    stack.count = count;
+  }
+}
+
+/// Expire old items, merge nearby stacks, and let players pick items up. Call this every tick.
+pub fn update(server: &server::T, tick: u64) {
+  let expired: Vec<_> =
+    server.items.lock().unwrap().expiry.iter()
+    .filter(|&(_, &expiry)| expiry <= tick)
+    .map(|(&item_id, _)| item_id)
+    .collect();
+  for item_id in expired {
+    remove(server, item_id);
+  }
+
+  if tick % UPDATE_INTERVAL != 0 {
+    return
+  }
+  let mut item_ids: Vec<_> = server.items.lock().unwrap().expiry.keys().cloned().collect();
+  // Oldest first, so stacks merge into the ones that were there first.
+  item_ids.sort();
+  merge(server, &item_ids);
+  for item_id in item_ids {
+    pick_up(server, item_id);
+  }
+}
+
+/// Fold stacks into other stacks of the same material close by, as far as they'll fit.
+fn merge(server: &server::T, item_ids: &[entity::id::Item]) {
+  for (i, &into) in item_ids.iter().enumerate() {
+    for &from in &item_ids[i + 1 ..] {
+      let (into_position, into_stack) =
+        match get(server, into) {
+          None => break,
+          Some(item) => item,
+        };
+      let (from_position, from_stack) =
+        match get(server, from) {
+          None => continue,
+          Some(item) => item,
+        };
+      if from_stack.material != into_stack.material
+      || into_position.distance(from_position) > MERGE_DISTANCE {
+        continue
+      }
+      let moved = std::cmp::min(from_stack.count, inventory::MAX_STACK - into_stack.count);
+      if moved == 0 {
+        continue
+      }
+      set_count(server, into, into_stack.count + moved);
+      if moved == from_stack.count {
+        remove(server, from);
+      } else {
+        set_count(server, from, from_stack.count - moved);
+      }
+    }
+  }
+}
+
+/// Put an item in the inventory of a player close enough to it, as much of it as fits.
+fn pick_up(server: &server::T, item_id: entity::id::Item) {
+  let (position, stack) =
+    match get(server, item_id) {
+      None => return,
+      Some(item) => item,
+    };
+  let picked_up = {
+    let mut players = server.players.lock().unwrap();
+    players.values_mut()
+      .filter(|player| player.position.distance(position) <= PICKUP_DISTANCE)
+      .filter(|player| player.inventory.room_for(stack.material) > 0)
+      .next()
+      .map(|player| {
+        let left = player.inventory.add(stack.material, stack.count);
+        (player.client_id, player.inventory.update_message(), left)
+      })
+  };
+  let (client_id, inventory, left) =
+    match picked_up {
+      None => return,
+      Some(picked_up) => picked_up,
+    };
+  if let Some(client) = server.clients.lock().unwrap().get_mut(&client_id) {
+    client.send(inventory);
+  }
+  if left == 0 {
+    remove(server, item_id);
+  } else {
+    set_count(server, item_id, left);
+  }
+}
+
+/// Tell a client that just joined about the items lying around, since ones at rest aren't resent.
+pub fn send_all(server: &server::T, client_id: protocol::ClientId) {
+  let updates: Vec<_> = {
+    let components = server.components.lock().unwrap();
+    server.items.lock().unwrap().expiry.keys()
+      .filter_map(|&item_id| {
+        let id = EntityId::Item(item_id);
+        match (components.position.get(&id), components.item.get(&id)) {
+          (Some(&position), Some(stack)) => Some(protocol::ServerToClient::UpdateItem(item_id, position, stack.material)),
+          _ => None,
+        }
+      })
+      .collect()
+  };
+  if let Some(client) = server.clients.lock().unwrap().get_mut(&client_id) {
+    for update in updates {
+      client.send(update);
+    }
+  }
+}
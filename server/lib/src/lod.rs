@@ -125,6 +125,14 @@ impl Map {
     }
   }
 
+  /// Every position an owner has a handle on.
+  pub fn owned_by(&self, owner: OwnerId) -> Vec<voxel::bounds::T> {
+    self.loaded.iter()
+      .filter(|&(_, bls)| bls.owner_lods.iter().any(|&(o, _)| o == owner))
+      .map(|(&position, _)| position)
+      .collect()
+  }
+
   /// Release an owner's handle on `position`.
   /// Returns (owner's previous T, T change if the location's T changes).
   pub fn remove(
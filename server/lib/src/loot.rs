@@ -0,0 +1,385 @@
+//! Loot tables: what mobs leave behind when they die. Each kind of mob has a table of weighted
+//! entries, each of which can be limited to deaths by some tool, in some biome, or at some
+//! difficulty. A death picks one of the entries that fit it, by weight, with the seeded `LOOT`
+//! stream. Tables are data, read from a file at startup and by the console's `reload`, so drops can
+//! be tuned without touching code.
+//!
+//! TODO: Roll tables for chests too, once there are chests: a table per kind of chest, filled when
+//! the chest is generated, with the same conditions on where it is and the server's difficulty.
+
+use rand::Rng;
+use std;
+use std::io::Read;
+
+use common::protocol;
+use common::voxel;
+use terrain::biome::climate;
+
+use spawn;
+use spawner;
+use trade;
+
+/// Environment variable naming a file of loot tables. If it's unset, `default_entries` are used.
+const LOOT_VAR: &'static str = "PLAYFORM_LOOT";
+
+/// How generous the world is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Difficulty {
+  Easy,
+  Normal,
+  Hard,
+}
+
+/// The difficulty with a given name, like `"hard"`.
+pub fn difficulty(name: &str) -> Option<Difficulty> {
+  match name {
+    "easy" => Some(Difficulty::Easy),
+    "normal" => Some(Difficulty::Normal),
+    "hard" => Some(Difficulty::Hard),
+    _ => None,
+  }
+}
+
+/// What something was killed with.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Tool {
+  /// Nothing anyone wielded, like a fall.
+  Nothing,
+  /// A player's bare hands.
+  Hand,
+  /// A material a player was holding, or threw.
+  Material(voxel::Material),
+}
+
+/// How a mob died, to be matched against the conditions on loot entries.
+pub struct Death {
+  #[allow(missing_docs)]
+  pub tool       : Tool,
+  /// Where it died.
+  pub biome      : climate::Kind,
+  /// The server's difficulty.
+  pub difficulty : Difficulty,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Condition {
+  Tool(Tool),
+  Biome(climate::Kind),
+  Difficulty(Difficulty),
+}
+
+impl Condition {
+  /// Whether this is the same sort of condition as `other`, whatever it asks for.
+  fn same_sort(&self, other: &Condition) -> bool {
+    match (self, other) {
+      (&Condition::Tool(_), &Condition::Tool(_)) |
+      (&Condition::Biome(_), &Condition::Biome(_)) |
+      (&Condition::Difficulty(_), &Condition::Difficulty(_)) => true,
+      _ => false,
+    }
+  }
+
+  fn holds(&self, death: &Death) -> bool {
+    match *self {
+      Condition::Tool(tool) => tool == death.tool,
+      Condition::Biome(biome) => biome == death.biome,
+      Condition::Difficulty(difficulty) => difficulty == death.difficulty,
+    }
+  }
+}
+
+/// One possible drop from a table.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+  /// The kind of mob whose table this is in.
+  table      : String,
+  /// How often this is picked over the other entries that fit a death.
+  weight     : u32,
+  /// The material dropped, and the fewest and most of it, or None if nothing's dropped.
+  drop       : Option<(voxel::Material, u32, u32)>,
+  /// Conditions of the same sort are alternatives, e.g. either of two biomes; all sorts that appear
+  /// have to hold.
+  conditions : Vec<Condition>,
+}
+
+impl Entry {
+  fn fits(&self, death: &Death) -> bool {
+    self.conditions.iter().all(|condition| {
+      self.conditions.iter()
+        .filter(|other| other.same_sort(condition))
+        .any(|other| other.holds(death))
+    })
+  }
+}
+
+fn default_entries() -> Vec<Entry> {
+  let entry = |table: &str, material, count| {
+    Entry {
+      table      : String::from(table),
+      weight     : 1,
+      drop       : Some((material, count, count)),
+      conditions : Vec::new(),
+    }
+  };
+  vec!(
+    entry("hunter", voxel::Material::Coal, 1),
+    entry("skittish", voxel::Material::Leaves, 2),
+  )
+}
+
+fn condition(word: &str) -> Result<Condition, String> {
+  let parts: Vec<&str> = word.splitn(2, '=').collect();
+  if parts.len() != 2 {
+    return Err(format!("expected a condition like `tool=iron`, not {:?}", word))
+  }
+  let (key, value) = (parts[0], parts[1]);
+  match key {
+    "tool" => {
+      match value {
+        "none" => Ok(Condition::Tool(Tool::Nothing)),
+        "hand" => Ok(Condition::Tool(Tool::Hand)),
+        material => {
+          trade::material(material)
+          .map(|material| Condition::Tool(Tool::Material(material)))
+          .ok_or_else(|| format!("unknown tool {:?}", material))
+        },
+      }
+    },
+    "biome" => spawner::biome(value).map(Condition::Biome).ok_or_else(|| format!("unknown biome {:?}", value)),
+    "difficulty" =>
+      difficulty(value).map(Condition::Difficulty).ok_or_else(|| format!("unknown difficulty {:?}", value)),
+    _ => Err(format!("unknown condition {:?}", key)),
+  }
+}
+
+/// A count, like `2`, or a range of counts, like `1-3`.
+fn counts(word: &str) -> Result<(u32, u32), String> {
+  let parts: Vec<&str> = word.splitn(2, '-').collect();
+  let parse = |count: &str| count.parse::<u32>().map_err(|_| format!("invalid count {:?}", word));
+  let (min, max) =
+    if parts.len() == 1 {
+      let count = try!(parse(parts[0]));
+      (count, count)
+    } else {
+      (try!(parse(parts[0])), try!(parse(parts[1])))
+    };
+  if min == 0 || min > max {
+    return Err(format!("invalid count {:?}", word))
+  }
+  Ok((min, max))
+}
+
+/// Parse a loot file: one entry per line, as `hunter 3 1-2 coal tool=iron biome=desert` (the kind of
+/// mob, a weight, a count or range of counts, a material, and any conditions), or as `hunter 1
+/// nothing` for a chance of dropping nothing. Conditions are `tool=` a material, `hand` or `none`;
+/// `biome=` a biome; and `difficulty=` `easy`, `normal` or `hard`. Blank lines, and anything after a
+/// `#`, are ignored.
+fn parse(text: &str) -> Result<Vec<Entry>, String> {
+  let mut entries = Vec::new();
+  for (i, line) in text.lines().enumerate() {
+    let line = line.split('#').next().unwrap().trim();
+    if line.is_empty() {
+      continue
+    }
+    let words: Vec<&str> = line.split_whitespace().collect();
+    if words.len() < 3 {
+      return Err(format!("line {}: expected `kind weight count material condition...` or `kind weight nothing`", i + 1))
+    }
+    if spawn::kind(words[0]).is_none() {
+      return Err(format!("line {}: unknown kind of mob {:?}", i + 1, words[0]))
+    }
+    let weight = try!(words[1].parse::<u32>().map_err(|_| format!("line {}: invalid weight {:?}", i + 1, words[1])));
+    let (drop, rest) =
+      if words[2] == "nothing" {
+        (None, &words[3 ..])
+      } else {
+        if words.len() < 4 {
+          return Err(format!("line {}: expected a count and a material", i + 1))
+        }
+        let (min, max) = try!(counts(words[2]).map_err(|err| format!("line {}: {}", i + 1, err)));
+        let material = try!(trade::material(words[3]).ok_or_else(|| format!("line {}: unknown material {:?}", i + 1, words[3])));
+        (Some((material, min, max)), &words[4 ..])
+      };
+    let mut conditions = Vec::new();
+    for word in rest {
+      conditions.push(try!(condition(word).map_err(|err| format!("line {}: {}", i + 1, err))));
+    }
+    entries.push(Entry {
+      table      : String::from(words[0]),
+      weight     : weight,
+      drop       : drop,
+      conditions : conditions,
+    });
+  }
+  Ok(entries)
+}
+
+/// Read the loot tables from the file named by `LOOT_VAR`, or the defaults if there isn't one.
+pub fn read_entries() -> Result<Vec<Entry>, String> {
+  let path =
+    match std::env::var(LOOT_VAR) {
+      Err(_) => return Ok(default_entries()),
+      Ok(path) => path,
+    };
+  let mut text = String::new();
+  try!(
+    std::fs::File::open(&path).and_then(|mut file| file.read_to_string(&mut text))
+    .map_err(|err| format!("{}: error reading {:?}: {:?}", LOOT_VAR, path, err))
+  );
+  parse(&text).map_err(|err| format!("{}: {:?}, {}", LOOT_VAR, path, err))
+}
+
+fn entries() -> Vec<Entry> {
+  match read_entries() {
+    Ok(entries) => entries,
+    Err(err) => {
+      warn!("Ignoring {}", err);
+      default_entries()
+    },
+  }
+}
+
+#[allow(missing_docs)]
+pub struct T {
+  /// Every table's entries.
+  pub entries : Vec<Entry>,
+}
+
+#[allow(missing_docs)]
+pub fn new() -> T {
+  T {
+    entries : entries(),
+  }
+}
+
+impl T {
+  /// Roll a table for a death: what's dropped, if anything.
+  pub fn roll<R: Rng>(&self, table: &str, death: &Death, rng: &mut R) -> Option<protocol::ItemStack> {
+    let fitting: Vec<&Entry> =
+      self.entries.iter()
+      .filter(|entry| entry.table == table && entry.weight > 0 && entry.fits(death))
+      .collect();
+    let total: u32 = fitting.iter().map(|entry| entry.weight).sum();
+    if total == 0 {
+      return None
+    }
+    let mut pick = rng.gen_range(0, total);
+    for entry in fitting {
+      if pick < entry.weight {
+        return entry.drop.map(|(material, min, max)| {
+          // Widened so a `max` of `u32::MAX` doesn't overflow.
+          let count = rng.gen_range(min as u64, max as u64 + 1) as u32;
+          protocol::ItemStack { material: material, count: count }
+        })
+      }
+      pick -= entry.weight;
+    }
+    None
+  }
+}
+
+#[cfg(test)]
+fn death(tool: Tool, biome: climate::Kind) -> Death {
+  Death {
+    tool       : tool,
+    biome      : biome,
+    difficulty : Difficulty::Normal,
+  }
+}
+
+#[test]
+fn parse_reads_entries_and_rejects_bad_lines() {
+  let entries =
+    parse("
+      # Hunters drop iron for iron tools in the desert.
+      hunter 3 1-2 iron tool=iron biome=desert difficulty=hard
+      hunter 1 nothing   # and sometimes nothing
+      skittish 2 4 leaves
+    ").unwrap();
+  assert_eq!(entries.len(), 3);
+  assert_eq!(entries[0].table, "hunter");
+  assert_eq!(entries[0].weight, 3);
+  assert_eq!(entries[0].drop, Some((voxel::Material::Iron, 1, 2)));
+  assert_eq!(
+    entries[0].conditions,
+    vec!(
+      Condition::Tool(Tool::Material(voxel::Material::Iron)),
+      Condition::Biome(climate::Kind::Desert),
+      Condition::Difficulty(Difficulty::Hard),
+    ),
+  );
+  assert_eq!(entries[1].drop, None);
+  assert_eq!(entries[2].drop, Some((voxel::Material::Leaves, 4, 4)));
+
+  assert!(parse("dragon 1 1 coal").is_err());
+  assert!(parse("hunter x 1 coal").is_err());
+  assert!(parse("hunter 1 1 unobtainium").is_err());
+  assert!(parse("hunter 1 1").is_err());
+  assert!(parse("hunter 1 1 coal tool=spoon").is_err());
+  assert!(parse("hunter 1 1 coal colour=red").is_err());
+  assert!(parse("hunter 1 1 coal biome").is_err());
+}
+
+#[test]
+fn counts_are_positive_ranges() {
+  assert_eq!(counts("2"), Ok((2, 2)));
+  assert_eq!(counts("1-3"), Ok((1, 3)));
+  assert!(counts("0").is_err());
+  assert!(counts("3-1").is_err());
+  assert!(counts("1-").is_err());
+  assert!(counts("a").is_err());
+}
+
+#[test]
+fn conditions_of_a_sort_are_alternatives() {
+  let entries = parse("hunter 1 1 coal tool=iron tool=hand biome=desert").unwrap();
+  let entry = &entries[0];
+  assert!(entry.fits(&death(Tool::Hand, climate::Kind::Desert)));
+  assert!(entry.fits(&death(Tool::Material(voxel::Material::Iron), climate::Kind::Desert)));
+  assert!(!entry.fits(&death(Tool::Nothing, climate::Kind::Desert)));
+  assert!(!entry.fits(&death(Tool::Hand, climate::Kind::Forest)));
+
+  let unconditional = &parse("hunter 1 1 coal").unwrap()[0];
+  assert!(unconditional.fits(&death(Tool::Nothing, climate::Kind::Tundra)));
+}
+
+#[test]
+fn rolls_follow_the_weights_of_fitting_entries() {
+  use common::rng;
+
+  let loot =
+    T {
+      entries :
+        parse("
+          hunter 3 1 coal
+          hunter 1 1 iron
+          hunter 100 1 crystal biome=tundra
+          skittish 1 nothing
+        ").unwrap(),
+    };
+  let mut rng = rng::stream(0, "test/loot", &[]);
+  let in_desert = death(Tool::Hand, climate::Kind::Desert);
+
+  let mut coal = 0;
+  for _ in 0 .. 4000 {
+    match loot.roll("hunter", &in_desert, &mut rng) {
+      Some(protocol::ItemStack { material: voxel::Material::Coal, count: 1 }) => coal += 1,
+      Some(protocol::ItemStack { material: voxel::Material::Iron, count: 1 }) => {},
+      other => panic!("unexpected drop {:?}", other),
+    }
+  }
+  assert!(coal > 2800 && coal < 3200, "{} of 4000 rolls were coal", coal);
+
+  assert_eq!(loot.roll("skittish", &in_desert, &mut rng), None);
+  assert_eq!(loot.roll("boss", &in_desert, &mut rng), None);
+}
+
+#[test]
+fn rolls_handle_the_largest_counts() {
+  use common::rng;
+
+  let loot = T { entries: parse(&format!("hunter 1 {}-{} coal", std::u32::MAX - 1, std::u32::MAX)).unwrap() };
+  let mut rng = rng::stream(0, "test/loot", &[]);
+  let count = loot.roll("hunter", &death(Tool::Hand, climate::Kind::Desert), &mut rng).unwrap().count;
+  assert!(count >= std::u32::MAX - 1);
+}
@@ -1,20 +1,43 @@
 use cgmath::{Point3, Vector3};
 
+use common::protocol;
 use common::surroundings_loader;
 
+use ai;
 use entity;
 use lod;
+use physics;
 use server;
 
+/// The tallest ledge mobs climb on their own.
+pub const STEP_HEIGHT: f32 = 1.0;
+
+/// How far around them mobs keep terrain loaded, in voxels.
+pub const LOAD_DISTANCE: u32 = 8;
+
+/// How much health mobs start with.
+pub const MAX_HEALTH: u32 = 10;
+
 pub type Behavior = fn(&server::T, &mut Mob);
 
 pub struct Mob {
   pub position            : Point3<f32>,
   pub speed               : Vector3<f32>,
+  /// Rotation about the vertical axis, in radians.
+  pub yaw                 : f32,
   pub behavior            : Behavior,
+  /// State for behaviors built on `ai`.
+  pub ai                  : ai::T,
+  /// What this mob will trade players. Only traders offer anything.
+  pub trades              : Vec<protocol::TradeOffer>,
+  /// What players know this mob as. Only traders have names.
+  pub name                : Option<String>,
 
   pub entity_id           : entity::id::Mob,
-  pub physics_id          : entity::id::Misc,
+  pub body                : physics::Body,
   pub owner_id            : lod::OwnerId,
   pub surroundings_loader : surroundings_loader::T,
+  /// Whether the mob is standing still with no terrain loaded around it, since no player is near.
+  /// Mobs start out dormant.
+  pub dormant             : bool,
 }
@@ -9,6 +9,7 @@ extern crate collision;
 extern crate common;
 #[macro_use]
 extern crate log;
+extern crate memmap;
 extern crate nanomsg;
 extern crate num;
 extern crate rand;
@@ -18,20 +19,53 @@ extern crate thread_scoped;
 extern crate time;
 extern crate voxel_data;
 
+mod accounts;
+pub mod admin;
+mod ai;
+mod block_updates;
+mod boss;
+mod circuits;
 mod client_recv_thread;
+mod commands;
+mod components;
+mod disconnect;
 mod entity;
+mod entity_regions;
+mod event_log;
+mod falling_block;
+mod fluid;
+mod health;
+mod gaia_queue;
+mod hints;
 mod in_progress_terrain;
 mod init_mobs;
+mod interest;
+mod inventory;
+mod items;
 mod lod;
+mod loot;
 mod mob;
+mod names;
 mod octree;
+mod orientations;
 mod physics;
+mod pistons;
+mod pregenerate;
+mod projectiles;
 mod player;
+mod reload;
 mod run;
 pub mod server;
+mod shapes;
+mod spawn;
+mod spawner;
 mod sun;
 mod terrain_loader;
+mod terrain_push;
+mod trade;
 pub mod update_gaia;
 mod update_world;
+mod view_distance;
+mod world_save;
 
 pub use run::run;
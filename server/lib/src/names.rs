@@ -0,0 +1,105 @@
+//! Made-up names for worlds, traders and animals. Each kind of name has a small grammar: a name is a
+//! few syllables, each an onset, a vowel and a coda picked from that kind's lists, sometimes with an
+//! ending. Names are drawn from seeded streams, so the same world seed names everything the same way.
+
+use rand::Rng;
+
+use common::rng;
+
+/// What's being named.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+  World,
+  Person,
+  Animal,
+}
+
+impl Kind {
+  /// The kind named by a word, like `"npc"`.
+  pub fn parse(word: &str) -> Option<Kind> {
+    match word {
+      "world" => Some(Kind::World),
+      "person" | "npc" | "trader" => Some(Kind::Person),
+      "animal" | "pet" => Some(Kind::Animal),
+      _ => None,
+    }
+  }
+}
+
+struct Grammar {
+  onsets    : &'static [&'static str],
+  vowels    : &'static [&'static str],
+  codas     : &'static [&'static str],
+  /// The fewest and most syllables in a name.
+  syllables : (u32, u32),
+  /// Whole endings, one of which is sometimes put on the end.
+  endings   : &'static [&'static str],
+}
+
+static WORLD: Grammar = Grammar {
+  onsets    : &["", "b", "br", "d", "dr", "f", "g", "gr", "k", "l", "m", "n", "r", "s", "st", "th", "v", "w"],
+  vowels    : &["a", "e", "i", "o", "u", "ae", "ea", "ou"],
+  codas     : &["", "", "", "l", "n", "r", "s", "th", "nd", "rn"],
+  syllables : (2, 3),
+  endings   : &["heim", "mark", "dor", "fell", "moor", "wyn"],
+};
+
+static PERSON: Grammar = Grammar {
+  onsets    : &["", "b", "d", "f", "g", "h", "j", "k", "l", "m", "n", "p", "r", "s", "t", "v", "z"],
+  vowels    : &["a", "e", "i", "o", "u", "ai", "ei"],
+  codas     : &["", "", "", "k", "l", "m", "n", "r", "s"],
+  syllables : (2, 3),
+  endings   : &["a", "o", "in", "wen"],
+};
+
+static ANIMAL: Grammar = Grammar {
+  onsets    : &["b", "ch", "f", "l", "m", "n", "p", "r", "s", "t", "w"],
+  vowels    : &["a", "e", "i", "o", "u", "oo", "ee"],
+  codas     : &["", "", "", "b", "ff", "m", "p", "x"],
+  syllables : (1, 2),
+  endings   : &["y", "ie", "kins", "le"],
+};
+
+fn grammar(kind: Kind) -> &'static Grammar {
+  match kind {
+    Kind::World => &WORLD,
+    Kind::Person => &PERSON,
+    Kind::Animal => &ANIMAL,
+  }
+}
+
+/// A new name of some kind.
+pub fn generate<R: Rng>(rng: &mut R, kind: Kind) -> String {
+  let grammar = grammar(kind);
+  let (min, max) = grammar.syllables;
+  let syllables = rng.gen_range(min, max + 1);
+  let mut name = String::new();
+  for _ in 0 .. syllables {
+    name.push_str(rng.choose(grammar.onsets).unwrap());
+    name.push_str(rng.choose(grammar.vowels).unwrap());
+    name.push_str(rng.choose(grammar.codas).unwrap());
+  }
+  if rng.gen_weighted_bool(3) {
+    name.push_str(rng.choose(grammar.endings).unwrap());
+  }
+  capitalize(&name)
+}
+
+/// The name of the world generated from `seed`.
+pub fn world(seed: u64) -> String {
+  generate(&mut rng::stream(seed, rng::subsystem::WORLD_NAME, &[]), Kind::World)
+}
+
+/// The name of the trader at a hut, which is the same each time the world is loaded.
+pub fn trader(seed: u64, hut: (i32, i32, i32)) -> String {
+  let key = [hut.0 as i64, hut.1 as i64, hut.2 as i64];
+  generate(&mut rng::stream(seed, rng::subsystem::TRADER_NAME, &key), Kind::Person)
+}
+
+fn capitalize(name: &str) -> String {
+  let mut chars = name.chars();
+  match chars.next() {
+    None => String::new(),
+    Some(first) => first.to_uppercase().chain(chars).collect(),
+  }
+}
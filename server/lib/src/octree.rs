@@ -1,11 +1,13 @@
-use cgmath::{Point3};
-use collision::{Aabb3};
+use cgmath::{Point3, Vector3};
+use collision::{Aabb3, Ray3};
 use std::fmt::Debug;
 use std::ptr;
 
+use common::ray;
+
 pub const MIN_CELL_WIDTH: f32 = 0.1;
 
-fn aabb_overlap(aabb1: &Aabb3<f32>, aabb2: &Aabb3<f32>) -> bool {
+pub fn aabb_overlap(aabb1: &Aabb3<f32>, aabb2: &Aabb3<f32>) -> bool {
   true
   && aabb1.min.x < aabb2.max.x
   && aabb1.min.y < aabb2.max.y
@@ -15,7 +17,7 @@ fn aabb_overlap(aabb1: &Aabb3<f32>, aabb2: &Aabb3<f32>) -> bool {
   && aabb2.min.z < aabb1.max.z
 }
 
-fn contains(aabb1: &Aabb3<f32>, aabb2: &Aabb3<f32>) -> bool {
+pub fn contains(aabb1: &Aabb3<f32>, aabb2: &Aabb3<f32>) -> bool {
   true
   && aabb1.min.x <= aabb2.min.x
   && aabb1.min.y <= aabb2.min.y
@@ -254,6 +256,64 @@ impl<V: Debug + Copy + Eq + PartialOrd> Octree<V> {
     }
   }
 
+  /// Every object overlapping `bounds`, besides `self_v`. Objects that span several cells are found
+  /// once per cell, clipped to it.
+  pub fn intersect_all(&self, bounds: &Aabb3<f32>, self_v: Option<V>) -> Vec<(Aabb3<f32>, V)> {
+    let mut found = Vec::new();
+    self.collect_intersections(bounds, self_v, &mut found);
+    found
+  }
+
+  fn collect_intersections(&self, bounds: &Aabb3<f32>, self_v: Option<V>, found: &mut Vec<(Aabb3<f32>, V)>) {
+    match self.contents {
+      OctreeContents::Leaf(ref vs) => {
+        found.extend(
+          vs.iter()
+          .filter(|&&(ref bs, ref v)| Some(*v) != self_v && aabb_overlap(bounds, bs))
+          .cloned()
+        );
+      },
+      OctreeContents::Branch(ref b) => {
+        let mid = middle(&self.bounds, self.dimension);
+        let (low_bounds, high_bounds) = split(mid, self.dimension, bounds);
+        if let Some(bs) = low_bounds {
+          b.low_tree.collect_intersections(&bs, self_v, found);
+        }
+        if let Some(bs) = high_bounds {
+          b.high_tree.collect_intersections(&bs, self_v, found);
+        }
+      },
+    }
+  }
+
+  /// The first object hit by a ray within `max_distance`, along with how far along the ray it was hit
+  /// and the normal of the face that was hit. See `common::ray::hit`.
+  /// Objects for which `skip` is true are passed through.
+  pub fn cast_ray(&self, ray: &Ray3<f32>, max_distance: f32, skip: &Fn(V) -> bool) -> Option<(f32, Vector3<f32>, V)> {
+    let inside =
+      contains(&self.bounds, &Aabb3::new(ray.origin, ray.origin));
+    if !inside && ray::hit(ray, &self.bounds).map_or(true, |(distance, _)| distance > max_distance) {
+      return None
+    }
+    match self.contents {
+      OctreeContents::Leaf(ref vs) => {
+        vs.iter()
+          .filter(|&&(_, v)| !skip(v))
+          .filter_map(|&(ref bounds, v)| ray::hit(ray, bounds).map(|(distance, normal)| (distance, normal, v)))
+          .filter(|&(distance, _, _)| distance <= max_distance)
+          .min_by(|&(d1, _, _), &(d2, _, _)| d1.partial_cmp(&d2).unwrap())
+      },
+      OctreeContents::Branch(ref b) => {
+        let low = b.low_tree.cast_ray(ray, max_distance, skip);
+        let high = b.high_tree.cast_ray(ray, max_distance, skip);
+        match (low, high) {
+          (None, hit) | (hit, None) => hit,
+          (Some(low), Some(high)) => if low.0 <= high.0 { Some(low) } else { Some(high) },
+        }
+      },
+    }
+  }
+
   // like insert, but before recursing downward, we recurse up the parents
   // until the bounds provided are inside the tree.
   fn insert_from(&mut self, bounds: &Aabb3<f32>, v: V) {
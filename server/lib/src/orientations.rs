@@ -0,0 +1,140 @@
+//! Which way oriented voxels face. The voxels themselves only hold a material and a surface, so
+//! each oriented voxel's orientation is kept here, under its bounds, and saved with the world:
+//! placed shapes (see `shapes`) keep their model and orientation, so they can be rotated and
+//! re-brushed later, and machines like pistons keep the direction they act in.
+
+use bincode;
+use cgmath::Vector3;
+use std;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use common::fnv_map;
+use common::shape;
+use common::voxel;
+
+#[derive(Debug, Clone, PartialEq)]
+/// A shape placed into the terrain.
+pub struct Placed {
+  /// The name of its model.
+  pub shape       : String,
+  #[allow(missing_docs)]
+  pub orientation : shape::Orientation,
+  #[allow(missing_docs)]
+  pub material    : voxel::Material,
+}
+
+/// How orientations are saved: placed shapes, then machine facings.
+type Saved = (
+  Vec<(voxel::bounds::T, String, shape::Orientation, voxel::Material)>,
+  Vec<(voxel::bounds::T, u8)>,
+);
+
+#[allow(missing_docs)]
+pub struct T {
+  path        : PathBuf,
+  /// Placed shapes, under the voxel at the low corner of their grid cell.
+  pub shapes  : fnv_map::T<voxel::bounds::T, Placed>,
+  /// The direction each machine faces, as an index into `block_updates::neighbors`.
+  pub facings : fnv_map::T<voxel::bounds::T, usize>,
+}
+
+/// Keep orientations in the file at `path`, starting with whatever was saved there.
+pub fn open(path: &Path) -> T {
+  let mut orientations =
+    T {
+      path    : path.to_owned(),
+      shapes  : fnv_map::new(),
+      facings : fnv_map::new(),
+    };
+  let mut bytes = Vec::new();
+  match std::fs::File::open(path) {
+    Err(_) => return orientations,
+    Ok(mut file) => {
+      if let Err(err) = file.read_to_end(&mut bytes) {
+        warn!("Error reading {:?}: {:?}", path, err);
+        return orientations
+      }
+    },
+  }
+  match bincode::deserialize::<Saved>(&bytes) {
+    Ok((shapes, facings)) => {
+      for (bounds, name, orientation, material) in shapes {
+        orientations.shapes.insert(bounds, Placed {
+          shape       : name,
+          orientation : orientation,
+          material    : material,
+        });
+      }
+      for (bounds, facing) in facings {
+        orientations.facings.insert(bounds, facing as usize);
+      }
+    },
+    Err(err) => warn!("Ignoring corrupt orientations {:?}: {:?}", path, err),
+  }
+  orientations
+}
+
+impl T {
+  /// Save every orientation.
+  pub fn save(&self) {
+    let saved: Saved = (
+      self.shapes.iter()
+        .map(|(bounds, placed)| (*bounds, placed.shape.clone(), placed.orientation, placed.material))
+        .collect(),
+      self.facings.iter().map(|(bounds, &facing)| (*bounds, facing as u8)).collect(),
+    );
+    let r =
+      bincode::serialize(&saved, bincode::Infinite)
+      .map_err(|err| format!("{:?}", err))
+      .and_then(|bytes| {
+        std::fs::File::create(&self.path)
+        .and_then(|mut file| file.write_all(&bytes))
+        .map_err(|err| format!("{:?}", err))
+      });
+    if let Err(err) = r {
+      warn!("Error saving {:?}: {}", self.path, err);
+    }
+  }
+}
+
+/// The direction, as an index into `block_updates::neighbors`, closest to `direction`.
+pub fn facing(direction: &Vector3<f32>) -> usize {
+  let direction = [direction.x, direction.y, direction.z];
+  let mut axis = 0;
+  for d in 1 .. 3 {
+    if direction[d].abs() > direction[axis].abs() {
+      axis = d;
+    }
+  }
+  if direction[axis] > 0.0 { 2 * axis + 1 } else { 2 * axis }
+}
+
+#[test]
+fn facings_point_along_the_strongest_axis() {
+  assert_eq!(facing(&Vector3::new(-1.0, 0.2, 0.3)), 0);
+  assert_eq!(facing(&Vector3::new(0.1, 0.9, -0.3)), 3);
+  assert_eq!(facing(&Vector3::new(0.1, 0.2, -0.5)), 4);
+}
+
+#[test]
+fn orientations_survive_a_save() {
+  let path = std::env::temp_dir().join(format!("playform-orientations-test-{}", std::process::id()));
+  let cell = voxel::bounds::new(4, -8, 12, 0);
+  let placed =
+    Placed {
+      shape       : String::from("stairs"),
+      orientation : shape::Orientation { facing: shape::Facing::East, axis: shape::Axis::Y },
+      material    : voxel::Material::Marble,
+    };
+  {
+    let mut orientations = open(&path);
+    orientations.shapes.insert(cell, placed.clone());
+    orientations.facings.insert(voxel::bounds::new(1, 2, 3, 0), 5);
+    orientations.save();
+  }
+  let orientations = open(&path);
+  std::fs::remove_file(&path).unwrap();
+  assert_eq!(orientations.shapes.get(&cell), Some(&placed));
+  assert_eq!(orientations.facings.get(&voxel::bounds::new(1, 2, 3, 0)), Some(&5));
+}
@@ -1,11 +1,32 @@
-use cgmath::{Vector3};
-use collision::{Aabb3};
+use cgmath::{Point3, Vector3, InnerSpace};
+use collision::{Aabb3, Ray3};
 
 use common::fnv_map;
 
 use entity;
+use octree;
 use octree::Octree;
 
+/// Loose boxes move in steps no longer than this fraction of their own size along each axis, so
+/// they can't pass through anything thin, however fast they're going.
+const MAX_STEP_FRACTION: f32 = 0.5;
+
+/// How many times to halve the distance to whatever stops a loose box, to bring it up against it.
+const CONTACT_BISECTIONS: u32 = 4;
+
+/// Bodies stop this far short of whatever they run into, so rounding can't leave them inside it.
+const SKIN: f32 = 0.001;
+
+/// Boxes that overlap a body by no more than this are only touching it, like the floor under a body
+/// walking across it, or a wall beside one walking along it, and don't get in its way.
+const TOUCH_EPSILON: f32 = 0.0005;
+
+/// The most ledges a body climbs in one move along an axis, e.g. running up stairs.
+const MAX_CLIMBS: u32 = 4;
+
+/// Bodies pushed out of the way of placed terrain end up this far clear of it.
+const PUSH_MARGIN: f32 = 0.001;
+
 pub struct T {
   pub terrain_octree : Octree<entity::id::Terrain>,
   terrain_bounds : fnv_map::T<entity::id::Terrain, Aabb3<f32>>,
@@ -13,11 +34,33 @@ pub struct T {
   misc_bounds    : fnv_map::T<entity::id::Misc, Aabb3<f32>>,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Collision {
   Misc(entity::id::Misc),
   Terrain(entity::id::Terrain),
 }
 
+/// Something that moves through the world, colliding with terrain and with other bodies.
+/// Players and mobs both move this way.
+#[derive(Debug, Clone, Copy)]
+pub struct Body {
+  pub id          : entity::id::Misc,
+  /// The tallest ledge this body climbs onto, rather than being stopped by.
+  pub step_height : f32,
+}
+
+/// What happened when a body moved.
+pub struct Moved {
+  /// The body's bounds afterwards.
+  pub bounds     : Aabb3<f32>,
+  /// How far it actually moved, including any climbing.
+  pub shift      : Vector3<f32>,
+  /// Everything it ran into, in order.
+  pub collisions : Vec<Collision>,
+  /// Whether it was stopped short of where it was asked to go.
+  pub blocked    : bool,
+}
+
 impl T {
   pub fn new(world_bounds: Aabb3<f32>) -> T {
     T {
@@ -39,19 +82,19 @@ impl T {
   }
 
   pub fn remove_terrain(&mut self, id: entity::id::Terrain) {
-    match self.terrain_bounds.get(&id) {
+    match self.terrain_bounds.remove(&id) {
       None => {},
       Some(bounds) => {
-        self.terrain_octree.remove(bounds, id);
+        self.terrain_octree.remove(&bounds, id);
       },
     }
   }
 
   pub fn remove_misc(&mut self, id: entity::id::Misc) {
-    match self.misc_bounds.get(&id) {
+    match self.misc_bounds.remove(&id) {
       None => {},
       Some(bounds) => {
-        self.misc_octree.remove(bounds, id);
+        self.misc_octree.remove(&bounds, id);
       },
     }
   }
@@ -60,29 +103,515 @@ impl T {
     self.misc_bounds.get(&id)
   }
 
-  pub fn translate_misc(&mut self, id: entity::id::Misc, amount: Vector3<f32>) -> Option<(Aabb3<f32>, Collision)> {
+  /// Change a body's bounds in place, e.g. to make it shorter, if there's room for the new bounds.
+  /// Returns whether it was changed.
+  pub fn reshape_misc(&mut self, id: entity::id::Misc, new_bounds: &Aabb3<f32>) -> bool {
+    if self.terrain_octree.intersect(new_bounds, None).is_some()
+    || self.misc_octree.intersect(new_bounds, Some(id)).is_some() {
+      return false
+    }
     let bounds = self.misc_bounds.get_mut(&id).unwrap();
-    let new_bounds =
+    self.misc_octree.reinsert(id, bounds, new_bounds);
+    *bounds = *new_bounds;
+    true
+  }
+
+  /// Whether any terrain is inside `bounds`.
+  pub fn terrain_blocks(&self, bounds: &Aabb3<f32>) -> bool {
+    self.terrain_octree.intersect(bounds, None).is_some()
+  }
+
+  /// Whether there's terrain within `depth` below the bottom of `bounds`.
+  pub fn supported(&self, bounds: &Aabb3<f32>, depth: f32) -> bool {
+    let below =
       Aabb3::new(
-        bounds.min + amount,
-        bounds.max + amount,
+        Point3::new(bounds.min.x, bounds.min.y - depth, bounds.min.z),
+        Point3::new(bounds.max.x, bounds.min.y, bounds.max.z),
       );
-    match self.terrain_octree.intersect(&new_bounds, None) {
-      Some((bounds, terrain_id)) => {
-        Some((bounds, Collision::Terrain(terrain_id)))
-      },
-      None => {
-        match self.misc_octree.intersect(&new_bounds, Some(id)) {
-          Some((bounds, misc_id)) => {
-            Some ((bounds, Collision::Misc(misc_id)))
-          },
-          None => {
-            self.misc_octree.reinsert(id, bounds, &new_bounds);
-            *bounds = new_bounds;
-            None
-          },
+    self.terrain_octree.intersect(&below, None).is_some()
+  }
+
+  /// The first terrain or other body hit by a ray within `max_distance`, with the point where it
+  /// was hit and the normal of the face hit. Anything the ray starts inside of is ignored, so rays
+  /// can be cast out from inside a body.
+  pub fn raycast(
+    &self,
+    origin       : &Point3<f32>,
+    direction    : &Vector3<f32>,
+    max_distance : f32,
+  ) -> Option<(Collision, Point3<f32>, Vector3<f32>)> {
+    if direction.magnitude2() == 0.0 {
+      return None
+    }
+    let ray = Ray3::new(*origin, direction.normalize());
+    let starts_inside = |bounds: Option<&Aabb3<f32>>| {
+      bounds.map_or(false, |bounds| octree::contains(bounds, &Aabb3::new(*origin, *origin)))
+    };
+
+    let terrain =
+      self.terrain_octree.cast_ray(&ray, max_distance, &|id| starts_inside(self.terrain_bounds.get(&id)))
+      .map(|(distance, normal, id)| (distance, normal, Collision::Terrain(id)));
+    let misc =
+      self.misc_octree.cast_ray(&ray, max_distance, &|id| starts_inside(self.misc_bounds.get(&id)))
+      .map(|(distance, normal, id)| (distance, normal, Collision::Misc(id)));
+    let hit =
+      match (terrain, misc) {
+        (None, hit) | (hit, None) => hit,
+        (Some(terrain), Some(misc)) => if terrain.0 <= misc.0 { Some(terrain) } else { Some(misc) },
+      };
+    hit.map(|(distance, normal, collision)| (collision, ray.origin + ray.direction * distance, normal))
+  }
+
+  /// Move a body, climbing onto anything in the way that's no taller than its `step_height`, and
+  /// stopping just short of the first thing it can't climb. Each axis is swept separately, so bodies
+  /// slide along whatever they hit; they rise before moving sideways, so jumps clear ledges, and fall
+  /// after, so they can walk off them.
+  pub fn move_body(&mut self, body: &Body, requested_shift: Vector3<f32>) -> Moved {
+    let init_bounds = *self.get_bounds(body.id).unwrap();
+
+    let order = if requested_shift.y > 0.0 { [1, 0, 2] } else { [0, 2, 1] };
+    let mut collisions = Vec::new();
+    let mut blocked = false;
+    for &axis in &order {
+      if requested_shift[axis] != 0.0 && self.move_along(body, axis, requested_shift[axis], &mut collisions) {
+        blocked = true;
+      }
+    }
+
+    let bounds = *self.get_bounds(body.id).unwrap();
+    Moved {
+      bounds     : bounds,
+      shift      : bounds.min - init_bounds.min,
+      collisions : collisions,
+      blocked    : blocked,
+    }
+  }
+
+  /// Move a box that isn't a body, like a dropped item, stopping short of any terrain in the way.
+  /// Bodies don't block it, and it doesn't block them. Returns where it ended up, and whether it
+  /// was stopped.
+  pub fn move_loose(&self, bounds: &Aabb3<f32>, requested_shift: Vector3<f32>) -> (Aabb3<f32>, bool) {
+    let translated = |bounds: &Aabb3<f32>, shift: Vector3<f32>| Aabb3::new(bounds.min + shift, bounds.max + shift);
+    let size = bounds.max - bounds.min;
+    let steps_along = |shift: f32, size: f32| {
+      (shift.abs() / (size.max(octree::MIN_CELL_WIDTH) * MAX_STEP_FRACTION)).ceil() as u32
+    };
+    let steps =
+      steps_along(requested_shift.x, size.x)
+      .max(steps_along(requested_shift.y, size.y))
+      .max(steps_along(requested_shift.z, size.z))
+      .max(1);
+    let step = requested_shift / steps as f32;
+
+    let mut bounds = *bounds;
+    for _ in 0 .. steps {
+      if !self.terrain_blocks(&translated(&bounds, step)) {
+        bounds = translated(&bounds, step);
+        continue
+      }
+      // Close the gap to whatever's in the way.
+      let mut gap = step;
+      for _ in 0 .. CONTACT_BISECTIONS {
+        gap = gap * 0.5;
+        if !self.terrain_blocks(&translated(&bounds, gap)) {
+          bounds = translated(&bounds, gap);
         }
-      },
+      }
+      return (bounds, true)
+    }
+    (bounds, false)
+  }
+
+  /// Move a body straight through anything in the way, e.g. for a player flying through walls.
+  pub fn move_body_freely(&mut self, body: &Body, shift: Vector3<f32>) -> Aabb3<f32> {
+    self.shift_misc(body.id, shift)
+  }
+
+  fn shift_misc(&mut self, id: entity::id::Misc, shift: Vector3<f32>) -> Aabb3<f32> {
+    let bounds = self.misc_bounds.get_mut(&id).unwrap();
+    let new_bounds = Aabb3::new(bounds.min + shift, bounds.max + shift);
+    self.misc_octree.reinsert(id, bounds, &new_bounds);
+    *bounds = new_bounds;
+    new_bounds
+  }
+
+  /// Make room for new terrain filling `bounds`, by pushing any bodies in the way out of it. Each
+  /// one is pushed along whichever axis moves it least without pushing it into terrain or another
+  /// body. If any of them can't be pushed out, nothing is moved and this returns None; otherwise it
+  /// returns the bodies that were moved, and how far.
+  pub fn make_room(&mut self, bounds: &Aabb3<f32>) -> Option<Vec<(entity::id::Misc, Vector3<f32>)>> {
+    let in_the_way: Vec<(entity::id::Misc, Aabb3<f32>)> =
+      self.misc_bounds.iter()
+      .filter(|&(_, body_bounds)| octree::aabb_overlap(body_bounds, bounds))
+      .map(|(&id, &body_bounds)| (id, body_bounds))
+      .collect();
+
+    let mut pushed = Vec::new();
+    for (id, body_bounds) in in_the_way {
+      let mut pushes = vec!(
+        Vector3::new(bounds.max.x - body_bounds.min.x + PUSH_MARGIN, 0.0, 0.0),
+        Vector3::new(bounds.min.x - body_bounds.max.x - PUSH_MARGIN, 0.0, 0.0),
+        Vector3::new(0.0, bounds.max.y - body_bounds.min.y + PUSH_MARGIN, 0.0),
+        Vector3::new(0.0, bounds.min.y - body_bounds.max.y - PUSH_MARGIN, 0.0),
+        Vector3::new(0.0, 0.0, bounds.max.z - body_bounds.min.z + PUSH_MARGIN),
+        Vector3::new(0.0, 0.0, bounds.min.z - body_bounds.max.z - PUSH_MARGIN),
+      );
+      pushes.sort_by(|v1, v2| v1.magnitude2().partial_cmp(&v2.magnitude2()).unwrap());
+      let push =
+        pushes.into_iter().find(|push| {
+          let new_bounds = Aabb3::new(body_bounds.min + *push, body_bounds.max + *push);
+          self.terrain_octree.intersect(&new_bounds, None).is_none()
+          && self.misc_octree.intersect(&new_bounds, Some(id)).is_none()
+        });
+      match push {
+        None => {
+          // Put back the bodies that were already pushed.
+          for &(id, push) in &pushed {
+            self.shift_misc(id, -push);
+          }
+          return None
+        },
+        Some(push) => {
+          self.shift_misc(id, push);
+          pushed.push((id, push));
+        },
+      }
+    }
+    Some(pushed)
+  }
+
+  /// Move a body `distance` along one axis, recording what it runs into. Sideways moves climb
+  /// terrain up to the body's `step_height`. Returns whether the body was stopped short.
+  fn move_along(&mut self, body: &Body, axis: usize, distance: f32, collisions: &mut Vec<Collision>) -> bool {
+    let mut left = distance;
+    for _ in 0 .. MAX_CLIMBS + 1 {
+      let bounds = *self.get_bounds(body.id).unwrap();
+      let (moved, blockers) = self.sweep(body.id, &bounds, axis, left);
+      self.shift_misc(body.id, along(axis, moved));
+      left -= moved;
+      if blockers.is_empty() {
+        return false
+      }
+      collisions.extend(blockers.iter().map(|&(_, collision)| collision));
+
+      let climbable =
+        axis != 1
+        && blockers.iter().all(|&(_, collision)| match collision { Collision::Terrain(_) => true, Collision::Misc(_) => false });
+      if !climbable {
+        return true
+      }
+      let bounds = *self.get_bounds(body.id).unwrap();
+      let ledge = blockers.iter().map(|&(blocker, _)| blocker.max.y - bounds.min.y).fold(0.0, f32::max);
+      if ledge > body.step_height {
+        return true
+      }
+      let climb = ledge + SKIN;
+      let (rise, _) = self.sweep(body.id, &bounds, 1, climb);
+      if rise < climb {
+        // No headroom to get up there.
+        return true
+      }
+      self.shift_misc(body.id, along(1, climb));
+    }
+    true
+  }
+
+  /// How far `bounds` can move along an axis, up to `distance`, and what stops it there, if anything.
+  /// Boxes that only touch it, or that it's already inside, don't get in its way; it stops `SKIN`
+  /// short of the rest.
+  fn sweep(
+    &self,
+    id       : entity::id::Misc,
+    bounds   : &Aabb3<f32>,
+    axis     : usize,
+    distance : f32,
+  ) -> (f32, Vec<(Aabb3<f32>, Collision)>) {
+    let mut swept = *bounds;
+    if distance > 0.0 {
+      swept.max[axis] += distance;
+    } else {
+      swept.min[axis] += distance;
+    }
+
+    let mut candidates: Vec<(Aabb3<f32>, Collision)> = Vec::new();
+    for (_, terrain_id) in self.terrain_octree.intersect_all(&swept, None) {
+      let collision = Collision::Terrain(terrain_id);
+      if !candidates.iter().any(|&(_, c)| c == collision) {
+        candidates.push((self.terrain_bounds[&terrain_id], collision));
+      }
+    }
+    for (_, misc_id) in self.misc_octree.intersect_all(&swept, Some(id)) {
+      let collision = Collision::Misc(misc_id);
+      if !candidates.iter().any(|&(_, c)| c == collision) {
+        candidates.push((self.misc_bounds[&misc_id], collision));
+      }
+    }
+
+    let ahead: Vec<(f32, Aabb3<f32>, Collision)> =
+      candidates.into_iter()
+      .filter(|&(other, _)| {
+        (0 .. 3).filter(|&a| a != axis).all(|a| {
+          other.min[a] < bounds.max[a] - TOUCH_EPSILON && bounds.min[a] + TOUCH_EPSILON < other.max[a]
+        })
+      })
+      .filter_map(|(other, collision)| {
+        let gap =
+          if distance > 0.0 {
+            other.min[axis] - bounds.max[axis]
+          } else {
+            bounds.min[axis] - other.max[axis]
+          };
+        if gap < -TOUCH_EPSILON {
+          None
+        } else {
+          Some(((gap - SKIN).max(0.0), other, collision))
+        }
+      })
+      .filter(|&(reach, _, _)| reach < distance.abs())
+      .collect();
+
+    let nearest = ahead.iter().map(|&(reach, _, _)| reach).fold(distance.abs(), f32::min);
+    let blockers =
+      ahead.into_iter()
+      .filter(|&(reach, _, _)| reach <= nearest + TOUCH_EPSILON)
+      .map(|(_, other, collision)| (other, collision))
+      .collect();
+    (nearest * distance.signum(), blockers)
+  }
+}
+
+/// A shift of `distance` along one axis.
+fn along(axis: usize, distance: f32) -> Vector3<f32> {
+  let mut shift = Vector3::new(0.0, 0.0, 0.0);
+  shift[axis] = distance;
+  shift
+}
+
+#[cfg(test)]
+fn block(x: f32, y: f32, z: f32) -> Aabb3<f32> {
+  Aabb3::new(Point3::new(x, y, z), Point3::new(x + 1.0, y + 1.0, z + 1.0))
+}
+
+#[test]
+fn raycast_hits_nearest_face() {
+  let mut physics = T::new(Aabb3::new(Point3::new(-64.0, -64.0, -64.0), Point3::new(64.0, 64.0, 64.0)));
+  let near: entity::id::Terrain = Default::default();
+  let far = near + 1;
+  physics.insert_terrain(near, &block(4.0, 0.0, 0.0));
+  physics.insert_terrain(far, &block(8.0, 0.0, 0.0));
+
+  let origin = Point3::new(0.0, 0.5, 0.5);
+  let (collision, point, normal) = physics.raycast(&origin, &Vector3::new(1.0, 0.0, 0.0), 16.0).unwrap();
+  assert_eq!(collision, Collision::Terrain(near));
+  assert!((point - Point3::new(4.0, 0.5, 0.5)).magnitude() < 0.001);
+  assert_eq!(normal, Vector3::new(-1.0, 0.0, 0.0));
+
+  // Looking down onto the top of the far block.
+  let (collision, point, normal) = physics.raycast(&Point3::new(8.5, 10.0, 0.5), &Vector3::new(0.0, -1.0, 0.0), 16.0).unwrap();
+  assert_eq!(collision, Collision::Terrain(far));
+  assert!((point - Point3::new(8.5, 1.0, 0.5)).magnitude() < 0.001);
+  assert_eq!(normal, Vector3::new(0.0, 1.0, 0.0));
+
+  assert!(physics.raycast(&origin, &Vector3::new(1.0, 0.0, 0.0), 3.5).is_none());
+  assert!(physics.raycast(&origin, &Vector3::new(-1.0, 0.0, 0.0), 16.0).is_none());
+}
+
+#[test]
+fn raycast_ignores_what_it_starts_inside() {
+  let mut physics = T::new(Aabb3::new(Point3::new(-64.0, -64.0, -64.0), Point3::new(64.0, 64.0, 64.0)));
+  let caster: entity::id::Misc = Default::default();
+  let other = caster + 1;
+  physics.insert_misc(caster, &Aabb3::new(Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 2.0, 1.0)));
+  physics.insert_misc(other, &Aabb3::new(Point3::new(0.0, 0.0, 5.0), Point3::new(1.0, 2.0, 6.0)));
+
+  let (collision, point, normal) =
+    physics.raycast(&Point3::new(0.5, 1.5, 0.5), &Vector3::new(0.0, 0.0, 2.0), 16.0).unwrap();
+  assert_eq!(collision, Collision::Misc(other));
+  assert!((point - Point3::new(0.5, 1.5, 5.0)).magnitude() < 0.001);
+  assert_eq!(normal, Vector3::new(0.0, 0.0, -1.0));
+}
+
+#[test]
+fn fast_bodies_stop_at_thin_terrain() {
+  let mut physics = T::new(Aabb3::new(Point3::new(-64.0, -64.0, -64.0), Point3::new(64.0, 64.0, 64.0)));
+  let floor: entity::id::Terrain = Default::default();
+  physics.insert_terrain(floor, &Aabb3::new(Point3::new(-4.0, 0.0, -4.0), Point3::new(4.0, 0.1, 4.0)));
+  let body = Body { id: Default::default(), step_height: 1.0 };
+  physics.insert_misc(body.id, &Aabb3::new(Point3::new(0.0, 10.0, 0.0), Point3::new(1.0, 12.0, 1.0)));
+
+  // In one go, this would land entirely below the floor without ever touching it.
+  let moved = physics.move_body(&body, Vector3::new(0.0, -20.0, 0.0));
+  assert_eq!(moved.collisions.first(), Some(&Collision::Terrain(floor)));
+  assert!(moved.bounds.min.y >= 0.1 && moved.bounds.min.y < 0.2, "landed at {:?}", moved.bounds);
+}
+
+#[test]
+fn bodies_walk_across_seams_without_snagging() {
+  let mut physics = T::new(Aabb3::new(Point3::new(-64.0, -64.0, -64.0), Point3::new(64.0, 64.0, 64.0)));
+  let mut floor: entity::id::Terrain = Default::default();
+  for x in -2 .. 8 {
+    physics.insert_terrain(floor, &block(x as f32, 0.0, 0.0));
+    floor = floor + 1;
+  }
+  let body = Body { id: Default::default(), step_height: 1.0 };
+  // Sunk into the floor a little, as rounding can leave bodies.
+  physics.insert_misc(body.id, &Aabb3::new(Point3::new(0.0, 0.9999, 0.0), Point3::new(1.0, 2.9999, 1.0)));
+
+  let moved = physics.move_body(&body, Vector3::new(4.0, 0.0, 0.0));
+  assert!(!moved.blocked);
+  assert!(moved.collisions.is_empty(), "snagged on {:?}", moved.collisions);
+  assert_eq!(moved.shift, Vector3::new(4.0, 0.0, 0.0));
+}
+
+#[test]
+fn bodies_slide_along_walls_they_touch() {
+  let mut physics = T::new(Aabb3::new(Point3::new(-64.0, -64.0, -64.0), Point3::new(64.0, 64.0, 64.0)));
+  let mut wall: entity::id::Terrain = Default::default();
+  for x in -2 .. 8 {
+    for y in 0 .. 2 {
+      physics.insert_terrain(wall, &block(x as f32, y as f32, -1.0));
+      wall = wall + 1;
+    }
+  }
+  let body = Body { id: Default::default(), step_height: 1.0 };
+  physics.insert_misc(body.id, &Aabb3::new(Point3::new(0.0, 0.0, -0.0002), Point3::new(1.0, 2.0, 0.9998)));
+
+  let moved = physics.move_body(&body, Vector3::new(4.0, 0.0, 0.0));
+  assert!(!moved.blocked);
+  assert_eq!(moved.shift, Vector3::new(4.0, 0.0, 0.0));
+  let moved = physics.move_body(&body, Vector3::new(-6.0, 0.0, 0.0));
+  assert!(!moved.blocked);
+  assert_eq!(moved.shift, Vector3::new(-6.0, 0.0, 0.0));
+}
+
+#[test]
+fn bodies_stop_flush_against_walls() {
+  let mut physics = T::new(Aabb3::new(Point3::new(-64.0, -64.0, -64.0), Point3::new(64.0, 64.0, 64.0)));
+  let wall: entity::id::Terrain = Default::default();
+  physics.insert_terrain(wall, &block(3.0, 0.0, 0.0));
+  physics.insert_terrain(wall + 1, &block(3.0, 1.0, 0.0));
+  let body = Body { id: Default::default(), step_height: 1.0 };
+  physics.insert_misc(body.id, &Aabb3::new(Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 2.0, 1.0)));
+
+  let moved = physics.move_body(&body, Vector3::new(5.0, 0.0, 0.0));
+  assert!(moved.blocked);
+  assert!(moved.collisions.contains(&Collision::Terrain(wall)));
+  assert!(moved.bounds.max.x < 3.0 && moved.bounds.max.x > 3.0 - 2.0 * SKIN, "stopped at {:?}", moved.bounds);
+
+  // Pressing on into the wall doesn't move it, or let it through.
+  let moved = physics.move_body(&body, Vector3::new(0.5, 0.0, 0.0));
+  assert!(moved.blocked);
+  assert!(moved.shift.magnitude() < 0.0001, "moved {:?}", moved.shift);
+}
+
+#[test]
+fn bodies_climb_steps_unless_there_is_no_headroom() {
+  let world = Aabb3::new(Point3::new(-64.0, -64.0, -64.0), Point3::new(64.0, 64.0, 64.0));
+  let step: entity::id::Terrain = Default::default();
+  let body = Body { id: Default::default(), step_height: 1.0 };
+  let start = Aabb3::new(Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 2.0, 1.0));
+
+  let mut physics = T::new(world);
+  physics.insert_terrain(step, &block(2.0, 0.0, 0.0));
+  physics.insert_misc(body.id, &start);
+  let moved = physics.move_body(&body, Vector3::new(3.0, 0.0, 0.0));
+  assert!(!moved.blocked);
+  assert_eq!(moved.collisions, vec!(Collision::Terrain(step)));
+  assert!((moved.bounds.min.x - 3.0).abs() < 0.0001, "ended up at {:?}", moved.bounds);
+  assert!(moved.bounds.min.y > 1.0 && moved.bounds.min.y < 1.0 + 2.0 * SKIN, "ended up at {:?}", moved.bounds);
+
+  // A ceiling just above where the body would climb to.
+  let mut physics = T::new(world);
+  physics.insert_terrain(step, &block(2.0, 0.0, 0.0));
+  physics.insert_terrain(step + 1, &block(1.0, 2.5, 0.0));
+  physics.insert_misc(body.id, &start);
+  let moved = physics.move_body(&body, Vector3::new(3.0, 0.0, 0.0));
+  assert!(moved.blocked);
+  assert!(moved.bounds.max.x < 2.0 && moved.bounds.min.y == 0.0, "ended up at {:?}", moved.bounds);
+}
+
+#[test]
+fn loose_boxes_stop_at_terrain_but_not_bodies() {
+  let mut physics = T::new(Aabb3::new(Point3::new(-64.0, -64.0, -64.0), Point3::new(64.0, 64.0, 64.0)));
+  let floor: entity::id::Terrain = Default::default();
+  physics.insert_terrain(floor, &Aabb3::new(Point3::new(-4.0, 0.0, -4.0), Point3::new(4.0, 0.1, 4.0)));
+  physics.insert_misc(Default::default(), &Aabb3::new(Point3::new(-1.0, 2.0, -1.0), Point3::new(1.0, 4.0, 1.0)));
+
+  let item = Aabb3::new(Point3::new(0.0, 10.0, 0.0), Point3::new(0.25, 10.25, 0.25));
+  let (bounds, blocked) = physics.move_loose(&item, Vector3::new(0.0, -20.0, 0.0));
+  assert!(blocked);
+  assert!(bounds.min.y >= 0.1 && bounds.min.y < 0.15, "landed at {:?}", bounds);
+
+  let (bounds, blocked) = physics.move_loose(&item, Vector3::new(0.0, -1.0, 0.0));
+  assert!(!blocked);
+  assert_eq!(bounds.min.y, 9.0);
+}
+
+#[test]
+fn placed_blocks_push_bodies_out_the_short_way() {
+  let mut physics = T::new(Aabb3::new(Point3::new(-64.0, -64.0, -64.0), Point3::new(64.0, 64.0, 64.0)));
+  let body: entity::id::Misc = Default::default();
+  physics.insert_misc(body, &Aabb3::new(Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 2.0, 1.0)));
+
+  // The block only clips the body's side, so it's pushed sideways rather than up.
+  let pushed = physics.make_room(&block(0.75, 0.0, 0.0)).unwrap();
+  assert_eq!(pushed.len(), 1);
+  let bounds = *physics.get_bounds(body).unwrap();
+  assert!(bounds.max.x <= 0.75 && bounds.max.x > 0.74, "pushed to {:?}", bounds);
+  assert_eq!(bounds.min.y, 0.0);
+
+  assert_eq!(physics.make_room(&block(8.0, 0.0, 0.0)).map(|pushed| pushed.len()), Some(0));
+}
+
+#[test]
+fn placed_blocks_are_refused_when_bodies_cant_move() {
+  let mut physics = T::new(Aabb3::new(Point3::new(-64.0, -64.0, -64.0), Point3::new(64.0, 64.0, 64.0)));
+  // A body in a shaft with no room to move sideways, or down, and a ceiling just overhead.
+  let mut wall: entity::id::Terrain = Default::default();
+  for &(x, y, z) in &[(-1.0, 0.0, 0.0), (1.0, 0.0, 0.0), (0.0, 0.0, -1.0), (0.0, 0.0, 1.0), (0.0, -1.0, 0.0), (0.0, 2.5, 0.0)] {
+    physics.insert_terrain(wall, &block(x, y, z));
+    wall = wall + 1;
+    // The walls are as tall as the body.
+    if y == 0.0 {
+      physics.insert_terrain(wall, &block(x, y + 1.0, z));
+      wall = wall + 1;
     }
   }
+  let body: entity::id::Misc = Default::default();
+  let bounds = Aabb3::new(Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 2.0, 1.0));
+  physics.insert_misc(body, &bounds);
+
+  assert!(physics.make_room(&block(0.0, 0.0, 0.0)).is_none());
+  assert_eq!(*physics.get_bounds(body).unwrap(), bounds);
+}
+
+#[test]
+fn removed_bodies_are_not_pushed() {
+  let mut physics = T::new(Aabb3::new(Point3::new(-64.0, -64.0, -64.0), Point3::new(64.0, 64.0, 64.0)));
+  let gone: entity::id::Misc = Default::default();
+  let body = gone + 1;
+  physics.insert_misc(gone, &Aabb3::new(Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 2.0, 1.0)));
+  physics.insert_misc(body, &Aabb3::new(Point3::new(0.0, 0.0, 4.0), Point3::new(1.0, 2.0, 5.0)));
+  physics.remove_misc(gone);
+  assert!(physics.get_bounds(gone).is_none());
+
+  // Only the body that's still there is in the way.
+  let pushed = physics.make_room(&block(0.5, 0.0, 0.0)).unwrap();
+  assert!(pushed.is_empty());
+  let pushed = physics.make_room(&block(0.5, 0.0, 4.0)).unwrap();
+  assert_eq!(pushed.iter().map(|&(id, _)| id).collect::<Vec<_>>(), vec!(body));
+}
+
+#[test]
+fn unloaded_terrain_is_forgotten() {
+  let mut physics = T::new(Aabb3::new(Point3::new(-64.0, -64.0, -64.0), Point3::new(64.0, 64.0, 64.0)));
+  let mut id: entity::id::Terrain = Default::default();
+  // Load and unload the same block over and over, as players wander back and forth.
+  for _ in 0 .. 100 {
+    physics.insert_terrain(id, &block(8.0, 0.0, 0.0));
+    physics.remove_terrain(id);
+    id = id + 1;
+  }
+  assert!(!physics.terrain_blocks(&block(8.0, 0.0, 0.0)));
+  assert!(physics.terrain_bounds.is_empty());
 }
@@ -0,0 +1,120 @@
+//! Pistons: when powered, a piston pushes the line of voxels in front of it one voxel forward,
+//! and pulls its head back in when the power goes away.
+//! Pistons push the way they were placed facing (see `orientations`); older pistons, placed before
+//! they kept a facing, push away from whatever is powering them.
+
+use common::fnv_map;
+use common::voxel;
+use common::voxel::Material;
+
+use block_updates;
+use circuits;
+
+/// The longest line of voxels a piston can push.
+pub const MAX_PUSH: usize = 12;
+
+fn offset(bounds: &voxel::bounds::T, direction: usize) -> voxel::bounds::T {
+  block_updates::neighbors(bounds)[direction]
+}
+
+/// Whether pistons can move a voxel of this material.
+fn is_movable(material: Material) -> bool {
+  match material {
+    Material::ExtendedPiston | Material::PistonHead => false,
+    _ => true,
+  }
+}
+
+/// Re-evaluate the piston at `bounds`. Returns the voxels that need to change.
+pub fn update(
+  voxels  : &voxel::tree::T,
+  facings : &fnv_map::T<voxel::bounds::T, usize>,
+  bounds  : &voxel::bounds::T,
+  piston  : voxel::T,
+) -> Vec<(voxel::bounds::T, voxel::T)> {
+  let powered_from = circuits::powered_from(voxels, bounds);
+  match (circuits::material_of(&piston), powered_from) {
+    (Material::Piston, Some(from)) => {
+      let direction =
+        match facings.get(bounds) {
+          Some(&facing) => facing,
+          // `neighbors` lists opposite directions next to each other.
+          None => from ^ 1,
+        };
+      push(voxels, facings, bounds, piston, direction)
+    },
+    (Material::ExtendedPiston, None) => retract(voxels, bounds, piston, facings.get(bounds).cloned()),
+    _ => Vec::new(),
+  }
+}
+
+fn push(
+  voxels    : &voxel::tree::T,
+  facings   : &fnv_map::T<voxel::bounds::T, usize>,
+  bounds    : &voxel::bounds::T,
+  piston    : voxel::T,
+  direction : usize,
+) -> Vec<(voxel::bounds::T, voxel::T)> {
+  let front = offset(bounds, direction);
+
+  // Find the line of voxels to push, and make sure there's room at the end of it.
+  let mut line = Vec::new();
+  let mut p = front;
+  loop {
+    let voxel =
+      match voxels.get(&p) {
+        // Don't push into unloaded terrain.
+        None => return Vec::new(),
+        Some(voxel) => *voxel,
+      };
+    let material = circuits::material_of(&voxel);
+    // Water is pushed aside, same as open space.
+    if !material.is_solid() {
+      break
+    }
+    // Pistons with a facing would leave it behind.
+    if !is_movable(material) || facings.contains_key(&p) || line.len() >= MAX_PUSH {
+      return Vec::new()
+    }
+    line.push((p, voxel));
+    p = offset(&p, direction);
+  }
+
+  let mut changes = Vec::with_capacity(line.len() + 2);
+  for &(p, voxel) in line.iter().rev() {
+    // Pressure plates are released when they're moved out from under whatever was on them.
+    let voxel =
+      if circuits::material_of(&voxel) == Material::PoweredPressurePlate {
+        circuits::with_material(voxel, Material::PressurePlate)
+      } else {
+        voxel
+      };
+    changes.push((offset(&p, direction), voxel));
+  }
+  changes.push((front, voxel::Volume(Material::PistonHead)));
+  changes.push((*bounds, circuits::with_material(piston, Material::ExtendedPiston)));
+  changes
+}
+
+fn retract(
+  voxels : &voxel::tree::T,
+  bounds : &voxel::bounds::T,
+  piston : voxel::T,
+  facing : Option<usize>,
+) -> Vec<(voxel::bounds::T, voxel::T)> {
+  let mut changes = vec!((*bounds, circuits::with_material(piston, Material::Piston)));
+  let neighbors = block_updates::neighbors(bounds);
+  // A piston with a facing only ever pushed its head out that way.
+  let fronts =
+    match facing {
+      Some(facing) => &neighbors[facing .. facing + 1],
+      None => &neighbors[..],
+    };
+  for neighbor in fronts.iter() {
+    if voxels.get(neighbor).map(circuits::material_of) == Some(Material::PistonHead) {
+      changes.push((*neighbor, voxel::Volume(Material::Empty)));
+      break
+    }
+  }
+  changes
+}
@@ -2,15 +2,16 @@ use cgmath;
 use cgmath::{Point3, Matrix3, Vector3, ElementWise};
 use collision::{Aabb3, Ray3};
 use std::f32::consts::PI;
-use std::ops::DerefMut;
 use std::sync::Mutex;
 use stopwatch;
 
 use common::id_allocator;
+use common::protocol;
 use common::surroundings_loader;
 use common::voxel;
 
 use entity;
+use inventory;
 use lod;
 use physics;
 use server;
@@ -18,8 +19,29 @@ use update_gaia;
 use update_world::load_placeholders;
 
 const MAX_JUMP_FUEL: u32 = 4;
+
+/// How much health players start with.
+pub const MAX_HEALTH: u32 = 20;
 const MAX_STEP_HEIGHT: f32 = 1.0;
 
+/// Crouching players climb less, and won't walk off a drop any deeper than this.
+const CROUCH_STEP_HEIGHT: f32 = 0.5;
+
+const STANDING_HEIGHT: f32 = 2.0;
+/// Crouching lowers the player, and the camera with them.
+const CROUCHING_HEIGHT: f32 = 1.5;
+
+/// How fast players walk in each movement mode, relative to walking.
+const SPRINT_SPEED: f32 = 1.6;
+const CROUCH_SPEED: f32 = 0.4;
+
+/// Where players join, and come back when they die.
+pub fn spawn_bounds() -> Aabb3<f32> {
+  // TODO: shift upward until outside terrain
+  let min = Point3::new(0.0, 64.0, 4.0);
+  Aabb3::new(min, min + Vector3::new(1.0, 2.0, 1.0))
+}
+
 #[derive(Debug, Clone)]
 pub enum Collision {
   Terrain(entity::id::Terrain),
@@ -38,8 +60,27 @@ pub struct T {
   pub jump_fuel: u32,
   // are we currently trying to jump? (e.g. holding the key).
   pub is_jumping: bool,
+  // did we land on something the last time we moved down?
+  on_ground: bool,
+  /// How the player is moving.
+  pub movement_mode: protocol::MovementMode,
+  /// Flying players ignore gravity and pass through terrain.
+  pub flying: bool,
+  /// How the player has asked to move. This differs from `movement_mode` while there's no room to
+  /// stand up from a crouch.
+  requested_mode: protocol::MovementMode,
   pub entity_id: entity::id::Player,
-  pub physics_id: entity::id::Misc,
+  pub body: physics::Body,
+  /// The materials the player is carrying.
+  pub inventory: inventory::T,
+  // the client controlling this player
+  pub client_id: protocol::ClientId,
+  /// The name the player is playing under. See `accounts`.
+  pub name: String,
+  /// How fast the player was falling when they last landed, in voxels per tick, until it's taken.
+  pub landing_speed: Option<f32>,
+  /// The first tick the player can attack on.
+  pub next_attack_tick: u64,
 
   // rotation around the y-axis, in radians
   pub lateral_rotation: f32,
@@ -54,6 +95,8 @@ pub struct T {
 }
 
 pub fn new(
+  client_id: protocol::ClientId,
+  name: String,
   entity_id: entity::id::Player,
   physics_id: entity::id::Misc,
   owner_allocator: &Mutex<id_allocator::T<lod::OwnerId>>,
@@ -67,8 +110,17 @@ pub fn new(
     walk_accel          : Vector3::new(0.0, 0.0, 0.0),
     jump_fuel           : 0,
     is_jumping          : false,
+    on_ground           : false,
+    movement_mode       : protocol::MovementMode::Walk,
+    flying              : false,
+    requested_mode      : protocol::MovementMode::Walk,
     entity_id           : entity_id,
-    physics_id          : physics_id,
+    body                : physics::Body { id: physics_id, step_height: MAX_STEP_HEIGHT },
+    inventory           : inventory::new(),
+    client_id           : client_id,
+    name                : name,
+    landing_speed       : None,
+    next_attack_tick    : 0,
     lateral_rotation    : 0.0,
     vertical_rotation   : 0.0,
 
@@ -80,6 +132,11 @@ pub fn new(
 }
 
 impl T {
+  /// The owners of the terrain loaded around the player.
+  pub fn owners(&self) -> [lod::OwnerId; 2] {
+    [self.surroundings_owner, self.solid_owner]
+  }
+
   /// Translates the player by a vector.
   /// If the player collides with something with a small height jump, the player will shift upward.
   /// Returns the actual amount moved by.
@@ -89,52 +146,17 @@ impl T {
     requested_shift: Vector3<f32>,
   ) -> (Aabb3<f32>, Vec<Collision>)
   {
-    let mut physics = physics.lock().unwrap();
-    let physics = physics.deref_mut();
-    let init_bounds = *physics.get_bounds(self.physics_id).unwrap();
-    let requested_bounds =
-      Aabb3::new(
-        init_bounds.min + requested_shift,
-        init_bounds.max + requested_shift,
-      );
+    let moved = physics.lock().unwrap().move_body(&self.body, requested_shift);
+    self.position += moved.shift;
 
-    let mut shift = requested_shift;
-    let mut collisions = Vec::new();
-    let mut collided = false;
-    loop {
-      match physics.translate_misc(self.physics_id, shift) {
-        None => {
-          break
-        },
-        Some((_, physics::Collision::Misc(id))) => {
-          collided = true;
-          collisions.push(Collision::Misc(id));
-          break
-        },
-        Some((collision_bounds, physics::Collision::Terrain(id))) => {
-          collisions.push(Collision::Terrain(id));
-          collided = true;
-
-          // Step to the top of whatever we hit.
-          let step_height = collision_bounds.max.y - requested_bounds.min.y;
-          assert!(step_height > 0.0);
-
-          if step_height > MAX_STEP_HEIGHT {
-            // Step is too big; we just ran into something.
-            break
-          }
-
-          shift += Vector3::new(0.0, step_height, 0.0);
-        },
-      }
+    if requested_shift.y < 0.0 {
+      self.on_ground = !moved.collisions.is_empty();
     }
 
-    let shifted = *physics.get_bounds(self.physics_id).unwrap();
-    self.position += shifted.min - init_bounds.min;
-
-    if collided {
+    if !moved.collisions.is_empty() {
       if requested_shift.y < 0.0 {
         self.jump_fuel = MAX_JUMP_FUEL;
+        self.landing_speed = Some(-requested_shift.y);
       }
 
       self.speed.y -= requested_shift.y;
@@ -144,7 +166,16 @@ impl T {
       }
     }
 
-    (shifted, collisions)
+    let collisions =
+      moved.collisions.into_iter()
+      .map(|collision| {
+        match collision {
+          physics::Collision::Terrain(id) => Collision::Terrain(id),
+          physics::Collision::Misc(id) => Collision::Misc(id),
+        }
+      })
+      .collect();
+    (moved.bounds, collisions)
   }
 
 
@@ -210,37 +241,117 @@ impl T {
       }
     }
 
+    if self.movement_mode != self.requested_mode {
+      self.change_movement_mode(&server.physics);
+    }
+
     let delta_p = self.speed;
-    let mut new_bounds = *server.physics.lock().unwrap().get_bounds(self.physics_id).unwrap();
+    let mut new_bounds = *server.physics.lock().unwrap().get_bounds(self.body.id).unwrap();
     let mut collisions = Vec::new();
-    if delta_p.x != 0.0 {
-      let (b, c) = self.translate(&server.physics, Vector3::new(delta_p.x, 0.0, 0.0));
-      new_bounds = b;
-      collisions.extend_from_slice(c.as_slice());
-    }
-    if delta_p.y != 0.0 {
-      let (b, c) = self.translate(&server.physics, Vector3::new(0.0, delta_p.y, 0.0));
-      new_bounds = b;
-      collisions.extend_from_slice(c.as_slice());
-    }
-    if delta_p.z != 0.0 {
-      let (b, c) = self.translate(&server.physics, Vector3::new(0.0, 0.0, delta_p.z));
-      new_bounds = b;
-      collisions.extend_from_slice(c.as_slice());
+    if self.flying {
+      new_bounds = server.physics.lock().unwrap().move_body_freely(&self.body, delta_p);
+      self.position += delta_p;
+    } else {
+      if delta_p.x != 0.0 && !self.stops_at_edge(&server.physics, Vector3::new(delta_p.x, 0.0, 0.0)) {
+        let (b, c) = self.translate(&server.physics, Vector3::new(delta_p.x, 0.0, 0.0));
+        new_bounds = b;
+        collisions.extend_from_slice(c.as_slice());
+      }
+      if delta_p.y != 0.0 {
+        let (b, c) = self.translate(&server.physics, Vector3::new(0.0, delta_p.y, 0.0));
+        new_bounds = b;
+        collisions.extend_from_slice(c.as_slice());
+      }
+      if delta_p.z != 0.0 && !self.stops_at_edge(&server.physics, Vector3::new(0.0, 0.0, delta_p.z)) {
+        let (b, c) = self.translate(&server.physics, Vector3::new(0.0, 0.0, delta_p.z));
+        new_bounds = b;
+        collisions.extend_from_slice(c.as_slice());
+      }
     }
 
     let y_axis = Vector3::new(0.0, 1.0, 0.0);
-    let walk_v =
+    let walk_speed =
+      match self.movement_mode {
+        protocol::MovementMode::Walk => 1.0,
+        protocol::MovementMode::Sprint => SPRINT_SPEED,
+        protocol::MovementMode::Crouch => CROUCH_SPEED,
+      };
+    let mut walk_v =
         Matrix3::from_axis_angle(y_axis, cgmath::Rad(self.lateral_rotation))
-        * self.walk_accel;
-    self.speed += walk_v;
-    self.speed += self.accel;
-    // friction
-    self.speed.mul_assign_element_wise(Vector3::new(0.7, 0.99, 0.7 as f32));
+        * self.walk_accel * walk_speed;
+    if self.flying {
+      self.speed += walk_v;
+      self.speed.mul_assign_element_wise(Vector3::new(0.7, 0.7, 0.7 as f32));
+    } else {
+      // Only flying players can walk up and down.
+      walk_v.y = 0.0;
+      self.speed += walk_v;
+      self.speed += self.accel;
+      // friction
+      self.speed.mul_assign_element_wise(Vector3::new(0.7, 0.99, 0.7 as f32));
+    }
 
     (new_bounds, collisions)
   }
 
+  /// Start moving in a different way. Standing up from a crouch waits until there's room overhead.
+  pub fn set_movement_mode(&mut self, physics: &Mutex<physics::T>, mode: protocol::MovementMode) {
+    self.requested_mode = mode;
+    self.change_movement_mode(physics);
+  }
+
+  /// Try to switch to the requested movement mode, resizing the player to match.
+  fn change_movement_mode(&mut self, physics: &Mutex<physics::T>) {
+    let mode = self.requested_mode;
+    let (height, step_height) =
+      match mode {
+        protocol::MovementMode::Crouch => (CROUCHING_HEIGHT, CROUCH_STEP_HEIGHT),
+        _ => (STANDING_HEIGHT, MAX_STEP_HEIGHT),
+      };
+
+    let mut physics = physics.lock().unwrap();
+    let bounds = *physics.get_bounds(self.body.id).unwrap();
+    let old_height = bounds.max.y - bounds.min.y;
+    if old_height != height {
+      let new_bounds = Aabb3::new(bounds.min, Point3::new(bounds.max.x, bounds.min.y + height, bounds.max.z));
+      if !physics.reshape_misc(self.body.id, &new_bounds) {
+        return
+      }
+      self.position.y += (height - old_height) / 2.0;
+    }
+    self.body.step_height = step_height;
+    self.movement_mode = mode;
+  }
+
+  /// Whether a crouching player on the ground should stop rather than take a step that would leave
+  /// them over a drop. If they should, their speed in that direction is dropped.
+  fn stops_at_edge(&mut self, physics: &Mutex<physics::T>, shift: Vector3<f32>) -> bool {
+    if self.movement_mode != protocol::MovementMode::Crouch || !self.on_ground {
+      return false
+    }
+    let physics = physics.lock().unwrap();
+    let bounds = *physics.get_bounds(self.body.id).unwrap();
+    let shifted = Aabb3::new(bounds.min + shift, bounds.max + shift);
+    if physics.supported(&shifted, CROUCH_STEP_HEIGHT) {
+      return false
+    }
+    if shift.x != 0.0 {
+      self.speed.x = 0.0;
+    }
+    if shift.z != 0.0 {
+      self.speed.z = 0.0;
+    }
+    true
+  }
+
+  #[allow(missing_docs)]
+  pub fn set_flying(&mut self, flying: bool) {
+    self.flying = flying;
+    // Whatever vertical walking there was belonged to the old mode.
+    self.walk_accel.y = 0.0;
+    self.speed.y = 0.0;
+  }
+
   /// Changes the player's acceleration by the given `da`.
   pub fn walk(&mut self, da: Vector3<f32>) {
     self.walk_accel += &da * 0.1;
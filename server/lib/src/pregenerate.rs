@@ -0,0 +1,87 @@
+//! Generate terrain ahead of time, so public servers don't hitch the first time somewhere is visited,
+//! and the world save is warm before anybody connects.
+
+use cgmath::Point3;
+use collision::Aabb3;
+use std;
+use std::sync::Mutex;
+use time;
+
+use common::voxel;
+
+use server;
+use update_gaia;
+use world_save;
+
+/// The vertical range to generate, in world coordinates. Generated terrain stays well within this.
+const LOW_Y: i32 = -64;
+const HIGH_Y: i32 = 64;
+
+/// Generate all the full-detail voxels within `radius` regions of spawn, horizontally, nearest first.
+/// This drives gaia directly, as fast as it'll go, and gives up early if the server is quitting.
+/// Each region is saved and dropped from memory once it's done, so a big radius doesn't need the
+/// whole area in memory at once.
+pub fn pregenerate(server: &server::T, radius: i32, quit_signal: &Mutex<bool>) {
+  let width = 1 << world_save::LG_REGION_WIDTH;
+  let mut regions =
+    world_save::regions_in(
+      &Aabb3::new(
+        Point3::new(-radius * width, LOW_Y, -radius * width),
+        Point3::new((radius + 1) * width - 1, HIGH_Y - 1, (radius + 1) * width - 1),
+      )
+    );
+  regions.sort_by_key(|region| {
+    let low = region.low_corner();
+    low.x.abs() + low.z.abs()
+  });
+
+  println!("Pre-generating {} regions", regions.len());
+  let start = time::precise_time_ns();
+  for (i, region) in regions.iter().enumerate() {
+    let low = region.low_corner();
+    for x in low.x .. low.x + width {
+      if *quit_signal.lock().unwrap() {
+        println!("Pre-generation stopped after {} of {} regions", i, regions.len());
+        return
+      }
+
+      let mut slice = Vec::with_capacity((width * width) as usize);
+      for y in low.y .. low.y + width {
+      for z in low.z .. low.z + width {
+        slice.push(voxel::bounds::new(x, y, z, 0));
+      }}
+      update_gaia::update_gaia(
+        server,
+        update_gaia::Message::Load(time::precise_time_ns(), slice, update_gaia::LoadDestination::None),
+      );
+    }
+    save_and_unload(server, quit_signal);
+
+    let done = i + 1;
+    let elapsed_s = (time::precise_time_ns() - start) as f64 / 1_000_000_000.0;
+    let eta_s = elapsed_s / done as f64 * (regions.len() - done) as f64;
+    println!(
+      "Pre-generated {} of {} regions ({:.0}%), about {:.0}s left",
+      done,
+      regions.len(),
+      100.0 * done as f64 / regions.len() as f64,
+      eta_s,
+    );
+  }
+
+  println!("Pre-generation done");
+}
+
+/// Save what's been generated and drop whatever's far from the players, through the I/O thread like
+/// any other disk work, and wait for it to get through the queue.
+fn save_and_unload(server: &server::T, quit_signal: &Mutex<bool>) {
+  let players =
+    server.players.lock().unwrap().values()
+    .map(|player| world_save::region_at(&player.position))
+    .collect();
+  server.world_save.request(world_save::Request::Flush);
+  server.world_save.request(world_save::Request::UnloadFar(players));
+  while server.world_save.pending_requests() > 0 && !*quit_signal.lock().unwrap() {
+    std::thread::sleep(std::time::Duration::from_millis(10));
+  }
+}
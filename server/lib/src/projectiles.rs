@@ -0,0 +1,156 @@
+//! Things players throw: one of whatever's in their selected slot flies off along a falling arc,
+//! hurts the first player or mob it hits, and drops as an item wherever it stops.
+
+use cgmath::{Vector3, InnerSpace};
+
+use common::protocol;
+
+use components;
+use entity;
+use entity::EntityId;
+use health;
+use items;
+use loot;
+use physics;
+use server;
+
+/// How fast things leave the thrower's hand, in voxels per tick.
+const THROW_SPEED: f32 = 0.8;
+
+const DAMAGE: u32 = 3;
+
+/// Players can throw once every this many ticks. It shares a cooldown with melee attacks.
+const COOLDOWN: u64 = 10;
+
+/// Thrown things that haven't hit anything after this many ticks, like ones thrown off into unloaded
+/// terrain, are dropped where they are.
+const LIFETIME: u64 = 10 * server::UPDATES_PER_SECOND;
+
+/// Items are dropped this far back from what a thrown thing hit, so they don't start inside it.
+const LANDING_MARGIN: f32 = 0.2;
+
+/// Throw one of what's in a player's selected slot. Nothing happens if the slot's empty, or the
+/// player threw or attacked too recently.
+pub fn throw(server: &server::T, thrower: entity::id::Player, direction: Vector3<f32>) {
+  // Also false for NaNs.
+  if !(direction.magnitude2() > 0.0) {
+    return
+  }
+  let tick = *server.tick.lock().unwrap();
+  let (position, material, client_id, inventory) = {
+    let mut players = server.players.lock().unwrap();
+    let player =
+      match players.get_mut(&thrower) {
+        None => return,
+        Some(player) => player,
+      };
+    if tick < player.next_attack_tick {
+      return
+    }
+    let material =
+      match player.inventory.selected() {
+        None => return,
+        Some(material) => material,
+      };
+    player.inventory.take(material, 1);
+    player.next_attack_tick = tick + COOLDOWN;
+    (player.position, material, player.client_id, player.inventory.update_message())
+  };
+  if let Some(client) = server.clients.lock().unwrap().get_mut(&client_id) {
+    client.send(inventory);
+  }
+
+  let projectile_id = server.projectile_allocator.lock().unwrap().allocate();
+  let id = EntityId::Projectile(projectile_id);
+  let velocity = direction.normalize() * THROW_SPEED;
+  {
+    let mut components = server.components.lock().unwrap();
+    components.position.insert(id, position);
+    components.velocity.insert(id, velocity);
+    components.item.insert(id, protocol::ItemStack { material: material, count: 1 });
+    components.projectile.insert(id, components::Projectile { thrower: thrower, expiry: tick + LIFETIME });
+  }
+  debug!("Player {} threw {:?}", thrower.to_u32(), material);
+}
+
+/// Move everything thrown along its arc, and see what it hits. Call this every tick.
+pub fn update(server: &server::T, tick: u64) {
+  let ids: Vec<_> = server.components.lock().unwrap().projectile.iter().map(|(&id, _)| id).collect();
+  for id in ids {
+    fly(server, id, tick);
+  }
+}
+
+fn fly(server: &server::T, id: EntityId, tick: u64) {
+  let projectile_id =
+    match id {
+      EntityId::Projectile(projectile_id) => projectile_id,
+      _ => return,
+    };
+  let (position, velocity, stack, projectile) = {
+    let mut components = server.components.lock().unwrap();
+    let components = &mut *components;
+    let velocity =
+      match components.velocity.get_mut(&id) {
+        None => return,
+        Some(velocity) => velocity,
+      };
+    velocity.y = (velocity.y - components::GRAVITY).max(-components::TERMINAL_SPEED);
+    match (components.position.get(&id), components.item.get(&id), components.projectile.get(&id)) {
+      (Some(&position), Some(&stack), Some(projectile)) => (position, *velocity, stack, *projectile),
+      _ => return,
+    }
+  };
+
+  let hit = server.physics.lock().unwrap().raycast(&position, &velocity, velocity.magnitude());
+  let landing =
+    match hit {
+      Some((collision, point, _)) => {
+        if let physics::Collision::Misc(misc_id) = collision {
+          // Players can't hurt themselves, say by throwing straight up.
+          match body_owner(server, misc_id) {
+            Some(target) if target != EntityId::Player(projectile.thrower) => health::strike(server, target, DAMAGE, loot::Tool::Material(stack.material)),
+            _ => {},
+          }
+        }
+        point - velocity.normalize() * LANDING_MARGIN
+      },
+      None if tick >= projectile.expiry => position,
+      None => {
+        let position = position + velocity;
+        if let Some(p) = server.components.lock().unwrap().position.get_mut(&id) {
+          *p = position;
+        }
+        let update =
+          protocol::ServerToClient::UpdateProjectile(
+            projectile_id,
+            position,
+            velocity * server::UPDATES_PER_SECOND as f32,
+            stack.material,
+          );
+        for (_, client) in server.clients.lock().unwrap().iter_mut() {
+          client.send(update.clone());
+        }
+        return
+      },
+    };
+
+  server.components.lock().unwrap().remove(&id);
+  for (_, client) in server.clients.lock().unwrap().iter_mut() {
+    client.send(protocol::ServerToClient::RemoveProjectile(projectile_id));
+  }
+  items::drop_stack(server, landing, stack);
+}
+
+/// The player or mob whose body has some physics id, if any.
+fn body_owner(server: &server::T, misc_id: entity::id::Misc) -> Option<EntityId> {
+  let player =
+    server.players.lock().unwrap().values()
+    .find(|player| player.body.id == misc_id)
+    .map(|player| EntityId::Player(player.entity_id));
+  player.or_else(|| {
+    server.mobs.lock().unwrap().values()
+      .find(|mob| mob.body.id == misc_id)
+      .map(|mob| EntityId::Mob(mob.entity_id))
+  })
+}
@@ -0,0 +1,86 @@
+//! The console's `reload`: re-read the data files the server was started with, so content can be
+//! tweaked without a restart. Each file is checked in full before anything changes, and a file with
+//! mistakes in it leaves what's running alone.
+
+use common::protocol;
+
+use loot;
+use server;
+use shapes;
+use spawner;
+use trade;
+
+/// Reload what's safe to change while the server runs, and describe what happened, a line per file.
+pub fn reload(server: &server::T) -> Vec<String> {
+  let mut report = Vec::new();
+
+  match trade::read_offers() {
+    Err(err) => report.push(format!("Kept the old trade offers: {}", err)),
+    Ok(offers) => {
+      let mut trade = server.trade.lock().unwrap();
+      if trade.offers == offers {
+        report.push(String::from("Trade offers are unchanged."));
+      } else {
+        report.push(format!("Loaded {} trade offers; traders stocked from now on will offer them.", offers.len()));
+        trade.offers = offers;
+      }
+    },
+  }
+
+  match spawner::read_rules() {
+    Err(err) => report.push(format!("Kept the old spawn rules: {}", err)),
+    Ok(rules) => {
+      let mut spawner = server.spawner.lock().unwrap();
+      if spawner.rules == rules {
+        report.push(String::from("Spawn rules are unchanged."));
+      } else {
+        report.push(format!("Loaded {} spawn rules.", rules.len()));
+        spawner.rules = rules;
+      }
+    },
+  }
+
+  match loot::read_entries() {
+    Err(err) => report.push(format!("Kept the old loot tables: {}", err)),
+    Ok(entries) => {
+      let mut loot = server.loot.lock().unwrap();
+      if loot.entries == entries {
+        report.push(String::from("Loot tables are unchanged."));
+      } else {
+        report.push(format!("Loaded {} loot table entries.", entries.len()));
+        loot.entries = entries;
+      }
+    },
+  }
+
+  match shapes::read_models() {
+    Err(err) => report.push(format!("Kept the old shapes: {}", err)),
+    Ok(models) => {
+      let names;
+      {
+        let mut shapes = server.shapes.lock().unwrap();
+        if shapes.models == models {
+          report.push(String::from("Shapes are unchanged."));
+          names = None;
+        } else {
+          report.push(format!("Loaded {} shapes; shapes already placed keep the old ones' form.", models.len()));
+          shapes.models = models;
+          names = Some(shapes.names());
+        }
+      }
+      if let Some(names) = names {
+        for (_, client) in server.clients.lock().unwrap().iter_mut() {
+          client.send(protocol::ServerToClient::Shapes(names.clone()));
+        }
+      }
+    },
+  }
+
+  // Terrain already generated (and saved, and sent to clients) would no longer match what's
+  // generated next to it, and voxels are saved by material id.
+  report.push(String::from(
+    "Materials, biomes, and terrain generation settings can only be changed by restarting the server.",
+  ));
+
+  report
+}
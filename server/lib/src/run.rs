@@ -9,23 +9,76 @@ use voxel_data;
 
 use common;
 use common::closure_series;
+use common::data_dir;
+use common::interval_timer::IntervalTimer;
+use common::protocol;
+use common::reliable;
 use common::socket::ReceiveSocket;
+use common::telemetry;
+use common::thread_priority;
 
+use accounts;
+use admin;
+use boss;
+use event_log;
 use client_recv_thread::apply_client_update;
+use gaia_queue;
+use pregenerate::pregenerate;
+use reload;
 use server;
+use spawn;
 use update_gaia;
 use update_gaia::update_gaia;
 use update_world::update_world;
+use world_save;
 
-mod terrain {
-  pub use ::terrain::*;
+/// How often to write dirty terrain regions to disk.
+const WORLD_FLUSH_INTERVAL_NS: u64 = 30_000_000_000;
+
+/// The most simulation steps to run back-to-back to catch up after a slow one. Any further behind
+/// than this and the simulation skips ahead instead, so it can't fall into a spiral of catching up.
+const MAX_CATCH_UP_STEPS: u64 = 5;
+
+/// How many events the `events` console command prints.
+const MAX_EVENTS_SHOWN: usize = 20;
+
+/// How often to tell the gaia queue where players are, so it can re-prioritize loads.
+const GAIA_REPRIORITIZE_INTERVAL_NS: u64 = 100_000_000;
+
+/// Environment variable holding the number of threads to generate terrain on. Defaults to 1.
+const GAIA_THREADS_VAR: &'static str = "PLAYFORM_GAIA_THREADS";
+
+/// Set this environment variable to 0 to run every thread at the same priority.
+const THREAD_PRIORITIES_VAR: &'static str = "PLAYFORM_THREAD_PRIORITIES";
+
+/// Set this environment variable to pin the tick and gaia threads to their own cores.
+const PIN_CORES_VAR: &'static str = "PLAYFORM_PIN_CORES";
+
+fn gaia_thread_count() -> usize {
+  match std::env::var(GAIA_THREADS_VAR) {
+    Err(_) => 1,
+    Ok(count) => {
+      match count.parse() {
+        Ok(count) if count > 0 => count,
+        _ => {
+          warn!("Ignoring invalid {}: {:?}", GAIA_THREADS_VAR, count);
+          1
+        },
+      }
+    },
+  }
 }
 
-const SAVE_TERRAIN: bool = false;
+fn thread_config() -> thread_priority::Config {
+  let mut config = thread_priority::default();
+  config.priorities = std::env::var(THREAD_PRIORITIES_VAR).map(|v| v != "0").unwrap_or(true);
+  config.pin_cores = std::env::var_os(PIN_CORES_VAR).is_some();
+  config
+}
 
-#[allow(missing_docs)]
-pub fn run(listen_url: &str, quit_signal: &Mutex<bool>) {
-  let gaia_updates = Mutex::new(std::collections::VecDeque::new());
+/// Run a server until `quit_signal` is set. Console commands are taken from `commands` as they arrive.
+pub fn run(listen_url: &str, quit_signal: &Mutex<bool>, commands: &Mutex<Vec<admin::Command>>) {
+  let gaia_updates = Mutex::new(gaia_queue::new());
 
   let listen_socket = ReceiveSocket::new(listen_url.as_ref(), None);
   let listen_socket = Mutex::new(listen_socket);
@@ -33,10 +86,9 @@ pub fn run(listen_url: &str, quit_signal: &Mutex<bool>) {
   let server = server::new();
   let server = &server;
 
-  let terrain_path = std::path::Path::new("default.terrain");
-
-  println!("Loading terrain from {}", terrain_path.to_str().unwrap());
-  load_terrain(&server.terrain_loader.terrain, &terrain_path);
+  let thread_config = thread_config();
+  let gaia_threads = gaia_thread_count();
+  info!("Generating terrain on {} thread(s)", gaia_threads);
 
   let mut threads = Vec::new();
 
@@ -44,6 +96,7 @@ pub fn run(listen_url: &str, quit_signal: &Mutex<bool>) {
     threads.push(thread_scoped::scoped(|| {
       while !*quit_signal.lock().unwrap() {
         info!("Outstanding gaia updates: {}", gaia_updates.lock().unwrap().len());
+        info!("Outstanding world I/O requests: {}", server.world_save.pending_requests());
         std::thread::sleep(std::time::Duration::from_secs(1));
       }
 
@@ -52,31 +105,122 @@ pub fn run(listen_url: &str, quit_signal: &Mutex<bool>) {
   }
 
   unsafe {
-    let server = &server;
-    let gaia_updates = &gaia_updates;
-    let listen_socket = &listen_socket;
-    threads.push(thread_scoped::scoped(move || {
-      closure_series::new(vec!(
-        quit_upon(&quit_signal),
-        consider_world_update(&server, |up| { gaia_updates.lock().unwrap().push_back(up) }),
-        network_listen(&listen_socket, server, |up| { gaia_updates.lock().unwrap().push_back(up) }),
-        consider_gaia_update(&server, || { gaia_updates.lock().unwrap().pop_front() } ),
-      ))
-      .until_quit();
+    threads.push(thread_scoped::scoped(|| {
+      let now = time::precise_time_ns();
+      let mut flush_timer = IntervalTimer::new(WORLD_FLUSH_INTERVAL_NS, now + WORLD_FLUSH_INTERVAL_NS);
+      while !*quit_signal.lock().unwrap() {
+        if flush_timer.update(time::precise_time_ns()) > 0 {
+          server.world_save.request(world_save::Request::Flush);
+          let players =
+            server.players.lock().unwrap().values()
+            .map(|player| world_save::region_at(&player.position))
+            .collect();
+          server.world_save.request(world_save::Request::UnloadFar(players));
+          accounts::save_all(server);
+          server.orientations.lock().unwrap().save();
+        }
+        let served =
+          stopwatch::time("world_save.serve_request", || {
+            server.world_save.serve_request(&server.terrain_loader.terrain)
+          });
+        if !served {
+          std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+      }
 
       stopwatch::clone()
-    }));
+    }))
+  }
+
+  unsafe {
+    threads.push(thread_scoped::scoped(|| {
+      while !*quit_signal.lock().unwrap() {
+        let command = commands.lock().unwrap().pop();
+        match command {
+          None => {},
+          Some(admin::Command::Events(_)) => {},
+          Some(ref command) => {
+            server.event_log.lock().unwrap().record(&event_log::Actor::Console, &event_log::Event::Command(format!("{:?}", command)));
+          },
+        }
+        match command {
+          None => std::thread::sleep(std::time::Duration::from_millis(100)),
+          Some(admin::Command::Pregenerate(radius)) => pregenerate(server, radius, quit_signal),
+          Some(admin::Command::SpawnBoss(name, position)) => {
+            if let Some(kind) = boss::kind(&name) {
+              boss::spawn(server, kind, position);
+            }
+          },
+          Some(admin::Command::Spawn(name, place)) => {
+            let feet =
+              match place {
+                admin::Place::At(feet) => Some(feet),
+                admin::Place::LookedAtBy(player) => {
+                  let size = spawn::size(&name).unwrap();
+                  spawn::looked_at(server, player).map(|(point, normal)| spawn::against(point, normal, size))
+                },
+              };
+            match feet {
+              None => println!("That player isn't looking at anything within reach."),
+              Some(feet) => {
+                spawn::spawn_named(server, &name, feet);
+              },
+            }
+          },
+          Some(admin::Command::Events(filter)) => {
+            match server.event_log.lock().unwrap().query(&filter, MAX_EVENTS_SHOWN) {
+              Ok(events) => {
+                for event in events {
+                  println!("{}", event);
+                }
+              },
+              Err(err) => println!("Error reading the event log: {}", err),
+            }
+          },
+          Some(admin::Command::Reload) => {
+            for line in reload::reload(server) {
+              println!("{}", line);
+            }
+          },
+        }
+      }
+
+      stopwatch::clone()
+    }))
+  }
+
+  for index in 0 .. gaia_threads {
+    unsafe {
+      let server = &server;
+      let gaia_updates = &gaia_updates;
+      let quit_signal = &quit_signal;
+      let thread_config = &thread_config;
+      threads.push(thread_scoped::scoped(move || {
+        thread_priority::apply(thread_config, thread_priority::Role::Gaia { index: index });
+        // Only the first gaia thread applies brushes, so they're applied in the order they arrived.
+        let brushes = index == 0;
+        closure_series::new(vec!(
+          quit_upon(&quit_signal),
+          consider_gaia_update(&server, gaia_updates, brushes),
+        ))
+        .until_quit();
+
+        stopwatch::clone()
+      }));
+    }
   }
   unsafe {
     let server = &server;
     let gaia_updates = &gaia_updates;
     let quit_signal = &quit_signal;
     let listen_socket = &listen_socket;
+    let thread_config = &thread_config;
     threads.push(thread_scoped::scoped(move || {
+      thread_priority::apply(thread_config, thread_priority::Role::Tick);
       closure_series::new(vec!(
         quit_upon(&quit_signal),
-        consider_world_update(&server, |up| { gaia_updates.lock().unwrap().push_back(up) }),
-        network_listen(&listen_socket, server, |up| { gaia_updates.lock().unwrap().push_back(up) }),
+        consider_world_update(&server, |up| { gaia_updates.lock().unwrap().push(up) }),
+        network_listen(&listen_socket, server, |up| { gaia_updates.lock().unwrap().push(up) }),
       ))
       .until_quit();
 
@@ -96,13 +240,26 @@ pub fn run(listen_url: &str, quit_signal: &Mutex<bool>) {
     tree_ram_usage(&server.terrain_loader.terrain.voxels.lock().unwrap()) as f32 / (1 << 20) as f32,
   );
 
-  if SAVE_TERRAIN {
-    println!("Saving terrain to {}", terrain_path.to_str().unwrap());
-    stopwatch::time("save_terrain", || {
-      save_terrain(&server.terrain_loader.terrain, &terrain_path);
-    });
+  if telemetry::enabled() {
+    let mut report = telemetry::report();
+    report.push_samples("server_tick_ns", &server.tick_times.lock().unwrap());
+    report.push_samples("world_io_latency_ns", &server.world_save.latencies.lock().unwrap());
+    report.push("world_io_queue_depth", server.world_save.pending_requests());
+    report.push("world_corruptions", *server.world_save.corruptions.lock().unwrap());
+    let path = data_dir::dir(data_dir::Kind::Logs).join("telemetry_server.out");
+    match report.write_to(&path) {
+      Ok(()) => println!("Wrote telemetry to {}", path.display()),
+      Err(err) => warn!("Error writing telemetry: {:?}", err),
+    }
   }
 
+  println!("Saving world");
+  accounts::save_all(server);
+  server.orientations.lock().unwrap().save();
+  stopwatch::time("world_save.flush", || {
+    server.world_save.flush(&server.terrain_loader.terrain);
+  });
+
   stopwatch::clone().print();
 }
 
@@ -123,11 +280,28 @@ fn consider_world_update<'a, ToGaia>(
   ToGaia: FnMut(update_gaia::Message) + 'a,
 {
   Box::new(move || {
-    if server.update_timer.lock().unwrap().update(time::precise_time_ns()) > 0 {
-      update_world(
-        server,
-        &mut to_gaia,
-      );
+    // Client messages are handled between calls, so they're never held up waiting for the next step.
+    let steps = server.update_timer.lock().unwrap().update(time::precise_time_ns());
+    if steps > 0 {
+      if steps > MAX_CATCH_UP_STEPS {
+        warn!("Simulation fell {} steps behind; skipping {}", steps, steps - MAX_CATCH_UP_STEPS);
+      }
+      for _ in 0 .. std::cmp::min(steps, MAX_CATCH_UP_STEPS) {
+        let start = time::precise_time_ns();
+        update_world(
+          server,
+          &mut to_gaia,
+        );
+        let end = time::precise_time_ns();
+        server.tick_times.lock().unwrap().push(end - start);
+        let players = server.players.lock().unwrap().len();
+        if let Some(distance) = server.view_distance.lock().unwrap().update(end - start, players, end) {
+          info!("Clients may now load terrain {} chunks out", distance);
+          for (_, client) in server.clients.lock().unwrap().iter_mut() {
+            client.send(protocol::ServerToClient::MaxLoadDistance(distance));
+          }
+        }
+      }
       closure_series::Restart
     } else {
       closure_series::Continue
@@ -147,22 +321,81 @@ fn network_listen<'a, ToGaia>(
       common::socket::Result::Empty => closure_series::Continue,
       common::socket::Result::Terminating => closure_series::Quit,
       common::socket::Result::Success(up) => {
-        let up = bincode::deserialize(up.as_ref()).unwrap();
-        apply_client_update(server, &mut to_gaia, up);
+        // Clients tag what they send with their id and key once they have them, so it can be put in
+        // order with the rest of what they've sent.
+        let up: (Option<(protocol::ClientId, u64)>, reliable::Envelope<protocol::ClientToServer>) =
+          match bincode::deserialize(up.as_ref()) {
+            Ok(up) => up,
+            Err(err) => {
+              warn!("Ignoring a malformed message: {:?}", err);
+              return closure_series::Restart
+            },
+          };
+        let (lease, envelope) = up;
+        let sender = lease.map(|(client_id, _)| client_id);
+        let ups =
+          match lease {
+            // Until a client has its id and key, all it can do is ask to join.
+            None => {
+              match envelope.message {
+                up @ protocol::ClientToServer::Init(..) |
+                up @ protocol::ClientToServer::ProxiedInit { .. } => vec!(up),
+                _ => {
+                  warn!("Ignoring a message from a client without an id");
+                  return closure_series::Restart
+                },
+              }
+            },
+            Some((client_id, key)) => {
+              let mut clients = server.clients.lock().unwrap();
+              match clients.get_mut(&client_id) {
+                // The client has been dropped; anything it had left to say is moot.
+                None => return closure_series::Restart,
+                Some(client) => {
+                  if key != client.link_key {
+                    warn!("Ignoring a message with the wrong key for client {}", client.address);
+                    return closure_series::Restart
+                  }
+                  client.link.receive(envelope)
+                },
+              }
+            },
+          };
+        for up in ups {
+          apply_client_update(server, &mut to_gaia, sender, up);
+        }
         closure_series::Restart
       },
     }
   })
 }
 
-fn consider_gaia_update<'a, Get>(
+/// Apply the next gaia update. If `brushes` is false, only terrain loads are taken.
+fn consider_gaia_update<'a>(
   server: &'a server::T,
-  mut get_update: Get,
-) -> closure_series::Closure<'a> where
-  Get: FnMut() -> Option<update_gaia::Message> + 'a,
-{
+  gaia_updates: &'a Mutex<gaia_queue::T>,
+  brushes: bool,
+) -> closure_series::Closure<'a> {
+  let mut reprioritize_timer = IntervalTimer::new(GAIA_REPRIORITIZE_INTERVAL_NS, time::precise_time_ns());
   Box::new(move || {
-    match get_update() {
+    if reprioritize_timer.update(time::precise_time_ns()) > 0 {
+      let viewers: Vec<gaia_queue::Viewer> =
+        server.players.lock().unwrap().values()
+        .map(|player| {
+          gaia_queue::Viewer {
+            position : player.position,
+            forward  : player.forward(),
+          }
+        })
+        .collect();
+      gaia_updates.lock().unwrap().update_viewers(viewers);
+    }
+
+    let up = {
+      let mut gaia_updates = gaia_updates.lock().unwrap();
+      if brushes { gaia_updates.pop() } else { gaia_updates.pop_load() }
+    };
+    match up {
       Some(up) => {
         update_gaia(server, up);
         closure_series::Restart
@@ -172,40 +405,6 @@ fn consider_gaia_update<'a, Get>(
   })
 }
 
-fn load_terrain(terrain: &terrain::T, path: &std::path::Path) {
-  let mut file =
-    match std::fs::File::open(path) {
-      Err(err) => {
-        warn!("Error opening terrain file: {:?}", err);
-        return
-      },
-      Ok(file) => file,
-    };
-  let loaded =
-    bincode::deserialize_from(
-      &mut file,
-      bincode::Infinite,
-    );
-  let loaded =
-    match loaded {
-      Ok(loaded) => loaded,
-      Err(err) => {
-        warn!("Error loading terrain: {:?}", err);
-        return
-      },
-    };
-  *terrain.voxels.lock().unwrap() = loaded;
-}
-
-fn save_terrain(terrain: &terrain::T, path: &std::path::Path) {
-  let mut file = std::fs::File::create(path).unwrap();
-  bincode::serialize_into(
-    &mut file,
-    &*terrain.voxels.lock().unwrap(),
-    bincode::Infinite,
-  ).unwrap();
-}
-
 fn tree_ram_usage(tree: &common::voxel::tree::T) -> usize {
   fn tree_ram_usage_inner(branches: &common::voxel::tree::Branches, size: &mut usize) {
     *size += std::mem::size_of_val(branches);
@@ -3,42 +3,138 @@
 use cgmath::{Point3};
 use collision::{Aabb3};
 use rand;
+use std;
 use std::sync::Mutex;
 use time;
 
+use common::data_dir;
 use common::protocol;
+use common::reliable;
+use common::rng;
 use common::fnv_map;
 use common::id_allocator;
 use common::interval_timer::IntervalTimer;
 use common::socket::SendSocket;
+use common::telemetry;
 
+use accounts;
+use block_updates;
+use boss;
+use circuits;
+use commands;
+use components;
+use disconnect;
 use entity;
+use entity_regions;
+use event_log;
+use falling_block;
+use hints;
 use init_mobs::init_mobs;
+use items;
 use lod;
+use loot;
 use mob;
+use names;
+use orientations;
 use physics;
 use player;
+use shapes;
+use spawner;
 use sun::Sun;
+use terrain;
 use terrain_loader;
+use terrain_push;
+use trade;
+use view_distance;
+use world_save;
 
-const UPDATES_PER_SECOND: u64 = 30;
+/// How many times per second the world is updated.
+pub const UPDATES_PER_SECOND: u64 = 30;
 const SUN_TICK_NS: u64 = 1600000;
 
+/// Environment variable holding the secret that trusted proxies must present.
+/// If it's unset, proxied connections are rejected.
+const PROXY_SECRET_VAR: &'static str = "PLAYFORM_PROXY_SECRET";
+
+/// Environment variables declaring a resource pack that clients must use, as a URL to a .tar archive
+/// and its hex-encoded SHA-256. If either is unset, no pack is required.
+const RESOURCE_PACK_URL_VAR: &'static str = "PLAYFORM_RESOURCE_PACK_URL";
+const RESOURCE_PACK_SHA256_VAR: &'static str = "PLAYFORM_RESOURCE_PACK_SHA256";
+
+/// Environment variable holding the message to greet players with. If it's unset, there's no greeting.
+const MOTD_VAR: &'static str = "PLAYFORM_MOTD";
+/// If this environment variable is set, players aren't sent hints.
+const NO_HINTS_VAR: &'static str = "PLAYFORM_NO_HINTS";
+
+/// Environment variable holding the seed to generate new worlds from. Existing worlds keep the seed
+/// they were generated with. If it's unset, new worlds use seed 0.
+const SEED_VAR: &'static str = "PLAYFORM_SEED";
+/// Environment variable naming the heightmap that shapes the landscape: `brownian` (the default),
+/// `ridged` or `warped`.
+const HEIGHTMAP_VAR: &'static str = "PLAYFORM_HEIGHTMAP";
+
+/// Environment variable giving how many rounds of erosion to weather the landscape with. None by default.
+const EROSION_VAR: &'static str = "PLAYFORM_EROSION";
+
+/// If this environment variable is set, players may fly, passing through terrain.
+const ALLOW_FLY_VAR: &'static str = "PLAYFORM_ALLOW_FLY";
+
+/// If this environment variable is set, terrain around players is sent to their clients before they ask.
+const PUSH_TERRAIN_VAR: &'static str = "PLAYFORM_PUSH_TERRAIN";
+
+/// If this environment variable is set, clients are only sent the mob movements their player might see.
+const CULL_UPDATES_VAR: &'static str = "PLAYFORM_CULL_UPDATES";
+
+/// Environment variable giving the most mobs that may be spawned around players at once. 0 turns
+/// spawning off.
+const MOB_CAP_VAR: &'static str = "PLAYFORM_MOB_CAP";
+const DEFAULT_MOB_CAP: usize = 48;
+
+/// Environment variable holding the difficulty: `easy`, `normal` (the default) or `hard`. Loot
+/// tables can drop different things at each.
+const DIFFICULTY_VAR: &'static str = "PLAYFORM_DIFFICULTY";
+
+/// A resource pack that clients are required to use.
+pub struct ResourcePack {
+  #[allow(missing_docs)]
+  pub url    : String,
+  #[allow(missing_docs)]
+  pub sha256 : String,
+}
+
 /// Client handle
 pub struct Client {
   /// Socket to the client
   pub socket: SendSocket,
+  /// The client's real address, for logging and identification.
+  /// For proxied clients, this is the address the proxy forwarded for.
+  pub address: String,
+  /// The name the client is playing under.
+  pub name: String,
+  /// When the client was last heard from, in ns. See `disconnect`.
+  pub last_heard_ns: u64,
+  /// What's been sent to and received from the client, so critical messages get through.
+  pub link: reliable::Link<protocol::ServerToClient, protocol::ClientToServer>,
+  /// The secret the client tags its messages with, given to it with its id. Only messages tagged
+  /// with it go through `link`, so nobody else can ack or hold up what the client's sent.
+  pub link_key: u64,
 }
 
 impl Client {
-  #[allow(missing_docs)]
+  /// Send a message, along with any critical ones the client hasn't acked in a while. A failed send
+  /// isn't fatal: the socket reconnects, and anything critical is resent.
   pub fn send(&mut self, msg: protocol::ServerToClient) {
     use bincode;
     use bincode::serialize;
-    let msg = serialize(&msg, bincode::Infinite).unwrap();
-    match self.socket.write(msg.as_ref()) {
-      Ok(()) => {},
-      Err(err) => warn!("Error sending to client: {:?}", err),
+    let now = time::precise_time_ns();
+    let mut envelopes = self.link.resends(now);
+    let critical = msg.is_critical();
+    envelopes.push(self.link.send(msg, critical, now));
+    for envelope in envelopes {
+      let msg = serialize(&envelope, bincode::Infinite).unwrap();
+      if let Err(err) = self.socket.write(msg.as_ref()) {
+        debug!("Error sending to client {}: {:?}", self.address, err);
+      }
     }
   }
 }
@@ -48,22 +144,181 @@ impl Client {
 pub struct T {
   pub players           : Mutex<fnv_map::T<entity::id::Player, player::T>>,
   pub mobs              : Mutex<fnv_map::T<entity::id::Mob, mob::Mob>>,
+  /// Blocks that are falling. They share ids with mobs so clients can draw them the same way.
+  pub falling_blocks    : Mutex<Vec<falling_block::T>>,
+  /// Which region each player and mob is in.
+  pub entity_regions    : Mutex<entity_regions::T>,
+  /// Entity data kept by component.
+  pub components        : Mutex<components::T>,
 
-  pub player_allocator  : Mutex<id_allocator::T<entity::id::Player>>,
-  pub mob_allocator     : Mutex<id_allocator::T<entity::id::Mob>>,
-  pub terrain_allocator : Mutex<id_allocator::T<entity::id::Terrain>>,
-  pub misc_allocator    : Mutex<id_allocator::T<entity::id::Misc>>,
-  pub owner_allocator   : Mutex<id_allocator::T<lod::OwnerId>>,
-  pub client_allocator  : Mutex<id_allocator::T<protocol::ClientId>>,
+  pub player_allocator     : Mutex<id_allocator::T<entity::id::Player>>,
+  pub mob_allocator        : Mutex<id_allocator::T<entity::id::Mob>>,
+  pub item_allocator       : Mutex<id_allocator::T<entity::id::Item>>,
+  pub projectile_allocator : Mutex<id_allocator::T<entity::id::Projectile>>,
+  pub terrain_allocator    : Mutex<id_allocator::T<entity::id::Terrain>>,
+  pub misc_allocator       : Mutex<id_allocator::T<entity::id::Misc>>,
+  pub owner_allocator      : Mutex<id_allocator::T<lod::OwnerId>>,
+  pub client_allocator     : Mutex<id_allocator::T<protocol::ClientId>>,
 
   pub physics           : Mutex<physics::T>,
   pub terrain_loader    : terrain_loader::T,
-  pub rng               : Mutex<rand::StdRng>,
+  pub world_save        : world_save::T,
+  /// Who did what, for operators to look back over.
+  pub event_log         : Mutex<event_log::T>,
+  /// Who players are, and what's saved about them.
+  pub accounts          : accounts::T,
+  /// Voxels whose neighbors have changed, waiting to react.
+  pub block_updates     : Mutex<block_updates::T>,
+  /// Circuit state that isn't stored in the voxels themselves.
+  pub circuits          : Mutex<circuits::T>,
+  /// The boss fights going on.
+  pub bosses            : Mutex<boss::T>,
+  /// What traders offer, and which huts have them.
+  pub trade             : Mutex<trade::T>,
+  /// The mobs that have been spawned around players.
+  pub spawner           : Mutex<spawner::T>,
+  /// When each dropped item disappears.
+  pub items             : Mutex<items::T>,
+  /// What mobs drop when they die.
+  pub loot              : Mutex<loot::T>,
+  /// The shapes players can place.
+  pub shapes            : Mutex<shapes::T>,
+  /// Which way placed shapes and machines face.
+  pub orientations      : Mutex<orientations::T>,
 
   pub clients           : Mutex<fnv_map::T<protocol::ClientId, Client>>,
 
   pub sun               : Mutex<Sun>,
+  /// Tips waiting to be sent to players.
+  pub hints             : Mutex<hints::T>,
   pub update_timer      : Mutex<IntervalTimer>,
+  /// When to next ping clients. See `disconnect`.
+  pub ping_timer        : Mutex<IntervalTimer>,
+  /// The number of simulation steps run so far.
+  pub tick              : Mutex<u64>,
+  /// Durations of world updates, in nanoseconds, for opt-in telemetry.
+  pub tick_times        : Mutex<telemetry::Samples>,
+  /// How far out clients may currently load terrain.
+  pub view_distance     : Mutex<view_distance::T>,
+  /// What terrain has been sent to clients unasked.
+  pub terrain_push      : Mutex<terrain_push::T>,
+
+  /// Secret that proxies must present to forward client connections.
+  pub proxy_secret      : Option<String>,
+  /// Resource pack that every client is told to use.
+  pub resource_pack     : Option<ResourcePack>,
+  /// Message sent to every player when they join.
+  pub motd              : Option<String>,
+  /// What players can run from chat, and who may run the operator-only commands.
+  pub commands          : commands::T,
+  /// The seed the world is generated from.
+  pub seed              : u64,
+  /// The world's name, which comes from its seed.
+  pub world_name        : String,
+  /// Whether players may fly.
+  pub allow_fly         : bool,
+  /// Whether to cull mob updates to what each player might see. See `interest`.
+  pub cull_updates      : bool,
+  #[allow(missing_docs)]
+  pub difficulty        : loot::Difficulty,
+}
+
+impl T {
+  /// The random stream for a subsystem (one of `rng::subsystem`) and key in this world.
+  pub fn rng(&self, subsystem: &str, key: &[i64]) -> rand::XorShiftRng {
+    rng::stream(self.seed, subsystem, key)
+  }
+
+  /// The number of simulation steps run so far.
+  pub fn tick(&self) -> u64 {
+    *self.tick.lock().unwrap()
+  }
+}
+
+/// The seed the world is generated from: the saved one, if the world has been saved before.
+fn world_seed(world_save: &world_save::T) -> u64 {
+  let requested =
+    match std::env::var(SEED_VAR) {
+      Err(_) => None,
+      Ok(seed) => {
+        match seed.parse() {
+          Ok(seed) => Some(seed),
+          Err(_) => {
+            warn!("Ignoring invalid {}: {:?}", SEED_VAR, seed);
+            None
+          },
+        }
+      },
+    };
+  match world_save.load_seed() {
+    None => requested.unwrap_or(0),
+    Some(saved) => {
+      if requested.map_or(false, |requested| requested != saved) {
+        warn!("Ignoring {}, since this world was already generated with seed {}", SEED_VAR, saved);
+      }
+      saved
+    },
+  }
+}
+
+fn heightmap() -> terrain::heightmap::Kind {
+  match std::env::var(HEIGHTMAP_VAR) {
+    Err(_) => terrain::heightmap::Kind::Brownian,
+    Ok(name) => {
+      match terrain::heightmap::Kind::parse(&name) {
+        Some(kind) => kind,
+        None => {
+          warn!("Ignoring invalid {}: {:?}", HEIGHTMAP_VAR, name);
+          terrain::heightmap::Kind::Brownian
+        },
+      }
+    },
+  }
+}
+
+fn erosion_iterations() -> u32 {
+  match std::env::var(EROSION_VAR) {
+    Err(_) => 0,
+    Ok(iterations) => {
+      match iterations.parse() {
+        Ok(iterations) => iterations,
+        Err(_) => {
+          warn!("Ignoring invalid {}: {:?}", EROSION_VAR, iterations);
+          0
+        },
+      }
+    },
+  }
+}
+
+fn mob_cap() -> usize {
+  match std::env::var(MOB_CAP_VAR) {
+    Err(_) => DEFAULT_MOB_CAP,
+    Ok(cap) => {
+      match cap.parse() {
+        Ok(cap) => cap,
+        Err(_) => {
+          warn!("Ignoring invalid {}: {:?}", MOB_CAP_VAR, cap);
+          DEFAULT_MOB_CAP
+        },
+      }
+    },
+  }
+}
+
+fn difficulty() -> loot::Difficulty {
+  match std::env::var(DIFFICULTY_VAR) {
+    Err(_) => loot::Difficulty::Normal,
+    Ok(name) => {
+      match loot::difficulty(&name) {
+        Some(difficulty) => difficulty,
+        None => {
+          warn!("Ignoring invalid {}: {:?}", DIFFICULTY_VAR, name);
+          loot::Difficulty::Normal
+        },
+      }
+    },
+  }
 }
 
 #[allow(missing_docs)]
@@ -78,27 +333,54 @@ pub fn new() -> T {
       )
     );
 
+  let world_dir = data_dir::dir(data_dir::Kind::Saves).join("world");
+  let world_save = world_save::open(&world_dir);
+  let event_log = event_log::open(&world_dir.join("events.log"));
+  let seed = world_seed(&world_save);
+  world_save.save_seed(seed);
+  let world_name = names::world(seed);
+  info!("World seed is {}, so the world is called {}", seed, world_name);
+  let heightmap = heightmap();
+  info!("Using the {:?} heightmap", heightmap);
+  let erosion_iterations = erosion_iterations();
+  if erosion_iterations > 0 {
+    info!("Eroding the landscape for {} rounds", erosion_iterations);
+  }
+
   let server = T {
     players           : Mutex::new(fnv_map::new()),
     mobs              : Mutex::new(fnv_map::new()),
+    falling_blocks    : Mutex::new(Vec::new()),
+    entity_regions    : Mutex::new(entity_regions::new()),
+    components        : Mutex::new(components::new()),
 
-    player_allocator  : Mutex::new(id_allocator::new()),
-    mob_allocator     : Mutex::new(id_allocator::new()),
-    terrain_allocator : Mutex::new(id_allocator::new()),
-    misc_allocator    : Mutex::new(id_allocator::new()),
-    owner_allocator   : Mutex::new(id_allocator::new()),
-    client_allocator  : Mutex::new(id_allocator::new()),
+    player_allocator     : Mutex::new(id_allocator::new()),
+    mob_allocator        : Mutex::new(id_allocator::new()),
+    item_allocator       : Mutex::new(id_allocator::new()),
+    projectile_allocator : Mutex::new(id_allocator::new()),
+    terrain_allocator    : Mutex::new(id_allocator::new()),
+    misc_allocator       : Mutex::new(id_allocator::new()),
+    owner_allocator      : Mutex::new(id_allocator::new()),
+    client_allocator     : Mutex::new(id_allocator::new()),
 
     physics: Mutex::new(physics),
-    terrain_loader: terrain_loader::T::new(),
-    rng: {
-      let seed = [0];
-      let seed: &[usize] = &seed;
-      Mutex::new(rand::SeedableRng::from_seed(seed))
-    },
+    terrain_loader: terrain_loader::T::new(seed, heightmap, erosion_iterations),
+    world_save: world_save,
+    event_log: Mutex::new(event_log),
+    accounts: accounts::open(&world_dir.join("players")),
+    block_updates: Mutex::new(block_updates::new()),
+    circuits: Mutex::new(circuits::new()),
+    bosses: Mutex::new(boss::new()),
+    trade: Mutex::new(trade::new()),
+    spawner: Mutex::new(spawner::new(mob_cap())),
+    items: Mutex::new(items::new()),
+    loot: Mutex::new(loot::new()),
+    shapes: Mutex::new(shapes::new()),
+    orientations: Mutex::new(orientations::open(&world_dir.join("orientations"))),
 
     clients: Mutex::new(fnv_map::new()),
     sun: Mutex::new(Sun::new(SUN_TICK_NS)),
+    hints: Mutex::new(hints::new(std::env::var(NO_HINTS_VAR).is_err())),
 
     update_timer: {
       let now = time::precise_time_ns();
@@ -106,7 +388,36 @@ pub fn new() -> T {
       Mutex::new(
         IntervalTimer::new(nanoseconds_per_second / UPDATES_PER_SECOND, now)
       )
-    }
+    },
+    ping_timer: {
+      let now = time::precise_time_ns();
+      Mutex::new(IntervalTimer::new(disconnect::PING_INTERVAL_NS, now + disconnect::PING_INTERVAL_NS))
+    },
+    tick: Mutex::new(0),
+    tick_times: Mutex::new(telemetry::new()),
+    view_distance: Mutex::new(view_distance::new()),
+    terrain_push: Mutex::new(terrain_push::new(std::env::var_os(PUSH_TERRAIN_VAR).is_some())),
+
+    proxy_secret: std::env::var(PROXY_SECRET_VAR).ok(),
+    resource_pack: {
+      match (std::env::var(RESOURCE_PACK_URL_VAR), std::env::var(RESOURCE_PACK_SHA256_VAR)) {
+        (Ok(url), Ok(sha256)) => {
+          info!("Requiring resource pack {}", url);
+          Some(ResourcePack {
+            url    : url,
+            sha256 : sha256.to_lowercase(),
+          })
+        },
+        _ => None,
+      }
+    },
+    motd: std::env::var(MOTD_VAR).ok(),
+    commands: commands::new(),
+    seed: seed,
+    world_name: world_name,
+    allow_fly: std::env::var_os(ALLOW_FLY_VAR).is_some(),
+    cull_updates: std::env::var_os(CULL_UPDATES_VAR).is_some(),
+    difficulty: difficulty(),
   };
 
   init_mobs(&server);
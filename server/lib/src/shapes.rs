@@ -0,0 +1,180 @@
+//! The shapes players can place (see `common::shape`), and brushing them into the terrain. Models
+//! are data, read from a file at startup and by the console's `reload`, so shapes can be added
+//! without touching code.
+
+use cgmath::Point3;
+use collision::Aabb3;
+use std;
+use std::io::Read;
+
+use common::shape;
+use common::voxel;
+
+use server;
+use terrain;
+use voxel_data;
+
+/// Width of the grid cells that placed shapes are aligned to.
+pub const WIDTH: f32 = 4.0;
+
+/// Environment variable naming a file of shape models (see `shape::parse`). If it's unset,
+/// `shape::DEFAULT_MODELS` are used.
+const SHAPES_VAR: &'static str = "PLAYFORM_SHAPES";
+
+/// Read the shape models from the file named by `SHAPES_VAR`, or the defaults if there isn't one.
+pub fn read_models() -> Result<Vec<shape::Model>, String> {
+  let path =
+    match std::env::var(SHAPES_VAR) {
+      Err(_) => return Ok(shape::default_models()),
+      Ok(path) => path,
+    };
+  let mut text = String::new();
+  try!(
+    std::fs::File::open(&path).and_then(|mut file| file.read_to_string(&mut text))
+    .map_err(|err| format!("{}: error reading {:?}: {:?}", SHAPES_VAR, path, err))
+  );
+  shape::parse(&text).map_err(|err| format!("{}: {:?}, {}", SHAPES_VAR, path, err))
+}
+
+/// The shape models to start with.
+pub fn models() -> Vec<shape::Model> {
+  match read_models() {
+    Ok(models) => models,
+    Err(err) => {
+      warn!("Ignoring {}", err);
+      shape::default_models()
+    },
+  }
+}
+
+#[allow(missing_docs)]
+pub struct T {
+  /// Every shape that can be placed.
+  pub models : Vec<shape::Model>,
+}
+
+#[allow(missing_docs)]
+pub fn new() -> T {
+  T {
+    models : models(),
+  }
+}
+
+impl T {
+  /// The model with a given name.
+  pub fn find(&self, name: &str) -> Option<&shape::Model> {
+    shape::find(&self.models, name)
+  }
+
+  /// The names of the models, in order, to tell clients what they can place.
+  pub fn names(&self) -> Vec<String> {
+    self.models.iter().map(|model| model.name.clone()).collect()
+  }
+}
+
+/// The solid material next to each face of the cell at `low`, if there is one, in the order of
+/// `shape::culled_faces`. Each face is checked in its middle, just outside the cell.
+fn neighbors(server: &server::T, low: &Point3<f32>) -> [Option<voxel::Material>; 6] {
+  let (x, y, z) = (low.x as i32, low.y as i32, low.z as i32);
+  let w = WIDTH as i32;
+  let h = w / 2;
+  let samples = [
+    (x - 1 , y + h , z + h),
+    (x + w , y + h , z + h),
+    (x + h , y - 1 , z + h),
+    (x + h , y + w , z + h),
+    (x + h , y + h , z - 1),
+    (x + h , y + h , z + w),
+  ];
+  let voxels = server.terrain_loader.terrain.voxels.lock().unwrap();
+  let mut neighbors = [None; 6];
+  for (i, &(x, y, z)) in samples.iter().enumerate() {
+    // A surface voxel has some of its own surface showing, so there's nothing to join up with.
+    if let Some(&voxel::Volume(material)) = voxels.get(&voxel::bounds::new(x, y, z, 0)) {
+      if material.is_solid() {
+        neighbors[i] = Some(material);
+      }
+    }
+  }
+  neighbors
+}
+
+/// The low corner of the grid cell containing `p`.
+pub fn cell(p: &Point3<f32>) -> Point3<f32> {
+  let snap = |x: f32| (x / WIDTH).floor() * WIDTH;
+  Point3::new(snap(p.x), snap(p.y), snap(p.z))
+}
+
+/// The voxel at the low corner of a grid cell, which its orientation is kept under (see `orientations`).
+pub fn cell_bounds(low: &Point3<f32>) -> voxel::bounds::T {
+  voxel::bounds::new(low.x as i32, low.y as i32, low.z as i32, 0)
+}
+
+/// Whether any voxel in the grid cell at `low` is made of `material`, i.e. whether a shape placed
+/// there is still (at least partly) there.
+pub fn occupied(server: &server::T, low: &Point3<f32>, material: voxel::Material) -> bool {
+  let (x0, y0, z0) = (low.x as i32, low.y as i32, low.z as i32);
+  let w = WIDTH as i32;
+  let voxels = server.terrain_loader.terrain.voxels.lock().unwrap();
+  for x in x0 .. x0 + w {
+    for y in y0 .. y0 + w {
+      for z in z0 .. z0 + w {
+        let found =
+          match voxels.get(&voxel::bounds::new(x, y, z, 0)) {
+            Some(&voxel::Volume(m)) => m == material,
+            Some(&voxel::Surface(ref surface)) => surface.corner == material,
+            None => false,
+          };
+        if found {
+          return true
+        }
+      }
+    }
+  }
+  false
+}
+
+/// A brush that empties the grid cell at `low`, e.g. so a shape can be brushed back in rotated.
+pub fn clear(
+  low : Point3<f32>,
+) -> voxel_data::brush::T<Box<voxel_data::mosaic::T<voxel::Material> + Send>> {
+  let cube =
+    shape::Model {
+      name     : String::from("cube"),
+      elements : vec!(([0.0, 0.0, 0.0], [1.0, 1.0, 1.0])),
+      axial    : false,
+    };
+  let upright = shape::Orientation { facing: shape::Facing::South, axis: shape::Axis::Y };
+  cell_brush(low, terrain::shape::new(&cube, &upright, low, WIDTH, voxel::Material::Empty, [None; 6]))
+}
+
+/// A brush that fills the grid cell with its low corner at `low` with a shape.
+pub fn brush(
+  server      : &server::T,
+  model       : &shape::Model,
+  orientation : &shape::Orientation,
+  low         : Point3<f32>,
+  material    : voxel::Material,
+) -> voxel_data::brush::T<Box<voxel_data::mosaic::T<voxel::Material> + Send>> {
+  let neighbors = neighbors(server, &low);
+  cell_brush(low, terrain::shape::new(model, orientation, low, WIDTH, material, neighbors))
+}
+
+fn cell_brush(
+  low    : Point3<f32>,
+  mosaic : terrain::shape::T,
+) -> voxel_data::brush::T<Box<voxel_data::mosaic::T<voxel::Material> + Send>> {
+  voxel_data::brush::T {
+    bounds:
+      Aabb3::new(
+        Point3::new(low.x as i32 - 1, low.y as i32 - 1, low.z as i32 - 1),
+        Point3::new(
+          (low.x + WIDTH) as i32 + 1,
+          (low.y + WIDTH) as i32 + 1,
+          (low.z + WIDTH) as i32 + 1,
+        ),
+      ),
+    mosaic: Box::new(mosaic) as Box<voxel_data::mosaic::T<voxel::Material> + Send>,
+    min_lg_size: 0,
+  }
+}
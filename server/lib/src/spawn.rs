@@ -0,0 +1,102 @@
+//! The kinds of mobs that can be spawned, and the one way they're all spawned: through here, so
+//! anything spawned by hand is set up exactly like the mobs that appear on their own.
+
+use cgmath::{Point3, Vector3, InnerSpace};
+
+use ai;
+use boss;
+use entity;
+use init_mobs;
+use mob;
+use server;
+
+/// How far away from a player `looked_at` finds what they're looking at.
+const REACH: f32 = 64.0;
+
+/// A kind of mob.
+pub struct Kind {
+  /// What it's called in `spawn`.
+  pub name     : &'static str,
+  /// Width, height and depth.
+  pub size     : (f32, f32, f32),
+  #[allow(missing_docs)]
+  pub behavior : mob::Behavior,
+}
+
+/// Every kind of mob, besides bosses and traders.
+pub const KINDS: &'static [Kind] = &[
+  Kind { name: "hunter", size: (1.0, 2.0, 1.0), behavior: ai::hunt },
+  Kind { name: "skittish", size: (0.8, 1.0, 0.8), behavior: ai::shy },
+];
+
+/// The kind of mob with a given name.
+pub fn kind(name: &str) -> Option<&'static Kind> {
+  KINDS.iter().find(|kind| kind.name == name)
+}
+
+/// The names of everything that can be spawned, bosses included.
+pub fn names() -> Vec<&'static str> {
+  KINDS.iter().map(|kind| kind.name)
+    .chain(boss::KINDS.iter().map(|kind| kind.name))
+    .collect()
+}
+
+/// Spawn a mob standing at `feet`.
+pub fn spawn(server: &server::T, kind: &Kind, feet: Point3<f32>) -> entity::id::Mob {
+  let (width, height, depth) = kind.size;
+  let low_corner = feet + Vector3::new(-width / 2.0, 0.0, -depth / 2.0);
+  let id = init_mobs::add_mob(server, low_corner, Vector3::new(width, height, depth), kind.behavior, Vec::new());
+  // What it drops is rolled from the loot table named after its kind.
+  server.components.lock().unwrap().loot.insert(entity::EntityId::Mob(id), String::from(kind.name));
+  debug!("Spawned a {} at {:?}", kind.name, feet);
+  id
+}
+
+/// The size of the mob or boss of the named kind, if there's such a kind.
+pub fn size(name: &str) -> Option<(f32, f32, f32)> {
+  kind(name).map(|kind| kind.size)
+    .or_else(|| boss::kind(name).map(|kind| kind.size))
+}
+
+/// Spawn a mob or boss of the named kind standing at `feet`. Returns false if there's no such kind.
+pub fn spawn_named(server: &server::T, name: &str, feet: Point3<f32>) -> bool {
+  if let Some(kind) = kind(name) {
+    spawn(server, kind, feet);
+    true
+  } else if let Some(kind) = boss::kind(name) {
+    boss::spawn(server, kind, feet);
+    true
+  } else {
+    false
+  }
+}
+
+/// What a player's crosshair is pointing at, if it's within reach: the point hit, and the normal of
+/// the face hit.
+pub fn looked_at(server: &server::T, player_id: u32) -> Option<(Point3<f32>, Vector3<f32>)> {
+  let ray = {
+    let players = server.players.lock().unwrap();
+    let player =
+      match players.values().find(|player| player.entity_id.to_u32() == player_id) {
+        None => return None,
+        Some(player) => player,
+      };
+    player.forward_ray()
+  };
+  let hit = server.physics.lock().unwrap().raycast(&ray.origin, &ray.direction, REACH);
+  hit.map(|(_, point, normal)| (point, normal.normalize()))
+}
+
+/// Where to put the feet of something of a given size so it's against a face at `point`, on the
+/// side `normal` points to, rather than stuck in it.
+pub fn against(point: Point3<f32>, normal: Vector3<f32>, size: (f32, f32, f32)) -> Point3<f32> {
+  let (width, height, depth) = size;
+  let margin = 0.01;
+  let out =
+    Vector3::new(
+      normal.x * (width / 2.0 + margin),
+      if normal.y < 0.0 { -height - margin } else { margin },
+      normal.z * (depth / 2.0 + margin),
+    );
+  point + out
+}
@@ -0,0 +1,295 @@
+//! Keeps the world populated: every so often, mobs suited to the biome and the light are spawned out
+//! of sight around players, and spawned mobs that every player has left far behind are despawned.
+
+use cgmath::{Point3, Vector3, MetricSpace};
+use collision::Aabb3;
+use rand::Rng;
+use std;
+use std::io::Read;
+
+use common::fnv_set;
+use common::rng;
+use terrain::biome::climate;
+
+use entity;
+use init_mobs;
+use physics;
+use server;
+use spawn;
+
+/// Spawning is tried this often, in ticks.
+const SPAWN_INTERVAL: u64 = 30;
+
+/// Mobs are spawned at least this far from a player, so they don't appear in plain sight.
+const MIN_SPAWN_DISTANCE: f32 = 24.0;
+
+/// Mobs are spawned at most this far from a player.
+const MAX_SPAWN_DISTANCE: f32 = 48.0;
+
+/// No more mobs are spawned around a player once this many spawned mobs are within
+/// `MAX_SPAWN_DISTANCE` of them.
+const LOCAL_CAP: usize = 6;
+
+/// Spawned mobs are despawned once every player is at least this far away.
+const DESPAWN_DISTANCE: f32 = 96.0;
+
+/// How far above and below a player to look for ground to spawn on.
+const GROUND_SEARCH: f32 = 24.0;
+
+/// A spot is dark, even by day, if there's terrain this close overhead.
+const COVER_HEIGHT: f32 = 32.0;
+
+/// Environment variable naming a file of spawn rules. If it's unset, `default_rules` are used.
+const SPAWNS_VAR: &'static str = "PLAYFORM_SPAWNS";
+
+/// Where a kind of mob spawns.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rule {
+  kind   : String,
+  biomes : Vec<climate::Kind>,
+  /// Whether the mob spawns in the dark (at night, or under cover) rather than in daylight.
+  dark   : bool,
+  /// How often this is picked over the other rules that fit a spot.
+  weight : u32,
+}
+
+fn default_rules() -> Vec<Rule> {
+  vec!(
+    Rule {
+      kind   : String::from("hunter"),
+      biomes : vec!(climate::Kind::Desert, climate::Kind::Forest, climate::Kind::Tundra, climate::Kind::Plains),
+      dark   : true,
+      weight : 1,
+    },
+    Rule {
+      kind   : String::from("skittish"),
+      biomes : vec!(climate::Kind::Forest, climate::Kind::Plains),
+      dark   : false,
+      weight : 1,
+    },
+  )
+}
+
+/// The biomes that can be named in a spawns file, or a loot file.
+pub fn biome(name: &str) -> Option<climate::Kind> {
+  match name {
+    "desert" => Some(climate::Kind::Desert),
+    "forest" => Some(climate::Kind::Forest),
+    "tundra" => Some(climate::Kind::Tundra),
+    "plains" => Some(climate::Kind::Plains),
+    _ => None,
+  }
+}
+
+/// Parse a spawns file: one rule per line, as `hunter dark 2 forest plains` (the kind of mob, `day`
+/// or `dark`, a weight, and the biomes it spawns in). Blank lines, and anything after a `#`, are
+/// ignored.
+fn parse(text: &str) -> Result<Vec<Rule>, String> {
+  let mut rules = Vec::new();
+  for (i, line) in text.lines().enumerate() {
+    let line = line.split('#').next().unwrap().trim();
+    if line.is_empty() {
+      continue
+    }
+    let words: Vec<&str> = line.split_whitespace().collect();
+    if words.len() < 4 {
+      return Err(format!("line {}: expected `kind day|dark weight biome...`", i + 1))
+    }
+    if spawn::kind(words[0]).is_none() {
+      return Err(format!("line {}: unknown kind of mob {:?}", i + 1, words[0]))
+    }
+    let dark =
+      match words[1] {
+        "day" => false,
+        "dark" => true,
+        light => return Err(format!("line {}: expected `day` or `dark`, not {:?}", i + 1, light)),
+      };
+    let weight = try!(words[2].parse::<u32>().map_err(|_| format!("line {}: invalid weight {:?}", i + 1, words[2])));
+    let mut biomes = Vec::new();
+    for name in &words[3..] {
+      biomes.push(try!(biome(name).ok_or_else(|| format!("line {}: unknown biome {:?}", i + 1, name))));
+    }
+    rules.push(Rule {
+      kind   : String::from(words[0]),
+      biomes : biomes,
+      dark   : dark,
+      weight : weight,
+    });
+  }
+  Ok(rules)
+}
+
+/// Read the spawn rules from the file named by `SPAWNS_VAR`, or the defaults if there isn't one.
+pub fn read_rules() -> Result<Vec<Rule>, String> {
+  let path =
+    match std::env::var(SPAWNS_VAR) {
+      Err(_) => return Ok(default_rules()),
+      Ok(path) => path,
+    };
+  let mut text = String::new();
+  try!(
+    std::fs::File::open(&path).and_then(|mut file| file.read_to_string(&mut text))
+    .map_err(|err| format!("{}: error reading {:?}: {:?}", SPAWNS_VAR, path, err))
+  );
+  parse(&text).map_err(|err| format!("{}: {:?}, {}", SPAWNS_VAR, path, err))
+}
+
+fn rules() -> Vec<Rule> {
+  match read_rules() {
+    Ok(rules) => rules,
+    Err(err) => {
+      warn!("Ignoring {}", err);
+      default_rules()
+    },
+  }
+}
+
+#[allow(missing_docs)]
+pub struct T {
+  /// The mobs spawned here. Only these are despawned or count toward the caps, so bosses, traders
+  /// and anything spawned by hand stay put.
+  spawned   : fnv_set::T<entity::id::Mob>,
+  /// The most spawned mobs there can be at once.
+  cap       : usize,
+  /// What spawns where.
+  pub rules : Vec<Rule>,
+}
+
+#[allow(missing_docs)]
+pub fn new(cap: usize) -> T {
+  T {
+    spawned : fnv_set::new(),
+    cap     : cap,
+    rules   : rules(),
+  }
+}
+
+/// Spawn and despawn mobs around players. Call this every tick.
+pub fn update(server: &server::T, tick: u64) {
+  if tick % SPAWN_INTERVAL != 0 {
+    return
+  }
+
+  let players: Vec<Point3<f32>> = server.players.lock().unwrap().values().map(|player| player.position).collect();
+  despawn(server, &players);
+
+  let night = server.sun.lock().unwrap().is_night();
+  for player in &players {
+    spawn_near(server, player, night);
+  }
+}
+
+/// Remove the spawned mobs that are far from every player.
+fn despawn(server: &server::T, players: &[Point3<f32>]) {
+  let spawned: Vec<entity::id::Mob> = server.spawner.lock().unwrap().spawned.iter().cloned().collect();
+  let far: Vec<entity::id::Mob> = {
+    let mobs = server.mobs.lock().unwrap();
+    spawned.into_iter()
+      .filter(|mob_id| {
+        // Mobs that are already gone are forgotten too.
+        mobs.get(mob_id).map_or(true, |mob| {
+          players.iter().all(|player| player.distance(mob.position) >= DESPAWN_DISTANCE)
+        })
+      })
+      .collect()
+  };
+
+  for mob_id in far {
+    server.spawner.lock().unwrap().spawned.remove(&mob_id);
+    init_mobs::remove_mob(server, mob_id);
+  }
+}
+
+/// Maybe spawn a mob somewhere around a player, if the caps allow it and there's somewhere for it.
+fn spawn_near(server: &server::T, player: &Point3<f32>, night: bool) {
+  let (total, cap, spawned) = {
+    let spawner = server.spawner.lock().unwrap();
+    (spawner.spawned.len(), spawner.cap, spawner.spawned.iter().cloned().collect::<Vec<_>>())
+  };
+  if total >= cap {
+    return
+  }
+  let nearby = {
+    let mobs = server.mobs.lock().unwrap();
+    spawned.iter()
+      .filter_map(|mob_id| mobs.get(mob_id))
+      .filter(|mob| mob.position.distance(*player) <= MAX_SPAWN_DISTANCE)
+      .count()
+  };
+  if nearby >= LOCAL_CAP {
+    return
+  }
+
+  let mut rng =
+    server.rng(rng::subsystem::MOB_SPAWN, &[server.tick() as i64, player.x.floor() as i64, player.z.floor() as i64]);
+  let (x, z) = {
+    let angle = rng.gen_range(0.0, 2.0 * std::f32::consts::PI);
+    let distance = rng.gen_range(MIN_SPAWN_DISTANCE, MAX_SPAWN_DISTANCE);
+    (player.x + distance * angle.cos(), player.z + distance * angle.sin())
+  };
+  let feet =
+    match ground(server, x, player.y, z) {
+      None => return,
+      Some(feet) => feet,
+    };
+
+  let dark = night || covered(server, &feet);
+  let biome = server.terrain_loader.terrain.climate.kind(x, z);
+  let kind = {
+    let spawner = server.spawner.lock().unwrap();
+    let rules: Vec<&Rule> =
+      spawner.rules.iter()
+      .filter(|rule| rule.dark == dark && rule.biomes.contains(&biome) && rule.weight > 0)
+      .collect();
+    if rules.is_empty() {
+      return
+    }
+    let total: u32 = rules.iter().map(|rule| rule.weight).sum();
+    let mut pick = rng.gen_range(0, total);
+    let mut chosen = rules[0];
+    for rule in rules {
+      if pick < rule.weight {
+        chosen = rule;
+        break
+      }
+      pick -= rule.weight;
+    }
+    // Rules are checked against `spawn::KINDS` when they're read.
+    spawn::kind(&chosen.kind).unwrap()
+  };
+
+  let (width, height, depth) = kind.size;
+  let bounds =
+    Aabb3::new(
+      feet + Vector3::new(-width / 2.0, 0.0, -depth / 2.0),
+      feet + Vector3::new(width / 2.0, height, depth / 2.0),
+    );
+  if server.physics.lock().unwrap().terrain_blocks(&bounds) {
+    return
+  }
+
+  let mob_id = spawn::spawn(server, kind, feet);
+  server.spawner.lock().unwrap().spawned.insert(mob_id);
+}
+
+/// Where something could stand in a column: the first terrain surface down from `GROUND_SEARCH` above
+/// `y`, if there's one within `GROUND_SEARCH` below it.
+fn ground(server: &server::T, x: f32, y: f32, z: f32) -> Option<Point3<f32>> {
+  let top = Point3::new(x, y + GROUND_SEARCH, z);
+  let hit = server.physics.lock().unwrap().raycast(&top, &Vector3::new(0.0, -1.0, 0.0), 2.0 * GROUND_SEARCH);
+  match hit {
+    // Just above the surface, so whatever stands there isn't touching it.
+    Some((physics::Collision::Terrain(_), point, normal)) if normal.y > 0.0 => Some(point + Vector3::new(0.0, 0.01, 0.0)),
+    _ => None,
+  }
+}
+
+/// Whether there's terrain overhead, shading a spot from the sun.
+fn covered(server: &server::T, feet: &Point3<f32>) -> bool {
+  let above = *feet + Vector3::new(0.0, 0.5, 0.0);
+  let hit = server.physics.lock().unwrap().raycast(&above, &Vector3::new(0.0, 1.0, 0.0), COVER_HEIGHT);
+  match hit {
+    Some((physics::Collision::Terrain(_), _, _)) => true,
+    _ => false,
+  }
+}
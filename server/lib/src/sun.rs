@@ -18,6 +18,23 @@ impl Sun {
     }
   }
 
+  /// Fraction completed of a full cycle.
+  pub fn fraction(&self) -> f32 {
+    let fraction = (self.position as f32) / 65536.0;
+    // Longer day, shorter night.
+    fraction * fraction
+  }
+
+  /// Move the sun to a fraction of a full cycle, as returned by `fraction`.
+  pub fn set_fraction(&mut self, fraction: f32) {
+    self.position = (fraction.max(0.0).min(1.0).sqrt() * 65536.0).min(65535.0) as u16;
+  }
+
+  /// Whether the sun is below the horizon.
+  pub fn is_night(&self) -> bool {
+    self.fraction() >= 0.5
+  }
+
   pub fn update(&mut self) -> Option<f32> {
     let ticks = self.timer.update(time::precise_time_ns());
 
@@ -27,10 +44,7 @@ impl Sun {
 
     self.position = (std::num::Wrapping(self.position) + std::num::Wrapping(ticks as u16)).0;
 
-    // Fraction completed of a full cycle.
-    let fraction = (self.position as f32) / 65536.0;
-    // Longer day, shorter night.
-    let fraction = fraction * fraction;
+    let fraction = self.fraction();
 
     if self.print_timer.update(time::precise_time_ns()) > 0 {
       debug!("Sun is at {:.1}%.", fraction * 100.0);
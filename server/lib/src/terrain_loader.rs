@@ -1,4 +1,5 @@
 use collision::{Aabb3};
+use std::collections::VecDeque;
 use std::sync::Mutex;
 use stopwatch;
 use time;
@@ -17,6 +18,11 @@ use update_gaia::LoadDestination;
 
 // TODO: Consider factoring this logic such that what to load is separated from how it's loaded.
 
+/// The most time to spend per tick putting blocks from gaia into the world, in ns.
+const INGEST_BUDGET_NS: u64 = 2_000_000;
+/// The most blocks to put into the world per tick.
+const MAX_INGESTED_PER_TICK: usize = 64;
+
 /// Load and unload terrain::TerrainBlocks from the game.
 /// Each terrain::TerrainBlock can be owned by a set of owners, each of which can independently request LODs.
 /// The maximum lod::T requested is the one that is actually loaded.
@@ -25,15 +31,18 @@ pub struct T {
   pub in_progress_terrain : Mutex<in_progress_terrain::T>,
   pub lod_map             : Mutex<lod::Map>,
   pub loaded              : Mutex<fnv_map::T<voxel::bounds::T, Vec<entity::id::Terrain>>>,
+  /// Blocks gaia has finished loading for the server, waiting to be put into the world by the tick.
+  pub ready               : Mutex<VecDeque<Ready>>,
 }
 
 impl T {
-  pub fn new() -> T {
+  pub fn new(seed: u64, heightmap: terrain::heightmap::Kind, erosion_iterations: u32) -> T {
     T {
-      terrain             : terrain::T::new(terrain::Seed::new(0)),
+      terrain             : terrain::T::new(seed, heightmap, erosion_iterations),
       in_progress_terrain : Mutex::new(in_progress_terrain::T::new()),
       lod_map             : Mutex::new(lod::Map::new()),
       loaded              : Mutex::new(fnv_map::new()),
+      ready               : Mutex::new(VecDeque::new()),
     }
   }
 
@@ -149,6 +158,70 @@ impl T {
     });
   }
 
+  /// Put blocks that gaia has loaded into the world, within a per-tick budget so a flood of them can't
+  /// stall the tick. Whatever doesn't fit waits for the next tick. Returns how many were put in.
+  pub fn ingest(&self, physics: &Mutex<physics::T>) -> usize {
+    let start = time::precise_time_ns();
+    let mut count = 0;
+    while count < MAX_INGESTED_PER_TICK && time::precise_time_ns() - start < INGEST_BUDGET_NS {
+      let ready =
+        match self.ready.lock().unwrap().pop_front() {
+          None => break,
+          Some(ready) => ready,
+        };
+      T::insert_block(
+        &ready.block,
+        &ready.position,
+        ready.owner,
+        physics,
+        &mut *self.lod_map.lock().unwrap(),
+        &mut *self.in_progress_terrain.lock().unwrap(),
+        &mut *self.loaded.lock().unwrap(),
+      );
+      count += 1;
+    }
+    count
+  }
+
+  /// Update the collision bounds of a loaded voxel after it's changed.
+  pub fn update_physics(
+    &self,
+    id_allocator : &Mutex<id_allocator::T<entity::id::Terrain>>,
+    physics      : &Mutex<physics::T>,
+    position     : &voxel::bounds::T,
+    voxel        : &voxel::T,
+  ) {
+    let mut loaded = self.loaded.lock().unwrap();
+    let ids =
+      match loaded.get_mut(position) {
+        // Nobody has this voxel loaded, so it has no collision bounds.
+        None => return,
+        Some(ids) => ids,
+      };
+
+    let mut physics = physics.lock().unwrap();
+    for id in ids.drain(..) {
+      physics.remove_terrain(id);
+    }
+    match *voxel {
+      voxel::Volume(material) if !material.is_solid() => {},
+      _ => {
+        let (low, high) = position.corners();
+        let id = id_allocator.lock().unwrap().allocate();
+        physics.insert_terrain(id, &Aabb3::new(low, high));
+        ids.push(id);
+      },
+    }
+  }
+
+  /// Release everything an owner has loaded.
+  pub fn unload_owner(&self, physics: &Mutex<physics::T>, owner: lod::OwnerId) {
+    let positions = self.lod_map.lock().unwrap().owned_by(owner);
+    for position in positions {
+      self.unload(physics, &position, owner);
+    }
+  }
+
   pub fn unload(
     &self,
     physics  : &Mutex<physics::T>,
@@ -189,3 +262,10 @@ impl T {
 pub struct LoadedTerrain {
   pub bounds: Vec<(entity::id::Terrain, Aabb3<f32>)>,
 }
+
+/// A block gaia has loaded for the server.
+pub struct Ready {
+  pub block    : LoadedTerrain,
+  pub position : voxel::bounds::T,
+  pub owner    : lod::OwnerId,
+}
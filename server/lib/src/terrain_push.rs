@@ -0,0 +1,84 @@
+//! Streaming terrain to clients before they ask for it.
+//! Each tick, the chunks around where each player is headed are queued up for their client, at the
+//! LODs the client will want them, nearest first. Clients keep requesting whatever they're still
+//! missing, but mostly find it already arrived.
+
+use cgmath::{Point3, Vector3};
+use std;
+
+use common::chunk;
+use common::cube_shell::cube_shell;
+use common::fnv_map;
+use common::lod;
+use common::protocol;
+use common::surroundings_loader;
+use common::voxel;
+
+/// How far around each player to push terrain, in chunks.
+const PUSH_DISTANCE: u32 = 8;
+
+/// How many ticks ahead to guess where players will be, from their speed.
+const LOOKAHEAD_TICKS: f32 = 60.0;
+
+/// The most chunks to push to each client per tick, so pushing doesn't crowd out requests.
+const MAX_CHUNKS_PER_TICK: usize = 16;
+
+#[allow(missing_docs)]
+pub struct T {
+  /// Terrain is only pushed when this is set.
+  pub enabled : bool,
+  /// The LOD each chunk was last pushed to each client at.
+  sent        : fnv_map::T<protocol::ClientId, fnv_map::T<chunk::position::T, lod::T>>,
+}
+
+#[allow(missing_docs)]
+pub fn new(enabled: bool) -> T {
+  T {
+    enabled : enabled,
+    sent    : fnv_map::new(),
+  }
+}
+
+impl T {
+  /// Choose the terrain to push to a client whose player is at `position`, moving at `speed` per tick,
+  /// as the voxels for each chunk. `max_distance` is the farthest out clients are currently allowed to load.
+  pub fn voxels_to_push(
+    &mut self,
+    client_id    : protocol::ClientId,
+    position     : &Point3<f32>,
+    speed        : &Vector3<f32>,
+    max_distance : u32,
+  ) -> Vec<Vec<voxel::bounds::T>> {
+    let center = chunk::position::of_world_position(&(*position + *speed * LOOKAHEAD_TICKS));
+    let distance = std::cmp::min(PUSH_DISTANCE, max_distance);
+    let sent = self.sent.entry(client_id).or_insert_with(fnv_map::new);
+
+    // Forget what's out of range, so it's pushed again if the player comes back.
+    sent.retain(|chunk_position, _| {
+      surroundings_loader::distance_between(center.as_pnt(), chunk_position.as_pnt()) < distance
+    });
+
+    let mut chunks = Vec::new();
+    for radius in 0 .. distance as i32 {
+      for p in cube_shell(center.as_pnt(), radius) {
+        let chunk_position = chunk::position::of_pnt(&p);
+        let current = sent.get(&chunk_position).cloned();
+        let lod = lod::select(center.as_pnt(), &p, current);
+        if current == Some(lod) {
+          continue
+        }
+        sent.insert(chunk_position, lod);
+        chunks.push(lod::voxels_for_chunk(&chunk_position, lod));
+        if chunks.len() >= MAX_CHUNKS_PER_TICK {
+          return chunks
+        }
+      }
+    }
+    chunks
+  }
+
+  /// Forget the clients that aren't in `client_ids`.
+  pub fn retain_clients(&mut self, client_ids: &[protocol::ClientId]) {
+    self.sent.retain(|client_id, _| client_ids.contains(client_id));
+  }
+}
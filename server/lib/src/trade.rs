@@ -0,0 +1,269 @@
+//! Traders: passive mobs that keep shop outside the huts generated in the world, exchanging
+//! materials with players. What they offer is data, read from a file of offers at startup and by the
+//! console's `reload`.
+
+use cgmath::{Point3, Vector3, MetricSpace};
+use rand::Rng;
+use std;
+use std::io::Read;
+
+use common::fnv_set;
+use common::protocol;
+use common::rng;
+use common::voxel;
+
+use entity;
+use init_mobs;
+use mob;
+use names;
+use physics;
+use server;
+
+/// Environment variable naming a file of trade offers. If it's unset, traders offer `default_offers`.
+const TRADES_VAR: &'static str = "PLAYFORM_TRADES";
+
+/// How many of the offers each trader makes.
+const OFFERS_PER_TRADER: usize = 3;
+
+/// How far away from a player traders can be traded with.
+const MAX_TRADE_DISTANCE: f32 = 6.0;
+
+/// Huts within this distance of a player are given traders.
+const SEARCH_RADIUS: f32 = 128.0;
+
+#[allow(missing_docs)]
+pub struct T {
+  /// Everything traders might offer.
+  pub offers : Vec<protocol::TradeOffer>,
+  /// The huts that have been given traders, by where their traders were put.
+  furnished  : fnv_set::T<(i32, i32, i32)>,
+}
+
+#[allow(missing_docs)]
+pub fn new() -> T {
+  T {
+    offers    : offers(),
+    furnished : fnv_set::new(),
+  }
+}
+
+fn default_offers() -> Vec<protocol::TradeOffer> {
+  let offer = |price, goods| protocol::TradeOffer { price: price, goods: goods };
+  vec!(
+    offer((voxel::Material::Stone, 8), (voxel::Material::Marble, 2)),
+    offer((voxel::Material::Terrain, 16), (voxel::Material::Coal, 1)),
+    offer((voxel::Material::Coal, 4), (voxel::Material::Iron, 1)),
+    offer((voxel::Material::Iron, 4), (voxel::Material::Crystal, 1)),
+    offer((voxel::Material::Bark, 4), (voxel::Material::Lever, 1)),
+    offer((voxel::Material::Gravel, 8), (voxel::Material::Wire, 4)),
+    offer((voxel::Material::Stone, 4), (voxel::Material::PressurePlate, 2)),
+    offer((voxel::Material::Coal, 2), (voxel::Material::Lamp, 1)),
+    offer((voxel::Material::Iron, 2), (voxel::Material::Piston, 1)),
+  )
+}
+
+/// The materials that can be named in an offers file, or given with `/give`.
+pub fn material(name: &str) -> Option<voxel::Material> {
+  match name {
+    "terrain" => Some(voxel::Material::Terrain),
+    "bark" => Some(voxel::Material::Bark),
+    "leaves" => Some(voxel::Material::Leaves),
+    "stone" => Some(voxel::Material::Stone),
+    "marble" => Some(voxel::Material::Marble),
+    "sand" => Some(voxel::Material::Sand),
+    "snow" => Some(voxel::Material::Snow),
+    "gravel" => Some(voxel::Material::Gravel),
+    "wire" => Some(voxel::Material::Wire),
+    "lever" => Some(voxel::Material::Lever),
+    "pressure_plate" => Some(voxel::Material::PressurePlate),
+    "lamp" => Some(voxel::Material::Lamp),
+    "piston" => Some(voxel::Material::Piston),
+    "coal" => Some(voxel::Material::Coal),
+    "iron" => Some(voxel::Material::Iron),
+    "crystal" => Some(voxel::Material::Crystal),
+    _ => None,
+  }
+}
+
+/// Parse an offers file: one offer per line, as `8 stone -> 2 marble`. Blank lines, and anything
+/// after a `#`, are ignored.
+fn parse(text: &str) -> Result<Vec<protocol::TradeOffer>, String> {
+  let side = |words: &str| {
+    let words: Vec<&str> = words.split_whitespace().collect();
+    if words.len() != 2 {
+      return Err(format!("expected a count and a material, not {:?}", words))
+    }
+    let count = try!(words[0].parse::<u32>().map_err(|_| format!("invalid count {:?}", words[0])));
+    let material = try!(material(words[1]).ok_or_else(|| format!("unknown material {:?}", words[1])));
+    Ok((material, count))
+  };
+
+  let mut offers = Vec::new();
+  for (i, line) in text.lines().enumerate() {
+    let line = line.split('#').next().unwrap().trim();
+    if line.is_empty() {
+      continue
+    }
+    let sides: Vec<&str> = line.split("->").collect();
+    if sides.len() != 2 {
+      return Err(format!("line {}: expected `price -> goods`", i + 1))
+    }
+    let price = try!(side(sides[0]).map_err(|err| format!("line {}: {}", i + 1, err)));
+    let goods = try!(side(sides[1]).map_err(|err| format!("line {}: {}", i + 1, err)));
+    offers.push(protocol::TradeOffer { price: price, goods: goods });
+  }
+  Ok(offers)
+}
+
+/// Read the offers from the file named by `TRADES_VAR`, or the defaults if there isn't one.
+pub fn read_offers() -> Result<Vec<protocol::TradeOffer>, String> {
+  let path =
+    match std::env::var(TRADES_VAR) {
+      Err(_) => return Ok(default_offers()),
+      Ok(path) => path,
+    };
+  let mut text = String::new();
+  try!(
+    std::fs::File::open(&path).and_then(|mut file| file.read_to_string(&mut text))
+    .map_err(|err| format!("{}: error reading {:?}: {:?}", TRADES_VAR, path, err))
+  );
+  match parse(&text) {
+    Ok(ref offers) if offers.is_empty() => Err(format!("{}: no offers in {:?}", TRADES_VAR, path)),
+    Ok(offers) => Ok(offers),
+    Err(err) => Err(format!("{}: {:?}, {}", TRADES_VAR, path, err)),
+  }
+}
+
+fn offers() -> Vec<protocol::TradeOffer> {
+  match read_offers() {
+    Ok(offers) => {
+      info!("Loaded {} trade offers", offers.len());
+      offers
+    },
+    Err(err) => {
+      warn!("Ignoring {}", err);
+      default_offers()
+    },
+  }
+}
+
+/// Traders stay at their huts.
+fn keep_shop(_: &server::T, mob: &mut mob::Mob) {
+  mob.speed.x = 0.0;
+  mob.speed.z = 0.0;
+}
+
+/// Give traders to any huts near `position` that don't have them yet.
+/// Finding huts means sampling the terrain generator, so this is run on a gaia thread.
+pub fn spawn_traders(server: &server::T, position: &Point3<f32>) {
+  let huts = server.terrain_loader.terrain.huts_near(position.x, position.z, SEARCH_RADIUS);
+  for spot in huts {
+    let key = (spot.x.floor() as i32, spot.y.floor() as i32, spot.z.floor() as i32);
+    let offers = {
+      let mut trade = server.trade.lock().unwrap();
+      if !trade.furnished.insert(key) {
+        continue
+      }
+      let mut offers = trade.offers.clone();
+      let mut rng = server.rng(rng::subsystem::TRADE_OFFERS, &[key.0 as i64, key.1 as i64, key.2 as i64]);
+      rng.shuffle(&mut offers);
+      offers.truncate(OFFERS_PER_TRADER);
+      offers
+    };
+    let name = names::trader(server.seed, key);
+    debug!("{}, the trader at {:?}, offers {:?}", name, spot, offers);
+    // Stand a little above the ground, so as not to start stuck in it.
+    let low_corner = spot + Vector3::new(-0.5, 0.5, -0.5);
+    let id = init_mobs::add_mob(server, low_corner, Vector3::new(1.0, 2.0, 1.0), keep_shop, offers);
+    if let Some(mob) = server.mobs.lock().unwrap().get_mut(&id) {
+      mob.name = Some(name);
+    }
+  }
+}
+
+/// The trader a player at `position` is looking at, if there's one within reach.
+fn trader_toward(server: &server::T, position: &Point3<f32>, target: &Point3<f32>) -> Option<entity::id::Mob> {
+  let hit = server.physics.lock().unwrap().raycast(position, &(*target - *position), MAX_TRADE_DISTANCE);
+  match hit {
+    Some((physics::Collision::Misc(id), _, _)) => {
+      server.mobs.lock().unwrap().values()
+        .find(|mob| mob.body.id == id && !mob.trades.is_empty())
+        .map(|mob| mob.entity_id)
+    },
+    _ => None,
+  }
+}
+
+/// If a player is interacting with a trader, start trading with them and return true.
+pub fn interact(server: &server::T, player_id: entity::id::Player, target: &Point3<f32>) -> bool {
+  let (position, client_id) = {
+    let players = server.players.lock().unwrap();
+    let player = players.get(&player_id).unwrap();
+    (player.position, player.client_id)
+  };
+  let trader =
+    match trader_toward(server, &position, target) {
+      None => return false,
+      Some(trader) => trader,
+    };
+  let (name, offers) = {
+    let mobs = server.mobs.lock().unwrap();
+    let mob = mobs.get(&trader).unwrap();
+    (mob.name.clone().unwrap_or_else(|| String::from("the trader")), mob.trades.clone())
+  };
+  if let Some(client) = server.clients.lock().unwrap().get_mut(&client_id) {
+    client.send(protocol::ServerToClient::TradeOffers(trader, name, offers));
+  }
+  true
+}
+
+/// Make a trade, if the trader is still in reach and the player can pay, and tell them how it went.
+pub fn accept(server: &server::T, player_id: entity::id::Player, trader: entity::id::Mob, index: u32) {
+  let offer = {
+    let mobs = server.mobs.lock().unwrap();
+    mobs.get(&trader).and_then(|mob| mob.trades.get(index as usize).map(|&offer| (offer, mob.position)))
+  };
+
+  let (client_id, result, inventory) = {
+    let mut players = server.players.lock().unwrap();
+    let player = players.get_mut(&player_id).unwrap();
+    let result =
+      match offer {
+        None => Err(String::from("That offer isn't available.")),
+        Some((_, position)) if position.distance(player.position) > MAX_TRADE_DISTANCE => {
+          Err(String::from("You're too far away to trade."))
+        },
+        Some((offer, _)) => {
+          let (price, price_count) = offer.price;
+          let (goods, goods_count) = offer.goods;
+          if !player.inventory.take(price, price_count) {
+            Err(format!("You need {} {:?}, and have {}.", price_count, price, player.inventory.count(price)))
+          } else {
+            let left = player.inventory.add(goods, goods_count);
+            if left > 0 {
+              // Undo the trade.
+              player.inventory.take(goods, goods_count - left);
+              player.inventory.add(price, price_count);
+              Err(format!("You don't have room for {} {:?}.", goods_count, goods))
+            } else {
+              Ok(format!("Traded {} {:?} for {} {:?}.", price_count, price, goods_count, goods))
+            }
+          }
+        },
+      };
+    (player.client_id, result, player.inventory.update_message())
+  };
+
+  let text =
+    match result {
+      Ok(text) => text,
+      Err(text) => {
+        debug!("Rejected trade with {:?}: {}", trader, text);
+        text
+      },
+    };
+  if let Some(client) = server.clients.lock().unwrap().get_mut(&client_id) {
+    client.send(protocol::ServerToClient::Message(protocol::MessageKind::Trade, text));
+    client.send(inventory);
+  }
+}
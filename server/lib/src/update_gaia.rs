@@ -1,16 +1,21 @@
 //! Creator of the earth.
 
+use cgmath::Point3;
 use collision::{Aabb3};
 use stopwatch;
 
 use common;
+use common::chunk;
+use common::horizon;
 use common::protocol;
 use common::voxel;
 
 use lod;
 use server;
 use terrain_loader;
+use trade;
 use voxel_data;
+use world_save;
 
 #[derive(Debug, Clone, Copy)]
 /// What to do with a loaded block
@@ -19,6 +24,19 @@ pub enum LoadDestination {
   Local(lod::OwnerId),
   /// A client requested this block. Send it to them.
   Client(protocol::ClientId),
+  /// The server is sending this block to a client ahead of it being requested.
+  Pushed(protocol::ClientId),
+  /// A client asked whether its copy of a block is current. Send the block only if it isn't.
+  Check {
+    #[allow(missing_docs)]
+    client_id : protocol::ClientId,
+    #[allow(missing_docs)]
+    chunk     : chunk::position::T,
+    #[allow(missing_docs)]
+    lod       : common::lod::T,
+    /// The checksum of the client's copy.
+    checksum  : u64,
+  },
   /// Drop the loaded voxels on the floor.
   None
 }
@@ -27,11 +45,14 @@ pub enum LoadDestination {
 pub enum Message {
   /// Load some voxels
   Load(u64, Vec<voxel::bounds::T>, LoadDestination),
+  /// Sample a tile of the far horizon for a client.
+  Horizon(protocol::ClientId, horizon::Tile),
+  /// Give traders to the huts around a point.
+  SpawnTraders(Point3<f32>),
   /// Apply a brush operation
   Brush(voxel_data::brush::T<Box<voxel_data::mosaic::T<common::voxel::Material> + Send>>),
 }
 
-// TODO: Consider adding terrain loads to a thread pool instead of having one monolithic separate thread.
 #[allow(missing_docs)]
 pub fn update_gaia(
   server: &server::T,
@@ -44,7 +65,23 @@ pub fn update_gaia(
           load(server, time_requested, voxel_bounds, load_reason);
         });
       },
+      Message::Horizon(client_id, tile) => {
+        let columns =
+          stopwatch::time("terrain.horizon", || {
+            server.terrain_loader.terrain.horizon(&tile)
+          });
+        send(server, client_id, protocol::ServerToClient::Horizon(tile, columns));
+      },
+      Message::SpawnTraders(position) => {
+        stopwatch::time("terrain.spawn_traders", || {
+          trade::spawn_traders(server, &position);
+        });
+      },
       Message::Brush(mut brush) => {
+        for region in world_save::regions_in(&brush.bounds) {
+          server.world_save.ensure_loaded(&server.terrain_loader.terrain, &region);
+        }
+
         let mut updates = Vec::new();
         server.terrain_loader.terrain.brush(
           &mut brush,
@@ -54,20 +91,77 @@ pub fn update_gaia(
           },
         );
 
-        let mut clients = server.clients.lock().unwrap();
-        for (_, client) in clients.iter_mut() {
-          client.send(
-            protocol::ServerToClient::Voxels {
-              voxels : updates.clone(),
-              reason : protocol::VoxelReason::Updated,
-            }
-          );
-        }
+        voxels_changed(server, updates);
       },
     };
   })
 }
 
+/// Overwrite individual voxels, e.g. in response to neighbor updates.
+pub fn set_voxels(
+  server: &server::T,
+  updates: Vec<(voxel::bounds::T, voxel::T)>,
+) {
+  {
+    let mut voxels = server.terrain_loader.terrain.voxels.lock().unwrap();
+    for &(ref bounds, voxel) in &updates {
+      voxels.get_mut_or_create(bounds).data = Some(voxel);
+    }
+  }
+  voxels_changed(server, updates);
+}
+
+/// Save changed voxels, update their collision, notify their neighbors, and send them to clients.
+fn voxels_changed(
+  server: &server::T,
+  updates: Vec<(voxel::bounds::T, voxel::T)>,
+) {
+  for &(ref bounds, ref voxel) in &updates {
+    server.terrain_loader.update_physics(&server.terrain_allocator, &server.physics, bounds, voxel);
+  }
+
+  {
+    let mut block_updates = server.block_updates.lock().unwrap();
+    for &(ref bounds, _) in &updates {
+      server.world_save.mark_dirty(bounds);
+      block_updates.voxel_changed(bounds);
+    }
+  }
+
+  let checksum = voxel::checksum(&updates);
+  let mut clients = server.clients.lock().unwrap();
+  for (_, client) in clients.iter_mut() {
+    client.send(
+      protocol::ServerToClient::Voxels {
+        voxels   : updates.clone(),
+        reason   : protocol::VoxelReason::Updated,
+        checksum : checksum,
+      }
+    );
+  }
+}
+
+/// Send a message to a client that asked for something, if it's still connected.
+fn send(server: &server::T, client_id: protocol::ClientId, message: protocol::ServerToClient) {
+  // The client may have left since this was queued.
+  if let Some(client) = server.clients.lock().unwrap().get_mut(&client_id) {
+    client.send(message);
+  }
+}
+
+/// Load voxels from the world save, to send to a client.
+fn load_voxels(
+  server: &server::T,
+  voxel_bounds: Vec<voxel::bounds::T>,
+) -> Vec<(voxel::bounds::T, voxel::T)> {
+  voxel_bounds.into_iter()
+    .map(|voxel_bounds| {
+      let voxel = server.world_save.load(&server.terrain_loader.terrain, &voxel_bounds);
+      (voxel_bounds, voxel)
+    })
+    .collect()
+}
+
 #[inline(never)]
 fn load(
   server: &server::T,
@@ -75,22 +169,19 @@ fn load(
   voxel_bounds: Vec<voxel::bounds::T>,
   load_reason: LoadDestination,
 ) {
-  // TODO: Just lock `terrain` for the check and then the move;
-  // don't lock for the whole time where we're generating the block.
-  let mut lod_map = server.terrain_loader.lod_map.lock().unwrap();
-  let mut in_progress_terrain = server.terrain_loader.in_progress_terrain.lock().unwrap();
+  // Nothing is locked while blocks are generated, so other gaia threads can generate at the same time.
   match load_reason {
     LoadDestination::None => {
       for voxel_bounds in voxel_bounds {
-        server.terrain_loader.terrain.load(&voxel_bounds);
+        server.world_save.load(&server.terrain_loader.terrain, &voxel_bounds);
       }
     },
     LoadDestination::Local(owner) => {
       for voxel_bounds in voxel_bounds {
-        let block = server.terrain_loader.terrain.load(&voxel_bounds);
+        let block = server.world_save.load(&server.terrain_loader.terrain, &voxel_bounds);
         let bounds =
           match block {
-            voxel::Volume(voxel::Material::Empty) => Vec::new(),
+            voxel::Volume(material) if !material.is_solid() => Vec::new(),
             _ => {
               let (low, high) = voxel_bounds.corners();
               let id = server.terrain_allocator.lock().unwrap().allocate();
@@ -98,33 +189,52 @@ fn load(
             },
           };
         // TODO: Check that this block isn't stale, i.e. should still be loaded.
-        // Maybe this should just ping the original thread, same as we ping the client.
-        terrain_loader::T::insert_block(
-          &terrain_loader::LoadedTerrain { bounds: bounds },
-          &voxel_bounds,
-          owner,
-          &server.physics,
-          &mut *lod_map,
-          &mut *in_progress_terrain,
-          &mut *server.terrain_loader.loaded.lock().unwrap(),
+        // The tick puts it into the world, a few at a time.
+        server.terrain_loader.ready.lock().unwrap().push_back(
+          terrain_loader::Ready {
+            block    : terrain_loader::LoadedTerrain { bounds: bounds },
+            position : voxel_bounds,
+            owner    : owner,
+          }
         );
       }
     },
     LoadDestination::Client(id) => {
-      let mut voxels = Vec::new();
-      for voxel_bounds in voxel_bounds {
-        let voxel = server.terrain_loader.terrain.load(&voxel_bounds);
-        voxels.push((voxel_bounds, voxel));
-      }
-
-      let mut clients = server.clients.lock().unwrap();
-      let client = clients.get_mut(&id).unwrap();
-      client.send(
+      let voxels = load_voxels(server, voxel_bounds);
+      let checksum = voxel::checksum(&voxels);
+      send(server, id,
         protocol::ServerToClient::Voxels {
-          voxels : voxels,
-          reason : protocol::VoxelReason::Requested { at: time_requested },
+          voxels   : voxels,
+          reason   : protocol::VoxelReason::Requested { at: time_requested },
+          checksum : checksum,
         }
       );
     },
+    LoadDestination::Pushed(id) => {
+      let voxels = load_voxels(server, voxel_bounds);
+      let checksum = voxel::checksum(&voxels);
+      send(server, id,
+        protocol::ServerToClient::Voxels {
+          voxels   : voxels,
+          reason   : protocol::VoxelReason::Pushed,
+          checksum : checksum,
+        }
+      );
+    },
+    LoadDestination::Check { client_id, chunk, lod, checksum } => {
+      let voxels = load_voxels(server, voxel_bounds);
+      let current = voxel::checksum(&voxels);
+      if current == checksum {
+        send(server, client_id, protocol::ServerToClient::BlockCurrent(chunk, lod));
+      } else {
+        send(server, client_id,
+          protocol::ServerToClient::Voxels {
+            voxels   : voxels,
+            reason   : protocol::VoxelReason::Requested { at: time_requested },
+            checksum : current,
+          }
+        );
+      }
+    },
   }
 }
@@ -1,19 +1,40 @@
-use cgmath::{Point3, Vector3};
+use cgmath::{Point3, Vector3, EuclideanSpace, InnerSpace};
 use std::ops::Neg;
 use stopwatch;
+use time;
 
+use common::fnv_map;
+use common::fnv_set;
 use common::protocol;
+use common::surroundings_loader;
 use common::surroundings_loader::LoadType;
 use common::voxel;
 
+use block_updates;
+use boss;
+use circuits;
+use components;
+use disconnect;
+use entity_regions;
+use falling_block;
+use fluid;
+use health;
+use hints;
+use interest;
+use items;
 use lod;
 use mob;
 use player;
+use projectiles;
 use server;
+use spawner;
 use update_gaia;
+use world_save;
 
-// TODO: Consider removing the IntervalTimer.
+/// Mobs only think when a player is within this many regions of them.
+const MOB_AI_RADIUS: i32 = 2;
 
+/// Run one fixed-length step of the simulation.
 pub fn update_world<RequestBlock>(
   server: &server::T,
   request_block: &mut RequestBlock,
@@ -21,11 +42,31 @@ pub fn update_world<RequestBlock>(
   RequestBlock: FnMut(update_gaia::Message),
 {
   stopwatch::time("update_world", || {
+    let tick = {
+      let mut tick = server.tick.lock().unwrap();
+      *tick += 1;
+      *tick
+    };
+    for (_, client) in server.clients.lock().unwrap().iter_mut() {
+      client.send(protocol::ServerToClient::Tick(tick));
+    }
+
+    stopwatch::time("update_world.ingest_terrain", || {
+      server.terrain_loader.ingest(&server.physics);
+    });
+
     stopwatch::time("update_world.player", || {
       let mut updates = Vec::new();
+      let mut landings = Vec::new();
 
       for (_, player) in server.players.lock().unwrap().iter_mut() {
         let (bounds, collisions) = player.update(server, request_block);
+        if let Some(speed) = player.landing_speed.take() {
+          landings.push((player.entity_id, speed));
+        }
+        if entered_region(server, entity_regions::Entity::Player(player.entity_id), &player.position) {
+          request_block(update_gaia::Message::SpawnTraders(player.position));
+        }
         updates.push(protocol::ServerToClient::UpdatePlayer(player.entity_id, bounds));
         updates.extend(
           collisions.into_iter()
@@ -41,85 +82,338 @@ pub fn update_world<RequestBlock>(
         );
       }
 
-      let mut clients = server.clients.lock().unwrap();
-      for (_, client) in &mut *clients {
+      for (_, client) in server.clients.lock().unwrap().iter_mut() {
         for update in &updates {
           client.send(update.clone());
         }
       }
+
+      for (player_id, speed) in landings {
+        health::landed(server, player_id, speed);
+      }
+    });
+
+    stopwatch::time("update_world.push_terrain", || {
+      push_terrain(server, request_block);
     });
 
     stopwatch::time("update_world.mobs", || {
+      let interest = interest::new(server);
       for (_, mob) in server.mobs.lock().unwrap().iter_mut() {
-        let position =
-          Point3::new(
-            mob.position.x as i32,
-            mob.position.y as i32,
-            mob.position.z as i32,
-          );
-
-        let owner_id = mob.owner_id;
-        for (position, load_type) in mob.surroundings_loader.updates(&position) {
-          load_placeholders(
-            owner_id,
-            server,
-            request_block,
-            &voxel::bounds::new(position.x, position.y, position.z, 0),
-            load_type,
-          )
+        // Mobs far from every player stand still until someone comes near. Meanwhile they let go of
+        // the terrain around them, so physics only holds collision bounds where things can move.
+        let near_player = {
+          let entity_regions = server.entity_regions.lock().unwrap();
+          entity_regions.region_of(&entity_regions::Entity::Mob(mob.entity_id))
+            .map(|region| entity_regions.player_near(&region, MOB_AI_RADIUS))
+            .unwrap_or(true)
+        };
+        if near_player {
+          mob.dormant = false;
+          move_mob(server, request_block, mob);
+        } else if !mob.dormant {
+          fall_asleep(server, mob);
         }
 
-        {
-          let behavior = mob.behavior;
-          (behavior)(server, mob);
+        entered_region(server, entity_regions::Entity::Mob(mob.entity_id), &mob.position);
+
+        let bounds = *server.physics.lock().unwrap().get_bounds(mob.body.id).unwrap();
+        let transform =
+          protocol::MobTransform {
+            position : Point3::from_vec((bounds.min.to_vec() + bounds.max.to_vec()) * 0.5),
+            yaw      : mob.yaw,
+            velocity : mob.speed * server::UPDATES_PER_SECOND as f32,
+            size     : bounds.max - bounds.min,
+          };
+        let radius = transform.size.magnitude() / 2.0;
+        for (&client_id, client) in server.clients.lock().unwrap().iter_mut() {
+          if interest.sees(client_id, &transform.position, radius) {
+            client.send(protocol::ServerToClient::UpdateMob(mob.entity_id, transform));
+          }
         }
+      }
+    });
 
-        mob.speed = mob.speed + -Vector3::new(0.0, 0.1, 0.0 as f32);
+    stopwatch::time("update_world.entities", || {
+      components::simulate(server);
+    });
 
-        // TODO: This logic is dumb (isolating along components shouldn't be a thing). Change it.
-        let delta_p = mob.speed;
-        if delta_p.x != 0.0 {
-          translate_mob(server, mob, &Vector3::new(delta_p.x, 0.0, 0.0));
-        }
-        if delta_p.y != 0.0 {
-          translate_mob(server, mob, &Vector3::new(0.0, delta_p.y, 0.0));
+    stopwatch::time("update_world.projectiles", || {
+      projectiles::update(server, tick);
+    });
+
+    stopwatch::time("update_world.items", || {
+      items::update(server, tick);
+    });
+
+    stopwatch::time("update_world.bosses", || {
+      boss::update(server, tick);
+    });
+
+    stopwatch::time("update_world.spawner", || {
+      spawner::update(server, tick);
+    });
+
+    stopwatch::time("update_world.block_updates", || {
+      let pending = server.block_updates.lock().unwrap().take(block_updates::MAX_UPDATES_PER_TICK);
+      let mut falling = Vec::new();
+      let mut changes = fnv_map::new();
+      let mut deferred = Vec::new();
+      {
+        let voxels = server.terrain_loader.terrain.voxels.lock().unwrap();
+        let orientations = server.orientations.lock().unwrap();
+        let mut evaluated = fnv_set::new();
+        for bounds in &pending {
+          if let Some(material) = block_updates::update(&voxels, bounds) {
+            falling.push((*bounds, material));
+          }
+          let mut edits = circuits::update(&voxels, &orientations.facings, &mut evaluated, bounds);
+          edits.extend(fluid::update(&voxels, bounds).map(|voxel| (*bounds, voxel)));
+          // Edits were computed against this tick's voxels, so anything touching an earlier edit waits for the next tick.
+          if edits.iter().any(|&(ref bounds, _)| changes.contains_key(bounds)) {
+            deferred.push(*bounds);
+          } else {
+            changes.extend(edits);
+          }
         }
-        if delta_p.z != 0.0 {
-          translate_mob(server, mob, &Vector3::new(0.0, 0.0, delta_p.z));
+      }
+      if !deferred.is_empty() {
+        let mut block_updates = server.block_updates.lock().unwrap();
+        for bounds in &deferred {
+          block_updates.queue(bounds);
         }
       }
+
+      for &(bounds, _) in &falling {
+        changes.insert(bounds, voxel::Volume(voxel::Material::Empty));
+      }
+      if !changes.is_empty() {
+        update_gaia::set_voxels(server, changes.into_iter().collect());
+      }
+
+      let mut falling_blocks = server.falling_blocks.lock().unwrap();
+      for (bounds, material) in falling {
+        let entity_id = server.mob_allocator.lock().unwrap().allocate();
+        falling_blocks.push(falling_block::new(entity_id, bounds, material));
+      }
     });
 
-    server.sun.lock().unwrap().update().map(|fraction| {
+    stopwatch::time("update_world.pressure_plates", || {
+      update_pressure_plates(server);
+    });
+
+    stopwatch::time("update_world.falling_blocks", || {
+      let mut updates = Vec::new();
+      let mut landed = Vec::new();
+      {
+        let voxels = server.terrain_loader.terrain.voxels.lock().unwrap();
+        let mut falling_blocks = server.falling_blocks.lock().unwrap();
+        let mut i = 0;
+        while i < falling_blocks.len() {
+          match falling_blocks[i].update(&voxels) {
+            None => {
+              updates.push(protocol::ServerToClient::UpdateMob(falling_blocks[i].entity_id, falling_blocks[i].transform()));
+              i += 1;
+            },
+            Some(bounds) => {
+              let block = falling_blocks.swap_remove(i);
+              updates.push(protocol::ServerToClient::RemoveMob(block.entity_id));
+              landed.push((bounds, voxel::Volume(block.material)));
+            },
+          }
+        }
+      }
+
       for (_, client) in server.clients.lock().unwrap().iter_mut() {
-        client.send(protocol::ServerToClient::UpdateSun(fraction));
+        for update in &updates {
+          client.send(update.clone());
+        }
+      }
+      if !landed.is_empty() {
+        update_gaia::set_voxels(server, landed);
       }
     });
+
+    {
+      let mut sun = server.sun.lock().unwrap();
+      let was_night = sun.is_night();
+      sun.update().map(|fraction| {
+        for (_, client) in server.clients.lock().unwrap().iter_mut() {
+          client.send(protocol::ServerToClient::UpdateSun(fraction));
+        }
+      });
+      if sun.is_night() && !was_night {
+        let now = time::precise_time_ns();
+        let mut queue = server.hints.lock().unwrap();
+        for (&client_id, _) in server.clients.lock().unwrap().iter() {
+          queue.happened(client_id, hints::Event::Nightfall, now);
+        }
+      }
+    }
+
+    let due = server.hints.lock().unwrap().due(time::precise_time_ns());
+    if !due.is_empty() {
+      let mut clients = server.clients.lock().unwrap();
+      for (client_id, text) in due {
+        if let Some(client) = clients.get_mut(&client_id) {
+          client.send(protocol::ServerToClient::Message(protocol::MessageKind::Hint, String::from(text)));
+        }
+      }
+    }
+
+    disconnect::check(server);
   });
 }
 
-fn translate_mob(
+/// Queue up terrain to send to clients around where their players are headed.
+fn push_terrain<RequestBlock>(
   server: &server::T,
-  mob: &mut mob::Mob,
-  delta_p: &Vector3<f32>,
-) {
-  let bounds;
+  request_block: &mut RequestBlock,
+) where
+  RequestBlock: FnMut(update_gaia::Message),
+{
+  let mut terrain_push = server.terrain_push.lock().unwrap();
+  if !terrain_push.enabled {
+    return
+  }
+  let max_distance = server.view_distance.lock().unwrap().current();
+  let players = server.players.lock().unwrap();
+  let client_ids: Vec<_> = players.values().map(|player| player.client_id).collect();
+  terrain_push.retain_clients(&client_ids);
+  for player in players.values() {
+    let chunks = terrain_push.voxels_to_push(player.client_id, &player.position, &player.speed, max_distance);
+    for voxels in chunks {
+      request_block(
+        update_gaia::Message::Load(
+          time::precise_time_ns(),
+          voxels,
+          update_gaia::LoadDestination::Pushed(player.client_id),
+        )
+      );
+    }
+  }
+}
+
+/// Press the pressure plates that players and mobs are standing on, and release the rest.
+fn update_pressure_plates(server: &server::T) {
+  let mut ids: Vec<_> = server.players.lock().unwrap().values().map(|player| player.body.id).collect();
+  ids.extend(server.mobs.lock().unwrap().values().map(|mob| mob.body.id));
+
+  let mut standing_on = fnv_set::new();
   {
-    let mut physics = server.physics.lock().unwrap();
-    if physics.translate_misc(mob.physics_id, *delta_p).is_some() {
-      mob.speed += delta_p.neg();
-      return;
-    } else {
-      bounds = *physics.get_bounds(mob.physics_id).unwrap();
+    let physics = server.physics.lock().unwrap();
+    for id in ids {
+      let bounds = physics.get_bounds(id).unwrap();
+      let x = (bounds.min.x + bounds.max.x) / 2.0;
+      let z = (bounds.min.z + bounds.max.z) / 2.0;
+      let below = bounds.min.y - 0.5;
+      standing_on.insert(voxel::bounds::new(x.floor() as i32, below.floor() as i32, z.floor() as i32, 0));
     }
   }
 
-  mob.position += *delta_p;
+  let mut changes = Vec::new();
+  {
+    let voxels = server.terrain_loader.terrain.voxels.lock().unwrap();
+    let mut circuits = server.circuits.lock().unwrap();
+    for bounds in &standing_on {
+      if let Some(&voxel) = voxels.get(bounds) {
+        if circuits::material_of(&voxel) == voxel::Material::PressurePlate {
+          changes.push((*bounds, circuits::with_material(voxel, voxel::Material::PoweredPressurePlate)));
+        }
+      }
+    }
+    for bounds in circuits.pressed.difference(&standing_on) {
+      if let Some(&voxel) = voxels.get(bounds) {
+        if circuits::material_of(&voxel) == voxel::Material::PoweredPressurePlate {
+          changes.push((*bounds, circuits::with_material(voxel, voxel::Material::PressurePlate)));
+        }
+      }
+    }
+    circuits.pressed = standing_on;
+  }
+
+  if !changes.is_empty() {
+    update_gaia::set_voxels(server, changes);
+  }
+}
 
-  for (_, client) in server.clients.lock().unwrap().iter_mut() {
-    client.send(
-      protocol::ServerToClient::UpdateMob(mob.entity_id, bounds),
+/// Track an entity's region, and have any saved edits there loaded when it arrives somewhere new.
+/// The loading happens on the I/O thread, so a slow disk can't hold up the tick.
+/// Returns whether the entity changed regions.
+fn entered_region(server: &server::T, entity: entity_regions::Entity, position: &Point3<f32>) -> bool {
+  let region = server.entity_regions.lock().unwrap().moved(entity, position);
+  if let Some(region) = region {
+    server.world_save.request(world_save::Request::Load(region));
+  }
+  region.is_some()
+}
+
+/// Load the terrain around a mob, let it think, and move it.
+fn move_mob<RequestBlock>(
+  server: &server::T,
+  request_block: &mut RequestBlock,
+  mob: &mut mob::Mob,
+) where
+  RequestBlock: FnMut(update_gaia::Message),
+{
+  let position =
+    Point3::new(
+      mob.position.x as i32,
+      mob.position.y as i32,
+      mob.position.z as i32,
     );
+
+  let owner_id = mob.owner_id;
+  for (position, load_type) in mob.surroundings_loader.updates(&position) {
+    load_placeholders(
+      owner_id,
+      server,
+      request_block,
+      &voxel::bounds::new(position.x, position.y, position.z, 0),
+      load_type,
+    )
+  }
+
+  let behavior = mob.behavior;
+  (behavior)(server, mob);
+
+  mob.speed = mob.speed + -Vector3::new(0.0, 0.1, 0.0 as f32);
+
+  // TODO: This logic is dumb (isolating along components shouldn't be a thing). Change it.
+  let delta_p = mob.speed;
+  if delta_p.x != 0.0 {
+    translate_mob(server, mob, &Vector3::new(delta_p.x, 0.0, 0.0));
+  }
+  if delta_p.y != 0.0 {
+    translate_mob(server, mob, &Vector3::new(0.0, delta_p.y, 0.0));
+  }
+  if delta_p.z != 0.0 {
+    translate_mob(server, mob, &Vector3::new(0.0, 0.0, delta_p.z));
+  }
+
+  if mob.speed.x != 0.0 || mob.speed.z != 0.0 {
+    mob.yaw = mob.speed.x.atan2(mob.speed.z);
+  }
+}
+
+/// Let go of the terrain around a mob nobody's near, and stop it where it is.
+fn fall_asleep(server: &server::T, mob: &mut mob::Mob) {
+  server.terrain_loader.unload_owner(&server.physics, mob.owner_id);
+  // Start over, so everything's loaded again when it wakes.
+  mob.surroundings_loader = surroundings_loader::new(mob::LOAD_DISTANCE, Vec::new());
+  mob.speed = Vector3::new(0.0, 0.0, 0.0);
+  mob.dormant = true;
+}
+
+fn translate_mob(
+  server: &server::T,
+  mob: &mut mob::Mob,
+  delta_p: &Vector3<f32>,
+) {
+  let moved = server.physics.lock().unwrap().move_body(&mob.body, *delta_p);
+  mob.position += moved.shift;
+  if moved.blocked {
+    mob.speed += delta_p.neg();
   }
 }
 
@@ -0,0 +1,83 @@
+//! How far out, in chunks, clients may load terrain. It's cut back when the server gets busy, either
+//! because ticks are running long or because there are many players to serve, and grows back as
+//! things calm down. A lone player on an idle server gets the full distance.
+
+use std;
+
+/// The most the server ever allows. Clients have their own limits too, usually lower.
+const MAX_DISTANCE: u32 = 80;
+/// The least the server ever allows, no matter how busy it is.
+const MIN_DISTANCE: u32 = 16;
+/// How much the distance changes by at a time.
+const STEP: u32 = 8;
+
+/// Up to this many players get the full distance. Past it, the distance shrinks so that the total
+/// area served stays about the same.
+const FULL_DISTANCE_PLAYERS: usize = 4;
+
+/// Ticks averaging longer than this, in ns, shrink the distance.
+const SLOW_TICK_NS: f32 = 20_000_000.0;
+/// Ticks averaging shorter than this, in ns, let it grow back.
+const FAST_TICK_NS: f32 = 8_000_000.0;
+/// How much each tick counts towards the running average of tick times.
+const TICK_WEIGHT: f32 = 0.05;
+
+/// How long to wait after a change for it to take effect before changing again, in ns.
+const SETTLE_NS: u64 = 5_000_000_000;
+
+#[allow(missing_docs)]
+pub struct T {
+  /// The distance clients are currently allowed.
+  current        : u32,
+  /// Running average of how long ticks take, in ns.
+  average_tick   : f32,
+  /// When `current` last changed, in ns.
+  last_change_ns : u64,
+}
+
+#[allow(missing_docs)]
+pub fn new() -> T {
+  T {
+    current        : MAX_DISTANCE,
+    average_tick   : 0.0,
+    last_change_ns : 0,
+  }
+}
+
+/// The farthest distance that can be served to `players` players at once.
+fn ceiling(players: usize) -> u32 {
+  if players <= FULL_DISTANCE_PLAYERS {
+    return MAX_DISTANCE
+  }
+  let scale = (FULL_DISTANCE_PLAYERS as f32 / players as f32).sqrt();
+  std::cmp::max(MIN_DISTANCE, (MAX_DISTANCE as f32 * scale) as u32)
+}
+
+impl T {
+  #[allow(missing_docs)]
+  pub fn current(&self) -> u32 {
+    self.current
+  }
+
+  /// Account for a tick that took `tick_ns` with `players` players on. Returns the new distance if it changed.
+  pub fn update(&mut self, tick_ns: u64, players: usize, now_ns: u64) -> Option<u32> {
+    self.average_tick = self.average_tick * (1.0 - TICK_WEIGHT) + tick_ns as f32 * TICK_WEIGHT;
+
+    let ceiling = ceiling(players);
+    let mut distance = std::cmp::min(self.current, ceiling);
+    if now_ns.saturating_sub(self.last_change_ns) >= SETTLE_NS {
+      if self.average_tick > SLOW_TICK_NS {
+        distance = std::cmp::max(MIN_DISTANCE, distance.saturating_sub(STEP));
+      } else if self.average_tick < FAST_TICK_NS {
+        distance = std::cmp::min(ceiling, distance + STEP);
+      }
+    }
+
+    if distance == self.current {
+      return None
+    }
+    self.current = distance;
+    self.last_change_ns = now_ns;
+    Some(distance)
+  }
+}
@@ -0,0 +1,859 @@
+//! Persist terrain to disk in region files, so the world survives server restarts.
+//! Region files are memory-mapped the first time a voxel inside them is needed, and each voxel is
+//! decoded only when it's asked for. Dirty regions are periodically flushed back to disk.
+//! Disk work the tick would otherwise wait on goes through a request queue, served by the I/O thread.
+//! Once saved, regions far from every player are dropped from memory until they're needed again.
+//! Saved voxels are checksummed. Corrupt ones are regenerated instead, and the bad data is copied
+//! into `quarantine/` for inspection.
+
+use bincode;
+use cgmath::{Point3};
+use collision::{Aabb3};
+use memmap;
+use std;
+use std::collections::VecDeque;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use time;
+
+use common::fnv_map;
+use common::fnv_set;
+use common::telemetry;
+use common::voxel;
+
+use terrain;
+
+/// lg of the width of a region, in world coordinates.
+pub const LG_REGION_WIDTH: i32 = 6;
+
+/// Regions further than this many regions from every player are dropped from memory once they're saved.
+/// It's a little past the furthest any client is allowed to load terrain.
+const KEEP_RADIUS: i32 = 12;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+/// Position of a region, in units of region width.
+pub struct Region(Point3<i32>);
+
+impl Region {
+  /// The lowest corner of this region, in world coordinates.
+  pub fn low_corner(&self) -> Point3<i32> {
+    let Region(p) = *self;
+    Point3::new(p.x << LG_REGION_WIDTH, p.y << LG_REGION_WIDTH, p.z << LG_REGION_WIDTH)
+  }
+
+  /// The distance to another region, in regions, along whichever axis it's furthest.
+  pub fn distance(&self, other: &Region) -> i32 {
+    let (&Region(p), &Region(q)) = (self, other);
+    std::cmp::max(std::cmp::max((p.x - q.x).abs(), (p.y - q.y).abs()), (p.z - q.z).abs())
+  }
+}
+
+/// The region containing a voxel.
+pub fn region_containing(bounds: &voxel::bounds::T) -> Region {
+  let world = |x: i32| {
+    if bounds.lg_size < 0 {
+      x >> -bounds.lg_size
+    } else {
+      x << bounds.lg_size
+    }
+  };
+  Region(
+    Point3::new(
+      world(bounds.x) >> LG_REGION_WIDTH,
+      world(bounds.y) >> LG_REGION_WIDTH,
+      world(bounds.z) >> LG_REGION_WIDTH,
+    )
+  )
+}
+
+/// The region containing a point in world space.
+pub fn region_at(p: &Point3<f32>) -> Region {
+  Region(
+    Point3::new(
+      (p.x.floor() as i32) >> LG_REGION_WIDTH,
+      (p.y.floor() as i32) >> LG_REGION_WIDTH,
+      (p.z.floor() as i32) >> LG_REGION_WIDTH,
+    )
+  )
+}
+
+/// The cube of regions within `radius` regions of `center`, including `center`.
+pub fn regions_around(center: &Region, radius: i32) -> Vec<Region> {
+  let &Region(c) = center;
+  let mut regions = Vec::new();
+  for x in c.x - radius .. c.x + radius + 1 {
+  for y in c.y - radius .. c.y + radius + 1 {
+  for z in c.z - radius .. c.z + radius + 1 {
+    regions.push(Region(Point3::new(x, y, z)));
+  }}}
+  regions
+}
+
+/// All the regions intersecting some world-space bounds.
+pub fn regions_in(bounds: &Aabb3<i32>) -> Vec<Region> {
+  let mut regions = Vec::new();
+  for x in (bounds.min.x >> LG_REGION_WIDTH) .. (bounds.max.x >> LG_REGION_WIDTH) + 1 {
+  for y in (bounds.min.y >> LG_REGION_WIDTH) .. (bounds.max.y >> LG_REGION_WIDTH) + 1 {
+  for z in (bounds.min.z >> LG_REGION_WIDTH) .. (bounds.max.z >> LG_REGION_WIDTH) + 1 {
+    regions.push(Region(Point3::new(x, y, z)));
+  }}}
+  regions
+}
+
+/// Marks the start of a region file, and its format version.
+const MAGIC: &'static [u8] = b"PFR2";
+/// Marks region files from before records were checksummed.
+const UNCHECKED_MAGIC: &'static [u8] = b"PFR1";
+/// The magic bytes, then the length of the index.
+const HEADER_LEN: usize = 4 + 8;
+
+/// Where each voxel's record is in a region file: its byte range, and its checksum if it has one.
+type Index = fnv_map::T<voxel::bounds::T, (usize, usize, Option<u64>)>;
+
+/// The contents of a region file. These are usually mapped straight from disk,
+/// but files converted from an older format live in memory until they're rewritten.
+enum Bytes {
+  Mapped(memmap::Mmap),
+  Owned(Vec<u8>),
+}
+
+impl Bytes {
+  fn as_slice(&self) -> &[u8] {
+    match *self {
+      Bytes::Mapped(ref mmap) => &mmap[..],
+      Bytes::Owned(ref bytes) => &bytes[..],
+    }
+  }
+}
+
+/// A region file: a header, an index of where each voxel's record is, then the records.
+/// Only the index is read up front; each voxel is decoded the first time it's asked for.
+struct RegionFile {
+  bytes : Bytes,
+  index : Index,
+}
+
+/// Whether some bytes claim to be a region file with an index, rather than one from before there was an index.
+fn has_header(bytes: &[u8]) -> bool {
+  bytes.len() >= MAGIC.len() && (&bytes[.. MAGIC.len()] == MAGIC || &bytes[.. MAGIC.len()] == UNCHECKED_MAGIC)
+}
+
+/// Read a region file's index. Also returns whether its records are checksummed.
+fn parse_index(bytes: &[u8]) -> Result<(Index, bool), String> {
+  if bytes.len() < HEADER_LEN || !has_header(bytes) {
+    return Err(String::from("missing region file header"))
+  }
+  let checked = &bytes[.. MAGIC.len()] == MAGIC;
+  let index_len: u64 = try!(bincode::deserialize(&bytes[MAGIC.len() .. HEADER_LEN]).map_err(|err| format!("{:?}", err)));
+  // Everything read off disk is checked before it's used to find anything, so a corrupt file is
+  // an error rather than a panic.
+  let file_len = bytes.len() as u64;
+  let records_start =
+    match (HEADER_LEN as u64).checked_add(index_len) {
+      Some(records_start) if records_start <= file_len => records_start as usize,
+      _ => return Err(String::from("truncated region file index")),
+    };
+  let index_bytes = &bytes[HEADER_LEN .. records_start];
+  let entries: Vec<(voxel::bounds::T, u64, u64, Option<u64>)> =
+    if checked {
+      let entries: Vec<(voxel::bounds::T, u64, u64, u64)> =
+        try!(bincode::deserialize(index_bytes).map_err(|err| format!("{:?}", err)));
+      entries.into_iter().map(|(bounds, offset, len, checksum)| (bounds, offset, len, Some(checksum))).collect()
+    } else {
+      let entries: Vec<(voxel::bounds::T, u64, u64)> =
+        try!(bincode::deserialize(index_bytes).map_err(|err| format!("{:?}", err)));
+      entries.into_iter().map(|(bounds, offset, len)| (bounds, offset, len, None)).collect()
+    };
+
+  let mut index = fnv_map::new();
+  for (bounds, offset, len, checksum) in entries {
+    let start = (records_start as u64).checked_add(offset);
+    let end = start.and_then(|start| start.checked_add(len));
+    match (start, end) {
+      (Some(start), Some(end)) if end <= file_len => {
+        index.insert(bounds, (start as usize, end as usize, checksum));
+      },
+      _ => return Err(format!("truncated record for {:?}", bounds)),
+    }
+  }
+  Ok((index, checked))
+}
+
+/// Lay out a region file from already-serialized voxel records.
+fn encode(records: &[(voxel::bounds::T, Vec<u8>)]) -> Result<Vec<u8>, String> {
+  let mut entries = Vec::with_capacity(records.len());
+  let mut offset = 0;
+  for &(bounds, ref record) in records {
+    entries.push((bounds, offset as u64, record.len() as u64, voxel::checksum_bytes(record)));
+    offset += record.len();
+  }
+  let index = try!(bincode::serialize(&entries, bincode::Infinite).map_err(|err| format!("{:?}", err)));
+  let index_len = try!(bincode::serialize(&(index.len() as u64), bincode::Infinite).map_err(|err| format!("{:?}", err)));
+  assert_eq!(MAGIC.len() + index_len.len(), HEADER_LEN);
+
+  let mut bytes = Vec::with_capacity(HEADER_LEN + index.len() + offset);
+  bytes.extend_from_slice(MAGIC);
+  bytes.extend_from_slice(&index_len);
+  bytes.extend_from_slice(&index);
+  for &(_, ref record) in records {
+    bytes.extend_from_slice(record);
+  }
+  Ok(bytes)
+}
+
+fn serialize_voxel(voxel: &voxel::T) -> Result<Vec<u8>, String> {
+  bincode::serialize(voxel, bincode::Infinite).map_err(|err| format!("{:?}", err))
+}
+
+impl RegionFile {
+  fn new(bytes: Bytes) -> Result<RegionFile, String> {
+    let (index, _) = try!(parse_index(bytes.as_slice()));
+    Ok(RegionFile {
+      bytes : bytes,
+      index : index,
+    })
+  }
+
+  /// Region files from before there was an index were one big list of voxels.
+  fn of_legacy(bytes: &[u8]) -> Result<RegionFile, String> {
+    let voxels: Vec<(voxel::bounds::T, voxel::T)> =
+      try!(bincode::deserialize(bytes).map_err(|err| format!("{:?}", err)));
+    let mut records = Vec::with_capacity(voxels.len());
+    for (bounds, voxel) in voxels {
+      records.push((bounds, try!(serialize_voxel(&voxel))));
+    }
+    RegionFile::new(Bytes::Owned(try!(encode(&records))))
+  }
+
+  /// Map a region file into memory. Returns whether the file needs rewriting, along with the file.
+  fn open(path: &Path) -> Result<Option<(RegionFile, bool)>, String> {
+    let file =
+      match std::fs::File::open(path) {
+        Err(_) => return Ok(None),
+        Ok(file) => file,
+      };
+    let mmap = try!(unsafe { memmap::Mmap::map(&file) }.map_err(|err| format!("{:?}", err)));
+    if has_header(&mmap[..]) {
+      let (index, checked) = try!(parse_index(&mmap[..]));
+      let file =
+        RegionFile {
+          bytes : Bytes::Mapped(mmap),
+          index : index,
+        };
+      Ok(Some((file, !checked)))
+    } else {
+      RegionFile::of_legacy(&mmap[..]).map(|file| Some((file, true)))
+    }
+  }
+
+  fn record(&self, bounds: &voxel::bounds::T) -> Option<&[u8]> {
+    self.index.get(bounds).map(|&(start, end, _)| &self.bytes.as_slice()[start .. end])
+  }
+
+  /// The saved copy of a voxel, if there is one. It's an error if the record fails its checksum
+  /// or doesn't decode.
+  fn voxel(&self, bounds: &voxel::bounds::T) -> Result<Option<voxel::T>, String> {
+    let (start, end, checksum) =
+      match self.index.get(bounds) {
+        None => return Ok(None),
+        Some(&record) => record,
+      };
+    let record = &self.bytes.as_slice()[start .. end];
+    if let Some(checksum) = checksum {
+      if voxel::checksum_bytes(record) != checksum {
+        return Err(String::from("checksum mismatch"))
+      }
+    }
+    bincode::deserialize(record).map(Some).map_err(|err| format!("{:?}", err))
+  }
+}
+
+struct RegionState {
+  /// Every voxel in this region that exists in the terrain tree.
+  voxels       : fnv_set::T<voxel::bounds::T>,
+  /// Have any of `voxels` changed since the region was last written?
+  dirty        : bool,
+  /// Is a flush writing this region's file right now? If so, it stays in memory until that's done,
+  /// so it isn't read back from the old file, and no other flush writes it at the same time.
+  flushing     : bool,
+  /// What's on disk for this region, if anything.
+  file         : Option<RegionFile>,
+  /// Has everything in `file` been copied into the terrain tree?
+  fully_loaded : bool,
+  /// Voxels in `file` that turned out to be corrupt. They're regenerated instead, and left out
+  /// when the region is rewritten.
+  corrupt      : fnv_set::T<voxel::bounds::T>,
+}
+
+/// Disk work for the I/O thread.
+#[derive(Debug, Clone)]
+pub enum Request {
+  /// Read all of a region's saved voxels into the terrain.
+  Load(Region),
+  /// Write all the dirty regions to disk.
+  Flush,
+  /// Drop the saved regions far from all of these, which are the regions players are in.
+  UnloadFar(Vec<Region>),
+}
+
+#[allow(missing_docs)]
+pub struct T {
+  root            : PathBuf,
+  regions         : Mutex<fnv_map::T<Region, RegionState>>,
+  /// Outstanding requests, oldest first, with the time each was made.
+  requests        : Mutex<VecDeque<(u64, Request)>>,
+  /// How long each served request took from being made to being finished, in ns.
+  pub latencies   : Mutex<telemetry::Samples>,
+  /// How many corrupt region files and voxels have been found.
+  pub corruptions : Mutex<u64>,
+}
+
+/// Open (or create) a saved world in the directory `root`.
+/// No region files are touched until something inside them is needed.
+pub fn open(root: &Path) -> T {
+  if let Err(err) = std::fs::create_dir_all(root.join("regions")) {
+    warn!("Error creating world directory {:?}: {:?}", root, err);
+  }
+
+  T {
+    root        : root.to_owned(),
+    regions     : Mutex::new(fnv_map::new()),
+    requests    : Mutex::new(VecDeque::new()),
+    latencies   : Mutex::new(telemetry::new()),
+    corruptions : Mutex::new(0),
+  }
+}
+
+impl T {
+  fn seed_path(&self) -> PathBuf {
+    self.root.join("seed")
+  }
+
+  fn region_path(&self, region: &Region) -> PathBuf {
+    let &Region(p) = region;
+    self.root.join("regions").join(format!("region.{}.{}.{}.bin", p.x, p.y, p.z))
+  }
+
+  /// Where to put a copy of some corrupt data, so it's not lost.
+  fn quarantine_path(&self, name: &str) -> PathBuf {
+    let dir = self.root.join("quarantine");
+    if let Err(err) = std::fs::create_dir_all(&dir) {
+      warn!("Error creating quarantine directory {:?}: {:?}", dir, err);
+    }
+    dir.join(format!("{}.{}", name, time::get_time().sec))
+  }
+
+  /// Move a region file that can't be read out of the way, so it's not overwritten when the region is next saved.
+  fn quarantine_file(&self, region: &Region, err: &str) {
+    warn!("Region {:?} is corrupt ({}); regenerating it", region, err);
+    *self.corruptions.lock().unwrap() += 1;
+    let path = self.region_path(region);
+    let name = path.file_name().unwrap().to_string_lossy().into_owned();
+    let quarantine_path = self.quarantine_path(&name);
+    if let Err(err) = std::fs::rename(&path, &quarantine_path) {
+      warn!("Error quarantining {:?}: {:?}", path, err);
+    }
+  }
+
+  /// Keep a copy of a corrupt voxel's record, which will be dropped from its region file.
+  fn quarantine_record(&self, bounds: &voxel::bounds::T, record: &[u8], err: &str) {
+    warn!("Saved voxel {:?} is corrupt ({}); regenerating it", bounds, err);
+    *self.corruptions.lock().unwrap() += 1;
+    let name = format!("voxel.{}.{}.{}.{}.bin", bounds.x, bounds.y, bounds.z, bounds.lg_size);
+    let path = self.quarantine_path(&name);
+    if let Err(err) = std::fs::File::create(&path).and_then(|mut file| file.write_all(record)) {
+      warn!("Error quarantining {:?}: {:?}", path, err);
+    }
+  }
+
+  /// Read a saved voxel, noting it as corrupt if it is. Returns None if it's missing or corrupt.
+  fn read_saved(&self, state: &mut RegionState, bounds: &voxel::bounds::T) -> Option<voxel::T> {
+    if state.corrupt.contains(bounds) {
+      return None
+    }
+    let r =
+      match state.file {
+        None => return None,
+        Some(ref file) => file.voxel(bounds).map_err(|err| (err, file.record(bounds).unwrap().to_vec())),
+      };
+    match r {
+      Ok(voxel) => voxel,
+      Err((err, record)) => {
+        self.quarantine_record(bounds, &record, &err);
+        state.corrupt.insert(*bounds);
+        // Rewrite the region without the bad record.
+        state.dirty = true;
+        None
+      },
+    }
+  }
+
+  /// The seed this world was generated with, if it's been saved before.
+  pub fn load_seed(&self) -> Option<u64> {
+    let mut file =
+      match std::fs::File::open(self.seed_path()) {
+        Err(_) => return None,
+        Ok(file) => file,
+      };
+    let mut bytes = Vec::new();
+    if let Err(err) = file.read_to_end(&mut bytes) {
+      warn!("Error loading world seed: {:?}", err);
+      return None
+    }
+    // Worlds saved before seeds were widened to 64 bits have 32-bit seeds.
+    let r =
+      if bytes.len() == 4 {
+        bincode::deserialize::<u32>(&bytes).map(|seed| seed as u64)
+      } else {
+        bincode::deserialize::<u64>(&bytes)
+      };
+    match r {
+      Ok(seed) => Some(seed),
+      Err(err) => {
+        warn!("Error loading world seed: {:?}", err);
+        None
+      },
+    }
+  }
+
+  #[allow(missing_docs)]
+  pub fn save_seed(&self, seed: u64) {
+    let r =
+      std::fs::File::create(self.seed_path())
+      .map_err(|err| format!("{:?}", err))
+      .and_then(|mut file| {
+        bincode::serialize_into(&mut file, &seed, bincode::Infinite)
+        .map_err(|err| format!("{:?}", err))
+      });
+    if let Err(err) = r {
+      warn!("Error saving world seed: {}", err);
+    }
+  }
+
+  /// Queue up some disk work for the I/O thread.
+  pub fn request(&self, request: Request) {
+    self.requests.lock().unwrap().push_back((time::precise_time_ns(), request));
+  }
+
+  /// The number of requests waiting for the I/O thread.
+  pub fn pending_requests(&self) -> usize {
+    self.requests.lock().unwrap().len()
+  }
+
+  /// Serve the oldest outstanding request. Returns false if there weren't any.
+  pub fn serve_request(&self, terrain: &terrain::T) -> bool {
+    let (requested_at, request) =
+      match self.requests.lock().unwrap().pop_front() {
+        None => return false,
+        Some(request) => request,
+      };
+    match request {
+      Request::Load(region) => self.ensure_loaded(terrain, &region),
+      Request::Flush => self.flush(terrain),
+      Request::UnloadFar(players) => {
+        let unloaded = self.unload_far(terrain, &players);
+        debug!("Unloaded {} far regions", unloaded);
+      },
+    }
+    self.latencies.lock().unwrap().push(time::precise_time_ns() - requested_at);
+    true
+  }
+
+  /// The state of a region, mapping its file in if this is the first time it's been needed.
+  fn region_state<'a>(
+    &self,
+    regions : &'a mut fnv_map::T<Region, RegionState>,
+    region  : &Region,
+  ) -> &'a mut RegionState {
+    let path = self.region_path(region);
+    regions.entry(*region).or_insert_with(|| {
+      let (file, dirty) =
+        match RegionFile::open(&path) {
+          Ok(None) => (None, false),
+          Ok(Some((file, dirty))) => (Some(file), dirty),
+          Err(err) => {
+            self.quarantine_file(region, &err);
+            (None, false)
+          },
+        };
+      RegionState {
+        voxels       : fnv_set::new(),
+        dirty        : dirty,
+        flushing     : false,
+        file         : file,
+        fully_loaded : false,
+        corrupt      : fnv_set::new(),
+      }
+    })
+  }
+
+  /// Make sure all of a region's saved voxels have been read into the terrain,
+  /// e.g. before editing a whole area at once.
+  pub fn ensure_loaded(&self, terrain: &terrain::T, region: &Region) {
+    let mut corrupt = Vec::new();
+    {
+      let mut regions = self.regions.lock().unwrap();
+      let state = self.region_state(&mut regions, region);
+      if state.fully_loaded {
+        return
+      }
+      state.fully_loaded = true;
+
+      let saved: Vec<voxel::bounds::T> =
+        match state.file {
+          None => return,
+          Some(ref file) => file.index.keys().cloned().collect(),
+        };
+      let mut voxels = terrain.voxels.lock().unwrap();
+      for bounds in saved {
+        // Anything already in the tree is at least as new as what's on disk.
+        if state.voxels.contains(&bounds) {
+          continue
+        }
+        match self.read_saved(state, &bounds) {
+          Some(voxel) => {
+            voxels.get_mut_or_create(&bounds).data = Some(voxel);
+            state.voxels.insert(bounds);
+          },
+          None => corrupt.push(bounds),
+        }
+      }
+    }
+
+    for bounds in corrupt {
+      self.load(terrain, &bounds);
+    }
+  }
+
+  /// Note that a voxel has changed, so its region needs to be rewritten.
+  pub fn mark_dirty(&self, bounds: &voxel::bounds::T) {
+    let mut regions = self.regions.lock().unwrap();
+    let state = self.region_state(&mut regions, &region_containing(bounds));
+    state.voxels.insert(*bounds);
+    state.dirty = true;
+  }
+
+  /// Load a voxel, preferring the saved copy over generating a new one.
+  pub fn load(&self, terrain: &terrain::T, bounds: &voxel::bounds::T) -> voxel::T {
+    {
+      let mut regions = self.regions.lock().unwrap();
+      let state = self.region_state(&mut regions, &region_containing(bounds));
+      let mut voxels = terrain.voxels.lock().unwrap();
+      if let Some(voxel) = voxels.get(bounds) {
+        return *voxel
+      }
+      if let Some(voxel) = self.read_saved(state, bounds) {
+        voxels.get_mut_or_create(bounds).data = Some(voxel);
+        state.voxels.insert(*bounds);
+        return voxel
+      }
+    }
+
+    let voxel = terrain.load(bounds);
+    self.mark_dirty(bounds);
+    voxel
+  }
+
+  /// Write all the dirty regions to disk.
+  /// The regions are only locked while their contents are gathered, not while they're written.
+  pub fn flush(&self, terrain: &terrain::T) {
+    let mut dirty = Vec::new();
+    {
+      let mut regions = self.regions.lock().unwrap();
+      for (region, state) in regions.iter_mut() {
+        // A region another flush is still writing stays dirty, and is written next time.
+        if !state.dirty || state.flushing {
+          continue
+        }
+        match self.region_records(terrain, state) {
+          Ok(records) => {
+            state.dirty = false;
+            state.flushing = true;
+            dirty.push((*region, records));
+          },
+          Err(err) => warn!("Error saving region {:?}: {}", region, err),
+        }
+      }
+    }
+
+    let mut written = 0;
+    for (region, records) in dirty {
+      let path = self.region_path(&region);
+      let r =
+        encode(&records)
+        .and_then(|bytes| {
+          // Write next to the old file and move it into place, so the old mapping stays valid until we're done.
+          let tmp_path = path.with_extension("tmp");
+          try!(
+            std::fs::File::create(&tmp_path)
+            .and_then(|mut file| file.write_all(&bytes))
+            .and_then(|()| std::fs::rename(&tmp_path, &path))
+            .map_err(|err| format!("{:?}", err))
+          );
+          RegionFile::open(&path)
+        });
+      let mut regions = self.regions.lock().unwrap();
+      let state =
+        match regions.get_mut(&region) {
+          None => {
+            warn!("Region {:?} was dropped while it was being saved", region);
+            continue
+          },
+          Some(state) => state,
+        };
+      state.flushing = false;
+      match r {
+        Ok(file) => {
+          // Anything edited since the records were gathered keeps the region dirty.
+          state.file = file.map(|(file, _)| file);
+          // The corrupt records were left out of the new file.
+          state.corrupt.clear();
+          written += 1;
+        },
+        Err(err) => {
+          warn!("Error saving region {:?}: {}", region, err);
+          state.dirty = true;
+        },
+      }
+    }
+    debug!("Flushed {} regions", written);
+  }
+
+  /// Drop the regions that are far from every player out of memory: their voxels leave the terrain
+  /// tree and their files are unmapped. Anything needed again is read back from disk.
+  /// Dirty regions, and ones being flushed, are kept until they've been written. Returns how many
+  /// regions were dropped.
+  pub fn unload_far(&self, terrain: &terrain::T, players: &[Region]) -> usize {
+    let mut regions = self.regions.lock().unwrap();
+    let far: Vec<Region> =
+      regions.iter()
+      .filter(|&(region, state)| {
+        !state.dirty && !state.flushing && players.iter().all(|player| region.distance(player) > KEEP_RADIUS)
+      })
+      .map(|(region, _)| *region)
+      .collect();
+
+    let mut voxels = terrain.voxels.lock().unwrap();
+    for region in &far {
+      let state = regions.remove(region).unwrap();
+      for bounds in &state.voxels {
+        // TODO: Prune the emptied nodes out of the tree.
+        if voxels.get(bounds).is_some() {
+          voxels.get_mut_or_create(bounds).data = None;
+        }
+      }
+    }
+    far.len()
+  }
+
+  /// Everything that belongs in a region's file: voxels in the tree, plus anything saved that was never loaded.
+  fn region_records(
+    &self,
+    terrain : &terrain::T,
+    state   : &RegionState,
+  ) -> Result<Vec<(voxel::bounds::T, Vec<u8>)>, String> {
+    let mut records = Vec::new();
+    {
+      let voxels = terrain.voxels.lock().unwrap();
+      for bounds in &state.voxels {
+        if let Some(voxel) = voxels.get(bounds) {
+          records.push((*bounds, try!(serialize_voxel(voxel))));
+        }
+      }
+    }
+    if let Some(ref file) = state.file {
+      for bounds in file.index.keys() {
+        if !state.voxels.contains(bounds) && !state.corrupt.contains(bounds) {
+          records.push((*bounds, file.record(bounds).unwrap().to_vec()));
+        }
+      }
+    }
+    Ok(records)
+  }
+}
+
+#[cfg(test)]
+fn scratch_dir(name: &str) -> PathBuf {
+  let dir = std::env::temp_dir().join(format!("playform-world-save-{}-{}", name, time::precise_time_ns()));
+  std::fs::create_dir_all(&dir).unwrap();
+  dir
+}
+
+#[cfg(test)]
+fn scratch_terrain() -> terrain::T {
+  terrain::T::new(0, terrain::heightmap::Kind::Brownian, 0)
+}
+
+/// Put a voxel in the terrain by hand, as an edit would.
+#[cfg(test)]
+fn edit(world: &T, terrain: &terrain::T, bounds: &voxel::bounds::T, material: voxel::Material) {
+  world.load(terrain, bounds);
+  terrain.voxels.lock().unwrap().get_mut_or_create(bounds).data = Some(voxel::Volume(material));
+  world.mark_dirty(bounds);
+}
+
+#[cfg(test)]
+fn same(a: voxel::T, b: voxel::T) -> bool {
+  let bounds = voxel::bounds::new(0, 0, 0, 0);
+  voxel::checksum(&[(bounds, a)]) == voxel::checksum(&[(bounds, b)])
+}
+
+#[test]
+fn saved_voxels_survive_reopening() {
+  let dir = scratch_dir("round-trip");
+  let bounds = voxel::bounds::new(1, 2, 3, 0);
+  {
+    let world = open(&dir);
+    let terrain = scratch_terrain();
+    edit(&world, &terrain, &bounds, voxel::Material::Stone);
+    world.flush(&terrain);
+  }
+
+  let world = open(&dir);
+  assert!(same(world.load(&scratch_terrain(), &bounds), voxel::Volume(voxel::Material::Stone)));
+  std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn editing_keeps_the_rest_of_the_saved_region() {
+  let dir = scratch_dir("edit");
+  let first = voxel::bounds::new(1, 2, 3, 0);
+  let second = voxel::bounds::new(4, 5, 6, 0);
+  {
+    let world = open(&dir);
+    let terrain = scratch_terrain();
+    edit(&world, &terrain, &first, voxel::Material::Stone);
+    world.flush(&terrain);
+  }
+  {
+    // Only the second voxel is touched this time, so the first is only on disk.
+    let world = open(&dir);
+    let terrain = scratch_terrain();
+    edit(&world, &terrain, &second, voxel::Material::Sand);
+    world.flush(&terrain);
+  }
+
+  let world = open(&dir);
+  let terrain = scratch_terrain();
+  assert!(same(world.load(&terrain, &first), voxel::Volume(voxel::Material::Stone)));
+  assert!(same(world.load(&terrain, &second), voxel::Volume(voxel::Material::Sand)));
+  std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn flushed_regions_unload_and_come_back() {
+  let dir = scratch_dir("unload");
+  let world = open(&dir);
+  let terrain = scratch_terrain();
+  let bounds = voxel::bounds::new(1, 2, 3, 0);
+  edit(&world, &terrain, &bounds, voxel::Material::Stone);
+
+  // Unsaved edits aren't dropped.
+  assert_eq!(world.unload_far(&terrain, &[]), 0);
+
+  // Nor are regions a flush is in the middle of writing.
+  let set = |dirty, flushing| {
+    let mut regions = world.regions.lock().unwrap();
+    let state = regions.get_mut(&region_containing(&bounds)).unwrap();
+    state.dirty = dirty;
+    state.flushing = flushing;
+  };
+  set(false, true);
+  assert_eq!(world.unload_far(&terrain, &[]), 0);
+  set(true, false);
+
+  world.flush(&terrain);
+  assert_eq!(world.unload_far(&terrain, &[]), 1);
+  assert!(terrain.voxels.lock().unwrap().get(&bounds).is_none());
+
+  assert!(same(world.load(&terrain, &bounds), voxel::Volume(voxel::Material::Stone)));
+  std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn corrupt_records_are_quarantined_and_regenerated() {
+  let dir = scratch_dir("corrupt");
+  let bounds = voxel::bounds::new(1, 2, 3, 0);
+  let path = {
+    let world = open(&dir);
+    let terrain = scratch_terrain();
+    edit(&world, &terrain, &bounds, voxel::Material::Stone);
+    world.flush(&terrain);
+    world.region_path(&region_containing(&bounds))
+  };
+
+  // The voxel's record is the last thing in the file.
+  let mut bytes = Vec::new();
+  std::fs::File::open(&path).unwrap().read_to_end(&mut bytes).unwrap();
+  let last = bytes.len() - 1;
+  bytes[last] ^= 0xff;
+  std::fs::File::create(&path).unwrap().write_all(&bytes).unwrap();
+
+  let world = open(&dir);
+  let regenerated = world.load(&scratch_terrain(), &bounds);
+  assert!(same(regenerated, scratch_terrain().load(&bounds)));
+  assert_eq!(*world.corruptions.lock().unwrap(), 1);
+  let quarantined: Vec<String> =
+    std::fs::read_dir(dir.join("quarantine")).unwrap()
+    .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+    .collect();
+  assert_eq!(quarantined.len(), 1);
+  assert!(quarantined[0].starts_with("voxel.1.2.3.0.bin"));
+  std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn corrupt_indexes_are_errors() {
+  let bounds = voxel::bounds::new(1, 2, 3, 0);
+  let record = serialize_voxel(&voxel::Volume(voxel::Material::Stone)).unwrap();
+  let file = |index_len: Option<u64>, offset: u64, len: u64| {
+    let entries = vec!((bounds, offset, len, voxel::checksum_bytes(&record)));
+    let index = bincode::serialize(&entries, bincode::Infinite).unwrap();
+    let index_len = index_len.unwrap_or(index.len() as u64);
+    let mut bytes = MAGIC.to_vec();
+    bytes.extend_from_slice(&bincode::serialize(&index_len, bincode::Infinite).unwrap());
+    bytes.extend_from_slice(&index);
+    bytes.extend_from_slice(&record);
+    bytes
+  };
+  assert!(parse_index(&file(None, 0, record.len() as u64)).is_ok());
+  assert!(parse_index(&file(Some(std::u64::MAX), 0, record.len() as u64)).is_err());
+  assert!(parse_index(&file(Some(1 << 20), 0, record.len() as u64)).is_err());
+  assert!(parse_index(&file(None, std::u64::MAX, record.len() as u64)).is_err());
+  assert!(parse_index(&file(None, 1, std::u64::MAX)).is_err());
+  assert!(parse_index(&file(None, 1, record.len() as u64)).is_err());
+}
+
+#[test]
+fn regions_with_corrupt_indexes_are_quarantined() {
+  let dir = scratch_dir("corrupt-index");
+  let bounds = voxel::bounds::new(1, 2, 3, 0);
+  let path = {
+    let world = open(&dir);
+    let terrain = scratch_terrain();
+    edit(&world, &terrain, &bounds, voxel::Material::Stone);
+    world.flush(&terrain);
+    world.region_path(&region_containing(&bounds))
+  };
+
+  // Claim an index that runs off the end of the address space.
+  let mut bytes = Vec::new();
+  std::fs::File::open(&path).unwrap().read_to_end(&mut bytes).unwrap();
+  for byte in &mut bytes[MAGIC.len() .. HEADER_LEN] {
+    *byte = 0xff;
+  }
+  std::fs::File::create(&path).unwrap().write_all(&bytes).unwrap();
+
+  let world = open(&dir);
+  let regenerated = world.load(&scratch_terrain(), &bounds);
+  assert!(same(regenerated, scratch_terrain().load(&bounds)));
+  assert_eq!(*world.corruptions.lock().unwrap(), 1);
+  let quarantined: Vec<String> =
+    std::fs::read_dir(dir.join("quarantine")).unwrap()
+    .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+    .collect();
+  assert_eq!(quarantined.len(), 1);
+  assert!(quarantined[0].starts_with("region."));
+  std::fs::remove_dir_all(&dir).unwrap();
+}
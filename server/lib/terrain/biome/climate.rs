@@ -0,0 +1,178 @@
+//! Temperature and humidity, which choose the biome for each column of the world.
+
+use noise::{Seed, Brownian2, perlin2};
+
+use common::voxel;
+
+use tree;
+
+#[allow(missing_docs)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+  Desert,
+  Forest,
+  Tundra,
+  Plains,
+}
+
+const ALL: [Kind; 4] = [Kind::Desert, Kind::Forest, Kind::Tundra, Kind::Plains];
+
+/// Biome-specific parameters for terrain generation.
+#[derive(Debug, Clone, Copy)]
+pub struct Params {
+  /// Multiplier for the height of the landscape.
+  pub height_scale : f32,
+  /// Multiplier for the amplitude of the 3D features on top of the heightmap.
+  pub roughness    : f32,
+  /// Material of the ground surface.
+  pub surface      : voxel::Material,
+  /// Multiplier for the size of trees.
+  pub tree_scale   : f32,
+  /// Chance that a patch of ground grows a tree.
+  pub tree_density : f32,
+  /// Which kind of tree grows here.
+  pub tree_species : tree::Species,
+}
+
+impl Kind {
+  /// This biome's position in (temperature, humidity) space.
+  fn center(self) -> (f32, f32) {
+    match self {
+      Kind::Desert => ( 0.5, -0.6),
+      Kind::Forest => ( 0.4,  0.6),
+      Kind::Tundra => (-0.7,  0.0),
+      Kind::Plains => ( 0.1,  0.0),
+    }
+  }
+
+  #[allow(missing_docs)]
+  pub fn params(self) -> Params {
+    match self {
+      Kind::Desert =>
+        Params {
+          height_scale : 0.5,
+          roughness    : 0.5,
+          surface      : voxel::Material::Sand,
+          tree_scale   : 0.5,
+          tree_density : 0.05,
+          tree_species : tree::Species::Acacia,
+        },
+      Kind::Forest =>
+        Params {
+          height_scale : 1.0,
+          roughness    : 1.0,
+          surface      : voxel::Material::Terrain,
+          tree_scale   : 1.25,
+          tree_density : 0.6,
+          tree_species : tree::Species::Oak,
+        },
+      Kind::Tundra =>
+        Params {
+          height_scale : 1.5,
+          roughness    : 1.25,
+          surface      : voxel::Material::Snow,
+          tree_scale   : 0.75,
+          tree_density : 0.25,
+          tree_species : tree::Species::Pine,
+        },
+      Kind::Plains =>
+        Params {
+          height_scale : 0.5,
+          roughness    : 0.25,
+          surface      : voxel::Material::Terrain,
+          tree_scale   : 1.0,
+          tree_density : 0.1,
+          tree_species : tree::Species::Oak,
+        },
+    }
+  }
+}
+
+#[allow(missing_docs)]
+pub struct T {
+  temperature: Brownian2<f64, fn (&Seed, &[f64; 2]) -> f64>,
+  humidity: Brownian2<f64, fn (&Seed, &[f64; 2]) -> f64>,
+  seed: Seed,
+}
+
+#[allow(missing_docs)]
+pub fn new(seed: Seed) -> T {
+  let perlin2: fn(&Seed, &[f64; 2]) -> f64 = perlin2;
+  T {
+    seed: seed,
+    temperature:
+      Brownian2::new(perlin2, 3)
+      .frequency(1.0 / 512.0)
+    ,
+    humidity:
+      Brownian2::new(perlin2, 3)
+      .frequency(1.0 / 384.0)
+    ,
+  }
+}
+
+impl T {
+  /// The (temperature, humidity) of a column, each roughly in [-1, 1].
+  pub fn climate(&self, x: f32, z: f32) -> (f32, f32) {
+    // Offset the samples so temperature and humidity aren't correlated.
+    let temperature = self.temperature.apply(&self.seed, &[x as f64 + 1024.0, z as f64]);
+    let humidity = self.humidity.apply(&self.seed, &[x as f64, z as f64 - 1024.0]);
+    (temperature as f32, humidity as f32)
+  }
+
+  /// How strongly each biome applies to a column. The weights sum to 1.
+  fn weights(&self, x: f32, z: f32) -> [(Kind, f32); 4] {
+    let (t, h) = self.climate(x, z);
+    let mut weights = [(Kind::Desert, 0.0); 4];
+    let mut total = 0.0;
+    for (i, &kind) in ALL.iter().enumerate() {
+      let (ct, ch) = kind.center();
+      let d2 = (t - ct) * (t - ct) + (h - ch) * (h - ch);
+      // Inverse-distance weighting, sharpened so biomes have mostly-uniform interiors.
+      let w = 1.0 / ((d2 + 0.001) * (d2 + 0.001));
+      weights[i] = (kind, w);
+      total += w;
+    }
+    for w in weights.iter_mut() {
+      w.1 /= total;
+    }
+    weights
+  }
+
+  /// The dominant biome of a column.
+  pub fn kind(&self, x: f32, z: f32) -> Kind {
+    dominant(&self.weights(x, z))
+  }
+
+  /// Biome parameters for a column, blended across biome borders so the landscape stays continuous.
+  pub fn params(&self, x: f32, z: f32) -> Params {
+    let weights = self.weights(x, z);
+    let mut params =
+      Params {
+        height_scale : 0.0,
+        roughness    : 0.0,
+        surface      : dominant(&weights).params().surface,
+        tree_scale   : 0.0,
+        tree_density : 0.0,
+        tree_species : dominant(&weights).params().tree_species,
+      };
+    for &(kind, w) in weights.iter() {
+      let p = kind.params();
+      params.height_scale += w * p.height_scale;
+      params.roughness    += w * p.roughness;
+      params.tree_scale   += w * p.tree_scale;
+      params.tree_density += w * p.tree_density;
+    }
+    params
+  }
+}
+
+fn dominant(weights: &[(Kind, f32); 4]) -> Kind {
+  let mut best = weights[0];
+  for &w in weights.iter().skip(1) {
+    if w.1 > best.1 {
+      best = w;
+    }
+  }
+  best.0
+}
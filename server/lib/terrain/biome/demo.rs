@@ -1,30 +1,70 @@
-//! Grass, hilly biome
+//! The default world: grassy hills and stone mountains, varied by biome.
 
 use cgmath::{Point3, Vector3, InnerSpace};
 use noise::{Seed, Brownian2, Brownian3, perlin2, perlin3};
+use std::cell::RefCell;
 
 use common::voxel;
 
+use biome::climate;
+use erosion;
+use heightmap::HeightmapSource;
+use hydrology;
+use ores;
+use structures;
+use tree_placer;
+
+/// How far below the surface the ground turns to stone.
+const SOIL_DEPTH: f32 = 4.0;
+/// Thickness of the band under the soil where gravel beds can form.
+const GRAVEL_DEPTH: f32 = 3.0;
+/// Noise level above which the gravel band is gravel rather than stone.
+const GRAVEL_THRESHOLD: f64 = 0.3;
+/// Caves are squeezed shut within this distance of the surface, so only the occasional one breaks through.
+const CAVE_SURFACE_MARGIN: f32 = 6.0;
+/// Half-width of the tunnels, in noise units.
+const TUNNEL_WIDTH: f64 = 0.06;
+/// Noise level above which open caverns are carved.
+const CAVERN_THRESHOLD: f64 = 0.55;
+/// Converts noise units into (very roughly) world-space distances.
+const CAVE_DENSITY_SCALE: f32 = 16.0;
+
 #[allow(missing_docs)]
 pub struct T {
-  height: Brownian2<f64, fn (&Seed, &[f64; 2]) -> f64>,
+  height: Box<HeightmapSource>,
   mountains: Brownian2<f64, fn (&Seed, &[f64; 2]) -> f64>,
   features: Brownian3<f64, fn (&Seed, &[f64; 3]) -> f64>,
+  tunnels: Brownian3<f64, fn (&Seed, &[f64; 3]) -> f64>,
+  caverns: Brownian3<f64, fn (&Seed, &[f64; 3]) -> f64>,
+  ores: ores::T,
+  erosion: RefCell<erosion::T>,
+  hydrology: RefCell<hydrology::T>,
+  climate: climate::T,
+  structures: RefCell<structures::T>,
+  trees: RefCell<tree_placer::T>,
   seed: Seed,
 }
 
 #[allow(missing_docs)]
-pub fn new(seed: Seed) -> T {
+pub fn new(
+  seed       : Seed,
+  height     : Box<HeightmapSource>,
+  erosion    : erosion::T,
+  climate    : climate::T,
+  structures : structures::T,
+  trees      : tree_placer::T,
+) -> T {
   let perlin2: fn(&Seed, &[f64; 2]) -> f64 = perlin2;
   let perlin3: fn(&Seed, &[f64; 3]) -> f64 = perlin3;
   T {
     seed: seed,
-    height:
-      Brownian2::new(perlin2, 5)
-      .frequency(1.0 / 4.0)
-      .persistence(2.0)
-      .lacunarity(1.0 / 2.0)
-    ,
+    ores: ores::new(),
+    erosion: RefCell::new(erosion),
+    hydrology: RefCell::new(hydrology::new()),
+    climate: climate,
+    structures: RefCell::new(structures),
+    trees: RefCell::new(trees),
+    height: height,
     mountains:
       Brownian2::new(perlin2, 3)
       .frequency(1.0 / 16.0)
@@ -37,27 +77,158 @@ pub fn new(seed: Seed) -> T {
       .persistence(8.0)
       .lacunarity(1.0 / 4.0)
     ,
+    tunnels:
+      Brownian3::new(perlin3, 2)
+      .frequency(1.0 / 64.0)
+    ,
+    caverns:
+      Brownian3::new(perlin3, 3)
+      .frequency(1.0 / 128.0)
+    ,
   }
 }
 
 impl T {
-  fn mat_density(&self, p: &Point3<f32>) -> (f32, voxel::Material) {
-    let height = self.height.apply(&self.seed, &[p.x as f64, p.z as f64]);
-    let height = height as f32;
-    let heightmap_density = height - p.y;
+  /// Density of the rock left after carving caves: negative inside a cave.
+  fn cave_density(&self, p: &Point3<f32>) -> f32 {
+    let sample = |noise: &Brownian3<f64, fn (&Seed, &[f64; 3]) -> f64>, offset: f64| {
+      noise.apply(&self.seed, &[p.x as f64 + offset, p.y as f64, p.z as f64 - offset])
+    };
+
+    // Tunnels run along the intersection of two noise isosurfaces.
+    let tunnel = f64::max(sample(&self.tunnels, 0.0).abs(), sample(&self.tunnels, 512.0).abs()) - TUNNEL_WIDTH;
+    let cavern = CAVERN_THRESHOLD - sample(&self.caverns, -512.0);
+
+    f64::min(tunnel, cavern) as f32 * CAVE_DENSITY_SCALE
+  }
+
+  /// The material of solid rock at a point `depth` below the surface: stone, or ore deep enough down.
+  fn rock(&self, depth: f32, p: &Point3<f32>) -> voxel::Material {
+    if depth < SOIL_DEPTH + GRAVEL_DEPTH {
+      return voxel::Material::Stone
+    }
+    self.ores.ore_at(&self.seed, p).unwrap_or(voxel::Material::Stone)
+  }
+
+  /// Heights of the hills and of the mountains in a column, as they come out of the noise.
+  fn uneroded_heights(&self, biome: &climate::Params, x: f32, z: f32) -> (f32, f32) {
+    let height = self.height.height(&self.seed, x as f64, z as f64);
+    let height = height as f32 * biome.height_scale;
+
+    let mountain_height = 16.0 * self.mountains.apply(&self.seed, &[x as f64 - 32.0, z as f64 - 10.0]) - 32.0;
+    (height, mountain_height as f32)
+  }
+
+  /// Heights of the hills and of the mountains in a column, after erosion but before rivers are carved into them.
+  fn heights(&self, biome: &climate::Params, x: f32, z: f32) -> (f32, f32) {
+    let (height, mountain_height) = self.uneroded_heights(biome, x, z);
+    let surface = |x: f32, z: f32| {
+      let (height, mountain_height) = self.uneroded_heights(&self.climate.params(x, z), x, z);
+      f32::max(height, mountain_height)
+    };
+    let change = self.erosion.borrow_mut().change_at(&surface, x, z);
+    (height + change, mountain_height + change)
+  }
+
+  /// The rivers and lakes in a column.
+  fn water(&self, x: f32, z: f32) -> hydrology::Water {
+    let height = |x: f32, z: f32| {
+      let (height, mountain_height) = self.heights(&self.climate.params(x, z), x, z);
+      f32::max(height, mountain_height)
+    };
+    self.hydrology.borrow_mut().at(&height, x, z)
+  }
+
+  /// The height and material at the top of a column, roughly: rivers and lakes are included, but not
+  /// caves, structures, trees or the finer 3D features. Cheap enough to sample across the whole horizon.
+  pub fn column(&self, x: f32, z: f32) -> (f32, voxel::Material) {
+    let biome = self.climate.params(x, z);
+    let water = self.water(x, z);
+    let (height, mountain_height) = self.heights(&biome, x, z);
+    let (ground, material) =
+      if mountain_height > height {
+        (mountain_height, voxel::Material::Stone)
+      } else {
+        (height, biome.surface)
+      };
+    let ground = ground - water.lowering;
 
-    let mountain_height = 16.0 * self.mountains.apply(&self.seed, &[p.x as f64 - 32.0, p.z as f64 - 10.0]) - 32.0;
-    let mountain_height = mountain_height as f32;
-    let mountain_heightmap_density = mountain_height - p.y;
+    let water_level = water.level.unwrap_or(voxel::SEA_LEVEL as f32);
+    if ground < water_level {
+      (water_level, voxel::Material::Water)
+    } else {
+      (ground, material)
+    }
+  }
 
+  /// Where traders can stand outside the huts within `radius` of a column. See `structures::T::huts_near`.
+  pub fn huts_near(&self, x: f32, z: f32, radius: f32) -> Vec<Point3<f32>> {
+    let ground = |p: &Point3<f32>| self.ground_mat_density(p);
+    self.structures.borrow_mut().huts_near(&ground, x, z, radius)
+  }
+
+  /// Density and material of the ground, before caves are carved out of it.
+  fn ground_mat_density(&self, p: &Point3<f32>) -> (f32, voxel::Material) {
+    let biome = self.climate.params(p.x, p.z);
+    let water = self.water(p.x, p.z);
+
+    let (height, mountain_height) = self.heights(&biome, p.x, p.z);
+    let heightmap_density = height - water.lowering - p.y;
+    let mountain_heightmap_density = mountain_height - water.lowering - p.y;
+
+    // Smooth out river and lake beds, so their banks hold the water in.
     let feature_density = self.features.apply(&self.seed, &[p.x as f64, p.y as f64, p.z as f64]) * 8.0;
-    let feature_density = feature_density as f32;
+    let feature_density = feature_density as f32 * biome.roughness * (1.0 - water.wetness);
     let d = feature_density + heightmap_density;
 
-    if mountain_heightmap_density > d {
-      (mountain_heightmap_density, voxel::Material::Stone)
-    } else {
-      (d, voxel::Material::Terrain)
+    let (d, material) =
+      if mountain_heightmap_density > d {
+        (mountain_heightmap_density, self.rock(mountain_heightmap_density, p))
+      } else if d > SOIL_DEPTH + GRAVEL_DEPTH {
+        (d, self.rock(d, p))
+      } else if d > SOIL_DEPTH {
+        let gravel = perlin3(&self.seed, &[p.x as f64 / 16.0, p.y as f64 / 16.0, p.z as f64 / 16.0]);
+        if gravel > GRAVEL_THRESHOLD {
+          (d, voxel::Material::Gravel)
+        } else {
+          (d, voxel::Material::Stone)
+        }
+      } else if water.wetness > 0.5 {
+        (d, voxel::Material::Gravel)
+      } else {
+        (d, biome.surface)
+      };
+
+    (d, material)
+  }
+
+  /// Open air below sea level, or below the surface of a river or lake, is flooded. Caves are left dry.
+  fn is_flooded(&self, p: &Point3<f32>) -> bool {
+    let below_water =
+      p.y < voxel::SEA_LEVEL as f32 ||
+      self.water(p.x, p.z).level.map_or(false, |level| p.y < level);
+    below_water && self.ground_mat_density(p).0 < 0.0
+  }
+
+  fn mat_density(&self, p: &Point3<f32>) -> (f32, voxel::Material) {
+    let ground = |p: &Point3<f32>| self.ground_mat_density(p);
+    let mut structures = self.structures.borrow_mut();
+    let (d, material) = structures.level(&ground, p, self.ground_mat_density(p));
+    // Carve caves out of the ground, closing them up as they near the surface.
+    let cave_density = self.cave_density(p) + f32::max(0.0, CAVE_SURFACE_MARGIN - d);
+    let d = f32::min(d, cave_density);
+
+    let (d, material) =
+      match structures.mat_density(&ground, p) {
+        Some((structure_d, structure_material)) if structure_d > d => (structure_d, structure_material),
+        _ => (d, material),
+      };
+
+    let trees =
+      self.trees.borrow_mut().mat_density(&self.climate, &ground, p);
+    match trees {
+      Some((tree_d, tree_material)) if tree_d > d => (tree_d, tree_material),
+      _ => (d, material),
     }
   }
 }
@@ -100,6 +271,8 @@ impl voxel::mosaic::T<voxel::Material> for T {
     Some(
       if d >= 0.0 {
         mat
+      } else if self.is_flooded(p) {
+        voxel::Material::Water
       } else {
         voxel::Material::Empty
       }
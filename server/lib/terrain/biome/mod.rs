@@ -1,6 +1,7 @@
 //! Voxel mosaic implementations for different biomes.
 
 pub mod caves;
+pub mod climate;
 pub mod demo;
 pub mod hills;
 pub mod mountains;
@@ -0,0 +1,182 @@
+//! Weathering of the landscape. The world is divided into square tiles, and in each one the heightmap
+//! is sampled on a grid and run through a few rounds of erosion: thermal erosion crumbles slopes too
+//! steep to stand into talus, and rain washes soil downhill, cutting into slopes and dropping its
+//! sediment where valleys flatten out.
+//! Each tile is eroded on its own, so the effect fades out towards tile edges to keep them seamless.
+
+use fnv;
+use lru_cache;
+use std;
+
+/// Width of the tiles that are eroded independently.
+const TILE_WIDTH: f32 = 256.0;
+/// Distance between the heightmap samples in a tile.
+const SPACING: f32 = 4.0;
+/// Samples along each side of a tile, including both edges.
+const SAMPLES: usize = 65;
+
+/// The steepest height difference between neighboring samples that doesn't crumble.
+const TALUS: f32 = 0.8 * SPACING;
+/// Portion of the excess over `TALUS` that slides downhill each round.
+const THERMAL_RATE: f32 = 0.25;
+
+/// Rain falling on every sample each round.
+const RAIN: f32 = 0.1;
+/// How much sediment water can carry, per unit of water per unit of drop.
+const CAPACITY: f32 = 1.0;
+/// Portion of spare capacity that's filled by eroding the ground each round.
+const EROSION_RATE: f32 = 0.3;
+/// Portion of excess sediment that settles out each round.
+const DEPOSITION_RATE: f32 = 0.3;
+/// Portion of the water that evaporates each round.
+const EVAPORATION: f32 = 0.05;
+
+/// Erosion fades out over this many samples from a tile's edge.
+const EDGE_FADE: f32 = 8.0;
+
+type Cache = lru_cache::LruCache<(i32, i32), Vec<f32>, std::hash::BuildHasherDefault<fnv::FnvHasher>>;
+
+#[allow(missing_docs)]
+pub struct T {
+  /// How many rounds of erosion to run. None at all when this is zero.
+  iterations : u32,
+  /// How much each sample of a tile was raised or lowered by erosion, indexed by `index`.
+  tiles      : Cache,
+}
+
+#[allow(missing_docs)]
+pub fn new(iterations: u32) -> T {
+  T {
+    iterations : iterations,
+    tiles      : lru_cache::LruCache::with_hasher(1 << 6, Default::default()),
+  }
+}
+
+fn index(i: usize, j: usize) -> usize {
+  i * SAMPLES + j
+}
+
+/// The orthogonal neighbors of a sample, and the sample itself for those off the edge of the tile.
+fn neighbors(c: usize) -> [usize; 4] {
+  let (i, j) = (c / SAMPLES, c % SAMPLES);
+  [
+    if i > 0 { index(i - 1, j) } else { c },
+    if i + 1 < SAMPLES { index(i + 1, j) } else { c },
+    if j > 0 { index(i, j - 1) } else { c },
+    if j + 1 < SAMPLES { index(i, j + 1) } else { c },
+  ]
+}
+
+/// Crumble the slopes that are too steep to stand.
+fn thermal(heights: &mut Vec<f32>) {
+  let mut change = vec!(0.0; SAMPLES * SAMPLES);
+  for c in 0 .. SAMPLES * SAMPLES {
+    for &n in &neighbors(c) {
+      let drop = heights[c] - heights[n];
+      if drop > TALUS {
+        let moved = THERMAL_RATE * (drop - TALUS) / 2.0;
+        change[c] -= moved;
+        change[n] += moved;
+      }
+    }
+  }
+  for c in 0 .. SAMPLES * SAMPLES {
+    heights[c] += change[c];
+  }
+}
+
+/// Rain on every sample, and let the water carry soil downhill.
+fn hydraulic(heights: &mut Vec<f32>, water: &mut Vec<f32>, sediment: &mut Vec<f32>) {
+  let mut next_water = vec!(0.0; SAMPLES * SAMPLES);
+  let mut next_sediment = vec!(0.0; SAMPLES * SAMPLES);
+  for c in 0 .. SAMPLES * SAMPLES {
+    water[c] += RAIN;
+    let lowest = neighbors(c).iter().cloned().min_by(|&a, &b| heights[a].partial_cmp(&heights[b]).unwrap()).unwrap();
+    let drop = heights[c] - heights[lowest];
+    if drop <= 0.0 {
+      // Standing water drops everything it's carrying.
+      heights[c] += sediment[c];
+      next_water[c] += water[c];
+      continue
+    }
+
+    let capacity = CAPACITY * drop * water[c];
+    if sediment[c] > capacity {
+      let deposit = DEPOSITION_RATE * (sediment[c] - capacity);
+      heights[c] += deposit;
+      sediment[c] -= deposit;
+    } else {
+      // Never dig below the sample the water's flowing into.
+      let eroded = f32::min(EROSION_RATE * (capacity - sediment[c]), drop / 2.0);
+      heights[c] -= eroded;
+      sediment[c] += eroded;
+    }
+    next_water[lowest] += water[c];
+    next_sediment[lowest] += sediment[c];
+  }
+  for c in 0 .. SAMPLES * SAMPLES {
+    water[c] = next_water[c] * (1.0 - EVAPORATION);
+    sediment[c] = next_sediment[c];
+  }
+}
+
+fn build<Height>(height: &Height, tile: (i32, i32), iterations: u32) -> Vec<f32>
+  where Height: Fn(f32, f32) -> f32
+{
+  let x0 = tile.0 as f32 * TILE_WIDTH;
+  let z0 = tile.1 as f32 * TILE_WIDTH;
+  let mut original = vec!(0.0; SAMPLES * SAMPLES);
+  for i in 0 .. SAMPLES {
+    for j in 0 .. SAMPLES {
+      original[index(i, j)] = height(x0 + i as f32 * SPACING, z0 + j as f32 * SPACING);
+    }
+  }
+
+  let mut heights = original.clone();
+  let mut water = vec!(0.0; SAMPLES * SAMPLES);
+  let mut sediment = vec!(0.0; SAMPLES * SAMPLES);
+  for _ in 0 .. iterations {
+    hydraulic(&mut heights, &mut water, &mut sediment);
+    thermal(&mut heights);
+  }
+
+  let mut change = vec!(0.0; SAMPLES * SAMPLES);
+  for i in 0 .. SAMPLES {
+    for j in 0 .. SAMPLES {
+      let from_edge = std::cmp::min(std::cmp::min(i, j), std::cmp::min(SAMPLES - 1 - i, SAMPLES - 1 - j));
+      let fade = f32::min(1.0, from_edge as f32 / EDGE_FADE);
+      let c = index(i, j);
+      change[c] = (heights[c] - original[c]) * fade;
+    }
+  }
+  change
+}
+
+impl T {
+  /// How much erosion has raised (or, if negative, lowered) the landscape at a column. `height`
+  /// gives the height of the landscape before erosion.
+  pub fn change_at<Height>(&mut self, height: &Height, x: f32, z: f32) -> f32
+    where Height: Fn(f32, f32) -> f32
+  {
+    if self.iterations == 0 {
+      return 0.0
+    }
+
+    let tile = ((x / TILE_WIDTH).floor() as i32, (z / TILE_WIDTH).floor() as i32);
+    if !self.tiles.contains_key(&tile) {
+      let built = build(height, tile, self.iterations);
+      self.tiles.insert(tile, built);
+    }
+    let change = self.tiles.get_mut(&tile).unwrap();
+
+    let u = (x - tile.0 as f32 * TILE_WIDTH) / SPACING;
+    let v = (z - tile.1 as f32 * TILE_WIDTH) / SPACING;
+    let i = std::cmp::min(u.floor() as usize, SAMPLES - 2);
+    let j = std::cmp::min(v.floor() as usize, SAMPLES - 2);
+    let (fu, fv) = (u - i as f32, v - j as f32);
+    change[index(i, j)] * (1.0 - fu) * (1.0 - fv) +
+    change[index(i + 1, j)] * fu * (1.0 - fv) +
+    change[index(i, j + 1)] * (1.0 - fu) * fv +
+    change[index(i + 1, j + 1)] * fu * fv
+  }
+}
@@ -0,0 +1,137 @@
+//! Noise functions giving the rough height of the landscape in each column, before it's scaled by
+//! biome and roughened by 3D features. Which one to use can be picked by name when the server starts.
+
+use noise::{Seed, Brownian2, perlin2};
+
+/// A source of landscape heights.
+pub trait HeightmapSource: Send {
+  /// The height of the landscape at a column. Sources should stay within about 32 of zero,
+  /// so biomes scale them similarly.
+  fn height(&self, seed: &Seed, x: f64, z: f64) -> f64;
+}
+
+/// The heightmaps to choose from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+  /// Gently rolling fractal Brownian noise. The default.
+  Brownian,
+  /// Ridged multifractal noise: sharp ridges and crests between smooth valleys.
+  Ridged,
+  /// Brownian noise with its coordinates warped by more noise, giving swirling, eroded-looking shapes.
+  Warped,
+}
+
+impl Kind {
+  /// Parse the name of a heightmap.
+  pub fn parse(name: &str) -> Option<Kind> {
+    match name {
+      "brownian" => Some(Kind::Brownian),
+      "ridged" => Some(Kind::Ridged),
+      "warped" => Some(Kind::Warped),
+      _ => None,
+    }
+  }
+
+  #[allow(missing_docs)]
+  pub fn source(self) -> Box<HeightmapSource> {
+    match self {
+      Kind::Brownian => Box::new(brownian()),
+      Kind::Ridged => Box::new(ridged()),
+      Kind::Warped => Box::new(warped()),
+    }
+  }
+}
+
+#[allow(missing_docs)]
+pub struct Brownian {
+  height: Brownian2<f64, fn (&Seed, &[f64; 2]) -> f64>,
+}
+
+#[allow(missing_docs)]
+pub fn brownian() -> Brownian {
+  let perlin2: fn(&Seed, &[f64; 2]) -> f64 = perlin2;
+  Brownian {
+    height:
+      Brownian2::new(perlin2, 5)
+      .frequency(1.0 / 4.0)
+      .persistence(2.0)
+      .lacunarity(1.0 / 2.0)
+    ,
+  }
+}
+
+impl HeightmapSource for Brownian {
+  fn height(&self, seed: &Seed, x: f64, z: f64) -> f64 {
+    self.height.apply(seed, &[x, z])
+  }
+}
+
+#[allow(missing_docs)]
+pub struct Ridged {
+  octaves    : usize,
+  /// Frequency of the first, broadest octave.
+  frequency  : f64,
+  /// Height of the tallest ridges.
+  amplitude  : f64,
+}
+
+#[allow(missing_docs)]
+pub fn ridged() -> Ridged {
+  Ridged {
+    octaves   : 5,
+    frequency : 1.0 / 128.0,
+    amplitude : 48.0,
+  }
+}
+
+impl HeightmapSource for Ridged {
+  fn height(&self, seed: &Seed, x: f64, z: f64) -> f64 {
+    let mut frequency = self.frequency;
+    let mut scale = 1.0;
+    let mut weight = 1.0;
+    let mut total = 0.0;
+    let mut max_total = 0.0;
+    for _ in 0 .. self.octaves {
+      // Folding the noise about zero makes a crest wherever it crosses zero.
+      let signal = 1.0 - perlin2(seed, &[x * frequency, z * frequency]).abs();
+      let signal = signal * signal * weight;
+      // Finer octaves only show up on the ridges, leaving the valleys smooth.
+      weight = f64::max(0.0, f64::min(1.0, signal * 2.0));
+      total += signal * scale;
+      max_total += scale;
+      frequency *= 2.0;
+      scale *= 0.5;
+    }
+    (total / max_total - 0.5) * self.amplitude
+  }
+}
+
+#[allow(missing_docs)]
+pub struct Warped {
+  base : Brownian,
+  warp : Brownian2<f64, fn (&Seed, &[f64; 2]) -> f64>,
+  /// How far, at most, the coordinates are pushed around.
+  strength : f64,
+}
+
+#[allow(missing_docs)]
+pub fn warped() -> Warped {
+  let perlin2: fn(&Seed, &[f64; 2]) -> f64 = perlin2;
+  Warped {
+    base : brownian(),
+    warp :
+      Brownian2::new(perlin2, 3)
+      .frequency(1.0 / 96.0)
+    ,
+    strength : 48.0,
+  }
+}
+
+impl HeightmapSource for Warped {
+  fn height(&self, seed: &Seed, x: f64, z: f64) -> f64 {
+    // Offset the samples so the two warp directions aren't correlated.
+    let dx = self.warp.apply(seed, &[x + 731.0, z - 219.0]);
+    let dz = self.warp.apply(seed, &[x - 467.0, z + 853.0]);
+    self.base.height(seed, x + dx * self.strength, z + dz * self.strength)
+  }
+}
@@ -0,0 +1,251 @@
+//! Rivers and lakes. The world is divided into square regions, and in each one the landscape's
+//! heightmap is sampled on a coarse grid, its depressions are filled into lakes, and water is traced
+//! downhill through it. Wherever enough water gathers, a river channel is carved.
+//! Each region drains to its own edges, so rivers fade out as they near them rather than joining up
+//! with the next region's. Everything is decided from the heightmap, which comes from the world seed.
+
+use fnv;
+use lru_cache;
+use std;
+use std::collections::BinaryHeap;
+
+use common::voxel;
+
+/// Width of the regions that are drained independently.
+const REGION_WIDTH: f32 = 512.0;
+/// Distance between the heightmap samples in a region.
+const SPACING: f32 = 8.0;
+/// Samples along each side of a region, including both edges.
+const SAMPLES: usize = 65;
+
+/// How many samples' worth of rain it takes to make a river.
+const RIVER_FLOW: f32 = 120.0;
+/// Rivers are cut this deep where they start, and deepen as they gather more water.
+const MIN_RIVER_DEPTH: f32 = 1.5;
+const MAX_RIVER_DEPTH: f32 = 5.0;
+/// How full of water river channels are.
+const RIVER_FULLNESS: f32 = 0.6;
+/// Widest a river gets, in samples either side of its course.
+const MAX_RIVER_RADIUS: f32 = 2.5;
+/// Depressions shallower than this are left dry.
+const MIN_LAKE_DEPTH: f32 = 1.0;
+/// Rivers taper off over this many samples from a region's edge.
+const EDGE_FADE: f32 = 4.0;
+/// Each step along a filled-in flat rises by this much, so water still has a way downhill.
+const FLAT_RISE: f32 = 0.001;
+
+type Cache = lru_cache::LruCache<(i32, i32), Region, std::hash::BuildHasherDefault<fnv::FnvHasher>>;
+
+/// The water at a point in the world.
+#[derive(Debug, Clone, Copy)]
+pub struct Water {
+  /// How far the ground is cut down by a river channel.
+  pub lowering : f32,
+  /// How wet this spot is, from 0 (dry land) to 1 (a river or lake bed). Beds are smoothed out.
+  pub wetness  : f32,
+  /// The height of the water surface, if there's standing or flowing water here.
+  pub level    : Option<f32>,
+}
+
+/// One region's samples, indexed by `index`.
+struct Region {
+  lowering : Vec<f32>,
+  wetness  : Vec<f32>,
+  level    : Vec<Option<f32>>,
+}
+
+#[allow(missing_docs)]
+pub struct T {
+  regions : Cache,
+}
+
+#[allow(missing_docs)]
+pub fn new() -> T {
+  T {
+    regions : lru_cache::LruCache::with_hasher(1 << 6, Default::default()),
+  }
+}
+
+fn index(i: usize, j: usize) -> usize {
+  i * SAMPLES + j
+}
+
+/// A sample waiting in the priority flood. Lower cells come out first.
+struct Open {
+  height : f32,
+  index  : usize,
+}
+
+impl PartialEq for Open {
+  fn eq(&self, other: &Open) -> bool {
+    self.cmp(other) == std::cmp::Ordering::Equal
+  }
+}
+
+impl Eq for Open {}
+
+impl PartialOrd for Open {
+  fn partial_cmp(&self, other: &Open) -> Option<std::cmp::Ordering> {
+    Some(self.cmp(other))
+  }
+}
+
+impl Ord for Open {
+  // `BinaryHeap` pops the greatest entry first, so lower cells compare greater.
+  fn cmp(&self, other: &Open) -> std::cmp::Ordering {
+    other.height.partial_cmp(&self.height).unwrap_or(std::cmp::Ordering::Equal)
+      .then_with(|| other.index.cmp(&self.index))
+  }
+}
+
+fn build<Height>(height: &Height, region: (i32, i32)) -> Region
+  where Height: Fn(f32, f32) -> f32
+{
+  let x0 = region.0 as f32 * REGION_WIDTH;
+  let z0 = region.1 as f32 * REGION_WIDTH;
+  let mut heights = vec!(0.0; SAMPLES * SAMPLES);
+  for i in 0 .. SAMPLES {
+    for j in 0 .. SAMPLES {
+      heights[index(i, j)] = height(x0 + i as f32 * SPACING, z0 + j as f32 * SPACING);
+    }
+  }
+
+  // Flood the region inwards from its edges, lowest first. Every sample drains into the one it was
+  // reached from, and anything lower than that gets filled up to it, which turns depressions into lakes.
+  let mut filled = heights.clone();
+  let mut drains_to: Vec<Option<usize>> = vec!(None; SAMPLES * SAMPLES);
+  let mut closed = vec!(false; SAMPLES * SAMPLES);
+  let mut order = Vec::with_capacity(SAMPLES * SAMPLES);
+  let mut open = BinaryHeap::new();
+  for i in 0 .. SAMPLES {
+    for j in 0 .. SAMPLES {
+      if i == 0 || j == 0 || i == SAMPLES - 1 || j == SAMPLES - 1 {
+        closed[index(i, j)] = true;
+        open.push(Open { height: heights[index(i, j)], index: index(i, j) });
+      }
+    }
+  }
+  while let Some(Open { index: c, .. }) = open.pop() {
+    order.push(c);
+    let (ci, cj) = ((c / SAMPLES) as i32, (c % SAMPLES) as i32);
+    for di in -1 .. 2 {
+      for dj in -1 .. 2 {
+        let (ni, nj) = (ci + di, cj + dj);
+        if ni < 0 || nj < 0 || ni >= SAMPLES as i32 || nj >= SAMPLES as i32 {
+          continue
+        }
+        let n = index(ni as usize, nj as usize);
+        if closed[n] {
+          continue
+        }
+        closed[n] = true;
+        filled[n] = f32::max(heights[n], filled[c] + FLAT_RISE);
+        drains_to[n] = Some(c);
+        open.push(Open { height: filled[n], index: n });
+      }
+    }
+  }
+
+  // Every sample catches one unit of rain, and passes everything it's caught downstream.
+  let mut flow = vec!(1.0; SAMPLES * SAMPLES);
+  for &c in order.iter().rev() {
+    if let Some(d) = drains_to[c] {
+      flow[d] += flow[c];
+    }
+  }
+
+  let mut lowering = vec!(0.0; SAMPLES * SAMPLES);
+  let mut wetness = vec!(0.0; SAMPLES * SAMPLES);
+  let mut level = vec!(None; SAMPLES * SAMPLES);
+
+  for c in 0 .. SAMPLES * SAMPLES {
+    let depth = filled[c] - heights[c];
+    if depth >= MIN_LAKE_DEPTH && filled[c] > voxel::SEA_LEVEL as f32 {
+      wetness[c] = 1.0;
+      level[c] = Some(filled[c]);
+    }
+  }
+
+  for c in 0 .. SAMPLES * SAMPLES {
+    if flow[c] < RIVER_FLOW || level[c].is_some() {
+      continue
+    }
+    let (ci, cj) = (c / SAMPLES, c % SAMPLES);
+    let from_edge = std::cmp::min(std::cmp::min(ci, cj), std::cmp::min(SAMPLES - 1 - ci, SAMPLES - 1 - cj));
+    let fade = f32::min(1.0, from_edge as f32 / EDGE_FADE);
+    if fade <= 0.0 {
+      continue
+    }
+
+    let size = (flow[c] / RIVER_FLOW).ln();
+    let depth = f32::min(MAX_RIVER_DEPTH, MIN_RIVER_DEPTH + size) * fade;
+    let radius = f32::min(MAX_RIVER_RADIUS, 1.0 + size / 2.0) * fade;
+    let surface = heights[c] - depth * (1.0 - RIVER_FULLNESS);
+    let reach = radius.ceil() as i32;
+    for di in -reach .. reach + 1 {
+      for dj in -reach .. reach + 1 {
+        let (ni, nj) = (ci as i32 + di, cj as i32 + dj);
+        if ni < 0 || nj < 0 || ni >= SAMPLES as i32 || nj >= SAMPLES as i32 {
+          continue
+        }
+        let distance = ((di * di + dj * dj) as f32).sqrt();
+        if distance > radius {
+          continue
+        }
+        let n = index(ni as usize, nj as usize);
+        // Round-bottomed channels, deepest along the river's course.
+        let t = distance / (radius + 1.0);
+        lowering[n] = f32::max(lowering[n], depth * (1.0 - t * t));
+        wetness[n] = 1.0;
+        if fade >= 1.0 && distance + 1.0 <= radius {
+          level[n] = Some(level[n].map_or(surface, |l| f32::min(l, surface)));
+        }
+      }
+    }
+  }
+
+  Region {
+    lowering : lowering,
+    wetness  : wetness,
+    level    : level,
+  }
+}
+
+impl T {
+  /// The water at a column of the world. `height` gives the height of the landscape before any
+  /// rivers are carved into it.
+  pub fn at<Height>(&mut self, height: &Height, x: f32, z: f32) -> Water
+    where Height: Fn(f32, f32) -> f32
+  {
+    let region = ((x / REGION_WIDTH).floor() as i32, (z / REGION_WIDTH).floor() as i32);
+    if !self.regions.contains_key(&region) {
+      let built = build(height, region);
+      self.regions.insert(region, built);
+    }
+    let region_data = self.regions.get_mut(&region).unwrap();
+
+    let u = (x - region.0 as f32 * REGION_WIDTH) / SPACING;
+    let v = (z - region.1 as f32 * REGION_WIDTH) / SPACING;
+    let i = std::cmp::min(u.floor() as usize, SAMPLES - 2);
+    let j = std::cmp::min(v.floor() as usize, SAMPLES - 2);
+    let (fu, fv) = (u - i as f32, v - j as f32);
+    let corners = [index(i, j), index(i + 1, j), index(i, j + 1), index(i + 1, j + 1)];
+    let weights = [(1.0 - fu) * (1.0 - fv), fu * (1.0 - fv), (1.0 - fu) * fv, fu * fv];
+    let blend = |values: &Vec<f32>| corners.iter().zip(weights.iter()).map(|(&c, &w)| values[c] * w).sum::<f32>();
+
+    // Only fill with water where the whole cell is wet, so it doesn't spill up onto the banks.
+    let mut level = Some(0.0);
+    for (&c, &w) in corners.iter().zip(weights.iter()) {
+      level = match (level, region_data.level[c]) {
+        (Some(total), Some(l)) => Some(total + l * w),
+        _ => None,
+      };
+    }
+
+    Water {
+      lowering : blend(&region_data.lowering),
+      wetness  : blend(&region_data.wetness),
+      level    : level,
+    }
+  }
+}
@@ -17,44 +17,113 @@ extern crate voxel_data;
 extern crate num;
 
 mod cache_mosaic;
+mod seed;
 
 pub mod biome;
+pub mod erosion;
+pub mod heightmap;
+pub mod hydrology;
+pub mod ores;
+pub mod shape;
+pub mod structures;
+pub mod surface;
 pub mod tree;
+pub mod tree_placer;
 
 pub use noise::Seed;
 
 use std::sync::Mutex;
 
+use common::horizon;
 use common::voxel;
 
 /// This struct contains and lazily generates the world's terrain.
 #[allow(missing_docs)]
 pub struct T {
-  pub mosaic: Mutex<cache_mosaic::T<voxel::Material>>,
   pub voxels: Mutex<voxel::tree::T>,
+  /// Chooses the biome for each column of the world.
+  pub climate: biome::climate::T,
+  /// Generators that aren't in use. Each thread generating terrain takes one out (or makes a new one)
+  /// for as long as it needs it, so generation runs in parallel across threads.
+  mosaics: Mutex<Vec<cache_mosaic::T<voxel::Material>>>,
+  /// Samples the far horizon, and finds structures. It's kept apart from `mosaics` so neither waits on voxel loads.
+  horizon: Mutex<biome::demo::T>,
+  seed: u64,
+  heightmap: heightmap::Kind,
+  erosion_iterations: u32,
 }
 
 impl T {
-  #[allow(missing_docs)]
-  pub fn new(terrain_seed: Seed) -> T {
+  /// Generate terrain from a world seed, shaped by a kind of heightmap and weathered by
+  /// `erosion_iterations` rounds of erosion.
+  /// The same seed, heightmap and erosion always generate the same terrain.
+  pub fn new(seed: u64, heightmap: heightmap::Kind, erosion_iterations: u32) -> T {
     T {
-      mosaic: Mutex::new(cache_mosaic::new(Box::new(biome::demo::new(terrain_seed)))),
       voxels: Mutex::new(voxel::tree::new()),
+      climate: biome::climate::new(seed::noise(seed)),
+      mosaics: Mutex::new(Vec::new()),
+      horizon: Mutex::new(generator(seed, heightmap, erosion_iterations)),
+      seed: seed,
+      heightmap: heightmap,
+      erosion_iterations: erosion_iterations,
     }
   }
 
-  /// Load the block of terrain at a given position.
-  // TODO: Allow this to be performed in such a way that self is only briefly locked.
+  /// Run `f` with a generator to itself.
+  fn with_mosaic<F, R>(&self, f: F) -> R
+    where F: FnOnce(&mut cache_mosaic::T<voxel::Material>) -> R
+  {
+    let mosaic = self.mosaics.lock().unwrap().pop();
+    let mut mosaic =
+      mosaic.unwrap_or_else(|| {
+        cache_mosaic::new(Box::new(generator(self.seed, self.heightmap, self.erosion_iterations)))
+      });
+    let r = f(&mut mosaic);
+    self.mosaics.lock().unwrap().push(mosaic);
+    r
+  }
+
+  /// Sample the columns of a tile of the far horizon. Edits to the terrain aren't reflected.
+  pub fn horizon(&self, tile: &horizon::Tile) -> Vec<horizon::Column> {
+    let demo = self.horizon.lock().unwrap();
+    let mut columns = Vec::with_capacity(horizon::SAMPLES * horizon::SAMPLES);
+    for i in 0 .. horizon::SAMPLES {
+      for j in 0 .. horizon::SAMPLES {
+        let (x, z) = tile.sample_position(i, j);
+        let (height, material) = demo.column(x, z);
+        columns.push(
+          horizon::Column {
+            height   : height,
+            material : material,
+          }
+        );
+      }
+    }
+    columns
+  }
+
+  /// The spots just outside the doorways of the huts within `radius` of a column.
+  pub fn huts_near(&self, x: f32, z: f32, radius: f32) -> Vec<cgmath::Point3<f32>> {
+    self.horizon.lock().unwrap().huts_near(x, z, radius)
+  }
+
+  /// Load the block of terrain at a given position. Blocks are generated without holding any locks,
+  /// so several threads can load at once.
   pub fn load(&self, bounds: &voxel::bounds::T) -> voxel::T {
+    if let Some(voxel) = self.voxels.lock().unwrap().get(bounds) {
+      return *voxel
+    }
+
+    let generated = self.with_mosaic(|mosaic| voxel::unwrap(voxel::of_field(mosaic, bounds)));
+
+    // Another thread may have loaded or brushed this block in the meantime. Whatever got there first
+    // wins, so edits are never overwritten by a freshly generated block.
     let mut voxels = self.voxels.lock().unwrap();
     let node = voxels.get_mut_or_create(bounds);
     match node.data {
       None => {
-        let mut mosaic = self.mosaic.lock().unwrap();
-        let voxel = voxel::unwrap(voxel::of_field(&mut *mosaic, bounds));
-        let r = voxel;
-        node.data = Some(voxel);
-        r
+        node.data = Some(generated);
+        generated
       },
       Some(data) => {
         data
@@ -79,11 +148,32 @@ impl T {
         if bounds.lg_size > 3 {
           None
         } else {
-          let mut mosaic = self.mosaic.lock().unwrap();
-          Some(voxel::unwrap(voxel::of_field(&mut *mosaic, bounds)))
+          Some(self.with_mosaic(|mosaic| voxel::unwrap(voxel::of_field(mosaic, bounds))))
         }
       },
       &mut voxel_changed,
     );
   }
 }
+
+/// A fresh terrain generator.
+fn generator(seed: u64, heightmap: heightmap::Kind, erosion_iterations: u32) -> biome::demo::T {
+  biome::demo::new(
+    seed::noise(seed),
+    heightmap.source(),
+    erosion::new(erosion_iterations),
+    biome::climate::new(seed::noise(seed)),
+    structures::new(seed),
+    tree_placer::new(seed),
+  )
+}
+
+#[test]
+fn same_seed_same_terrain() {
+  let a = T::new(0x1234_5678_9abc_def0, heightmap::Kind::Brownian, 4);
+  let b = T::new(0x1234_5678_9abc_def0, heightmap::Kind::Brownian, 4);
+  for &(x, y, z, lg_size) in &[(0, 0, 0, 0), (3, -2, 7, 0), (-5, 1, 2, 1), (40, 0, -40, 2)] {
+    let bounds = voxel::bounds::new(x, y, z, lg_size);
+    assert!(a.load(&bounds) == b.load(&bounds));
+  }
+}
@@ -0,0 +1,70 @@
+//! Veins of ore running through the rock underground. Each ore keeps to a band of depths, and
+//! forms wherever a 3D noise field peaks, which strings it out into twisting veins.
+
+use cgmath::Point3;
+use noise::{Seed, Brownian3, perlin3};
+
+use common::voxel;
+
+/// Veins thin out over this distance towards the top and bottom of their band.
+const BAND_FADE: f32 = 16.0;
+
+struct Ore {
+  material  : voxel::Material,
+  /// Highest y the ore is found at.
+  top       : f32,
+  /// Lowest y the ore is found at.
+  bottom    : f32,
+  /// Scales coordinates into the noise. Smaller frequencies give longer, thicker veins.
+  frequency : f64,
+  /// Noise level above which there's ore. Higher thresholds give thinner, rarer veins.
+  threshold : f64,
+  /// Offsets this ore's samples so veins of different ores don't line up.
+  offset    : f64,
+}
+
+/// The ores, from shallowest to deepest. Where veins overlap, earlier ones win.
+const ORES: [Ore; 3] = [
+  Ore { material: voxel::Material::Coal   , top:   16.0, bottom:  -64.0, frequency: 1.0 / 12.0, threshold: 0.45, offset:    0.0 },
+  Ore { material: voxel::Material::Iron   , top:  -16.0, bottom: -128.0, frequency: 1.0 / 8.0 , threshold: 0.5 , offset:  317.0 },
+  Ore { material: voxel::Material::Crystal, top:  -64.0, bottom: -256.0, frequency: 1.0 / 6.0 , threshold: 0.6 , offset: -541.0 },
+];
+
+#[allow(missing_docs)]
+pub struct T {
+  veins: Brownian3<f64, fn (&Seed, &[f64; 3]) -> f64>,
+}
+
+#[allow(missing_docs)]
+pub fn new() -> T {
+  let perlin3: fn(&Seed, &[f64; 3]) -> f64 = perlin3;
+  T {
+    veins: Brownian3::new(perlin3, 2),
+  }
+}
+
+impl T {
+  /// The ore at a point in solid rock, if any.
+  pub fn ore_at(&self, seed: &Seed, p: &Point3<f32>) -> Option<voxel::Material> {
+    for ore in &ORES {
+      if p.y > ore.top || p.y < ore.bottom {
+        continue
+      }
+      let edge = f32::min(1.0, f32::min(ore.top - p.y, p.y - ore.bottom) / BAND_FADE);
+      let threshold = ore.threshold + (1.0 - edge as f64) * (1.0 - ore.threshold);
+      let sample =
+        self.veins.apply(
+          seed,
+          &[
+            p.x as f64 * ore.frequency + ore.offset,
+            p.y as f64 * ore.frequency,
+            p.z as f64 * ore.frequency - ore.offset,
+          ],
+        );
+      if sample > threshold {
+        return Some(ore.material)
+      }
+    }
+    None
+  }
+}
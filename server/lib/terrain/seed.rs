@@ -0,0 +1,9 @@
+//! Deriving the seeds of the world's noise functions from the world seed, so the same world seed always
+//! generates the same world. Other random sources are streams from `common::rng`.
+
+use noise;
+
+/// The noise functions take 32-bit seeds, so fold the world seed down to one.
+pub fn noise(seed: u64) -> noise::Seed {
+  noise::Seed::new((seed ^ (seed >> 32)) as u32)
+}
@@ -0,0 +1,156 @@
+//! Mosaics for placing `common::shape` models into the terrain.
+
+use cgmath::{Point3, Vector3};
+use voxel_data::field;
+use voxel_data::mosaic;
+
+use common::shape;
+use common::voxel;
+
+struct Element {
+  /// In world space.
+  low    : Point3<f32>,
+  high   : Point3<f32>,
+  /// Faces to leave out, in the order of `shape::culled_faces`.
+  culled : [bool; 6],
+}
+
+#[allow(missing_docs)]
+pub struct T {
+  elements  : Vec<Element>,
+  material  : voxel::Material,
+  /// The cell being filled.
+  low       : Point3<f32>,
+  width     : f32,
+  /// The solid material on the other side of each face of the cell, if there is one.
+  neighbors : [Option<voxel::Material>; 6],
+}
+
+/// Orient a shape, and scale it up to a `width`-sized cell with its low corner at `low`. `neighbors`
+/// holds the solid material next to each face of the cell, if there is one, in the order of
+/// `shape::culled_faces`; faces pressed against them are left out, so the shape joins up with them.
+pub fn new(
+  model: &shape::Model,
+  orientation: &shape::Orientation,
+  low: Point3<f32>,
+  width: f32,
+  material: voxel::Material,
+  neighbors: [Option<voxel::Material>; 6],
+) -> T {
+  let to_world = |p: [f32; 3]| {
+    Point3::new(low.x + p[0] * width, low.y + p[1] * width, low.z + p[2] * width)
+  };
+  let mut solid = [false; 6];
+  for i in 0 .. 6 {
+    solid[i] = neighbors[i].is_some();
+  }
+  T {
+    elements:
+      model.oriented_elements(orientation).into_iter()
+      .map(|element| {
+        Element {
+          low    : to_world(element.0),
+          high   : to_world(element.1),
+          culled : shape::culled_faces(&element, &solid),
+        }
+      })
+      .collect(),
+    material  : material,
+    low       : low,
+    width     : width,
+    neighbors : neighbors,
+  }
+}
+
+/// Distance from `p` to the nearest face of a box (negative outside), and the outward normal of that face.
+/// Culled faces count as a whole cell further out than they are, which puts them out past anything
+/// the brush touches, so the box runs on into the neighbor instead of ending in a face.
+fn element_density(element: &Element, width: f32, p: &Point3<f32>) -> (f32, Vector3<f32>) {
+  let (low, high) = (element.low, element.high);
+  let faces = [
+    (p.x - low.x  , Vector3::new(-1.0,  0.0,  0.0)),
+    (high.x - p.x , Vector3::new( 1.0,  0.0,  0.0)),
+    (p.y - low.y  , Vector3::new( 0.0, -1.0,  0.0)),
+    (high.y - p.y , Vector3::new( 0.0,  1.0,  0.0)),
+    (p.z - low.z  , Vector3::new( 0.0,  0.0, -1.0)),
+    (high.z - p.z , Vector3::new( 0.0,  0.0,  1.0)),
+  ];
+  let distance = |i: usize| if element.culled[i] { faces[i].0 + width } else { faces[i].0 };
+  let mut nearest = 0;
+  for i in 1 .. faces.len() {
+    if distance(i) < distance(nearest) {
+      nearest = i;
+    }
+  }
+  (distance(nearest), faces[nearest].1)
+}
+
+impl T {
+  fn density_normal(&self, p: &Point3<f32>) -> (f32, Vector3<f32>) {
+    let mut densest = element_density(&self.elements[0], self.width, p);
+    for element in self.elements.iter().skip(1) {
+      let d = element_density(element, self.width, p);
+      if d.0 > densest.0 {
+        densest = d;
+      }
+    }
+    densest
+  }
+
+  /// The neighbor whose side of the cell `p` is on, if it's outside the cell next to one.
+  fn neighbor_at(&self, p: &Point3<f32>) -> Option<voxel::Material> {
+    let high = self.low + Vector3::new(self.width, self.width, self.width);
+    let outside = [
+      p.x < self.low.x, p.x > high.x,
+      p.y < self.low.y, p.y > high.y,
+      p.z < self.low.z, p.z > high.z,
+    ];
+    outside.iter().zip(self.neighbors.iter())
+      .filter(|&(&outside, _)| outside)
+      .filter_map(|(_, &neighbor)| neighbor)
+      .next()
+  }
+}
+
+impl field::T for T {
+  fn density(&mut self, p: &Point3<f32>) -> f32 {
+    self.density_normal(p).0
+  }
+
+  fn normal(&mut self, p: &Point3<f32>) -> Vector3<f32> {
+    self.density_normal(p).1
+  }
+}
+
+impl mosaic::T<voxel::Material> for T {
+  fn material(&mut self, p: &Point3<f32>) -> Option<voxel::Material> {
+    if self.density_normal(p).0 < 0.0 {
+      return None
+    }
+    // Where the shape runs on into a neighbor, the neighbor keeps its own material.
+    Some(self.neighbor_at(p).unwrap_or(self.material))
+  }
+}
+
+#[test]
+fn culled_faces_run_on_into_the_neighbor() {
+  let slab = shape::find(&shape::default_models(), "slab").unwrap().clone();
+  let upright = shape::Orientation { facing: shape::Facing::South, axis: shape::Axis::Y };
+  let low = Point3::new(0.0, 0.0, 0.0);
+  let below = Point3::new(2.0, -0.5, 2.0);
+  let above = Point3::new(2.0, 2.5, 2.0);
+
+  let mut floating = new(&slab, &upright, low, 4.0, voxel::Material::Stone, [None; 6]);
+  assert!(field::T::density(&mut floating, &below) < 0.0);
+  assert_eq!(mosaic::T::material(&mut floating, &below), None);
+
+  // On the ground, there's no bottom face: the slab carries on into the ground, which keeps its material.
+  let mut ground = [None; 6];
+  ground[2] = Some(voxel::Material::Terrain);
+  let mut grounded = new(&slab, &upright, low, 4.0, voxel::Material::Stone, ground);
+  assert!(field::T::density(&mut grounded, &below) > 0.0);
+  assert_eq!(mosaic::T::material(&mut grounded, &below), Some(voxel::Material::Terrain));
+  assert_eq!(mosaic::T::material(&mut grounded, &Point3::new(2.0, 1.0, 2.0)), Some(voxel::Material::Stone));
+  // The top is still there.
+  assert!(field::T::density(&mut grounded, &above) < 0.0);
+}
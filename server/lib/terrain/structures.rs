@@ -0,0 +1,311 @@
+//! Prebuilt structures stamped into the world as it's generated: huts, and ruined walls.
+//! The world is divided into square cells, each of which might hold one structure. The ground under a
+//! structure is leveled off at the height of the land it sits on, blending back into the surrounding
+//! landscape at its edges, so structures neither float nor get buried.
+//! Every cell's structure is decided from the world seed and the cell's position alone.
+
+use cgmath::{Point3, Vector3, InnerSpace};
+use collision::Aabb3;
+use fnv;
+use lru_cache;
+use rand;
+use rand::Rng;
+use std;
+
+use common::rng;
+use common::voxel;
+
+use surface;
+
+/// Width of the cells that each hold at most one structure.
+const CELL_WIDTH: f32 = 128.0;
+
+/// Chance that a cell has a structure.
+const CHANCE: f32 = 0.15;
+
+/// Width of the band around a structure over which leveled ground blends back into the landscape.
+const BLEND_WIDTH: f32 = 6.0;
+
+/// Sites whose ground varies in height by more than this aren't built on.
+const MAX_LEVELING: f32 = 6.0;
+
+type Cache = lru_cache::LruCache<(i32, i32), Option<Site>, std::hash::BuildHasherDefault<fnv::FnvHasher>>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+  Hut,
+  Ruin,
+}
+
+/// A structure, placed in the world.
+struct Site {
+  kind    : Kind,
+  /// The low corner of the footprint, at ground level.
+  origin  : Vector3<f32>,
+  /// The width and depth of the footprint.
+  size    : (f32, f32),
+  /// What the leveled ground is made of.
+  surface : voxel::Material,
+  /// The structure's blocks, relative to `origin`.
+  blocks  : Vec<(Aabb3<f32>, voxel::Material)>,
+}
+
+#[allow(missing_docs)]
+pub struct T {
+  seed  : u64,
+  cells : Cache,
+}
+
+#[allow(missing_docs)]
+pub fn new(seed: u64) -> T {
+  T {
+    seed  : seed,
+    cells : lru_cache::LruCache::with_hasher(1 << 8, Default::default()),
+  }
+}
+
+fn block(low: (f32, f32, f32), high: (f32, f32, f32), material: voxel::Material) -> (Aabb3<f32>, voxel::Material) {
+  (Aabb3::new(Point3::new(low.0, low.1, low.2), Point3::new(high.0, high.1, high.2)), material)
+}
+
+/// A wooden hut with a thatched roof and a doorway in its south wall.
+fn hut() -> ((f32, f32), Vec<(Aabb3<f32>, voxel::Material)>) {
+  let size = (8.0, 8.0);
+  let blocks =
+    vec!(
+      block((0.0, -1.0, 0.0), (8.0, 0.5, 8.0), voxel::Material::Stone),
+      block((0.0,  0.0, 0.0), (1.0, 4.0, 8.0), voxel::Material::Bark),
+      block((7.0,  0.0, 0.0), (8.0, 4.0, 8.0), voxel::Material::Bark),
+      block((0.0,  0.0, 0.0), (8.0, 4.0, 1.0), voxel::Material::Bark),
+      block((0.0,  0.0, 7.0), (3.0, 4.0, 8.0), voxel::Material::Bark),
+      block((5.0,  0.0, 7.0), (8.0, 4.0, 8.0), voxel::Material::Bark),
+      block((3.0,  3.0, 7.0), (5.0, 4.0, 8.0), voxel::Material::Bark),
+      block((-0.5, 4.0, -0.5), (8.5, 5.0, 8.5), voxel::Material::Leaves),
+    );
+  (size, blocks)
+}
+
+/// Crumbling stone walls around a cracked marble floor, with a pillar or two still standing.
+fn ruin<Rng: rand::Rng>(rng: &mut Rng) -> ((f32, f32), Vec<(Aabb3<f32>, voxel::Material)>) {
+  let width = 10.0;
+  let segment = 2.0;
+  let mut blocks = Vec::new();
+
+  for i in 0 .. 5 {
+    for j in 0 .. 5 {
+      if rng.gen::<f32>() < 0.7 {
+        let (x, z) = (i as f32 * segment, j as f32 * segment);
+        blocks.push(block((x, -1.0, z), (x + segment, 0.25, z + segment), voxel::Material::Marble));
+      }
+    }
+  }
+
+  // Walk the walls in segments, knocking each down to a random height.
+  for i in 0 .. 5 {
+    let along = i as f32 * segment;
+    let sides =
+      [
+        ((along, 0.0), (along + segment, 1.0)),
+        ((along, width - 1.0), (along + segment, width)),
+        ((0.0, along), (1.0, along + segment)),
+        ((width - 1.0, along), (width, along + segment)),
+      ];
+    for &((x0, z0), (x1, z1)) in sides.iter() {
+      if rng.gen::<f32>() < 0.3 {
+        continue
+      }
+      let height = rng.gen_range(1.0, 4.0);
+      blocks.push(block((x0, 0.0, z0), (x1, height, z1), voxel::Material::Stone));
+    }
+  }
+
+  for _ in 0 .. rng.gen_range(1, 3) {
+    let x = rng.gen_range(2.0, width - 3.0);
+    let z = rng.gen_range(2.0, width - 3.0);
+    let height = rng.gen_range(3.0, 6.0);
+    blocks.push(block((x, 0.0, z), (x + 1.0, height, z + 1.0), voxel::Material::Marble));
+  }
+
+  ((width, width), blocks)
+}
+
+/// Roughly the distance from `p` into a box (negative outside).
+fn box_density(bounds: &Aabb3<f32>, p: &Point3<f32>) -> f32 {
+  let outside =
+    Vector3::new(
+      f32::max(0.0, f32::max(bounds.min.x - p.x, p.x - bounds.max.x)),
+      f32::max(0.0, f32::max(bounds.min.y - p.y, p.y - bounds.max.y)),
+      f32::max(0.0, f32::max(bounds.min.z - p.z, p.z - bounds.max.z)),
+    );
+  if outside.magnitude2() > 0.0 {
+    return -outside.magnitude()
+  }
+  f32::min(
+    f32::min(
+      f32::min(p.x - bounds.min.x, bounds.max.x - p.x),
+      f32::min(p.y - bounds.min.y, bounds.max.y - p.y),
+    ),
+    f32::min(p.z - bounds.min.z, bounds.max.z - p.z),
+  )
+}
+
+impl Site {
+  /// Horizontal distance from a point to the footprint.
+  fn distance(&self, p: &Point3<f32>) -> f32 {
+    let dx = f32::max(0.0, f32::max(self.origin.x - p.x, p.x - (self.origin.x + self.size.0)));
+    let dz = f32::max(0.0, f32::max(self.origin.z - p.z, p.z - (self.origin.z + self.size.1)));
+    (dx * dx + dz * dz).sqrt()
+  }
+}
+
+impl T {
+  fn build<Ground>(&self, ground: &Ground, cell: (i32, i32)) -> Option<Site>
+    where Ground: Fn(&Point3<f32>) -> (f32, voxel::Material)
+  {
+    let mut rng = rng::cell(self.seed, rng::subsystem::STRUCTURES, cell);
+    if rng.gen::<f32>() >= CHANCE {
+      return None
+    }
+
+    let kind = if rng.gen() { Kind::Hut } else { Kind::Ruin };
+    let (size, blocks) =
+      match kind {
+        Kind::Hut => hut(),
+        Kind::Ruin => ruin(&mut rng),
+      };
+
+    // Keep the structure and its blending band inside the cell, so only one cell ever needs checking.
+    let margin = BLEND_WIDTH + 1.0;
+    let x = cell.0 as f32 * CELL_WIDTH + rng.gen_range(margin, CELL_WIDTH - size.0 - margin);
+    let z = cell.1 as f32 * CELL_WIDTH + rng.gen_range(margin, CELL_WIDTH - size.1 - margin);
+
+    // Level the site off at the average height of the ground under it.
+    let mut heights = Vec::new();
+    let mut surface = None;
+    for &(dx, dz) in &[(0.0, 0.0), (1.0, 0.0), (0.0, 1.0), (1.0, 1.0), (0.5, 0.5)] {
+      match surface::find(ground, x + dx * size.0, z + dz * size.1) {
+        None => return None,
+        Some((y, material)) => {
+          heights.push(y);
+          if dx == 0.5 {
+            surface = Some(material);
+          }
+        },
+      }
+    }
+    let low = heights.iter().cloned().fold(std::f32::INFINITY, f32::min);
+    let high = heights.iter().cloned().fold(std::f32::NEG_INFINITY, f32::max);
+    if high - low > MAX_LEVELING {
+      return None
+    }
+    let y = heights.iter().sum::<f32>() / heights.len() as f32;
+    if y < voxel::SEA_LEVEL as f32 + 1.0 {
+      return None
+    }
+
+    Some(
+      Site {
+        kind    : kind,
+        origin  : Vector3::new(x, y.round(), z),
+        size    : size,
+        surface : surface.unwrap(),
+        blocks  : blocks,
+      }
+    )
+  }
+
+  fn site<Ground>(&mut self, ground: &Ground, p: &Point3<f32>) -> Option<&Site>
+    where Ground: Fn(&Point3<f32>) -> (f32, voxel::Material)
+  {
+    let cell = ((p.x / CELL_WIDTH).floor() as i32, (p.z / CELL_WIDTH).floor() as i32);
+    self.site_in(ground, cell)
+  }
+
+  fn site_in<Ground>(&mut self, ground: &Ground, cell: (i32, i32)) -> Option<&Site>
+    where Ground: Fn(&Point3<f32>) -> (f32, voxel::Material)
+  {
+    if !self.cells.contains_key(&cell) {
+      let site = self.build(ground, cell);
+      self.cells.insert(cell, site);
+    }
+    match self.cells.get_mut(&cell) {
+      Some(&mut Some(ref site)) => Some(site),
+      _ => None,
+    }
+  }
+
+  /// The spot just outside the doorway of every hut within `radius` of a column, as its feet would be
+  /// placed to stand there.
+  pub fn huts_near<Ground>(&mut self, ground: &Ground, x: f32, z: f32, radius: f32) -> Vec<Point3<f32>>
+    where Ground: Fn(&Point3<f32>) -> (f32, voxel::Material)
+  {
+    let cell = |w: f32| (w / CELL_WIDTH).floor() as i32;
+    let mut huts = Vec::new();
+    for i in cell(x - radius) .. cell(x + radius) + 1 {
+      for j in cell(z - radius) .. cell(z + radius) + 1 {
+        if let Some(site) = self.site_in(ground, (i, j)) {
+          if site.kind == Kind::Hut && site.distance(&Point3::new(x, site.origin.y, z)) <= radius {
+            // The doorway is in the middle of the south wall.
+            huts.push(Point3::new(site.origin.x + site.size.0 / 2.0, site.origin.y, site.origin.z + site.size.1 + 1.0));
+          }
+        }
+      }
+    }
+    huts
+  }
+
+  /// Level off the ground under any structure near `p`. `ground` gives the unleveled ground everywhere,
+  /// and `here` is its density and material at `p`.
+  pub fn level<Ground>(
+    &mut self,
+    ground : &Ground,
+    p      : &Point3<f32>,
+    here   : (f32, voxel::Material),
+  ) -> (f32, voxel::Material)
+    where Ground: Fn(&Point3<f32>) -> (f32, voxel::Material)
+  {
+    let site =
+      match self.site(ground, p) {
+        None => return here,
+        Some(site) => site,
+      };
+    let distance = site.distance(p);
+    if distance >= BLEND_WIDTH {
+      return here
+    }
+
+    // Smoothly fade from the flat site into the landscape.
+    let t = distance / BLEND_WIDTH;
+    let w = 1.0 - t * t * (3.0 - 2.0 * t);
+    let (d, material) = here;
+    let leveled = d * (1.0 - w) + (site.origin.y - p.y) * w;
+    if d < 0.0 && leveled >= 0.0 {
+      (leveled, site.surface)
+    } else {
+      (leveled, material)
+    }
+  }
+
+  /// The density and material of any structure near `p`.
+  pub fn mat_density<Ground>(&mut self, ground: &Ground, p: &Point3<f32>) -> Option<(f32, voxel::Material)>
+    where Ground: Fn(&Point3<f32>) -> (f32, voxel::Material)
+  {
+    let site =
+      match self.site(ground, p) {
+        None => return None,
+        Some(site) => site,
+      };
+    if site.distance(p) > 1.0 {
+      return None
+    }
+    let local = p + -site.origin;
+    let mut best = None;
+    for &(ref bounds, material) in &site.blocks {
+      let d = box_density(bounds, &local);
+      if best.map_or(true, |(best_d, _)| d > best_d) {
+        best = Some((d, material));
+      }
+    }
+    best
+  }
+}
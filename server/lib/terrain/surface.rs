@@ -0,0 +1,30 @@
+//! Finding the ground surface in a column of the world.
+
+use cgmath::Point3;
+
+use common::voxel;
+
+/// Heights between which to look for the ground.
+const SEARCH_HIGH: f32 = 128.0;
+const SEARCH_LOW: f32 = -64.0;
+
+/// Step size when searching for the ground.
+const SEARCH_STEP: f32 = 1.0;
+
+/// Find the height of the topmost ground in a column, and what the ground there is made of.
+pub fn find<Ground>(ground: &Ground, x: f32, z: f32) -> Option<(f32, voxel::Material)>
+  where Ground: Fn(&Point3<f32>) -> (f32, voxel::Material)
+{
+  let mut y = SEARCH_HIGH;
+  let mut above = ground(&Point3::new(x, y, z)).0;
+  while y > SEARCH_LOW {
+    let below = ground(&Point3::new(x, y - SEARCH_STEP, z));
+    if below.0 >= 0.0 {
+      // Interpolate to where the density crosses zero.
+      return Some((y - SEARCH_STEP * above / (above - below.0), below.1))
+    }
+    above = below.0;
+    y -= SEARCH_STEP;
+  }
+  None
+}
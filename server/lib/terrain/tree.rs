@@ -1,150 +1,274 @@
-//! A tree is comprised of a cylindrical trunk, a spherical bunch of leaves, and a spherical
-//! rounding to the bottom of the trunk.
+//! Procedural trees. Each species has a branching rule, a little L-system: every branch ends by splitting
+//! into smaller branches, until the last ones end in blobs of leaves. Species with a leader, like pines,
+//! also keep growing one branch straight up, ringed by short side branches.
 
-use cgmath::{Point3, Vector3, Basis3, Rotation, EuclideanSpace, InnerSpace};
+use cgmath::{Point3, Vector3, InnerSpace};
+use collision::Aabb3;
 use rand;
-use voxel_data;
+use std;
 use voxel_data::field;
 use voxel_data::mosaic;
 
 use common::voxel;
 
-mod pillar {
-  use cgmath::{Point3, Vector3, EuclideanSpace, InnerSpace};
+#[allow(missing_docs)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Species {
+  /// Broad and round, splitting into a few thick limbs.
+  Oak,
+  /// Tall and narrow: one straight trunk ringed with branches that shorten toward the top.
+  Pine,
+  /// Short, with limbs that spread wide under a flat canopy.
+  Acacia,
+}
 
-  use voxel_data::field;
+/// A species' branching rule, for a tree of scale 1.
+struct Rule {
+  trunk_height    : f32,
+  trunk_radius    : f32,
+  /// How many times branches split.
+  depth           : u32,
+  /// How many branches each branch splits into.
+  children        : u32,
+  /// Angle between a branch and its children, in radians.
+  spread          : f32,
+  /// Length of a child branch, relative to its parent.
+  length_ratio    : f32,
+  /// Radius of a child branch, relative to its parent.
+  radius_ratio    : f32,
+  /// Whether a branch also continues straight on past its children. Side branches off a leader don't split.
+  leader          : bool,
+  canopy_radius   : f32,
+  /// Vertical squash of the leaf blobs.
+  canopy_flatness : f32,
+}
 
-  pub struct T {
-    pub radius: f32,
+impl Species {
+  fn rule(self) -> Rule {
+    match self {
+      Species::Oak =>
+        Rule {
+          trunk_height    : 10.0,
+          trunk_radius    : 1.5,
+          depth           : 2,
+          children        : 3,
+          spread          : 0.7,
+          length_ratio    : 0.7,
+          radius_ratio    : 0.6,
+          leader          : false,
+          canopy_radius   : 5.0,
+          canopy_flatness : 0.8,
+        },
+      Species::Pine =>
+        Rule {
+          trunk_height    : 12.0,
+          trunk_radius    : 1.0,
+          depth           : 3,
+          children        : 4,
+          spread          : 1.3,
+          length_ratio    : 0.55,
+          radius_ratio    : 0.7,
+          leader          : true,
+          canopy_radius   : 2.5,
+          canopy_flatness : 0.6,
+        },
+      Species::Acacia =>
+        Rule {
+          trunk_height    : 5.0,
+          trunk_radius    : 1.0,
+          depth           : 2,
+          children        : 3,
+          spread          : 0.9,
+          length_ratio    : 0.9,
+          radius_ratio    : 0.6,
+          leader          : false,
+          canopy_radius   : 5.0,
+          canopy_flatness : 0.35,
+        },
+    }
   }
+}
 
-  impl field::T for T {
-    fn density(&mut self, p: &Point3<f32>) -> f32 {
-      let mut p = *p;
-      p.y = 0.0;
-      self.radius*self.radius - p.to_vec().magnitude2()
-    }
+/// A capsule of bark.
+struct Branch {
+  from   : Point3<f32>,
+  to     : Point3<f32>,
+  radius : f32,
+}
 
-    fn normal(&mut self, p: &Point3<f32>) -> Vector3<f32> {
-      let mut p = *p;
-      p.y = 0.0;
-      p.to_vec().normalize()
-    }
-  }
+/// A squashed sphere of leaves.
+struct Canopy {
+  center   : Point3<f32>,
+  radius   : f32,
+  flatness : f32,
 }
 
 #[allow(missing_docs)]
 pub struct T {
-  union: voxel_data::mosaic::union::T<voxel::Material>,
+  branches : Vec<Branch>,
+  canopies : Vec<Canopy>,
+  low      : Point3<f32>,
+  high     : Point3<f32>,
 }
 
-fn inside_sphere<Rng>(
-  rng: &mut Rng,
-  radius: f32,
-) -> Point3<f32>
-  where Rng: rand::Rng,
-{
-  loop {
-    let p =
-      Point3::new(
-        rng.gen_range(-radius, radius),
-        rng.gen_range(-radius, radius),
-        rng.gen_range(-radius, radius),
-      );
-    if p.to_vec().magnitude2() < radius*radius {
-      return p
-    }
+/// Tilt `direction` by `angle` radians, toward the side given by `azimuth`.
+fn tilt(direction: &Vector3<f32>, angle: f32, azimuth: f32) -> Vector3<f32> {
+  let across =
+    if direction.y.abs() < 0.9 {
+      Vector3::new(0.0, 1.0, 0.0)
+    } else {
+      Vector3::new(1.0, 0.0, 0.0)
+    };
+  let u = direction.cross(across).normalize();
+  let v = direction.cross(u);
+  let side = u * azimuth.cos() + v * azimuth.sin();
+  let mut tilted = *direction * angle.cos() + side * angle.sin();
+  // Branches can droop, but not grow down into the ground.
+  tilted.y = f32::max(tilted.y, 0.1);
+  tilted.normalize()
+}
+
+fn grow_branch<Rng: rand::Rng>(
+  tree      : &mut T,
+  rule      : &Rule,
+  scale     : f32,
+  rng       : &mut Rng,
+  from      : Point3<f32>,
+  direction : Vector3<f32>,
+  length    : f32,
+  radius    : f32,
+  depth     : u32,
+) {
+  let to = from + direction * length;
+  tree.branches.push(Branch { from: from, to: to, radius: radius });
+
+  if depth == 0 {
+    tree.canopies.push(
+      Canopy {
+        center   : to,
+        radius   : rule.canopy_radius * scale * rng.gen_range(0.8, 1.2),
+        flatness : rule.canopy_flatness,
+      }
+    );
+    return
+  }
+
+  let twist = rng.gen_range(0.0, 2.0 * std::f32::consts::PI);
+  for i in 0 .. rule.children {
+    let azimuth = twist + i as f32 * 2.0 * std::f32::consts::PI / rule.children as f32;
+    let child = tilt(&direction, rule.spread * rng.gen_range(0.8, 1.2), azimuth);
+    let child_depth = if rule.leader { 0 } else { depth - 1 };
+    grow_branch(tree, rule, scale, rng, to, child, length * rule.length_ratio, radius * rule.radius_ratio, child_depth);
+  }
+  if rule.leader {
+    let leader = tilt(&direction, rng.gen_range(0.0, 0.1), twist);
+    grow_branch(tree, rule, scale, rng, to, leader, length * rule.length_ratio, radius * rule.radius_ratio, depth - 1);
   }
 }
 
-#[allow(missing_docs)]
-pub fn new<Rng>(
-  rng: &mut Rng,
-  trunk_height: f32,
-  trunk_radius: f32,
-  leaf_radius: f32,
-) -> T
-  where Rng: rand::Rng,
-{
-  let trunk_top = Point3::new(0.0, trunk_height, 0.0);
-  let leaf_center = trunk_top + Vector3::new(0.0, leaf_radius / 2.0, 0.0);
-  let trunk_center = Point3::new(0.0, trunk_height / 2.0, 0.0);
-
-  let mut union = mosaic::union::new();
-
-  let trunk =
-    field::translation::T {
-      translation: trunk_center.to_vec(),
-      field: field::intersection::new(
-        pillar::T {
-          radius: trunk_radius,
-        },
-        field::sphere::T {
-          radius: trunk_height / 2.0,
-        },
-      ),
+/// Grow a tree of some species with its base at the origin. `scale` multiplies all its dimensions.
+pub fn grow<Rng: rand::Rng>(rng: &mut Rng, species: Species, scale: f32) -> T {
+  let rule = species.rule();
+  let mut tree =
+    T {
+      branches : Vec::new(),
+      canopies : Vec::new(),
+      low      : Point3::new(0.0, 0.0, 0.0),
+      high     : Point3::new(0.0, 0.0, 0.0),
     };
+  let trunk_height = rule.trunk_height * scale * rng.gen_range(0.8, 1.2);
+  let trunk = tilt(&Vector3::new(0.0, 1.0, 0.0), rng.gen_range(0.0, 0.1), rng.gen_range(0.0, 2.0 * std::f32::consts::PI));
+  grow_branch(
+    &mut tree, &rule, scale, rng,
+    Point3::new(0.0, 0.0, 0.0), trunk, trunk_height, rule.trunk_radius * scale, rule.depth,
+  );
 
-  union.push(voxel::Material::Bark, trunk);
-
-  let leaf_count = {
-    let leaf_radius = leaf_radius / 3.0;
-    (leaf_radius * leaf_radius * leaf_radius) as i32
-  };
-  for _ in 0..leaf_count {
-    let branch_end = leaf_center + inside_sphere(rng, leaf_radius).to_vec();
-    let branch = branch_end - trunk_top;
-    let half_length = branch.magnitude() / 2.0;
-
-    union.push(
-      voxel::Material::Bark,
-      field::translation::T {
-        translation: trunk_top.to_vec(),
-        field: field::rotation::T {
-          rotation: Basis3::between_vectors(Vector3::new(0.0, 1.0, 0.0), branch.normalize()),
-          field: field::translation::T {
-            translation: Vector3::new(0.0, half_length, 0.0),
-            field: field::intersection::new(
-              field::sphere::T {
-                radius: half_length,
-              },
-              pillar::T {
-                radius: 0.5,
-              },
-            ),
-          },
-        },
-      },
-    );
+  let mut low = Point3::new(std::f32::INFINITY, std::f32::INFINITY, std::f32::INFINITY);
+  let mut high = Point3::new(std::f32::NEG_INFINITY, std::f32::NEG_INFINITY, std::f32::NEG_INFINITY);
+  {
+    let mut include = |p: Point3<f32>, r: f32| {
+      low = Point3::new(f32::min(low.x, p.x - r), f32::min(low.y, p.y - r), f32::min(low.z, p.z - r));
+      high = Point3::new(f32::max(high.x, p.x + r), f32::max(high.y, p.y + r), f32::max(high.z, p.z + r));
+    };
+    for branch in &tree.branches {
+      include(branch.from, branch.radius);
+      include(branch.to, branch.radius);
+    }
+    for canopy in &tree.canopies {
+      include(canopy.center, canopy.radius);
+    }
+  }
+  tree.low = low;
+  tree.high = high;
+  tree
+}
 
-    union.push(
-      voxel::Material::Leaves,
-      field::translation::T {
-        translation: branch_end.to_vec(),
-        field: field::sphere::T {
-          radius: 4.0,
-        },
-      },
-    );
+impl T {
+  /// A box around the whole tree, relative to its base.
+  pub fn bounds(&self) -> Aabb3<f32> {
+    Aabb3::new(self.low, self.high)
   }
 
-  T {
-    union: union,
+  /// Roughly the distance from `p` into the tree (negative outside), and what it's made of there.
+  pub fn mat_density(&self, p: &Point3<f32>) -> (f32, voxel::Material) {
+    // Far from the tree, the distance to the bounding box is close enough, and much cheaper.
+    let outside =
+      Vector3::new(
+        f32::max(0.0, f32::max(self.low.x - p.x, p.x - self.high.x)),
+        f32::max(0.0, f32::max(self.low.y - p.y, p.y - self.high.y)),
+        f32::max(0.0, f32::max(self.low.z - p.z, p.z - self.high.z)),
+      );
+    if outside.magnitude2() > 1.0 {
+      return (-outside.magnitude(), voxel::Material::Empty)
+    }
+
+    let mut best = (std::f32::NEG_INFINITY, voxel::Material::Empty);
+    for branch in &self.branches {
+      let along = branch.to - branch.from;
+      let t = f32::max(0.0, f32::min(1.0, (p - branch.from).dot(along) / along.magnitude2()));
+      let d = branch.radius - (p - (branch.from + along * t)).magnitude();
+      if d > best.0 {
+        best = (d, voxel::Material::Bark);
+      }
+    }
+    for canopy in &self.canopies {
+      let offset = p - canopy.center;
+      let offset = Vector3::new(offset.x, offset.y / canopy.flatness, offset.z);
+      let d = canopy.radius - offset.magnitude();
+      if d > best.0 {
+        best = (d, voxel::Material::Leaves);
+      }
+    }
+    best
   }
 }
 
 impl field::T for T {
   fn density(&mut self, p: &Point3<f32>) -> f32 {
-    field::T::density(&mut self.union, p)
+    self.mat_density(p).0
   }
 
   fn normal(&mut self, p: &Point3<f32>) -> Vector3<f32> {
-    field::T::normal(&mut self.union, p)
+    let delta = 0.01;
+    let differential = |d: Vector3<f32>| {
+      self.mat_density(&(p + d)).0 - self.mat_density(&(p + -d)).0
+    };
+    let v =
+      Vector3::new(
+        differential(Vector3::new(delta, 0.0, 0.0)),
+        differential(Vector3::new(0.0, delta, 0.0)),
+        differential(Vector3::new(0.0, 0.0, delta)),
+      );
+    // Negate because we're leaving the volume when density is decreasing.
+    (-v).normalize()
   }
 }
 
 impl mosaic::T<voxel::Material> for T {
   fn material(&mut self, p: &Point3<f32>) -> Option<voxel::Material> {
-    mosaic::T::material(&mut self.union, p)
+    match self.mat_density(p) {
+      (d, material) if d >= 0.0 => Some(material),
+      _ => None,
+    }
   }
 }
@@ -0,0 +1,117 @@
+//! Scatters trees over the world. The world is divided into square cells, each of which might grow one
+//! tree at a random spot inside it, with a chance given by its biome's tree density.
+//! Every cell's tree is decided from the world seed and the cell's position alone, so the same trees
+//! come back no matter what order the terrain is generated in.
+
+use cgmath::{Point3, Vector3};
+use fnv;
+use lru_cache;
+use rand::Rng;
+use std;
+
+use common::rng;
+use common::voxel;
+
+use biome::climate;
+use surface;
+use tree;
+
+/// Width of the cells that each grow at most one tree.
+const CELL_WIDTH: f32 = 32.0;
+
+/// How far from their base trees can reach sideways. Cells with bases further away than this aren't checked.
+const MAX_REACH: f32 = 24.0;
+
+type Cache = lru_cache::LruCache<(i32, i32), Option<Planted>, std::hash::BuildHasherDefault<fnv::FnvHasher>>;
+
+/// A tree, and where its base is.
+struct Planted {
+  base : Vector3<f32>,
+  tree : tree::T,
+}
+
+#[allow(missing_docs)]
+pub struct T {
+  seed  : u64,
+  cells : Cache,
+}
+
+#[allow(missing_docs)]
+pub fn new(seed: u64) -> T {
+  T {
+    seed  : seed,
+    cells : lru_cache::LruCache::with_hasher(1 << 10, Default::default()),
+  }
+}
+
+/// Find the height of the ground in a column, if it's somewhere trees will grow.
+fn find_base<Ground>(ground: &Ground, x: f32, z: f32) -> Option<f32>
+  where Ground: Fn(&Point3<f32>) -> (f32, voxel::Material)
+{
+  match surface::find(ground, x, z) {
+    Some((y, voxel::Material::Terrain)) |
+    Some((y, voxel::Material::Sand)) |
+    Some((y, voxel::Material::Snow)) if y >= voxel::SEA_LEVEL as f32 => Some(y),
+    _ => None,
+  }
+}
+
+impl T {
+  fn plant<Ground>(&self, climate: &climate::T, ground: &Ground, cell: (i32, i32)) -> Option<Planted>
+    where Ground: Fn(&Point3<f32>) -> (f32, voxel::Material)
+  {
+    let mut rng = rng::cell(self.seed, rng::subsystem::TREES, cell);
+    let x = (cell.0 as f32 + rng.gen::<f32>()) * CELL_WIDTH;
+    let z = (cell.1 as f32 + rng.gen::<f32>()) * CELL_WIDTH;
+    let params = climate.params(x, z);
+    if rng.gen::<f32>() >= params.tree_density {
+      return None
+    }
+    let y = match find_base(ground, x, z) {
+      None => return None,
+      Some(y) => y,
+    };
+    Some(
+      Planted {
+        base : Vector3::new(x, y, z),
+        tree : tree::grow(&mut rng, params.tree_species, params.tree_scale),
+      }
+    )
+  }
+
+  /// The density and material of the trees at a point, given the ground they grow out of.
+  /// Returns `None` when there are no trees nearby.
+  pub fn mat_density<Ground>(
+    &mut self,
+    climate : &climate::T,
+    ground  : Ground,
+    p       : &Point3<f32>,
+  ) -> Option<(f32, voxel::Material)>
+    where Ground: Fn(&Point3<f32>) -> (f32, voxel::Material)
+  {
+    let low_x = ((p.x - MAX_REACH) / CELL_WIDTH).floor() as i32;
+    let high_x = ((p.x + MAX_REACH) / CELL_WIDTH).floor() as i32;
+    let low_z = ((p.z - MAX_REACH) / CELL_WIDTH).floor() as i32;
+    let high_z = ((p.z + MAX_REACH) / CELL_WIDTH).floor() as i32;
+
+    let mut best: Option<(f32, voxel::Material)> = None;
+    for cx in low_x .. high_x + 1 {
+      for cz in low_z .. high_z + 1 {
+        if !self.cells.contains_key(&(cx, cz)) {
+          let planted = self.plant(climate, &ground, (cx, cz));
+          self.cells.insert((cx, cz), planted);
+        }
+        let planted =
+          match self.cells.get_mut(&(cx, cz)) {
+            Some(&mut Some(ref planted)) => planted,
+            _ => continue,
+          };
+        let md = planted.tree.mat_density(&(p + -planted.base));
+        if best.map_or(true, |(d, _)| md.0 > d) {
+          best = Some(md);
+        }
+      }
+    }
+    best
+  }
+}
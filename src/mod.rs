@@ -14,20 +14,32 @@ extern crate dummy_client_lib;
 extern crate server_lib;
 
 use std::borrow::Borrow;
+use std::env;
 use std::sync::Mutex;
 
 fn main() {
   env_logger::init().unwrap();
 
+  let mut args = env::args();
+  args.next().unwrap();
+  match (args.next(), args.next()) {
+    (None, _) => {},
+    // This is where everything the server and client write goes; see common::data_dir.
+    (Some(ref flag), Some(dir)) if flag == "--data-dir" => env::set_var("PLAYFORM_DATA_DIR", dir),
+    _ => panic!("usage: playform [--data-dir <dir>]"),
+  }
+  assert!(args.next().is_none());
+
   let listen_url = String::from("ipc:///tmp/client.ipc");
   let server_url = String::from("ipc:///tmp/server.ipc");
 
   let quit_signal = Mutex::new(false);
+  let commands = Mutex::new(Vec::new());
 
   unsafe {
     let server_thread =
       thread_scoped::scoped(|| {
-        server_lib::run(server_url.borrow(), &quit_signal);
+        server_lib::run(server_url.borrow(), &quit_signal, &commands);
       });
 
     #[cfg(feature = "dummy-client")]